@@ -0,0 +1,56 @@
+use violet_core::Algorithm;
+
+/// Name accepted by `--algorithm` and shown by `violet algorithms`
+pub fn cli_name(algorithm: Algorithm) -> &'static str {
+    match algorithm {
+        Algorithm::Aes256Gcm => "aes-256-gcm",
+        Algorithm::Aes256GcmSiv => "aes-256-gcm-siv",
+    }
+}
+
+/// Print every supported algorithm with its properties
+pub fn execute() {
+    print!("{}", listing());
+}
+
+fn listing() -> String {
+    let width = Algorithm::all().iter().map(|a| cli_name(*a).len()).max().unwrap_or(0);
+    Algorithm::all()
+        .iter()
+        .map(|algorithm| {
+            let misuse = if algorithm.is_nonce_misuse_resistant() {
+                "nonce-misuse resistant"
+            } else {
+                "not nonce-misuse resistant"
+            };
+            let default = if *algorithm == Algorithm::default() { ", default" } else { "" };
+            format!(
+                "{:<width$}  {} ({}{})\n",
+                cli_name(*algorithm),
+                algorithm.description(),
+                misuse,
+                default,
+                width = width
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listing_covers_all_algorithms() {
+        let listing = listing();
+        assert_eq!(listing.lines().count(), Algorithm::all().len());
+        for algorithm in Algorithm::all() {
+            let line = listing
+                .lines()
+                .find(|line| line.starts_with(&format!("{} ", cli_name(*algorithm))))
+                .unwrap_or_else(|| panic!("{} missing from {}", cli_name(*algorithm), listing));
+            assert!(line.contains(algorithm.description()));
+        }
+        assert!(listing.contains("aes-256-gcm-siv  AES-256-GCM-SIV; a repeated nonce only reveals whether two plaintexts are equal (nonce-misuse resistant)"));
+    }
+}
@@ -0,0 +1,15 @@
+use anyhow::{Context, Result};
+use violet_client::cache::DEFAULT_CACHE_TTL;
+use violet_client::KeyCache;
+
+/// Remove all entries from the on-disk key cache
+pub fn clear() -> Result<()> {
+    let dir = KeyCache::default_dir()
+        .context("Unable to determine key cache directory (HOME is not set)")?;
+    let cache = KeyCache::new(dir, DEFAULT_CACHE_TTL);
+
+    cache.clear().context("Failed to clear key cache")?;
+
+    tracing::info!("Cleared key cache in {}", cache.dir().display());
+    Ok(())
+}
@@ -1,15 +1,658 @@
-use anyhow::Result;
-use violet_daemon::DaemonServer;
-
-pub async fn execute(
-    server_url: &str,
-    socket: &str,
-) -> Result<()> {
-    tracing::info!("Starting Violet daemon on socket: {}", socket);
+use anyhow::{bail, Context, Result};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use violet_daemon::{
+    default_socket_path, parse_group, parse_listen_addr, parse_user, ConnectionLimitPolicy, DaemonMode, DaemonServer,
+    DEFAULT_BLOCKING_THREADS, DEFAULT_BREAKER_COOLDOWN, DEFAULT_BREAKER_THRESHOLD, DEFAULT_KEK_CACHE_CAPACITY, DEFAULT_KEK_CACHE_TTL,
+    DEFAULT_KEYS_SERVER_TIMEOUT, DEFAULT_MAX_BATCH_SIZE, DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_IN_FLIGHT,
+    DEFAULT_MAX_REQUEST_BYTES, DEFAULT_SHUTDOWN_GRACE, DEFAULT_SOCKET_MODE,
+};
+use violet_daemon_client::DaemonClient;
+use crate::commands::daemon_config::DaemonConfig;
+use crate::DEFAULT_SERVER_URL;
+
+/// How long `ping` waits for the daemon to answer
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Settings for `violet daemon`
+///
+/// Settings left unset here fall back to the `--config` file, then to the
+/// built-in defaults; see [`DaemonSettings::resolve`].
+#[derive(clap::Args)]
+pub struct DaemonOptions {
+    /// Read settings from this TOML file; flags and environment variables override it
+    #[arg(long, env = "VIOLET_DAEMON_CONFIG", value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Socket path, or @name for an abstract socket on Linux (default:
+    /// $XDG_RUNTIME_DIR/violet.sock, else /tmp/violet.sock; none if only --listen is given)
+    #[arg(short, long, env = "VIOLET_SOCKET_PATH")]
+    pub socket: Option<String>,
+
+    /// Permissions of the socket file, in octal (default: 600)
+    #[arg(long, env = "VIOLET_SOCKET_MODE", value_parser = parse_socket_mode)]
+    pub socket_mode: Option<u32>,
+
+    /// Give the socket file to this group (name or GID), e.g. with --socket-mode 660
+    #[arg(long, env = "VIOLET_SOCKET_GROUP")]
+    pub socket_group: Option<String>,
+
+    /// Only serve socket clients running as this user, by name or UID (repeatable)
+    #[arg(long = "allow-uid", value_name = "USER")]
+    pub allow_uids: Vec<String>,
+
+    /// Only serve socket clients whose primary group is this group, by name or GID (repeatable)
+    #[arg(long = "allow-gid", value_name = "GROUP")]
+    pub allow_gids: Vec<String>,
+
+    /// Allow the socket in a world-writable directory such as /tmp
+    #[arg(long)]
+    pub insecure_socket_dir: bool,
+
+    /// Start even if another daemon holds the socket's lock file, taking over its socket
+    #[arg(long)]
+    pub force: bool,
+
+    /// Serve one client on stdin and stdout instead of a socket, exiting when stdin closes
+    #[arg(long, conflicts_with_all = ["socket", "listen"])]
+    pub stdio: bool,
+
+    /// Also accept connections on a TCP address, e.g. tcp://127.0.0.1:9876
+    #[arg(long, env = "VIOLET_LISTEN")]
+    pub listen: Option<String>,
+
+    /// Serve Prometheus metrics (/metrics) and a health check (/healthz) over HTTP on this address, e.g. 127.0.0.1:9900
+    #[arg(long, env = "VIOLET_METRICS_ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Allow --listen and --metrics-addr on a non-loopback address
+    #[arg(long)]
+    pub allow_remote: bool,
+
+    /// Serve TLS on --listen with this PEM certificate chain (needs --tls-key)
+    #[arg(long, env = "VIOLET_TLS_CERT", value_name = "FILE", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "VIOLET_TLS_KEY", value_name = "FILE", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Require TLS clients to present a certificate signed by a CA in this PEM file
+    #[arg(long, env = "VIOLET_TLS_CLIENT_CA", value_name = "FILE")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Allow --listen on a non-loopback address without TLS (requests travel unencrypted)
+    #[arg(long)]
+    pub allow_insecure_remote: bool,
+
+    /// Append an audit record (JSON lines, hash-chained) for every operation
+    #[arg(long, env = "VIOLET_AUDIT_LOG")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Fail requests whose audit record cannot be written (default: log the failure and carry on)
+    #[arg(long, env = "VIOLET_AUDIT_STRICT")]
+    pub audit_strict: bool,
+
+    /// Encrypt requests without a key ID under one shared key, replaced after this many seconds
+    #[arg(long, env = "VIOLET_SHARED_KEY_TTL")]
+    pub shared_key_ttl: Option<u64>,
+
+    /// Seconds a fetched key is cached in memory before it is fetched again; 0 disables the cache (default: 300)
+    #[arg(long, env = "VIOLET_KEK_CACHE_TTL", value_name = "SECS")]
+    pub kek_cache_ttl: Option<u64>,
+
+    /// Most keys cached in memory at once; the oldest is dropped to make room (default: 1024)
+    #[arg(long, env = "VIOLET_KEK_CACHE_CAPACITY")]
+    pub kek_cache_capacity: Option<usize>,
+
+    /// Seconds to wait for the Keys server on each call; 0 uses the client's 30 second timeout (default: 3)
+    #[arg(long, env = "VIOLET_KEYS_SERVER_TIMEOUT", value_name = "SECS")]
+    pub keys_server_timeout: Option<u64>,
+
+    /// Consecutive Keys server failures after which requests fail fast; 0 never fails fast (default: 5)
+    #[arg(long, env = "VIOLET_BREAKER_THRESHOLD")]
+    pub breaker_threshold: Option<u32>,
+
+    /// Seconds to fail fast before trying the Keys server again (default: 30)
+    #[arg(long, env = "VIOLET_BREAKER_COOLDOWN", value_name = "SECS")]
+    pub breaker_cooldown: Option<u64>,
+
+    /// Let createKey requests ask for the new key's material (trusted local callers only)
+    #[arg(long)]
+    pub allow_key_export: bool,
+
+    /// Refuse encrypt requests without a key ID instead of creating a key for them
+    #[arg(long, env = "VIOLET_REQUIRE_KEY_ID")]
+    pub require_key_id: bool,
+
+    /// Only use this key ID; requests for unlisted keys are refused (repeatable)
+    #[arg(long = "allow-key-id", value_name = "KEY_ID")]
+    pub allow_key_ids: Vec<String>,
+
+    /// Only use key IDs starting with this prefix, including for new keys (repeatable)
+    #[arg(long = "allow-key-prefix", value_name = "PREFIX")]
+    pub allow_key_prefixes: Vec<String>,
+
+    /// Let encryptFile and decryptFile requests read and create files under this directory (repeatable)
+    #[arg(long = "allow-file-dir", value_name = "DIR")]
+    pub allow_file_dirs: Vec<PathBuf>,
+
+    /// Operations to accept: full, decrypt-only (no encrypting or key creation) or encrypt-only (default: full)
+    #[arg(long, env = "VIOLET_DAEMON_MODE")]
+    pub mode: Option<DaemonMode>,
+
+    /// Largest number of items accepted in one batch request (default: 1000)
+    #[arg(long, env = "VIOLET_MAX_BATCH_SIZE")]
+    pub max_batch_size: Option<usize>,
+
+    /// Largest request line or frame accepted, in bytes (default: 16 MiB)
+    #[arg(long, env = "VIOLET_MAX_REQUEST_BYTES")]
+    pub max_request_bytes: Option<usize>,
+
+    /// Most connections served at once; further connections are refused with an error (default: 256)
+    #[arg(long, env = "VIOLET_MAX_CONNECTIONS")]
+    pub max_connections: Option<usize>,
+
+    /// Hold connections over --max-connections in the backlog instead of refusing them
+    #[arg(long)]
+    pub queue_connections: bool,
+
+    /// Most requests handled at once across all connections; the rest wait (default: 64)
+    #[arg(long, env = "VIOLET_MAX_IN_FLIGHT")]
+    pub max_in_flight: Option<usize>,
+
+    /// Most large payloads (64 KiB and up) encrypted or decrypted at once, each on its own thread (default: 8)
+    #[arg(long, env = "VIOLET_BLOCKING_THREADS")]
+    pub blocking_threads: Option<usize>,
+
+    /// Seconds to let in-flight requests finish after SIGINT/SIGTERM before exiting (default: 30)
+    #[arg(long, env = "VIOLET_SHUTDOWN_GRACE")]
+    pub shutdown_grace: Option<u64>,
+}
+
+/// Daemon settings with every source applied
+///
+/// Precedence, highest first: command-line flags, environment variables,
+/// the `--config` file, built-in defaults. Switches such as
+/// `--allow-remote` can only turn a setting on, and a non-empty
+/// `--allow-uid`/`--allow-gid`/`--allow-key-id`/`--allow-key-prefix`/`--allow-file-dir`
+/// list replaces the file's list.
+#[derive(Debug, PartialEq)]
+pub struct DaemonSettings {
+    pub server_url: String,
+    pub socket: Option<String>,
+    pub socket_mode: u32,
+    pub socket_group: Option<String>,
+    pub allow_uids: Vec<String>,
+    pub allow_gids: Vec<String>,
+    pub insecure_socket_dir: bool,
+    pub force: bool,
+    pub stdio: bool,
+    pub listen: Option<String>,
+    pub allow_remote: bool,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_client_ca: Option<PathBuf>,
+    pub allow_insecure_remote: bool,
+    pub metrics_addr: Option<SocketAddr>,
+    pub audit_log: Option<PathBuf>,
+    pub audit_strict: bool,
+    pub shared_key_ttl: Option<Duration>,
+    pub kek_cache_ttl: Duration,
+    pub kek_cache_capacity: usize,
+    pub keys_server_timeout: Duration,
+    pub breaker_threshold: u32,
+    pub breaker_cooldown: Duration,
+    pub allow_key_export: bool,
+    pub require_key_id: bool,
+    pub allow_key_ids: Vec<String>,
+    pub allow_key_prefixes: Vec<String>,
+    pub allow_file_dirs: Vec<PathBuf>,
+    pub mode: DaemonMode,
+    pub max_batch_size: usize,
+    pub max_request_bytes: usize,
+    pub max_connections: usize,
+    pub queue_connections: bool,
+    pub max_in_flight: usize,
+    pub blocking_threads: usize,
+    pub shutdown_grace: Duration,
+}
+
+impl DaemonSettings {
+    /// Merge flags and environment (`options`, `server_url`) over `config`
+    pub fn resolve(options: DaemonOptions, server_url: Option<&str>, config: DaemonConfig) -> Result<Self> {
+        let socket_mode = match (options.socket_mode, &config.socket.mode) {
+            (Some(mode), _) => mode,
+            (None, Some(mode)) => mode.bits()?,
+            (None, None) => DEFAULT_SOCKET_MODE,
+        };
+        let non_empty = |list: Vec<String>| (!list.is_empty()).then_some(list);
+
+        Ok(Self {
+            server_url: server_url
+                .map(str::to_string)
+                .or(config.keys_server.url)
+                .unwrap_or_else(|| DEFAULT_SERVER_URL.to_string()),
+            socket: options.socket.or(config.socket.path),
+            socket_mode,
+            socket_group: options.socket_group.or(config.socket.group),
+            allow_uids: non_empty(options.allow_uids).or(config.socket.allow_uids).unwrap_or_default(),
+            allow_gids: non_empty(options.allow_gids).or(config.socket.allow_gids).unwrap_or_default(),
+            insecure_socket_dir: options.insecure_socket_dir || config.socket.insecure_dir.unwrap_or(false),
+            force: options.force,
+            stdio: options.stdio,
+            listen: options.listen.or(config.socket.listen),
+            allow_remote: options.allow_remote || config.socket.allow_remote.unwrap_or(false),
+            tls_cert: options.tls_cert.or(config.socket.tls_cert),
+            tls_key: options.tls_key.or(config.socket.tls_key),
+            tls_client_ca: options.tls_client_ca.or(config.socket.tls_client_ca),
+            allow_insecure_remote: options.allow_insecure_remote
+                || config.socket.allow_insecure_remote.unwrap_or(false),
+            metrics_addr: options.metrics_addr.or(config.metrics.addr),
+            audit_log: options.audit_log.or(config.audit.log),
+            audit_strict: options.audit_strict || config.audit.strict.unwrap_or(false),
+            shared_key_ttl: options
+                .shared_key_ttl
+                .or(config.cache.shared_key_ttl_secs)
+                .map(Duration::from_secs),
+            kek_cache_ttl: options
+                .kek_cache_ttl
+                .or(config.cache.kek_ttl_secs)
+                .map_or(DEFAULT_KEK_CACHE_TTL, Duration::from_secs),
+            kek_cache_capacity: options
+                .kek_cache_capacity
+                .or(config.cache.kek_capacity)
+                .unwrap_or(DEFAULT_KEK_CACHE_CAPACITY),
+            keys_server_timeout: options
+                .keys_server_timeout
+                .or(config.keys_server.timeout_secs)
+                .map_or(DEFAULT_KEYS_SERVER_TIMEOUT, Duration::from_secs),
+            breaker_threshold: options
+                .breaker_threshold
+                .or(config.keys_server.breaker_threshold)
+                .unwrap_or(DEFAULT_BREAKER_THRESHOLD),
+            breaker_cooldown: options
+                .breaker_cooldown
+                .or(config.keys_server.breaker_cooldown_secs)
+                .map_or(DEFAULT_BREAKER_COOLDOWN, Duration::from_secs),
+            allow_key_export: options.allow_key_export || config.keys_server.allow_key_export.unwrap_or(false),
+            require_key_id: options.require_key_id || config.keys_server.require_key_id.unwrap_or(false),
+            allow_key_ids: non_empty(options.allow_key_ids)
+                .or(config.keys_server.allow_key_ids)
+                .unwrap_or_default(),
+            allow_key_prefixes: non_empty(options.allow_key_prefixes)
+                .or(config.keys_server.allow_key_prefixes)
+                .unwrap_or_default(),
+            allow_file_dirs: match options.allow_file_dirs {
+                dirs if dirs.is_empty() => config.files.allow_dirs.unwrap_or_default(),
+                dirs => dirs,
+            },
+            mode: options.mode.or(config.mode).unwrap_or_default(),
+            max_batch_size: options
+                .max_batch_size
+                .or(config.limits.max_batch_size)
+                .unwrap_or(DEFAULT_MAX_BATCH_SIZE),
+            max_request_bytes: options
+                .max_request_bytes
+                .or(config.limits.max_request_bytes)
+                .unwrap_or(DEFAULT_MAX_REQUEST_BYTES),
+            max_connections: options
+                .max_connections
+                .or(config.limits.max_connections)
+                .unwrap_or(DEFAULT_MAX_CONNECTIONS),
+            queue_connections: options.queue_connections || config.limits.queue_connections.unwrap_or(false),
+            max_in_flight: options
+                .max_in_flight
+                .or(config.limits.max_in_flight)
+                .unwrap_or(DEFAULT_MAX_IN_FLIGHT),
+            blocking_threads: options
+                .blocking_threads
+                .or(config.limits.blocking_threads)
+                .unwrap_or(DEFAULT_BLOCKING_THREADS),
+            shutdown_grace: options
+                .shutdown_grace
+                .or(config.limits.shutdown_grace_secs)
+                .map_or(DEFAULT_SHUTDOWN_GRACE, Duration::from_secs),
+        })
+    }
+}
+
+/// Run the daemon; `server_url` is the `--server-url` flag or environment, if given
+pub async fn execute(server_url: Option<&str>, options: DaemonOptions) -> Result<()> {
+    let config = match &options.config {
+        Some(path) => DaemonConfig::load(path)?,
+        None => DaemonConfig::default(),
+    };
+    let settings = DaemonSettings::resolve(options, server_url, config)?;
+    if settings.allow_remote && settings.listen.is_none() && settings.metrics_addr.is_none() {
+        bail!("allow_remote needs a TCP listen or metrics address");
+    }
+    if settings.tls_cert.is_some() != settings.tls_key.is_some() {
+        bail!("TLS needs both a certificate and a private key (--tls-cert and --tls-key)");
+    }
+    if settings.audit_strict && settings.audit_log.is_none() {
+        bail!("Strict auditing needs an audit log");
+    }
+    let server_url = settings.server_url.as_str();
+    let tcp_addr = settings.listen.as_deref().map(parse_listen_addr).transpose()?;
+
+    // The Unix socket is only dropped when --listen is given on its own
+    let server = match (settings.socket.as_deref(), tcp_addr) {
+        _ if settings.stdio => DaemonServer::stdio(server_url.to_string()),
+        (None, Some(addr)) => DaemonServer::tcp(addr, server_url.to_string()),
+        (socket, tcp_addr) => {
+            let socket = socket.map(str::to_string).unwrap_or_else(default_socket_path);
+            tracing::info!("Starting Violet daemon on socket: {}", socket);
+            let server = DaemonServer::new(socket, server_url.to_string())
+                .with_socket_mode(settings.socket_mode)
+                .allow_insecure_socket_dir(settings.insecure_socket_dir);
+            let server = match settings.socket_group.as_deref() {
+                Some(group) => server.with_socket_group(parse_group(group)?),
+                None => server,
+            };
+            match tcp_addr {
+                Some(addr) => server.with_tcp_listener(addr),
+                None => server,
+            }
+        }
+    };
     tracing::info!("Keys server: {}", server_url);
 
-    let server = DaemonServer::new(socket.to_string(), server_url.to_string());
-    server.run().await?;
+    let mut server = server;
+    for user in &settings.allow_uids {
+        server = server.with_allowed_uid(parse_user(user)?);
+    }
+    for group in &settings.allow_gids {
+        server = server.with_allowed_gid(parse_group(group)?);
+    }
+    for key_id in settings.allow_key_ids {
+        server = server.with_allowed_key_id(key_id);
+    }
+    for prefix in settings.allow_key_prefixes {
+        server = server.with_allowed_key_prefix(prefix);
+    }
+    for dir in settings.allow_file_dirs {
+        server = server.with_allowed_file_dir(dir);
+    }
+
+    let server = match settings.audit_log {
+        Some(path) => server.with_audit_log(path),
+        None => server,
+    };
+    let server = match settings.metrics_addr {
+        Some(addr) => server.with_metrics_addr(addr),
+        None => server,
+    };
+    let server = match settings.shared_key_ttl {
+        Some(ttl) => server.with_shared_key_ttl(ttl),
+        None => server,
+    };
+    let server = match (settings.tls_cert, settings.tls_key) {
+        (Some(cert), Some(key)) => server.with_tls(cert, key),
+        _ => server,
+    };
+    let server = match settings.tls_client_ca {
+        Some(ca) => server.with_tls_client_ca(ca),
+        None => server,
+    };
 
+    let policy = if settings.queue_connections {
+        ConnectionLimitPolicy::Queue
+    } else {
+        ConnectionLimitPolicy::Refuse
+    };
+    server
+        .allow_remote(settings.allow_remote)
+        .allow_insecure_remote(settings.allow_insecure_remote)
+        .force_start(settings.force)
+        .allow_key_export(settings.allow_key_export)
+        .require_key_id(settings.require_key_id)
+        .with_mode(settings.mode)
+        .with_audit_strict(settings.audit_strict)
+        .with_max_batch_size(settings.max_batch_size)
+        .with_kek_cache_ttl(settings.kek_cache_ttl)
+        .with_kek_cache_capacity(settings.kek_cache_capacity)
+        .with_keys_server_timeout(settings.keys_server_timeout)
+        .with_circuit_breaker(settings.breaker_threshold, settings.breaker_cooldown)
+        .with_max_request_bytes(settings.max_request_bytes)
+        .with_max_connections(settings.max_connections, policy)
+        .with_max_in_flight(settings.max_in_flight)
+        .with_blocking_threads(settings.blocking_threads)
+        .with_shutdown_grace(settings.shutdown_grace)
+        .run()
+        .await?;
+
+    // Tokio reads stdin on a blocking thread that a signal cannot interrupt,
+    // and the runtime would wait for it on the way out
+    if settings.stdio {
+        std::process::exit(0);
+    }
     Ok(())
 }
+
+/// Parse an octal `--socket-mode` such as `660` or `0o660`
+pub(crate) fn parse_socket_mode(mode: &str) -> std::result::Result<u32, String> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("{} is not an octal permission mode such as 600", mode)),
+    }
+}
+
+/// Ping a running daemon, failing if it does not answer or cannot reach the Keys server
+pub async fn ping(socket: &str) -> Result<()> {
+    let client = DaemonClient::builder(socket).timeout(PING_TIMEOUT).connect().await?;
+    let pong = client.ping().await.context("Ping failed")?;
+
+    if !pong.keys_server_ok {
+        bail!("Daemon {} is up ({}s) but cannot reach the Keys server", pong.version, pong.uptime_secs);
+    }
+    println!("ok: daemon {} up {}s, Keys server reachable", pong.version, pong.uptime_secs);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ping_exit_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("violet.sock").display().to_string();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            // No daemon yet
+            let error = ping(&socket).await.unwrap_err();
+            assert!(format!("{:#}", error).contains("Failed to connect"), "{:#}", error);
+
+            // A daemon whose Keys server is down answers, but the probe still fails
+            let daemon = DaemonServer::new(socket.clone(), "http://127.0.0.1:9".into())
+                .bind()
+                .await
+                .unwrap();
+            tokio::spawn(daemon.serve());
+
+            let error = ping(&socket).await.unwrap_err();
+            assert!(error.to_string().contains("cannot reach the Keys server"), "{}", error);
+        });
+    }
+
+    #[derive(clap::Parser)]
+    struct DaemonArgs {
+        #[command(flatten)]
+        options: DaemonOptions,
+    }
+
+    fn parse_options(args: &[&str]) -> DaemonOptions {
+        use clap::Parser;
+        DaemonArgs::try_parse_from(std::iter::once("daemon").chain(args.iter().copied()))
+            .unwrap()
+            .options
+    }
+
+    #[test]
+    fn test_settings_precedence() {
+        let config = DaemonConfig::parse(
+            r#"
+            [socket]
+            path = "/from/file.sock"
+            mode = "660"
+            allow_uids = ["app"]
+
+            [keys_server]
+            url = "http://file:8080"
+
+            [limits]
+            max_batch_size = 50
+            max_in_flight = 8
+            blocking_threads = 3
+
+            [cache]
+            kek_ttl_secs = 60
+            kek_capacity = 10
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.mode, None);
+
+        // Only this test reads the variable
+        std::env::set_var("VIOLET_KEK_CACHE_CAPACITY", "7");
+        let options = parse_options(&["--max-batch-size", "5", "--allow-uid", "ops"]);
+        std::env::remove_var("VIOLET_KEK_CACHE_CAPACITY");
+        let settings = DaemonSettings::resolve(options, Some("http://flag:8080"), config).unwrap();
+
+        // Flags and environment variables win over the file
+        assert_eq!(settings.server_url, "http://flag:8080");
+        assert_eq!(settings.max_batch_size, 5);
+        assert_eq!(settings.allow_uids, ["ops"]);
+        assert_eq!(settings.kek_cache_capacity, 7);
+
+        // The file wins over defaults
+        assert_eq!(settings.socket.as_deref(), Some("/from/file.sock"));
+        assert_eq!(settings.socket_mode, 0o660);
+        assert_eq!(settings.max_in_flight, 8);
+        assert_eq!(settings.blocking_threads, 3);
+        assert_eq!(settings.kek_cache_ttl, Duration::from_secs(60));
+
+        // Defaults fill in the rest
+        assert_eq!(settings.max_connections, DEFAULT_MAX_CONNECTIONS);
+        assert_eq!(settings.shutdown_grace, DEFAULT_SHUTDOWN_GRACE);
+        assert!(!settings.audit_strict);
+    }
+
+    #[test]
+    fn test_settings_without_config_file() {
+        let settings = DaemonSettings::resolve(parse_options(&[]), None, DaemonConfig::default()).unwrap();
+        assert_eq!(settings.server_url, DEFAULT_SERVER_URL);
+        assert_eq!(settings.socket_mode, DEFAULT_SOCKET_MODE);
+        assert_eq!(settings.max_batch_size, DEFAULT_MAX_BATCH_SIZE);
+        assert_eq!(settings.kek_cache_ttl, DEFAULT_KEK_CACHE_TTL);
+        assert_eq!(settings.shared_key_ttl, None);
+        assert_eq!(settings.mode, DaemonMode::Full);
+        assert_eq!(settings.keys_server_timeout, DEFAULT_KEYS_SERVER_TIMEOUT);
+        assert_eq!(settings.breaker_threshold, DEFAULT_BREAKER_THRESHOLD);
+        assert_eq!(settings.breaker_cooldown, DEFAULT_BREAKER_COOLDOWN);
+    }
+
+    #[test]
+    fn test_keys_server_timeout_and_breaker_from_flags_or_file() {
+        let file = "[keys_server]
+timeout_secs = 1
+breaker_threshold = 2
+breaker_cooldown_secs = 10";
+        let options = parse_options(&["--breaker-threshold", "0"]);
+        let settings = DaemonSettings::resolve(options, None, DaemonConfig::parse(file).unwrap()).unwrap();
+        assert_eq!(settings.keys_server_timeout, Duration::from_secs(1));
+        assert_eq!(settings.breaker_threshold, 0);
+        assert_eq!(settings.breaker_cooldown, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_tls_from_flags_or_file() {
+        let file = "[socket]
+listen = \"tcp://0.0.0.0:9876\"
+allow_remote = true
+tls_cert = \"/etc/violet/cert.pem\"
+tls_key = \"/etc/violet/key.pem\"
+tls_client_ca = \"/etc/violet/ca.pem\"";
+        let options = parse_options(&["--tls-cert", "/tmp/cert.pem", "--tls-key", "/tmp/key.pem"]);
+        let settings = DaemonSettings::resolve(options, None, DaemonConfig::parse(file).unwrap()).unwrap();
+        assert_eq!(settings.tls_cert, Some(PathBuf::from("/tmp/cert.pem")));
+        assert_eq!(settings.tls_key, Some(PathBuf::from("/tmp/key.pem")));
+        assert_eq!(settings.tls_client_ca, Some(PathBuf::from("/etc/violet/ca.pem")));
+        assert!(!settings.allow_insecure_remote);
+
+        let settings = DaemonSettings::resolve(parse_options(&[]), None, DaemonConfig::default()).unwrap();
+        assert_eq!((settings.tls_cert, settings.tls_key, settings.tls_client_ca), (None, None, None));
+
+        use clap::Parser;
+        assert!(DaemonArgs::try_parse_from(["daemon", "--tls-cert", "/tmp/cert.pem"]).is_err());
+    }
+
+    #[test]
+    fn test_key_allow_list_from_flags_or_file() {
+        let file = "[keys_server]\nallow_key_ids = [\"a\"]\nallow_key_prefixes = [\"tenant-a-\"]";
+        let settings = DaemonSettings::resolve(parse_options(&[]), None, DaemonConfig::parse(file).unwrap()).unwrap();
+        assert_eq!(settings.allow_key_ids, ["a"]);
+        assert_eq!(settings.allow_key_prefixes, ["tenant-a-"]);
+
+        // A flag replaces the file's list of the same kind only
+        let options = parse_options(&["--allow-key-id", "b", "--allow-key-id", "c"]);
+        let settings = DaemonSettings::resolve(options, None, DaemonConfig::parse(file).unwrap()).unwrap();
+        assert_eq!(settings.allow_key_ids, ["b", "c"]);
+        assert_eq!(settings.allow_key_prefixes, ["tenant-a-"]);
+
+        let settings = DaemonSettings::resolve(parse_options(&[]), None, DaemonConfig::default()).unwrap();
+        assert!(settings.allow_key_ids.is_empty() && settings.allow_key_prefixes.is_empty());
+    }
+
+    #[test]
+    fn test_require_key_id_from_flag_or_file() {
+        let settings = DaemonSettings::resolve(parse_options(&[]), None, DaemonConfig::default()).unwrap();
+        assert!(!settings.require_key_id);
+
+        let config = DaemonConfig::parse("[keys_server]\nrequire_key_id = true").unwrap();
+        let settings = DaemonSettings::resolve(parse_options(&[]), None, config).unwrap();
+        assert!(settings.require_key_id);
+
+        let settings = DaemonSettings::resolve(parse_options(&["--require-key-id"]), None, DaemonConfig::default()).unwrap();
+        assert!(settings.require_key_id);
+    }
+
+    #[test]
+    fn test_file_dirs_from_flags_or_file() {
+        let file = "[files]\nallow_dirs = [\"/srv/exports\"]";
+        let settings = DaemonSettings::resolve(parse_options(&[]), None, DaemonConfig::parse(file).unwrap()).unwrap();
+        assert_eq!(settings.allow_file_dirs, [PathBuf::from("/srv/exports")]);
+
+        let options = parse_options(&["--allow-file-dir", "/a", "--allow-file-dir", "/b"]);
+        let settings = DaemonSettings::resolve(options, None, DaemonConfig::parse(file).unwrap()).unwrap();
+        assert_eq!(settings.allow_file_dirs, [PathBuf::from("/a"), PathBuf::from("/b")]);
+
+        let settings = DaemonSettings::resolve(parse_options(&[]), None, DaemonConfig::default()).unwrap();
+        assert!(settings.allow_file_dirs.is_empty());
+    }
+
+    #[test]
+    fn test_mode_from_flag_or_file() {
+        let config = DaemonConfig::parse("mode = \"decrypt-only\"").unwrap();
+        let settings = DaemonSettings::resolve(parse_options(&[]), None, config).unwrap();
+        assert_eq!(settings.mode, DaemonMode::DecryptOnly);
+
+        let config = DaemonConfig::parse("mode = \"decrypt-only\"").unwrap();
+        let settings = DaemonSettings::resolve(parse_options(&["--mode", "encrypt-only"]), None, config).unwrap();
+        assert_eq!(settings.mode, DaemonMode::EncryptOnly);
+
+        assert!(DaemonConfig::parse("mode = \"read-only\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_socket_mode() {
+        assert_eq!(parse_socket_mode("600").unwrap(), 0o600);
+        assert_eq!(parse_socket_mode("0o660").unwrap(), 0o660);
+        assert!(parse_socket_mode("1777").is_err());
+        assert!(parse_socket_mode("rw").is_err());
+    }
+}
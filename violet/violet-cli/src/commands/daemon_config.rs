@@ -0,0 +1,282 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use violet_daemon::DaemonMode;
+
+/// Settings read from `violet daemon --config <file>`, a TOML file
+///
+/// Every setting is optional. Flags and environment variables override the
+/// file, and the file overrides the built-in defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// `full`, `decrypt-only` or `encrypt-only`
+    pub mode: Option<DaemonMode>,
+    pub socket: SocketConfig,
+    pub keys_server: KeysServerConfig,
+    pub limits: LimitsConfig,
+    pub cache: CacheConfig,
+    pub audit: AuditConfig,
+    pub metrics: MetricsConfig,
+    pub files: FilesConfig,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// `[socket]`: where and to whom the daemon listens
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SocketConfig {
+    pub path: Option<String>,
+    /// Octal permissions, as `0o660` or `"660"`
+    pub mode: Option<SocketMode>,
+    pub group: Option<String>,
+    pub allow_uids: Option<Vec<String>>,
+    pub allow_gids: Option<Vec<String>>,
+    pub insecure_dir: Option<bool>,
+    pub listen: Option<String>,
+    pub allow_remote: Option<bool>,
+    /// PEM certificate chain and key for TLS on `listen`
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// PEM CA that TLS clients' certificates must be signed by
+    pub tls_client_ca: Option<PathBuf>,
+    pub allow_insecure_remote: Option<bool>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// Socket permissions as written in the file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum SocketMode {
+    Bits(u32),
+    Octal(String),
+}
+
+impl SocketMode {
+    pub fn bits(&self) -> Result<u32> {
+        match self {
+            SocketMode::Bits(mode) if *mode <= 0o777 => Ok(*mode),
+            SocketMode::Bits(mode) => Err(anyhow!(
+                "socket.mode {} is not a permission mode; write it as 0o660 or \"660\"",
+                mode
+            )),
+            SocketMode::Octal(mode) => super::daemon::parse_socket_mode(mode).map_err(|e| anyhow!("socket.mode: {}", e)),
+        }
+    }
+}
+
+/// `[keys_server]`: the upstream Keys server
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct KeysServerConfig {
+    pub url: Option<String>,
+    pub allow_key_export: Option<bool>,
+    pub require_key_id: Option<bool>,
+    pub allow_key_ids: Option<Vec<String>>,
+    pub allow_key_prefixes: Option<Vec<String>>,
+    pub timeout_secs: Option<u64>,
+    pub breaker_threshold: Option<u32>,
+    pub breaker_cooldown_secs: Option<u64>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// `[limits]`: request, connection and shutdown limits
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    pub max_batch_size: Option<usize>,
+    pub max_request_bytes: Option<usize>,
+    pub max_connections: Option<usize>,
+    pub queue_connections: Option<bool>,
+    pub max_in_flight: Option<usize>,
+    pub blocking_threads: Option<usize>,
+    pub shutdown_grace_secs: Option<u64>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// `[cache]`: in-memory KEK caching and the shared keyless-encrypt KEK
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub kek_ttl_secs: Option<u64>,
+    pub kek_capacity: Option<usize>,
+    pub shared_key_ttl_secs: Option<u64>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// `[audit]`: the audit log
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub log: Option<PathBuf>,
+    pub strict: Option<bool>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// `[metrics]`: the Prometheus listener
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub addr: Option<SocketAddr>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// `[files]`: directories file operations may use
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FilesConfig {
+    pub allow_dirs: Option<Vec<PathBuf>>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+impl DaemonConfig {
+    /// Read and parse a config file, warning about settings it does not know
+    ///
+    /// Syntax and type errors fail with the line and column of the problem.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config = Self::parse(&text).map_err(|e| anyhow!("Invalid config file {}: {}", path.display(), e))?;
+
+        let unknown = config.unknown_keys();
+        if !unknown.is_empty() {
+            tracing::warn!("Ignoring unknown settings in {}: {}", path.display(), unknown.join(", "));
+        }
+        tracing::info!("Loaded daemon config from {}", path.display());
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> std::result::Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Settings in the file that are not understood, as `section.key`
+    pub fn unknown_keys(&self) -> Vec<String> {
+        let sections = [
+            ("socket", &self.socket.unknown),
+            ("keys_server", &self.keys_server.unknown),
+            ("limits", &self.limits.unknown),
+            ("cache", &self.cache.unknown),
+            ("audit", &self.audit.unknown),
+            ("metrics", &self.metrics.unknown),
+            ("files", &self.files.unknown),
+        ];
+        let mut keys: Vec<String> = self.unknown.keys().cloned().collect();
+        for (section, unknown) in sections {
+            keys.extend(unknown.keys().map(|key| format!("{}.{}", section, key)));
+        }
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_full_config() {
+        let config = DaemonConfig::parse(
+            r#"
+            mode = "decrypt-only"
+
+            [socket]
+            path = "/run/violet/violet.sock"
+            mode = 0o660
+            group = "violet"
+            allow_uids = ["app", "1001"]
+
+            [keys_server]
+            url = "http://keys.internal:8080"
+            timeout_secs = 2
+            breaker_threshold = 3
+
+            [limits]
+            max_batch_size = 50
+            shutdown_grace_secs = 5
+
+            [cache]
+            kek_ttl_secs = 60
+            kek_capacity = 10
+
+            [audit]
+            log = "/var/log/violet/audit.log"
+            strict = true
+
+            [metrics]
+            addr = "127.0.0.1:9900"
+
+            [files]
+            allow_dirs = ["/srv/exports"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.mode, Some(DaemonMode::DecryptOnly));
+        assert_eq!(config.socket.path.as_deref(), Some("/run/violet/violet.sock"));
+        assert_eq!(config.socket.mode.as_ref().unwrap().bits().unwrap(), 0o660);
+        assert_eq!(config.socket.allow_uids.as_deref().unwrap(), ["app", "1001"]);
+        assert_eq!(config.keys_server.url.as_deref(), Some("http://keys.internal:8080"));
+        assert_eq!(config.keys_server.timeout_secs, Some(2));
+        assert_eq!(config.keys_server.breaker_threshold, Some(3));
+        assert_eq!(config.limits.max_batch_size, Some(50));
+        assert_eq!(config.cache.kek_capacity, Some(10));
+        assert_eq!(config.audit.strict, Some(true));
+        assert_eq!(config.metrics.addr, Some("127.0.0.1:9900".parse().unwrap()));
+        assert_eq!(config.files.allow_dirs.as_deref().unwrap(), [PathBuf::from("/srv/exports")]);
+        assert!(config.unknown_keys().is_empty());
+    }
+
+    #[test]
+    fn test_socket_mode_forms() {
+        let config = DaemonConfig::parse("[socket]\nmode = \"640\"").unwrap();
+        assert_eq!(config.socket.mode.unwrap().bits().unwrap(), 0o640);
+
+        // A decimal 660 is almost certainly a mistake for 0o660
+        let config = DaemonConfig::parse("[socket]\nmode = 660").unwrap();
+        assert!(config.socket.mode.unwrap().bits().is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_listed() {
+        let config = DaemonConfig::parse(
+            r#"
+            log_level = "debug"
+
+            [socket]
+            path = "/tmp/violet.sock"
+            permissions = "600"
+
+            [tls]
+            cert = "cert.pem"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.unknown_keys(), ["log_level", "tls", "socket.permissions"]);
+        assert_eq!(config.socket.path.as_deref(), Some("/tmp/violet.sock"));
+    }
+
+    #[test]
+    fn test_invalid_file_reports_position() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.toml");
+
+        std::fs::write(&path, "[limits]\nmax_batch_size = \n").unwrap();
+        let error = DaemonConfig::load(&path).unwrap_err().to_string();
+        assert!(error.contains("line 2, column"), "{}", error);
+
+        std::fs::write(&path, "[limits]\n\nmax_connections = \"many\"\n").unwrap();
+        let error = DaemonConfig::load(&path).unwrap_err().to_string();
+        assert!(error.contains("line "), "{}", error);
+        assert!(error.contains(&path.display().to_string()), "{}", error);
+    }
+}
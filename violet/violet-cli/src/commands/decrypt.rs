@@ -1,73 +1,534 @@
-use anyhow::{Context, Result};
-use std::io::{self, Read, Write};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::collections::HashMap;
+use std::io::{self, BufRead, IsTerminal, Write};
 use std::fs::File;
-use violet_core::{EncryptionEnvelope, EnvelopeEncryptor, Algorithm};
-use violet_client::KeysClient;
+use std::path::PathBuf;
+use std::time::Duration;
+use violet_core::{EncryptionEnvelope, EnvelopeEncryptor, Algorithm, SecretKey};
+use crate::commands::input::read_input;
+use crate::commands::report::{CommandResult, Status};
+use crate::commands::{keys_client, prompt_password, write_output, EnvelopeFormat, EnvelopeLocation};
 
-pub async fn execute(
+/// Size of every KEK the Keys server hands out, in bytes
+const KEK_SIZE: usize = 32;
+
+/// KEK given on the command line as hex or base64, if either was
+///
+/// For tests and offline recovery: decrypting with it never calls the Keys
+/// server, whatever key_id the envelope names.
+pub fn inline_kek(hex: Option<&str>, base64: Option<&str>) -> Result<Option<SecretKey>> {
+    let kek = match (hex, base64) {
+        (Some(hex), _) => SecretKey::from_hex(hex.trim()).context("--kek is not valid hex")?,
+        (None, Some(encoded)) => BASE64
+            .decode(encoded.trim())
+            .map(SecretKey::from_vec)
+            .context("--kek-base64 is not valid base64")?,
+        (None, None) => return Ok(None),
+    };
+    if kek.len() != KEK_SIZE {
+        bail!("The KEK must be {} bytes, got {}", KEK_SIZE, kek.len());
+    }
+    Ok(Some(kek))
+}
+
+/// Decrypt one envelope with the KEK from `kek`, a password or the Keys server
+#[allow(clippy::too_many_arguments)]
+pub fn execute(
     server_url: &str,
-    input: &str,
+    key_cache: bool,
+    input: EnvelopeLocation<'_>,
+    input_timeout: Option<Duration>,
     output: &str,
-) -> Result<()> {
-    // Read envelope JSON
-    tracing::debug!("Reading envelope from: {}", input);
-    let envelope_json = read_input(input)
-        .context("Failed to read input")?;
+    format: EnvelopeFormat,
+    expect_algorithm: Option<Algorithm>,
+    binary: bool,
+    password: bool,
+    kek: Option<SecretKey>,
+) -> Result<CommandResult> {
+    let envelope = match input {
+        EnvelopeLocation::Path(path) => {
+            // Read envelope
+            tracing::debug!("Reading envelope from: {}", path);
+            let envelope_data = read_input(path, input_timeout)
+                .context("Failed to read input")?;
 
-    let envelope: EncryptionEnvelope = serde_json::from_slice(&envelope_json)
-        .context("Failed to parse envelope JSON")?;
+            format.decode(&envelope_data)
+                .context("Failed to parse envelope")?
+        }
+        EnvelopeLocation::Store { store, name, .. } => {
+            tracing::debug!("Loading stored envelope: {}", name);
+            store.get(name)
+                .with_context(|| format!("Failed to load stored envelope {}", name))?
+        }
+    };
 
     tracing::info!("Decrypting envelope for key: {}", envelope.key_id);
     tracing::info!("Algorithm: {}", envelope.algorithm);
 
-    // Get KEK from server
-    let client = KeysClient::new(server_url)
-        .context("Failed to create Keys client")?;
-
-    let key = client.get_key(&envelope.key_id)
-        .context("Failed to get key from server")?;
-
-    let kek_bytes = key.as_bytes()
-        .context("Failed to decode key")?;
+    let kek_bytes = envelope_kek(server_url, key_cache, &envelope, password, kek)?;
 
     // Decrypt
-    let algorithm = Algorithm::from_str(&envelope.algorithm)
-        .context("Invalid algorithm in envelope")?;
-    let encryptor = EnvelopeEncryptor::new(algorithm);
-
-    let plaintext = encryptor.decrypt(&envelope, &kek_bytes)
-        .context("Decryption failed")?;
+    let plaintext = decrypt_envelope(&envelope, &kek_bytes, expect_algorithm)?;
 
     tracing::info!("Decrypted {} bytes of plaintext", plaintext.len());
 
     // Write output
     tracing::debug!("Writing plaintext to: {}", output);
-    write_output(output, &plaintext)
-        .context("Failed to write output")?;
+    if output == "-" {
+        check_terminal_output(io::stdout().is_terminal(), binary, &plaintext)?;
+    }
+    write_output(output, &plaintext)?;
 
     tracing::info!("Decryption successful");
-    Ok(())
+    Ok(CommandResult::success("decrypt", &envelope.key_id, &envelope.algorithm, plaintext.len()))
 }
 
-fn read_input(path: &str) -> Result<Vec<u8>> {
-    let mut buffer = Vec::new();
-    if path == "-" {
-        tracing::debug!("Reading from stdin");
-        io::stdin().read_to_end(&mut buffer)?;
-    } else {
-        tracing::debug!("Reading from file: {}", path);
-        File::open(path)?.read_to_end(&mut buffer)?;
+/// Where plaintexts from a JSONL decrypt are written
+pub enum JsonlSink {
+    /// One file per line, named `line-<n>.bin`
+    Directory(PathBuf),
+
+    /// Each plaintext preceded by its length as a 4-byte big-endian integer
+    Stream(Box<dyn Write>),
+}
+
+impl JsonlSink {
+    fn write(&mut self, line_number: usize, plaintext: &[u8]) -> Result<()> {
+        match self {
+            JsonlSink::Directory(dir) => {
+                let path = dir.join(format!("line-{}.bin", line_number));
+                File::create(&path)
+                    .and_then(|mut f| f.write_all(plaintext))
+                    .with_context(|| format!("Failed to write {}", path.display()))
+            }
+            JsonlSink::Stream(writer) => {
+                let len = u32::try_from(plaintext.len())
+                    .context("Plaintext too large for length-prefixed output")?;
+                writer.write_all(&len.to_be_bytes())?;
+                writer.write_all(plaintext)?;
+                Ok(())
+            }
+        }
     }
-    Ok(buffer)
 }
 
-fn write_output(path: &str, data: &[u8]) -> Result<()> {
-    if path == "-" {
-        tracing::debug!("Writing to stdout");
-        io::stdout().write_all(data)?;
+/// Outcome of a JSONL decrypt
+#[derive(Debug, Default)]
+pub struct JsonlSummary {
+    pub decrypted: usize,
+
+    /// Total plaintext bytes written
+    pub bytes: u64,
+
+    /// Line number and error for each line that failed
+    pub failed: Vec<(usize, String)>,
+}
+
+/// Looks up the KEK for an envelope's key ID
+type KekFetcher = dyn FnMut(&str) -> Result<SecretKey>;
+
+/// Decrypt a JSONL file of envelopes, one envelope per line
+///
+/// Output goes to a directory (`output`) or, for `-`, to stdout as a
+/// length-prefixed stream. Each key is fetched from the server once, unless
+/// `kek` is given, which then decrypts every line.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_jsonl(
+    server_url: &str,
+    key_cache: bool,
+    input: &str,
+    input_timeout: Option<Duration>,
+    output: &str,
+    keep_going: bool,
+    expect_algorithm: Option<Algorithm>,
+    kek: Option<SecretKey>,
+) -> Result<CommandResult> {
+    let data = read_input(input, input_timeout)
+        .context("Failed to read input")?;
+
+    let fetch_kek: Box<KekFetcher> = match kek {
+        Some(kek) => Box::new(move |_| Ok(kek.clone())),
+        None => {
+            let client = keys_client(server_url, key_cache)
+                .context("Failed to create Keys client")?;
+            Box::new(move |key_id| {
+                let key = client.get_key(key_id)
+                    .context("Failed to get key from server")?;
+                SecretKey::from_hex(&key.key).context("Failed to decode key")
+            })
+        }
+    };
+
+    let mut sink = if output == "-" {
+        JsonlSink::Stream(Box::new(io::stdout()))
     } else {
-        tracing::debug!("Writing to file: {}", path);
-        File::create(path)?.write_all(data)?;
+        std::fs::create_dir_all(output)
+            .with_context(|| format!("Failed to create output directory {}", output))?;
+        JsonlSink::Directory(PathBuf::from(output))
+    };
+
+    let summary = decrypt_jsonl(data.as_slice(), &mut sink, keep_going, expect_algorithm, fetch_kek)?;
+
+    tracing::info!(
+        "Decrypted {} envelopes, {} failed",
+        summary.decrypted,
+        summary.failed.len()
+    );
+    for (line_number, error) in &summary.failed {
+        tracing::warn!("Line {}: {}", line_number, error);
+    }
+
+    // A failed line makes the whole run an error, reported once all lines are done
+    Ok(CommandResult {
+        status: if summary.failed.is_empty() { Status::Ok } else { Status::Error },
+        operation: "decrypt",
+        key_id: None,
+        bytes_processed: summary.bytes,
+        algorithm: None,
+        errors: summary
+            .failed
+            .iter()
+            .map(|(line_number, error)| format!("Line {}: {}", line_number, error))
+            .collect(),
+    })
+}
+
+/// Decrypt each non-blank line of `reader`, fetching each distinct KEK once
+///
+/// Without `keep_going` the first failure is returned as an error; with it,
+/// failures are collected in the summary and processing continues.
+pub fn decrypt_jsonl<R: BufRead>(
+    reader: R,
+    sink: &mut JsonlSink,
+    keep_going: bool,
+    expect_algorithm: Option<Algorithm>,
+    mut fetch_kek: impl FnMut(&str) -> Result<SecretKey>,
+) -> Result<JsonlSummary> {
+    let mut keks: HashMap<String, SecretKey> = HashMap::new();
+    let mut summary = JsonlSummary::default();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.with_context(|| format!("Failed to read line {}", line_number))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = decrypt_line(&line, &mut keks, expect_algorithm, &mut fetch_kek)
+            .and_then(|plaintext| {
+                sink.write(line_number, &plaintext)?;
+                Ok(plaintext.len())
+            });
+
+        match result {
+            Ok(len) => {
+                summary.decrypted += 1;
+                summary.bytes += len as u64;
+            }
+            Err(e) if keep_going => summary.failed.push((line_number, format!("{:#}", e))),
+            Err(e) => return Err(e.context(format!("Line {}", line_number))),
+        }
+    }
+
+    Ok(summary)
+}
+
+fn decrypt_line(
+    line: &str,
+    keks: &mut HashMap<String, SecretKey>,
+    expect_algorithm: Option<Algorithm>,
+    fetch_kek: &mut impl FnMut(&str) -> Result<SecretKey>,
+) -> Result<Vec<u8>> {
+    let envelope: EncryptionEnvelope = serde_json::from_str(line)
+        .context("Failed to parse envelope JSON")?;
+
+    if !keks.contains_key(&envelope.key_id) {
+        let kek = fetch_kek(&envelope.key_id)?;
+        keks.insert(envelope.key_id.clone(), kek);
+    }
+    let kek = &keks[&envelope.key_id];
+
+    decrypt_envelope(&envelope, kek, expect_algorithm)
+}
+
+/// The KEK that opens `envelope`: derived from a prompted password, the one
+/// given as `kek`, or fetched from the Keys server
+pub fn envelope_kek(
+    server_url: &str,
+    key_cache: bool,
+    envelope: &EncryptionEnvelope,
+    password: bool,
+    kek: Option<SecretKey>,
+) -> Result<SecretKey> {
+    Ok(match (&envelope.kdf, password, kek) {
+        (Some(_), _, Some(_)) => bail!("Envelope is password-protected; pass --password instead of a KEK"),
+        (Some(kdf), true, None) => {
+            let password = prompt_password(false)?;
+            kdf.derive_kek(password.as_bytes())
+                .context("Failed to derive key from password")?
+        }
+        (Some(_), false, None) => bail!("Envelope is password-protected; pass --password"),
+        (None, true, _) => bail!("Envelope is not password-protected; omit --password"),
+        (None, false, Some(kek)) => {
+            tracing::debug!("Using the KEK given on the command line for key: {}", envelope.key_id);
+            kek
+        }
+        (None, false, None) => {
+            // Get KEK from server
+            let client = keys_client(server_url, key_cache)
+                .context("Failed to create Keys client")?;
+
+            let key = client.get_key(&envelope.key_id)
+                .context("Failed to get key from server")?;
+
+            SecretKey::from_hex(&key.key)
+                .context("Failed to decode key")?
+        }
+    })
+}
+
+/// Decrypt one envelope, pinning its algorithm if `expect_algorithm` is set
+pub fn decrypt_envelope(
+    envelope: &EncryptionEnvelope,
+    kek: &[u8],
+    expect_algorithm: Option<Algorithm>,
+) -> Result<Vec<u8>> {
+    let algorithm = Algorithm::from_str(&envelope.algorithm)
+        .context("Invalid algorithm in envelope")?;
+    let encryptor = EnvelopeEncryptor::new(algorithm);
+
+    match expect_algorithm {
+        Some(expected) => encryptor.decrypt_expecting(envelope, kek, expected),
+        None => encryptor.decrypt(envelope, kek),
+    }
+    .context("Decryption failed")
+}
+
+/// Refuse to send non-UTF-8 plaintext to a terminal unless `binary` is set
+///
+/// Binary output can leave a terminal in a garbled state, so it is only written
+/// there when explicitly requested. Pipes and redirects are never affected.
+fn check_terminal_output(is_terminal: bool, binary: bool, data: &[u8]) -> Result<()> {
+    if is_terminal && !binary && std::str::from_utf8(data).is_err() {
+        bail!(
+            "Plaintext is {} bytes of binary (non-UTF-8) data; not writing it to the terminal. \
+             Use --output <file>, redirect stdout, or pass --binary to write it anyway",
+            data.len()
+        );
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    const KEK_A: [u8; 32] = [0xaa; 32];
+    const KEK_B: [u8; 32] = [0xbb; 32];
+
+    /// Builds a JSONL fixture: two valid envelopes under key A, one under key B,
+    /// plus a malformed line, a blank line and an envelope for an unknown key
+    fn fixture() -> String {
+        let gcm = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let siv = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv);
+        let lines = [
+            serde_json::to_string(&gcm.encrypt(b"first", &KEK_A, "key-a".into()).unwrap()).unwrap(),
+            "{not json".to_string(),
+            serde_json::to_string(&siv.encrypt(b"second", &KEK_A, "key-a".into()).unwrap()).unwrap(),
+            String::new(),
+            serde_json::to_string(&gcm.encrypt(b"third", &KEK_B, "key-b".into()).unwrap()).unwrap(),
+            serde_json::to_string(&gcm.encrypt(b"lost", &KEK_A, "key-missing".into()).unwrap()).unwrap(),
+        ];
+        lines.join("\n")
+    }
+
+    fn fetch(fetched: &mut Vec<String>, key_id: &str) -> Result<SecretKey> {
+        fetched.push(key_id.to_string());
+        match key_id {
+            "key-a" => Ok(SecretKey::new(&KEK_A)),
+            "key-b" => Ok(SecretKey::new(&KEK_B)),
+            _ => bail!("Key not found: {}", key_id),
+        }
+    }
+
+    /// A writer whose contents can be inspected after being boxed into a sink
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_keep_going_to_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = JsonlSink::Directory(dir.path().to_path_buf());
+        let mut fetched = Vec::new();
+
+        let summary = decrypt_jsonl(fixture().as_bytes(), &mut sink, true, None, |id| fetch(&mut fetched, id)).unwrap();
+
+        assert_eq!(summary.decrypted, 3);
+        assert_eq!(summary.bytes, (b"first".len() + b"second".len() + b"third".len()) as u64);
+        let failed_lines: Vec<usize> = summary.failed.iter().map(|(line, _)| *line).collect();
+        assert_eq!(failed_lines, vec![2, 6]);
+
+        assert_eq!(std::fs::read(dir.path().join("line-1.bin")).unwrap(), b"first");
+        assert_eq!(std::fs::read(dir.path().join("line-3.bin")).unwrap(), b"second");
+        assert_eq!(std::fs::read(dir.path().join("line-5.bin")).unwrap(), b"third");
+
+        // Each key is fetched once even though key-a is used twice
+        assert_eq!(fetched, vec!["key-a", "key-b", "key-missing"]);
+    }
+
+    #[test]
+    fn test_length_prefixed_stream() {
+        let buffer = SharedBuffer::default();
+        let mut sink = JsonlSink::Stream(Box::new(buffer.clone()));
+        let mut fetched = Vec::new();
+
+        decrypt_jsonl(fixture().as_bytes(), &mut sink, true, None, |id| fetch(&mut fetched, id)).unwrap();
+
+        let mut expected = Vec::new();
+        for plaintext in [&b"first"[..], b"second", b"third"] {
+            expected.extend_from_slice(&(plaintext.len() as u32).to_be_bytes());
+            expected.extend_from_slice(plaintext);
+        }
+        assert_eq!(*buffer.0.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_stops_at_first_error_without_keep_going() {
+        let buffer = SharedBuffer::default();
+        let mut sink = JsonlSink::Stream(Box::new(buffer.clone()));
+        let mut fetched = Vec::new();
+
+        let result = decrypt_jsonl(fixture().as_bytes(), &mut sink, false, None, |id| fetch(&mut fetched, id));
+
+        let error = result.unwrap_err();
+        assert!(format!("{:#}", error).contains("Line 2"), "{:#}", error);
+        // Only the first line was written
+        assert_eq!(buffer.0.lock().unwrap().len(), 4 + b"first".len());
+    }
+
+    const BINARY: &[u8] = &[0xff, 0xfe, 0x00, 0x1b, b'['];
+
+    #[test]
+    fn test_terminal_refuses_binary_plaintext() {
+        let error = check_terminal_output(true, false, BINARY).unwrap_err();
+        assert!(error.to_string().contains("--binary"), "{}", error);
+    }
+
+    #[test]
+    fn test_terminal_allows_utf8_plaintext() {
+        check_terminal_output(true, false, "héllo\n".as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_binary_flag_overrides_terminal_check() {
+        check_terminal_output(true, true, BINARY).unwrap();
+
+        // Non-terminal output is never checked
+        check_terminal_output(false, false, BINARY).unwrap();
+    }
+
+    #[test]
+    fn test_inline_kek() {
+        let kek = inline_kek(Some(&"aa".repeat(32)), None).unwrap().unwrap();
+        assert_eq!(&*kek, &KEK_A[..]);
+        let kek = inline_kek(None, Some(&BASE64.encode(KEK_A))).unwrap().unwrap();
+        assert_eq!(&*kek, &KEK_A[..]);
+        assert!(inline_kek(None, None).unwrap().is_none());
+
+        let error = inline_kek(Some("aabb"), None).unwrap_err();
+        assert!(error.to_string().contains("must be 32 bytes, got 2"), "{}", error);
+        let error = inline_kek(None, Some(&BASE64.encode([0u8; 16]))).unwrap_err();
+        assert!(error.to_string().contains("got 16"), "{}", error);
+        let error = inline_kek(Some("not hex"), None).unwrap_err();
+        assert!(error.to_string().contains("--kek"), "{}", error);
+    }
+
+    #[test]
+    fn test_decrypt_with_inline_kek() {
+        let dir = tempfile::tempdir().unwrap();
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"offline", &KEK_A, "key-a".into())
+            .unwrap();
+        let input = dir.path().join("envelope.json");
+        std::fs::write(&input, serde_json::to_vec(&envelope).unwrap()).unwrap();
+        let input = input.to_str().unwrap();
+        let output = dir.path().join("plaintext");
+        let output = output.to_str().unwrap();
+
+        // Nothing listens on port 9, so any Keys server call would fail
+        let decrypt = |kek: &[u8]| {
+            let kek = Some(SecretKey::new(kek));
+            let input = EnvelopeLocation::Path(input);
+            execute("http://127.0.0.1:9", false, input, None, output, EnvelopeFormat::Json, None, false, false, kek)
+        };
+        let result = decrypt(&KEK_A).unwrap();
+        assert_eq!(result.status, Status::Ok);
+        assert_eq!(std::fs::read(output).unwrap(), b"offline");
+
+        // The wrong KEK fails to decrypt instead of falling back to the server
+        let error = decrypt(&KEK_B).unwrap_err();
+        assert!(format!("{:#}", error).contains("Decryption failed"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_jsonl_with_inline_kek() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("envelopes.jsonl");
+        std::fs::write(&input, fixture()).unwrap();
+        let output = dir.path().join("out");
+
+        let result = execute_jsonl(
+            "http://127.0.0.1:9",
+            false,
+            input.to_str().unwrap(),
+            None,
+            output.to_str().unwrap(),
+            true,
+            None,
+            Some(SecretKey::new(&KEK_A)),
+        )
+        .unwrap();
+
+        // Every line is tried with the one KEK, whatever key_id it names
+        assert_eq!(std::fs::read(output.join("line-1.bin")).unwrap(), b"first");
+        assert_eq!(std::fs::read(output.join("line-3.bin")).unwrap(), b"second");
+        assert_eq!(std::fs::read(output.join("line-6.bin")).unwrap(), b"lost");
+        assert!(!output.join("line-5.bin").exists());
+        assert_eq!(result.errors.len(), 2, "{:?}", result.errors);
+    }
+
+    #[test]
+    fn test_expect_algorithm_rejects_other_algorithms() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = JsonlSink::Directory(dir.path().to_path_buf());
+        let mut fetched = Vec::new();
+
+        let summary = decrypt_jsonl(
+            fixture().as_bytes(),
+            &mut sink,
+            true,
+            Some(Algorithm::Aes256GcmSiv),
+            |id| fetch(&mut fetched, id),
+        )
+        .unwrap();
+
+        // Only the GCM-SIV envelope on line 3 is accepted
+        assert_eq!(summary.decrypted, 1);
+        assert!(dir.path().join("line-3.bin").exists());
+        let (_, error) = summary.failed.iter().find(|(line, _)| *line == 1).unwrap();
+        assert!(error.contains("Algorithm mismatch"), "{}", error);
+    }
+}
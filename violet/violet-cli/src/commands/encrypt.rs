@@ -1,85 +1,286 @@
-use anyhow::{Context, Result};
-use std::io::{self, Read, Write};
-use std::fs::File;
-use violet_core::{Algorithm, EnvelopeEncryptor};
-use violet_client::KeysClient;
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+use std::time::Duration;
+use violet_core::{Algorithm, EncryptionEnvelope, EnvelopeEncryptor, SecretKey};
+use crate::commands::input::read_input;
+use crate::commands::report::CommandResult;
+use crate::commands::{keys_client, prompt_password, write_output, EnvelopeFormat, EnvelopeLocation};
 
-pub async fn execute(
+#[allow(clippy::too_many_arguments)]
+pub fn execute(
     server_url: &str,
+    key_cache: bool,
     input: &str,
-    output: &str,
+    input_timeout: Option<Duration>,
+    output: EnvelopeLocation<'_>,
     key_id: Option<&str>,
     algorithm: Algorithm,
-) -> Result<()> {
+    kek_fingerprint: bool,
+    format: EnvelopeFormat,
+    emit: &[EnvelopeFormat],
+    password: bool,
+    canonical: bool,
+    require_key_id: bool,
+) -> Result<CommandResult> {
+    if canonical && format != EnvelopeFormat::Json {
+        bail!("--canonical only applies to JSON envelopes");
+    }
+
     // Read input
     tracing::debug!("Reading plaintext from: {}", input);
-    let plaintext = read_input(input)
+    let plaintext = read_input(input, input_timeout)
         .context("Failed to read input")?;
 
     tracing::info!("Read {} bytes of plaintext", plaintext.len());
 
+    let encryptor = EnvelopeEncryptor::new(algorithm).with_kek_fingerprint(kek_fingerprint);
+    let envelope = if password {
+        let password = prompt_password(true)?;
+        tracing::info!("Encrypting with a password-derived key, algorithm: {}", algorithm.as_str());
+        encryptor.encrypt_with_password(&plaintext, password.as_bytes())
+            .context("Encryption failed")?
+    } else {
+        tracing::info!("Encrypting with algorithm: {}", algorithm.as_str());
+        encrypt_with_server_key(server_url, key_cache, key_id, require_key_id, &encryptor, &plaintext)?
+    };
+
+    match output {
+        EnvelopeLocation::Path(path) if !emit.is_empty() => {
+            for written in write_formats(&envelope, path, emit)? {
+                tracing::debug!("Wrote envelope to: {}", written.display());
+            }
+        }
+        EnvelopeLocation::Path(path) => {
+            // Serialize envelope
+            let encoded = if canonical {
+                envelope.to_canonical_json()
+                    .context("Failed to serialize envelope")?
+            } else {
+                format.encode(&envelope)
+                    .context("Failed to serialize envelope")?
+            };
+
+            // Write output
+            tracing::debug!("Writing envelope to: {}", path);
+            write_output(path, &encoded)
+                .context("Failed to write output")?;
+        }
+        EnvelopeLocation::Store { store, name, overwrite } => {
+            tracing::debug!("Storing envelope as: {}", name);
+            store.put(name, &envelope, overwrite)
+                .with_context(|| format!("Failed to store envelope {}", name))?;
+        }
+    }
+
+    tracing::info!("Encryption successful");
+    Ok(CommandResult::success("encrypt", &envelope.key_id, &envelope.algorithm, plaintext.len()))
+}
+
+/// Encrypt under an existing Keys server key, or a newly created one
+fn encrypt_with_server_key(
+    server_url: &str,
+    key_cache: bool,
+    key_id: Option<&str>,
+    require_key_id: bool,
+    encryptor: &EnvelopeEncryptor,
+    plaintext: &[u8],
+) -> Result<EncryptionEnvelope> {
+    let (kek_id, kek_bytes) = server_kek(server_url, key_cache, key_id, require_key_id)?;
+    encryptor.encrypt(plaintext, &kek_bytes, kek_id)
+        .context("Encryption failed")
+}
+
+/// Fetch the KEK named `key_id` from the Keys server, or create one when it is `None`
+///
+/// With `require_key_id`, a missing `key_id` is an error rather than a new key.
+pub fn server_kek(
+    server_url: &str,
+    key_cache: bool,
+    key_id: Option<&str>,
+    require_key_id: bool,
+) -> Result<(String, SecretKey)> {
+    if require_key_id && key_id.is_none() {
+        bail!("No --key-id given, and --require-key-id forbids creating a new key");
+    }
+
     // Create Keys client
-    let client = KeysClient::new(server_url)
+    let client = keys_client(server_url, key_cache)
         .context("Failed to create Keys client")?;
 
     // Get or create key
-    let (kek_id, kek_bytes) = if let Some(kid) = key_id {
+    if let Some(kid) = key_id {
         // Use existing key
         tracing::info!("Using existing key: {}", kid);
         let key = client.get_key(kid)
             .context("Failed to get key from server")?;
-        let bytes = key.as_bytes()
+        let bytes = SecretKey::from_hex(&key.key)
             .context("Failed to decode key")?;
-        (key.uuid, bytes)
+        Ok((key.uuid, bytes))
     } else {
         // Create new key
         tracing::info!("Creating new key on server");
         let key = client.create_key()
             .context("Failed to create new key")?;
-        let bytes = key.as_bytes()
+        let bytes = SecretKey::from_hex(&key.key)
             .context("Failed to decode key")?;
         tracing::info!("Created new key: {}", key.uuid);
-        (key.uuid, bytes)
-    };
+        Ok((key.uuid, bytes))
+    }
+}
 
-    // Encrypt
-    tracing::info!("Encrypting with algorithm: {}", algorithm.as_str());
-    let encryptor = EnvelopeEncryptor::new(algorithm);
-    let envelope = encryptor.encrypt(&plaintext, &kek_bytes, kek_id)
-        .context("Encryption failed")?;
+/// Pick the encryption algorithm, checking an explicit choice against the recommended one
+///
+/// Without `--algorithm` the recommended algorithm (or the default) is used. An
+/// explicit algorithm that differs from the recommendation is refused if it is
+/// less nonce-misuse resistant, and otherwise used with a warning. `force`
+/// accepts any explicit algorithm without complaint.
+pub fn resolve_algorithm(
+    requested: Option<Algorithm>,
+    recommended: Option<Algorithm>,
+    force: bool,
+) -> Result<Algorithm> {
+    let (requested, recommended) = match (requested, recommended) {
+        (None, recommended) => return Ok(recommended.unwrap_or_default()),
+        (Some(requested), Some(recommended)) if requested != recommended => (requested, recommended),
+        (Some(requested), _) => return Ok(requested),
+    };
 
-    // Serialize to JSON
-    let json = serde_json::to_string_pretty(&envelope)
-        .context("Failed to serialize envelope")?;
+    if force {
+        tracing::info!(
+            "Using {} instead of recommended {} (--force-algorithm)",
+            requested.as_str(),
+            recommended.as_str()
+        );
+        return Ok(requested);
+    }
 
-    // Write output
-    tracing::debug!("Writing envelope to: {}", output);
-    write_output(output, json.as_bytes())
-        .context("Failed to write output")?;
+    if recommended.is_nonce_misuse_resistant() && !requested.is_nonce_misuse_resistant() {
+        bail!(
+            "{} is not nonce-misuse resistant but {} is recommended; pass --force-algorithm to use it anyway",
+            requested.as_str(),
+            recommended.as_str()
+        );
+    }
 
-    tracing::info!("Encryption successful");
-    Ok(())
+    tracing::warn!(
+        "Using {} although {} is recommended",
+        requested.as_str(),
+        recommended.as_str()
+    );
+    Ok(requested)
 }
 
-fn read_input(path: &str) -> Result<Vec<u8>> {
-    let mut buffer = Vec::new();
-    if path == "-" {
-        tracing::debug!("Reading from stdin");
-        io::stdin().read_to_end(&mut buffer)?;
-    } else {
-        tracing::debug!("Reading from file: {}", path);
-        File::open(path)?.read_to_end(&mut buffer)?;
+/// Write `envelope` once per format, to `base` with the format's extension appended
+///
+/// The files hold the same envelope, encrypted once, in different encodings.
+/// Returns the paths written.
+fn write_formats(envelope: &EncryptionEnvelope, base: &str, formats: &[EnvelopeFormat]) -> Result<Vec<PathBuf>> {
+    if base == "-" {
+        bail!("--emit writes one file per format; pass --output with the path to write them next to");
+    }
+
+    let mut written = Vec::new();
+    for format in formats {
+        let path = PathBuf::from(format!("{}.{}", base, format.extension()));
+        if written.contains(&path) {
+            continue;
+        }
+        let encoded = format.encode(envelope)
+            .context("Failed to serialize envelope")?;
+        std::fs::write(&path, encoded)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        written.push(path);
     }
-    Ok(buffer)
+    Ok(written)
 }
 
-fn write_output(path: &str, data: &[u8]) -> Result<()> {
-    if path == "-" {
-        tracing::debug!("Writing to stdout");
-        io::stdout().write_all(data)?;
-    } else {
-        tracing::debug!("Writing to file: {}", path);
-        File::create(path)?.write_all(data)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use violet_client::testutil::MockKeysServer;
+    use violet_client::Key;
+
+    const GCM: Algorithm = Algorithm::Aes256Gcm;
+    const SIV: Algorithm = Algorithm::Aes256GcmSiv;
+
+    #[test]
+    fn test_defaults_to_recommended_algorithm() {
+        assert_eq!(resolve_algorithm(None, None, false).unwrap(), GCM);
+        assert_eq!(resolve_algorithm(None, Some(SIV), false).unwrap(), SIV);
+    }
+
+    #[test]
+    fn test_matching_or_unconfigured_algorithm_is_used() {
+        assert_eq!(resolve_algorithm(Some(SIV), Some(SIV), false).unwrap(), SIV);
+        assert_eq!(resolve_algorithm(Some(GCM), None, false).unwrap(), GCM);
+    }
+
+    #[test]
+    fn test_stronger_algorithm_than_recommended_warns() {
+        assert_eq!(resolve_algorithm(Some(SIV), Some(GCM), false).unwrap(), SIV);
+    }
+
+    #[test]
+    fn test_weaker_algorithm_than_recommended_errors() {
+        let err = resolve_algorithm(Some(GCM), Some(SIV), false).unwrap_err();
+        assert!(err.to_string().contains("--force-algorithm"));
+    }
+
+    #[test]
+    fn test_emitted_formats_decrypt_to_same_plaintext() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("envelope").display().to_string();
+        let kek = [0x42u8; 32];
+        let envelope = EnvelopeEncryptor::new(GCM).encrypt(b"one pass", &kek, "key".into()).unwrap();
+
+        let formats = [EnvelopeFormat::Json, EnvelopeFormat::Cbor, EnvelopeFormat::Json];
+        let written = write_formats(&envelope, &base, &formats).unwrap();
+        assert_eq!(written, [PathBuf::from(format!("{}.json", base)), PathBuf::from(format!("{}.cbor", base))]);
+
+        let json = EnvelopeFormat::Json.decode(&std::fs::read(&written[0]).unwrap()).unwrap();
+        let cbor = EnvelopeFormat::Cbor.decode(&std::fs::read(&written[1]).unwrap()).unwrap();
+        let encryptor = EnvelopeEncryptor::new(GCM);
+        assert_eq!(encryptor.decrypt(&json, &kek).unwrap(), b"one pass");
+        assert_eq!(encryptor.decrypt(&cbor, &kek).unwrap(), b"one pass");
+
+        // Encrypted once: both files hold the same DEK and ciphertext
+        assert_eq!(json.encrypted_data, cbor.encrypted_data);
+        assert_eq!(json.encrypted_key, cbor.encrypted_key);
+    }
+
+    #[test]
+    fn test_emit_needs_a_file_path() {
+        let envelope = EnvelopeEncryptor::new(GCM).encrypt(b"x", &[0x42u8; 32], "key".into()).unwrap();
+        let err = write_formats(&envelope, "-", &[EnvelopeFormat::Json]).unwrap_err();
+        assert!(err.to_string().contains("--output"), "{}", err);
+    }
+
+    #[test]
+    fn test_require_key_id_refuses_to_create_a_key() {
+        let server = MockKeysServer::start();
+        server.insert_key(Key {
+            uuid: "named".to_string(),
+            key: "42".repeat(32),
+            algorithm: None,
+        });
+        let encryptor = EnvelopeEncryptor::new(GCM);
+
+        let err = encrypt_with_server_key(server.url(), false, None, true, &encryptor, b"data").unwrap_err();
+        assert!(err.to_string().contains("--require-key-id"), "{}", err);
+        assert_eq!(server.request_count(), 0);
+
+        let envelope = encrypt_with_server_key(server.url(), false, Some("named"), true, &encryptor, b"data").unwrap();
+        assert_eq!(envelope.key_id, "named");
+        assert_eq!(encryptor.decrypt(&envelope, &[0x42u8; 32]).unwrap(), b"data");
+
+        // Without the flag a key is created as before
+        let envelope = encrypt_with_server_key(server.url(), false, None, false, &encryptor, b"data").unwrap();
+        assert!(server.contains_key(&envelope.key_id));
+    }
+
+    #[test]
+    fn test_force_overrides_recommendation() {
+        assert_eq!(resolve_algorithm(Some(GCM), Some(SIV), true).unwrap(), GCM);
+        assert_eq!(resolve_algorithm(Some(SIV), Some(GCM), true).unwrap(), SIV);
     }
-    Ok(())
 }
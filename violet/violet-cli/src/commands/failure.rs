@@ -0,0 +1,205 @@
+use violet_client::ClientError;
+use violet_core::VioletError;
+
+/// Why a command failed, which decides its exit status and the message
+/// printed above the error itself
+///
+/// Status 2 is shared with command-line usage errors, which clap reports
+/// before any command runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// Anything not classified below: I/O, a bad key from the Keys server, a bad
+    /// option, a JSONL run with failed lines (exit status 1)
+    Other,
+    /// The input is not an envelope: malformed JSON or CBOR, or an unknown
+    /// algorithm or envelope version (exit status 2)
+    Parse,
+    /// The Keys server has no key with the envelope's key_id (exit status 3)
+    KeyNotFound,
+    /// The envelope did not authenticate: the wrong KEK or password, or
+    /// ciphertext that was modified (exit status 4)
+    AuthFailed,
+    /// The Keys server could not be reached, timed out or answered with an
+    /// unexpected status (exit status 5)
+    Server,
+}
+
+impl FailureKind {
+    /// Classify `error` by the first Violet, Keys client or JSON error in its chain
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<VioletError>() {
+                return Self::of_violet(e);
+            }
+            if let Some(e) = cause.downcast_ref::<ClientError>() {
+                return match e {
+                    ClientError::KeyNotFound(_) => FailureKind::KeyNotFound,
+                    ClientError::RequestFailed(_)
+                    | ClientError::Timeout(_)
+                    | ClientError::UnexpectedStatus(_)
+                    | ClientError::RateLimited(_)
+                    | ClientError::Throttled(_)
+                    | ClientError::ProviderError(_) => FailureKind::Server,
+                    _ => FailureKind::Other,
+                };
+            }
+            if cause.is::<serde_json::Error>() {
+                return FailureKind::Parse;
+            }
+        }
+        FailureKind::Other
+    }
+
+    fn of_violet(error: &VioletError) -> Self {
+        match error {
+            VioletError::SerializationError(_)
+            | VioletError::CborError(_)
+            | VioletError::Base64Error(_)
+            | VioletError::InvalidEnvelope(_)
+            | VioletError::InvalidAlgorithm(_)
+            | VioletError::UnsupportedVersion(_)
+            | VioletError::InvalidNonceSize(_)
+            | VioletError::InvalidTagSize(_) => FailureKind::Parse,
+            VioletError::DecryptionFailed(_)
+            | VioletError::KekFingerprintMismatch { .. }
+            | VioletError::StreamTruncated(_)
+            | VioletError::ChunkCountMismatch { .. } => FailureKind::AuthFailed,
+            _ => FailureKind::Other,
+        }
+    }
+
+    /// Process exit status for this kind of failure
+    pub fn exit_code(self) -> u8 {
+        match self {
+            FailureKind::Other => 1,
+            FailureKind::Parse => 2,
+            FailureKind::KeyNotFound => 3,
+            FailureKind::AuthFailed => 4,
+            FailureKind::Server => 5,
+        }
+    }
+
+    /// Short name for reports that list several outcomes, such as `verify`'s
+    pub fn label(self) -> &'static str {
+        match self {
+            FailureKind::Other => "error",
+            FailureKind::Parse => "malformed",
+            FailureKind::KeyNotFound => "key not found",
+            FailureKind::AuthFailed => "auth failed",
+            FailureKind::Server => "server error",
+        }
+    }
+
+    /// What went wrong and what to check, printed before the error's own message
+    pub fn headline(self) -> Option<&'static str> {
+        match self {
+            FailureKind::Other => None,
+            FailureKind::Parse => Some(
+                "The input is not a valid envelope; check that it is what encrypt wrote \
+                 and that --format matches it",
+            ),
+            FailureKind::KeyNotFound => Some(
+                "The Keys server has no key with the envelope's key_id; it may have been \
+                 deleted, or the envelope came from another Keys server",
+            ),
+            FailureKind::AuthFailed => Some(
+                "The envelope failed authentication: it was sealed under a different key \
+                 or password, or it has been modified",
+            ),
+            FailureKind::Server => Some(
+                "The Keys server could not be reached or answered with an error; check \
+                 --server-url and the server's logs",
+            ),
+        }
+    }
+}
+
+/// Print `error` to stderr, after its kind's headline if it has one
+pub fn print(error: &anyhow::Error) {
+    match FailureKind::of(error).headline() {
+        Some(headline) => eprintln!("Error: {}\n\nCaused by: {:#}", headline, error),
+        None => eprintln!("Error: {:?}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::decrypt;
+    use crate::commands::{EnvelopeFormat, EnvelopeLocation};
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use violet_core::{Algorithm, EnvelopeEncryptor, SecretKey};
+
+    const KEK: [u8; 32] = [0xaa; 32];
+
+    /// Exit status of decrypting `input` in `format` with the KEK `kek`
+    fn decrypt_status(input: &[u8], format: EnvelopeFormat, kek: &[u8]) -> u8 {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("envelope");
+        std::fs::write(&path, input).unwrap();
+        let output = dir.path().join("plaintext");
+
+        let result = decrypt::execute(
+            "http://127.0.0.1:9",
+            false,
+            EnvelopeLocation::Path(path.to_str().unwrap()),
+            None,
+            output.to_str().unwrap(),
+            format,
+            None,
+            false,
+            false,
+            Some(SecretKey::new(kek)),
+        );
+        match result {
+            Ok(_) => 0,
+            Err(error) => FailureKind::of(&error).exit_code(),
+        }
+    }
+
+    #[test]
+    fn test_exit_code_per_failure_class() {
+        let mut envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"classified", &KEK, "key-a".into())
+            .unwrap();
+        let json = serde_json::to_vec(&envelope).unwrap();
+        assert_eq!(decrypt_status(&json, EnvelopeFormat::Json, &KEK), 0);
+
+        // Malformed JSON or CBOR, or an algorithm this build does not know
+        assert_eq!(decrypt_status(b"{not json", EnvelopeFormat::Json, &KEK), 2);
+        assert_eq!(decrypt_status(b"not cbor", EnvelopeFormat::Cbor, &KEK), 2);
+        let unknown = String::from_utf8(json.clone()).unwrap().replace("AES-256-GCM", "ROT13");
+        assert_eq!(decrypt_status(unknown.as_bytes(), EnvelopeFormat::Json, &KEK), 2);
+
+        // The wrong KEK, and ciphertext modified after encryption
+        assert_eq!(decrypt_status(&json, EnvelopeFormat::Json, &[0xbb; 32]), 4);
+        let mut ciphertext = BASE64.decode(&envelope.encrypted_data).unwrap();
+        ciphertext[0] ^= 1;
+        envelope.encrypted_data = BASE64.encode(ciphertext);
+        let tampered = serde_json::to_vec(&envelope).unwrap();
+        assert_eq!(decrypt_status(&tampered, EnvelopeFormat::Json, &KEK), 4);
+
+        // A key the Keys server does not have
+        let error = anyhow::Error::new(ClientError::KeyNotFound("key-a".into()))
+            .context("Failed to get key from server");
+        assert_eq!(FailureKind::of(&error).exit_code(), 3);
+
+        // A Keys server that is down or failing
+        let error = anyhow::Error::new(ClientError::UnexpectedStatus(500))
+            .context("Failed to get key from server");
+        assert_eq!(FailureKind::of(&error).exit_code(), 5);
+
+        // Anything else, such as unreadable input
+        let error = anyhow::Error::new(std::fs::read("/nonexistent/violet-envelope").unwrap_err())
+            .context("Failed to read input");
+        assert_eq!(FailureKind::of(&error).exit_code(), 1);
+    }
+
+    #[test]
+    fn test_headline_only_for_classified_failures() {
+        assert!(FailureKind::Other.headline().is_none());
+        for kind in [FailureKind::Parse, FailureKind::KeyNotFound, FailureKind::AuthFailed, FailureKind::Server] {
+            assert!(kind.headline().is_some(), "{:?}", kind);
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use anyhow::{bail, Context, Result};
+use std::ffi::OsString;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use violet_core::{is_container, Algorithm, EncryptionEnvelope, EnvelopeEncryptor, SecretKey, CONTAINER_EXTENSION};
+use crate::commands::decrypt::{decrypt_envelope, envelope_kek};
+use crate::commands::encrypt::server_kek;
+use crate::commands::prompt_password;
+use crate::commands::report::CommandResult;
+
+/// Distinguishes temporary files written by concurrent replacements in one process
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Replace the file at `path` with `<path>.violet`, a container of its encryption
+///
+/// A file that already holds a container is refused unless `force` is set.
+/// See [`replace`] for how the swap is made and what `preserve` keeps.
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt(
+    server_url: &str,
+    key_cache: bool,
+    path: &Path,
+    key_id: Option<&str>,
+    require_key_id: bool,
+    encryptor: &EnvelopeEncryptor,
+    password: bool,
+    force: bool,
+    preserve: bool,
+) -> Result<CommandResult> {
+    let metadata = regular_file(path)?;
+    let plaintext = fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if is_container(&plaintext) && !force {
+        bail!("{} is already a .violet container; pass --force to encrypt it again", path.display());
+    }
+
+    let envelope = if password {
+        let password = prompt_password(true)?;
+        encryptor.encrypt_with_password(&plaintext, password.as_bytes())
+            .context("Encryption failed")?
+    } else {
+        let (kek_id, kek) = server_kek(server_url, key_cache, key_id, require_key_id)?;
+        encryptor.encrypt(&plaintext, &kek, kek_id)
+            .context("Encryption failed")?
+    };
+
+    let mut destination = OsString::from(path);
+    destination.push(".");
+    destination.push(CONTAINER_EXTENSION);
+    let destination = PathBuf::from(destination);
+    replace(path, &destination, &envelope.to_container()?, &metadata, preserve)?;
+
+    tracing::info!("Replaced {} with {}", path.display(), destination.display());
+    Ok(CommandResult::success("encrypt", &envelope.key_id, &envelope.algorithm, plaintext.len()))
+}
+
+/// Replace the container at `path`, which must end in `.violet`, with the file it holds
+///
+/// The file gets its original name back, `path` without the extension.
+pub fn decrypt(
+    server_url: &str,
+    key_cache: bool,
+    path: &Path,
+    expect_algorithm: Option<Algorithm>,
+    password: bool,
+    kek: Option<SecretKey>,
+    preserve: bool,
+) -> Result<CommandResult> {
+    let destination = match path.file_stem() {
+        Some(stem) if path.extension().is_some_and(|ext| ext == CONTAINER_EXTENSION) && !stem.is_empty() => {
+            path.with_file_name(stem)
+        }
+        _ => bail!("--in-place decrypt needs a file ending in .{}, not {}", CONTAINER_EXTENSION, path.display()),
+    };
+    let metadata = regular_file(path)?;
+    let data = fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let envelope = EncryptionEnvelope::from_container(&data)
+        .with_context(|| format!("Failed to parse container {}", path.display()))?;
+
+    let kek = envelope_kek(server_url, key_cache, &envelope, password, kek)?;
+    let plaintext = decrypt_envelope(&envelope, &kek, expect_algorithm)?;
+    replace(path, &destination, &plaintext, &metadata, preserve)?;
+
+    tracing::info!("Replaced {} with {}", path.display(), destination.display());
+    Ok(CommandResult::success("decrypt", &envelope.key_id, &envelope.algorithm, plaintext.len()))
+}
+
+/// Metadata of `path`, refusing symlinks and anything else that is not a regular file
+fn regular_file(path: &Path) -> Result<Metadata> {
+    let metadata = fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if !metadata.is_file() {
+        bail!("{} is not a regular file", path.display());
+    }
+    Ok(metadata)
+}
+
+/// Write `data` to `destination` and remove `original`, so one of them is always complete
+///
+/// The data goes to a temporary file beside `destination`, is synced, and the
+/// file is linked into place; only then is `original` removed. An existing
+/// `destination`, even one created while the data was written, is refused
+/// rather than replaced. The new file is readable by its owner only, unless
+/// `preserve` gives it `original`'s permissions and modification time.
+fn replace(original: &Path, destination: &Path, data: &[u8], metadata: &Metadata, preserve: bool) -> Result<()> {
+    // The temporary name does not embed `destination`'s, which may already be
+    // as long as a file name can be
+    let tmp = format!(".{}.{}.tmp", std::process::id(), TMP_COUNTER.fetch_add(1, Ordering::Relaxed));
+    let tmp_path = destination.with_file_name(tmp);
+
+    // Unlike a rename, a hard link fails if `destination` exists
+    let linked = write_synced(&tmp_path, data, preserve.then_some(metadata))
+        .and_then(|()| fs::hard_link(&tmp_path, destination));
+    let _ = fs::remove_file(&tmp_path);
+    match linked {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            bail!("{} already exists; move it out of the way first", destination.display())
+        }
+        linked => linked.and_then(|()| sync_dir(destination))
+            .with_context(|| format!("Failed to write {}", destination.display()))?,
+    }
+
+    fs::remove_file(original)
+        .and_then(|()| sync_dir(original))
+        .with_context(|| format!("Wrote {}, but failed to remove {}", destination.display(), original.display()))
+}
+
+fn write_synced(path: &Path, data: &[u8], preserve: Option<&Metadata>) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(path)?;
+    file.write_all(data)?;
+    if let Some(metadata) = preserve {
+        file.set_permissions(metadata.permissions())?;
+        file.set_modified(metadata.modified()?)?;
+    }
+    file.sync_all()
+}
+
+/// Make a rename or removal in `path`'s directory durable
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, SystemTime};
+    use violet_client::testutil::MockKeysServer;
+    use crate::commands::failure::FailureKind;
+
+    fn lock(server: &MockKeysServer, path: &Path, force: bool, preserve: bool) -> Result<CommandResult> {
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        encrypt(server.url(), false, path, Some("key-a"), false, &encryptor, false, force, preserve)
+    }
+
+    fn unlock(server: &MockKeysServer, path: &Path, preserve: bool) -> Result<CommandResult> {
+        decrypt(server.url(), false, path, None, false, None, preserve)
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn test_in_place_round_trip() {
+        let server = MockKeysServer::with_key("key-a", &"aa".repeat(32));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.xlsx");
+        let container = dir.path().join("secrets.xlsx.violet");
+        fs::write(&path, b"quarterly numbers").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+
+        let result = lock(&server, &path, false, true).unwrap();
+        assert_eq!(result.key_id.as_deref(), Some("key-a"));
+        assert!(!path.exists());
+        let data = fs::read(&container).unwrap();
+        assert!(is_container(&data));
+        assert_eq!(mode(&container), 0o640);
+        assert_eq!(fs::metadata(&container).unwrap().modified().unwrap(), mtime);
+        // The container is all that is left, temporary file included
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        unlock(&server, &container, false).unwrap();
+        assert!(!container.exists());
+        assert_eq!(fs::read(&path).unwrap(), b"quarterly numbers");
+        // Without --preserve the restored file is owner-only
+        assert_eq!(mode(&path), 0o600);
+        assert_ne!(fs::metadata(&path).unwrap().modified().unwrap(), mtime);
+    }
+
+    #[test]
+    fn test_refuses_double_encryption_unless_forced() {
+        let server = MockKeysServer::with_key("key-a", &"aa".repeat(32));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.pdf");
+        fs::write(&path, b"%PDF").unwrap();
+        lock(&server, &path, false, false).unwrap();
+
+        let container = dir.path().join("report.pdf.violet");
+        let before = fs::read(&container).unwrap();
+        let error = lock(&server, &container, false, false).unwrap_err();
+        assert!(error.to_string().contains("already a .violet container"), "{}", error);
+        assert_eq!(fs::read(&container).unwrap(), before);
+
+        lock(&server, &container, true, false).unwrap();
+        assert!(!container.exists());
+        assert!(dir.path().join("report.pdf.violet.violet").is_file());
+
+        // An existing destination is never replaced
+        fs::write(&path, b"new version").unwrap();
+        fs::write(&container, b"in the way").unwrap();
+        let error = lock(&server, &path, false, false).unwrap_err();
+        assert!(error.to_string().contains("already exists"), "{}", error);
+        assert_eq!(fs::read(&path).unwrap(), b"new version");
+        assert_eq!(fs::read(&container).unwrap(), b"in the way");
+    }
+
+    #[test]
+    fn test_in_place_handles_the_longest_file_names() {
+        let server = MockKeysServer::with_key("key-a", &"aa".repeat(32));
+        let dir = tempfile::tempdir().unwrap();
+        // With `.violet` added the container's name is 255 bytes, the longest most filesystems allow
+        let path = dir.path().join("n".repeat(255 - CONTAINER_EXTENSION.len() - 1));
+        fs::write(&path, b"long name").unwrap();
+
+        lock(&server, &path, false, false).unwrap();
+        let mut container = path.clone().into_os_string();
+        container.push(".violet");
+        unlock(&server, Path::new(&container), false).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"long name");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_decrypt_leaves_bad_containers_alone() {
+        let server = MockKeysServer::with_key("key-a", &"aa".repeat(32));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, b"meeting notes").unwrap();
+        let error = unlock(&server, &path, false).unwrap_err();
+        assert!(error.to_string().contains("needs a file ending in .violet"), "{}", error);
+
+        lock(&server, &path, false, false).unwrap();
+        let container = dir.path().join("notes.txt.violet");
+        let data = fs::read(&container).unwrap();
+        let mut tampered = data.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+
+        for (name, bad) in [("truncated", &data[..12]), ("tampered", &tampered[..])] {
+            fs::write(&container, bad).unwrap();
+            let error = unlock(&server, &container, false).unwrap_err();
+            assert!(!path.exists(), "{} container restored a file", name);
+            assert_eq!(fs::read(&container).unwrap(), bad, "{} container changed", name);
+            let expected = if name == "truncated" { FailureKind::Parse } else { FailureKind::AuthFailed };
+            assert_eq!(FailureKind::of(&error), expected, "{}: {:#}", name, error);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// Bytes handed over per read when reading on a helper thread
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Read all of `path`, or stdin for `-`
+///
+/// Regular files are read directly. Anything else (a FIFO, a device, or stdin)
+/// may never produce data, so with `timeout` it is opened and read on a helper
+/// thread and reading fails if no data arrives for that long. Opening a FIFO
+/// blocks until a writer connects, which is logged.
+pub fn read_input(path: &str, timeout: Option<Duration>) -> Result<Vec<u8>> {
+    if path == "-" {
+        tracing::debug!("Reading from stdin");
+        return read_all(|| Ok(io::stdin()), "stdin", timeout);
+    }
+
+    let file_type = fs::metadata(path)
+        .with_context(|| format!("Failed to read {}", path))?
+        .file_type();
+    if file_type.is_file() {
+        tracing::debug!("Reading from file: {}", path);
+        let mut buffer = Vec::new();
+        File::open(path)?.read_to_end(&mut buffer)?;
+        return Ok(buffer);
+    }
+
+    if is_fifo(&file_type) {
+        tracing::info!("Waiting for a writer on FIFO {}", path);
+    } else {
+        tracing::debug!("Reading from non-regular file: {}", path);
+    }
+    let owned = path.to_string();
+    read_all(move || File::open(owned), path, timeout)
+}
+
+#[cfg(unix)]
+fn is_fifo(file_type: &fs::FileType) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    file_type.is_fifo()
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_file_type: &fs::FileType) -> bool {
+    false
+}
+
+/// Open and read a source to its end, failing after `timeout` without data
+///
+/// On a timeout the helper thread is left blocked in `open` or `read`; the
+/// process is expected to exit on the error.
+fn read_all<R, F>(open: F, name: &str, timeout: Option<Duration>) -> Result<Vec<u8>>
+where
+    R: Read + 'static,
+    F: FnOnce() -> io::Result<R> + Send + 'static,
+{
+    let Some(timeout) = timeout else {
+        let mut buffer = Vec::new();
+        open()?.read_to_end(&mut buffer)?;
+        return Ok(buffer);
+    };
+
+    // A little buffering lets the reader run ahead while chunks are appended
+    let (sender, receiver) = mpsc::sync_channel::<io::Result<Vec<u8>>>(4);
+    thread::Builder::new()
+        .name("violet-input".into())
+        .spawn(move || {
+            let mut reader = match open() {
+                Ok(reader) => reader,
+                Err(e) => {
+                    sender.send(Err(e)).ok();
+                    return;
+                }
+            };
+            loop {
+                let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+                match reader.read(&mut chunk) {
+                    Ok(0) => return,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        if sender.send(Ok(chunk)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        sender.send(Err(e)).ok();
+                        return;
+                    }
+                }
+            }
+        })
+        .context("Failed to start input reader thread")?;
+
+    let mut buffer = Vec::new();
+    loop {
+        match receiver.recv_timeout(timeout) {
+            Ok(chunk) => {
+                buffer.extend_from_slice(&chunk.with_context(|| format!("Failed to read {}", name))?);
+                tracing::debug!("Read {} bytes from {} so far", buffer.len(), name);
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(buffer),
+            Err(RecvTimeoutError::Timeout) => bail!(
+                "No input from {} for {:?} (--input-timeout); is anything writing to it?",
+                name,
+                timeout
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::path::Path;
+
+    #[test]
+    fn test_regular_file_ignores_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plain.txt");
+        fs::write(&path, b"hello").unwrap();
+        let data = read_input(path.to_str().unwrap(), Some(Duration::from_millis(1))).unwrap();
+        assert_eq!(data, b"hello");
+    }
+
+    #[test]
+    fn test_missing_file_fails() {
+        let err = read_input("/nonexistent/violet-input", None).unwrap_err();
+        assert!(format!("{:#}", err).contains("/nonexistent/violet-input"), "{:#}", err);
+    }
+
+    #[cfg(unix)]
+    fn make_fifo(dir: &Path) -> String {
+        let path = dir.join("input.fifo");
+        let status = std::process::Command::new("mkfifo").arg(&path).status().unwrap();
+        assert!(status.success());
+        path.to_str().unwrap().to_string()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo_without_writer_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = make_fifo(dir.path());
+
+        let err = read_input(&fifo, Some(Duration::from_millis(200))).unwrap_err();
+        assert!(err.to_string().contains("No input from"), "{}", err);
+
+        // Connect and close a writer so the helper thread sees end of input and exits
+        drop(OpenOptions::new().write(true).open(&fifo).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo_with_writer_is_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = make_fifo(dir.path());
+
+        let writer_path = fifo.clone();
+        let writer = thread::spawn(move || {
+            let mut writer = OpenOptions::new().write(true).open(writer_path).unwrap();
+            writer.write_all(b"through a pipe").unwrap();
+        });
+
+        let data = read_input(&fifo, Some(Duration::from_secs(10))).unwrap();
+        assert_eq!(data, b"through a pipe");
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::Serialize;
+use std::io::Write;
+use std::time::Duration;
+use violet_core::crypto::types::GCM_TAG_SIZE;
+use violet_core::{EncryptionEnvelope, PasswordKdf, VioletError, WRAPPED_DEK_SIZE};
+use crate::commands::input::read_input;
+use crate::commands::EnvelopeFormat;
+
+/// Prefix of DEKs wrapped by Vault transit, whose length varies
+const VAULT_CIPHERTEXT_PREFIX: &[u8] = b"vault:";
+
+/// What `violet inspect` reports about an envelope
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inspection {
+    pub key_id: String,
+    pub algorithm: String,
+    pub version: u32,
+    pub iv_len: Option<usize>,
+    pub auth_tag_len: Option<usize>,
+    /// Whether the tag is appended to the ciphertext rather than stored in `authTag`
+    pub combined_tag: bool,
+    /// Ciphertext length in bytes, without the tag
+    pub ciphertext_len: Option<usize>,
+    /// AES-GCM and AES-GCM-SIV ciphertexts are as long as their plaintext
+    pub estimated_plaintext_len: Option<usize>,
+    pub encrypted_key_len: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kek_fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<PasswordKdf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ephemeral_public_key_len: Option<usize>,
+    /// Whether each base64 field decodes, in envelope order
+    pub fields: Vec<FieldCheck>,
+    /// Structural problems found; the envelope is valid when this is empty
+    pub problems: Vec<String>,
+}
+
+/// Base64 decoding result for one envelope field
+#[derive(Debug, Serialize)]
+pub struct FieldCheck {
+    pub name: &'static str,
+    pub decodes: bool,
+}
+
+impl Inspection {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Examine `envelope` without any key material
+///
+/// Every problem is collected rather than stopping at the first, starting
+/// with the one [`EncryptionEnvelope::validate_structure`] reports.
+pub fn inspect(envelope: &EncryptionEnvelope) -> Inspection {
+    let mut problems = Vec::new();
+    match envelope.validate_structure() {
+        Ok(_) => {}
+        Err(VioletError::InvalidEnvelope(problem)) => problems.push(problem),
+        Err(e) => problems.push(e.to_string()),
+    }
+
+    let mut fields = Vec::new();
+    let mut decode = |name: &'static str, value: &str| {
+        let decoded = BASE64.decode(value);
+        fields.push(FieldCheck { name, decodes: decoded.is_ok() });
+        if let Err(e) = &decoded {
+            let problem = format!("{} is not valid base64: {}", name, e);
+            if !problems.contains(&problem) {
+                problems.push(problem);
+            }
+        }
+        decoded.ok()
+    };
+    let encrypted_data = decode("encryptedData", &envelope.encrypted_data);
+    let encrypted_key = decode("encryptedKey", &envelope.encrypted_key);
+    let iv = decode("iv", &envelope.iv);
+    let auth_tag = decode("authTag", &envelope.auth_tag);
+    let ephemeral_public_key = envelope
+        .ephemeral_public_key
+        .as_deref()
+        .and_then(|key| decode("ephemeralPublicKey", key));
+
+    let combined_tag = auth_tag.as_ref().is_some_and(|tag| tag.is_empty());
+    let ciphertext_len = encrypted_data.as_ref().map(|data| {
+        if combined_tag {
+            data.len().saturating_sub(GCM_TAG_SIZE)
+        } else {
+            data.len()
+        }
+    });
+
+    if let Some(wrapped) = &encrypted_key {
+        let remote = wrapped.starts_with(VAULT_CIPHERTEXT_PREFIX);
+        if !remote && !wrapped.is_empty() && wrapped.len() != WRAPPED_DEK_SIZE {
+            problems.push(format!(
+                "encryptedKey is {} bytes; a wrapped DEK is {} (nonce, key and tag)",
+                wrapped.len(),
+                WRAPPED_DEK_SIZE
+            ));
+        }
+    }
+
+    Inspection {
+        key_id: envelope.key_id.clone(),
+        algorithm: envelope.algorithm.clone(),
+        version: envelope.version,
+        iv_len: iv.map(|iv| iv.len()),
+        auth_tag_len: auth_tag.map(|tag| if combined_tag { GCM_TAG_SIZE } else { tag.len() }),
+        combined_tag,
+        ciphertext_len,
+        estimated_plaintext_len: ciphertext_len,
+        encrypted_key_len: encrypted_key.map(|key| key.len()),
+        kek_fingerprint: envelope.kek_fingerprint.clone(),
+        kdf: envelope.kdf.clone(),
+        ephemeral_public_key_len: ephemeral_public_key.map(|key| key.len()),
+        fields,
+        problems,
+    }
+}
+
+/// Print what `inspect` finds in the envelope at `input`
+///
+/// The report is written even for an envelope that fails validation, which
+/// is then returned as `VioletError::InvalidEnvelope`.
+pub fn execute(
+    input: &str,
+    input_timeout: Option<Duration>,
+    format: EnvelopeFormat,
+    json: bool,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let data = read_input(input, input_timeout)
+        .context("Failed to read input")?;
+    let envelope = format.decode(&data).context("Failed to parse envelope")?;
+
+    let inspection = inspect(&envelope);
+    if json {
+        serde_json::to_writer_pretty(&mut *writer, &inspection)?;
+        writeln!(writer)?;
+    } else {
+        write_text(&inspection, writer)?;
+    }
+
+    if !inspection.is_valid() {
+        return Err(VioletError::InvalidEnvelope(format!(
+            "{} structural problem(s): {}",
+            inspection.problems.len(),
+            inspection.problems.join("; ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn write_text(inspection: &Inspection, writer: &mut impl Write) -> Result<()> {
+    let bytes = |len: Option<usize>| match len {
+        Some(len) => format!("{} bytes", len),
+        None => "undecodable".to_string(),
+    };
+
+    writeln!(writer, "keyId:               {}", inspection.key_id)?;
+    writeln!(writer, "algorithm:           {}", inspection.algorithm)?;
+    writeln!(writer, "version:             {}", inspection.version)?;
+    writeln!(writer, "iv:                  {}", bytes(inspection.iv_len))?;
+    let placement = if inspection.combined_tag { " (appended to ciphertext)" } else { "" };
+    writeln!(writer, "authTag:             {}{}", bytes(inspection.auth_tag_len), placement)?;
+    writeln!(writer, "ciphertext:          {}", bytes(inspection.ciphertext_len))?;
+    writeln!(writer, "plaintext (est.):    {}", bytes(inspection.estimated_plaintext_len))?;
+    writeln!(writer, "encryptedKey:        {}", bytes(inspection.encrypted_key_len))?;
+    if let Some(fingerprint) = &inspection.kek_fingerprint {
+        writeln!(writer, "kekFingerprint:      {}", fingerprint)?;
+    }
+    if let Some(kdf) = &inspection.kdf {
+        writeln!(
+            writer,
+            "kdf:                 {}, {} KiB, {} passes, {} lanes",
+            kdf.algorithm, kdf.memory_kib, kdf.iterations, kdf.parallelism
+        )?;
+    }
+    if let Some(len) = inspection.ephemeral_public_key_len {
+        writeln!(writer, "ephemeralPublicKey:  {} bytes", len)?;
+    }
+
+    writeln!(writer, "base64 fields:")?;
+    for field in &inspection.fields {
+        writeln!(writer, "  {:<19}{}", field.name, if field.decodes { "ok" } else { "INVALID" })?;
+    }
+
+    if inspection.is_valid() {
+        writeln!(writer, "status:              ok")?;
+    } else {
+        writeln!(writer, "status:              INVALID")?;
+        for problem in &inspection.problems {
+            writeln!(writer, "  - {}", problem)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::failure::FailureKind;
+    use violet_core::{Algorithm, EnvelopeEncryptor, LocalKekWrapper, LEGACY_ENVELOPE_VERSION};
+
+    const KEK: [u8; 32] = [0xaa; 32];
+
+    fn run(envelope: &[u8], json: bool) -> (String, Result<()>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("env.json");
+        std::fs::write(&path, envelope).unwrap();
+        let mut out = Vec::new();
+        let result = execute(path.to_str().unwrap(), None, EnvelopeFormat::Json, json, &mut out);
+        (String::from_utf8(out).unwrap(), result)
+    }
+
+    #[test]
+    fn test_valid_envelope() {
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .with_kek_fingerprint(true)
+            .encrypt(b"twenty-one bytes long", &KEK, "key-a".into())
+            .unwrap();
+        let (out, result) = run(&serde_json::to_vec(&envelope).unwrap(), false);
+        result.unwrap();
+        assert!(out.contains("keyId:               key-a"), "{}", out);
+        assert!(out.contains("plaintext (est.):    21 bytes"), "{}", out);
+        assert!(out.contains("encryptedKey:        60 bytes"), "{}", out);
+        assert!(out.contains("status:              ok"), "{}", out);
+
+        let (out, result) = run(&serde_json::to_vec(&envelope).unwrap(), true);
+        result.unwrap();
+        let report: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(report["version"], 2);
+        assert_eq!(report["ivLen"], 12);
+        assert_eq!(report["authTagLen"], 16);
+        assert_eq!(report["problems"], serde_json::json!([]));
+        assert!(report["fields"].as_array().unwrap().iter().all(|field| field["decodes"] == true));
+    }
+
+    #[test]
+    fn test_legacy_envelope() {
+        // Version 1 envelopes predate the `version` field
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt_with_wrapper(b"old", &LocalKekWrapper::new(&KEK).unwrap(), "key-a".into())
+            .unwrap();
+        let mut json = serde_json::to_value(&envelope).unwrap();
+        json.as_object_mut().unwrap().remove("version");
+
+        let (out, result) = run(json.to_string().as_bytes(), true);
+        result.unwrap();
+        let report: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(report["version"], LEGACY_ENVELOPE_VERSION);
+        assert_eq!(report["estimatedPlaintextLen"], 3);
+    }
+
+    #[test]
+    fn test_corrupted_envelope_lists_every_problem() {
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"data", &KEK, "key-a".into())
+            .unwrap();
+        let corrupted = EncryptionEnvelope {
+            iv: "not base64!".into(),
+            encrypted_key: BASE64.encode([0u8; 40]),
+            ..envelope
+        };
+
+        let (out, result) = run(&serde_json::to_vec(&corrupted).unwrap(), false);
+        assert!(out.contains("iv                 INVALID"), "{}", out);
+        assert!(out.contains("encryptedKey is 40 bytes"), "{}", out);
+        assert!(out.contains("status:              INVALID"), "{}", out);
+        let error = result.unwrap_err();
+        assert_eq!(FailureKind::of(&error).exit_code(), 2);
+
+        // Not an envelope at all
+        let (_, result) = run(b"{\"keyId\": 3}", false);
+        assert_eq!(FailureKind::of(&result.unwrap_err()).exit_code(), 2);
+    }
+}
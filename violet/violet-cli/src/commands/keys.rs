@@ -0,0 +1,197 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::io::{BufRead, IsTerminal, Write};
+use violet_client::{Key, KeysClient};
+use violet_core::crypto::fingerprint::kek_fingerprint;
+use violet_core::SecretKey;
+
+/// What `keys create` and `keys get` print about a key
+///
+/// The key material is only included when asked for with `--show-secret`;
+/// the fingerprint identifies the key without revealing it.
+#[derive(Debug, Serialize)]
+pub struct KeySummary {
+    pub uuid: String,
+    /// Key length in bytes
+    pub size: usize,
+    pub fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+impl KeySummary {
+    fn new(key: Key, show_secret: bool) -> Result<Self> {
+        let material = SecretKey::from_hex(&key.key)
+            .with_context(|| format!("Keys server sent malformed material for key {}", key.uuid))?;
+        Ok(Self {
+            size: material.len(),
+            fingerprint: kek_fingerprint(&material),
+            key: show_secret.then_some(key.key),
+            uuid: key.uuid,
+        })
+    }
+}
+
+/// Create a key and print its UUID, followed by its material with `show_secret`
+pub fn create(client: &KeysClient, show_secret: bool, json: bool, writer: &mut impl Write) -> Result<()> {
+    let key = client.create_key().context("Failed to create key")?;
+    tracing::info!("Created key {}", key.uuid);
+    let summary = KeySummary::new(key, show_secret)?;
+
+    if json {
+        serde_json::to_writer(&mut *writer, &summary)?;
+        writeln!(writer)?;
+    } else {
+        writeln!(writer, "{}", summary.uuid)?;
+        if let Some(material) = &summary.key {
+            writeln!(writer, "{}", material)?;
+        }
+    }
+    Ok(())
+}
+
+/// Print a key's UUID, size and fingerprint, and its material with `show_secret`
+pub fn get(client: &KeysClient, uuid: &str, show_secret: bool, json: bool, writer: &mut impl Write) -> Result<()> {
+    let key = client.get_key(uuid).with_context(|| format!("Failed to get key {}", uuid))?;
+    let summary = KeySummary::new(key, show_secret)?;
+
+    if json {
+        serde_json::to_writer(&mut *writer, &summary)?;
+        writeln!(writer)?;
+    } else {
+        writeln!(writer, "uuid:        {}", summary.uuid)?;
+        writeln!(writer, "size:        {} bytes", summary.size)?;
+        writeln!(writer, "fingerprint: {}", summary.fingerprint)?;
+        if let Some(material) = &summary.key {
+            writeln!(writer, "key:         {}", material)?;
+        }
+    }
+    Ok(())
+}
+
+/// Delete a key once `confirm` agrees, or straight away with `yes`
+///
+/// Declining is not an error; nothing is deleted and nothing is printed.
+pub fn delete(
+    client: &KeysClient,
+    uuid: &str,
+    yes: bool,
+    json: bool,
+    writer: &mut impl Write,
+    confirm: impl FnOnce(&str) -> Result<bool>,
+) -> Result<()> {
+    if !yes && !confirm(uuid)? {
+        tracing::info!("Not deleting key {}", uuid);
+        return Ok(());
+    }
+
+    client.delete_key(uuid).with_context(|| format!("Failed to delete key {}", uuid))?;
+    tracing::info!("Deleted key {}", uuid);
+
+    if json {
+        serde_json::to_writer(&mut *writer, &serde_json::json!({ "uuid": uuid, "deleted": true }))?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Ask on the terminal whether to delete `uuid`; anything but "y" or "yes" declines
+///
+/// Refuses when stdin is not a terminal, so scripts have to pass `--yes`.
+pub fn confirm_on_terminal(uuid: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        bail!("Refusing to delete key {} without confirmation; pass --yes", uuid);
+    }
+    eprint!("Delete key {}? Envelopes sealed under it can no longer be decrypted [y/N] ", uuid);
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer).context("Failed to read confirmation")?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::failure::FailureKind;
+    use violet_client::testutil::{MockKeysServer, Route};
+
+    fn text(out: Vec<u8>) -> String {
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_create_hides_material_unless_asked() {
+        let server = MockKeysServer::start();
+        let client = KeysClient::new(server.url()).unwrap();
+
+        let mut out = Vec::new();
+        create(&client, false, false, &mut out).unwrap();
+        let uuid = text(out).trim().to_string();
+        assert!(server.contains_key(&uuid));
+
+        let mut out = Vec::new();
+        create(&client, true, true, &mut out).unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(summary["size"], 32);
+        let material = summary["key"].as_str().unwrap();
+        assert_eq!(client.get_key(summary["uuid"].as_str().unwrap()).unwrap().key, material);
+    }
+
+    #[test]
+    fn test_get_shows_size_and_fingerprint() {
+        let server = MockKeysServer::start();
+        server.insert_key(Key {
+            uuid: "seeded".to_string(),
+            key: "ab".repeat(32),
+            algorithm: None,
+        });
+        let client = KeysClient::new(server.url()).unwrap();
+        let fingerprint = kek_fingerprint(&[0xab; 32]);
+
+        let mut out = Vec::new();
+        get(&client, "seeded", false, false, &mut out).unwrap();
+        let out = text(out);
+        assert!(out.contains("size:        32 bytes"), "{}", out);
+        assert!(out.contains(&fingerprint), "{}", out);
+        assert!(!out.contains(&"ab".repeat(32)), "{}", out);
+
+        let mut out = Vec::new();
+        get(&client, "seeded", false, true, &mut out).unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(summary["fingerprint"], fingerprint.as_str());
+        assert!(summary.get("key").is_none());
+
+        let mut out = Vec::new();
+        get(&client, "seeded", true, false, &mut out).unwrap();
+        assert!(text(out).contains(&"ab".repeat(32)));
+    }
+
+    #[test]
+    fn test_delete_asks_unless_yes() {
+        let server = MockKeysServer::start();
+        let client = KeysClient::new(server.url()).unwrap();
+        let uuid = client.create_key().unwrap().uuid;
+
+        delete(&client, &uuid, false, false, &mut Vec::new(), |_| Ok(false)).unwrap();
+        assert!(server.contains_key(&uuid));
+
+        delete(&client, &uuid, true, false, &mut Vec::new(), |_| panic!("--yes must not prompt")).unwrap();
+        assert!(!server.contains_key(&uuid));
+    }
+
+    #[test]
+    fn test_not_found_and_server_errors_exit_differently() {
+        let server = MockKeysServer::start();
+        let client = KeysClient::new(server.url()).unwrap();
+        let exit_code = |outcome: Result<()>| FailureKind::of(&outcome.unwrap_err()).exit_code();
+
+        assert_eq!(exit_code(get(&client, "missing", false, false, &mut Vec::new())), 3);
+        assert_eq!(exit_code(delete(&client, "missing", true, false, &mut Vec::new(), |_| Ok(true))), 3);
+
+        server.respond_with(Route::GetKey, 500, "boom");
+        server.respond_with(Route::CreateKey, 503, "maintenance");
+        assert_eq!(exit_code(get(&client, "missing", false, false, &mut Vec::new())), 5);
+        assert_eq!(exit_code(create(&client, false, false, &mut Vec::new())), 5);
+    }
+}
@@ -0,0 +1,353 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::Duration;
+use violet_core::{Algorithm, EncryptionEnvelope, EnvelopeEncryptor, SecretKey, ENVELOPE_VERSION, LEGACY_ENVELOPE_VERSION};
+use crate::commands::input::read_input;
+use crate::commands::{keys_client, write_output, EnvelopeFormat};
+
+/// Upgrades envelopes from one format version to a newer one, fetching each KEK once
+///
+/// Only the wrapped DEK changes; the encrypted data is copied as it is.
+pub struct Migrator<F> {
+    from: u32,
+    to: u32,
+    keks: HashMap<String, SecretKey>,
+    fetch_kek: F,
+}
+
+/// Outcome of a `violet migrate` run
+#[derive(Debug, Default)]
+pub struct MigrateSummary {
+    /// Envelopes rewritten for the new version
+    pub migrated: usize,
+
+    /// Envelopes already at the new version, written out unchanged
+    pub unchanged: usize,
+
+    /// Where each failed envelope came from (file or line) and why it failed
+    pub failed: Vec<(String, String)>,
+}
+
+impl<F: FnMut(&str) -> Result<SecretKey>> Migrator<F> {
+    /// Fails unless both versions are known and `to` is newer than `from`
+    pub fn new(from: u32, to: u32, fetch_kek: F) -> Result<Self> {
+        for version in [from, to] {
+            if !(LEGACY_ENVELOPE_VERSION..=ENVELOPE_VERSION).contains(&version) {
+                bail!(
+                    "Unknown envelope version {} (known versions: {} to {})",
+                    version,
+                    LEGACY_ENVELOPE_VERSION,
+                    ENVELOPE_VERSION
+                );
+            }
+        }
+        if to <= from {
+            bail!("--to must be a newer envelope version than --from");
+        }
+        Ok(Self { from, to, keks: HashMap::new(), fetch_kek })
+    }
+
+    /// Upgrade one envelope, or return `None` if it is already at the new version
+    pub fn migrate(&mut self, envelope: &EncryptionEnvelope) -> Result<Option<EncryptionEnvelope>> {
+        if envelope.version == self.to {
+            return Ok(None);
+        }
+        if envelope.version != self.from {
+            bail!("Envelope is version {}, not {}", envelope.version, self.from);
+        }
+        if envelope.kdf.is_some() {
+            bail!("Password-protected envelopes cannot be migrated");
+        }
+
+        if !self.keks.contains_key(&envelope.key_id) {
+            let kek = (self.fetch_kek)(&envelope.key_id)?;
+            self.keks.insert(envelope.key_id.clone(), kek);
+        }
+        let algorithm = Algorithm::from_str(&envelope.algorithm)
+            .context("Invalid algorithm in envelope")?;
+        let migrated = EnvelopeEncryptor::new(algorithm)
+            .migrate(envelope, &self.keks[&envelope.key_id], self.to)
+            .context("Failed to rewrap the data key")?;
+        Ok(Some(migrated))
+    }
+}
+
+/// Upgrade envelopes from version `from` to `to`
+///
+/// `input` is a single envelope (a file or `-`), a JSONL file of envelopes
+/// with `jsonl`, or a directory whose envelope files (those with `format`'s
+/// extension) are written to the `output` directory, which may be `input`
+/// itself. Envelopes already at `to` are passed through unchanged.
+#[allow(clippy::too_many_arguments)]
+pub fn execute(
+    server_url: &str,
+    key_cache: bool,
+    input: &str,
+    input_timeout: Option<Duration>,
+    output: &str,
+    from: u32,
+    to: u32,
+    jsonl: bool,
+    keep_going: bool,
+    format: EnvelopeFormat,
+) -> Result<()> {
+    let client = keys_client(server_url, key_cache)
+        .context("Failed to create Keys client")?;
+    let mut migrator = Migrator::new(from, to, |key_id| {
+        let key = client.get_key(key_id)
+            .context("Failed to get key from server")?;
+        SecretKey::from_hex(&key.key).context("Failed to decode key")
+    })?;
+
+    let summary = if input != "-" && Path::new(input).is_dir() {
+        if output == "-" {
+            bail!("Migrating a directory needs --output <DIR>, which may be the input directory");
+        }
+        migrate_dir(&mut migrator, Path::new(input), Path::new(output), format, keep_going)?
+    } else if jsonl {
+        let data = read_input(input, input_timeout)
+            .context("Failed to read input")?;
+        let mut migrated = Vec::new();
+        let summary = migrate_jsonl(&mut migrator, data.as_slice(), &mut migrated, keep_going)?;
+        write_output(output, &migrated)?;
+        summary
+    } else {
+        let data = read_input(input, input_timeout)
+            .context("Failed to read input")?;
+        let envelope = format.decode(&data).context("Failed to parse envelope")?;
+        let mut summary = MigrateSummary::default();
+        let migrated = match migrator.migrate(&envelope)? {
+            Some(migrated) => {
+                summary.migrated += 1;
+                migrated
+            }
+            None => {
+                summary.unchanged += 1;
+                envelope
+            }
+        };
+        write_output(output, &format.encode(&migrated)?)?;
+        summary
+    };
+
+    tracing::info!(
+        "Migrated {} envelopes to version {}, {} already current, {} failed",
+        summary.migrated,
+        to,
+        summary.unchanged,
+        summary.failed.len()
+    );
+    for (source, error) in &summary.failed {
+        tracing::warn!("{}: {}", source, error);
+    }
+    if !summary.failed.is_empty() {
+        bail!("{} envelope(s) could not be migrated", summary.failed.len());
+    }
+    Ok(())
+}
+
+/// Upgrade each non-blank line of `reader`, writing one envelope per line to `writer`
+///
+/// With `keep_going`, a line that fails is written out unchanged and recorded
+/// in the summary; otherwise the first failure is returned as an error.
+pub fn migrate_jsonl<F: FnMut(&str) -> Result<SecretKey>>(
+    migrator: &mut Migrator<F>,
+    reader: impl BufRead,
+    writer: &mut impl Write,
+    keep_going: bool,
+) -> Result<MigrateSummary> {
+    let mut summary = MigrateSummary::default();
+    for (index, line) in reader.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.with_context(|| format!("Failed to read line {}", line_number))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = serde_json::from_str::<EncryptionEnvelope>(&line)
+            .context("Failed to parse envelope JSON")
+            .and_then(|envelope| migrator.migrate(&envelope));
+        let migrated = match result {
+            Ok(Some(migrated)) => {
+                summary.migrated += 1;
+                serde_json::to_string(&migrated)?
+            }
+            Ok(None) => {
+                summary.unchanged += 1;
+                line
+            }
+            Err(e) if keep_going => {
+                summary.failed.push((format!("Line {}", line_number), format!("{:#}", e)));
+                line
+            }
+            Err(e) => return Err(e.context(format!("Line {}", line_number))),
+        };
+        writeln!(writer, "{}", migrated)?;
+    }
+    Ok(summary)
+}
+
+/// Upgrade every envelope file in `input` with `format`'s extension into `output`
+///
+/// Files are processed in name order. With `keep_going`, files that fail are
+/// skipped and recorded in the summary.
+pub fn migrate_dir<F: FnMut(&str) -> Result<SecretKey>>(
+    migrator: &mut Migrator<F>,
+    input: &Path,
+    output: &Path,
+    format: EnvelopeFormat,
+    keep_going: bool,
+) -> Result<MigrateSummary> {
+    let mut paths = std::fs::read_dir(input)
+        .with_context(|| format!("Failed to read directory {}", input.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.retain(|path| path.is_file() && path.extension().is_some_and(|ext| ext == format.extension()));
+    paths.sort();
+    std::fs::create_dir_all(output)
+        .with_context(|| format!("Failed to create output directory {}", output.display()))?;
+
+    let mut summary = MigrateSummary::default();
+    for path in paths {
+        let result = std::fs::read(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))
+            .and_then(|data| format.decode(&data).context("Failed to parse envelope"))
+            .and_then(|envelope| {
+                let migrated = migrator.migrate(&envelope)?;
+                let target = output.join(path.file_name().expect("read_dir entries have a file name"));
+                // Leave an unchanged envelope alone when migrating in place
+                if migrated.is_some() || target != path {
+                    std::fs::write(&target, format.encode(migrated.as_ref().unwrap_or(&envelope))?)
+                        .with_context(|| format!("Failed to write {}", target.display()))?;
+                }
+                Ok(migrated.is_some())
+            });
+        match result {
+            Ok(true) => summary.migrated += 1,
+            Ok(false) => summary.unchanged += 1,
+            Err(e) if keep_going => summary.failed.push((path.display().to_string(), format!("{:#}", e))),
+            Err(e) => return Err(e.context(path.display().to_string())),
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use violet_core::LocalKekWrapper;
+
+    const KEK_A: [u8; 32] = [0xaa; 32];
+    const KEK_B: [u8; 32] = [0xbb; 32];
+
+    fn fetch(fetched: &mut Vec<String>, key_id: &str) -> Result<SecretKey> {
+        fetched.push(key_id.to_string());
+        match key_id {
+            "key-a" => Ok(SecretKey::new(&KEK_A)),
+            "key-b" => Ok(SecretKey::new(&KEK_B)),
+            _ => bail!("Key not found: {}", key_id),
+        }
+    }
+
+    /// A version 1 envelope, whose DEK is wrapped directly under `kek`
+    fn v1_envelope(plaintext: &[u8], kek: &[u8; 32], key_id: &str) -> EncryptionEnvelope {
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt_with_wrapper(plaintext, &LocalKekWrapper::new(kek).unwrap(), key_id.to_string())
+            .unwrap();
+        EncryptionEnvelope { version: LEGACY_ENVELOPE_VERSION, ..envelope }
+    }
+
+    #[test]
+    fn test_upgrades_v1_envelope_to_v2() {
+        let legacy = v1_envelope(b"stored long ago", &KEK_A, "key-a");
+        let mut fetched = Vec::new();
+        let mut migrator = Migrator::new(1, 2, |id| fetch(&mut fetched, id)).unwrap();
+
+        let migrated = migrator.migrate(&legacy).unwrap().unwrap();
+        assert_eq!(migrated.version, 2);
+        assert_eq!(migrated.encrypted_data, legacy.encrypted_data);
+
+        // The new DEK wrapping is the v2 one: under a key derived from the KEK
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let derived = LocalKekWrapper::derived(&KEK_A).unwrap();
+        assert_eq!(encryptor.decrypt_with_wrapper(&migrated, &derived).unwrap(), b"stored long ago");
+        assert_eq!(encryptor.decrypt(&migrated, &KEK_A).unwrap(), b"stored long ago");
+
+        // Already-current envelopes are left alone
+        assert!(migrator.migrate(&migrated).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rejects_unknown_versions_and_downgrades() {
+        let no_keys = |_: &str| -> Result<SecretKey> { bail!("no keys") };
+        assert!(Migrator::new(2, 1, no_keys).is_err());
+        assert!(Migrator::new(1, 1, no_keys).is_err());
+        assert!(Migrator::new(1, 9, no_keys).is_err());
+        assert!(Migrator::new(0, 2, no_keys).is_err());
+    }
+
+    #[test]
+    fn test_jsonl_keep_going() {
+        let current = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"current", &KEK_B, "key-b".into())
+            .unwrap();
+        let lines = [
+            serde_json::to_string(&v1_envelope(b"first", &KEK_A, "key-a")).unwrap(),
+            "{not json".to_string(),
+            String::new(),
+            serde_json::to_string(&current).unwrap(),
+            serde_json::to_string(&v1_envelope(b"second", &KEK_A, "key-a")).unwrap(),
+            serde_json::to_string(&v1_envelope(b"lost", &KEK_A, "key-missing")).unwrap(),
+        ];
+        let mut fetched = Vec::new();
+        let mut migrator = Migrator::new(1, 2, |id| fetch(&mut fetched, id)).unwrap();
+
+        let mut out = Vec::new();
+        let summary = migrate_jsonl(&mut migrator, lines.join("\n").as_bytes(), &mut out, true).unwrap();
+        assert_eq!((summary.migrated, summary.unchanged), (2, 1));
+        let failed: Vec<_> = summary.failed.iter().map(|(source, _)| source.as_str()).collect();
+        assert_eq!(failed, ["Line 2", "Line 6"]);
+        drop(migrator);
+        assert_eq!(fetched, ["key-a", "key-missing"]);
+
+        // Every line is kept, in order; failed lines unchanged
+        let out = String::from_utf8(out).unwrap();
+        let out: Vec<&str> = out.lines().collect();
+        assert_eq!(out.len(), 5);
+        assert_eq!(out[1], "{not json");
+        assert_eq!(out[4], lines[5]);
+        let second: EncryptionEnvelope = serde_json::from_str(out[3]).unwrap();
+        assert_eq!(second.version, 2);
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        assert_eq!(encryptor.decrypt(&second, &KEK_A).unwrap(), b"second");
+
+        let mut migrator = Migrator::new(1, 2, |id| fetch(&mut Vec::new(), id)).unwrap();
+        let error = migrate_jsonl(&mut migrator, lines.join("\n").as_bytes(), &mut Vec::new(), false).unwrap_err();
+        assert!(error.to_string().contains("Line 2"), "{}", error);
+    }
+
+    #[test]
+    fn test_directory_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let format = EnvelopeFormat::Json;
+        std::fs::write(dir.path().join("a.json"), format.encode(&v1_envelope(b"a", &KEK_A, "key-a")).unwrap()).unwrap();
+        std::fs::write(dir.path().join("b.json"), format.encode(&v1_envelope(b"b", &KEK_B, "key-b")).unwrap()).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "not an envelope").unwrap();
+
+        let mut fetched = Vec::new();
+        let mut migrator = Migrator::new(1, 2, |id| fetch(&mut fetched, id)).unwrap();
+        let summary = migrate_dir(&mut migrator, dir.path(), dir.path(), format, false).unwrap();
+        assert_eq!((summary.migrated, summary.unchanged), (2, 0));
+
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let b = format.decode(&std::fs::read(dir.path().join("b.json")).unwrap()).unwrap();
+        assert_eq!(b.version, 2);
+        assert_eq!(encryptor.decrypt(&b, &KEK_B).unwrap(), b"b");
+
+        // A second run finds nothing left to do
+        let summary = migrate_dir(&mut migrator, dir.path(), dir.path(), format, false).unwrap();
+        assert_eq!((summary.migrated, summary.unchanged), (0, 2));
+    }
+}
@@ -1,3 +1,227 @@
+pub mod algorithms;
+pub mod cache;
 pub mod encrypt;
 pub mod decrypt;
+pub mod failure;
+pub mod in_place;
+pub mod input;
+pub mod inspect;
+pub mod keys;
+pub mod migrate;
+pub mod parallel;
 pub mod daemon;
+pub mod daemon_config;
+pub mod report;
+pub mod rewrap;
+pub mod selftest;
+pub mod store;
+pub mod tree;
+pub mod verify;
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::{self, Write};
+use violet_client::cache::DEFAULT_CACHE_TTL;
+use violet_client::{KeyCache, KeysClient};
+use violet_core::{is_container, EncryptionEnvelope, EnvelopeStore};
+
+/// Serialization format for envelopes read and written by the CLI
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EnvelopeFormat {
+    /// Pretty-printed JSON with base64 fields
+    #[default]
+    Json,
+    /// JSON on a single line, for piping into other tools
+    JsonCompact,
+    /// CBOR with raw binary fields
+    #[value(alias = "binary")]
+    Cbor,
+    /// A .violet container in base64 between BEGIN and END lines, safe to paste as text
+    Armor,
+    /// Reading only: tell JSON, armor, .violet containers and CBOR apart by their first bytes
+    Auto,
+}
+
+impl EnvelopeFormat {
+    /// Name of the format on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            EnvelopeFormat::Json => "json",
+            EnvelopeFormat::JsonCompact => "json-compact",
+            EnvelopeFormat::Cbor => "cbor",
+            EnvelopeFormat::Armor => "armor",
+            EnvelopeFormat::Auto => "auto",
+        }
+    }
+
+    /// File extension for envelopes in this format
+    ///
+    /// `auto` has none of its own and looks for `.json` files where it has to pick.
+    pub fn extension(self) -> &'static str {
+        match self {
+            EnvelopeFormat::Json | EnvelopeFormat::JsonCompact | EnvelopeFormat::Auto => "json",
+            EnvelopeFormat::Cbor => "cbor",
+            EnvelopeFormat::Armor => "asc",
+        }
+    }
+
+    pub fn encode(self, envelope: &EncryptionEnvelope) -> Result<Vec<u8>> {
+        match self {
+            EnvelopeFormat::Json => Ok(serde_json::to_vec_pretty(envelope)?),
+            EnvelopeFormat::JsonCompact => Ok(serde_json::to_vec(envelope)?),
+            EnvelopeFormat::Cbor => Ok(envelope.to_cbor()?),
+            EnvelopeFormat::Armor => Ok(envelope.to_armored()?.into_bytes()),
+            EnvelopeFormat::Auto => bail!("--format auto only applies to reading envelopes; pick a format to write"),
+        }
+    }
+
+    pub fn decode(self, data: &[u8]) -> Result<EncryptionEnvelope> {
+        match self {
+            EnvelopeFormat::Json | EnvelopeFormat::JsonCompact => Ok(serde_json::from_slice(data)?),
+            EnvelopeFormat::Cbor => Ok(EncryptionEnvelope::from_cbor(data)?),
+            EnvelopeFormat::Armor => {
+                let text = std::str::from_utf8(data).context("Armored envelope is not UTF-8 text")?;
+                Ok(EncryptionEnvelope::from_armored(text)?)
+            }
+            EnvelopeFormat::Auto if is_container(data) => Ok(EncryptionEnvelope::from_container(data)?),
+            EnvelopeFormat::Auto => Self::detect(data).decode(data),
+        }
+    }
+
+    /// The format `data` is in, judged by its first non-whitespace bytes:
+    /// `{` is JSON, `-----BEGIN` armor, and anything else CBOR
+    fn detect(data: &[u8]) -> Self {
+        let start = data.iter().position(|byte| !byte.is_ascii_whitespace()).map_or(&[][..], |at| &data[at..]);
+        if start.starts_with(b"{") {
+            EnvelopeFormat::Json
+        } else if start.starts_with(b"-----BEGIN") {
+            EnvelopeFormat::Armor
+        } else {
+            EnvelopeFormat::Cbor
+        }
+    }
+}
+
+/// Where encrypt writes its envelope, or decrypt reads it from
+pub enum EnvelopeLocation<'a> {
+    /// A file, or '-' for stdin/stdout, in the command's `--format`
+    Path(&'a str),
+
+    /// A named entry in an envelope store
+    Store {
+        store: &'a dyn EnvelopeStore,
+        name: &'a str,
+        /// Replace an existing entry when writing
+        overwrite: bool,
+    },
+}
+
+/// Read a password from the terminal without echoing it
+///
+/// With `confirm`, the password is asked for twice and must match. Empty
+/// passwords are refused.
+pub fn prompt_password(confirm: bool) -> Result<String> {
+    let password = rpassword::prompt_password("Password: ").context("Failed to read password")?;
+    if password.is_empty() {
+        anyhow::bail!("Password must not be empty");
+    }
+    if confirm {
+        let again = rpassword::prompt_password("Confirm password: ").context("Failed to read password")?;
+        if again != password {
+            anyhow::bail!("Passwords do not match");
+        }
+    }
+    Ok(password)
+}
+
+/// Write `data` to the file at `path`, or to stdout if `path` is `-`
+pub fn write_output(path: &str, data: &[u8]) -> Result<()> {
+    if path == "-" {
+        tracing::debug!("Writing to stdout");
+        let mut stdout = io::stdout().lock();
+        stdout
+            .write_all(data)
+            .and_then(|()| stdout.flush())
+            .context("Failed to write output")
+    } else {
+        tracing::debug!("Writing to file: {}", path);
+        fs::write(path, data).with_context(|| format!("Failed to write {}", path))
+    }
+}
+
+/// Build a Keys client, optionally backed by the on-disk key cache
+pub fn keys_client(server_url: &str, key_cache: bool) -> Result<KeysClient> {
+    let mut builder = KeysClient::builder(server_url);
+    if key_cache {
+        let dir = KeyCache::default_dir()
+            .context("Unable to determine key cache directory (HOME is not set)")?;
+        tracing::debug!("Using key cache: {}", dir.display());
+        builder = builder.key_cache(KeyCache::new(dir, DEFAULT_CACHE_TTL));
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use violet_core::{Algorithm, EnvelopeEncryptor};
+
+    fn roundtrip(format: EnvelopeFormat) {
+        let kek = [3u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let envelope = encryptor.encrypt(b"format roundtrip", &kek, "key".to_string()).unwrap();
+
+        let encoded = format.encode(&envelope).unwrap();
+        let decoded = format.decode(&encoded).unwrap();
+
+        assert_eq!(decoded, envelope);
+        assert_eq!(encryptor.decrypt(&decoded, &kek).unwrap(), b"format roundtrip");
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        roundtrip(EnvelopeFormat::Json);
+    }
+
+    #[test]
+    fn test_cbor_roundtrip() {
+        roundtrip(EnvelopeFormat::Cbor);
+    }
+
+    #[test]
+    fn test_compact_and_armor_roundtrip() {
+        roundtrip(EnvelopeFormat::JsonCompact);
+        roundtrip(EnvelopeFormat::Armor);
+    }
+
+    #[test]
+    fn test_auto_reads_every_format() {
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"data", &[3u8; 32], "key".to_string())
+            .unwrap();
+
+        for format in [EnvelopeFormat::Json, EnvelopeFormat::JsonCompact, EnvelopeFormat::Cbor, EnvelopeFormat::Armor] {
+            let encoded = format.encode(&envelope).unwrap();
+            assert_eq!(EnvelopeFormat::Auto.decode(&encoded).unwrap(), envelope, "{}", format.name());
+            let padded = [b"\n \t".as_slice(), &encoded].concat();
+            if format != EnvelopeFormat::Cbor {
+                assert_eq!(EnvelopeFormat::Auto.decode(&padded).unwrap(), envelope, "padded {}", format.name());
+            }
+        }
+        let container = envelope.to_container().unwrap();
+        assert_eq!(EnvelopeFormat::Auto.decode(&container).unwrap(), envelope);
+
+        assert!(EnvelopeFormat::Auto.decode(b"neither").is_err());
+        assert!(EnvelopeFormat::Auto.encode(&envelope).is_err());
+    }
+
+    #[test]
+    fn test_formats_are_not_interchangeable() {
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"data", &[3u8; 32], "key".to_string())
+            .unwrap();
+
+        let cbor = EnvelopeFormat::Cbor.encode(&envelope).unwrap();
+        assert!(EnvelopeFormat::Json.decode(&cbor).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Apply `f` to every item on up to `jobs` threads, returning the results in item order
+///
+/// Each thread takes the next unclaimed item as soon as it is free, so a slow
+/// item does not hold up the rest. `jobs` of 0 is treated as 1.
+pub fn map_in_order<T, R, F>(items: &[T], jobs: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every item is processed"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_keep_item_order() {
+        let items: Vec<u64> = (0..50).collect();
+        let doubled = map_in_order(&items, 8, |n| {
+            // Later items finish first
+            std::thread::sleep(std::time::Duration::from_micros(50 - n));
+            n * 2
+        });
+        assert_eq!(doubled, items.iter().map(|n| n * 2).collect::<Vec<_>>());
+
+        assert_eq!(map_in_order(&[1, 2], 0, |n| n + 1), [2, 3]);
+        assert!(map_in_order(&[] as &[u8], 4, |n| *n).is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::io::Write;
+
+/// How `encrypt` and `decrypt` report their outcome on stdout
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Nothing beyond the command's output; errors go to stderr
+    #[default]
+    Text,
+    /// One JSON result object on stdout, whether the command succeeds or fails
+    Json,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Error,
+}
+
+/// Machine-readable outcome of an encrypt or decrypt
+#[derive(Serialize, Debug)]
+pub struct CommandResult {
+    pub status: Status,
+    pub operation: &'static str,
+
+    /// Key the data was encrypted or decrypted under, if there was a single one
+    pub key_id: Option<String>,
+
+    /// Plaintext bytes encrypted or decrypted
+    pub bytes_processed: u64,
+
+    pub algorithm: Option<String>,
+    pub errors: Vec<String>,
+}
+
+impl CommandResult {
+    pub fn success(operation: &'static str, key_id: &str, algorithm: &str, bytes_processed: usize) -> Self {
+        Self {
+            status: Status::Ok,
+            operation,
+            key_id: Some(key_id.to_string()),
+            bytes_processed: bytes_processed as u64,
+            algorithm: Some(algorithm.to_string()),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn failure(operation: &'static str, error: &anyhow::Error) -> Self {
+        Self {
+            status: Status::Error,
+            operation,
+            key_id: None,
+            bytes_processed: 0,
+            algorithm: None,
+            errors: vec![format!("{:#}", error)],
+        }
+    }
+}
+
+/// Refuse to mix a JSON result with data written to stdout
+pub fn check_output(format: OutputFormat, output: &str) -> Result<()> {
+    if format == OutputFormat::Json && output == "-" {
+        bail!("--output-format json writes the result to stdout; use --output <file> for the data");
+    }
+    Ok(())
+}
+
+/// Report the outcome of `operation` in `format`, returning an error if it failed
+///
+/// In JSON mode the result is written to `writer` as a single line even when
+/// the command failed, so scripts can always parse stdout; the exit status
+/// still reflects the failure.
+pub fn report(
+    format: OutputFormat,
+    operation: &'static str,
+    outcome: Result<CommandResult>,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let (result, error) = match outcome {
+        Ok(result) => (result, None),
+        Err(e) => (CommandResult::failure(operation, &e), Some(e)),
+    };
+
+    if format == OutputFormat::Json {
+        serde_json::to_writer(&mut *writer, &result)?;
+        writeln!(writer)?;
+        writer.flush()?;
+    }
+
+    match error {
+        Some(e) => Err(e),
+        None if result.status == Status::Error => {
+            bail!("{} failed with {} error(s)", operation, result.errors.len())
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn report_json(outcome: Result<CommandResult>) -> (serde_json::Value, Result<()>) {
+        let mut stdout = Vec::new();
+        let returned = report(OutputFormat::Json, "encrypt", outcome, &mut stdout);
+        let text = String::from_utf8(stdout).unwrap();
+        assert_eq!(text.lines().count(), 1, "{}", text);
+        (serde_json::from_str(&text).unwrap(), returned)
+    }
+
+    #[test]
+    fn test_success_json() {
+        let (json, returned) =
+            report_json(Ok(CommandResult::success("encrypt", "key-1", "AES-256-GCM", 12)));
+
+        assert!(returned.is_ok());
+        assert_eq!(json["status"], "ok");
+        assert_eq!(json["operation"], "encrypt");
+        assert_eq!(json["key_id"], "key-1");
+        assert_eq!(json["bytes_processed"], 12);
+        assert_eq!(json["algorithm"], "AES-256-GCM");
+        assert_eq!(json["errors"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_failure_json_and_error_returned() {
+        let error = anyhow!("connection refused").context("Failed to get key from server");
+        let (json, returned) = report_json(Err(error));
+
+        assert!(returned.is_err());
+        assert_eq!(json["status"], "error");
+        assert_eq!(json["key_id"], serde_json::Value::Null);
+        assert_eq!(json["bytes_processed"], 0);
+        assert_eq!(
+            json["errors"],
+            serde_json::json!(["Failed to get key from server: connection refused"])
+        );
+    }
+
+    #[test]
+    fn test_partial_failure_is_an_error() {
+        let result = CommandResult {
+            status: Status::Error,
+            errors: vec!["Line 2: Decryption failed".into()],
+            ..CommandResult::success("decrypt", "key-1", "AES-256-GCM", 5)
+        };
+        let (json, returned) = report_json(Ok(result));
+
+        assert!(returned.is_err());
+        assert_eq!(json["status"], "error");
+        assert_eq!(json["bytes_processed"], 5);
+    }
+
+    #[test]
+    fn test_text_writes_nothing() {
+        let mut stdout = Vec::new();
+        let outcome = Ok(CommandResult::success("decrypt", "key-1", "AES-256-GCM", 3));
+        report(OutputFormat::Text, "decrypt", outcome, &mut stdout).unwrap();
+        assert!(stdout.is_empty());
+
+        assert!(report(OutputFormat::Text, "decrypt", Err(anyhow!("boom")), &mut stdout).is_err());
+        assert!(stdout.is_empty());
+    }
+
+    #[test]
+    fn test_json_requires_output_file() {
+        assert!(check_output(OutputFormat::Json, "-").is_err());
+        assert!(check_output(OutputFormat::Json, "out.bin").is_ok());
+        assert!(check_output(OutputFormat::Text, "-").is_ok());
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use violet_core::{Algorithm, EncryptionEnvelope, EnvelopeEncryptor, SecretKey};
+use crate::commands::input::read_input;
+use crate::commands::{keys_client, write_output, EnvelopeFormat};
+
+/// Move an envelope to a new KEK without decrypting its data
+///
+/// The old KEK is fetched for the envelope's `key_id`, and the new one for
+/// `new_key_id`, or created on the Keys server when it is `None`. The DEK is
+/// only ever unwrapped in memory. With `in_place`, `input` is replaced
+/// atomically and `output` is ignored.
+#[allow(clippy::too_many_arguments)]
+pub fn execute(
+    server_url: &str,
+    key_cache: bool,
+    input: &str,
+    input_timeout: Option<Duration>,
+    output: &str,
+    in_place: bool,
+    new_key_id: Option<&str>,
+    format: EnvelopeFormat,
+) -> Result<()> {
+    if in_place && input == "-" {
+        bail!("--in-place needs --input <FILE>, not stdin");
+    }
+
+    let data = read_input(input, input_timeout)
+        .context("Failed to read input")?;
+    let envelope = format.decode(&data).context("Failed to parse envelope")?;
+
+    let client = keys_client(server_url, key_cache)
+        .context("Failed to create Keys client")?;
+    let old_key = client.get_key(&envelope.key_id)
+        .context("Failed to get key from server")?;
+    let new_key = match new_key_id {
+        Some(key_id) => client.get_key(key_id)
+            .context("Failed to get new key from server")?,
+        None => {
+            let key = client.create_key()
+                .context("Failed to create new key")?;
+            tracing::info!("Created new key: {}", key.uuid);
+            key
+        }
+    };
+    let old_kek = SecretKey::from_hex(&old_key.key).context("Failed to decode key")?;
+    let new_kek = SecretKey::from_hex(&new_key.key).context("Failed to decode new key")?;
+
+    let rewrapped = rewrap(&envelope, &old_kek, &new_kek, new_key.uuid)?;
+    let encoded = format.encode(&rewrapped)?;
+    if in_place {
+        replace_file(Path::new(input), &encoded)?;
+    } else {
+        write_output(output, &encoded)?;
+    }
+
+    tracing::info!("Rewrapped envelope from key {} to key {}", envelope.key_id, rewrapped.key_id);
+    Ok(())
+}
+
+/// Wrap `envelope`'s DEK under `new_kek` instead of `old_kek`, leaving the data as it is
+pub fn rewrap(
+    envelope: &EncryptionEnvelope,
+    old_kek: &[u8],
+    new_kek: &[u8],
+    new_key_id: String,
+) -> Result<EncryptionEnvelope> {
+    if envelope.kdf.is_some() {
+        bail!("Password-protected envelopes cannot be rewrapped; decrypt and encrypt them again");
+    }
+    if new_key_id == envelope.key_id {
+        bail!("Envelope is already under key {}", new_key_id);
+    }
+
+    let algorithm = Algorithm::from_str(&envelope.algorithm)
+        .context("Invalid algorithm in envelope")?;
+    EnvelopeEncryptor::new(algorithm)
+        .rewrap(envelope, old_kek, new_kek, new_key_id)
+        .context("Failed to rewrap the data key")
+}
+
+/// Replace `path` with `data` so readers see either the old or the new file, never a mix
+///
+/// The new contents are written next to `path` and renamed over it, keeping
+/// the original file's permissions.
+fn replace_file(path: &Path, data: &[u8]) -> Result<()> {
+    let permissions = fs::metadata(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .permissions();
+    let file_name = path.file_name()
+        .with_context(|| format!("{} is not a file", path.display()))?;
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name.to_string_lossy(), std::process::id()));
+
+    let result = fs::write(&tmp_path, data)
+        .and_then(|()| fs::set_permissions(&tmp_path, permissions))
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result.with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use violet_client::testutil::MockKeysServer;
+    use violet_client::Key;
+
+    const OLD_KEK: [u8; 32] = [0xaa; 32];
+    const NEW_KEK: [u8; 32] = [0xbb; 32];
+
+    fn server_with(keys: &[(&str, &[u8; 32])]) -> MockKeysServer {
+        let server = MockKeysServer::start();
+        for (uuid, kek) in keys {
+            server.insert_key(Key {
+                uuid: uuid.to_string(),
+                key: kek.iter().map(|byte| format!("{:02x}", byte)).collect(),
+                algorithm: None,
+            });
+        }
+        server
+    }
+
+    fn run(server: &MockKeysServer, input: &Path, output: &Path, in_place: bool, new_key_id: Option<&str>) -> Result<()> {
+        execute(
+            server.url(),
+            false,
+            input.to_str().unwrap(),
+            None,
+            output.to_str().unwrap(),
+            in_place,
+            new_key_id,
+            EnvelopeFormat::Json,
+        )
+    }
+
+    fn read(path: &Path) -> EncryptionEnvelope {
+        serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_rewrap_to_named_key_keeps_ciphertext() {
+        let server = server_with(&[("old", &OLD_KEK), ("new", &NEW_KEK)]);
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("env.json");
+        let output = dir.path().join("env-new.json");
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let original = encryptor.encrypt(b"quarterly", &OLD_KEK, "old".into()).unwrap();
+        fs::write(&input, serde_json::to_vec(&original).unwrap()).unwrap();
+
+        run(&server, &input, &output, false, Some("new")).unwrap();
+        let rewrapped = read(&output);
+        assert_eq!(rewrapped.key_id, "new");
+        assert_eq!(rewrapped.encrypted_data, original.encrypted_data);
+        assert_ne!(rewrapped.encrypted_key, original.encrypted_key);
+        assert_eq!(encryptor.decrypt(&rewrapped, &NEW_KEK).unwrap(), b"quarterly");
+        assert!(encryptor.decrypt(&rewrapped, &OLD_KEK).is_err());
+
+        // The input is left alone
+        assert_eq!(read(&input), original);
+    }
+
+    #[test]
+    fn test_rewrap_in_place_to_created_key() {
+        let server = server_with(&[("old", &OLD_KEK)]);
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("env.json");
+        let original = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv)
+            .encrypt(b"in place", &OLD_KEK, "old".into())
+            .unwrap();
+        fs::write(&input, serde_json::to_vec(&original).unwrap()).unwrap();
+
+        run(&server, &input, Path::new("-"), true, None).unwrap();
+        let rewrapped = read(&input);
+        assert_ne!(rewrapped.key_id, "old");
+        assert!(server.contains_key(&rewrapped.key_id));
+        assert_eq!(rewrapped.encrypted_data, original.encrypted_data);
+        let entries: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(entries.len(), 1, "temporary file left behind");
+
+        let new_key = violet_client::KeysClient::new(server.url()).unwrap().get_key(&rewrapped.key_id).unwrap();
+        let new_kek = SecretKey::from_hex(&new_key.key).unwrap();
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv);
+        assert_eq!(encryptor.decrypt(&rewrapped, &new_kek).unwrap(), b"in place");
+        assert!(encryptor.decrypt(&rewrapped, &OLD_KEK).is_err());
+    }
+
+    #[test]
+    fn test_refuses_same_key_and_password_envelopes() {
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let envelope = encryptor.encrypt(b"data", &OLD_KEK, "old".into()).unwrap();
+        assert!(rewrap(&envelope, &OLD_KEK, &OLD_KEK, "old".into()).is_err());
+
+        let password = EncryptionEnvelope {
+            kdf: Some(violet_core::PasswordKdf::with_params(8, 1, 1)),
+            ..envelope
+        };
+        let error = rewrap(&password, &OLD_KEK, &NEW_KEK, "new".into()).unwrap_err();
+        assert!(error.to_string().contains("Password-protected"), "{}", error);
+    }
+}
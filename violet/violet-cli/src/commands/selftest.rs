@@ -0,0 +1,200 @@
+use anyhow::{bail, ensure, Result};
+use std::io::Write;
+use violet_client::KeysClient;
+use violet_core::crypto::kat;
+use violet_core::{Algorithm, EnvelopeEncryptor};
+use crate::commands::EnvelopeFormat;
+
+/// Outcome of one self-test check
+pub struct Check {
+    pub name: String,
+    pub result: Result<()>,
+}
+
+impl Check {
+    fn run(name: impl Into<String>, check: impl FnOnce() -> Result<()>) -> Self {
+        Self {
+            name: name.into(),
+            result: check(),
+        }
+    }
+}
+
+/// Run every self-test check, printing PASS or FAIL for each
+///
+/// With `server_url`, a key is also created on and fetched from the Keys
+/// server. Fails if any check failed.
+pub async fn execute(server_url: Option<&str>, writer: &mut impl Write) -> Result<()> {
+    let mut checks = offline_checks();
+    if let Some(server_url) = server_url {
+        // The Keys client is blocking, so keep it off the runtime
+        let server_url = server_url.to_string();
+        checks.extend(tokio::task::spawn_blocking(move || server_checks(&server_url)).await?);
+    }
+
+    let failed = report(&checks, writer)?;
+    if failed > 0 {
+        bail!("{} of {} self-test checks failed", failed, checks.len());
+    }
+    tracing::info!("All {} self-test checks passed", checks.len());
+    Ok(())
+}
+
+/// Write one line per check, returning the number that failed
+fn report(checks: &[Check], writer: &mut impl Write) -> Result<usize> {
+    let mut failed = 0;
+    for check in checks {
+        match &check.result {
+            Ok(()) => writeln!(writer, "PASS  {}", check.name)?,
+            Err(e) => {
+                failed += 1;
+                writeln!(writer, "FAIL  {}: {:#}", check.name, e)?;
+            }
+        }
+    }
+    Ok(failed)
+}
+
+/// Checks that need nothing but this binary
+pub fn offline_checks() -> Vec<Check> {
+    let mut checks: Vec<Check> = kat::VECTORS
+        .iter()
+        .map(|vector| Check::run(format!("known answer: {}", vector.name), || Ok(vector.check()?)))
+        .collect();
+    checks.push(Check::run("known answer: HKDF wrapping key", || {
+        Ok(kat::check_wrapping_key_derivation()?)
+    }));
+
+    for algorithm in Algorithm::all() {
+        checks.push(Check::run(format!("envelope round trip: {}", algorithm.as_str()), || {
+            envelope_round_trip(*algorithm)
+        }));
+    }
+    for (format, name) in [(EnvelopeFormat::Json, "JSON"), (EnvelopeFormat::Cbor, "CBOR")] {
+        checks.push(Check::run(format!("envelope serialization: {}", name), || {
+            serialization_round_trip(format)
+        }));
+    }
+    checks
+}
+
+/// KEKs for the offline checks; the envelopes are never kept, so fixed keys are fine
+const KEK: [u8; 32] = [0x5a; 32];
+const OTHER_KEK: [u8; 32] = [0xa5; 32];
+
+/// Encrypt and decrypt under a fixed KEK, and check that tampering and the
+/// wrong KEK are both detected
+fn envelope_round_trip(algorithm: Algorithm) -> Result<()> {
+    let kek = KEK;
+    let plaintext = b"violet self-test plaintext";
+    let encryptor = EnvelopeEncryptor::new(algorithm);
+
+    let envelope = encryptor.encrypt(plaintext, &kek, "selftest".to_string())?;
+    ensure!(envelope.algorithm == algorithm.as_str(), "envelope records {}", envelope.algorithm);
+    ensure!(encryptor.decrypt(&envelope, &kek)? == plaintext, "decrypted plaintext differs");
+
+    let mut tampered = envelope.clone();
+    tampered.auth_tag = corrupt_base64(&tampered.auth_tag);
+    ensure!(encryptor.decrypt(&tampered, &kek).is_err(), "tampered envelope was accepted");
+    ensure!(encryptor.decrypt(&envelope, &OTHER_KEK).is_err(), "wrong KEK was accepted");
+    Ok(())
+}
+
+fn serialization_round_trip(format: EnvelopeFormat) -> Result<()> {
+    let kek = KEK;
+    let encryptor = EnvelopeEncryptor::new(Algorithm::default());
+    let envelope = encryptor.encrypt(b"serialization", &kek, "selftest".to_string())?;
+
+    let decoded = format.decode(&format.encode(&envelope)?)?;
+    ensure!(decoded == envelope, "envelope changed in serialization");
+    ensure!(encryptor.decrypt(&decoded, &kek)? == b"serialization", "decrypted plaintext differs");
+    Ok(())
+}
+
+/// Checks against a live Keys server: create a key, fetch it back and use it
+pub fn server_checks(server_url: &str) -> Vec<Check> {
+    let client = match KeysClient::new(server_url) {
+        Ok(client) => client,
+        Err(e) => {
+            return vec![Check {
+                name: "Keys server client".into(),
+                result: Err(anyhow::Error::new(e).context("Failed to create Keys client")),
+            }]
+        }
+    };
+
+    let mut created = None;
+    let mut checks = vec![Check::run("Keys server: create key", || {
+        let key = client.create_key()?;
+        ensure!(key.as_bytes()?.len() == 32, "key is not 256 bits");
+        created = Some(key);
+        Ok(())
+    })];
+    let Some(created) = created else {
+        return checks;
+    };
+
+    checks.push(Check::run("Keys server: get key", || {
+        let fetched = client.get_key(&created.uuid)?;
+        ensure!(fetched.as_bytes()? == created.as_bytes()?, "fetched key differs from the created one");
+        let encryptor = EnvelopeEncryptor::new(Algorithm::default());
+        let envelope = encryptor.encrypt(b"server key", &created.as_bytes()?, created.uuid.clone())?;
+        ensure!(encryptor.decrypt(&envelope, &fetched.as_bytes()?)? == b"server key", "decrypted plaintext differs");
+        Ok(())
+    }));
+
+    if let Err(e) = client.delete_key(&created.uuid) {
+        tracing::warn!("Could not delete self-test key {}: {}", created.uuid, e);
+    }
+    checks
+}
+
+/// Change the first character of a base64 string, which changes the first
+/// decoded byte while keeping the encoding valid
+fn corrupt_base64(encoded: &str) -> String {
+    let mut chars = encoded.chars();
+    let replacement = match chars.next() {
+        Some('A') => 'B',
+        _ => 'A',
+    };
+    std::iter::once(replacement).chain(chars).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offline_selftest_passes() {
+        let checks = offline_checks();
+        assert!(checks.len() >= kat::VECTORS.len() + Algorithm::all().len() + 2);
+        for check in &checks {
+            assert!(check.result.is_ok(), "{}: {:?}", check.name, check.result);
+        }
+
+        let mut out = Vec::new();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(execute(None, &mut out)).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), checks.len());
+        assert!(out.lines().all(|line| line.starts_with("PASS  ")), "{}", out);
+    }
+
+    #[test]
+    fn test_failures_are_reported() {
+        let checks = vec![
+            Check::run("good", || Ok(())),
+            Check::run("bad", || bail!("broken")),
+        ];
+        let mut out = Vec::new();
+        assert_eq!(report(&checks, &mut out).unwrap(), 1);
+        assert_eq!(String::from_utf8(out).unwrap(), "PASS  good\nFAIL  bad: broken\n");
+    }
+
+    #[test]
+    fn test_unreachable_server_fails() {
+        let checks = server_checks("http://127.0.0.1:9");
+        assert_eq!(checks.len(), 1);
+        assert!(checks[0].result.is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+use std::path::Path;
+use violet_core::{EnvelopeStore, FsEnvelopeStore};
+
+/// Open the envelope store in `dir`, or in the default directory
+pub fn open(dir: Option<&Path>) -> Result<FsEnvelopeStore> {
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => FsEnvelopeStore::default_dir()
+            .context("Unable to determine envelope store directory (HOME is not set)")?,
+    };
+    tracing::debug!("Using envelope store: {}", dir.display());
+    Ok(FsEnvelopeStore::new(dir))
+}
+
+/// Print the name of each stored envelope, one per line
+pub fn list(store: &dyn EnvelopeStore, writer: &mut impl Write) -> Result<()> {
+    for name in store.list().context("Failed to list stored envelopes")? {
+        writeln!(writer, "{}", name)?;
+    }
+    Ok(())
+}
+
+/// Remove a stored envelope; a name that is not stored is an error
+pub fn delete(store: &dyn EnvelopeStore, name: &str) -> Result<()> {
+    if !store.delete(name).with_context(|| format!("Failed to delete stored envelope {}", name))? {
+        bail!("No stored envelope named {}", name);
+    }
+    tracing::info!("Deleted stored envelope {}", name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use violet_core::{Algorithm, EnvelopeEncryptor};
+
+    #[test]
+    fn test_list_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open(Some(dir.path())).unwrap();
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"data", &[3u8; 32], "key".to_string())
+            .unwrap();
+        store.put("second", &envelope, false).unwrap();
+        store.put("first", &envelope, false).unwrap();
+
+        let mut out = Vec::new();
+        list(&store, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "first\nsecond\n");
+
+        delete(&store, "first").unwrap();
+        assert!(delete(&store, "first").unwrap_err().to_string().contains("No stored envelope"));
+        assert_eq!(store.list().unwrap(), ["second"]);
+    }
+}
@@ -0,0 +1,587 @@
+use anyhow::{bail, Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use violet_core::{Algorithm, EnvelopeEncryptor, SecretKey};
+use crate::commands::encrypt::server_kek;
+use crate::commands::parallel::map_in_order;
+use crate::commands::report::{CommandResult, Status};
+use crate::commands::{keys_client, EnvelopeFormat};
+
+/// Name of the manifest written at the root of an encrypted tree
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// Current manifest format version
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Extension added to each file's name for its envelope
+pub const ENVELOPE_EXTENSION: &str = "violet";
+
+/// What an encrypted tree holds, written to [`MANIFEST_NAME`] at its root
+///
+/// Paths are relative to the tree's root and use `/` whatever the platform.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub version: u32,
+    /// Envelope format of every file, as named by `--format`
+    pub format: String,
+    pub files: Vec<ManifestEntry>,
+}
+
+/// One encrypted file in a [`Manifest`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    /// Original path of the file
+    pub path: String,
+    /// Plaintext size in bytes
+    pub size: u64,
+    /// Where its envelope is, relative to the encrypted tree's root
+    pub envelope: String,
+    pub key_id: String,
+}
+
+/// Which files under the input directory a recursive encrypt takes
+///
+/// Globs are matched against paths relative to the input directory, and `*`
+/// also matches `/`. With no include globs every file is included.
+pub struct TreeFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl TreeFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: if include.is_empty() { None } else { Some(glob_set(include)?) },
+            exclude: glob_set(exclude)?,
+        })
+    }
+
+    fn accepts(&self, path: &str) -> bool {
+        self.include.as_ref().is_none_or(|include| include.is_match(path)) && !self.exclude.is_match(path)
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("Invalid glob {:?}", pattern))?);
+    }
+    Ok(builder.build()?)
+}
+
+/// How a recursive encrypt treats the tree
+pub struct TreeOptions {
+    pub filter: TreeFilter,
+    pub format: EnvelopeFormat,
+    /// Files encrypted at once
+    pub jobs: usize,
+    /// Keep envelopes already in the output instead of encrypting their files again
+    pub skip_existing: bool,
+}
+
+/// A regular file found under the input directory
+struct TreeFile {
+    path: String,
+    size: u64,
+}
+
+/// Files a walk took, and what it passed over
+struct Walk {
+    files: Vec<TreeFile>,
+    skipped: Vec<String>,
+}
+
+/// Encrypt every regular file under `input` into `output`, under one KEK
+///
+/// Each file gets its own envelope at `<output>/<path>.violet`, in
+/// `options.format`, and `<output>/manifest.json` lists them all. The KEK is
+/// fetched once for `key_id`, or created once for the whole run. Symlinks
+/// are never followed, nor are other special files read; they are skipped
+/// along with files already ending in `.violet` and, when it lies inside
+/// `input`, the output directory itself. Files that fail are reported after
+/// the rest are done, and left out of the manifest.
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt(
+    server_url: &str,
+    key_cache: bool,
+    input: &Path,
+    output: &Path,
+    key_id: Option<&str>,
+    require_key_id: bool,
+    encryptor: &EnvelopeEncryptor,
+    options: &TreeOptions,
+) -> Result<CommandResult> {
+    check_dirs(input, output)?;
+    if options.format == EnvelopeFormat::Auto {
+        bail!("--format auto only applies to reading envelopes; pick a format to write");
+    }
+    if !input.is_dir() {
+        bail!("--recursive needs --input to be a directory, not {}", input.display());
+    }
+    fs::create_dir_all(output)
+        .with_context(|| format!("Failed to create output directory {}", output.display()))?;
+    let output_root = output.canonicalize()
+        .with_context(|| format!("Failed to resolve {}", output.display()))?;
+
+    let walk = walk(input, &output_root, &options.filter)?;
+    for path in &walk.skipped {
+        tracing::info!("Skipping {}", path);
+    }
+
+    let mut pending = Vec::new();
+    let mut kept = Vec::new();
+    for file in walk.files {
+        let envelope = envelope_name(&file.path);
+        if options.skip_existing && output.join(&envelope).is_file() {
+            kept.push((file, envelope));
+        } else {
+            pending.push((file, envelope));
+        }
+    }
+
+    let kek = if pending.is_empty() {
+        None
+    } else {
+        Some(server_kek(server_url, key_cache, key_id, require_key_id)?)
+    };
+    let encrypted = map_in_order(&pending, options.jobs, |(file, envelope)| {
+        let (kek_id, kek) = kek.as_ref().expect("a KEK is fetched when files are pending");
+        encrypt_file(input, output, file, envelope, kek, kek_id, encryptor, options.format)
+            .with_context(|| format!("Failed to encrypt {}", file.path))
+    });
+    let kept: Vec<_> = kept.into_iter().map(|(file, envelope)| -> Result<ManifestEntry> {
+        let existing = fs::read(output.join(&envelope))
+            .map_err(anyhow::Error::from)
+            .and_then(|data| options.format.decode(&data))
+            .with_context(|| format!("Failed to read the existing envelope for {}", file.path))?;
+        tracing::debug!("Keeping the existing envelope for {}", file.path);
+        Ok(ManifestEntry { path: file.path, size: file.size, envelope, key_id: existing.key_id })
+    }).collect();
+    let counts = (encrypted.iter().filter(|result| result.is_ok()).count(), kept.len());
+
+    let mut entries = Vec::new();
+    let mut failures = Vec::new();
+    for result in encrypted.into_iter().chain(kept) {
+        match result {
+            Ok(entry) => entries.push(entry),
+            Err(e) => failures.push(e),
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let bytes = entries.iter().map(|entry| entry.size).sum();
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        format: options.format.name().to_string(),
+        files: entries,
+    };
+    let mut json = serde_json::to_vec_pretty(&manifest)?;
+    json.push(b'\n');
+    fs::write(output.join(MANIFEST_NAME), json)
+        .with_context(|| format!("Failed to write {}", output.join(MANIFEST_NAME).display()))?;
+
+    tracing::info!(
+        "Encrypted {} files, kept {} existing envelopes, skipped {}, {} failed",
+        counts.0,
+        counts.1,
+        walk.skipped.len(),
+        failures.len()
+    );
+    finish("encrypt", kek.map(|(kek_id, _)| kek_id), bytes, Some(encryptor.algorithm()), failures)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn encrypt_file(
+    input: &Path,
+    output: &Path,
+    file: &TreeFile,
+    envelope_path: &str,
+    kek: &SecretKey,
+    kek_id: &str,
+    encryptor: &EnvelopeEncryptor,
+    format: EnvelopeFormat,
+) -> Result<ManifestEntry> {
+    let plaintext = fs::read(input.join(&file.path))?;
+    let envelope = encryptor.encrypt(&plaintext, kek, kek_id.to_string())
+        .context("Encryption failed")?;
+    write_under(output, Path::new(envelope_path), &format.encode(&envelope)?, true)?;
+    Ok(ManifestEntry {
+        path: file.path.clone(),
+        size: plaintext.len() as u64,
+        envelope: envelope_path.to_string(),
+        key_id: kek_id.to_string(),
+    })
+}
+
+/// Restore the tree described by `<input>/manifest.json` under `output`
+///
+/// Every manifest path must be a plain relative path, and nothing is written
+/// unless they all are. Files are only created, never overwritten, and never
+/// through a symlink, so a manifest cannot place anything outside `output`.
+/// Each KEK is fetched once, unless `kek` is given for every file.
+pub fn decrypt(
+    server_url: &str,
+    key_cache: bool,
+    input: &Path,
+    output: &Path,
+    jobs: usize,
+    kek: Option<SecretKey>,
+) -> Result<CommandResult> {
+    check_dirs(input, output)?;
+    let manifest_path = input.join(MANIFEST_NAME);
+    let manifest: Manifest = serde_json::from_slice(
+        &fs::read(&manifest_path).with_context(|| format!("Failed to read {}", manifest_path.display()))?,
+    )
+    .with_context(|| format!("Failed to parse {}", manifest_path.display()))?;
+    if manifest.version != MANIFEST_VERSION {
+        bail!("Unsupported manifest version {}", manifest.version);
+    }
+    let format: EnvelopeFormat = clap::ValueEnum::from_str(&manifest.format, false)
+        .map_err(|_| anyhow::anyhow!("Unknown envelope format {:?} in manifest", manifest.format))?;
+    for entry in &manifest.files {
+        relative_path(&entry.path)?;
+        relative_path(&entry.envelope)?;
+    }
+
+    fs::create_dir_all(output)
+        .with_context(|| format!("Failed to create output directory {}", output.display()))?;
+    let client = match kek {
+        Some(_) => None,
+        None => Some(keys_client(server_url, key_cache).context("Failed to create Keys client")?),
+    };
+    let keks: Mutex<HashMap<String, SecretKey>> = Mutex::new(HashMap::new());
+    let fetch_kek = |key_id: &str| -> Result<SecretKey> {
+        if let Some(kek) = &kek {
+            return Ok(kek.clone());
+        }
+        let client = client.as_ref().expect("a client is built when no KEK is given");
+        let mut keks = keks.lock().unwrap();
+        if let Some(kek) = keks.get(key_id) {
+            return Ok(kek.clone());
+        }
+        let key = client.get_key(key_id)
+            .context("Failed to get key from server")?;
+        let kek = SecretKey::from_hex(&key.key).context("Failed to decode key")?;
+        keks.insert(key_id.to_string(), kek.clone());
+        Ok(kek)
+    };
+
+    let results = map_in_order(&manifest.files, jobs, |entry| {
+        decrypt_file(input, output, entry, format, &fetch_kek)
+            .with_context(|| format!("Failed to decrypt {}", entry.path))
+    });
+    let mut bytes = 0;
+    let mut failures = Vec::new();
+    for result in results {
+        match result {
+            Ok(size) => bytes += size,
+            Err(e) => failures.push(e),
+        }
+    }
+
+    tracing::info!("Decrypted {} of {} files", manifest.files.len() - failures.len(), manifest.files.len());
+    let mut key_ids = manifest.files.iter().map(|entry| entry.key_id.as_str());
+    let single_key = key_ids.next().filter(|first| key_ids.all(|key_id| key_id == *first));
+    finish("decrypt", single_key.map(str::to_string), bytes, None, failures)
+}
+
+fn decrypt_file(
+    input: &Path,
+    output: &Path,
+    entry: &ManifestEntry,
+    format: EnvelopeFormat,
+    fetch_kek: &dyn Fn(&str) -> Result<SecretKey>,
+) -> Result<u64> {
+    let envelope_path = relative_path(&entry.envelope)?;
+    refuse_symlinks(input, &envelope_path)?;
+    let envelope = format.decode(&fs::read(input.join(&envelope_path))?)
+        .context("Failed to parse envelope")?;
+    if envelope.key_id != entry.key_id {
+        bail!("Envelope is under key {}, but the manifest says {}", envelope.key_id, entry.key_id);
+    }
+
+    let kek = fetch_kek(&envelope.key_id)?;
+    let algorithm = Algorithm::from_str(&envelope.algorithm)
+        .context("Invalid algorithm in envelope")?;
+    let plaintext = EnvelopeEncryptor::new(algorithm)
+        .decrypt(&envelope, &kek)
+        .context("Decryption failed")?;
+    if plaintext.len() as u64 != entry.size {
+        bail!("Decrypted {} bytes, but the manifest says {}", plaintext.len(), entry.size);
+    }
+
+    write_under(output, &relative_path(&entry.path)?, &plaintext, false)?;
+    Ok(entry.size)
+}
+
+/// Collect the regular files under `root` that `filter` accepts, in path order
+fn walk(root: &Path, output_root: &Path, filter: &TreeFilter) -> Result<Walk> {
+    let mut walk = Walk { files: Vec::new(), skipped: Vec::new() };
+    let mut dirs = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, prefix)) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?
+            .collect::<std::io::Result<Vec<_>>>()
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                walk.skipped.push(format!("{} (name is not UTF-8)", entry.path().display()));
+                continue;
+            };
+            let path = format!("{}{}", prefix, name);
+            let file_type = entry.file_type()
+                .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+
+            if file_type.is_dir() {
+                if entry.path().canonicalize().is_ok_and(|resolved| resolved == output_root) {
+                    walk.skipped.push(format!("{}/ (the output directory)", path));
+                } else {
+                    dirs.push((entry.path(), format!("{}/", path)));
+                }
+            } else if file_type.is_symlink() {
+                walk.skipped.push(format!("{} (symlink)", path));
+            } else if !file_type.is_file() {
+                walk.skipped.push(format!("{} (not a regular file)", path));
+            } else if Path::new(name).extension().is_some_and(|ext| ext == ENVELOPE_EXTENSION) {
+                walk.skipped.push(format!("{} (already encrypted)", path));
+            } else if filter.accepts(&path) {
+                let size = entry.metadata()
+                    .with_context(|| format!("Failed to read {}", entry.path().display()))?
+                    .len();
+                walk.files.push(TreeFile { path, size });
+            }
+        }
+    }
+    walk.files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(walk)
+}
+
+fn envelope_name(path: &str) -> String {
+    format!("{}.{}", path, ENVELOPE_EXTENSION)
+}
+
+/// `path` from a manifest, refusing anything but plain names joined by `/`
+fn relative_path(path: &str) -> Result<PathBuf> {
+    let relative = Path::new(path);
+    if path.is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        bail!("Manifest path {:?} is not a plain relative path", path);
+    }
+    Ok(relative.to_path_buf())
+}
+
+/// Fail if any part of `root/relative` below `root` is a symlink
+fn refuse_symlinks(root: &Path, relative: &Path) -> Result<()> {
+    let mut path = root.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            bail!("{} is a symlink; refusing to follow it", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Write `data` to `root/relative`, creating its directories, never through a symlink
+///
+/// Without `overwrite` the file must not exist yet.
+fn write_under(root: &Path, relative: &Path, data: &[u8], overwrite: bool) -> Result<()> {
+    let mut dir = root.to_path_buf();
+    if let Some(parent) = relative.parent() {
+        for component in parent.components() {
+            dir.push(component);
+            match fs::create_dir(&dir) {
+                Ok(()) => {}
+                // Also hit when a parallel job created it first
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dir.display())),
+            }
+            let metadata = fs::symlink_metadata(&dir)
+                .with_context(|| format!("Failed to read {}", dir.display()))?;
+            if !metadata.is_dir() {
+                bail!("{} is not a directory; refusing to write through it", dir.display());
+            }
+        }
+    }
+
+    let path = root.join(relative);
+    if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        bail!("{} is a symlink; refusing to write through it", path.display());
+    }
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    options.open(&path)
+        .and_then(|mut file| file.write_all(data))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn check_dirs(input: &Path, output: &Path) -> Result<()> {
+    if input == Path::new("-") || output == Path::new("-") {
+        bail!("--recursive needs --input <DIR> and --output <DIR>");
+    }
+    Ok(())
+}
+
+/// The command's result, or the first failure once every file has been tried
+fn finish(
+    operation: &'static str,
+    key_id: Option<String>,
+    bytes_processed: u64,
+    algorithm: Option<Algorithm>,
+    failures: Vec<anyhow::Error>,
+) -> Result<CommandResult> {
+    let failed = failures.len();
+    if let Some(first) = failures.into_iter().next() {
+        return Err(first.context(format!("{} files failed to {}", failed, operation)));
+    }
+    Ok(CommandResult {
+        status: Status::Ok,
+        operation,
+        key_id,
+        bytes_processed,
+        algorithm: algorithm.map(|algorithm| algorithm.as_str().to_string()),
+        errors: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use violet_client::testutil::MockKeysServer;
+
+    /// top.txt, empty.txt, nested/deeper/report.csv, a symlink to a file
+    /// outside the tree and one to a file inside it
+    fn fixture(root: &Path, outside: &Path) {
+        fs::create_dir_all(root.join("nested/deeper")).unwrap();
+        fs::write(root.join("top.txt"), b"top level").unwrap();
+        fs::write(root.join("empty.txt"), b"").unwrap();
+        fs::write(root.join("nested/deeper/report.csv"), b"a,b\n1,2\n").unwrap();
+        fs::write(outside, b"not part of the tree").unwrap();
+        symlink(outside, root.join("escape.txt")).unwrap();
+        symlink(root.join("top.txt"), root.join("nested/alias.txt")).unwrap();
+    }
+
+    fn options(include: &[&str], exclude: &[&str], skip_existing: bool) -> TreeOptions {
+        let strings = |globs: &[&str]| globs.iter().map(|glob| glob.to_string()).collect::<Vec<_>>();
+        TreeOptions {
+            filter: TreeFilter::new(&strings(include), &strings(exclude)).unwrap(),
+            format: EnvelopeFormat::Json,
+            jobs: 3,
+            skip_existing,
+        }
+    }
+
+    fn encrypt_tree(server: &MockKeysServer, input: &Path, output: &Path, options: &TreeOptions) -> Result<CommandResult> {
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        encrypt(server.url(), false, input, output, Some("key-a"), false, &encryptor, options)
+    }
+
+    fn read_manifest(output: &Path) -> Manifest {
+        serde_json::from_slice(&fs::read(output.join(MANIFEST_NAME)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_tree_round_trip_skips_symlinks() {
+        let server = MockKeysServer::with_key("key-a", &"aa".repeat(32));
+        let dir = tempfile::tempdir().unwrap();
+        let (input, encrypted, restored) = (dir.path().join("in"), dir.path().join("enc"), dir.path().join("out"));
+        fixture(&input, &dir.path().join("outside.txt"));
+
+        let result = encrypt_tree(&server, &input, &encrypted, &options(&[], &[], false)).unwrap();
+        assert_eq!(result.key_id.as_deref(), Some("key-a"));
+        assert_eq!(result.bytes_processed, 9 + 8);
+
+        let manifest = read_manifest(&encrypted);
+        let paths: Vec<_> = manifest.files.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["empty.txt", "nested/deeper/report.csv", "top.txt"]);
+        assert_eq!(manifest.files[1].envelope, "nested/deeper/report.csv.violet");
+        assert_eq!(manifest.files[0].size, 0);
+        assert!(encrypted.join("nested/deeper/report.csv.violet").is_file());
+        assert!(!encrypted.join("escape.txt.violet").exists());
+
+        decrypt(server.url(), false, &encrypted, &restored, 2, None).unwrap();
+        assert_eq!(fs::read(restored.join("top.txt")).unwrap(), b"top level");
+        assert_eq!(fs::read(restored.join("empty.txt")).unwrap(), b"");
+        assert_eq!(fs::read(restored.join("nested/deeper/report.csv")).unwrap(), b"a,b\n1,2\n");
+        assert!(fs::symlink_metadata(restored.join("escape.txt")).is_err());
+        assert!(fs::symlink_metadata(restored.join("nested/alias.txt")).is_err());
+    }
+
+    #[test]
+    fn test_globs_output_inside_input_and_skip_existing() {
+        let server = MockKeysServer::with_key("key-a", &"aa".repeat(32));
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        fixture(&input, &dir.path().join("outside.txt"));
+        let encrypted = input.join("enc");
+
+        let only_txt = options(&["*.txt"], &["empty*"], false);
+        encrypt_tree(&server, &input, &encrypted, &only_txt).unwrap();
+        let paths: Vec<_> = read_manifest(&encrypted).files.into_iter().map(|entry| entry.path).collect();
+        assert_eq!(paths, ["top.txt"]);
+
+        // The output directory, now inside the input, is not encrypted into itself
+        let first = fs::read(encrypted.join("top.txt.violet")).unwrap();
+        encrypt_tree(&server, &input, &encrypted, &options(&[], &[], true)).unwrap();
+        let paths: Vec<_> = read_manifest(&encrypted).files.into_iter().map(|entry| entry.path).collect();
+        assert_eq!(paths, ["empty.txt", "nested/deeper/report.csv", "top.txt"]);
+        assert_eq!(fs::read(encrypted.join("top.txt.violet")).unwrap(), first, "existing envelope rewritten");
+
+        // Envelopes lying around in the input are not encrypted again
+        fs::copy(encrypted.join("top.txt.violet"), input.join("copied.txt.violet")).unwrap();
+        encrypt_tree(&server, &input, &encrypted, &options(&[], &[], false)).unwrap();
+        assert!(!encrypted.join("copied.txt.violet.violet").exists());
+        assert_ne!(fs::read(encrypted.join("top.txt.violet")).unwrap(), first);
+    }
+
+    #[test]
+    fn test_decrypt_refuses_to_leave_the_output_root() {
+        let server = MockKeysServer::with_key("key-a", &"aa".repeat(32));
+        let dir = tempfile::tempdir().unwrap();
+        let (input, encrypted) = (dir.path().join("in"), dir.path().join("enc"));
+        fixture(&input, &dir.path().join("outside.txt"));
+        encrypt_tree(&server, &input, &encrypted, &options(&[], &[], false)).unwrap();
+        let manifest = read_manifest(&encrypted);
+
+        for path in ["../escaped.txt", "/tmp/absolute.txt", "nested/../../escaped.txt", ""] {
+            let mut tampered = manifest.files.clone();
+            tampered[2].path = path.to_string();
+            let tampered = Manifest { version: MANIFEST_VERSION, format: "json".into(), files: tampered };
+            fs::write(encrypted.join(MANIFEST_NAME), serde_json::to_vec(&tampered).unwrap()).unwrap();
+
+            let restored = dir.path().join("restored");
+            let error = decrypt(server.url(), false, &encrypted, &restored, 1, None).unwrap_err();
+            assert!(error.to_string().contains("not a plain relative path"), "{}: {}", path, error);
+            assert!(!restored.exists(), "{} wrote files", path);
+        }
+        assert!(!dir.path().join("escaped.txt").exists());
+
+        // A symlinked directory already in the output is not written through
+        fs::write(encrypted.join(MANIFEST_NAME), serde_json::to_vec(&manifest).unwrap()).unwrap();
+        let restored = dir.path().join("restored");
+        let elsewhere = dir.path().join("elsewhere");
+        fs::create_dir_all(&restored).unwrap();
+        fs::create_dir_all(&elsewhere).unwrap();
+        symlink(&elsewhere, restored.join("nested")).unwrap();
+
+        let error = decrypt(server.url(), false, &encrypted, &restored, 1, None).unwrap_err();
+        assert!(format!("{:#}", error).contains("refusing to write through it"), "{:#}", error);
+        assert_eq!(fs::read_dir(&elsewhere).unwrap().count(), 0);
+        // The other files are still restored
+        assert_eq!(fs::read(restored.join("top.txt")).unwrap(), b"top level");
+    }
+}
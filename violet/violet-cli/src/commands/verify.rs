@@ -0,0 +1,397 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+use violet_core::crypto::stream::STREAM_MAGIC;
+use violet_core::{
+    is_container, Algorithm, EncryptionEnvelope, EnvelopeEncryptor, SecretKey, StreamHeader, StreamOpener, VioletError,
+};
+use crate::commands::failure::FailureKind;
+use crate::commands::parallel::map_in_order;
+use crate::commands::{keys_client, EnvelopeFormat};
+
+/// Bytes read from a stream at a time, so memory use stays bounded
+const READ_SIZE: usize = 64 * 1024;
+
+/// Outcome of verifying one input
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Verification {
+    pub input: String,
+    /// `ok`, or the kind of failure: `malformed`, `key not found`, `auth failed`, ...
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// Envelope or stream bytes read
+    pub ciphertext_bytes: u64,
+    /// Plaintext bytes that authenticated and were discarded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plaintext_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    failure: Option<anyhow::Error>,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// What was learned about an input before it failed, if it did
+#[derive(Default)]
+struct Progress {
+    key_id: Option<String>,
+    ciphertext_bytes: u64,
+    plaintext_bytes: u64,
+}
+
+/// Check that every input decrypts, without writing any plaintext
+///
+/// Envelopes are decrypted in memory and streams chunk by chunk; either way
+/// the plaintext is dropped as soon as it authenticates. `data` names the
+/// stream file to check against a single `inputs` entry holding its
+/// `.envelope.json` sidecar. Each KEK is fetched once, and `jobs` inputs are
+/// verified at a time. The summary is printed even when some inputs fail,
+/// and the first failure is then returned, so the exit status reflects it.
+#[allow(clippy::too_many_arguments)]
+pub fn execute(
+    server_url: &str,
+    key_cache: bool,
+    inputs: &[String],
+    data: Option<&str>,
+    format: EnvelopeFormat,
+    jobs: usize,
+    json: bool,
+    writer: &mut impl Write,
+) -> Result<()> {
+    if jobs == 0 {
+        bail!("--jobs must be at least 1");
+    }
+    if data.is_some() && inputs.len() != 1 {
+        bail!("--data needs exactly one --input, the stream's envelope sidecar");
+    }
+    if inputs.iter().filter(|input| *input == "-").count() > 1 {
+        bail!("Only one input can be read from stdin");
+    }
+
+    let client = keys_client(server_url, key_cache)
+        .context("Failed to create Keys client")?;
+    let keks: Mutex<HashMap<String, SecretKey>> = Mutex::new(HashMap::new());
+    // Held across the fetch, so workers needing the same key wait for one call
+    let fetch_kek = |key_id: &str| -> Result<SecretKey> {
+        let mut keks = keks.lock().unwrap();
+        if let Some(kek) = keks.get(key_id) {
+            return Ok(kek.clone());
+        }
+        let key = client.get_key(key_id)
+            .context("Failed to get key from server")?;
+        let kek = SecretKey::from_hex(&key.key).context("Failed to decode key")?;
+        keks.insert(key_id.to_string(), kek.clone());
+        Ok(kek)
+    };
+
+    let results = map_in_order(inputs, jobs, |input| verify_one(input, data, format, &fetch_kek));
+    let failed = results.iter().filter(|result| !result.is_ok()).count();
+    if json {
+        let summary = serde_json::json!({
+            "verified": results.len() - failed,
+            "failed": failed,
+            "results": &results,
+        });
+        serde_json::to_writer_pretty(&mut *writer, &summary)?;
+        writeln!(writer)?;
+    } else {
+        write_table(&results, writer)?;
+    }
+
+    match results.into_iter().find_map(|result| result.failure) {
+        Some(first) => Err(first.context(format!("{} of {} inputs failed verification", failed, inputs.len()))),
+        None => Ok(()),
+    }
+}
+
+fn verify_one(
+    input: &str,
+    data: Option<&str>,
+    format: EnvelopeFormat,
+    fetch_kek: &dyn Fn(&str) -> Result<SecretKey>,
+) -> Verification {
+    let mut progress = Progress::default();
+    let outcome = match data {
+        Some(data) => verify_sidecar(input, data, fetch_kek, &mut progress),
+        None => verify_input(input, format, fetch_kek, &mut progress),
+    };
+
+    let (status, error, failure) = match outcome {
+        Ok(()) => ("ok", None, None),
+        Err(e) => {
+            let e = e.context(format!("Failed to verify {}", data.unwrap_or(input)));
+            (FailureKind::of(&e).label(), Some(format!("{:#}", e)), Some(e))
+        }
+    };
+    Verification {
+        input: data.unwrap_or(input).to_string(),
+        status,
+        key_id: progress.key_id,
+        ciphertext_bytes: progress.ciphertext_bytes,
+        plaintext_bytes: failure.is_none().then_some(progress.plaintext_bytes),
+        error,
+        failure,
+    }
+}
+
+/// Verify an envelope or, if it starts with the stream magic, a stream
+fn verify_input(
+    input: &str,
+    format: EnvelopeFormat,
+    fetch_kek: &dyn Fn(&str) -> Result<SecretKey>,
+    progress: &mut Progress,
+) -> Result<()> {
+    let mut reader = open(input)?;
+    let mut start = Vec::with_capacity(STREAM_MAGIC.len());
+    (&mut reader).take(STREAM_MAGIC.len() as u64).read_to_end(&mut start)
+        .with_context(|| format!("Failed to read {}", input))?;
+
+    if start == STREAM_MAGIC {
+        return verify_stream(io::Cursor::new(start).chain(reader), None, fetch_kek, progress);
+    }
+
+    let mut bytes = start;
+    reader.read_to_end(&mut bytes)
+        .with_context(|| format!("Failed to read {}", input))?;
+    progress.ciphertext_bytes = bytes.len() as u64;
+    let envelope = if is_container(&bytes) {
+        EncryptionEnvelope::from_container(&bytes).context("Failed to parse container")?
+    } else {
+        format.decode(&bytes).context("Failed to parse envelope")?
+    };
+    progress.key_id = Some(envelope.key_id.clone());
+    if envelope.kdf.is_some() {
+        bail!("Envelope is password-protected; verify only checks envelopes under Keys server keys");
+    }
+
+    let kek = fetch_kek(&envelope.key_id)?;
+    let algorithm = Algorithm::from_str(&envelope.algorithm)
+        .context("Invalid algorithm in envelope")?;
+    let plaintext = EnvelopeEncryptor::new(algorithm)
+        .decrypt(&envelope, &kek)
+        .context("Decryption failed")?;
+    progress.plaintext_bytes = plaintext.len() as u64;
+    Ok(())
+}
+
+/// Verify the stream at `data` against the header in its sidecar at `input`
+fn verify_sidecar(
+    input: &str,
+    data: &str,
+    fetch_kek: &dyn Fn(&str) -> Result<SecretKey>,
+    progress: &mut Progress,
+) -> Result<()> {
+    let mut sidecar = Vec::new();
+    open(input)?.read_to_end(&mut sidecar)
+        .with_context(|| format!("Failed to read {}", input))?;
+    let header: StreamHeader = serde_json::from_slice(&sidecar)
+        .context("Failed to parse stream envelope")?;
+    progress.key_id = Some(header.key_id.clone());
+    verify_stream(open(data)?, Some(&header), fetch_kek, progress)
+}
+
+/// Authenticate every chunk of a stream and its footer, discarding the plaintext
+fn verify_stream(
+    mut reader: impl Read,
+    expected: Option<&StreamHeader>,
+    fetch_kek: &dyn Fn(&str) -> Result<SecretKey>,
+    progress: &mut Progress,
+) -> Result<()> {
+    let mut opener = StreamOpener::new();
+    let mut buf = vec![0u8; READ_SIZE];
+    let mut unlocked = false;
+    loop {
+        let n = reader.read(&mut buf).context("Failed to read stream")?;
+        if n == 0 {
+            break;
+        }
+        progress.ciphertext_bytes += n as u64;
+        opener.push(&buf[..n]);
+
+        if !unlocked {
+            let Some(header) = opener.header()? else {
+                continue;
+            };
+            progress.key_id = Some(header.key_id.clone());
+            if expected.is_some_and(|expected| expected != header) {
+                return Err(VioletError::InvalidEnvelope(
+                    "Stream header does not match its envelope sidecar".into(),
+                ).into());
+            }
+            let kek = fetch_kek(&header.key_id)?;
+            opener.unlock(&kek).context("Failed to unwrap the stream key")?;
+            unlocked = true;
+        }
+        while let Some(plaintext) = opener.next_chunk().context("Decryption failed")? {
+            progress.plaintext_bytes += plaintext.len() as u64;
+        }
+    }
+    opener.finish().context("Decryption failed")?;
+    Ok(())
+}
+
+fn open(path: &str) -> Result<Box<dyn Read>> {
+    if path == "-" {
+        return Ok(Box::new(io::stdin()));
+    }
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    Ok(Box::new(file))
+}
+
+fn write_table(results: &[Verification], writer: &mut impl Write) -> Result<()> {
+    let width = |column: &str, values: &mut dyn Iterator<Item = usize>| values.max().unwrap_or(0).max(column.len());
+    let input_width = width("INPUT", &mut results.iter().map(|result| result.input.len()));
+    let key_width = width("KEY ID", &mut results.iter().map(|result| result.key_id.as_deref().unwrap_or("-").len()));
+    let status_width = width("STATUS", &mut results.iter().map(|result| result.status.len()));
+
+    writeln!(
+        writer,
+        "{:<input_width$}  {:<status_width$}  {:<key_width$}  {:>10}  {:>10}",
+        "INPUT", "STATUS", "KEY ID", "CIPHERTEXT", "PLAINTEXT"
+    )?;
+    for result in results {
+        let plaintext = result.plaintext_bytes.map_or("-".to_string(), |bytes| bytes.to_string());
+        writeln!(
+            writer,
+            "{:<input_width$}  {:<status_width$}  {:<key_width$}  {:>10}  {:>10}",
+            result.input,
+            result.status,
+            result.key_id.as_deref().unwrap_or("-"),
+            result.ciphertext_bytes,
+            plaintext
+        )?;
+    }
+
+    let failed: Vec<_> = results.iter().filter(|result| !result.is_ok()).collect();
+    writeln!(writer, "{} verified, {} failed", results.len() - failed.len(), failed.len())?;
+    for result in failed {
+        writeln!(writer, "  {}: {}", result.input, result.error.as_deref().unwrap_or_default())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use violet_client::testutil::MockKeysServer;
+    use violet_core::{EncryptionEnvelope, StreamEncryptor};
+
+    const KEK: [u8; 32] = [0xaa; 32];
+
+    fn write_envelope(dir: &Path, name: &str, envelope: &EncryptionEnvelope) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, serde_json::to_vec(envelope).unwrap()).unwrap();
+        path.display().to_string()
+    }
+
+    fn run(server: &MockKeysServer, inputs: &[String], data: Option<&str>, jobs: usize) -> (String, Result<()>) {
+        let mut out = Vec::new();
+        let result = execute(server.url(), false, inputs, data, EnvelopeFormat::Json, jobs, false, &mut out);
+        (String::from_utf8(out).unwrap(), result)
+    }
+
+    #[test]
+    fn test_valid_envelopes_and_streams_pass() {
+        let server = MockKeysServer::with_key("key-a", &"aa".repeat(32));
+        let dir = tempfile::tempdir().unwrap();
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"twenty-one bytes long", &KEK, "key-a".into())
+            .unwrap();
+        let stream_path = dir.path().join("data.vstr");
+        let plaintext = vec![7u8; 3000];
+        StreamEncryptor::new(Algorithm::Aes256GcmSiv)
+            .with_chunk_size(1024)
+            .unwrap()
+            .encrypt_stream(plaintext.as_slice(), File::create(&stream_path).unwrap(), &KEK, "key-a".into())
+            .unwrap();
+        let inputs = [
+            write_envelope(dir.path(), "one.json", &envelope),
+            stream_path.display().to_string(),
+            write_envelope(dir.path(), "two.json", &envelope),
+        ];
+
+        let (out, result) = run(&server, &inputs, None, 2);
+        result.unwrap();
+        assert!(out.contains("3 verified, 0 failed"), "{}", out);
+        assert!(out.contains("  21\n"), "{}", out);
+        assert!(out.contains("  3000\n"), "{}", out);
+        // Each key is fetched once, whatever the number of inputs
+        assert_eq!(server.request_count(), 1);
+    }
+
+    #[test]
+    fn test_stream_checked_against_its_sidecar() {
+        let server = MockKeysServer::with_key("key-a", &"aa".repeat(32));
+        let dir = tempfile::tempdir().unwrap();
+        let stream_path = dir.path().join("data.vstr");
+        StreamEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt_stream(&b"streamed"[..], File::create(&stream_path).unwrap(), &KEK, "key-a".into())
+            .unwrap();
+
+        let mut opener = StreamOpener::new();
+        opener.push(&std::fs::read(&stream_path).unwrap());
+        let header = opener.header().unwrap().unwrap().clone();
+        let sidecar = dir.path().join("data.vstr.envelope.json");
+        std::fs::write(&sidecar, serde_json::to_vec(&header).unwrap()).unwrap();
+        let inputs = [sidecar.display().to_string()];
+
+        let (_, result) = run(&server, &inputs, stream_path.to_str(), 1);
+        result.unwrap();
+
+        // A sidecar from another stream is refused
+        let other = StreamHeader { chunk_size: 2048, ..header };
+        std::fs::write(&sidecar, serde_json::to_vec(&other).unwrap()).unwrap();
+        let (_, result) = run(&server, &inputs, stream_path.to_str(), 1);
+        assert_eq!(FailureKind::of(&result.unwrap_err()), FailureKind::Parse);
+    }
+
+    #[test]
+    fn test_failures_are_told_apart() {
+        let server = MockKeysServer::with_key("key-a", &"aa".repeat(32));
+        let dir = tempfile::tempdir().unwrap();
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let good = encryptor.encrypt(b"data", &KEK, "key-a".into()).unwrap();
+        let mut corrupted = good.clone();
+        corrupted.encrypted_data = encryptor.encrypt(b"DATA", &KEK, "key-a".into()).unwrap().encrypted_data;
+        let missing = encryptor.encrypt(b"data", &[0xbb; 32], "key-gone".into()).unwrap();
+        let malformed = dir.path().join("malformed.json");
+        std::fs::write(&malformed, b"{\"keyId\": 3}").unwrap();
+
+        let inputs = [
+            write_envelope(dir.path(), "good.json", &good),
+            write_envelope(dir.path(), "corrupted.json", &corrupted),
+            write_envelope(dir.path(), "missing.json", &missing),
+            malformed.display().to_string(),
+        ];
+        let mut out = Vec::new();
+        let result = execute(server.url(), false, &inputs, None, EnvelopeFormat::Json, 4, true, &mut out);
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("3 of 4 inputs failed"), "{}", error);
+        // The exit status is that of the first failure, the corrupted envelope
+        assert_eq!(FailureKind::of(&error).exit_code(), 4);
+
+        let report: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(report["verified"], 1);
+        assert_eq!(report["failed"], 3);
+        let statuses: Vec<_> = report["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses, ["ok", "auth failed", "key not found", "malformed"]);
+        assert_eq!(report["results"][0]["plaintextBytes"], 4);
+        assert!(report["results"][1].get("plaintextBytes").is_none());
+    }
+}
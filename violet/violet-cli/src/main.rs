@@ -1,9 +1,22 @@
 use clap::{Parser, Subcommand};
-use violet_core::Algorithm;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::OnceLock;
+use std::time::Duration;
+use violet_core::{Algorithm, EnvelopeEncryptor};
 use anyhow::Result;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+use commands::{EnvelopeFormat, EnvelopeLocation};
+use commands::report::{self, OutputFormat};
+use commands::tree::{TreeFilter, TreeOptions};
 
 mod commands;
 
+/// Keys server used when neither `--server-url` nor a daemon config file names one
+const DEFAULT_SERVER_URL: &str = "http://localhost:8080";
+
 #[derive(Parser)]
 #[command(name = "violet")]
 #[command(about = "Envelope encryption CLI using the Keys server", long_about = None)]
@@ -12,15 +25,42 @@ struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    /// Keys server base URL
-    #[arg(long, env = "VIOLET_SERVER_URL", default_value = "http://localhost:8080")]
-    server_url: String,
+    /// Keys server base URL (default: http://localhost:8080)
+    #[arg(long, env = "VIOLET_SERVER_URL")]
+    server_url: Option<String>,
 
     /// Logging level
     #[arg(long, env = "VIOLET_LOG_LEVEL", default_value = "info")]
     log_level: String,
+
+    /// Cache fetched keys on disk (encrypted) to avoid repeated server round-trips
+    #[arg(long, env = "VIOLET_KEY_CACHE")]
+    key_cache: bool,
+
+    /// Directory of the envelope store used by --store (default: ~/.local/share/violet/envelopes)
+    #[arg(long, env = "VIOLET_STORE_DIR")]
+    store_dir: Option<PathBuf>,
+
+    /// Give up on encrypt or decrypt input (a FIFO, device or stdin) after this many seconds without data
+    #[arg(long, env = "VIOLET_INPUT_TIMEOUT", value_name = "SECS")]
+    input_timeout: Option<u64>,
+
+    /// Result format for encrypt and decrypt; json prints one result object on stdout
+    #[arg(long, env = "VIOLET_OUTPUT_FORMAT", value_enum, default_value = "text")]
+    output_format: OutputFormat,
+
+    /// Export spans to this OTLP/gRPC collector, e.g. http://localhost:4317
+    #[cfg(feature = "otel")]
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+
+    /// Recommended encryption algorithm; also the default for encrypt
+    #[arg(long, env = "VIOLET_RECOMMENDED_ALGORITHM", value_enum)]
+    recommended_algorithm: Option<AlgorithmArg>,
 }
 
+// The enum is parsed once per run, so the size of `Daemon`'s options doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// Encrypt plaintext data
@@ -37,9 +77,86 @@ enum Commands {
         #[arg(short, long)]
         key_id: Option<String>,
 
-        /// Algorithm to use
-        #[arg(short, long, value_enum, default_value = "aes-256-gcm")]
-        algorithm: AlgorithmArg,
+        /// Fail when no --key-id is given instead of creating a new key
+        #[arg(long, env = "VIOLET_REQUIRE_KEY_ID")]
+        require_key_id: bool,
+
+        /// Algorithm to use (default: the recommended algorithm, or aes-256-gcm)
+        #[arg(short, long, value_enum)]
+        algorithm: Option<AlgorithmArg>,
+
+        /// Use --algorithm even when it differs from the recommended algorithm
+        #[arg(long, requires = "algorithm")]
+        force_algorithm: bool,
+
+        /// Embed a non-secret fingerprint of the key in the envelope
+        #[arg(long)]
+        kek_fingerprint: bool,
+
+        /// Envelope output format
+        #[arg(long, value_enum, default_value = "json")]
+        format: EnvelopeFormat,
+
+        /// Write the envelope in each of these formats (comma-separated, e.g. json,binary)
+        /// to --output with the format's extension appended, encrypting once
+        #[arg(long, value_enum, value_delimiter = ',', conflicts_with_all = ["format", "store"])]
+        emit: Vec<EnvelopeFormat>,
+
+        /// Derive the key from a password (prompted for) instead of using the Keys server
+        #[arg(long, conflicts_with = "key_id")]
+        password: bool,
+
+        /// Write JSON with sorted keys and fixed formatting, so the same envelope
+        /// always serializes to the same bytes
+        #[arg(long, conflicts_with_all = ["emit", "store"])]
+        canonical: bool,
+
+        /// Save the envelope in the envelope store under this name instead of writing it out
+        #[arg(long, conflicts_with_all = ["output", "format"])]
+        store: Option<String>,
+
+        /// With --store, replace an envelope already stored under the name
+        #[arg(long, requires = "store")]
+        overwrite: bool,
+
+        /// Encrypt every file under the --input directory into the --output
+        /// directory, one envelope per file, with a manifest.json listing them
+        #[arg(short, long, conflicts_with_all = ["password", "emit", "store", "canonical"])]
+        recursive: bool,
+
+        /// With --recursive, only encrypt files matching this glob (repeatable)
+        #[arg(long, value_name = "GLOB", requires = "recursive")]
+        include: Vec<String>,
+
+        /// With --recursive, leave out files matching this glob (repeatable)
+        #[arg(long, value_name = "GLOB", requires = "recursive")]
+        exclude: Vec<String>,
+
+        /// With --recursive, number of files to encrypt at once
+        #[arg(short, long, default_value_t = 1, requires = "recursive")]
+        jobs: usize,
+
+        /// With --recursive, keep envelopes already in --output instead of
+        /// encrypting their files again
+        #[arg(long, requires = "recursive")]
+        skip_existing: bool,
+
+        /// Replace FILE with FILE.violet, a single-file container of its encryption
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with_all = ["input", "output", "format", "emit", "canonical", "store", "recursive"]
+        )]
+        in_place: Option<String>,
+
+        /// With --in-place, encrypt a file that is already a .violet container
+        #[arg(long, requires = "in_place")]
+        force: bool,
+
+        /// With --in-place, give the new file the original's permissions and
+        /// modification time instead of owner-only permissions
+        #[arg(long, requires = "in_place")]
+        preserve: bool,
     },
 
     /// Decrypt encrypted envelope
@@ -48,63 +165,525 @@ enum Commands {
         #[arg(short, long, default_value = "-")]
         input: String,
 
-        /// Output file for plaintext (use '-' for stdout)
+        /// Output file for plaintext (use '-' for stdout). With --jsonl, an output
+        /// directory, or '-' for a length-prefixed stream on stdout
+        #[arg(short, long, default_value = "-")]
+        output: String,
+
+        /// Input is JSONL with one envelope per line
+        #[arg(long)]
+        jsonl: bool,
+
+        /// With --jsonl, skip lines that fail to decrypt and report them at the end
+        #[arg(long, requires = "jsonl")]
+        keep_going: bool,
+
+        /// Envelope input format; auto tells them apart by their first bytes
+        /// (--jsonl input is always JSON)
+        #[arg(long, value_enum, default_value = "json", conflicts_with = "jsonl")]
+        format: EnvelopeFormat,
+
+        /// Refuse envelopes that do not use this algorithm (downgrade protection)
+        #[arg(long, value_enum)]
+        expect_algorithm: Option<AlgorithmArg>,
+
+        /// Write non-UTF-8 plaintext to stdout even when it is a terminal
+        #[arg(long)]
+        binary: bool,
+
+        /// Decrypt a password-protected envelope (prompts for the password)
+        #[arg(long, conflicts_with = "jsonl")]
+        password: bool,
+
+        /// Decrypt with this 32-byte KEK, in hex, instead of fetching it from the
+        /// Keys server (for tests and offline recovery; visible to other local users)
+        #[arg(long, value_name = "HEX", conflicts_with_all = ["password", "kek_base64"])]
+        kek: Option<String>,
+
+        /// Like --kek, with the KEK in base64
+        #[arg(long, value_name = "BASE64", conflicts_with = "password")]
+        kek_base64: Option<String>,
+
+        /// Read the envelope stored under this name instead of --input
+        #[arg(long, conflicts_with_all = ["input", "jsonl", "format"])]
+        store: Option<String>,
+
+        /// Restore the tree described by --input/manifest.json, written by
+        /// `encrypt --recursive`, into the --output directory
+        #[arg(short, long, conflicts_with_all = ["jsonl", "store", "password"])]
+        recursive: bool,
+
+        /// With --recursive, number of files to decrypt at once
+        #[arg(short, long, default_value_t = 1, requires = "recursive")]
+        jobs: usize,
+
+        /// Replace FILE.violet, a container written by `encrypt --in-place`,
+        /// with the file it holds under its original name
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with_all = ["input", "output", "jsonl", "format", "binary", "store", "recursive"]
+        )]
+        in_place: Option<String>,
+
+        /// With --in-place, give the restored file the container's permissions
+        /// and modification time instead of owner-only permissions
+        #[arg(long, requires = "in_place")]
+        preserve: bool,
+    },
+
+    /// Upgrade envelopes to a newer format version without decrypting their data
+    Migrate {
+        /// Envelope file, JSONL file with --jsonl, or directory of envelope files (use '-' for stdin)
+        #[arg(short, long, default_value = "-")]
+        input: String,
+
+        /// Output file (use '-' for stdout), or the output directory when --input is a
+        /// directory (may be the same directory)
+        #[arg(short, long, default_value = "-")]
+        output: String,
+
+        /// Envelope version to upgrade from
+        #[arg(long, default_value_t = violet_core::LEGACY_ENVELOPE_VERSION)]
+        from: u32,
+
+        /// Envelope version to upgrade to
+        #[arg(long, default_value_t = violet_core::ENVELOPE_VERSION)]
+        to: u32,
+
+        /// Input is JSONL with one envelope per line; the output is JSONL too
+        #[arg(long)]
+        jsonl: bool,
+
+        /// Carry on past envelopes that fail and report them at the end
+        #[arg(long)]
+        keep_going: bool,
+
+        /// Envelope format of the file or directory (--jsonl input is always JSON)
+        #[arg(long, value_enum, default_value = "json", conflicts_with = "jsonl")]
+        format: EnvelopeFormat,
+    },
+
+    /// Show an envelope's fields and check its structure, without contacting the server
+    Inspect {
+        /// Envelope file (use '-' for stdin)
+        #[arg(short, long, default_value = "-")]
+        input: String,
+
+        /// Envelope format
+        #[arg(long, value_enum, default_value = "json")]
+        format: EnvelopeFormat,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check that envelopes decrypt, discarding the plaintext
+    Verify {
+        /// Envelope or stream file (use '-' for stdin); repeat to verify several
+        #[arg(short, long = "input", default_value = "-")]
+        inputs: Vec<String>,
+
+        /// Stream file to verify against the single --input, its .envelope.json sidecar
+        #[arg(long)]
+        data: Option<String>,
+
+        /// Envelope format; streams are recognised whatever the format
+        #[arg(long, value_enum, default_value = "json")]
+        format: EnvelopeFormat,
+
+        /// Number of inputs to verify at once
+        #[arg(short, long, default_value_t = 1)]
+        jobs: usize,
+
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Move an envelope to a new key without decrypting its data
+    Rewrap {
+        /// Envelope file (use '-' for stdin)
+        #[arg(short, long, default_value = "-")]
+        input: String,
+
+        /// Output file for the rewrapped envelope (use '-' for stdout)
         #[arg(short, long, default_value = "-")]
         output: String,
+
+        /// Replace --input atomically instead of writing --output
+        #[arg(long, conflicts_with = "output")]
+        in_place: bool,
+
+        /// Key to move the envelope to (if not provided, creates new key)
+        #[arg(short = 'k', long)]
+        new_key_id: Option<String>,
+
+        /// Envelope format, kept for the output
+        #[arg(long, value_enum, default_value = "json")]
+        format: EnvelopeFormat,
     },
 
-    /// Run as Unix socket daemon
+    /// Run as Unix socket (and optionally TCP) daemon
     Daemon {
-        /// Socket path
-        #[arg(short, long, env = "VIOLET_SOCKET_PATH", default_value = "/tmp/violet.sock")]
+        #[command(subcommand)]
+        action: Option<DaemonAction>,
+
+        #[command(flatten)]
+        options: commands::daemon::DaemonOptions,
+    },
+
+    /// List supported encryption algorithms
+    Algorithms,
+
+    /// Manage the on-disk key cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+
+    /// Manage envelopes saved with --store
+    Store {
+        #[command(subcommand)]
+        action: StoreAction,
+    },
+
+    /// Create, inspect and delete keys on the Keys server
+    Keys {
+        #[command(subcommand)]
+        action: KeysAction,
+
+        /// Print one JSON object per command on stdout
+        #[arg(long, global = true)]
+        json: bool,
+    },
+
+    /// Run known-answer and round-trip checks of the crypto stack (exit status 0/1)
+    Selftest {
+        /// Also create and fetch a key on the Keys server
+        #[arg(long)]
+        server: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum DaemonAction {
+    /// Check that a running daemon answers and can reach the Keys server (exit status 0/1)
+    Ping {
+        /// Socket path of the daemon to probe, or @name for an abstract socket (Linux)
+        #[arg(short, long, env = "VIOLET_SOCKET_PATH", default_value_t = violet_daemon::default_socket_path())]
         socket: String,
     },
 }
 
-#[derive(clap::ValueEnum, Clone, Copy)]
-enum AlgorithmArg {
-    #[value(name = "aes-256-gcm")]
-    Aes256Gcm,
-    #[value(name = "aes-256-gcm-siv")]
-    Aes256GcmSiv,
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Remove all cached keys
+    Clear,
+}
+
+#[derive(Subcommand)]
+enum StoreAction {
+    /// List the names of stored envelopes
+    List,
+
+    /// Remove a stored envelope
+    Delete {
+        /// Name the envelope was stored under
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeysAction {
+    /// Create a key and print its UUID
+    Create {
+        /// Also print the key material
+        #[arg(long)]
+        show_secret: bool,
+    },
+
+    /// Show a key's UUID, size and fingerprint
+    Get {
+        /// UUID of the key
+        uuid: String,
+
+        /// Also print the key material
+        #[arg(long)]
+        show_secret: bool,
+    },
+
+    /// Delete a key, after asking for confirmation
+    Delete {
+        /// UUID of the key
+        uuid: String,
+
+        /// Delete without asking
+        #[arg(long, short)]
+        yes: bool,
+    },
+}
+
+/// Algorithm named on the command line; accepts every entry of `Algorithm::all()`
+#[derive(Clone, Copy)]
+struct AlgorithmArg(Algorithm);
+
+impl clap::ValueEnum for AlgorithmArg {
+    fn value_variants<'a>() -> &'a [Self] {
+        static VARIANTS: OnceLock<Vec<AlgorithmArg>> = OnceLock::new();
+        VARIANTS.get_or_init(|| Algorithm::all().iter().copied().map(AlgorithmArg).collect())
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(commands::algorithms::cli_name(self.0)))
+    }
 }
 
 impl From<AlgorithmArg> for Algorithm {
     fn from(arg: AlgorithmArg) -> Self {
-        match arg {
-            AlgorithmArg::Aes256Gcm => Algorithm::Aes256Gcm,
-            AlgorithmArg::Aes256GcmSiv => Algorithm::Aes256GcmSiv,
-        }
+        arg.0
     }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            commands::failure::print(&error);
+            ExitCode::from(commands::failure::FailureKind::of(&error).exit_code())
+        }
+    }
+}
 
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(&cli.log_level)
-        .init();
+async fn run(cli: Cli) -> Result<()> {
+    // Initialize logging; stdout is reserved for command output
+    let subscriber = tracing_subscriber::registry()
+        .with(EnvFilter::new(&cli.log_level))
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr));
+    #[cfg(feature = "otel")]
+    let otel_provider = cli
+        .otlp_endpoint
+        .as_deref()
+        .map(violet_daemon::telemetry::otlp_provider)
+        .transpose()?;
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(otel_provider.as_ref().map(violet_daemon::telemetry::layer));
+    subscriber.init();
 
     tracing::info!("Violet CLI starting");
+    let input_timeout = cli.input_timeout.map(Duration::from_secs);
+    let server_url = cli.server_url.as_deref().unwrap_or(DEFAULT_SERVER_URL);
 
+    // The Keys client is blocking, and tokio forbids blocking on its reactor, so
+    // commands that reach the Keys server run inside `block_in_place`
     match cli.command {
-        Commands::Encrypt { input, output, key_id, algorithm } => {
-            commands::encrypt::execute(
-                &cli.server_url,
-                &input,
-                &output,
-                key_id.as_deref(),
-                algorithm.into(),
-            ).await?;
+        Commands::Encrypt {
+            input, output, key_id, require_key_id, algorithm, force_algorithm, kek_fingerprint, format, emit, password,
+            canonical, store, overwrite, recursive, include, exclude, jobs, skip_existing, in_place, force, preserve,
+        } => {
+            let outcome = tokio::task::block_in_place(|| {
+                let algorithm = commands::encrypt::resolve_algorithm(
+                    algorithm.map(Into::into),
+                    cli.recommended_algorithm.map(Into::into),
+                    force_algorithm,
+                )?;
+                if let Some(path) = &in_place {
+                    return commands::in_place::encrypt(
+                        server_url,
+                        cli.key_cache,
+                        Path::new(path),
+                        key_id.as_deref(),
+                        require_key_id,
+                        &EnvelopeEncryptor::new(algorithm).with_kek_fingerprint(kek_fingerprint),
+                        password,
+                        force,
+                        preserve,
+                    );
+                }
+                if recursive {
+                    return commands::tree::encrypt(
+                        server_url,
+                        cli.key_cache,
+                        Path::new(&input),
+                        Path::new(&output),
+                        key_id.as_deref(),
+                        require_key_id,
+                        &EnvelopeEncryptor::new(algorithm).with_kek_fingerprint(kek_fingerprint),
+                        &TreeOptions { filter: TreeFilter::new(&include, &exclude)?, format, jobs, skip_existing },
+                    );
+                }
+                let envelope_store = store.as_ref().map(|_| commands::store::open(cli.store_dir.as_deref())).transpose()?;
+                let output = match (&envelope_store, store.as_deref()) {
+                    (Some(envelope_store), Some(name)) => EnvelopeLocation::Store { store: envelope_store, name, overwrite },
+                    _ => {
+                        report::check_output(cli.output_format, &output)?;
+                        EnvelopeLocation::Path(&output)
+                    }
+                };
+                commands::encrypt::execute(
+                    server_url,
+                    cli.key_cache,
+                    &input,
+                    input_timeout,
+                    output,
+                    key_id.as_deref(),
+                    algorithm,
+                    kek_fingerprint,
+                    format,
+                    &emit,
+                    password,
+                    canonical,
+                    require_key_id,
+                )
+            });
+            report::report(cli.output_format, "encrypt", outcome, &mut std::io::stdout())?;
+        }
+        Commands::Decrypt {
+            input, output, jsonl, keep_going, format, expect_algorithm, binary, password, kek, kek_base64, store, recursive, jobs,
+            in_place, preserve,
+        } => {
+            let expect_algorithm = expect_algorithm.map(Into::into);
+            let outcome = tokio::task::block_in_place(|| {
+                let kek = commands::decrypt::inline_kek(kek.as_deref(), kek_base64.as_deref())?;
+                if let Some(path) = &in_place {
+                    return commands::in_place::decrypt(
+                        server_url,
+                        cli.key_cache,
+                        Path::new(path),
+                        expect_algorithm,
+                        password,
+                        kek,
+                        preserve,
+                    );
+                }
+                report::check_output(cli.output_format, &output)?;
+                if recursive {
+                    commands::tree::decrypt(server_url, cli.key_cache, Path::new(&input), Path::new(&output), jobs, kek)
+                } else if jsonl {
+                    commands::decrypt::execute_jsonl(
+                        server_url,
+                        cli.key_cache,
+                        &input,
+                        input_timeout,
+                        &output,
+                        keep_going,
+                        expect_algorithm,
+                        kek,
+                    )
+                } else {
+                    let envelope_store = store.as_ref().map(|_| commands::store::open(cli.store_dir.as_deref())).transpose()?;
+                    let input = match (&envelope_store, store.as_deref()) {
+                        (Some(envelope_store), Some(name)) => EnvelopeLocation::Store { store: envelope_store, name, overwrite: false },
+                        _ => EnvelopeLocation::Path(&input),
+                    };
+                    commands::decrypt::execute(
+                        server_url,
+                        cli.key_cache,
+                        input,
+                        input_timeout,
+                        &output,
+                        format,
+                        expect_algorithm,
+                        binary,
+                        password,
+                        kek,
+                    )
+                }
+            });
+            report::report(cli.output_format, "decrypt", outcome, &mut std::io::stdout())?;
+        }
+        Commands::Inspect { input, format, json } => {
+            commands::inspect::execute(&input, input_timeout, format, json, &mut std::io::stdout())?;
+        }
+        Commands::Verify { inputs, data, format, jobs, json } => {
+            tokio::task::block_in_place(|| {
+                commands::verify::execute(
+                    server_url,
+                    cli.key_cache,
+                    &inputs,
+                    data.as_deref(),
+                    format,
+                    jobs,
+                    json,
+                    &mut std::io::stdout(),
+                )
+            })?;
+        }
+        Commands::Rewrap { input, output, in_place, new_key_id, format } => {
+            tokio::task::block_in_place(|| {
+                commands::rewrap::execute(
+                    server_url,
+                    cli.key_cache,
+                    &input,
+                    input_timeout,
+                    &output,
+                    in_place,
+                    new_key_id.as_deref(),
+                    format,
+                )
+            })?;
+        }
+        Commands::Migrate { input, output, from, to, jsonl, keep_going, format } => {
+            tokio::task::block_in_place(|| {
+                commands::migrate::execute(
+                    server_url,
+                    cli.key_cache,
+                    &input,
+                    input_timeout,
+                    &output,
+                    from,
+                    to,
+                    jsonl,
+                    keep_going,
+                    format,
+                )
+            })?;
         }
-        Commands::Decrypt { input, output } => {
-            commands::decrypt::execute(&cli.server_url, &input, &output).await?;
+        Commands::Daemon { action: Some(DaemonAction::Ping { socket }), .. } => {
+            commands::daemon::ping(&socket).await?;
         }
-        Commands::Daemon { socket } => {
-            commands::daemon::execute(&cli.server_url, &socket).await?;
+        Commands::Daemon { action: None, options } => {
+            commands::daemon::execute(cli.server_url.as_deref(), options).await?;
         }
+        Commands::Algorithms => {
+            commands::algorithms::execute();
+        }
+        Commands::Cache { action: CacheAction::Clear } => {
+            commands::cache::clear()?;
+        }
+        Commands::Store { action: StoreAction::List } => {
+            let store = commands::store::open(cli.store_dir.as_deref())?;
+            commands::store::list(&store, &mut std::io::stdout())?;
+        }
+        Commands::Store { action: StoreAction::Delete { name } } => {
+            let store = commands::store::open(cli.store_dir.as_deref())?;
+            commands::store::delete(&store, &name)?;
+        }
+        Commands::Keys { action, json } => tokio::task::block_in_place(|| -> Result<()> {
+            // Always ask the server, so a cached copy cannot hide a deleted key
+            let client = commands::keys_client(server_url, false)?;
+            let stdout = &mut std::io::stdout();
+            match action {
+                KeysAction::Create { show_secret } => commands::keys::create(&client, show_secret, json, stdout),
+                KeysAction::Get { uuid, show_secret } => commands::keys::get(&client, &uuid, show_secret, json, stdout),
+                KeysAction::Delete { uuid, yes } => {
+                    commands::keys::delete(&client, &uuid, yes, json, stdout, commands::keys::confirm_on_terminal)
+                }
+            }
+        })?,
+        Commands::Selftest { server } => {
+            let server_url = server.then_some(server_url);
+            commands::selftest::execute(server_url, &mut std::io::stdout()).await?;
+        }
+    }
+
+    // Send any spans still batched in the exporter
+    #[cfg(feature = "otel")]
+    if let Some(provider) = otel_provider {
+        provider.shutdown().ok();
     }
 
     Ok(())
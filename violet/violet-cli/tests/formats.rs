@@ -0,0 +1,154 @@
+//! Envelope formats through the `violet` binary, over pipes and files
+
+use assert_cmd::Command;
+use violet_client::testutil::MockKeysServer;
+use violet_client::Key;
+
+const PLAINTEXT: &[u8] = b"quarterly numbers, do not forward\n";
+
+/// Every `--format` encrypt writes, `binary` being an alias for `cbor`
+const FORMATS: &[&str] = &["json", "json-compact", "cbor", "binary", "armor"];
+
+fn server() -> MockKeysServer {
+    let server = MockKeysServer::start();
+    server.insert_key(Key {
+        uuid: "key-a".to_string(),
+        key: "aa".repeat(32),
+        algorithm: None,
+    });
+    server
+}
+
+/// `violet` against `server`, unaffected by the environment running the tests
+fn violet(server: &MockKeysServer) -> Command {
+    let mut command = Command::cargo_bin("violet").unwrap();
+    command.env_clear().args(["--server-url", server.url(), "--log-level", "error"]);
+    command
+}
+
+fn encrypt(server: &MockKeysServer, format: &str, stdin: &[u8]) -> Vec<u8> {
+    let output = violet(server)
+        .args(["encrypt", "--key-id", "key-a", "--format", format])
+        .write_stdin(stdin)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(!output.is_empty(), "{} wrote nothing", format);
+    output
+}
+
+fn decrypt(server: &MockKeysServer, format: &str, envelope: &[u8]) -> Vec<u8> {
+    violet(server)
+        .args(["decrypt", "--format", format])
+        .write_stdin(envelope)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone()
+}
+
+#[test]
+fn test_every_format_round_trips_through_pipes() {
+    let server = server();
+    for format in FORMATS {
+        let envelope = encrypt(&server, format, PLAINTEXT);
+        assert_eq!(decrypt(&server, format, &envelope), PLAINTEXT, "--format {}", format);
+        assert_eq!(decrypt(&server, "auto", &envelope), PLAINTEXT, "--format auto on {}", format);
+    }
+}
+
+#[test]
+fn test_format_shapes() {
+    let server = server();
+
+    let pretty = String::from_utf8(encrypt(&server, "json", PLAINTEXT)).unwrap();
+    assert!(pretty.starts_with("{\n"), "{}", pretty);
+    let compact = String::from_utf8(encrypt(&server, "json-compact", PLAINTEXT)).unwrap();
+    assert!(compact.starts_with('{') && !compact.trim_end().contains('\n'), "{}", compact);
+    let pretty: serde_json::Value = serde_json::from_str(&pretty).unwrap();
+    let compact: serde_json::Value = serde_json::from_str(&compact).unwrap();
+    assert_eq!(pretty["keyId"], compact["keyId"]);
+
+    let armor = String::from_utf8(encrypt(&server, "armor", PLAINTEXT)).unwrap();
+    assert!(armor.starts_with("-----BEGIN VIOLET ENVELOPE-----\n"), "{}", armor);
+    assert!(armor.ends_with("-----END VIOLET ENVELOPE-----\n"), "{}", armor);
+
+    // The default is still pretty JSON
+    let default = violet(&server)
+        .args(["encrypt", "--key-id", "key-a"])
+        .write_stdin(PLAINTEXT)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(default.starts_with(b"{\n"));
+
+    // CBOR starts with a map header, not JSON's brace
+    let cbor = encrypt(&server, "binary", PLAINTEXT);
+    assert_eq!(cbor[0] & 0xe0, 0xa0, "not a CBOR map");
+}
+
+#[test]
+fn test_every_format_round_trips_through_files() {
+    let server = server();
+    let dir = tempfile::tempdir().unwrap();
+    let plaintext = dir.path().join("plain.txt");
+    std::fs::write(&plaintext, PLAINTEXT).unwrap();
+
+    for format in FORMATS {
+        let envelope = dir.path().join(format!("envelope.{}", format));
+        let restored = dir.path().join(format!("restored.{}", format));
+        violet(&server)
+            .args(["encrypt", "--key-id", "key-a", "--format", format, "-i"])
+            .arg(&plaintext)
+            .arg("-o")
+            .arg(&envelope)
+            .assert()
+            .success();
+        violet(&server)
+            .args(["decrypt", "--format", "auto", "-i"])
+            .arg(&envelope)
+            .arg("-o")
+            .arg(&restored)
+            .assert()
+            .success();
+        assert_eq!(std::fs::read(&restored).unwrap(), PLAINTEXT, "--format {}", format);
+    }
+
+    // A .violet container from --in-place is recognised by its magic bytes
+    violet(&server)
+        .args(["encrypt", "--key-id", "key-a", "--in-place"])
+        .arg(&plaintext)
+        .assert()
+        .success();
+    let container = std::fs::read(dir.path().join("plain.txt.violet")).unwrap();
+    assert!(container.starts_with(b"VLTC"));
+    assert_eq!(decrypt(&server, "auto", &container), PLAINTEXT);
+}
+
+#[test]
+fn test_mismatched_and_unwritable_formats() {
+    let server = server();
+    let armor = encrypt(&server, "armor", PLAINTEXT);
+    violet(&server)
+        .args(["decrypt", "--format", "json"])
+        .write_stdin(armor)
+        .assert()
+        .code(2);
+
+    violet(&server)
+        .args(["encrypt", "--key-id", "key-a", "--format", "auto"])
+        .write_stdin(PLAINTEXT)
+        .assert()
+        .failure();
+
+    violet(&server)
+        .args(["decrypt", "--format", "auto"])
+        .write_stdin(&b"not an envelope in any format"[..])
+        .assert()
+        .code(2);
+}
@@ -0,0 +1,466 @@
+use crate::cache::KeyCache;
+use crate::client::{join_endpoint, record_status, report_error, validate_timeout, KeysClient};
+use crate::error::{ClientError, Result};
+use crate::models::{Key, KeyEncoding};
+use crate::rate_limit::RateLimiter;
+use futures::stream::{FuturesUnordered, StreamExt};
+use reqwest::{Client, StatusCode};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::field::Empty;
+use tracing::Instrument;
+use url::Url;
+
+/// Async HTTP client for the Keys server API
+///
+/// Same API and behaviour as [`KeysClient`], for use inside a tokio runtime.
+/// Built with [`KeysClientBuilder::build_async`](crate::KeysClientBuilder::build_async).
+///
+/// Clones share the underlying connection pool and rate limiter.
+#[derive(Clone)]
+pub struct AsyncKeysClient {
+    base_url: Url,
+    client: Client,
+    key_cache: Option<KeyCache>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    key_encoding: KeyEncoding,
+}
+
+impl AsyncKeysClient {
+    /// Create a new async Keys client
+    ///
+    /// # Example
+    /// ```no_run
+    /// use violet_client::AsyncKeysClient;
+    ///
+    /// let client = AsyncKeysClient::new("http://localhost:8080").unwrap();
+    /// ```
+    pub fn new(base_url: impl AsRef<str>) -> Result<Self> {
+        KeysClient::builder(base_url).build_async()
+    }
+
+    pub(crate) fn from_parts(
+        base_url: Url,
+        client: Client,
+        key_cache: Option<KeyCache>,
+        rate_limiter: Option<Arc<RateLimiter>>,
+        key_encoding: KeyEncoding,
+    ) -> Self {
+        Self {
+            base_url,
+            client,
+            key_cache,
+            rate_limiter,
+            key_encoding,
+        }
+    }
+
+    /// Create a new 256-bit key on the server
+    ///
+    /// Calls POST /v1/keys/ on the Keys server.
+    pub async fn create_key(&self) -> Result<Key> {
+        let span = tracing::info_span!(
+            "keys_client.create_key",
+            method = "POST",
+            uuid = Empty,
+            status = Empty,
+            attempt = Empty,
+            elapsed_ms = Empty
+        );
+
+        traced(
+            async {
+                self.throttle().await?;
+                let url = self.endpoint(&["keys", ""])?;
+
+                tracing::debug!("Creating new key at: {}", url);
+
+                let response = self
+                    .client
+                    .post(url)
+                    .header("Content-Type", "application/json")
+                    .send()
+                    .await?;
+                record_status(response.status());
+
+                match response.status() {
+                    StatusCode::CREATED => {
+                        let key = response.json::<Key>().await?.into_hex(self.key_encoding)?;
+                        tracing::Span::current().record("uuid", key.uuid.as_str());
+                        tracing::info!("Created key with UUID: {}", key.uuid);
+                        self.cache_key(&key);
+                        Ok(key)
+                    }
+                    status => {
+                        tracing::error!("Unexpected status creating key: {}", status);
+                        Err(ClientError::UnexpectedStatus(status.as_u16()))
+                    }
+                }
+            },
+            span,
+        )
+        .await
+    }
+
+    /// Get an existing key by UUID
+    ///
+    /// Calls GET /v1/keys/{uuid} on the Keys server, consulting the key cache
+    /// first if one is configured.
+    ///
+    /// # Errors
+    /// Returns `ClientError::KeyNotFound` if the key doesn't exist
+    pub async fn get_key(&self, uuid: &str) -> Result<Key> {
+        self.get_key_inner(uuid, None).await
+    }
+
+    /// Get an existing key, overriding the client's timeout for this call
+    pub async fn get_key_timeout(&self, uuid: &str, timeout: Duration) -> Result<Key> {
+        validate_timeout(timeout)?;
+        self.get_key_inner(uuid, Some(timeout)).await
+    }
+
+    async fn get_key_inner(&self, uuid: &str, timeout: Option<Duration>) -> Result<Key> {
+        if let Some(key) = self.key_cache.as_ref().and_then(|cache| cache.get(uuid)) {
+            return Ok(key);
+        }
+
+        let span = tracing::info_span!(
+            "keys_client.get_key",
+            method = "GET",
+            uuid = %uuid,
+            status = Empty,
+            attempt = Empty,
+            elapsed_ms = Empty
+        );
+
+        traced(
+            async {
+                self.throttle().await?;
+                let url = self.endpoint(&["keys", uuid])?;
+
+                tracing::debug!("Getting key: {}", uuid);
+
+                let mut request = self.client.get(url);
+                if let Some(timeout) = timeout {
+                    request = request.timeout(timeout);
+                }
+                let response = request.send().await?;
+                record_status(response.status());
+
+                match response.status() {
+                    StatusCode::OK => {
+                        let key = response.json::<Key>().await?.into_hex(self.key_encoding)?;
+                        tracing::debug!("Retrieved key: {}", key.uuid);
+                        self.cache_key(&key);
+                        Ok(key)
+                    }
+                    StatusCode::NOT_FOUND => {
+                        tracing::warn!("Key not found: {}", uuid);
+                        Err(ClientError::KeyNotFound(uuid.to_string()))
+                    }
+                    status => {
+                        tracing::error!("Unexpected status getting key {}: {}", uuid, status);
+                        Err(ClientError::UnexpectedStatus(status.as_u16()))
+                    }
+                }
+            },
+            span,
+        )
+        .await
+    }
+
+    /// Get many keys with at most `concurrency` requests in flight at once
+    ///
+    /// Results are returned in the same order as `uuids`; one key failing
+    /// does not stop the others. A `concurrency` of zero is treated as one.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use violet_client::AsyncKeysClient;
+    /// # async fn run(client: AsyncKeysClient, uuids: Vec<String>) {
+    /// for result in client.get_keys(&uuids, 8).await {
+    ///     match result {
+    ///         Ok(key) => println!("{}", key.uuid),
+    ///         Err(e) => eprintln!("{}", e),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub async fn get_keys(&self, uuids: &[String], concurrency: usize) -> Vec<Result<Key>> {
+        let concurrency = concurrency.max(1);
+        let mut results: Vec<Option<Result<Key>>> = Vec::with_capacity(uuids.len());
+        results.resize_with(uuids.len(), || None);
+
+        let mut pending = uuids
+            .iter()
+            .enumerate()
+            .map(|(index, uuid)| async move { (index, self.get_key(uuid).await) });
+        let mut in_flight = FuturesUnordered::new();
+
+        // Only start a new request when one finishes, so a slow server pushes
+        // back on the caller instead of queueing every request at once
+        in_flight.extend(pending.by_ref().take(concurrency));
+
+        while let Some((index, result)) = in_flight.next().await {
+            results[index] = Some(result);
+            if let Some(next) = pending.next() {
+                in_flight.push(next);
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("every key request completes"))
+            .collect()
+    }
+
+    /// Delete a key (currently a stub on the server)
+    ///
+    /// Calls DELETE /v1/keys/{uuid} on the Keys server.
+    pub async fn delete_key(&self, uuid: &str) -> Result<()> {
+        let span = tracing::info_span!(
+            "keys_client.delete_key",
+            method = "DELETE",
+            uuid = %uuid,
+            status = Empty,
+            attempt = Empty,
+            elapsed_ms = Empty
+        );
+
+        traced(
+            async {
+                self.throttle().await?;
+                let url = self.endpoint(&["keys", uuid])?;
+
+                tracing::debug!("Deleting key: {}", uuid);
+
+                let response = self.client.delete(url).send().await?;
+                record_status(response.status());
+
+                match response.status() {
+                    StatusCode::NO_CONTENT => {
+                        tracing::info!("Deleted key: {}", uuid);
+                        self.evict_key(uuid);
+                        Ok(())
+                    }
+                    StatusCode::NOT_FOUND => {
+                        tracing::warn!("Key not found for deletion: {}", uuid);
+                        Err(ClientError::KeyNotFound(uuid.to_string()))
+                    }
+                    status => {
+                        tracing::error!("Unexpected status deleting key {}: {}", uuid, status);
+                        Err(ClientError::UnexpectedStatus(status.as_u16()))
+                    }
+                }
+            },
+            span,
+        )
+        .await
+    }
+
+    fn endpoint(&self, segments: &[&str]) -> Result<Url> {
+        join_endpoint(&self.base_url, segments)
+    }
+
+    /// Wait for the rate limiter, if configured, without blocking the runtime
+    async fn throttle(&self) -> Result<()> {
+        if let Some(limiter) = &self.rate_limiter {
+            let wait = limiter.reserve()?;
+            if !wait.is_zero() {
+                tracing::debug!("Rate limited, waiting {:?}", wait);
+                tokio::time::sleep(wait).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a key to the cache, if configured. Cache failures never fail the request.
+    fn cache_key(&self, key: &Key) {
+        if let Some(cache) = &self.key_cache {
+            if let Err(e) = cache.put(key) {
+                tracing::warn!("Failed to cache key {}: {}", key.uuid, e);
+            }
+        }
+    }
+
+    /// Drop a key from the cache, if configured. Cache failures never fail the request.
+    fn evict_key(&self, uuid: &str) {
+        if let Some(cache) = &self.key_cache {
+            if let Err(e) = cache.remove(uuid) {
+                tracing::warn!("Failed to evict cached key {}: {}", uuid, e);
+            }
+        }
+    }
+}
+
+/// Async counterpart of the blocking client's `traced`: run a server call in
+/// `span`, recording elapsed time and emitting `client.error` on failure
+async fn traced<T>(call: impl Future<Output = Result<T>>, span: tracing::Span) -> Result<T> {
+    span.record("attempt", 1);
+
+    let start = Instant::now();
+    let result = call.instrument(span.clone()).await;
+    span.record("elapsed_ms", start.elapsed().as_millis() as u64);
+
+    if let Err(e) = &result {
+        span.in_scope(|| report_error(e));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const MISSING: &str = "missing-key";
+
+    /// Minimal HTTP server that tracks how many requests it is handling at once
+    ///
+    /// Each request is held for a short delay so overlapping requests are visible.
+    /// Requests for `MISSING` get a 404; everything else gets a key whose UUID
+    /// matches the request path.
+    struct StubServer {
+        url: String,
+        max_in_flight: Arc<AtomicUsize>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    async fn stub_server() -> StubServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let (in_flight_c, max_c, requests_c) =
+            (in_flight.clone(), max_in_flight.clone(), requests.clone());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (in_flight, max_in_flight, requests) =
+                    (in_flight_c.clone(), max_c.clone(), requests_c.clone());
+
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 4096];
+                    let n = stream.read(&mut buf).await.unwrap();
+                    let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                    let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+                    let uuid = path.rsplit('/').next().unwrap_or("").to_string();
+
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                    let (status, body) = if uuid == MISSING {
+                        ("404 Not Found", String::new())
+                    } else {
+                        (
+                            "200 OK",
+                            format!(r#"{{"uuid":"{}","key":"{}"}}"#, uuid, "ab".repeat(32)),
+                        )
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+
+        StubServer {
+            url,
+            max_in_flight,
+            requests,
+        }
+    }
+
+    fn uuids(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("key-{}", i)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_get_keys_bounds_concurrency() {
+        let server = stub_server().await;
+        let client = AsyncKeysClient::new(&server.url).unwrap();
+
+        let ids = uuids(12);
+        let results = client.get_keys(&ids, 3).await;
+
+        assert_eq!(results.len(), ids.len());
+        assert_eq!(server.requests.load(Ordering::SeqCst), ids.len());
+
+        let max = server.max_in_flight.load(Ordering::SeqCst);
+        assert!(max <= 3, "{} requests were in flight at once", max);
+        assert!(max > 1, "requests were not issued concurrently");
+    }
+
+    #[tokio::test]
+    async fn test_get_keys_preserves_input_order() {
+        let server = stub_server().await;
+        let client = AsyncKeysClient::new(&server.url).unwrap();
+
+        let mut ids = uuids(6);
+        ids.insert(2, MISSING.to_string());
+        let results = client.get_keys(&ids, 4).await;
+
+        assert_eq!(results.len(), ids.len());
+        for (uuid, result) in ids.iter().zip(&results) {
+            if uuid == MISSING {
+                assert!(matches!(result, Err(ClientError::KeyNotFound(id)) if id == MISSING));
+            } else {
+                assert_eq!(&result.as_ref().unwrap().uuid, uuid);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_keys_zero_concurrency_is_sequential() {
+        let server = stub_server().await;
+        let client = AsyncKeysClient::new(&server.url).unwrap();
+
+        let results = client.get_keys(&uuids(3), 0).await;
+
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(server.max_in_flight.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_keys_empty() {
+        let client = AsyncKeysClient::new("http://localhost:1").unwrap();
+        assert!(client.get_keys(&[], 4).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_async_get_key_timeout() {
+        // The stub server holds each request for 50ms
+        let server = stub_server().await;
+        let client = AsyncKeysClient::new(&server.url).unwrap();
+
+        let result = client.get_key_timeout("slow", Duration::from_millis(5)).await;
+        assert!(matches!(result, Err(ClientError::Timeout(_))));
+    }
+
+    #[tokio::test]
+    async fn test_async_get_key_uses_cache() {
+        let server = stub_server().await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = KeysClient::builder(&server.url)
+            .key_cache(KeyCache::new(dir.path(), crate::cache::DEFAULT_CACHE_TTL))
+            .build_async()
+            .unwrap();
+
+        client.get_key("cached").await.unwrap();
+        client.get_key("cached").await.unwrap();
+
+        assert_eq!(server.requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,476 @@
+use crate::error::{ClientError, Result};
+use crate::models::Key;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use violet_core::crypto::aes_gcm;
+use violet_core::crypto::types::{DEK_SIZE, GCM_NONCE_SIZE, GCM_TAG_SIZE};
+
+/// Default lifetime of a cached key
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+const CACHE_FILE: &str = "keys.bin";
+const FILE_KEY: &str = "cache.key";
+const LOCK_FILE: &str = "keys.lock";
+
+/// Distinguishes temporary files written by threads of the same process
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Persistent, encrypted on-disk cache of keys fetched from the Keys server
+///
+/// Entries are stored in a single file (`keys.bin`) encrypted with AES-256-GCM
+/// under a machine-local file key (`cache.key`), which is generated on first
+/// use and only readable by the owner. Each entry expires after the configured
+/// TTL. Clients scope their entries to the Keys server they talk to, so a
+/// directory shared by clients of different servers never mixes up their keys.
+///
+/// A cache file that cannot be read, decrypted or parsed is treated as empty,
+/// so a corrupt cache only ever costs a trip to the server. Writers hold a lock
+/// on `keys.lock` while they update the cache, so processes sharing a directory
+/// do not lose each other's entries.
+#[derive(Debug, Clone)]
+pub struct KeyCache {
+    dir: PathBuf,
+    ttl: Duration,
+    scope: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheContents {
+    entries: HashMap<String, CacheEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// Hex-encoded key data, as returned by the server
+    key: String,
+
+    /// Expiry time in seconds since the Unix epoch
+    expires_at: u64,
+}
+
+impl KeyCache {
+    /// Create a cache rooted at `dir` with the given per-entry TTL
+    pub fn new(dir: impl Into<PathBuf>, ttl: Duration) -> Self {
+        Self {
+            dir: dir.into(),
+            ttl,
+            scope: String::new(),
+        }
+    }
+
+    /// Scope entries to the Keys server at `base_url`
+    pub(crate) fn for_server(mut self, base_url: &str) -> Self {
+        self.scope = base_url.to_string();
+        self
+    }
+
+    /// Default cache directory: `$XDG_CACHE_HOME/violet` or `~/.cache/violet`
+    pub fn default_dir() -> Option<PathBuf> {
+        if let Some(dir) = std::env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty()) {
+            return Some(PathBuf::from(dir).join("violet"));
+        }
+        std::env::var_os("HOME")
+            .filter(|d| !d.is_empty())
+            .map(|home| PathBuf::from(home).join(".cache").join("violet"))
+    }
+
+    /// Directory holding the cache and file key
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Look up a key, returning `None` on a miss, an expired entry or an unreadable cache
+    pub fn get(&self, uuid: &str) -> Option<Key> {
+        let contents = self.load();
+        let entry = contents.entries.get(&self.entry_key(uuid))?;
+
+        if entry.expires_at <= now_secs() {
+            tracing::debug!("Cached key expired: {}", uuid);
+            return None;
+        }
+
+        tracing::debug!("Key cache hit: {}", uuid);
+        Some(Key {
+            uuid: uuid.to_string(),
+            key: entry.key.clone(),
+            algorithm: None,
+        })
+    }
+
+    /// Store a key, dropping any expired entries at the same time
+    pub fn put(&self, key: &Key) -> Result<()> {
+        let _lock = self.lock()?;
+        let now = now_secs();
+        let mut contents = self.load();
+        contents.entries.retain(|_, entry| entry.expires_at > now);
+        contents.entries.insert(
+            self.entry_key(&key.uuid),
+            CacheEntry {
+                key: key.key.clone(),
+                expires_at: now.saturating_add(self.ttl.as_secs()),
+            },
+        );
+        self.store(&contents)
+    }
+
+    /// Drop a key, e.g. once it has been deleted on the server
+    pub fn remove(&self, uuid: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut contents = self.load();
+        if contents.entries.remove(&self.entry_key(uuid)).is_none() {
+            return Ok(());
+        }
+        tracing::debug!("Removed cached key: {}", uuid);
+        self.store(&contents)
+    }
+
+    /// Remove the cache file. The file key is kept so it does not need to be regenerated.
+    pub fn clear(&self) -> Result<()> {
+        match fs::remove_file(self.dir.join(CACHE_FILE)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(ClientError::CacheError(e.to_string())),
+        }
+    }
+
+    /// Entries of an unscoped cache are keyed by UUID alone
+    fn entry_key(&self, uuid: &str) -> String {
+        if self.scope.is_empty() {
+            uuid.to_string()
+        } else {
+            format!("{} {}", self.scope, uuid)
+        }
+    }
+
+    /// A temporary file name unique to this process and call
+    fn tmp_path(&self, name: &str) -> PathBuf {
+        let counter = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        self.dir.join(format!("{}.{}.{}.tmp", name, std::process::id(), counter))
+    }
+
+    /// Take the writers' lock, held until the returned file is dropped
+    fn lock(&self) -> Result<File> {
+        create_private_dir(&self.dir)?;
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(false);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let file = options
+            .open(self.dir.join(LOCK_FILE))
+            .map_err(|e| ClientError::CacheError(e.to_string()))?;
+        file.lock().map_err(|e| ClientError::CacheError(e.to_string()))?;
+        Ok(file)
+    }
+
+    /// Read and decrypt the cache, treating any failure as an empty cache
+    fn load(&self) -> CacheContents {
+        let path = self.dir.join(CACHE_FILE);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(_) => return CacheContents::default(),
+        };
+
+        match self.decode(&data) {
+            Some(contents) => contents,
+            None => {
+                tracing::warn!("Ignoring unreadable key cache: {}", path.display());
+                CacheContents::default()
+            }
+        }
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<CacheContents> {
+        if data.len() < GCM_NONCE_SIZE + GCM_TAG_SIZE {
+            return None;
+        }
+
+        let file_key = self.read_file_key()?;
+        let tag_start = data.len() - GCM_TAG_SIZE;
+        let nonce = &data[..GCM_NONCE_SIZE];
+        let ciphertext = &data[GCM_NONCE_SIZE..tag_start];
+        let tag = &data[tag_start..];
+
+        let plaintext = aes_gcm::decrypt(ciphertext, &file_key, nonce, tag).ok()?;
+        serde_json::from_slice(&plaintext).ok()
+    }
+
+    /// Encrypt and write the cache: nonce || ciphertext || tag
+    fn store(&self, contents: &CacheContents) -> Result<()> {
+        let file_key = self.file_key()?;
+        let plaintext = serde_json::to_vec(contents)
+            .map_err(|e| ClientError::CacheError(e.to_string()))?;
+        let (ciphertext, nonce, tag) = aes_gcm::encrypt(&plaintext, &file_key)
+            .map_err(|e| ClientError::CacheError(e.to_string()))?;
+
+        let mut data = Vec::with_capacity(nonce.len() + ciphertext.len() + tag.len());
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        data.extend_from_slice(&tag);
+
+        // Write to a temporary file and rename so readers never see a partial cache
+        let tmp_path = self.tmp_path(CACHE_FILE);
+        let result = write_private(&tmp_path, &data).and_then(|()| {
+            fs::rename(&tmp_path, self.dir.join(CACHE_FILE))
+                .map_err(|e| ClientError::CacheError(e.to_string()))
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    fn read_file_key(&self) -> Option<Vec<u8>> {
+        fs::read(self.dir.join(FILE_KEY))
+            .ok()
+            .filter(|key| key.len() == DEK_SIZE)
+    }
+
+    /// Load the file key, generating it (and the cache directory) if missing
+    ///
+    /// A new key is written to a temporary file and linked into place, which
+    /// fails if another process created the key first; that key is then used
+    /// instead, so a concurrent writer's entries stay readable. Only a corrupt
+    /// key is replaced.
+    fn file_key(&self) -> Result<Vec<u8>> {
+        if let Some(key) = self.read_file_key() {
+            return Ok(key);
+        }
+
+        create_private_dir(&self.dir)?;
+
+        let mut key = vec![0u8; DEK_SIZE];
+        rand::thread_rng().fill_bytes(&mut key);
+        let tmp_path = self.tmp_path(FILE_KEY);
+        write_private(&tmp_path, &key)?;
+
+        let path = self.dir.join(FILE_KEY);
+        let result = match fs::hard_link(&tmp_path, &path) {
+            Ok(()) => {
+                tracing::debug!("Generated new key cache file key in {}", self.dir.display());
+                Ok(key)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => match self.read_file_key() {
+                Some(existing) => Ok(existing),
+                None => {
+                    tracing::warn!("Replacing corrupt key cache file key in {}", self.dir.display());
+                    fs::rename(&tmp_path, &path)
+                        .map(|()| key)
+                        .map_err(|e| ClientError::CacheError(e.to_string()))
+                }
+            },
+            Err(e) => Err(ClientError::CacheError(e.to_string())),
+        };
+        let _ = fs::remove_file(&tmp_path);
+        result
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn create_private_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).map_err(|e| ClientError::CacheError(e.to_string()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+            .map_err(|e| ClientError::CacheError(e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Write a file readable and writable only by the owner (0600)
+fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| ClientError::CacheError(e.to_string()))?;
+    file.write_all(data)
+        .map_err(|e| ClientError::CacheError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key(uuid: &str) -> Key {
+        Key {
+            uuid: uuid.to_string(),
+            key: "ab".repeat(32),
+            algorithm: None,
+        }
+    }
+
+    #[test]
+    fn test_cache_hit() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = KeyCache::new(dir.path(), DEFAULT_CACHE_TTL);
+
+        assert!(cache.get("uuid-1").is_none());
+
+        cache.put(&test_key("uuid-1")).unwrap();
+        assert_eq!(cache.get("uuid-1"), Some(test_key("uuid-1")));
+
+        // A second instance over the same directory sees the same entries
+        let reopened = KeyCache::new(dir.path(), DEFAULT_CACHE_TTL);
+        assert_eq!(reopened.get("uuid-1"), Some(test_key("uuid-1")));
+    }
+
+    #[test]
+    fn test_cache_file_is_encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = KeyCache::new(dir.path(), DEFAULT_CACHE_TTL);
+        cache.put(&test_key("uuid-1")).unwrap();
+
+        let raw = fs::read(dir.path().join(CACHE_FILE)).unwrap();
+        let raw = String::from_utf8_lossy(&raw);
+        assert!(!raw.contains("uuid-1"));
+        assert!(!raw.contains(&"ab".repeat(32)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_key_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = KeyCache::new(dir.path().join("violet"), DEFAULT_CACHE_TTL);
+        cache.put(&test_key("uuid-1")).unwrap();
+
+        let metadata = fs::metadata(dir.path().join("violet").join(FILE_KEY)).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = KeyCache::new(dir.path(), Duration::ZERO);
+
+        cache.put(&test_key("uuid-1")).unwrap();
+        assert!(cache.get("uuid-1").is_none());
+    }
+
+    #[test]
+    fn test_corrupt_cache_is_a_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = KeyCache::new(dir.path(), DEFAULT_CACHE_TTL);
+        cache.put(&test_key("uuid-1")).unwrap();
+
+        fs::write(dir.path().join(CACHE_FILE), b"definitely not a cache").unwrap();
+        assert!(cache.get("uuid-1").is_none());
+
+        // The cache recovers on the next write
+        cache.put(&test_key("uuid-2")).unwrap();
+        assert_eq!(cache.get("uuid-2"), Some(test_key("uuid-2")));
+    }
+
+    #[test]
+    fn test_corrupt_file_key_is_a_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = KeyCache::new(dir.path(), DEFAULT_CACHE_TTL);
+        cache.put(&test_key("uuid-1")).unwrap();
+
+        fs::write(dir.path().join(FILE_KEY), b"short").unwrap();
+        assert!(cache.get("uuid-1").is_none());
+
+        cache.put(&test_key("uuid-2")).unwrap();
+        assert_eq!(cache.get("uuid-2"), Some(test_key("uuid-2")));
+    }
+
+    #[test]
+    fn test_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = KeyCache::new(dir.path(), DEFAULT_CACHE_TTL);
+
+        // Removing a key that is not cached is not an error
+        cache.remove("uuid-1").unwrap();
+
+        cache.put(&test_key("uuid-1")).unwrap();
+        cache.put(&test_key("uuid-2")).unwrap();
+        cache.remove("uuid-1").unwrap();
+        assert!(cache.get("uuid-1").is_none());
+        assert_eq!(cache.get("uuid-2"), Some(test_key("uuid-2")));
+    }
+
+    #[test]
+    fn test_entries_are_scoped_by_server() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = KeyCache::new(dir.path(), DEFAULT_CACHE_TTL).for_server("http://first/v1/");
+        let second = KeyCache::new(dir.path(), DEFAULT_CACHE_TTL).for_server("http://second/v1/");
+
+        first.put(&test_key("uuid-1")).unwrap();
+        assert!(second.get("uuid-1").is_none());
+        assert_eq!(first.get("uuid-1"), Some(test_key("uuid-1")));
+
+        // Removing through one server leaves the other's entry alone
+        second.put(&test_key("uuid-1")).unwrap();
+        second.remove("uuid-1").unwrap();
+        assert_eq!(first.get("uuid-1"), Some(test_key("uuid-1")));
+    }
+
+    #[test]
+    fn test_concurrent_first_use_shares_file_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let cache = KeyCache::new(dir.path().join("violet"), DEFAULT_CACHE_TTL);
+                std::thread::spawn(move || {
+                    cache.put(&test_key(&format!("uuid-{}", i))).unwrap();
+                    cache.file_key().unwrap()
+                })
+            })
+            .collect();
+        let keys: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert!(keys.windows(2).all(|pair| pair[0] == pair[1]));
+        // Every writer's entry survives the others' updates
+        let cache = KeyCache::new(dir.path().join("violet"), DEFAULT_CACHE_TTL);
+        for i in 0..8 {
+            assert!(cache.get(&format!("uuid-{}", i)).is_some(), "uuid-{} was lost", i);
+        }
+        // No temporary files are left behind
+        let names: Vec<_> = fs::read_dir(dir.path().join("violet"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert!(names.iter().all(|name| !name.ends_with(".tmp")), "{:?}", names);
+    }
+
+    #[test]
+    fn test_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = KeyCache::new(dir.path(), DEFAULT_CACHE_TTL);
+
+        // Clearing an empty cache is not an error
+        cache.clear().unwrap();
+
+        cache.put(&test_key("uuid-1")).unwrap();
+        cache.clear().unwrap();
+        assert!(cache.get("uuid-1").is_none());
+    }
+}
@@ -1,16 +1,35 @@
+use crate::models::KeyEncoding;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("HTTP request failed: {0}")]
-    RequestFailed(#[from] reqwest::Error),
+    RequestFailed(#[source] reqwest::Error),
+
+    #[error("Request to Keys server timed out: {0}")]
+    Timeout(#[source] reqwest::Error),
+
+    #[error("Timeout must be greater than zero")]
+    InvalidTimeout,
+
+    #[error("Rate limit must be a positive number of requests per second, not {0}")]
+    InvalidRateLimit(f64),
 
     #[error("URL parse error: {0}")]
     UrlParseError(#[from] url::ParseError),
 
+    #[error("Invalid base URL: {0}")]
+    InvalidBaseUrl(String),
+
+    #[error("Invalid default header: {0}")]
+    InvalidHeader(String),
+
     #[error("Key not found: {0}")]
     KeyNotFound(String),
 
+    #[error("Keys server does not support listing keys (HTTP {0})")]
+    ListingUnsupported(u16),
+
     #[error("Unexpected HTTP status: {0}")]
     UnexpectedStatus(u16),
 
@@ -19,6 +38,60 @@ pub enum ClientError {
 
     #[error("Invalid key format")]
     InvalidKeyFormat,
+
+    #[error("Key {uuid} is not valid {expected}; does the client's key encoding match the Keys server?")]
+    KeyEncodingMismatch { uuid: String, expected: KeyEncoding },
+
+    #[error("Key cache error: {0}")]
+    CacheError(String),
+
+    #[error("Access denied: {0}")]
+    AccessDenied(String),
+
+    #[error("Request throttled: {0}")]
+    Throttled(String),
+
+    #[error("Key provider error: {0}")]
+    ProviderError(String),
+
+    #[error("Rate limit exceeded: request would wait {0:?}")]
+    RateLimited(std::time::Duration),
+}
+
+impl ClientError {
+    /// Name of the error variant, used as a structured logging field
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            ClientError::RequestFailed(_) => "RequestFailed",
+            ClientError::Timeout(_) => "Timeout",
+            ClientError::InvalidTimeout => "InvalidTimeout",
+            ClientError::InvalidRateLimit(_) => "InvalidRateLimit",
+            ClientError::UrlParseError(_) => "UrlParseError",
+            ClientError::InvalidBaseUrl(_) => "InvalidBaseUrl",
+            ClientError::InvalidHeader(_) => "InvalidHeader",
+            ClientError::KeyNotFound(_) => "KeyNotFound",
+            ClientError::ListingUnsupported(_) => "ListingUnsupported",
+            ClientError::UnexpectedStatus(_) => "UnexpectedStatus",
+            ClientError::HexDecodeError(_) => "HexDecodeError",
+            ClientError::InvalidKeyFormat => "InvalidKeyFormat",
+            ClientError::KeyEncodingMismatch { .. } => "KeyEncodingMismatch",
+            ClientError::CacheError(_) => "CacheError",
+            ClientError::AccessDenied(_) => "AccessDenied",
+            ClientError::Throttled(_) => "Throttled",
+            ClientError::ProviderError(_) => "ProviderError",
+            ClientError::RateLimited(_) => "RateLimited",
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ClientError::Timeout(e)
+        } else {
+            ClientError::RequestFailed(e)
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, ClientError>;
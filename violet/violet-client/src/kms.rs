@@ -0,0 +1,274 @@
+use crate::error::{ClientError, Result};
+use crate::models::Key;
+use crate::provider::KeyProvider;
+use aws_sdk_kms::error::ProvideErrorMetadata;
+use aws_sdk_kms::primitives::Blob;
+use aws_sdk_kms::types::DataKeySpec;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+/// A freshly generated KMS data key
+pub struct DataKey {
+    /// Plaintext data key, used as the KEK
+    pub plaintext: Vec<u8>,
+
+    /// The data key encrypted under the KMS key
+    pub ciphertext_blob: Vec<u8>,
+}
+
+/// The subset of the KMS API used by [`KmsKeyProvider`]
+///
+/// Implemented by [`SdkKmsApi`] for real AWS calls and by mocks in tests.
+pub trait KmsApi: Send + Sync {
+    /// Call GenerateDataKey for a 256-bit key under `kms_key_id`
+    fn generate_data_key(&self, kms_key_id: &str) -> Result<DataKey>;
+
+    /// Call Decrypt on a ciphertext blob returned by GenerateDataKey
+    fn decrypt(&self, ciphertext_blob: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// [`KmsApi`] backed by the AWS SDK
+///
+/// Region and credentials come from the standard SDK provider chain
+/// (environment, profile, IMDS, ...).
+pub struct SdkKmsApi {
+    client: aws_sdk_kms::Client,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl SdkKmsApi {
+    /// Load AWS configuration from the environment
+    pub fn from_env() -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| ClientError::ProviderError(e.to_string()))?;
+        let config = runtime.block_on(aws_config::load_defaults(aws_config::BehaviorVersion::latest()));
+
+        Ok(Self {
+            client: aws_sdk_kms::Client::new(&config),
+            runtime,
+        })
+    }
+}
+
+impl KmsApi for SdkKmsApi {
+    fn generate_data_key(&self, kms_key_id: &str) -> Result<DataKey> {
+        let output = self
+            .runtime
+            .block_on(
+                self.client
+                    .generate_data_key()
+                    .key_id(kms_key_id)
+                    .key_spec(DataKeySpec::Aes256)
+                    .send(),
+            )
+            .map_err(|e| map_kms_error(e.code(), e.message(), kms_key_id))?;
+
+        let plaintext = output.plaintext().ok_or(ClientError::InvalidKeyFormat)?;
+        let ciphertext_blob = output.ciphertext_blob().ok_or(ClientError::InvalidKeyFormat)?;
+
+        Ok(DataKey {
+            plaintext: plaintext.as_ref().to_vec(),
+            ciphertext_blob: ciphertext_blob.as_ref().to_vec(),
+        })
+    }
+
+    fn decrypt(&self, ciphertext_blob: &[u8]) -> Result<Vec<u8>> {
+        let output = self
+            .runtime
+            .block_on(
+                self.client
+                    .decrypt()
+                    .ciphertext_blob(Blob::new(ciphertext_blob))
+                    .send(),
+            )
+            .map_err(|e| map_kms_error(e.code(), e.message(), "<ciphertext blob>"))?;
+
+        let plaintext = output.plaintext().ok_or(ClientError::InvalidKeyFormat)?;
+        Ok(plaintext.as_ref().to_vec())
+    }
+}
+
+/// Key provider that uses AWS KMS data keys as KEKs
+///
+/// `create_key` calls GenerateDataKey under the configured KMS key and returns
+/// the plaintext data key as the KEK. The key_id is the base64-encoded
+/// encrypted data key, so `get_key` recovers the KEK with a single Decrypt
+/// call and no extra storage is needed.
+pub struct KmsKeyProvider<A: KmsApi = SdkKmsApi> {
+    api: A,
+    kms_key_id: String,
+}
+
+impl KmsKeyProvider<SdkKmsApi> {
+    /// Create a provider for a KMS key ID, ARN or alias using the standard SDK chain
+    pub fn from_env(kms_key_id: impl Into<String>) -> Result<Self> {
+        Ok(Self::with_api(SdkKmsApi::from_env()?, kms_key_id))
+    }
+}
+
+impl<A: KmsApi> KmsKeyProvider<A> {
+    /// Create a provider over an explicit KMS API implementation
+    pub fn with_api(api: A, kms_key_id: impl Into<String>) -> Self {
+        Self {
+            api,
+            kms_key_id: kms_key_id.into(),
+        }
+    }
+}
+
+impl<A: KmsApi> KeyProvider for KmsKeyProvider<A> {
+    fn create_key(&self) -> Result<Key> {
+        let data_key = self.api.generate_data_key(&self.kms_key_id)?;
+        let key = Key {
+            uuid: BASE64.encode(&data_key.ciphertext_blob),
+            key: hex::encode(&data_key.plaintext),
+            algorithm: None,
+        };
+        tracing::info!("Generated KMS data key under {}", self.kms_key_id);
+        Ok(key)
+    }
+
+    fn get_key(&self, key_id: &str) -> Result<Key> {
+        let ciphertext_blob = BASE64
+            .decode(key_id)
+            .map_err(|_| ClientError::KeyNotFound(key_id.to_string()))?;
+        let plaintext = self.api.decrypt(&ciphertext_blob)?;
+
+        Ok(Key {
+            uuid: key_id.to_string(),
+            key: hex::encode(plaintext),
+            algorithm: None,
+        })
+    }
+}
+
+/// Map a KMS error code to the closest `ClientError`
+fn map_kms_error(code: Option<&str>, message: Option<&str>, key_id: &str) -> ClientError {
+    let message = message.unwrap_or("no message").to_string();
+    match code {
+        Some("NotFoundException") | Some("InvalidCiphertextException") | Some("IncorrectKeyException") => {
+            ClientError::KeyNotFound(key_id.to_string())
+        }
+        Some("AccessDeniedException") | Some("DisabledException") | Some("KMSInvalidStateException") => {
+            ClientError::AccessDenied(message)
+        }
+        Some("ThrottlingException") | Some("LimitExceededException") => ClientError::Throttled(message),
+        Some(code) => ClientError::ProviderError(format!("{}: {}", code, message)),
+        None => ClientError::ProviderError(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Mock KMS that "encrypts" data keys by prefixing them
+    struct MockKms {
+        next_key: u8,
+        error_code: Option<&'static str>,
+        requested: Mutex<Vec<String>>,
+    }
+
+    impl MockKms {
+        fn new(next_key: u8) -> Self {
+            Self {
+                next_key,
+                error_code: None,
+                requested: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn failing(code: &'static str) -> Self {
+            Self {
+                error_code: Some(code),
+                ..Self::new(0)
+            }
+        }
+    }
+
+    impl KmsApi for MockKms {
+        fn generate_data_key(&self, kms_key_id: &str) -> Result<DataKey> {
+            self.requested.lock().unwrap().push(kms_key_id.to_string());
+            if let Some(code) = self.error_code {
+                return Err(map_kms_error(Some(code), Some("mock"), kms_key_id));
+            }
+            let plaintext = vec![self.next_key; 32];
+            let mut ciphertext_blob = b"wrapped:".to_vec();
+            ciphertext_blob.extend_from_slice(&plaintext);
+            Ok(DataKey { plaintext, ciphertext_blob })
+        }
+
+        fn decrypt(&self, ciphertext_blob: &[u8]) -> Result<Vec<u8>> {
+            if let Some(code) = self.error_code {
+                return Err(map_kms_error(Some(code), Some("mock"), "blob"));
+            }
+            ciphertext_blob
+                .strip_prefix(b"wrapped:")
+                .map(|k| k.to_vec())
+                .ok_or_else(|| map_kms_error(Some("InvalidCiphertextException"), None, "blob"))
+        }
+    }
+
+    #[test]
+    fn test_create_and_get_key_roundtrip() {
+        let provider = KmsKeyProvider::with_api(MockKms::new(7), "arn:aws:kms:us-east-1:111122223333:key/test");
+
+        let created = provider.create_key().unwrap();
+        assert_eq!(created.as_bytes().unwrap(), vec![7u8; 32]);
+        assert_eq!(
+            provider.api.requested.lock().unwrap().as_slice(),
+            ["arn:aws:kms:us-east-1:111122223333:key/test"]
+        );
+
+        let fetched = provider.get_key(&created.uuid).unwrap();
+        assert_eq!(created, fetched);
+    }
+
+    #[test]
+    fn test_get_key_with_invalid_key_id() {
+        let provider = KmsKeyProvider::with_api(MockKms::new(1), "alias/test");
+
+        let result = provider.get_key("not base64!");
+        assert!(matches!(result, Err(ClientError::KeyNotFound(_))));
+
+        let result = provider.get_key(&BASE64.encode(b"garbage"));
+        assert!(matches!(result, Err(ClientError::KeyNotFound(_))));
+    }
+
+    #[test]
+    fn test_sdk_errors_are_mapped() {
+        let denied = KmsKeyProvider::with_api(MockKms::failing("AccessDeniedException"), "alias/test");
+        assert!(matches!(denied.create_key(), Err(ClientError::AccessDenied(_))));
+
+        let throttled = KmsKeyProvider::with_api(MockKms::failing("ThrottlingException"), "alias/test");
+        assert!(matches!(throttled.create_key(), Err(ClientError::Throttled(_))));
+
+        let missing = KmsKeyProvider::with_api(MockKms::failing("NotFoundException"), "alias/test");
+        assert!(matches!(missing.create_key(), Err(ClientError::KeyNotFound(id)) if id == "alias/test"));
+    }
+
+    #[test]
+    fn test_unknown_error_code() {
+        let error = map_kms_error(Some("KMSInternalException"), Some("boom"), "alias/test");
+        assert!(matches!(error, ClientError::ProviderError(ref m) if m == "KMSInternalException: boom"));
+
+        let error = map_kms_error(None, None, "alias/test");
+        assert!(matches!(error, ClientError::ProviderError(_)));
+    }
+
+    // Integration test: set VIOLET_KMS_KEY_ID (and AWS_ENDPOINT_URL for LocalStack)
+    #[test]
+    #[ignore]
+    fn test_kms_roundtrip() {
+        let kms_key_id = std::env::var("VIOLET_KMS_KEY_ID").expect("VIOLET_KMS_KEY_ID must be set");
+        let provider = KmsKeyProvider::from_env(kms_key_id).unwrap();
+
+        let created = provider.create_key().unwrap();
+        assert_eq!(created.size_bytes(), 32);
+
+        let fetched = provider.get_key(&created.uuid).unwrap();
+        assert_eq!(created, fetched);
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+use std::ops::Deref;
+use violet_core::SecretKey;
+use zeroize::Zeroize;
+
+/// Decoded key bytes borrowed from the Keys server for the length of one operation
+///
+/// Returned by [`KeysClient::lease_key`](crate::KeysClient::lease_key). The
+/// lease derefs to the key bytes and wipes them when it goes out of scope, or
+/// as soon as [`release`](Self::release) is called, so key material does not
+/// outlive the code that needed it.
+///
+/// The buffer defaults to [`SecretKey`]; any zeroizable byte buffer works.
+pub struct KeyLease<K: Zeroize + AsRef<[u8]> = SecretKey> {
+    uuid: String,
+    key: K,
+}
+
+impl<K: Zeroize + AsRef<[u8]>> KeyLease<K> {
+    pub(crate) fn new(uuid: impl Into<String>, key: K) -> Self {
+        Self { uuid: uuid.into(), key }
+    }
+
+    /// UUID of the leased key
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    /// End the lease now, wiping the key bytes
+    pub fn release(self) {}
+}
+
+impl<K: Zeroize + AsRef<[u8]>> Deref for KeyLease<K> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.key.as_ref()
+    }
+}
+
+impl<K: Zeroize + AsRef<[u8]>> AsRef<[u8]> for KeyLease<K> {
+    fn as_ref(&self) -> &[u8] {
+        self.key.as_ref()
+    }
+}
+
+impl<K: Zeroize + AsRef<[u8]>> Drop for KeyLease<K> {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// Key material is redacted so leases can't leak through `{:?}` logging
+impl<K: Zeroize + AsRef<[u8]>> fmt::Debug for KeyLease<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyLease")
+            .field("uuid", &self.uuid)
+            .field("len", &self.key.as_ref().len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// The bytes an `Observed` key held when it was dropped
+    type DropRecord = Rc<RefCell<Option<Vec<u8>>>>;
+
+    /// Buffer that records its contents when dropped, after the lease has
+    /// had its chance to wipe it
+    struct Observed {
+        bytes: Vec<u8>,
+        on_drop: DropRecord,
+    }
+
+    impl Zeroize for Observed {
+        fn zeroize(&mut self) {
+            self.bytes.iter_mut().for_each(|b| *b = 0);
+        }
+    }
+
+    impl AsRef<[u8]> for Observed {
+        fn as_ref(&self) -> &[u8] {
+            &self.bytes
+        }
+    }
+
+    impl Drop for Observed {
+        fn drop(&mut self) {
+            *self.on_drop.borrow_mut() = Some(self.bytes.clone());
+        }
+    }
+
+    fn observed_lease(bytes: &[u8]) -> (KeyLease<Observed>, DropRecord) {
+        let on_drop = Rc::new(RefCell::new(None));
+        let key = Observed { bytes: bytes.to_vec(), on_drop: on_drop.clone() };
+        (KeyLease::new("lease-uuid", key), on_drop)
+    }
+
+    #[test]
+    fn test_bytes_accessible_during_lease() {
+        let lease = KeyLease::new("lease-uuid", SecretKey::new(&[0x42; 32]));
+        assert_eq!(lease.uuid(), "lease-uuid");
+        assert_eq!(&*lease, &[0x42; 32][..]);
+        assert_eq!(lease.as_ref().len(), 32);
+    }
+
+    #[test]
+    fn test_zeroized_on_drop() {
+        let (lease, on_drop) = observed_lease(&[0x42; 32]);
+        assert_eq!(&*lease, &[0x42; 32][..]);
+        assert!(on_drop.borrow().is_none());
+
+        drop(lease);
+        assert_eq!(on_drop.borrow().as_deref(), Some(&[0u8; 32][..]));
+    }
+
+    #[test]
+    fn test_release_zeroizes() {
+        let (lease, on_drop) = observed_lease(&[0x17; 16]);
+        lease.release();
+        assert_eq!(on_drop.borrow().as_deref(), Some(&[0u8; 16][..]));
+    }
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let lease = KeyLease::new("lease-uuid", SecretKey::new(&[0xab; 32]));
+        let debug = format!("{:?}", lease);
+        assert!(debug.contains("lease-uuid") && debug.contains("len: 32"), "{}", debug);
+        assert!(!debug.contains("ab,") && !debug.contains("171"), "{}", debug);
+    }
+}
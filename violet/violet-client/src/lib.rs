@@ -1,8 +1,27 @@
+pub mod async_client;
+pub mod cache;
 pub mod client;
 pub mod error;
+#[cfg(feature = "aws-kms")]
+pub mod kms;
+pub mod lease;
 pub mod models;
+pub mod provider;
+pub mod rate_limit;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+#[cfg(feature = "vault")]
+pub mod vault;
 
 // Re-export commonly used types
-pub use client::KeysClient;
+pub use async_client::AsyncKeysClient;
+pub use cache::KeyCache;
+pub use client::{CallOptions, KeysClient, KeysClientBuilder};
 pub use error::{ClientError, Result};
-pub use models::Key;
+pub use lease::KeyLease;
+pub use models::{Key, KeyEncoding};
+pub use provider::KeyProvider;
+#[cfg(feature = "aws-kms")]
+pub use kms::KmsKeyProvider;
+#[cfg(feature = "vault")]
+pub use vault::VaultKeyProvider;
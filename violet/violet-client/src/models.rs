@@ -1,15 +1,55 @@
+use crate::error::{ClientError, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How a Keys server encodes key material in its responses
+///
+/// The reference server uses hex. Keys fetched by a client configured with
+/// [`KeysClientBuilder::key_encoding`](crate::KeysClientBuilder::key_encoding)
+/// are converted to hex, so [`Key::as_bytes`] works whatever the server sends.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+impl KeyEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyEncoding::Hex => "hex",
+            KeyEncoding::Base64 => "base64",
+        }
+    }
+}
+
+impl fmt::Display for KeyEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
 /// Key response from the Keys server API
 ///
 /// Matches the Java Key interface in api/src/main/java/com/codeheadsystems/api/keys/v1/Key.java
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Key {
     /// UUID identifier for the key
     pub uuid: String,
 
     /// Hex-encoded key data (64 characters for 256-bit key)
+    ///
+    /// Servers that send base64 are supported through [`KeyEncoding`].
     pub key: String,
+
+    /// Algorithm the server recorded for the key, if it tracks one
+    ///
+    /// Only set when the key was created with
+    /// [`KeysClient::create_key_for`](crate::KeysClient::create_key_for) and
+    /// the server supports algorithm hints.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<String>,
 }
 
 impl Key {
@@ -17,16 +57,82 @@ impl Key {
     ///
     /// The Keys server returns keys as hex strings (e.g., "a1b2c3d4..."),
     /// which must be converted to bytes for cryptographic use.
-    pub fn as_bytes(&self) -> Result<Vec<u8>, hex::FromHexError> {
+    pub fn as_bytes(&self) -> std::result::Result<Vec<u8>, hex::FromHexError> {
         hex::decode(&self.key)
     }
 
+    /// Decode the key data as `encoding`
+    ///
+    /// # Errors
+    /// Returns `ClientError::KeyEncodingMismatch` if the data is not valid
+    /// `encoding`, which usually means the client and server disagree on it.
+    pub fn as_bytes_with(&self, encoding: KeyEncoding) -> Result<Vec<u8>> {
+        let decoded = match encoding {
+            KeyEncoding::Hex => hex::decode(&self.key).ok(),
+            KeyEncoding::Base64 => BASE64.decode(&self.key).ok(),
+        };
+        decoded.ok_or_else(|| ClientError::KeyEncodingMismatch {
+            uuid: self.uuid.clone(),
+            expected: encoding,
+        })
+    }
+
+    /// Re-encode key data received as `encoding` as hex
+    pub(crate) fn into_hex(mut self, encoding: KeyEncoding) -> Result<Self> {
+        if encoding != KeyEncoding::Hex {
+            self.key = hex::encode(self.as_bytes_with(encoding)?);
+        }
+        Ok(self)
+    }
+
     /// Get the key size in bytes
     pub fn size_bytes(&self) -> usize {
         self.key.len() / 2 // Hex uses 2 characters per byte
     }
 }
 
+/// Key material is redacted so keys can't leak through `{:?}` logging
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("uuid", &self.uuid)
+            .field("key", &"<redacted>")
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+/// One page of `GET /v1/keys/`
+///
+/// Servers may answer with a bare array of the whole list, or with an object
+/// whose `next` cursor asks for another page.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum KeyListPage {
+    Paged {
+        keys: Vec<KeyListEntry>,
+        #[serde(default, alias = "nextCursor")]
+        next: Option<String>,
+    },
+    All(Vec<KeyListEntry>),
+}
+
+/// A listed key: its UUID, or a key object of which only the UUID is kept
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum KeyListEntry {
+    Uuid(String),
+    Key { uuid: String },
+}
+
+impl KeyListEntry {
+    pub(crate) fn into_uuid(self) -> String {
+        match self {
+            KeyListEntry::Uuid(uuid) | KeyListEntry::Key { uuid } => uuid,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -36,6 +142,7 @@ mod tests {
         let key = Key {
             uuid: "test-uuid".to_string(),
             key: "0123456789abcdef".to_string(), // 8 bytes
+            algorithm: None,
         };
 
         let bytes = key.as_bytes().unwrap();
@@ -43,11 +150,53 @@ mod tests {
         assert_eq!(bytes, vec![0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]);
     }
 
+    #[test]
+    fn test_key_as_bytes_with_encoding() {
+        let material: Vec<u8> = (0..32).collect();
+        let hex_key = Key {
+            uuid: "hex".to_string(),
+            key: hex::encode(&material),
+            algorithm: None,
+        };
+        let base64_key = Key {
+            uuid: "base64".to_string(),
+            key: BASE64.encode(&material),
+            algorithm: None,
+        };
+
+        assert_eq!(hex_key.as_bytes_with(KeyEncoding::Hex).unwrap(), material);
+        assert_eq!(base64_key.as_bytes_with(KeyEncoding::Base64).unwrap(), material);
+
+        let converted = base64_key.clone().into_hex(KeyEncoding::Base64).unwrap();
+        assert_eq!(converted.key, hex_key.key);
+        assert_eq!(converted.as_bytes().unwrap(), material);
+    }
+
+    #[test]
+    fn test_key_encoding_mismatch() {
+        let base64_key = Key {
+            uuid: "base64".to_string(),
+            key: BASE64.encode([0xffu8; 32]),
+            algorithm: None,
+        };
+        let err = base64_key.as_bytes_with(KeyEncoding::Hex).unwrap_err();
+        assert!(matches!(err, ClientError::KeyEncodingMismatch { expected: KeyEncoding::Hex, .. }));
+        assert!(err.to_string().contains("not valid hex"), "{}", err);
+
+        let hex_key = Key {
+            uuid: "hex".to_string(),
+            key: "not base64!".to_string(),
+            algorithm: None,
+        };
+        assert!(hex_key.into_hex(KeyEncoding::Base64).is_err());
+    }
+
     #[test]
     fn test_key_size_bytes() {
         let key = Key {
             uuid: "test".to_string(),
             key: "00".repeat(32), // 32 bytes = 64 hex chars
+            algorithm: None,
         };
 
         assert_eq!(key.size_bytes(), 32);
@@ -58,6 +207,7 @@ mod tests {
         let key = Key {
             uuid: "uuid-123".to_string(),
             key: "deadbeef".to_string(),
+            algorithm: None,
         };
 
         let json = serde_json::to_string(&key).unwrap();
@@ -66,11 +216,35 @@ mod tests {
         assert_eq!(key, deserialized);
     }
 
+    #[test]
+    fn test_algorithm_is_optional() {
+        let key: Key = serde_json::from_str(r#"{"uuid":"u","key":"00"}"#).unwrap();
+        assert_eq!(key.algorithm, None);
+        assert!(!serde_json::to_string(&key).unwrap().contains("algorithm"));
+
+        let key: Key = serde_json::from_str(r#"{"uuid":"u","key":"00","algorithm":"AES-256-GCM-SIV"}"#).unwrap();
+        assert_eq!(key.algorithm.as_deref(), Some("AES-256-GCM-SIV"));
+    }
+
+    #[test]
+    fn test_debug_redacts_key_material() {
+        let key = Key {
+            uuid: "uuid-123".to_string(),
+            key: "deadbeef".to_string(),
+            algorithm: None,
+        };
+
+        let debug = format!("{:?}", key);
+        assert!(debug.contains("uuid-123"));
+        assert!(!debug.contains("deadbeef"));
+    }
+
     #[test]
     fn test_invalid_hex() {
         let key = Key {
             uuid: "test".to_string(),
             key: "not-hex".to_string(),
+            algorithm: None,
         };
 
         assert!(key.as_bytes().is_err());
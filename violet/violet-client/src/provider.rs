@@ -0,0 +1,38 @@
+use crate::client::KeysClient;
+use crate::error::{ClientError, Result};
+use crate::models::Key;
+use violet_core::{DekWrapper, LocalKekWrapper};
+
+/// Source of key encryption keys (KEKs) for envelope encryption
+///
+/// `KeysClient` talks to the Keys server; other backends (such as AWS KMS)
+/// can be used in its place. The `uuid` of a returned [`Key`] is the opaque
+/// key_id stored in the envelope and passed back to `get_key` on decrypt.
+pub trait KeyProvider: Send + Sync {
+    /// Create a new KEK
+    fn create_key(&self) -> Result<Key>;
+
+    /// Retrieve the KEK for a key_id previously returned by `create_key`
+    fn get_key(&self, key_id: &str) -> Result<Key>;
+
+    /// Wrapper used to protect DEKs under `key`
+    ///
+    /// The default wraps DEKs locally under a key derived from the key
+    /// material, matching `EnvelopeEncryptor::encrypt`. Providers that never
+    /// export key material override this to wrap DEKs remotely.
+    fn dek_wrapper<'a>(&'a self, key: &'a Key) -> Result<Box<dyn DekWrapper + 'a>> {
+        let kek = key.as_bytes()?;
+        let wrapper = LocalKekWrapper::derived(&kek).map_err(|_| ClientError::InvalidKeyFormat)?;
+        Ok(Box::new(wrapper))
+    }
+}
+
+impl KeyProvider for KeysClient {
+    fn create_key(&self) -> Result<Key> {
+        KeysClient::create_key(self)
+    }
+
+    fn get_key(&self, key_id: &str) -> Result<Key> {
+        KeysClient::get_key(self, key_id)
+    }
+}
@@ -0,0 +1,127 @@
+use crate::error::{ClientError, Result};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token-bucket rate limiter shared by all clones of a client
+///
+/// Tokens refill continuously at `requests_per_second` up to `burst`. Each
+/// request takes one token; when the bucket is empty the caller waits for the
+/// next token, unless that wait would exceed `max_wait`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_second: f64,
+    burst: f64,
+    max_wait: Duration,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Create a limiter. `burst` is clamped to at least one request.
+    ///
+    /// `requests_per_second` should be finite and positive; the client builder
+    /// refuses anything else with `ClientError::InvalidRateLimit`.
+    pub fn new(requests_per_second: f64, burst: u32, max_wait: Duration) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            requests_per_second,
+            burst,
+            max_wait,
+            state: Mutex::new(BucketState {
+                tokens: burst,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token, returning how long the caller must wait before sending
+    ///
+    /// The token is reserved immediately, so concurrent callers queue up
+    /// behind each other rather than all waking at once.
+    ///
+    /// # Errors
+    /// Returns `ClientError::RateLimited` (without taking a token) if the wait
+    /// would exceed the configured budget.
+    pub fn reserve(&self) -> Result<Duration> {
+        let mut state = self.state.lock().unwrap();
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.requests_per_second).min(self.burst);
+        state.last_refill = now;
+
+        let tokens = state.tokens - 1.0;
+        let wait = if tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            // A rate too small for the wait to fit a `Duration` never refills
+            Duration::try_from_secs_f64(-tokens / self.requests_per_second).unwrap_or(Duration::MAX)
+        };
+
+        if wait > self.max_wait {
+            return Err(ClientError::RateLimited(wait));
+        }
+
+        state.tokens = tokens;
+        Ok(wait)
+    }
+
+    /// Take a token, sleeping the current thread until it is available
+    pub fn acquire_blocking(&self) -> Result<()> {
+        let wait = self.reserve()?;
+        if !wait.is_zero() {
+            tracing::debug!("Rate limited, waiting {:?}", wait);
+            std::thread::sleep(wait);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_is_immediate() {
+        let limiter = RateLimiter::new(1.0, 3, Duration::from_secs(10));
+        for _ in 0..3 {
+            assert_eq!(limiter.reserve().unwrap(), Duration::ZERO);
+        }
+        assert!(limiter.reserve().unwrap() > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_waits_queue_up() {
+        let limiter = RateLimiter::new(10.0, 1, Duration::from_secs(10));
+        limiter.reserve().unwrap();
+
+        let first = limiter.reserve().unwrap();
+        let second = limiter.reserve().unwrap();
+        assert!(first > Duration::from_millis(50));
+        assert!(second > first + Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_wait_budget_exceeded() {
+        let limiter = RateLimiter::new(1.0, 1, Duration::from_millis(100));
+        limiter.reserve().unwrap();
+
+        assert!(matches!(limiter.reserve(), Err(ClientError::RateLimited(_))));
+        // A rejected request does not consume a token
+        assert!(matches!(limiter.reserve(), Err(ClientError::RateLimited(wait)) if wait <= Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_unusable_rates_are_rate_limited_instead_of_panicking() {
+        for requests_per_second in [f64::MIN_POSITIVE, 0.0, -1.0] {
+            let limiter = RateLimiter::new(requests_per_second, 1, Duration::from_secs(10));
+            let _ = limiter.reserve();
+            assert!(matches!(limiter.reserve(), Err(ClientError::RateLimited(_))), "{}", requests_per_second);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! In-process mock of the Keys server for tests
+//!
+//! Available to this crate's tests and, with the `testutil` feature, to other
+//! crates' tests. The server runs on its own thread and runtime, so it works
+//! with both the blocking and the async client.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use rand::RngCore;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tokio::sync::oneshot;
+use crate::models::Key;
+
+/// Endpoints served by [`MockKeysServer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Route {
+    /// `POST /v1/keys/`
+    CreateKey,
+    /// `GET /v1/keys/{uuid}`
+    GetKey,
+    /// `DELETE /v1/keys/{uuid}`
+    DeleteKey,
+}
+
+/// Fixed response returned for a route instead of the default behaviour
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+#[derive(Default)]
+struct MockState {
+    keys: Mutex<HashMap<String, String>>,
+    overrides: Mutex<HashMap<Route, MockResponse>>,
+    requests: AtomicUsize,
+}
+
+impl MockState {
+    /// Count the request and return the configured override for `route`, if any
+    fn begin(&self, route: Route) -> Option<Response> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        let overrides = self.overrides.lock().unwrap();
+        overrides.get(&route).map(|response| {
+            let status = StatusCode::from_u16(response.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            (status, response.body.clone()).into_response()
+        })
+    }
+}
+
+/// A Keys server stand-in listening on a random loopback port
+///
+/// By default it behaves like the real server: `POST /v1/keys/` creates a
+/// random 256-bit key, `GET` returns stored keys and `DELETE` removes them,
+/// with 404 for unknown UUIDs. Use [`respond_with`](Self::respond_with) to
+/// return a fixed status and body for a route instead.
+///
+/// The server shuts down when dropped.
+pub struct MockKeysServer {
+    base_url: String,
+    state: Arc<MockState>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockKeysServer {
+    /// Start a server with no keys
+    ///
+    /// # Panics
+    /// Panics if the listener cannot be bound; this is test-only code.
+    pub fn start() -> Self {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind mock Keys server");
+        listener.set_nonblocking(true).expect("set mock listener non-blocking");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let state = Arc::new(MockState::default());
+        let app = Router::new()
+            .route("/v1/keys/", post(create_key))
+            .route("/v1/keys/:uuid", get(get_key).delete(delete_key))
+            .with_state(Arc::clone(&state));
+
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("build mock Keys server runtime");
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).expect("adopt mock listener");
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(async {
+                        shutdown_rx.await.ok();
+                    })
+                    .await
+                    .expect("mock Keys server failed");
+            });
+        });
+
+        Self {
+            base_url,
+            state,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        }
+    }
+
+    /// Start a server holding one key, `uuid` with hex material `key`
+    pub fn with_key(uuid: &str, key: &str) -> Self {
+        let server = Self::start();
+        server.insert_key(Key {
+            uuid: uuid.to_string(),
+            key: key.to_string(),
+            algorithm: None,
+        });
+        server
+    }
+
+    /// Base URL to pass to `KeysClient::new`, e.g. `http://127.0.0.1:54321`
+    pub fn url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Store a key so that `GET /v1/keys/{uuid}` finds it
+    pub fn insert_key(&self, key: Key) {
+        self.state.keys.lock().unwrap().insert(key.uuid, key.key);
+    }
+
+    /// Whether a key with this UUID is currently stored
+    pub fn contains_key(&self, uuid: &str) -> bool {
+        self.state.keys.lock().unwrap().contains_key(uuid)
+    }
+
+    /// Answer every request to `route` with `status` and `body` until cleared
+    pub fn respond_with(&self, route: Route, status: u16, body: impl Into<String>) {
+        let response = MockResponse {
+            status,
+            body: body.into(),
+        };
+        self.state.overrides.lock().unwrap().insert(route, response);
+    }
+
+    /// Restore the default behaviour for `route`
+    pub fn clear_response(&self, route: Route) {
+        self.state.overrides.lock().unwrap().remove(&route);
+    }
+
+    /// Number of requests received so far, across all routes
+    pub fn request_count(&self) -> usize {
+        self.state.requests.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MockKeysServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+async fn create_key(State(state): State<Arc<MockState>>) -> Response {
+    if let Some(response) = state.begin(Route::CreateKey) {
+        return response;
+    }
+
+    let mut material = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut material);
+    let key = Key {
+        uuid: uuid::Uuid::new_v4().to_string(),
+        key: hex::encode(material),
+        algorithm: None,
+    };
+    state.keys.lock().unwrap().insert(key.uuid.clone(), key.key.clone());
+    (StatusCode::CREATED, Json(key)).into_response()
+}
+
+async fn get_key(State(state): State<Arc<MockState>>, Path(uuid): Path<String>) -> Response {
+    if let Some(response) = state.begin(Route::GetKey) {
+        return response;
+    }
+
+    let material = state.keys.lock().unwrap().get(&uuid).cloned();
+    match material {
+        Some(key) => Json(Key { uuid, key, algorithm: None }).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn delete_key(State(state): State<Arc<MockState>>, Path(uuid): Path<String>) -> Response {
+    if let Some(response) = state.begin(Route::DeleteKey) {
+        return response;
+    }
+
+    match state.keys.lock().unwrap().remove(&uuid) {
+        Some(_) => StatusCode::NO_CONTENT.into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::KeysClient;
+    use crate::error::ClientError;
+
+    #[test]
+    fn test_create_get_delete_roundtrip() {
+        let server = MockKeysServer::start();
+        let client = KeysClient::new(server.url()).unwrap();
+
+        let key = client.create_key().unwrap();
+        assert!(server.contains_key(&key.uuid));
+        assert_eq!(client.get_key(&key.uuid).unwrap(), key);
+
+        client.delete_key(&key.uuid).unwrap();
+        assert!(!server.contains_key(&key.uuid));
+        assert!(matches!(client.get_key(&key.uuid), Err(ClientError::KeyNotFound(_))));
+        assert_eq!(server.request_count(), 4);
+    }
+
+    #[test]
+    fn test_inserted_keys_are_served() {
+        let server = MockKeysServer::start();
+        server.insert_key(Key {
+            uuid: "seeded".to_string(),
+            key: "ab".repeat(32),
+            algorithm: None,
+        });
+
+        let key = KeysClient::new(server.url()).unwrap().get_key("seeded").unwrap();
+        assert_eq!(key.key, "ab".repeat(32));
+    }
+
+    #[test]
+    fn test_configured_responses() {
+        let server = MockKeysServer::start();
+        let client = KeysClient::new(server.url()).unwrap();
+
+        server.respond_with(Route::CreateKey, 503, "maintenance");
+        assert!(matches!(client.create_key(), Err(ClientError::UnexpectedStatus(503))));
+
+        server.clear_response(Route::CreateKey);
+        assert!(client.create_key().is_ok());
+    }
+
+    #[test]
+    fn test_serves_async_client() {
+        let server = MockKeysServer::start();
+        let client = crate::AsyncKeysClient::new(server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let key = client.create_key().await.unwrap();
+            assert_eq!(client.get_key(&key.uuid).await.unwrap(), key);
+        });
+    }
+}
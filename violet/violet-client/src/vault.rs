@@ -0,0 +1,334 @@
+use crate::error::{ClientError, Result};
+use crate::models::Key;
+use crate::provider::KeyProvider;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+use violet_core::{DekWrapper, VioletError};
+
+/// Default mount path of the transit secrets engine
+pub const DEFAULT_TRANSIT_MOUNT: &str = "transit";
+
+/// Key provider backed by HashiCorp Vault's transit secrets engine
+///
+/// Transit keys never leave Vault: `create_key` creates a named transit key
+/// and returns its name as the key_id, and DEKs are wrapped and unwrapped with
+/// the transit encrypt/decrypt endpoints via [`KeyProvider::dek_wrapper`].
+/// Keys returned by this provider therefore carry no key material.
+pub struct VaultKeyProvider {
+    address: Url,
+    token: String,
+    namespace: Option<String>,
+    mount: String,
+    client: Client,
+}
+
+#[derive(Deserialize)]
+struct VaultResponse<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct EncryptData {
+    ciphertext: String,
+}
+
+#[derive(Deserialize)]
+struct DecryptData {
+    plaintext: String,
+}
+
+impl VaultKeyProvider {
+    /// Create a provider for the Vault server at `address` using token auth
+    pub fn new(address: impl AsRef<str>, token: impl Into<String>) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(crate::client::DEFAULT_TIMEOUT)
+            .build()?;
+
+        Ok(Self {
+            address: Url::parse(address.as_ref())?,
+            token: token.into(),
+            namespace: None,
+            mount: DEFAULT_TRANSIT_MOUNT.to_string(),
+            client,
+        })
+    }
+
+    /// Send requests to a Vault Enterprise namespace
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Use a transit engine mounted somewhere other than `transit/`
+    pub fn with_mount(mut self, mount: impl Into<String>) -> Self {
+        self.mount = mount.into().trim_matches('/').to_string();
+        self
+    }
+
+    fn url(&self, action: &str, key_name: &str) -> Result<Url> {
+        Ok(self
+            .address
+            .join(&format!("/v1/{}/{}/{}", self.mount, action, key_name))?)
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = request.header("X-Vault-Token", &self.token);
+        match &self.namespace {
+            Some(namespace) => request.header("X-Vault-Namespace", namespace),
+            None => request,
+        }
+    }
+
+    /// Encrypt a DEK with the named transit key, returning Vault's ciphertext string
+    pub fn transit_encrypt(&self, key_name: &str, dek: &[u8]) -> Result<String> {
+        let url = self.url("encrypt", key_name)?;
+        let response = self
+            .authorized(self.client.post(url))
+            .json(&json!({ "plaintext": BASE64.encode(dek) }))
+            .send()?;
+
+        match response.status() {
+            StatusCode::OK => {
+                let body: VaultResponse<EncryptData> = response.json()?;
+                Ok(body.data.ciphertext)
+            }
+            status => Err(map_vault_status(status, key_name)),
+        }
+    }
+
+    /// Decrypt a transit ciphertext string with the named key, returning the DEK
+    pub fn transit_decrypt(&self, key_name: &str, ciphertext: &str) -> Result<Vec<u8>> {
+        let url = self.url("decrypt", key_name)?;
+        let response = self
+            .authorized(self.client.post(url))
+            .json(&json!({ "ciphertext": ciphertext }))
+            .send()?;
+
+        match response.status() {
+            StatusCode::OK => {
+                let body: VaultResponse<DecryptData> = response.json()?;
+                BASE64
+                    .decode(body.data.plaintext)
+                    .map_err(|_| ClientError::InvalidKeyFormat)
+            }
+            status => Err(map_vault_status(status, key_name)),
+        }
+    }
+}
+
+impl KeyProvider for VaultKeyProvider {
+    fn create_key(&self) -> Result<Key> {
+        let key_name = format!("violet-{}", uuid::Uuid::new_v4());
+        let url = self.url("keys", &key_name)?;
+
+        let response = self
+            .authorized(self.client.post(url))
+            .json(&json!({ "type": "aes256-gcm96" }))
+            .send()?;
+
+        match response.status() {
+            StatusCode::OK | StatusCode::NO_CONTENT => {
+                tracing::info!("Created Vault transit key: {}", key_name);
+                Ok(Key {
+                    uuid: key_name,
+                    key: String::new(),
+                    algorithm: None,
+                })
+            }
+            status => Err(map_vault_status(status, &key_name)),
+        }
+    }
+
+    fn get_key(&self, key_id: &str) -> Result<Key> {
+        let url = self.url("keys", key_id)?;
+        let response = self.authorized(self.client.get(url)).send()?;
+
+        match response.status() {
+            StatusCode::OK => Ok(Key {
+                uuid: key_id.to_string(),
+                key: String::new(),
+                algorithm: None,
+            }),
+            status => Err(map_vault_status(status, key_id)),
+        }
+    }
+
+    fn dek_wrapper<'a>(&'a self, key: &'a Key) -> Result<Box<dyn DekWrapper + 'a>> {
+        Ok(Box::new(TransitWrapper {
+            provider: self,
+            key_name: &key.uuid,
+        }))
+    }
+}
+
+/// Wraps DEKs with a Vault transit key; the envelope stores Vault's ciphertext string
+struct TransitWrapper<'a> {
+    provider: &'a VaultKeyProvider,
+    key_name: &'a str,
+}
+
+impl DekWrapper for TransitWrapper<'_> {
+    fn wrap_dek(&self, dek: &[u8]) -> violet_core::Result<Vec<u8>> {
+        self.provider
+            .transit_encrypt(self.key_name, dek)
+            .map(String::into_bytes)
+            .map_err(|e| VioletError::KeyWrapError(e.to_string()))
+    }
+
+    fn unwrap_dek(&self, wrapped_dek: &[u8]) -> violet_core::Result<Vec<u8>> {
+        let ciphertext = std::str::from_utf8(wrapped_dek)
+            .map_err(|_| VioletError::KeyWrapError("Invalid Vault ciphertext".into()))?;
+        self.provider
+            .transit_decrypt(self.key_name, ciphertext)
+            .map_err(|e| VioletError::KeyWrapError(e.to_string()))
+    }
+}
+
+fn map_vault_status(status: StatusCode, key_name: &str) -> ClientError {
+    match status {
+        StatusCode::NOT_FOUND => ClientError::KeyNotFound(key_name.to_string()),
+        StatusCode::FORBIDDEN | StatusCode::UNAUTHORIZED => {
+            ClientError::AccessDenied(format!("Vault denied access to {}", key_name))
+        }
+        StatusCode::TOO_MANY_REQUESTS => ClientError::Throttled(format!("Vault rate limited {}", key_name)),
+        status => ClientError::UnexpectedStatus(status.as_u16()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+    use violet_core::{Algorithm, EnvelopeEncryptor};
+
+    /// Mock transit encrypt/decrypt that "wraps" by prefixing the base64 plaintext
+    fn mock_transit(server: &mut mockito::Server, key_name: &str) {
+        server
+            .mock("POST", format!("/v1/transit/encrypt/{}", key_name).as_str())
+            .match_header("x-vault-token", "test-token")
+            .with_status(200)
+            .with_body_from_request(|request| {
+                let body: serde_json::Value = serde_json::from_slice(request.body().unwrap()).unwrap();
+                let plaintext = body["plaintext"].as_str().unwrap();
+                json!({ "data": { "ciphertext": format!("vault:v1:{}", plaintext) } })
+                    .to_string()
+                    .into()
+            })
+            .create();
+        server
+            .mock("POST", format!("/v1/transit/decrypt/{}", key_name).as_str())
+            .match_header("x-vault-token", "test-token")
+            .with_status(200)
+            .with_body_from_request(|request| {
+                let body: serde_json::Value = serde_json::from_slice(request.body().unwrap()).unwrap();
+                let ciphertext = body["ciphertext"].as_str().unwrap();
+                let plaintext = ciphertext.strip_prefix("vault:v1:").unwrap();
+                json!({ "data": { "plaintext": plaintext } }).to_string().into()
+            })
+            .create();
+    }
+
+    #[test]
+    fn test_create_key_sends_token_and_namespace() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("POST", Matcher::Regex(r"^/v1/transit/keys/violet-[0-9a-f-]+$".to_string()))
+            .match_header("x-vault-token", "test-token")
+            .match_header("x-vault-namespace", "team-a")
+            .match_body(Matcher::Json(json!({ "type": "aes256-gcm96" })))
+            .with_status(204)
+            .create();
+
+        let provider = VaultKeyProvider::new(server.url(), "test-token")
+            .unwrap()
+            .with_namespace("team-a");
+        let key = provider.create_key().unwrap();
+
+        assert!(key.uuid.starts_with("violet-"));
+        assert!(key.key.is_empty());
+        mock.assert();
+    }
+
+    #[test]
+    fn test_get_key_custom_mount_and_errors() {
+        let mut server = mockito::Server::new();
+        server.mock("GET", "/v1/kek/keys/present").with_status(200).with_body("{}").create();
+        server.mock("GET", "/v1/kek/keys/missing").with_status(404).create();
+        server.mock("GET", "/v1/kek/keys/forbidden").with_status(403).create();
+
+        let provider = VaultKeyProvider::new(server.url(), "test-token")
+            .unwrap()
+            .with_mount("/kek/");
+
+        assert_eq!(provider.get_key("present").unwrap().uuid, "present");
+        assert!(matches!(provider.get_key("missing"), Err(ClientError::KeyNotFound(_))));
+        assert!(matches!(provider.get_key("forbidden"), Err(ClientError::AccessDenied(_))));
+    }
+
+    #[test]
+    fn test_envelope_roundtrip_via_transit() {
+        let mut server = mockito::Server::new();
+        mock_transit(&mut server, "orders");
+
+        let provider = VaultKeyProvider::new(server.url(), "test-token").unwrap();
+        let key = Key {
+            uuid: "orders".to_string(),
+            key: String::new(),
+            algorithm: None,
+        };
+        let wrapper = provider.dek_wrapper(&key).unwrap();
+
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let envelope = encryptor
+            .encrypt_with_wrapper(b"wrapped by vault", wrapper.as_ref(), key.uuid.clone())
+            .unwrap();
+
+        let stored = BASE64.decode(&envelope.encrypted_key).unwrap();
+        assert!(stored.starts_with(b"vault:v1:"));
+
+        let decrypted = encryptor.decrypt_with_wrapper(&envelope, wrapper.as_ref()).unwrap();
+        assert_eq!(decrypted, b"wrapped by vault");
+    }
+
+    #[test]
+    fn test_wrap_failure_is_key_wrap_error() {
+        let mut server = mockito::Server::new();
+        server.mock("POST", "/v1/transit/encrypt/orders").with_status(429).create();
+
+        let provider = VaultKeyProvider::new(server.url(), "test-token").unwrap();
+        let key = Key {
+            uuid: "orders".to_string(),
+            key: String::new(),
+            algorithm: None,
+        };
+        let wrapper = provider.dek_wrapper(&key).unwrap();
+
+        let result = wrapper.wrap_dek(&[0u8; 32]);
+        assert!(matches!(result, Err(VioletError::KeyWrapError(_))));
+    }
+
+    // Integration test against a Vault dev server with transit enabled:
+    //   vault server -dev && vault secrets enable transit
+    #[test]
+    #[ignore]
+    fn test_vault_dev_server_roundtrip() {
+        let address = std::env::var("VAULT_ADDR").expect("VAULT_ADDR must be set");
+        let token = std::env::var("VAULT_TOKEN").expect("VAULT_TOKEN must be set");
+        let provider = VaultKeyProvider::new(address, token).unwrap();
+
+        let key = provider.create_key().unwrap();
+        let key = provider.get_key(&key.uuid).unwrap();
+        let wrapper = provider.dek_wrapper(&key).unwrap();
+
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv);
+        let envelope = encryptor
+            .encrypt_with_wrapper(b"vault integration", wrapper.as_ref(), key.uuid.clone())
+            .unwrap();
+        let decrypted = encryptor.decrypt_with_wrapper(&envelope, wrapper.as_ref()).unwrap();
+        assert_eq!(decrypted, b"vault integration");
+    }
+}
@@ -0,0 +1,79 @@
+//! Crypto throughput: `cargo bench -p violet-core --features bench`
+//!
+//! Each group runs at every size in [`PLAINTEXT_SIZES`] and reports bytes per
+//! second of plaintext, so runs can be compared across commits.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+use violet_core::bench::{self, PLAINTEXT_SIZES};
+use violet_core::Algorithm;
+
+fn aead(c: &mut Criterion) {
+    let mut group = c.benchmark_group("aes_gcm");
+    for &len in PLAINTEXT_SIZES {
+        let data = bench::plaintext(len);
+        let sealed = bench::aes_gcm_seal(&data).unwrap();
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::new("encrypt", len), &data, |b, data| {
+            b.iter(|| bench::aes_gcm_seal(black_box(data)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("decrypt", len), &sealed, |b, sealed| {
+            b.iter(|| bench::aes_gcm_open(black_box(sealed)).unwrap())
+        });
+    }
+    group.finish();
+
+    #[cfg(feature = "aes-gcm-siv")]
+    {
+        let mut group = c.benchmark_group("aes_gcm_siv");
+        for &len in PLAINTEXT_SIZES {
+            let data = bench::plaintext(len);
+            let sealed = bench::aes_gcm_siv_seal(&data).unwrap();
+            group.throughput(Throughput::Bytes(len as u64));
+            group.bench_with_input(BenchmarkId::new("encrypt", len), &data, |b, data| {
+                b.iter(|| bench::aes_gcm_siv_seal(black_box(data)).unwrap())
+            });
+            group.bench_with_input(BenchmarkId::new("decrypt", len), &sealed, |b, sealed| {
+                b.iter(|| bench::aes_gcm_siv_open(black_box(sealed)).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+fn envelope(c: &mut Criterion) {
+    for algorithm in Algorithm::all() {
+        let mut group = c.benchmark_group(format!("envelope/{}", algorithm.as_str()));
+        for &len in PLAINTEXT_SIZES {
+            let data = bench::plaintext(len);
+            let envelope = bench::envelope_seal(*algorithm, &data).unwrap();
+            group.throughput(Throughput::Bytes(len as u64));
+            group.bench_with_input(BenchmarkId::new("encrypt", len), &data, |b, data| {
+                b.iter(|| bench::envelope_seal(*algorithm, black_box(data)).unwrap())
+            });
+            group.bench_with_input(BenchmarkId::new("decrypt", len), &envelope, |b, envelope| {
+                b.iter(|| bench::envelope_open(black_box(envelope)).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+fn base64(c: &mut Criterion) {
+    let mut group = c.benchmark_group("base64");
+    for &len in PLAINTEXT_SIZES {
+        let data = bench::plaintext(len);
+        let encoded = bench::base64_encode(&data);
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::new("encode", len), &data, |b, data| {
+            b.iter(|| bench::base64_encode(black_box(data)))
+        });
+        group.bench_with_input(BenchmarkId::new("decode", len), &encoded, |b, encoded| {
+            b.iter(|| bench::base64_decode(black_box(encoded)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, aead, envelope, base64);
+criterion_main!(benches);
@@ -0,0 +1,108 @@
+//! Fixtures and operations measured by the criterion benchmarks in `benches/`
+//!
+//! Available to this crate's tests and, with the `bench` feature, to the
+//! benchmarks. Each operation returns what it produced so a benchmark cannot
+//! be optimised away, and the tests below check that the operations round
+//! trip, so the benchmarks never time code that is broken.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use crate::crypto::{aes_gcm, envelope::decode_base64_chunked};
+use crate::{Algorithm, EncryptionEnvelope, EnvelopeEncryptor, Result};
+
+/// Plaintext sizes the benchmarks run at, from a small record to a large file
+pub const PLAINTEXT_SIZES: &[usize] = &[64, 1024, 16 * 1024, 256 * 1024, 1024 * 1024];
+
+/// KEK and DEK used throughout; the benchmarks measure throughput, not secrecy
+pub const KEY: [u8; 32] = [0x42; 32];
+
+/// Key ID written into benchmark envelopes
+pub const KEY_ID: &str = "bench-key";
+
+/// Output of a raw AEAD encryption: (ciphertext, nonce, tag)
+pub type Sealed = (Vec<u8>, Vec<u8>, Vec<u8>);
+
+/// `len` bytes of deterministic, non-repeating-looking plaintext
+pub fn plaintext(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i.wrapping_mul(31) ^ (i >> 8)) as u8).collect()
+}
+
+pub fn aes_gcm_seal(plaintext: &[u8]) -> Result<Sealed> {
+    aes_gcm::encrypt(plaintext, &KEY)
+}
+
+pub fn aes_gcm_open((ciphertext, nonce, tag): &Sealed) -> Result<Vec<u8>> {
+    aes_gcm::decrypt(ciphertext, &KEY, nonce, tag)
+}
+
+#[cfg(feature = "aes-gcm-siv")]
+pub fn aes_gcm_siv_seal(plaintext: &[u8]) -> Result<Sealed> {
+    crate::crypto::aes_gcm_siv::encrypt(plaintext, &KEY)
+}
+
+#[cfg(feature = "aes-gcm-siv")]
+pub fn aes_gcm_siv_open((ciphertext, nonce, tag): &Sealed) -> Result<Vec<u8>> {
+    crate::crypto::aes_gcm_siv::decrypt(ciphertext, &KEY, nonce, tag)
+}
+
+/// Full envelope encryption: fresh DEK, data encryption, DEK wrapping and base64
+pub fn envelope_seal(algorithm: Algorithm, plaintext: &[u8]) -> Result<EncryptionEnvelope> {
+    EnvelopeEncryptor::new(algorithm).encrypt(plaintext, &KEY, KEY_ID.into())
+}
+
+pub fn envelope_open(envelope: &EncryptionEnvelope) -> Result<Vec<u8>> {
+    EnvelopeEncryptor::new(Algorithm::default()).decrypt(envelope, &KEY)
+}
+
+pub fn base64_encode(data: &[u8]) -> String {
+    BASE64.encode(data)
+}
+
+/// Decoded the way envelope fields are, in chunks straight into the output
+pub fn base64_decode(encoded: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    decode_base64_chunked(encoded, &mut out, 0)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_operations_round_trip() {
+        for &len in PLAINTEXT_SIZES {
+            let data = plaintext(len);
+            assert_eq!(data.len(), len);
+
+            let sealed = aes_gcm_seal(&data).unwrap();
+            assert_ne!(sealed.0, data, "aes-gcm at {} bytes", len);
+            assert_eq!(aes_gcm_open(&sealed).unwrap(), data, "aes-gcm at {} bytes", len);
+
+            #[cfg(feature = "aes-gcm-siv")]
+            {
+                let sealed = aes_gcm_siv_seal(&data).unwrap();
+                assert_eq!(aes_gcm_siv_open(&sealed).unwrap(), data, "aes-gcm-siv at {} bytes", len);
+            }
+
+            for algorithm in Algorithm::all() {
+                let envelope = envelope_seal(*algorithm, &data).unwrap();
+                assert_eq!(envelope.algorithm, algorithm.as_str());
+                assert_eq!(envelope_open(&envelope).unwrap(), data, "{} envelope at {} bytes", algorithm.as_str(), len);
+            }
+
+            let encoded = base64_encode(&data);
+            assert_eq!(base64_decode(&encoded).unwrap(), data, "base64 at {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn test_open_rejects_tampering() {
+        let mut sealed = aes_gcm_seal(&plaintext(64)).unwrap();
+        sealed.0[0] ^= 1;
+        assert!(aes_gcm_open(&sealed).is_err());
+
+        let mut envelope = envelope_seal(Algorithm::default(), &plaintext(64)).unwrap();
+        envelope.auth_tag = base64_encode(&[0u8; 16]);
+        assert!(envelope_open(&envelope).is_err());
+    }
+}
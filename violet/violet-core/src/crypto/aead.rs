@@ -0,0 +1,122 @@
+use crate::crypto::types::Algorithm;
+use crate::error::{Result, VioletError};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+
+// Dispatch from an `Algorithm` to its implementation. Algorithms whose cargo
+// feature is off fail here with `UnsupportedAlgorithm`, so callers never have
+// to match on the features themselves.
+
+fn unsupported(algorithm: Algorithm) -> VioletError {
+    VioletError::UnsupportedAlgorithm(algorithm.as_str().to_string())
+}
+
+/// Encrypt data with `algorithm`
+///
+/// Returns: (ciphertext, nonce, tag)
+pub fn encrypt(algorithm: Algorithm, plaintext: &[u8], key: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    match algorithm {
+        #[cfg(feature = "aes-gcm")]
+        Algorithm::Aes256Gcm => super::aes_gcm::encrypt(plaintext, key),
+        #[cfg(feature = "aes-gcm-siv")]
+        Algorithm::Aes256GcmSiv => super::aes_gcm_siv::encrypt(plaintext, key),
+        #[allow(unreachable_patterns)]
+        _ => Err(unsupported(algorithm)),
+    }
+}
+
+/// Decrypt data with `algorithm` and a separate tag
+pub fn decrypt(algorithm: Algorithm, ciphertext: &[u8], key: &[u8], nonce: &[u8], tag: &[u8]) -> Result<Vec<u8>> {
+    match algorithm {
+        #[cfg(feature = "aes-gcm")]
+        Algorithm::Aes256Gcm => super::aes_gcm::decrypt(ciphertext, key, nonce, tag),
+        #[cfg(feature = "aes-gcm-siv")]
+        Algorithm::Aes256GcmSiv => super::aes_gcm_siv::decrypt(ciphertext, key, nonce, tag),
+        #[allow(unreachable_patterns)]
+        _ => Err(unsupported(algorithm)),
+    }
+}
+
+/// Decrypt `ciphertext || tag` with `algorithm`, leaving the plaintext in `buffer`
+pub fn decrypt_in_place(algorithm: Algorithm, buffer: &mut Vec<u8>, key: &[u8], nonce: &[u8]) -> Result<()> {
+    match algorithm {
+        #[cfg(feature = "aes-gcm")]
+        Algorithm::Aes256Gcm => super::aes_gcm::decrypt_in_place(buffer, key, nonce),
+        #[cfg(feature = "aes-gcm-siv")]
+        Algorithm::Aes256GcmSiv => super::aes_gcm_siv::decrypt_in_place(buffer, key, nonce),
+        #[allow(unreachable_patterns)]
+        _ => Err(unsupported(algorithm)),
+    }
+}
+
+/// Encrypt with a caller-chosen nonce and associated data; returns `ciphertext || tag`
+pub fn seal(algorithm: Algorithm, key: &[u8], nonce: &[u8; 12], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let payload = Payload { msg: plaintext, aad };
+    let sealed = match algorithm {
+        #[cfg(feature = "aes-gcm")]
+        Algorithm::Aes256Gcm => aes_gcm::Aes256Gcm::new_from_slice(key)
+            .map_err(|_| VioletError::CryptoError("Invalid key".into()))?
+            .encrypt(aes_gcm::Nonce::from_slice(nonce), payload),
+        #[cfg(feature = "aes-gcm-siv")]
+        Algorithm::Aes256GcmSiv => aes_gcm_siv::Aes256GcmSiv::new_from_slice(key)
+            .map_err(|_| VioletError::CryptoError("Invalid key".into()))?
+            .encrypt(aes_gcm_siv::Nonce::from_slice(nonce), payload),
+        #[allow(unreachable_patterns)]
+        _ => return Err(unsupported(algorithm)),
+    };
+    sealed.map_err(|e| VioletError::EncryptionFailed(e.to_string()))
+}
+
+/// Decrypt `ciphertext || tag` sealed by [`seal`]
+pub fn open(algorithm: Algorithm, key: &[u8], nonce: &[u8; 12], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let payload = Payload { msg: ciphertext, aad };
+    let opened = match algorithm {
+        #[cfg(feature = "aes-gcm")]
+        Algorithm::Aes256Gcm => aes_gcm::Aes256Gcm::new_from_slice(key)
+            .map_err(|_| VioletError::CryptoError("Invalid key".into()))?
+            .decrypt(aes_gcm::Nonce::from_slice(nonce), payload),
+        #[cfg(feature = "aes-gcm-siv")]
+        Algorithm::Aes256GcmSiv => aes_gcm_siv::Aes256GcmSiv::new_from_slice(key)
+            .map_err(|_| VioletError::CryptoError("Invalid key".into()))?
+            .decrypt(aes_gcm_siv::Nonce::from_slice(nonce), payload),
+        #[allow(unreachable_patterns)]
+        _ => return Err(unsupported(algorithm)),
+    };
+    opened.map_err(|e| VioletError::DecryptionFailed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enabled_algorithms_round_trip() {
+        let key = [7u8; 32];
+        for algorithm in Algorithm::all() {
+            let (ciphertext, nonce, tag) = encrypt(*algorithm, b"hello", &key).unwrap();
+            assert_eq!(decrypt(*algorithm, &ciphertext, &key, &nonce, &tag).unwrap(), b"hello");
+
+            let sealed = seal(*algorithm, &key, &[1u8; 12], b"hello", b"aad").unwrap();
+            assert_eq!(open(*algorithm, &key, &[1u8; 12], &sealed, b"aad").unwrap(), b"hello");
+            assert!(open(*algorithm, &key, &[1u8; 12], &sealed, b"other").is_err());
+        }
+    }
+
+    #[cfg(not(feature = "aes-gcm-siv"))]
+    #[test]
+    fn test_gcm_siv_unsupported_without_feature() {
+        let err = encrypt(Algorithm::Aes256GcmSiv, b"hello", &[7u8; 32]).unwrap_err();
+        assert!(matches!(err, VioletError::UnsupportedAlgorithm(ref name) if name == "AES-256-GCM-SIV"), "{}", err);
+        let err = seal(Algorithm::Aes256GcmSiv, &[7u8; 32], &[1u8; 12], b"hello", b"").unwrap_err();
+        assert!(matches!(err, VioletError::UnsupportedAlgorithm(_)), "{}", err);
+    }
+
+    #[cfg(not(feature = "aes-gcm"))]
+    #[test]
+    fn test_gcm_unsupported_without_feature() {
+        let err = encrypt(Algorithm::Aes256Gcm, b"hello", &[7u8; 32]).unwrap_err();
+        assert!(matches!(err, VioletError::UnsupportedAlgorithm(ref name) if name == "AES-256-GCM"), "{}", err);
+        let mut buffer = vec![0u8; 32];
+        let err = decrypt_in_place(Algorithm::Aes256Gcm, &mut buffer, &[7u8; 32], &[0u8; 12]).unwrap_err();
+        assert!(matches!(err, VioletError::UnsupportedAlgorithm(_)), "{}", err);
+    }
+}
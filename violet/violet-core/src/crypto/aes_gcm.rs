@@ -1,5 +1,5 @@
 use aes_gcm::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, AeadInPlace, KeyInit, Payload},
     Aes256Gcm, Nonce,
 };
 use crate::crypto::types::{GCM_NONCE_SIZE, GCM_TAG_SIZE};
@@ -20,23 +20,37 @@ pub fn encrypt(plaintext: &[u8], key: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8
     // Generate random nonce
     let mut nonce_bytes = vec![0u8; GCM_NONCE_SIZE];
     rand::thread_rng().fill_bytes(&mut nonce_bytes);
-    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let (ciphertext, tag) = encrypt_with_nonce(plaintext, b"", key, &nonce_bytes)?;
+    Ok((ciphertext, nonce_bytes, tag))
+}
+
+/// Encrypt data and associated data with AES-256-GCM under a fixed nonce
+///
+/// Returns: (ciphertext, tag)
+///
+/// Reusing a nonce under one key breaks GCM entirely, so outside of
+/// [`encrypt`] this is only for known-answer tests.
+pub(crate) fn encrypt_with_nonce(plaintext: &[u8], aad: &[u8], key: &[u8], nonce: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    if key.len() != 32 {
+        return Err(VioletError::InvalidKeySize(key.len()));
+    }
+    if nonce.len() != GCM_NONCE_SIZE {
+        return Err(VioletError::InvalidNonceSize(nonce.len()));
+    }
 
     // Create cipher
     let cipher = Aes256Gcm::new_from_slice(key)
         .map_err(|_| VioletError::CryptoError("Invalid key".into()))?;
 
     // Encrypt
-    let ciphertext_with_tag = cipher
-        .encrypt(nonce, plaintext)
+    let mut ciphertext = cipher
+        .encrypt(Nonce::from_slice(nonce), Payload { msg: plaintext, aad })
         .map_err(|e| VioletError::EncryptionFailed(e.to_string()))?;
 
     // Split ciphertext and tag
-    let tag_start = ciphertext_with_tag.len() - GCM_TAG_SIZE;
-    let ciphertext = ciphertext_with_tag[..tag_start].to_vec();
-    let tag = ciphertext_with_tag[tag_start..].to_vec();
-
-    Ok((ciphertext, nonce_bytes, tag))
+    let tag = ciphertext.split_off(ciphertext.len() - GCM_TAG_SIZE);
+    Ok((ciphertext, tag))
 }
 
 /// Decrypt data with AES-256-GCM
@@ -57,19 +71,36 @@ pub fn decrypt(
     }
 
     // Reconstruct ciphertext with tag
-    let mut ciphertext_with_tag = Vec::with_capacity(ciphertext.len() + tag.len());
-    ciphertext_with_tag.extend_from_slice(ciphertext);
-    ciphertext_with_tag.extend_from_slice(tag);
+    let mut buffer = Vec::with_capacity(ciphertext.len() + tag.len());
+    buffer.extend_from_slice(ciphertext);
+    buffer.extend_from_slice(tag);
+
+    decrypt_in_place(&mut buffer, key, nonce)?;
+    Ok(buffer)
+}
+
+/// Decrypt `ciphertext || tag` with AES-256-GCM in place, leaving the plaintext in `buffer`
+///
+/// Unlike [`decrypt`], no second buffer the size of the data is allocated.
+/// `buffer` is left unspecified if decryption fails.
+pub fn decrypt_in_place(buffer: &mut Vec<u8>, key: &[u8], nonce: &[u8]) -> Result<()> {
+    if key.len() != 32 {
+        return Err(VioletError::InvalidKeySize(key.len()));
+    }
+    if nonce.len() != GCM_NONCE_SIZE {
+        return Err(VioletError::InvalidNonceSize(nonce.len()));
+    }
+    if buffer.len() < GCM_TAG_SIZE {
+        return Err(VioletError::InvalidTagSize(buffer.len()));
+    }
 
     let nonce_obj = Nonce::from_slice(nonce);
     let cipher = Aes256Gcm::new_from_slice(key)
         .map_err(|_| VioletError::CryptoError("Invalid key".into()))?;
 
-    let plaintext = cipher
-        .decrypt(nonce_obj, ciphertext_with_tag.as_ref())
-        .map_err(|e| VioletError::DecryptionFailed(e.to_string()))?;
-
-    Ok(plaintext)
+    cipher
+        .decrypt_in_place(nonce_obj, b"", buffer)
+        .map_err(|e| VioletError::DecryptionFailed(e.to_string()))
 }
 
 #[cfg(test)]
@@ -116,4 +147,98 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    /// An AES-256-GCM vector from NIST CAVP `gcmEncryptExtIV256.rsp`
+    struct CavpVector {
+        key: &'static str,
+        iv: &'static str,
+        pt: &'static str,
+        aad: &'static str,
+        ct: &'static str,
+        tag: &'static str,
+    }
+
+    /// Count 0 of the 96-bit IV, 128-bit tag sections with the given PT and AAD lengths
+    const CAVP_VECTORS: &[CavpVector] = &[
+        // PTlen = 0, AADlen = 0
+        CavpVector {
+            key: "b52c505a37d78eda5dd34f20c22540ea1b58963cf8e5bf8ffa85f9f2492505b4",
+            iv: "516c33929df5a3284ff463d7",
+            pt: "",
+            aad: "",
+            ct: "",
+            tag: "bdc1ac884d332457a1d2664f168c76f0",
+        },
+        // PTlen = 128, AADlen = 0
+        CavpVector {
+            key: "31bdadd96698c204aa9ce1448ea94ae1fb4a9a0b3c9d773b51bb1822666b8f22",
+            iv: "0d18e06c7c725ac9e362e1ce",
+            pt: "2db5168e932556f8089a0622981d017d",
+            aad: "",
+            ct: "fa4362189661d163fcd6a56d8bf0405a",
+            tag: "d636ac1bbedd5cc3ee727dc2ab4a9489",
+        },
+        // PTlen = 0, AADlen = 128
+        CavpVector {
+            key: "78dc4e0aaf52d935c3c01eea57428f00ca1fd475f5da86a49c8dd73d68c8e223",
+            iv: "d79cf22d504cc793c3fb6c8a",
+            pt: "",
+            aad: "b96baa8c1c75a671bfb2d08d06be5f36",
+            ct: "",
+            tag: "3e5d486aa2e30b22e040b85723a06e76",
+        },
+        // PTlen = 128, AADlen = 128
+        CavpVector {
+            key: "92e11dcdaa866f5ce790fd24501f92509aacf4cb8b1339d50c9c1240935dd08b",
+            iv: "ac93a1a6145299bde902f21a",
+            pt: "2d71bcfa914e4ac045b2aa60955fad24",
+            aad: "1e0889016f67601c8ebea4943bc23ad6",
+            ct: "8995ae2e6df3dbf96fac7b7137bae67f",
+            tag: "eca5aa77d51d4a0a14d9c51e1da474ab",
+        },
+    ];
+
+    #[test]
+    fn test_nist_cavp_known_answers() {
+        for (i, vector) in CAVP_VECTORS.iter().enumerate() {
+            let key = hex::decode(vector.key).unwrap();
+            let iv = hex::decode(vector.iv).unwrap();
+            let pt = hex::decode(vector.pt).unwrap();
+            let aad = hex::decode(vector.aad).unwrap();
+
+            let (ct, tag) = encrypt_with_nonce(&pt, &aad, &key, &iv).unwrap();
+            assert_eq!(hex::encode(&ct), vector.ct, "vector {} ciphertext", i);
+            assert_eq!(hex::encode(&tag), vector.tag, "vector {} tag", i);
+
+            if aad.is_empty() {
+                assert_eq!(decrypt(&ct, &key, &iv, &tag).unwrap(), pt, "vector {} plaintext", i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_multi_block_known_answer() {
+        // Test case 15 of McGrew & Viega, "The Galois/Counter Mode of Operation"
+        let key = hex::decode("feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308").unwrap();
+        let iv = hex::decode("cafebabefacedbaddecaf888").unwrap();
+        let pt = hex::decode(
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+             1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255",
+        )
+        .unwrap();
+
+        let (ct, tag) = encrypt_with_nonce(&pt, b"", &key, &iv).unwrap();
+        assert_eq!(
+            hex::encode(&ct),
+            "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+             8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662898015ad"
+        );
+        assert_eq!(hex::encode(&tag), "b094dac5d93471bdec1a502270e3cc6c");
+    }
+
+    #[test]
+    fn test_fixed_nonce_size_checked() {
+        let result = encrypt_with_nonce(b"test", b"", &[0u8; 32], &[0u8; 8]);
+        assert!(matches!(result, Err(VioletError::InvalidNonceSize(8))));
+    }
 }
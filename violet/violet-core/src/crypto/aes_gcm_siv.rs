@@ -1,5 +1,5 @@
 use aes_gcm_siv::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, AeadInPlace, KeyInit},
     Aes256GcmSiv, Nonce,
 };
 use crate::crypto::types::{GCM_SIV_NONCE_SIZE, GCM_TAG_SIZE};
@@ -57,19 +57,36 @@ pub fn decrypt(
     }
 
     // Reconstruct ciphertext with tag
-    let mut ciphertext_with_tag = Vec::with_capacity(ciphertext.len() + tag.len());
-    ciphertext_with_tag.extend_from_slice(ciphertext);
-    ciphertext_with_tag.extend_from_slice(tag);
+    let mut buffer = Vec::with_capacity(ciphertext.len() + tag.len());
+    buffer.extend_from_slice(ciphertext);
+    buffer.extend_from_slice(tag);
+
+    decrypt_in_place(&mut buffer, key, nonce)?;
+    Ok(buffer)
+}
+
+/// Decrypt `ciphertext || tag` with AES-256-GCM-SIV in place, leaving the plaintext in `buffer`
+///
+/// Unlike [`decrypt`], no second buffer the size of the data is allocated.
+/// `buffer` is left unspecified if decryption fails.
+pub fn decrypt_in_place(buffer: &mut Vec<u8>, key: &[u8], nonce: &[u8]) -> Result<()> {
+    if key.len() != 32 {
+        return Err(VioletError::InvalidKeySize(key.len()));
+    }
+    if nonce.len() != GCM_SIV_NONCE_SIZE {
+        return Err(VioletError::InvalidNonceSize(nonce.len()));
+    }
+    if buffer.len() < GCM_TAG_SIZE {
+        return Err(VioletError::InvalidTagSize(buffer.len()));
+    }
 
     let nonce_obj = Nonce::from_slice(nonce);
     let cipher = Aes256GcmSiv::new_from_slice(key)
         .map_err(|_| VioletError::CryptoError("Invalid key".into()))?;
 
-    let plaintext = cipher
-        .decrypt(nonce_obj, ciphertext_with_tag.as_ref())
-        .map_err(|e| VioletError::DecryptionFailed(e.to_string()))?;
-
-    Ok(plaintext)
+    cipher
+        .decrypt_in_place(nonce_obj, b"", buffer)
+        .map_err(|e| VioletError::DecryptionFailed(e.to_string()))
 }
 
 #[cfg(test)]
@@ -1,23 +1,90 @@
-use crate::crypto::{aes_gcm, aes_gcm_siv, types::{Algorithm, DEK_SIZE}};
+use crate::crypto::{aead, fingerprint::kek_fingerprint, types::{Algorithm, DEK_SIZE, GCM_TAG_SIZE}};
+use crate::crypto::fingerprint::FINGERPRINT_SIZE;
+use crate::crypto::kdf::PasswordKdf;
+use crate::crypto::types::{GCM_NONCE_SIZE, GCM_SIV_NONCE_SIZE};
+use crate::crypto::wrapper::{DekWrapper, LocalKekWrapper, WRAPPED_DEK_SIZE};
+#[cfg(feature = "x25519")]
+use crate::crypto::x25519;
 use crate::error::{Result, VioletError};
-use crate::models::encryption_envelope::EncryptionEnvelope;
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use crate::models::encryption_envelope::{EncryptionEnvelope, ENVELOPE_VERSION, LEGACY_ENVELOPE_VERSION};
+use base64::{engine::general_purpose::STANDARD as BASE64, DecodeError, Engine};
 use rand::RngCore;
 
+/// `keyId` recorded in envelopes whose KEK is derived from a password
+pub const PASSWORD_KEY_ID: &str = "password";
+
+/// Longest `keyId` covered by [`EnvelopeEncryptor::estimated_envelope_size`],
+/// the length of a Keys server UUID
+pub const ESTIMATED_KEY_ID_LEN: usize = 36;
+
+/// Base64 characters of `encryptedData` decoded per step; a multiple of 4 so
+/// steps fall on quantum boundaries
+const BASE64_CHUNK_CHARS: usize = 64 * 1024;
+
 /// Envelope encryptor implementing two-layer encryption pattern
 ///
 /// Workflow:
 /// 1. Generate random 256-bit DEK (Data Encryption Key)
 /// 2. Encrypt data with DEK using chosen algorithm (AES-GCM or AES-GCM-SIV)
-/// 3. Encrypt DEK with KEK (Key Encryption Key) from server
+/// 3. Encrypt DEK with a key derived from the KEK (Key Encryption Key), or via a `DekWrapper`
 /// 4. Return EncryptionEnvelope with all components
 pub struct EnvelopeEncryptor {
     algorithm: Algorithm,
+    embed_kek_fingerprint: bool,
 }
 
 impl EnvelopeEncryptor {
     pub fn new(algorithm: Algorithm) -> Self {
-        Self { algorithm }
+        Self {
+            algorithm,
+            embed_kek_fingerprint: false,
+        }
+    }
+
+    /// Record a non-secret fingerprint of the KEK in each envelope produced by `encrypt`
+    ///
+    /// Decryption always verifies the fingerprint when an envelope carries one.
+    pub fn with_kek_fingerprint(mut self, enabled: bool) -> Self {
+        self.embed_kek_fingerprint = enabled;
+        self
+    }
+
+    /// Algorithm new envelopes are sealed with
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Upper bound on the JSON size, in bytes, of the envelope `encrypt` would
+    /// produce for `plaintext_len` bytes of plaintext, without encrypting anything
+    ///
+    /// Counts the base64 ciphertext, the fixed IV, tag and wrapped-DEK fields,
+    /// the fingerprint when enabled, and the field names and punctuation of
+    /// compact JSON (`serde_json::to_string`). It assumes a `keyId` of at most
+    /// [`ESTIMATED_KEY_ID_LEN`] characters that needs no escaping; add the
+    /// difference for longer ids. Pretty-printed JSON, password envelopes
+    /// (`kdf`) and wrappers other than the local KEK are not covered.
+    pub fn estimated_envelope_size(&self, plaintext_len: usize) -> usize {
+        let iv_len = match self.algorithm {
+            Algorithm::Aes256Gcm => GCM_NONCE_SIZE,
+            Algorithm::Aes256GcmSiv => GCM_SIV_NONCE_SIZE,
+        };
+        // Every field but the ciphertext, which is the same length as the plaintext
+        let template = EncryptionEnvelope {
+            version: ENVELOPE_VERSION,
+            key_id: "0".repeat(ESTIMATED_KEY_ID_LEN),
+            encrypted_data: String::new(),
+            encrypted_key: BASE64.encode([0u8; WRAPPED_DEK_SIZE]),
+            iv: BASE64.encode(vec![0u8; iv_len]),
+            algorithm: self.algorithm.as_str().to_string(),
+            auth_tag: BASE64.encode([0u8; GCM_TAG_SIZE]),
+            kek_fingerprint: self.embed_kek_fingerprint.then(|| "0".repeat(FINGERPRINT_SIZE * 2)),
+            kdf: None,
+            ephemeral_public_key: None,
+        };
+        let fixed = serde_json::to_string(&template).expect("envelope serializes").len();
+
+        // Padded base64: 4 characters per started 3 bytes
+        plaintext_len.div_ceil(3).saturating_mul(4).saturating_add(fixed)
     }
 
     /// Encrypt plaintext using envelope encryption
@@ -28,46 +95,55 @@ impl EnvelopeEncryptor {
     /// * `key_id` - UUID of the KEK for later retrieval
     ///
     /// # Returns
-    /// EncryptionEnvelope with base64-encoded components
+    /// EncryptionEnvelope with base64-encoded components. The DEK is wrapped
+    /// under a key derived from `kek` (envelope version 2), not `kek` itself.
     pub fn encrypt(
         &self,
         plaintext: &[u8],
         kek: &[u8],
         key_id: String,
     ) -> Result<EncryptionEnvelope> {
-        if kek.len() != DEK_SIZE {
-            return Err(VioletError::InvalidKeySize(kek.len()));
+        let wrapper = LocalKekWrapper::derived(kek)?;
+        let mut envelope = self.encrypt_with_wrapper(plaintext, &wrapper, key_id)?;
+        if self.embed_kek_fingerprint {
+            envelope.kek_fingerprint = Some(kek_fingerprint(kek));
         }
+        Ok(envelope)
+    }
 
+    /// Encrypt plaintext, delegating DEK wrapping to `wrapper`
+    ///
+    /// Use this with backends that wrap DEKs remotely rather than exporting a raw KEK.
+    pub fn encrypt_with_wrapper(
+        &self,
+        plaintext: &[u8],
+        wrapper: &dyn DekWrapper,
+        key_id: String,
+    ) -> Result<EncryptionEnvelope> {
         // TODO: Use the RNG provided by the Aes256Gcm create instead.
         // Step 1: Generate random DEK
         let mut dek = vec![0u8; DEK_SIZE];
         rand::thread_rng().fill_bytes(&mut dek);
 
         // Step 2: Encrypt plaintext with DEK
-        let (ciphertext, data_iv, data_tag) = match self.algorithm {
-            Algorithm::Aes256Gcm => aes_gcm::encrypt(plaintext, &dek)?,
-            Algorithm::Aes256GcmSiv => aes_gcm_siv::encrypt(plaintext, &dek)?,
-        };
+        let (ciphertext, data_iv, data_tag) = aead::encrypt(self.algorithm, plaintext, &dek)?;
 
-        // Step 3: Encrypt DEK with KEK (always use AES-GCM for DEK encryption)
-        let (encrypted_dek, dek_iv, dek_tag) = aes_gcm::encrypt(&dek, kek)?;
-
-        // Store DEK encryption components concatenated: nonce || ciphertext || tag
-        // This allows us to decrypt the DEK later without additional storage
-        let mut dek_package = Vec::with_capacity(dek_iv.len() + encrypted_dek.len() + dek_tag.len());
-        dek_package.extend_from_slice(&dek_iv);
-        dek_package.extend_from_slice(&encrypted_dek);
-        dek_package.extend_from_slice(&dek_tag);
+        // Step 3: Wrap DEK with KEK. The wrapped form must carry everything
+        // needed to unwrap it (for local wrapping: nonce || ciphertext || tag)
+        let dek_package = wrapper.wrap_dek(&dek)?;
 
         // Step 4: Build envelope
         Ok(EncryptionEnvelope {
+            version: ENVELOPE_VERSION,
             key_id,
             encrypted_data: BASE64.encode(&ciphertext),
             encrypted_key: BASE64.encode(&dek_package),
             iv: BASE64.encode(&data_iv),
             algorithm: self.algorithm.as_str().to_string(),
             auth_tag: BASE64.encode(&data_tag),
+            kek_fingerprint: None,
+            kdf: None,
+            ephemeral_public_key: None,
         })
     }
 
@@ -79,84 +155,359 @@ impl EnvelopeEncryptor {
     ///
     /// # Returns
     /// Decrypted plaintext
+    ///
+    /// # Errors
+    /// Returns `VioletError::KekFingerprintMismatch` before any decryption is
+    /// attempted if the envelope records a KEK fingerprint that `kek` does not match.
+    ///
+    /// # Versions
+    /// Version 1 envelopes, whose DEK is wrapped directly under the KEK, are
+    /// still accepted. Unknown versions fail with `VioletError::UnsupportedVersion`.
+    ///
+    /// # Combined form
+    /// Envelopes with an empty `auth_tag` are treated as combined form, see
+    /// [`decrypt_combined`](Self::decrypt_combined).
     pub fn decrypt(&self, envelope: &EncryptionEnvelope, kek: &[u8]) -> Result<Vec<u8>> {
-        if kek.len() != DEK_SIZE {
-            return Err(VioletError::InvalidKeySize(kek.len()));
-        }
+        check_kek(envelope, kek)?;
+        self.decrypt_with_wrapper(envelope, &local_wrapper(envelope.version, kek)?)
+    }
 
-        // Decode base64 fields
-        let encrypted_dek_with_overhead = BASE64.decode(&envelope.encrypted_key)?;
-        let ciphertext = BASE64.decode(&envelope.encrypted_data)?;
-        let iv = BASE64.decode(&envelope.iv)?;
-        let auth_tag = BASE64.decode(&envelope.auth_tag)?;
+    /// Move an envelope to a new KEK without decrypting its data
+    ///
+    /// The DEK is unwrapped with `old_kek` and wrapped again under `new_kek`;
+    /// `encrypted_data`, `iv` and `auth_tag` are copied unchanged. The result
+    /// is always a current-version envelope, and records a fingerprint of
+    /// `new_kek` if the original had one or fingerprints are enabled.
+    ///
+    /// # Errors
+    /// Fails as `decrypt` would if `old_kek` does not match the envelope.
+    pub fn rewrap(
+        &self,
+        envelope: &EncryptionEnvelope,
+        old_kek: &[u8],
+        new_kek: &[u8],
+        new_key_id: String,
+    ) -> Result<EncryptionEnvelope> {
+        check_kek(envelope, old_kek)?;
+        let dek = unwrap_local_dek(envelope, old_kek)?;
+        let dek_package = LocalKekWrapper::derived(new_kek)?.wrap_dek(&dek)?;
 
-        // Step 1: Decrypt DEK with KEK (AES-GCM appends nonce+tag, so we need to split)
-        // The encrypted_dek contains: ciphertext + tag (nonce is included in the aes_gcm::encrypt output)
-        // Actually, looking at our aes_gcm::encrypt implementation, it returns (ciphertext, nonce, tag) separately
-        // But when we encrypted the DEK above, we only stored the ciphertext part
-        // So encrypted_dek_with_overhead contains the full ciphertext+tag from aes_gcm::encrypt
+        let kek_fingerprint = (self.embed_kek_fingerprint || envelope.kek_fingerprint.is_some())
+            .then(|| kek_fingerprint(new_kek));
+        Ok(EncryptionEnvelope {
+            version: ENVELOPE_VERSION,
+            key_id: new_key_id,
+            encrypted_key: BASE64.encode(&dek_package),
+            kek_fingerprint,
+            // The new KEK comes from the Keys server, not a password
+            kdf: None,
+            ephemeral_public_key: None,
+            ..envelope.clone()
+        })
+    }
 
-        // For decryption, we need to extract: actual_encrypted_dek, dek_nonce, dek_tag
-        // The aes-gcm crate's encrypt appends the tag, and we split it in our aes_gcm::encrypt
-        // But we only saved the ciphertext part when building the envelope!
+    /// Move an envelope to envelope version `to_version` without decrypting its data
+    ///
+    /// The DEK is unwrapped under `kek` the way the envelope's own version
+    /// wraps it and wrapped again the way `to_version` does, e.g. under a key
+    /// derived from `kek` for version 2. Everything else, including `key_id`,
+    /// `kdf` and the KEK fingerprint, is copied unchanged.
+    ///
+    /// # Errors
+    /// Fails as `decrypt` would if `kek` does not match the envelope, and with
+    /// `VioletError::UnsupportedVersion` if either version is unknown.
+    pub fn migrate(&self, envelope: &EncryptionEnvelope, kek: &[u8], to_version: u32) -> Result<EncryptionEnvelope> {
+        check_kek(envelope, kek)?;
+        let new_wrapper = local_wrapper(to_version, kek)?;
+        let dek = unwrap_local_dek(envelope, kek)?;
+        Ok(EncryptionEnvelope {
+            version: to_version,
+            encrypted_key: BASE64.encode(new_wrapper.wrap_dek(&dek)?),
+            ..envelope.clone()
+        })
+    }
 
-        // Wait, I need to reconsider this. Let me check the encrypt method again.
-        // In encrypt(), we call aes_gcm::encrypt(&dek, kek) which returns (encrypted_dek, _dek_iv, _dek_tag)
-        // Then we only store BASE64.encode(&encrypted_dek) in encrypted_key.
-        // This means we lost the IV and tag for DEK decryption!
+    /// Re-encrypt an envelope's data under a fresh DEK, keeping the same KEK
+    ///
+    /// Unlike [`rewrap`](Self::rewrap), this decrypts the data: a new DEK is
+    /// generated, the plaintext is encrypted with it and the new DEK is wrapped
+    /// under `kek`. The envelope's algorithm, `key_id` and `kdf` are kept, and a
+    /// KEK fingerprint is recorded if the original had one or fingerprints are
+    /// enabled. The result is always a current-version envelope.
+    ///
+    /// # Errors
+    /// Fails as `decrypt` would if `kek` does not match the envelope.
+    pub fn reencrypt_data(&self, envelope: &EncryptionEnvelope, kek: &[u8]) -> Result<EncryptionEnvelope> {
+        let plaintext = self.decrypt(envelope, kek)?;
 
-        // FIX: We need to store the DEK's IV and tag as well, OR we need to concatenate them.
-        // For simplicity, let's concatenate IV + ciphertext + tag in the encrypted_key field.
-        // But that changes the encrypt() method.
+        let encryptor = EnvelopeEncryptor {
+            algorithm: Algorithm::from_str(&envelope.algorithm)?,
+            embed_kek_fingerprint: self.embed_kek_fingerprint || envelope.kek_fingerprint.is_some(),
+        };
+        let mut reencrypted = encryptor.encrypt(&plaintext, kek, envelope.key_id.clone())?;
+        reencrypted.kdf = envelope.kdf.clone();
+        Ok(reencrypted)
+    }
 
-        // Actually, let me rethink the design. The EncryptionEnvelope has one IV field.
-        // That IV is for the data encryption, not DEK encryption.
-        // For DEK encryption, we could use a different IV, but we need to store it.
+    /// Encrypt with a KEK derived from `password` instead of a Keys server key
+    ///
+    /// Uses Argon2id with default costs and a random salt; the salt and costs
+    /// are stored in the envelope's `kdf` field and `keyId` is
+    /// [`PASSWORD_KEY_ID`].
+    pub fn encrypt_with_password(&self, plaintext: &[u8], password: &[u8]) -> Result<EncryptionEnvelope> {
+        self.encrypt_with_password_kdf(plaintext, password, PasswordKdf::generate())
+    }
 
-        // Standard practice: The encrypted_key field should contain everything needed to decrypt the DEK.
-        // So encrypted_key = IV || encrypted_DEK || tag (all concatenated)
+    /// Encrypt with a KEK derived from `password` using the given KDF parameters
+    pub fn encrypt_with_password_kdf(
+        &self,
+        plaintext: &[u8],
+        password: &[u8],
+        kdf: PasswordKdf,
+    ) -> Result<EncryptionEnvelope> {
+        let kek = kdf.derive_kek(password)?;
+        let mut envelope = self.encrypt(plaintext, &kek, PASSWORD_KEY_ID.to_string())?;
+        envelope.kdf = Some(kdf);
+        Ok(envelope)
+    }
 
-        // I'll fix this by modifying the encrypt method to concatenate.
+    /// Decrypt an envelope produced by [`encrypt_with_password`](Self::encrypt_with_password)
+    ///
+    /// # Errors
+    /// `VioletError::InvalidEnvelope` if the envelope has no `kdf`, and a
+    /// decryption error if the password is wrong.
+    pub fn decrypt_with_password(&self, envelope: &EncryptionEnvelope, password: &[u8]) -> Result<Vec<u8>> {
+        let kdf = envelope
+            .kdf
+            .as_ref()
+            .ok_or_else(|| VioletError::InvalidEnvelope("Envelope is not password-protected".into()))?;
+        self.decrypt(envelope, &kdf.derive_kek(password)?)
+    }
 
-        // For now, let me implement a version that handles this correctly.
-        // The encrypted_dek_with_overhead should contain: nonce(12) + ciphertext(32) + tag(16) = 60 bytes
+    /// Encrypt so that only the holder of the X25519 private key for
+    /// `recipient_public_key` can decrypt, with no shared KEK
+    ///
+    /// A fresh ephemeral keypair is generated for the envelope. Its shared
+    /// secret with the recipient key is expanded with HKDF-SHA256, salted with
+    /// both public keys, into the key that wraps the DEK, and its public key
+    /// is stored in `ephemeralPublicKey`. `key_id` names the recipient key for
+    /// the reader's benefit. With fingerprints enabled, a fingerprint of the
+    /// recipient public key is recorded.
+    #[cfg(feature = "x25519")]
+    pub fn encrypt_to_public_key(
+        &self,
+        plaintext: &[u8],
+        recipient_public_key: &[u8],
+        key_id: String,
+    ) -> Result<EncryptionEnvelope> {
+        let (wrapper, ephemeral_public_key) = x25519::sender_wrapper(recipient_public_key)?;
+        let mut envelope = self.encrypt_with_wrapper(plaintext, &wrapper, key_id)?;
+        envelope.ephemeral_public_key = Some(BASE64.encode(ephemeral_public_key));
+        if self.embed_kek_fingerprint {
+            envelope.kek_fingerprint = Some(kek_fingerprint(recipient_public_key));
+        }
+        Ok(envelope)
+    }
 
-        // Let me parse the algorithm
-        let algorithm = Algorithm::from_str(&envelope.algorithm)?;
+    /// Decrypt an envelope produced by [`encrypt_to_public_key`](Self::encrypt_to_public_key)
+    ///
+    /// # Errors
+    /// `VioletError::InvalidEnvelope` if the envelope has no
+    /// `ephemeralPublicKey`, `VioletError::KekFingerprintMismatch` if it
+    /// records a fingerprint of a different recipient key, and a decryption
+    /// error if `private_key` is not the recipient's.
+    #[cfg(feature = "x25519")]
+    pub fn decrypt_with_private_key(&self, envelope: &EncryptionEnvelope, private_key: &[u8]) -> Result<Vec<u8>> {
+        let ephemeral_public_key = envelope
+            .ephemeral_public_key
+            .as_ref()
+            .ok_or_else(|| VioletError::InvalidEnvelope("Envelope is not wrapped to a public key".into()))?;
+        if let Some(expected) = &envelope.kek_fingerprint {
+            let actual = kek_fingerprint(&x25519::public_key(private_key)?);
+            if !expected.eq_ignore_ascii_case(&actual) {
+                return Err(VioletError::KekFingerprintMismatch {
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+
+        let wrapper = x25519::recipient_wrapper(private_key, &BASE64.decode(ephemeral_public_key)?)?;
+        self.decrypt_with_wrapper(envelope, &wrapper)
+    }
+
+    /// Decrypt envelope, refusing unless it uses the `expected` algorithm
+    ///
+    /// The algorithm field is not authenticated, so an attacker who can edit
+    /// envelopes could relabel AES-256-GCM-SIV data as AES-256-GCM. Pinning the
+    /// algorithm rules such downgrades out.
+    ///
+    /// # Errors
+    /// Returns `VioletError::AlgorithmMismatch` before any decryption is
+    /// attempted if the envelope names a different algorithm.
+    pub fn decrypt_expecting(
+        &self,
+        envelope: &EncryptionEnvelope,
+        kek: &[u8],
+        expected: Algorithm,
+    ) -> Result<Vec<u8>> {
+        let actual = Algorithm::from_str(&envelope.algorithm)?;
+        if actual != expected {
+            return Err(VioletError::AlgorithmMismatch {
+                expected: expected.as_str().to_string(),
+                actual: actual.as_str().to_string(),
+            });
+        }
+        self.decrypt(envelope, kek)
+    }
+
+    /// Decrypt a combined-form envelope, where the GCM tag is appended to
+    /// `encrypted_data` and `auth_tag` is empty
+    ///
+    /// # Errors
+    /// Returns `VioletError::InvalidEnvelope` if the envelope also carries a
+    /// separate `auth_tag`, since it is then ambiguous which tag applies.
+    pub fn decrypt_combined(&self, envelope: &EncryptionEnvelope, kek: &[u8]) -> Result<Vec<u8>> {
+        if !envelope.auth_tag.is_empty() {
+            return Err(VioletError::InvalidEnvelope(
+                "combined-form envelope must not have a separate authTag".into(),
+            ));
+        }
+        self.decrypt(envelope, kek)
+    }
 
-        // For the DEK, we'll use a simpler approach:
-        // Store nonce || ciphertext || tag in encrypted_key
+    /// Decrypt envelope, delegating DEK unwrapping to `wrapper`
+    ///
+    /// # Memory
+    /// `encryptedData` is base64-decoded in 64 KiB steps straight into one
+    /// buffer, which is then decrypted in place, so beyond the envelope itself
+    /// only a single copy of the data is held. The tag authenticates the whole
+    /// message and is checked before any plaintext is released, so the data
+    /// cannot be decrypted incrementally; for inputs too large to hold in
+    /// memory use the chunked format in [`crate::crypto::stream`].
+    pub fn decrypt_with_wrapper(
+        &self,
+        envelope: &EncryptionEnvelope,
+        wrapper: &dyn DekWrapper,
+    ) -> Result<Vec<u8>> {
+        envelope.check_version()?;
 
-        // Extract nonce, ciphertext, tag from encrypted_dek_with_overhead
-        if encrypted_dek_with_overhead.len() < 12 + 16 {
-            return Err(VioletError::CryptoError("Invalid encrypted DEK length".into()));
+        // Decode the small base64 fields
+        let encrypted_dek_with_overhead = BASE64.decode(&envelope.encrypted_key)?;
+        let iv = BASE64.decode(&envelope.iv)?;
+        let auth_tag = BASE64.decode(&envelope.auth_tag)?;
+        if !auth_tag.is_empty() && auth_tag.len() != GCM_TAG_SIZE {
+            return Err(VioletError::InvalidTagSize(auth_tag.len()));
         }
 
-        let dek_nonce = &encrypted_dek_with_overhead[..12];
-        let dek_data_end = encrypted_dek_with_overhead.len() - 16;
-        let dek_ciphertext = &encrypted_dek_with_overhead[12..dek_data_end];
-        let dek_tag = &encrypted_dek_with_overhead[dek_data_end..];
+        let algorithm = Algorithm::from_str(&envelope.algorithm)?;
 
-        let dek = aes_gcm::decrypt(dek_ciphertext, kek, dek_nonce, dek_tag)?;
+        // Step 1: Unwrap DEK with KEK
+        let dek = wrapper.unwrap_dek(&encrypted_dek_with_overhead)?;
 
         if dek.len() != DEK_SIZE {
             return Err(VioletError::CryptoError(format!("Invalid DEK size: {}", dek.len())));
         }
 
-        // Step 2: Decrypt plaintext with DEK
-        let plaintext = match algorithm {
-            Algorithm::Aes256Gcm => aes_gcm::decrypt(&ciphertext, &dek, &iv, &auth_tag)?,
-            Algorithm::Aes256GcmSiv => aes_gcm_siv::decrypt(&ciphertext, &dek, &iv, &auth_tag)?,
-        };
+        // Step 2: Decode ciphertext || tag into one buffer (combined form already ends with the tag)
+        let mut buffer = Vec::new();
+        decode_base64_chunked(&envelope.encrypted_data, &mut buffer, auth_tag.len())?;
+        if auth_tag.is_empty() {
+            split_combined_tag(&buffer)?;
+        }
+        buffer.extend_from_slice(&auth_tag);
+
+        // Step 3: Decrypt plaintext with DEK, reusing the buffer
+        aead::decrypt_in_place(algorithm, &mut buffer, &dek, &iv)?;
 
-        Ok(plaintext)
+        Ok(buffer)
     }
 }
 
+/// Append the base64 decoding of `encoded` to `out`, [`BASE64_CHUNK_CHARS`] at a time
+///
+/// Decodes straight into `out`, which is sized up front with room for `extra`
+/// more bytes, instead of into a temporary buffer. Accepts and rejects exactly
+/// what a one-shot decode does, with the same error offsets.
+pub(crate) fn decode_base64_chunked(encoded: &str, out: &mut Vec<u8>, extra: usize) -> Result<()> {
+    out.reserve(base64::decoded_len_estimate(encoded.len()) + extra);
+
+    let chunks = encoded.as_bytes().chunks(BASE64_CHUNK_CHARS);
+    let last = chunks.len().saturating_sub(1);
+    for (index, chunk) in chunks.enumerate() {
+        let offset = index * BASE64_CHUNK_CHARS;
+        // Padding may only end the input, not a chunk in the middle of it
+        if index < last {
+            if let Some(position) = chunk.iter().position(|&byte| byte == b'=') {
+                return Err(DecodeError::InvalidByte(offset + position, b'=').into());
+            }
+        }
+        BASE64.decode_vec(chunk, out).map_err(|e| match e {
+            DecodeError::InvalidByte(at, byte) => DecodeError::InvalidByte(offset + at, byte),
+            DecodeError::InvalidLastSymbol(at, byte) => DecodeError::InvalidLastSymbol(offset + at, byte),
+            DecodeError::InvalidLength(len) => DecodeError::InvalidLength(offset + len),
+            other => other,
+        })?;
+    }
+    Ok(())
+}
+
+/// Local wrapper for the DEK of an envelope of the given version
+fn local_wrapper(version: u32, kek: &[u8]) -> Result<LocalKekWrapper> {
+    match version {
+        LEGACY_ENVELOPE_VERSION => LocalKekWrapper::new(kek),
+        ENVELOPE_VERSION => LocalKekWrapper::derived(kek),
+        other => Err(VioletError::UnsupportedVersion(other)),
+    }
+}
+
+/// Unwrap the DEK of an envelope wrapped locally under `kek`, as its version prescribes
+fn unwrap_local_dek(envelope: &EncryptionEnvelope, kek: &[u8]) -> Result<Vec<u8>> {
+    let dek = local_wrapper(envelope.version, kek)?.unwrap_dek(&BASE64.decode(&envelope.encrypted_key)?)?;
+    if dek.len() != DEK_SIZE {
+        return Err(VioletError::CryptoError(format!("Invalid DEK size: {}", dek.len())));
+    }
+    Ok(dek)
+}
+
+/// Check the KEK size and, if the envelope records one, its fingerprint
+///
+/// Envelopes wrapped to a public key have no KEK and are refused outright.
+fn check_kek(envelope: &EncryptionEnvelope, kek: &[u8]) -> Result<()> {
+    if envelope.ephemeral_public_key.is_some() {
+        return Err(VioletError::InvalidEnvelope(
+            "Envelope is wrapped to an X25519 public key; decrypt it with the recipient's private key".into(),
+        ));
+    }
+    if kek.len() != DEK_SIZE {
+        return Err(VioletError::InvalidKeySize(kek.len()));
+    }
+
+    if let Some(expected) = &envelope.kek_fingerprint {
+        let actual = kek_fingerprint(kek);
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(VioletError::KekFingerprintMismatch {
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Split combined-form data (ciphertext || tag) into its ciphertext and tag
+pub(crate) fn split_combined_tag(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    if data.len() < GCM_TAG_SIZE {
+        return Err(VioletError::InvalidTagSize(0));
+    }
+    Ok(data.split_at(data.len() - GCM_TAG_SIZE))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[cfg(feature = "aes-gcm")]
     #[test]
     fn test_envelope_encryption_gcm() {
         let kek = [42u8; 32];
@@ -178,6 +529,7 @@ mod tests {
         assert_eq!(plaintext, &decrypted[..]);
     }
 
+    #[cfg(feature = "aes-gcm-siv")]
     #[test]
     fn test_envelope_encryption_gcm_siv() {
         let kek = [99u8; 32];
@@ -192,6 +544,7 @@ mod tests {
         assert_eq!(plaintext, &decrypted[..]);
     }
 
+    #[cfg(feature = "aes-gcm")]
     #[test]
     fn test_decrypt_with_wrong_kek() {
         let kek1 = [1u8; 32];
@@ -205,6 +558,7 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[cfg(feature = "aes-gcm")]
     #[test]
     fn test_serialization_roundtrip() {
         let kek = [77u8; 32];
@@ -224,10 +578,566 @@ mod tests {
         assert_eq!(plaintext, &decrypted[..]);
     }
 
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_kek_fingerprint_not_embedded_by_default() {
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let envelope = encryptor.encrypt(b"data", &[5u8; 32], "test".to_string()).unwrap();
+        assert_eq!(envelope.kek_fingerprint, None);
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_kek_fingerprint_roundtrip() {
+        let kek = [5u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm).with_kek_fingerprint(true);
+        let envelope = encryptor.encrypt(b"data", &kek, "test".to_string()).unwrap();
+
+        assert_eq!(envelope.kek_fingerprint, Some(kek_fingerprint(&kek)));
+        assert_eq!(encryptor.decrypt(&envelope, &kek).unwrap(), b"data");
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn test_kek_fingerprint_mismatch_same_key_id() {
+        // Two different KEKs sharing a key_id, e.g. a key that was replaced on the server
+        let original_kek = [1u8; 32];
+        let replaced_kek = [2u8; 32];
+
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv).with_kek_fingerprint(true);
+        let envelope = encryptor.encrypt(b"secret", &original_kek, "shared-id".to_string()).unwrap();
+
+        let result = encryptor.decrypt(&envelope, &replaced_kek);
+        match result {
+            Err(VioletError::KekFingerprintMismatch { expected, actual }) => {
+                assert_eq!(expected, kek_fingerprint(&original_kek));
+                assert_eq!(actual, kek_fingerprint(&replaced_kek));
+            }
+            other => panic!("expected fingerprint mismatch, got {:?}", other),
+        }
+    }
+
+    /// Test wrapper that "wraps" by XOR so results are easy to predict
+    struct XorWrapper(u8);
+
+    impl DekWrapper for XorWrapper {
+        fn wrap_dek(&self, dek: &[u8]) -> Result<Vec<u8>> {
+            Ok(dek.iter().map(|b| b ^ self.0).collect())
+        }
+
+        fn unwrap_dek(&self, wrapped_dek: &[u8]) -> Result<Vec<u8>> {
+            self.wrap_dek(wrapped_dek)
+        }
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn test_envelope_with_custom_wrapper() {
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv);
+        let envelope = encryptor
+            .encrypt_with_wrapper(b"remote wrapped", &XorWrapper(0x5a), "transit-key".to_string())
+            .unwrap();
+
+        // The wrapper's output is stored as-is
+        assert_eq!(BASE64.decode(&envelope.encrypted_key).unwrap().len(), DEK_SIZE);
+
+        let decrypted = encryptor.decrypt_with_wrapper(&envelope, &XorWrapper(0x5a)).unwrap();
+        assert_eq!(decrypted, b"remote wrapped");
+
+        assert!(encryptor.decrypt_with_wrapper(&envelope, &XorWrapper(0x11)).is_err());
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn test_decrypt_expecting_matching_algorithm() {
+        let kek = [9u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv);
+        let envelope = encryptor.encrypt(b"pinned", &kek, "pinned".to_string()).unwrap();
+
+        let plaintext = encryptor.decrypt_expecting(&envelope, &kek, Algorithm::Aes256GcmSiv).unwrap();
+        assert_eq!(plaintext, b"pinned");
+    }
+
+    #[cfg(all(feature = "aes-gcm", feature = "aes-gcm-siv"))]
+    #[test]
+    fn test_decrypt_expecting_rejects_downgrade() {
+        let kek = [9u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv);
+        let mut envelope = encryptor.encrypt(b"pinned", &kek, "pinned".to_string()).unwrap();
+        envelope.algorithm = Algorithm::Aes256Gcm.as_str().to_string();
+
+        match encryptor.decrypt_expecting(&envelope, &kek, Algorithm::Aes256GcmSiv) {
+            Err(VioletError::AlgorithmMismatch { expected, actual }) => {
+                assert_eq!(expected, "AES-256-GCM-SIV");
+                assert_eq!(actual, "AES-256-GCM");
+            }
+            other => panic!("expected algorithm mismatch, got {:?}", other),
+        }
+
+        // An unknown algorithm is still reported as such
+        envelope.algorithm = "ROT13".to_string();
+        assert!(matches!(
+            encryptor.decrypt_expecting(&envelope, &kek, Algorithm::Aes256GcmSiv),
+            Err(VioletError::InvalidAlgorithm(_))
+        ));
+    }
+
+    /// Re-pack an envelope the way producers using combined form store it
+    fn to_combined_form(envelope: &EncryptionEnvelope) -> EncryptionEnvelope {
+        let mut data = BASE64.decode(&envelope.encrypted_data).unwrap();
+        data.extend(BASE64.decode(&envelope.auth_tag).unwrap());
+        EncryptionEnvelope {
+            encrypted_data: BASE64.encode(&data),
+            auth_tag: String::new(),
+            ..envelope.clone()
+        }
+    }
+
+    #[cfg(all(feature = "aes-gcm", feature = "aes-gcm-siv"))]
+    #[test]
+    fn test_decrypt_combined_form() {
+        let kek = [8u8; 32];
+        for algorithm in [Algorithm::Aes256Gcm, Algorithm::Aes256GcmSiv] {
+            let encryptor = EnvelopeEncryptor::new(algorithm);
+            let envelope = encryptor.encrypt(b"tag on the end", &kek, "combined".to_string()).unwrap();
+            let combined = to_combined_form(&envelope);
+
+            assert_eq!(encryptor.decrypt_combined(&combined, &kek).unwrap(), b"tag on the end");
+            // Auto-detected from the empty auth_tag
+            assert_eq!(encryptor.decrypt(&combined, &kek).unwrap(), b"tag on the end");
+        }
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_decrypt_combined_form_errors() {
+        let kek = [8u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let envelope = encryptor.encrypt(b"data", &kek, "combined".to_string()).unwrap();
+
+        // A separate tag makes combined form ambiguous
+        assert!(matches!(
+            encryptor.decrypt_combined(&envelope, &kek),
+            Err(VioletError::InvalidEnvelope(_))
+        ));
+
+        // Too short to hold a tag
+        let truncated = EncryptionEnvelope {
+            encrypted_data: BASE64.encode([0u8; 4]),
+            auth_tag: String::new(),
+            ..envelope.clone()
+        };
+        assert!(matches!(encryptor.decrypt(&truncated, &kek), Err(VioletError::InvalidTagSize(0))));
+
+        // A tampered tag still fails authentication
+        let mut tampered = to_combined_form(&envelope);
+        let mut data = BASE64.decode(&tampered.encrypted_data).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        tampered.encrypted_data = BASE64.encode(&data);
+        assert!(encryptor.decrypt_combined(&tampered, &kek).is_err());
+    }
+
+    #[test]
+    fn test_chunked_base64_matches_one_shot() {
+        let sizes = [0, 1, 2, 3, BASE64_CHUNK_CHARS / 4 * 3 - 1, BASE64_CHUNK_CHARS / 4 * 3, 200_001];
+        for size in sizes {
+            let data: Vec<u8> = (0..size).map(|i| (i * 7 % 251) as u8).collect();
+            let encoded = BASE64.encode(&data);
+
+            let mut decoded = vec![0xAA];
+            decode_base64_chunked(&encoded, &mut decoded, 16).unwrap();
+            assert_eq!(decoded[0], 0xAA);
+            assert_eq!(&decoded[1..], BASE64.decode(&encoded).unwrap().as_slice(), "size {}", size);
+        }
+
+        // Errors match the one-shot decode, offsets included
+        let mut bad_byte = BASE64.encode(vec![1u8; BASE64_CHUNK_CHARS]);
+        bad_byte.replace_range(BASE64_CHUNK_CHARS + 5..BASE64_CHUNK_CHARS + 6, "*");
+        let mut padded_chunk = BASE64.encode([9u8; 1]);
+        padded_chunk.push_str(&"A".repeat(BASE64_CHUNK_CHARS));
+        let mut padded_at_boundary = "A".repeat(BASE64_CHUNK_CHARS - 4);
+        padded_at_boundary.push_str(&BASE64.encode([9u8; 1]));
+        padded_at_boundary.push_str("AAAA");
+        for encoded in [bad_byte, padded_chunk, padded_at_boundary, "AAAAA".to_string()] {
+            let one_shot = BASE64.decode(&encoded).unwrap_err();
+            match decode_base64_chunked(&encoded, &mut Vec::new(), 0) {
+                Err(VioletError::Base64Error(e)) => assert_eq!(e, one_shot),
+                other => panic!("expected {:?}, got {:?}", one_shot, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decrypt_large_envelope_across_base64_chunks() {
+        let kek = [5u8; 32];
+        let plaintext: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+
+        for algorithm in Algorithm::all() {
+            let encryptor = EnvelopeEncryptor::new(*algorithm);
+            let envelope = encryptor.encrypt(&plaintext, &kek, "large".to_string()).unwrap();
+            assert!(envelope.encrypted_data.len() > 4 * BASE64_CHUNK_CHARS);
+            assert_eq!(encryptor.decrypt(&envelope, &kek).unwrap(), plaintext);
+            assert_eq!(encryptor.decrypt(&to_combined_form(&envelope), &kek).unwrap(), plaintext);
+        }
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    const VECTOR_KEK: [u8; 32] = [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+        16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
+    ];
+
+    #[cfg(feature = "aes-gcm")]
+    /// Envelope produced independently (Python `cryptography`) with DEK 0x42 * 32
+    fn vector_envelope(version: u32, encrypted_key: &str) -> EncryptionEnvelope {
+        EncryptionEnvelope {
+            version,
+            key_id: "vector".to_string(),
+            encrypted_data: "ajFoQ0c8V01Ag0nVJRa1EplK".to_string(),
+            encrypted_key: encrypted_key.to_string(),
+            iv: "yMnKy8zNzs/Q0dLT".to_string(),
+            algorithm: "AES-256-GCM".to_string(),
+            auth_tag: "t+fLMfhWHKqaRmLWkCDDHQ==".to_string(),
+            kek_fingerprint: None,
+            kdf: None,
+            ephemeral_public_key: None,
+        }
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_decrypt_version_vectors() {
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+
+        let v1 = vector_envelope(
+            LEGACY_ENVELOPE_VERSION,
+            "ZGVmZ2hpamtsbW5vClmcJDurFNx8IB2qmCcovwCAREjJLrEw5ZPuCrnh5wpmEh29RJi/00NsDcXaoQYI",
+        );
+        assert_eq!(encryptor.decrypt(&v1, &VECTOR_KEK).unwrap(), b"violet test vector");
+
+        let v2 = vector_envelope(
+            ENVELOPE_VERSION,
+            "ZGVmZ2hpamtsbW5vwcrjszGz09MbLmVY1JM7PiQlW4Mm7t2WDOtpxY0TwbvSqUaMCds9NR1bdhvvNzR5",
+        );
+        assert_eq!(encryptor.decrypt(&v2, &VECTOR_KEK).unwrap(), b"violet test vector");
+
+        // The version decides how the DEK was wrapped; relabelling breaks unwrapping
+        let relabelled = EncryptionEnvelope { version: ENVELOPE_VERSION, ..v1 };
+        assert!(encryptor.decrypt(&relabelled, &VECTOR_KEK).is_err());
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn test_encrypt_writes_current_version() {
+        let kek = [12u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv);
+        let envelope = encryptor.encrypt(b"derived", &kek, "v2".to_string()).unwrap();
+        assert_eq!(envelope.version, ENVELOPE_VERSION);
+
+        // The DEK is not wrapped under the raw KEK
+        let raw = LocalKekWrapper::new(&kek).unwrap();
+        assert!(encryptor.decrypt_with_wrapper(&envelope, &raw).is_err());
+        assert_eq!(encryptor.decrypt(&envelope, &kek).unwrap(), b"derived");
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_decrypt_legacy_envelope_without_version_field() {
+        let kek = [12u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let legacy = EncryptionEnvelope {
+            version: LEGACY_ENVELOPE_VERSION,
+            ..encryptor
+                .encrypt_with_wrapper(b"old format", &LocalKekWrapper::new(&kek).unwrap(), "v1".to_string())
+                .unwrap()
+        };
+
+        // Envelopes written before versioning have no "version" field at all
+        let mut json: serde_json::Value = serde_json::to_value(&legacy).unwrap();
+        json.as_object_mut().unwrap().remove("version");
+        let parsed: EncryptionEnvelope = serde_json::from_value(json).unwrap();
+
+        assert_eq!(parsed.version, LEGACY_ENVELOPE_VERSION);
+        assert_eq!(encryptor.decrypt(&parsed, &kek).unwrap(), b"old format");
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_decrypt_unsupported_version() {
+        let kek = [12u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let mut envelope = encryptor.encrypt(b"future", &kek, "v3".to_string()).unwrap();
+        envelope.version = 3;
+
+        assert!(matches!(encryptor.decrypt(&envelope, &kek), Err(VioletError::UnsupportedVersion(3))));
+        assert!(matches!(
+            encryptor.decrypt_with_wrapper(&envelope, &XorWrapper(1)),
+            Err(VioletError::UnsupportedVersion(3))
+        ));
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn test_rewrap_keeps_data_and_changes_kek() {
+        let old_kek = [1u8; 32];
+        let new_kek = [2u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv).with_kek_fingerprint(true);
+        let original = encryptor.encrypt(b"rotate me", &old_kek, "old-key".to_string()).unwrap();
+
+        let rewrapped = encryptor.rewrap(&original, &old_kek, &new_kek, "new-key".to_string()).unwrap();
+
+        // The data ciphertext is untouched; only the wrapped DEK and key references change
+        assert_eq!(rewrapped.encrypted_data, original.encrypted_data);
+        assert_eq!(rewrapped.iv, original.iv);
+        assert_eq!(rewrapped.auth_tag, original.auth_tag);
+        assert_eq!(rewrapped.algorithm, original.algorithm);
+        assert_ne!(rewrapped.encrypted_key, original.encrypted_key);
+        assert_eq!(rewrapped.key_id, "new-key");
+        assert_eq!(rewrapped.kek_fingerprint, Some(kek_fingerprint(&new_kek)));
+
+        assert_eq!(encryptor.decrypt(&rewrapped, &new_kek).unwrap(), b"rotate me");
+        assert!(matches!(
+            encryptor.decrypt(&rewrapped, &old_kek),
+            Err(VioletError::KekFingerprintMismatch { .. })
+        ));
+
+        // Without a fingerprint to short-circuit, the old KEK still cannot unwrap the DEK
+        let unfingerprinted = EncryptionEnvelope { kek_fingerprint: None, ..rewrapped };
+        assert!(encryptor.decrypt(&unfingerprinted, &old_kek).is_err());
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_rewrap_upgrades_legacy_envelope() {
+        let old_kek = [3u8; 32];
+        let new_kek = [4u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let legacy = EncryptionEnvelope {
+            version: LEGACY_ENVELOPE_VERSION,
+            ..encryptor
+                .encrypt_with_wrapper(b"legacy", &LocalKekWrapper::new(&old_kek).unwrap(), "old".to_string())
+                .unwrap()
+        };
+
+        let rewrapped = encryptor.rewrap(&legacy, &old_kek, &new_kek, "new".to_string()).unwrap();
+        assert_eq!(rewrapped.version, ENVELOPE_VERSION);
+        assert_eq!(rewrapped.kek_fingerprint, None);
+        assert_eq!(encryptor.decrypt(&rewrapped, &new_kek).unwrap(), b"legacy");
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_migrate_legacy_envelope_keeps_kek_and_data() {
+        let kek = [5u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let legacy = EncryptionEnvelope {
+            version: LEGACY_ENVELOPE_VERSION,
+            kek_fingerprint: Some(kek_fingerprint(&kek)),
+            ..encryptor
+                .encrypt_with_wrapper(b"migrate me", &LocalKekWrapper::new(&kek).unwrap(), "kek".to_string())
+                .unwrap()
+        };
+
+        let migrated = encryptor.migrate(&legacy, &kek, ENVELOPE_VERSION).unwrap();
+        assert_eq!(migrated.version, ENVELOPE_VERSION);
+        assert_eq!(migrated.key_id, "kek");
+        assert_eq!(migrated.encrypted_data, legacy.encrypted_data);
+        assert_eq!(migrated.kek_fingerprint, legacy.kek_fingerprint);
+        assert_ne!(migrated.encrypted_key, legacy.encrypted_key);
+        assert_eq!(encryptor.decrypt(&migrated, &kek).unwrap(), b"migrate me");
+
+        assert!(encryptor.migrate(&legacy, &[6u8; 32], ENVELOPE_VERSION).is_err());
+        assert!(matches!(
+            encryptor.migrate(&legacy, &kek, 3),
+            Err(VioletError::UnsupportedVersion(3))
+        ));
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_rewrap_with_wrong_old_kek() {
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let envelope = encryptor.encrypt(b"data", &[5u8; 32], "key".to_string()).unwrap();
+        assert!(encryptor.rewrap(&envelope, &[6u8; 32], &[7u8; 32], "new".to_string()).is_err());
+    }
+
+    #[cfg(all(feature = "aes-gcm", feature = "aes-gcm-siv"))]
+    #[test]
+    fn test_reencrypt_data_changes_dek_and_keeps_plaintext() {
+        let kek = [9u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let original = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv)
+            .with_kek_fingerprint(true)
+            .encrypt(b"exposed dek", &kek, "same-key".to_string())
+            .unwrap();
+
+        let reencrypted = encryptor.reencrypt_data(&original, &kek).unwrap();
+
+        // Data and wrapped DEK are both fresh; key reference and algorithm are kept
+        assert_ne!(reencrypted.encrypted_data, original.encrypted_data);
+        assert_ne!(reencrypted.encrypted_key, original.encrypted_key);
+        assert_ne!(reencrypted.iv, original.iv);
+        assert_eq!(reencrypted.key_id, "same-key");
+        assert_eq!(reencrypted.algorithm, original.algorithm);
+        assert_eq!(reencrypted.kek_fingerprint, original.kek_fingerprint);
+
+        assert_eq!(encryptor.decrypt(&reencrypted, &kek).unwrap(), b"exposed dek");
+        assert!(encryptor.reencrypt_data(&original, &[8u8; 32]).is_err());
+    }
+
+    #[cfg(feature = "aes-gcm")]
     #[test]
     fn test_invalid_kek_size() {
         let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
         let result = encryptor.encrypt(b"test", &[0u8; 16], "test".to_string());
         assert!(matches!(result, Err(VioletError::InvalidKeySize(16))));
     }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn test_password_roundtrip_and_wrong_password() {
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv);
+        let kdf = PasswordKdf::with_params(64, 1, 1);
+        let envelope = encryptor
+            .encrypt_with_password_kdf(b"diary entry", b"hunter2", kdf.clone())
+            .unwrap();
+
+        assert_eq!(envelope.key_id, PASSWORD_KEY_ID);
+        assert_eq!(envelope.kdf.as_ref(), Some(&kdf));
+        assert!(envelope.validate_structure().is_ok());
+
+        let json = serde_json::to_string(&envelope).unwrap();
+        assert!(!json.contains("hunter2"));
+        let parsed: EncryptionEnvelope = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(encryptor.decrypt_with_password(&parsed, b"hunter2").unwrap(), b"diary entry");
+        assert!(encryptor.decrypt_with_password(&parsed, b"hunter3").is_err());
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_decrypt_with_password_requires_kdf() {
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        let envelope = encryptor.encrypt(b"data", &[1u8; 32], "key".to_string()).unwrap();
+        assert!(matches!(
+            encryptor.decrypt_with_password(&envelope, b"anything"),
+            Err(VioletError::InvalidEnvelope(_))
+        ));
+    }
+
+    #[cfg(not(feature = "aes-gcm-siv"))]
+    #[test]
+    fn test_gcm_siv_unsupported_without_feature() {
+        let kek = [3u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv);
+        match encryptor.encrypt(b"data", &kek, "key".to_string()) {
+            Err(VioletError::UnsupportedAlgorithm(name)) => assert_eq!(name, "AES-256-GCM-SIV"),
+            other => panic!("expected UnsupportedAlgorithm, got {:?}", other),
+        }
+
+        // Envelopes written by a build with the feature still parse, but do not decrypt
+        let mut envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"data", &kek, "key".to_string())
+            .unwrap();
+        envelope.algorithm = Algorithm::Aes256GcmSiv.as_str().to_string();
+        assert!(envelope.validate_structure().is_ok());
+        assert!(matches!(encryptor.decrypt(&envelope, &kek), Err(VioletError::UnsupportedAlgorithm(_))));
+    }
+
+    #[cfg(not(feature = "aes-gcm"))]
+    #[test]
+    fn test_gcm_unsupported_without_feature() {
+        let kek = [3u8; 32];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        match encryptor.encrypt(b"data", &kek, "key".to_string()) {
+            Err(VioletError::UnsupportedAlgorithm(name)) => assert_eq!(name, "AES-256-GCM"),
+            other => panic!("expected UnsupportedAlgorithm, got {:?}", other),
+        }
+
+        // DEKs are still wrapped with AES-256-GCM, so SIV envelopes work
+        let encryptor = EnvelopeEncryptor::new(Algorithm::default());
+        let mut envelope = encryptor.encrypt(b"data", &kek, "key".to_string()).unwrap();
+        assert_eq!(encryptor.decrypt(&envelope, &kek).unwrap(), b"data");
+        envelope.algorithm = Algorithm::Aes256Gcm.as_str().to_string();
+        assert!(matches!(encryptor.decrypt(&envelope, &kek), Err(VioletError::UnsupportedAlgorithm(_))));
+    }
+
+    #[test]
+    fn test_estimated_envelope_size_bounds_actual() {
+        let kek = [5u8; 32];
+        let uuid = "9b2d4c3e-1f5a-4e8b-a7c6-0d1e2f3a4b5c".to_string();
+        for algorithm in Algorithm::all() {
+            for fingerprint in [false, true] {
+                let encryptor = EnvelopeEncryptor::new(*algorithm).with_kek_fingerprint(fingerprint);
+                for len in [0, 1, 2, 3, 4, 100, 1000, 4096, 65_537] {
+                    let plaintext = vec![0xa5u8; len];
+                    let estimate = encryptor.estimated_envelope_size(len);
+
+                    // Exact for a UUID key_id, and an upper bound for shorter ones
+                    let envelope = encryptor.encrypt(&plaintext, &kek, uuid.clone()).unwrap();
+                    let actual = serde_json::to_string(&envelope).unwrap().len();
+                    assert_eq!(estimate, actual, "{:?} fingerprint={} len={}", algorithm, fingerprint, len);
+
+                    let envelope = encryptor.encrypt(&plaintext, &kek, "k1".to_string()).unwrap();
+                    let actual = serde_json::to_string(&envelope).unwrap().len();
+                    assert!(estimate >= actual, "{:?} len={}: {} < {}", algorithm, len, estimate, actual);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_estimated_envelope_size_saturates() {
+        let encryptor = EnvelopeEncryptor::new(Algorithm::default());
+        assert_eq!(encryptor.estimated_envelope_size(usize::MAX), usize::MAX);
+        assert_eq!(encryptor.estimated_envelope_size(3) - encryptor.estimated_envelope_size(0), 4);
+    }
+
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_public_key_round_trip() {
+        let (private_key, public_key) = x25519::generate_keypair();
+        let encryptor = EnvelopeEncryptor::new(Algorithm::default()).with_kek_fingerprint(true);
+
+        let envelope = encryptor.encrypt_to_public_key(b"for the recipient", &public_key, "alice".into()).unwrap();
+        assert_eq!(envelope.key_id, "alice");
+        assert_eq!(envelope.kek_fingerprint, Some(kek_fingerprint(&public_key)));
+        envelope.validate_structure().unwrap();
+        assert_eq!(encryptor.decrypt_with_private_key(&envelope, &private_key).unwrap(), b"for the recipient");
+
+        // The JSON form carries the ephemeral public key
+        let json = serde_json::to_string(&envelope).unwrap();
+        assert!(json.contains("\"ephemeralPublicKey\""));
+        let parsed: EncryptionEnvelope = serde_json::from_str(&json).unwrap();
+        assert_eq!(encryptor.decrypt_with_private_key(&parsed, &private_key).unwrap(), b"for the recipient");
+    }
+
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn test_public_key_envelope_needs_the_recipient_private_key() {
+        let (private_key, public_key) = x25519::generate_keypair();
+        let (other_key, _) = x25519::generate_keypair();
+        let encryptor = EnvelopeEncryptor::new(Algorithm::default());
+        let envelope = encryptor.encrypt_to_public_key(b"secret", &public_key, "alice".into()).unwrap();
+
+        assert!(encryptor.decrypt_with_private_key(&envelope, &other_key).is_err());
+        let fingerprinted = EncryptionEnvelope {
+            kek_fingerprint: Some(kek_fingerprint(&public_key)),
+            ..envelope.clone()
+        };
+        assert!(matches!(
+            encryptor.decrypt_with_private_key(&fingerprinted, &other_key),
+            Err(VioletError::KekFingerprintMismatch { .. })
+        ));
+
+        // Not a KEK envelope, whatever 32 bytes are offered
+        assert!(matches!(encryptor.decrypt(&envelope, &private_key), Err(VioletError::InvalidEnvelope(_))));
+
+        // And a KEK envelope is not a public key envelope
+        let symmetric = encryptor.encrypt(b"secret", &[5u8; 32], "kek".into()).unwrap();
+        assert!(matches!(
+            encryptor.decrypt_with_private_key(&symmetric, &private_key),
+            Err(VioletError::InvalidEnvelope(_))
+        ));
+    }
 }
@@ -0,0 +1,31 @@
+use sha2::{Digest, Sha256};
+
+/// Number of SHA-256 bytes kept in a KEK fingerprint
+pub const FINGERPRINT_SIZE: usize = 8;
+
+/// Compute a non-secret fingerprint of a KEK
+///
+/// The fingerprint is the SHA-256 digest of the key bytes truncated to
+/// `FINGERPRINT_SIZE` bytes and hex-encoded. It identifies which key was used
+/// without revealing the key itself.
+pub fn kek_fingerprint(kek: &[u8]) -> String {
+    let digest = Sha256::digest(kek);
+    hex::encode(&digest[..FINGERPRINT_SIZE])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_is_truncated_sha256() {
+        // SHA-256 of 32 zero bytes begins 66687aadf862bd77
+        assert_eq!(kek_fingerprint(&[0u8; 32]), "66687aadf862bd77");
+        assert_eq!(kek_fingerprint(&[0u8; 32]).len(), FINGERPRINT_SIZE * 2);
+    }
+
+    #[test]
+    fn test_fingerprint_differs_between_keys() {
+        assert_ne!(kek_fingerprint(&[1u8; 32]), kek_fingerprint(&[2u8; 32]));
+    }
+}
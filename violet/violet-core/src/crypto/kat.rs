@@ -0,0 +1,144 @@
+use crate::crypto::types::Algorithm;
+use crate::crypto::{aead, kdf};
+use crate::error::{Result, VioletError};
+
+/// A published ciphertext for a fixed key and nonce, used to check the AEAD
+/// implementations without any randomness
+#[derive(Debug, Clone, Copy)]
+pub struct KnownAnswer {
+    pub name: &'static str,
+    pub algorithm: Algorithm,
+    /// Hex-encoded fields, as printed in the source document
+    pub key: &'static str,
+    pub nonce: &'static str,
+    pub plaintext: &'static str,
+    pub ciphertext: &'static str,
+    pub tag: &'static str,
+}
+
+/// Known-answer vectors for every algorithm
+///
+/// AES-256-GCM: test cases 13 and 14 of McGrew & Viega, "The Galois/Counter
+/// Mode of Operation". AES-256-GCM-SIV: RFC 8452 appendix C.2.
+pub const VECTORS: &[KnownAnswer] = &[
+    KnownAnswer {
+        name: "AES-256-GCM empty plaintext",
+        algorithm: Algorithm::Aes256Gcm,
+        key: "0000000000000000000000000000000000000000000000000000000000000000",
+        nonce: "000000000000000000000000",
+        plaintext: "",
+        ciphertext: "",
+        tag: "530f8afbc74536b9a963b4f1c4cb738b",
+    },
+    KnownAnswer {
+        name: "AES-256-GCM one block",
+        algorithm: Algorithm::Aes256Gcm,
+        key: "0000000000000000000000000000000000000000000000000000000000000000",
+        nonce: "000000000000000000000000",
+        plaintext: "00000000000000000000000000000000",
+        ciphertext: "cea7403d4d606b6e074ec5d3baf39d18",
+        tag: "d0d1c8a799996bf0265b98b5d48ab919",
+    },
+    KnownAnswer {
+        name: "AES-256-GCM-SIV empty plaintext",
+        algorithm: Algorithm::Aes256GcmSiv,
+        key: "0100000000000000000000000000000000000000000000000000000000000000",
+        nonce: "030000000000000000000000",
+        plaintext: "",
+        ciphertext: "",
+        tag: "07f5f4169bbf55a8400cd47ea6fd400f",
+    },
+    KnownAnswer {
+        name: "AES-256-GCM-SIV 8 bytes",
+        algorithm: Algorithm::Aes256GcmSiv,
+        key: "0100000000000000000000000000000000000000000000000000000000000000",
+        nonce: "030000000000000000000000",
+        plaintext: "0100000000000000",
+        ciphertext: "c2ef328e5c71c83b",
+        tag: "843122130f7364b761e0b97427e3df28",
+    },
+    KnownAnswer {
+        name: "AES-256-GCM-SIV one block",
+        algorithm: Algorithm::Aes256GcmSiv,
+        key: "0100000000000000000000000000000000000000000000000000000000000000",
+        nonce: "030000000000000000000000",
+        plaintext: "01000000000000000000000000000000",
+        ciphertext: "85a01b63025ba19b7fd3ddfc033b3e76",
+        tag: "c9eac6fa700942702e90862383c6c366",
+    },
+];
+
+/// HKDF-SHA256 wrapping key derived from an all-zero KEK, see [`kdf::derive_wrapping_key`]
+const WRAPPING_KEY_OF_ZERO_KEK: &str = "27c8351a368d0e5d345f3fa9ed7eb5d4ba2795fae7e8c1fb6c5a7d9c0b5faf37";
+
+impl KnownAnswer {
+    /// Decrypt the vector and compare with the expected plaintext, then check
+    /// that a corrupted tag is rejected
+    pub fn check(&self) -> Result<()> {
+        let key = hex::decode(self.key)?;
+        let nonce = hex::decode(self.nonce)?;
+        let ciphertext = hex::decode(self.ciphertext)?;
+        let mut tag = hex::decode(self.tag)?;
+
+        let decrypt = |ciphertext: &[u8], key: &[u8], nonce: &[u8], tag: &[u8]| {
+            aead::decrypt(self.algorithm, ciphertext, key, nonce, tag)
+        };
+
+        let plaintext = decrypt(&ciphertext, &key, &nonce, &tag)?;
+        if plaintext != hex::decode(self.plaintext)? {
+            return Err(VioletError::CryptoError(format!("{}: wrong plaintext", self.name)));
+        }
+
+        tag[0] ^= 0x01;
+        if decrypt(&ciphertext, &key, &nonce, &tag).is_ok() {
+            return Err(VioletError::CryptoError(format!("{}: corrupted tag accepted", self.name)));
+        }
+        Ok(())
+    }
+}
+
+/// Check the HKDF derivation of the DEK wrapping key against a fixed output
+pub fn check_wrapping_key_derivation() -> Result<()> {
+    if *kdf::derive_wrapping_key(&[0u8; 32])? != *hex::decode(WRAPPING_KEY_OF_ZERO_KEK)? {
+        return Err(VioletError::CryptoError("HKDF wrapping key: wrong output".into()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_vectors_pass() {
+        for vector in VECTORS.iter().filter(|v| v.algorithm.is_enabled()) {
+            vector.check().unwrap_or_else(|e| panic!("{}: {}", vector.name, e));
+        }
+        check_wrapping_key_derivation().unwrap();
+    }
+
+    #[test]
+    fn test_every_algorithm_has_a_vector() {
+        for algorithm in Algorithm::all() {
+            assert!(VECTORS.iter().any(|v| v.algorithm == *algorithm), "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_disabled_algorithm_vectors_are_unsupported() {
+        for vector in VECTORS.iter().filter(|v| !v.algorithm.is_enabled()) {
+            assert!(matches!(vector.check(), Err(VioletError::UnsupportedAlgorithm(_))), "{}", vector.name);
+        }
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_wrong_expectation_fails() {
+        let wrong = KnownAnswer {
+            plaintext: "01000000000000000000000000000000",
+            ..VECTORS[1]
+        };
+        let err = wrong.check().unwrap_err();
+        assert!(err.to_string().contains("wrong plaintext"), "{}", err);
+    }
+}
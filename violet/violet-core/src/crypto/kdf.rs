@@ -0,0 +1,245 @@
+use crate::crypto::types::DEK_SIZE;
+use crate::error::{Result, VioletError};
+use crate::secret::SecretKey;
+use argon2::{Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hkdf::Hkdf;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+/// HKDF info string binding derived keys to DEK wrapping
+pub const DEK_WRAP_INFO: &[u8] = b"violet-dek-wrap";
+
+/// Derive the key used to wrap DEKs from a KEK
+///
+/// HKDF-SHA256 with no salt and [`DEK_WRAP_INFO`] as the info string. Wrapping
+/// with a derived key rather than the KEK itself keeps the KEK from being used
+/// directly for more than one purpose.
+pub fn derive_wrapping_key(kek: &[u8]) -> Result<SecretKey> {
+    if kek.len() != DEK_SIZE {
+        return Err(VioletError::InvalidKeySize(kek.len()));
+    }
+
+    let mut wrapping_key = Zeroizing::new([0u8; DEK_SIZE]);
+    Hkdf::<Sha256>::new(None, kek)
+        .expand(DEK_WRAP_INFO, &mut wrapping_key[..])
+        .map_err(|e| VioletError::CryptoError(format!("HKDF expand failed: {}", e)))?;
+    Ok(SecretKey::new(&wrapping_key[..]))
+}
+
+/// `PasswordKdf::algorithm` value for Argon2id, the only supported password KDF
+pub const ARGON2ID: &str = "argon2id";
+
+/// Length of the random salt generated for each password-protected envelope
+pub const PASSWORD_SALT_SIZE: usize = 16;
+
+/// Default Argon2id memory cost in KiB (19 MiB)
+pub const DEFAULT_ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+
+/// Default Argon2id number of passes
+pub const DEFAULT_ARGON2_ITERATIONS: u32 = 2;
+
+/// Default Argon2id degree of parallelism
+pub const DEFAULT_ARGON2_PARALLELISM: u32 = 1;
+
+/// Largest memory cost accepted from an envelope (1 GiB), so a crafted
+/// envelope cannot make decryption allocate without bound
+pub const MAX_ARGON2_MEMORY_KIB: u32 = 1024 * 1024;
+
+/// Most passes accepted from an envelope, so a crafted envelope cannot make
+/// decryption run for hours
+pub const MAX_ARGON2_ITERATIONS: u32 = 16;
+
+/// Most lanes accepted from an envelope
+pub const MAX_ARGON2_PARALLELISM: u32 = 16;
+
+/// Shortest salt accepted from an envelope
+const MIN_PASSWORD_SALT_SIZE: usize = 8;
+
+/// How a KEK was derived from a password, stored in the envelope so decryption
+/// can derive it again
+///
+/// Holds only the salt and cost parameters, never the password or the KEK.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordKdf {
+    /// Always [`ARGON2ID`]
+    pub algorithm: String,
+
+    /// Base64-encoded random salt
+    pub salt: String,
+
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl PasswordKdf {
+    /// Default Argon2id costs with a fresh random salt
+    pub fn generate() -> Self {
+        Self::with_params(DEFAULT_ARGON2_MEMORY_KIB, DEFAULT_ARGON2_ITERATIONS, DEFAULT_ARGON2_PARALLELISM)
+    }
+
+    /// Custom Argon2id costs with a fresh random salt
+    pub fn with_params(memory_kib: u32, iterations: u32, parallelism: u32) -> Self {
+        let mut salt = [0u8; PASSWORD_SALT_SIZE];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Self {
+            algorithm: ARGON2ID.to_string(),
+            salt: BASE64.encode(salt),
+            memory_kib,
+            iterations,
+            parallelism,
+        }
+    }
+
+    /// Derive the 32-byte KEK for `password`
+    ///
+    /// A wrong password derives a different KEK; it is only detected when
+    /// that KEK fails to unwrap the envelope's DEK.
+    pub fn derive_kek(&self, password: &[u8]) -> Result<SecretKey> {
+        let salt = self.validate()?;
+        derive_password_kek(password, &salt, self.memory_kib, self.iterations, self.parallelism)
+    }
+
+    /// Check the algorithm and bounds, returning the decoded salt
+    pub fn validate(&self) -> Result<Vec<u8>> {
+        if self.algorithm != ARGON2ID {
+            return Err(VioletError::InvalidEnvelope(format!("Unsupported password KDF: {}", self.algorithm)));
+        }
+        if self.memory_kib > MAX_ARGON2_MEMORY_KIB {
+            return Err(VioletError::InvalidEnvelope(format!(
+                "Argon2 memory cost {} KiB exceeds the {} KiB limit",
+                self.memory_kib, MAX_ARGON2_MEMORY_KIB
+            )));
+        }
+        if self.iterations > MAX_ARGON2_ITERATIONS {
+            return Err(VioletError::InvalidEnvelope(format!(
+                "Argon2 iteration count {} exceeds the limit of {}",
+                self.iterations, MAX_ARGON2_ITERATIONS
+            )));
+        }
+        if self.parallelism > MAX_ARGON2_PARALLELISM {
+            return Err(VioletError::InvalidEnvelope(format!(
+                "Argon2 parallelism {} exceeds the limit of {}",
+                self.parallelism, MAX_ARGON2_PARALLELISM
+            )));
+        }
+
+        let salt = BASE64.decode(&self.salt)?;
+        if salt.len() < MIN_PASSWORD_SALT_SIZE {
+            return Err(VioletError::InvalidEnvelope(format!("Password salt is only {} bytes", salt.len())));
+        }
+        Ok(salt)
+    }
+}
+
+/// Derive a 32-byte KEK from a password and salt with Argon2id (version 0x13)
+pub fn derive_password_kek(
+    password: &[u8],
+    salt: &[u8],
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+) -> Result<SecretKey> {
+    let params = Params::new(memory_kib, iterations, parallelism, Some(DEK_SIZE))
+        .map_err(|e| VioletError::CryptoError(format!("Invalid Argon2 parameters: {}", e)))?;
+
+    let mut kek = Zeroizing::new([0u8; DEK_SIZE]);
+    Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password, salt, &mut kek[..])
+        .map_err(|e| VioletError::CryptoError(format!("Argon2 failed: {}", e)))?;
+    Ok(SecretKey::new(&kek[..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn test_derive_wrapping_key_vectors() {
+        assert_eq!(
+            &*derive_wrapping_key(&[0u8; 32]).unwrap(),
+            hex!("27c8351a368d0e5d345f3fa9ed7eb5d4ba2795fae7e8c1fb6c5a7d9c0b5faf37")
+        );
+
+        let kek: Vec<u8> = (0u8..32).collect();
+        assert_eq!(
+            &*derive_wrapping_key(&kek).unwrap(),
+            hex!("2ffb3534c93e885b7a9e6ec748e28dab2e843e2e5c9065dfc2b2c0bc85ccf797")
+        );
+    }
+
+    #[test]
+    fn test_derive_wrapping_key_invalid_size() {
+        assert!(matches!(
+            derive_wrapping_key(&[0u8; 16]),
+            Err(VioletError::InvalidKeySize(16))
+        ));
+    }
+
+    #[test]
+    fn test_derive_password_kek_vector() {
+        assert_eq!(
+            &*derive_password_kek(b"password", b"somesalt", 64, 1, 1).unwrap(),
+            hex!("729c7a54441bc13559bdca71348c4e554599e719c08a952601ed5c83618c1bbd")
+        );
+    }
+
+    /// Cheap costs so tests stay fast in debug builds
+    fn test_kdf() -> PasswordKdf {
+        PasswordKdf::with_params(64, 1, 1)
+    }
+
+    #[test]
+    fn test_same_password_and_salt_derive_same_kek() {
+        let kdf = test_kdf();
+        let kek = kdf.derive_kek(b"correct horse").unwrap();
+        assert_eq!(kek.len(), 32);
+        assert_eq!(&*kdf.derive_kek(b"correct horse").unwrap(), &*kek);
+        assert_ne!(&*kdf.derive_kek(b"correct horsf").unwrap(), &*kek);
+
+        // A fresh salt gives a different KEK for the same password
+        assert_ne!(&*test_kdf().derive_kek(b"correct horse").unwrap(), &*kek);
+    }
+
+    #[test]
+    fn test_password_kdf_rejects_bad_parameters() {
+        let kdf = test_kdf();
+        let unknown = PasswordKdf {
+            algorithm: "scrypt".into(),
+            ..kdf.clone()
+        };
+        assert!(matches!(unknown.derive_kek(b"pw"), Err(VioletError::InvalidEnvelope(_))));
+
+        let greedy = PasswordKdf {
+            memory_kib: MAX_ARGON2_MEMORY_KIB + 1,
+            ..kdf.clone()
+        };
+        assert!(matches!(greedy.derive_kek(b"pw"), Err(VioletError::InvalidEnvelope(_))));
+
+        let slow = PasswordKdf {
+            iterations: MAX_ARGON2_ITERATIONS + 1,
+            ..kdf.clone()
+        };
+        assert!(matches!(slow.derive_kek(b"pw"), Err(VioletError::InvalidEnvelope(_))));
+
+        let wide = PasswordKdf {
+            parallelism: MAX_ARGON2_PARALLELISM + 1,
+            ..kdf.clone()
+        };
+        assert!(matches!(wide.derive_kek(b"pw"), Err(VioletError::InvalidEnvelope(_))));
+
+        let short_salt = PasswordKdf {
+            salt: BASE64.encode([0u8; 4]),
+            ..kdf.clone()
+        };
+        assert!(matches!(short_salt.derive_kek(b"pw"), Err(VioletError::InvalidEnvelope(_))));
+
+        let zero_passes = PasswordKdf { iterations: 0, ..kdf };
+        assert!(matches!(zero_passes.derive_kek(b"pw"), Err(VioletError::CryptoError(_))));
+    }
+}
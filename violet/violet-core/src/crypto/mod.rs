@@ -1,4 +1,13 @@
+pub mod aead;
 pub mod aes_gcm;
+#[cfg(feature = "aes-gcm-siv")]
 pub mod aes_gcm_siv;
 pub mod envelope;
+pub mod fingerprint;
+pub mod kat;
+pub mod kdf;
+pub mod stream;
 pub mod types;
+pub mod wrapper;
+#[cfg(feature = "x25519")]
+pub mod x25519;
@@ -0,0 +1,776 @@
+use crate::crypto::aead;
+use crate::crypto::fingerprint::kek_fingerprint;
+use crate::crypto::types::{Algorithm, DEK_SIZE, GCM_TAG_SIZE};
+use crate::crypto::wrapper::{DekWrapper, LocalKekWrapper};
+use crate::error::{Result, VioletError};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read, Write};
+
+/// Magic bytes at the start of every encrypted stream
+pub const STREAM_MAGIC: &[u8; 4] = b"VSTR";
+
+/// Current streaming format version
+pub const STREAM_VERSION: u32 = 1;
+
+/// Plaintext bytes per chunk unless configured otherwise (64 KiB)
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Smallest accepted chunk size (1 KiB)
+pub const MIN_CHUNK_SIZE: usize = 1024;
+
+/// Largest accepted chunk size (16 MiB), which bounds memory use when decrypting
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Longest header accepted when decrypting
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Bytes a data frame adds to its chunk: kind, length and tag
+pub const STREAM_FRAME_OVERHEAD: usize = 1 + 4 + GCM_TAG_SIZE;
+
+const NONCE_PREFIX_SIZE: usize = 7;
+const FRAME_DATA: u8 = 0;
+const FRAME_FOOTER: u8 = 1;
+
+/// Parameters written in clear at the start of a stream
+///
+/// The serialized header is authenticated as associated data of every chunk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamHeader {
+    pub version: u32,
+    pub key_id: String,
+    pub algorithm: String,
+    pub encrypted_key: String,
+    pub chunk_size: usize,
+    pub nonce_prefix: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kek_fingerprint: Option<String>,
+}
+
+/// Envelope encryption for inputs too large to hold in memory
+///
+/// Format: `VSTR`, a 4-byte big-endian header length and the JSON
+/// [`StreamHeader`], then frames of a kind byte (0 = data, 1 = footer), a
+/// 4-byte big-endian length and the chunk ciphertext with its tag.
+///
+/// Each data chunk is sealed with the DEK under the nonce
+/// `prefix (7) || chunk index (4) || 0`, so reordered or duplicated chunks fail
+/// to authenticate. The final footer chunk is sealed under `prefix || 0 || 1`
+/// and holds the total number of data chunks; decryption fails if the stream
+/// ends without a footer or the footer's count differs from the chunks seen.
+pub struct StreamEncryptor {
+    algorithm: Algorithm,
+    chunk_size: usize,
+}
+
+impl StreamEncryptor {
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Set the plaintext bytes per chunk, between [`MIN_CHUNK_SIZE`] and [`MAX_CHUNK_SIZE`]
+    ///
+    /// Only affects encryption; decryption uses the size recorded in the header.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Result<Self> {
+        check_chunk_size(chunk_size)?;
+        self.chunk_size = chunk_size;
+        Ok(self)
+    }
+
+    /// Encrypt everything read from `reader` into `writer`
+    ///
+    /// The DEK is wrapped under a key derived from `kek`, as for version 2
+    /// envelopes. Returns the number of data chunks written.
+    pub fn encrypt_stream<R: Read, W: Write>(
+        &self,
+        mut reader: R,
+        mut writer: W,
+        kek: &[u8],
+        key_id: String,
+    ) -> Result<u64> {
+        let (mut sealer, preamble) = self.sealer(kek, key_id)?;
+        writer.write_all(&preamble)?;
+
+        let mut buf = vec![0u8; self.chunk_size];
+        loop {
+            let n = read_full(&mut reader, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            writer.write_all(&sealer.seal_chunk(&buf[..n])?)?;
+            if n < buf.len() {
+                break;
+            }
+        }
+
+        let (footer, chunks) = sealer.finish()?;
+        writer.write_all(&footer)?;
+        writer.flush()?;
+        Ok(chunks)
+    }
+
+    /// Start a stream under `kek` for plaintext that arrives piece by piece
+    ///
+    /// Returns the sealer and the bytes that open the stream (magic and
+    /// header); see [`StreamSealer`].
+    pub fn sealer(&self, kek: &[u8], key_id: String) -> Result<(StreamSealer, Vec<u8>)> {
+        let mut dek = vec![0u8; DEK_SIZE];
+        rand::thread_rng().fill_bytes(&mut dek);
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        rand::thread_rng().fill_bytes(&mut nonce_prefix);
+
+        let header = StreamHeader {
+            version: STREAM_VERSION,
+            key_id,
+            algorithm: self.algorithm.as_str().to_string(),
+            encrypted_key: BASE64.encode(LocalKekWrapper::derived(kek)?.wrap_dek(&dek)?),
+            chunk_size: self.chunk_size,
+            nonce_prefix: BASE64.encode(nonce_prefix),
+            kek_fingerprint: Some(kek_fingerprint(kek)),
+        };
+        let header_bytes = serde_json::to_vec(&header)?;
+        let mut preamble = STREAM_MAGIC.to_vec();
+        preamble.extend_from_slice(&frame_len(header_bytes.len())?);
+        preamble.extend_from_slice(&header_bytes);
+
+        let sealer = StreamSealer {
+            header,
+            cipher: ChunkCipher::new(self.algorithm, &dek, nonce_prefix, header_bytes)?,
+            chunks: 0,
+        };
+        Ok((sealer, preamble))
+    }
+
+    /// Decrypt a stream produced by [`encrypt_stream`](Self::encrypt_stream)
+    ///
+    /// Plaintext is written chunk by chunk as each one authenticates, so on
+    /// error `writer` may already hold a prefix of the data and the caller
+    /// should discard it. Returns the number of plaintext bytes written.
+    ///
+    /// # Errors
+    /// `VioletError::StreamTruncated` if the input ends before the footer, and
+    /// `VioletError::ChunkCountMismatch` if the footer's count is wrong.
+    pub fn decrypt_stream<R: Read, W: Write>(&self, mut reader: R, mut writer: W, kek: &[u8]) -> Result<u64> {
+        let (header, header_bytes) = read_header(&mut reader)?;
+        let opener = header_cipher(&header, header_bytes, kek)?;
+
+        let max_frame = header.chunk_size + GCM_TAG_SIZE;
+        let mut chunks: u64 = 0;
+        let mut written: u64 = 0;
+        loop {
+            let Some((kind, ciphertext)) = read_frame(&mut reader, max_frame)? else {
+                return Err(VioletError::StreamTruncated(format!(
+                    "no footer after {} chunks",
+                    chunks
+                )));
+            };
+
+            match kind {
+                FRAME_DATA => {
+                    let index = u32::try_from(chunks)
+                        .map_err(|_| VioletError::InvalidEnvelope("Too many chunks in stream".into()))?;
+                    let plaintext = opener.open(index, FRAME_DATA, &ciphertext)?;
+                    writer.write_all(&plaintext)?;
+                    written += plaintext.len() as u64;
+                    chunks += 1;
+                }
+                FRAME_FOOTER => {
+                    check_footer(opener.open(0, FRAME_FOOTER, &ciphertext)?, chunks)?;
+                    if reader.read(&mut [0u8; 1])? != 0 {
+                        return Err(VioletError::InvalidEnvelope("Data after stream footer".into()));
+                    }
+                    writer.flush()?;
+                    return Ok(written);
+                }
+                other => {
+                    return Err(VioletError::InvalidEnvelope(format!("Unknown stream frame kind {}", other)));
+                }
+            }
+        }
+    }
+}
+
+/// Encrypts a stream one chunk at a time, for callers that are handed
+/// plaintext in pieces rather than through a `Read`
+///
+/// Created by [`StreamEncryptor::sealer`]. The opening bytes, each frame from
+/// [`seal_chunk`](Self::seal_chunk) and the footer from
+/// [`finish`](Self::finish) concatenate to the format written by
+/// [`StreamEncryptor::encrypt_stream`].
+pub struct StreamSealer {
+    header: StreamHeader,
+    cipher: ChunkCipher,
+    chunks: u64,
+}
+
+impl StreamSealer {
+    pub fn header(&self) -> &StreamHeader {
+        &self.header
+    }
+
+    /// Seal one chunk of 1 to `chunk_size` bytes, returning its frame
+    ///
+    /// Every chunk but the last should be full, as `encrypt_stream` writes
+    /// them, though decryption accepts short chunks anywhere.
+    pub fn seal_chunk(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        if plaintext.is_empty() || plaintext.len() > self.header.chunk_size {
+            return Err(VioletError::EncryptionFailed(format!(
+                "Chunk of {} bytes; expected 1 to {}",
+                plaintext.len(),
+                self.header.chunk_size
+            )));
+        }
+        let index = u32::try_from(self.chunks)
+            .map_err(|_| VioletError::EncryptionFailed("Too many chunks in stream".into()))?;
+        let frame = encode_frame(FRAME_DATA, &self.cipher.seal(index, FRAME_DATA, plaintext)?)?;
+        self.chunks += 1;
+        Ok(frame)
+    }
+
+    /// End the stream, returning the footer frame and the number of data chunks
+    pub fn finish(self) -> Result<(Vec<u8>, u64)> {
+        let footer = encode_frame(FRAME_FOOTER, &self.cipher.seal(0, FRAME_FOOTER, &self.chunks.to_be_bytes())?)?;
+        Ok((footer, self.chunks))
+    }
+}
+
+/// Decrypts a stream pushed to it in pieces of any size, for callers that
+/// cannot hand over a `Read`
+///
+/// [`push`](Self::push) input until [`header`](Self::header) returns the
+/// header, [`unlock`](Self::unlock) it with the KEK the header names, then
+/// take plaintext from [`next_chunk`](Self::next_chunk) after each push. At
+/// the end of input, [`finish`](Self::finish) checks that the footer was seen.
+/// Only input up to the end of the next frame is kept, so memory use is
+/// bounded by the chunk size plus the largest piece pushed.
+#[derive(Default)]
+pub struct StreamOpener {
+    buffer: Vec<u8>,
+    header: Option<(StreamHeader, Vec<u8>)>,
+    cipher: Option<ChunkCipher>,
+    chunks: u64,
+    written: u64,
+    finished: bool,
+}
+
+impl StreamOpener {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The stream header, or `None` until enough input has been pushed to parse it
+    pub fn header(&mut self) -> Result<Option<&StreamHeader>> {
+        if self.header.is_none() {
+            if self.buffer.len() >= STREAM_MAGIC.len() && &self.buffer[..STREAM_MAGIC.len()] != STREAM_MAGIC {
+                return Err(VioletError::InvalidEnvelope("Not an encrypted stream".into()));
+            }
+            if self.buffer.len() < 8 {
+                return Ok(None);
+            }
+            let len = u32::from_be_bytes(self.buffer[4..8].try_into().expect("4 bytes")) as usize;
+            if len > MAX_HEADER_SIZE {
+                return Err(VioletError::InvalidEnvelope(format!("Stream header too large: {} bytes", len)));
+            }
+            if self.buffer.len() < 8 + len {
+                return Ok(None);
+            }
+
+            let header_bytes: Vec<u8> = self.buffer.drain(..8 + len).skip(8).collect();
+            self.header = Some((parse_header(&header_bytes)?, header_bytes));
+        }
+        Ok(self.header.as_ref().map(|(header, _)| header))
+    }
+
+    /// Supply the KEK for the stream, once its header has been parsed
+    pub fn unlock(&mut self, kek: &[u8]) -> Result<()> {
+        let Some((header, header_bytes)) = &self.header else {
+            return Err(VioletError::InvalidEnvelope("Stream header has not been read".into()));
+        };
+        self.cipher = Some(header_cipher(header, header_bytes.clone(), kek)?);
+        Ok(())
+    }
+
+    /// Plaintext of the next data chunk, or `None` if more input is needed or
+    /// the footer has been read (see [`is_finished`](Self::is_finished))
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        let (Some((header, _)), Some(cipher)) = (&self.header, &self.cipher) else {
+            return Err(VioletError::InvalidEnvelope("Stream has not been unlocked".into()));
+        };
+        if self.finished {
+            if !self.buffer.is_empty() {
+                return Err(VioletError::InvalidEnvelope("Data after stream footer".into()));
+            }
+            return Ok(None);
+        }
+        if self.buffer.len() < 5 {
+            return Ok(None);
+        }
+
+        let kind = self.buffer[0];
+        let len = u32::from_be_bytes(self.buffer[1..5].try_into().expect("4 bytes")) as usize;
+        if len > header.chunk_size + GCM_TAG_SIZE {
+            return Err(VioletError::InvalidEnvelope(format!(
+                "Stream frame of {} bytes exceeds chunk size",
+                len
+            )));
+        }
+        if self.buffer.len() < 5 + len {
+            return Ok(None);
+        }
+        let ciphertext: Vec<u8> = self.buffer.drain(..5 + len).skip(5).collect();
+
+        match kind {
+            FRAME_DATA => {
+                let index = u32::try_from(self.chunks)
+                    .map_err(|_| VioletError::InvalidEnvelope("Too many chunks in stream".into()))?;
+                let plaintext = cipher.open(index, FRAME_DATA, &ciphertext)?;
+                self.chunks += 1;
+                self.written += plaintext.len() as u64;
+                Ok(Some(plaintext))
+            }
+            FRAME_FOOTER => {
+                check_footer(cipher.open(0, FRAME_FOOTER, &ciphertext)?, self.chunks)?;
+                self.finished = true;
+                if !self.buffer.is_empty() {
+                    return Err(VioletError::InvalidEnvelope("Data after stream footer".into()));
+                }
+                Ok(None)
+            }
+            other => Err(VioletError::InvalidEnvelope(format!("Unknown stream frame kind {}", other))),
+        }
+    }
+
+    /// Whether the footer has been read and authenticated
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Number of data chunks decrypted so far
+    pub fn chunks(&self) -> u64 {
+        self.chunks
+    }
+
+    /// Check, at the end of input, that the stream was complete
+    ///
+    /// Returns the number of plaintext bytes produced.
+    ///
+    /// # Errors
+    /// `VioletError::StreamTruncated` if input ended before the footer.
+    pub fn finish(self) -> Result<u64> {
+        if self.header.is_none() {
+            return Err(VioletError::StreamTruncated("input ended inside header".into()));
+        }
+        if !self.finished {
+            return Err(VioletError::StreamTruncated(format!("no footer after {} chunks", self.chunks)));
+        }
+        Ok(self.written)
+    }
+}
+
+fn check_chunk_size(chunk_size: usize) -> Result<()> {
+    if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+        return Err(VioletError::InvalidChunkSize(chunk_size));
+    }
+    Ok(())
+}
+
+/// Seals and opens chunks with the DEK, binding each to the stream header
+struct ChunkCipher {
+    algorithm: Algorithm,
+    dek: Vec<u8>,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    header: Vec<u8>,
+}
+
+impl ChunkCipher {
+    fn new(algorithm: Algorithm, dek: &[u8], nonce_prefix: [u8; NONCE_PREFIX_SIZE], header: Vec<u8>) -> Result<Self> {
+        if dek.len() != DEK_SIZE {
+            return Err(VioletError::InvalidKeySize(dek.len()));
+        }
+        Ok(Self {
+            algorithm,
+            dek: dek.to_vec(),
+            nonce_prefix,
+            header,
+        })
+    }
+
+    fn nonce(&self, index: u32, kind: u8) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_SIZE].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_SIZE..11].copy_from_slice(&index.to_be_bytes());
+        nonce[11] = kind;
+        nonce
+    }
+
+    fn seal(&self, index: u32, kind: u8, plaintext: &[u8]) -> Result<Vec<u8>> {
+        aead::seal(self.algorithm, &self.dek, &self.nonce(index, kind), plaintext, &self.header)
+    }
+
+    fn open(&self, index: u32, kind: u8, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        aead::open(self.algorithm, &self.dek, &self.nonce(index, kind), ciphertext, &self.header).map_err(|e| match e {
+            VioletError::DecryptionFailed(reason) => VioletError::DecryptionFailed(format!("chunk {}: {}", index, reason)),
+            other => other,
+        })
+    }
+}
+
+fn frame_len(len: usize) -> Result<[u8; 4]> {
+    u32::try_from(len)
+        .map(u32::to_be_bytes)
+        .map_err(|_| VioletError::EncryptionFailed("Frame too large".into()))
+}
+
+fn encode_frame(kind: u8, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let mut frame = Vec::with_capacity(STREAM_FRAME_OVERHEAD + ciphertext.len());
+    frame.push(kind);
+    frame.extend_from_slice(&frame_len(ciphertext.len())?);
+    frame.extend_from_slice(ciphertext);
+    Ok(frame)
+}
+
+/// Parse and validate a serialized stream header
+fn parse_header(header_bytes: &[u8]) -> Result<StreamHeader> {
+    let header: StreamHeader = serde_json::from_slice(header_bytes)?;
+    if header.version != STREAM_VERSION {
+        return Err(VioletError::UnsupportedVersion(header.version));
+    }
+    check_chunk_size(header.chunk_size)?;
+    Ok(header)
+}
+
+/// Check `kek` against the header and unwrap the stream's DEK with it
+fn header_cipher(header: &StreamHeader, header_bytes: Vec<u8>, kek: &[u8]) -> Result<ChunkCipher> {
+    if let Some(expected) = &header.kek_fingerprint {
+        let actual = kek_fingerprint(kek);
+        if *expected != actual {
+            return Err(VioletError::KekFingerprintMismatch {
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+
+    let algorithm = Algorithm::from_str(&header.algorithm)?;
+    let dek = LocalKekWrapper::derived(kek)?.unwrap_dek(&BASE64.decode(&header.encrypted_key)?)?;
+    let nonce_prefix: [u8; NONCE_PREFIX_SIZE] = BASE64
+        .decode(&header.nonce_prefix)?
+        .try_into()
+        .map_err(|_| VioletError::InvalidEnvelope("Invalid stream nonce prefix".into()))?;
+    ChunkCipher::new(algorithm, &dek, nonce_prefix, header_bytes)
+}
+
+/// Compare the chunk count sealed in the footer with the chunks seen
+fn check_footer(footer: Vec<u8>, chunks: u64) -> Result<()> {
+    let count: [u8; 8] = footer
+        .try_into()
+        .map_err(|_| VioletError::InvalidEnvelope("Invalid stream footer".into()))?;
+    let expected = u64::from_be_bytes(count);
+    if expected != chunks {
+        return Err(VioletError::ChunkCountMismatch {
+            expected,
+            actual: chunks,
+        });
+    }
+    Ok(())
+}
+
+fn read_header<R: Read>(reader: &mut R) -> Result<(StreamHeader, Vec<u8>)> {
+    let mut magic = [0u8; 4];
+    read_exact(reader, &mut magic, "header")?;
+    if &magic != STREAM_MAGIC {
+        return Err(VioletError::InvalidEnvelope("Not an encrypted stream".into()));
+    }
+
+    let mut len = [0u8; 4];
+    read_exact(reader, &mut len, "header")?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_HEADER_SIZE {
+        return Err(VioletError::InvalidEnvelope(format!("Stream header too large: {} bytes", len)));
+    }
+
+    let mut header_bytes = vec![0u8; len];
+    read_exact(reader, &mut header_bytes, "header")?;
+    Ok((parse_header(&header_bytes)?, header_bytes))
+}
+
+/// Read the next frame, or `None` at a clean end of input
+fn read_frame<R: Read>(reader: &mut R, max_len: usize) -> Result<Option<(u8, Vec<u8>)>> {
+    let mut kind = [0u8; 1];
+    if read_full(reader, &mut kind)? == 0 {
+        return Ok(None);
+    }
+
+    let mut len = [0u8; 4];
+    read_exact(reader, &mut len, "frame length")?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(VioletError::InvalidEnvelope(format!(
+            "Stream frame of {} bytes exceeds chunk size",
+            len
+        )));
+    }
+
+    let mut ciphertext = vec![0u8; len];
+    read_exact(reader, &mut ciphertext, "chunk")?;
+    Ok(Some((kind[0], ciphertext)))
+}
+
+/// `read_exact` that reports a short read as a truncated stream
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8], what: &str) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => VioletError::StreamTruncated(format!("input ended inside {}", what)),
+        _ => VioletError::Io(e),
+    })
+}
+
+/// Fill `buf` as far as possible, returning fewer bytes only at end of input
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEK: [u8; 32] = [7u8; 32];
+
+    fn encrypt(plaintext: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        StreamEncryptor::new(Algorithm::default())
+            .with_chunk_size(chunk_size)
+            .unwrap()
+            .encrypt_stream(plaintext, &mut out, &KEK, "stream-key".to_string())
+            .unwrap();
+        out
+    }
+
+    fn decrypt(stream: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        StreamEncryptor::new(Algorithm::default()).decrypt_stream(stream, &mut out, &KEK)?;
+        Ok(out)
+    }
+
+    /// Byte offsets at which each frame starts, followed by the stream length
+    fn frame_offsets(stream: &[u8]) -> Vec<usize> {
+        let header_len = u32::from_be_bytes(stream[4..8].try_into().unwrap()) as usize;
+        let mut offsets = vec![8 + header_len];
+        while *offsets.last().unwrap() < stream.len() {
+            let at = *offsets.last().unwrap();
+            let len = u32::from_be_bytes(stream[at + 1..at + 5].try_into().unwrap()) as usize;
+            offsets.push(at + 5 + len);
+        }
+        offsets
+    }
+
+    #[test]
+    fn test_roundtrip_across_chunk_boundaries() {
+        for len in [0, 1, 1023, 1024, 1025, 5000] {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            assert_eq!(decrypt(&encrypt(&plaintext, 1024)).unwrap(), plaintext, "len {}", len);
+        }
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn test_gcm_siv_roundtrip() {
+        let plaintext = vec![0x5au8; 3000];
+        let encryptor = StreamEncryptor::new(Algorithm::Aes256GcmSiv).with_chunk_size(1024).unwrap();
+
+        let mut stream = Vec::new();
+        assert_eq!(encryptor.encrypt_stream(&plaintext[..], &mut stream, &KEK, "k".into()).unwrap(), 3);
+
+        let mut out = Vec::new();
+        assert_eq!(encryptor.decrypt_stream(&stream[..], &mut out, &KEK).unwrap(), 3000);
+        assert_eq!(out, plaintext);
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_chunk_size_bounds() {
+        let encryptor = || StreamEncryptor::new(Algorithm::Aes256Gcm);
+        assert!(matches!(encryptor().with_chunk_size(0), Err(VioletError::InvalidChunkSize(0))));
+        assert!(matches!(
+            encryptor().with_chunk_size(MIN_CHUNK_SIZE - 1),
+            Err(VioletError::InvalidChunkSize(_))
+        ));
+        assert!(matches!(
+            encryptor().with_chunk_size(MAX_CHUNK_SIZE + 1),
+            Err(VioletError::InvalidChunkSize(_))
+        ));
+        assert!(encryptor().with_chunk_size(MIN_CHUNK_SIZE).is_ok());
+        assert!(encryptor().with_chunk_size(MAX_CHUNK_SIZE).is_ok());
+    }
+
+    #[test]
+    fn test_truncation_at_chunk_boundary_detected() {
+        let stream = encrypt(&[1u8; 4096], 1024);
+        let offsets = frame_offsets(&stream);
+        assert_eq!(offsets.len(), 6); // four data chunks, the footer, then the end
+
+        // Dropping the footer, or the footer and trailing chunks
+        for &cut in &offsets[..5] {
+            assert!(
+                matches!(decrypt(&stream[..cut]), Err(VioletError::StreamTruncated(_))),
+                "cut at {}",
+                cut
+            );
+        }
+    }
+
+    #[test]
+    fn test_truncation_mid_chunk_detected() {
+        let stream = encrypt(&[2u8; 4096], 1024);
+        let offsets = frame_offsets(&stream);
+
+        for cut in [offsets[1] + 1, offsets[2] + 3, offsets[2] + 100, stream.len() - 1] {
+            assert!(
+                matches!(decrypt(&stream[..cut]), Err(VioletError::StreamTruncated(_))),
+                "cut at {}",
+                cut
+            );
+        }
+    }
+
+    #[test]
+    fn test_dropped_trailing_chunks_with_footer_kept_detected() {
+        let stream = encrypt(&[3u8; 4096], 1024);
+        let offsets = frame_offsets(&stream);
+
+        // Keep chunks 0 and 1, then splice the footer straight after them
+        let mut spliced = stream[..offsets[2]].to_vec();
+        spliced.extend_from_slice(&stream[offsets[4]..]);
+
+        assert!(matches!(
+            decrypt(&spliced),
+            Err(VioletError::ChunkCountMismatch { expected: 4, actual: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_reordered_chunks_rejected() {
+        let stream = encrypt(&[4u8; 2048], 1024);
+        let offsets = frame_offsets(&stream);
+
+        let mut swapped = stream[..offsets[0]].to_vec();
+        swapped.extend_from_slice(&stream[offsets[1]..offsets[2]]);
+        swapped.extend_from_slice(&stream[offsets[0]..offsets[1]]);
+        swapped.extend_from_slice(&stream[offsets[2]..]);
+
+        assert!(matches!(decrypt(&swapped), Err(VioletError::DecryptionFailed(_))));
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_wrong_kek_rejected() {
+        let stream = encrypt(b"secret", 1024);
+        let result = StreamEncryptor::new(Algorithm::Aes256Gcm).decrypt_stream(&stream[..], Vec::new(), &[8u8; 32]);
+        assert!(matches!(result, Err(VioletError::KekFingerprintMismatch { .. })));
+    }
+
+    /// Decrypt `stream` with a `StreamOpener`, pushing it `piece` bytes at a time
+    fn open_in_pieces(stream: &[u8], piece: usize) -> Result<Vec<u8>> {
+        let mut opener = StreamOpener::new();
+        let mut unlocked = false;
+        let mut out = Vec::new();
+        for data in stream.chunks(piece) {
+            opener.push(data);
+            if !unlocked {
+                if opener.header()?.is_none() {
+                    continue;
+                }
+                opener.unlock(&KEK)?;
+                unlocked = true;
+            }
+            while let Some(plaintext) = opener.next_chunk()? {
+                out.extend_from_slice(&plaintext);
+            }
+        }
+        assert_eq!(opener.finish()?, out.len() as u64);
+        Ok(out)
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_sealer_matches_encrypt_stream_format() {
+        let plaintext: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        let (mut sealer, mut stream) = StreamEncryptor::new(Algorithm::Aes256Gcm)
+            .with_chunk_size(1024)
+            .unwrap()
+            .sealer(&KEK, "stream-key".to_string())
+            .unwrap();
+        for chunk in plaintext.chunks(1024) {
+            stream.extend_from_slice(&sealer.seal_chunk(chunk).unwrap());
+        }
+        let (footer, chunks) = sealer.finish().unwrap();
+        stream.extend_from_slice(&footer);
+
+        assert_eq!(chunks, 5);
+        assert_eq!(decrypt(&stream).unwrap(), plaintext);
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_sealer_rejects_bad_chunk_lengths() {
+        let (mut sealer, _) = StreamEncryptor::new(Algorithm::Aes256Gcm)
+            .with_chunk_size(1024)
+            .unwrap()
+            .sealer(&KEK, "k".into())
+            .unwrap();
+        assert!(sealer.seal_chunk(&[]).is_err());
+        assert!(sealer.seal_chunk(&[0u8; 1025]).is_err());
+        assert!(sealer.seal_chunk(&[0u8; 1024]).is_ok());
+    }
+
+    #[test]
+    fn test_opener_accepts_any_split() {
+        let plaintext: Vec<u8> = (0..4500).map(|i| (i * 7) as u8).collect();
+        let stream = encrypt(&plaintext, 1024);
+        for piece in [1, 5, 8, 100, 1029, stream.len()] {
+            assert_eq!(open_in_pieces(&stream, piece).unwrap(), plaintext, "piece {}", piece);
+        }
+    }
+
+    #[test]
+    fn test_opener_detects_truncation_and_trailing_data() {
+        let stream = encrypt(&[5u8; 3000], 1024);
+        let offsets = frame_offsets(&stream);
+
+        assert!(matches!(open_in_pieces(&stream[..4], 64), Err(VioletError::StreamTruncated(_))));
+        assert!(matches!(
+            open_in_pieces(&stream[..offsets[3]], 64),
+            Err(VioletError::StreamTruncated(_))
+        ));
+
+        let mut trailing = stream.clone();
+        trailing.push(0);
+        assert!(matches!(open_in_pieces(&trailing, 64), Err(VioletError::InvalidEnvelope(_))));
+        assert!(matches!(open_in_pieces(b"nope, not a stream", 64), Err(VioletError::InvalidEnvelope(_))));
+    }
+
+    #[test]
+    fn test_trailing_data_rejected() {
+        let mut stream = encrypt(b"secret", 1024);
+        stream.push(0);
+        assert!(matches!(decrypt(&stream), Err(VioletError::InvalidEnvelope(_))));
+    }
+}
@@ -10,6 +10,28 @@ pub enum Algorithm {
 }
 
 impl Algorithm {
+    /// Every algorithm enabled in this build, default first
+    pub fn all() -> &'static [Algorithm] {
+        &[
+            #[cfg(feature = "aes-gcm")]
+            Algorithm::Aes256Gcm,
+            #[cfg(feature = "aes-gcm-siv")]
+            Algorithm::Aes256GcmSiv,
+        ]
+    }
+
+    /// Whether the algorithm's cargo feature is enabled
+    ///
+    /// Disabled algorithms still parse, so their envelopes can be inspected,
+    /// but encrypting or decrypting with them fails with
+    /// `VioletError::UnsupportedAlgorithm`.
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            Algorithm::Aes256Gcm => cfg!(feature = "aes-gcm"),
+            Algorithm::Aes256GcmSiv => cfg!(feature = "aes-gcm-siv"),
+        }
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
             Algorithm::Aes256Gcm => "AES-256-GCM",
@@ -17,6 +39,7 @@ impl Algorithm {
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<Self> {
         match s {
             "AES-256-GCM" => Ok(Algorithm::Aes256Gcm),
@@ -24,11 +47,28 @@ impl Algorithm {
             _ => Err(VioletError::InvalidAlgorithm(s.to_string())),
         }
     }
+
+    /// Whether a repeated nonce only leaks plaintext equality rather than
+    /// breaking confidentiality and authenticity
+    pub fn is_nonce_misuse_resistant(&self) -> bool {
+        matches!(self, Algorithm::Aes256GcmSiv)
+    }
+
+    /// One-line summary for listings
+    pub fn description(&self) -> &'static str {
+        match self {
+            Algorithm::Aes256Gcm => "AES-256 in Galois/Counter Mode; fastest, but a repeated nonce is catastrophic",
+            Algorithm::Aes256GcmSiv => {
+                "AES-256-GCM-SIV; a repeated nonce only reveals whether two plaintexts are equal"
+            }
+        }
+    }
 }
 
+/// AES-256-GCM, or the first enabled algorithm in builds without it
 impl Default for Algorithm {
     fn default() -> Self {
-        Algorithm::Aes256Gcm
+        Algorithm::all()[0]
     }
 }
 
@@ -37,6 +77,7 @@ pub const DEK_SIZE: usize = 32; // 256 bits
 pub const GCM_NONCE_SIZE: usize = 12; // 96 bits (recommended)
 pub const GCM_SIV_NONCE_SIZE: usize = 12; // 96 bits
 pub const GCM_TAG_SIZE: usize = 16; // 128 bits
+pub const X25519_KEY_SIZE: usize = 32; // public and private keys alike
 
 #[cfg(test)]
 mod tests {
@@ -55,8 +96,40 @@ mod tests {
         assert!(Algorithm::from_str("INVALID").is_err());
     }
 
+    #[test]
+    fn test_nonce_misuse_resistance() {
+        assert!(!Algorithm::Aes256Gcm.is_nonce_misuse_resistant());
+        assert!(Algorithm::Aes256GcmSiv.is_nonce_misuse_resistant());
+    }
+
+    #[test]
+    fn test_all_roundtrips_through_str() {
+        assert!(!Algorithm::all().is_empty());
+        assert_eq!(Algorithm::all()[0], Algorithm::default());
+        for algorithm in Algorithm::all() {
+            assert_eq!(Algorithm::from_str(algorithm.as_str()).unwrap(), *algorithm);
+            assert!(!algorithm.description().is_empty());
+        }
+    }
+
+    #[cfg(feature = "aes-gcm")]
     #[test]
     fn test_algorithm_default() {
         assert_eq!(Algorithm::default(), Algorithm::Aes256Gcm);
     }
+
+    #[cfg(not(feature = "aes-gcm"))]
+    #[test]
+    fn test_algorithm_default_without_gcm() {
+        assert_eq!(Algorithm::default(), Algorithm::Aes256GcmSiv);
+        assert!(!Algorithm::Aes256Gcm.is_enabled());
+    }
+
+    #[test]
+    fn test_all_lists_enabled_algorithms() {
+        for algorithm in [Algorithm::Aes256Gcm, Algorithm::Aes256GcmSiv] {
+            assert_eq!(Algorithm::all().contains(&algorithm), algorithm.is_enabled(), "{:?}", algorithm);
+        }
+        assert_eq!(Algorithm::Aes256GcmSiv.is_enabled(), cfg!(feature = "aes-gcm-siv"));
+    }
 }
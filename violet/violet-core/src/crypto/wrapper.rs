@@ -0,0 +1,152 @@
+use crate::crypto::aes_gcm;
+use crate::crypto::kdf::derive_wrapping_key;
+use crate::crypto::types::{DEK_SIZE, GCM_NONCE_SIZE, GCM_TAG_SIZE};
+use crate::error::{Result, VioletError};
+use crate::secret::SecretKey;
+
+/// Size of a DEK wrapped by [`LocalKekWrapper`]: nonce, encrypted DEK and tag
+pub const WRAPPED_DEK_SIZE: usize = GCM_NONCE_SIZE + DEK_SIZE + GCM_TAG_SIZE;
+
+/// Protects data encryption keys (DEKs) under a key encryption key (KEK)
+///
+/// The default implementation, [`LocalKekWrapper`], wraps DEKs locally with
+/// raw KEK bytes. Backends that never export key material (Vault transit,
+/// KMS) implement this trait to wrap and unwrap DEKs remotely instead.
+pub trait DekWrapper {
+    /// Wrap a DEK, returning the bytes stored in the envelope's `encryptedKey`
+    fn wrap_dek(&self, dek: &[u8]) -> Result<Vec<u8>>;
+
+    /// Recover a DEK from the bytes stored in the envelope's `encryptedKey`
+    fn unwrap_dek(&self, wrapped_dek: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Wraps DEKs with AES-256-GCM under a local 32-byte KEK
+///
+/// The wrapped form is `nonce || ciphertext || tag`, so it carries everything
+/// needed to decrypt the DEK without additional envelope fields. It is always
+/// [`WRAPPED_DEK_SIZE`] bytes; anything else is rejected before decryption.
+///
+/// The KEK is held in a [`SecretKey`], so it is zeroed when the wrapper is dropped.
+pub struct LocalKekWrapper {
+    kek: SecretKey,
+}
+
+impl LocalKekWrapper {
+    pub fn new(kek: &[u8]) -> Result<Self> {
+        if kek.len() != DEK_SIZE {
+            return Err(VioletError::InvalidKeySize(kek.len()));
+        }
+        Ok(Self { kek: SecretKey::new(kek) })
+    }
+
+    /// Wrap DEKs under a key derived from `kek` rather than `kek` itself
+    ///
+    /// This is how version 2 envelopes are wrapped; see [`derive_wrapping_key`].
+    pub fn derived(kek: &[u8]) -> Result<Self> {
+        Ok(Self {
+            kek: derive_wrapping_key(kek)?,
+        })
+    }
+}
+
+impl DekWrapper for LocalKekWrapper {
+    fn wrap_dek(&self, dek: &[u8]) -> Result<Vec<u8>> {
+        if dek.len() != DEK_SIZE {
+            return Err(VioletError::InvalidKeySize(dek.len()));
+        }
+        let (encrypted_dek, dek_iv, dek_tag) = aes_gcm::encrypt(dek, &self.kek)?;
+
+        let mut dek_package = Vec::with_capacity(dek_iv.len() + encrypted_dek.len() + dek_tag.len());
+        dek_package.extend_from_slice(&dek_iv);
+        dek_package.extend_from_slice(&encrypted_dek);
+        dek_package.extend_from_slice(&dek_tag);
+        Ok(dek_package)
+    }
+
+    fn unwrap_dek(&self, wrapped_dek: &[u8]) -> Result<Vec<u8>> {
+        if wrapped_dek.len() != WRAPPED_DEK_SIZE {
+            return Err(VioletError::CryptoError(format!(
+                "Invalid encrypted DEK length: {} bytes (expected {})",
+                wrapped_dek.len(),
+                WRAPPED_DEK_SIZE
+            )));
+        }
+
+        let (dek_nonce, rest) = wrapped_dek.split_at(GCM_NONCE_SIZE);
+        let (dek_ciphertext, dek_tag) = rest.split_at(DEK_SIZE);
+
+        aes_gcm::decrypt(dek_ciphertext, &self.kek, dek_nonce, dek_tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_wrap_unwrap_roundtrip() {
+        let wrapper = LocalKekWrapper::new(&[3u8; 32]).unwrap();
+        let dek = [9u8; 32];
+
+        let wrapped = wrapper.wrap_dek(&dek).unwrap();
+        assert_eq!(wrapped.len(), WRAPPED_DEK_SIZE);
+        assert_eq!(wrapper.unwrap_dek(&wrapped).unwrap(), dek);
+    }
+
+    #[test]
+    fn test_local_unwrap_truncated() {
+        let wrapper = LocalKekWrapper::new(&[3u8; 32]).unwrap();
+        let result = wrapper.unwrap_dek(&[0u8; 10]);
+        assert!(matches!(result, Err(VioletError::CryptoError(_))));
+    }
+
+    #[test]
+    fn test_local_unwrap_requires_exact_length() {
+        let wrapper = LocalKekWrapper::new(&[3u8; 32]).unwrap();
+        let wrapped = wrapper.wrap_dek(&[9u8; 32]).unwrap();
+
+        for len in [27, 28, 59, 61] {
+            let mut package = wrapped.clone();
+            package.resize(len, 0);
+            match wrapper.unwrap_dek(&package) {
+                Err(VioletError::CryptoError(message)) => {
+                    assert!(message.contains(&format!("{} bytes (expected 60)", len)), "{}", message)
+                }
+                other => panic!("{}-byte package: expected a length error, got {:?}", len, other),
+            }
+        }
+
+        assert_eq!(wrapped.len(), 60);
+        assert_eq!(wrapper.unwrap_dek(&wrapped).unwrap(), [9u8; 32]);
+
+        // Right length but not a real package reaches the cipher and fails there
+        let result = wrapper.unwrap_dek(&[0u8; 60]);
+        assert!(matches!(result, Err(VioletError::DecryptionFailed(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_local_wrap_rejects_wrong_dek_size() {
+        let wrapper = LocalKekWrapper::new(&[3u8; 32]).unwrap();
+        assert!(matches!(wrapper.wrap_dek(&[9u8; 16]), Err(VioletError::InvalidKeySize(16))));
+    }
+
+    #[test]
+    fn test_derived_wrapper_uses_separate_key() {
+        let kek = [3u8; 32];
+        let dek = [9u8; 32];
+        let derived = LocalKekWrapper::derived(&kek).unwrap();
+        let raw = LocalKekWrapper::new(&kek).unwrap();
+
+        let wrapped = derived.wrap_dek(&dek).unwrap();
+        assert_eq!(derived.unwrap_dek(&wrapped).unwrap(), dek);
+        assert!(raw.unwrap_dek(&wrapped).is_err());
+    }
+
+    #[test]
+    fn test_local_wrapper_invalid_kek_size() {
+        assert!(matches!(
+            LocalKekWrapper::new(&[0u8; 16]),
+            Err(VioletError::InvalidKeySize(16))
+        ));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::crypto::types::{DEK_SIZE, X25519_KEY_SIZE};
+use crate::crypto::wrapper::LocalKekWrapper;
+use crate::error::{Result, VioletError};
+use crate::secret::SecretKey;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// HKDF info string binding derived keys to X25519 DEK wrapping
+pub const X25519_WRAP_INFO: &[u8] = b"violet-x25519-dek-wrap";
+
+/// Generate a recipient keypair, returning the private and the public key
+pub fn generate_keypair() -> (SecretKey, [u8; X25519_KEY_SIZE]) {
+    let secret = StaticSecret::random_from_rng(rand::thread_rng());
+    let public = PublicKey::from(&secret);
+    (SecretKey::new(secret.as_bytes()), public.to_bytes())
+}
+
+/// Public key belonging to `private_key`
+pub fn public_key(private_key: &[u8]) -> Result<[u8; X25519_KEY_SIZE]> {
+    Ok(PublicKey::from(&static_secret(private_key)?).to_bytes())
+}
+
+/// Wrapper for a new DEK to `recipient`, with the ephemeral public key to store beside it
+pub(crate) fn sender_wrapper(recipient: &[u8]) -> Result<(LocalKekWrapper, [u8; X25519_KEY_SIZE])> {
+    let recipient = PublicKey::from(key_array(recipient)?);
+    let ephemeral = EphemeralSecret::random_from_rng(rand::thread_rng());
+    let ephemeral_public = PublicKey::from(&ephemeral);
+
+    let shared = ephemeral.diffie_hellman(&recipient);
+    if !shared.was_contributory() {
+        return Err(VioletError::KeyWrapError("X25519 public key is a low-order point".into()));
+    }
+    let wrapper = wrapper(shared.as_bytes(), &ephemeral_public, &recipient)?;
+    Ok((wrapper, ephemeral_public.to_bytes()))
+}
+
+/// Wrapper that unwraps a DEK sent to `private_key` with `ephemeral_public`
+pub(crate) fn recipient_wrapper(private_key: &[u8], ephemeral_public: &[u8]) -> Result<LocalKekWrapper> {
+    let secret = static_secret(private_key)?;
+    let ephemeral_public = PublicKey::from(key_array(ephemeral_public)?);
+
+    let shared = secret.diffie_hellman(&ephemeral_public);
+    if !shared.was_contributory() {
+        return Err(VioletError::InvalidEnvelope("ephemeralPublicKey is a low-order point".into()));
+    }
+    wrapper(shared.as_bytes(), &ephemeral_public, &PublicKey::from(&secret))
+}
+
+/// AES-256-GCM wrapper under HKDF-SHA256(shared, salt = ephemeral || recipient)
+fn wrapper(shared: &[u8], ephemeral_public: &PublicKey, recipient: &PublicKey) -> Result<LocalKekWrapper> {
+    let mut salt = [0u8; 2 * X25519_KEY_SIZE];
+    salt[..X25519_KEY_SIZE].copy_from_slice(ephemeral_public.as_bytes());
+    salt[X25519_KEY_SIZE..].copy_from_slice(recipient.as_bytes());
+
+    let mut wrapping_key = Zeroizing::new([0u8; DEK_SIZE]);
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(X25519_WRAP_INFO, &mut wrapping_key[..])
+        .map_err(|e| VioletError::CryptoError(format!("HKDF expand failed: {}", e)))?;
+    LocalKekWrapper::new(&wrapping_key[..])
+}
+
+fn static_secret(private_key: &[u8]) -> Result<StaticSecret> {
+    Ok(StaticSecret::from(key_array(private_key)?))
+}
+
+fn key_array(key: &[u8]) -> Result<[u8; X25519_KEY_SIZE]> {
+    key.try_into().map_err(|_| VioletError::InvalidKeySize(key.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::wrapper::DekWrapper;
+
+    #[test]
+    fn test_recipient_unwraps_what_sender_wrapped() {
+        let (private_key, public) = generate_keypair();
+        assert_eq!(public_key(&private_key).unwrap(), public);
+
+        let dek = [7u8; DEK_SIZE];
+        let (sender, ephemeral_public) = sender_wrapper(&public).unwrap();
+        let wrapped = sender.wrap_dek(&dek).unwrap();
+
+        let recipient = recipient_wrapper(&private_key, &ephemeral_public).unwrap();
+        assert_eq!(recipient.unwrap_dek(&wrapped).unwrap(), dek);
+
+        let (other_key, _) = generate_keypair();
+        let other = recipient_wrapper(&other_key, &ephemeral_public).unwrap();
+        assert!(other.unwrap_dek(&wrapped).is_err());
+    }
+
+    #[test]
+    fn test_each_wrap_uses_a_fresh_ephemeral_key() {
+        let (_, public) = generate_keypair();
+        let (_, first) = sender_wrapper(&public).unwrap();
+        let (_, second) = sender_wrapper(&public).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_rejects_bad_keys() {
+        assert!(matches!(sender_wrapper(&[1u8; 31]), Err(VioletError::InvalidKeySize(31))));
+        assert!(matches!(public_key(&[1u8; 33]), Err(VioletError::InvalidKeySize(33))));
+
+        // The all-zero point gives an all-zero shared secret
+        assert!(matches!(sender_wrapper(&[0u8; X25519_KEY_SIZE]), Err(VioletError::KeyWrapError(_))));
+    }
+}
@@ -26,11 +26,90 @@ pub enum VioletError {
     #[error("Invalid algorithm: {0}")]
     InvalidAlgorithm(String),
 
+    #[error("Algorithm {0} is not enabled in this build")]
+    UnsupportedAlgorithm(String),
+
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
 
+    #[error("CBOR error: {0}")]
+    CborError(String),
+
     #[error("Hex decode error: {0}")]
     HexError(#[from] hex::FromHexError),
+
+    #[error("Invalid envelope: {0}")]
+    InvalidEnvelope(String),
+
+    #[error("Key wrapping failed: {0}")]
+    KeyWrapError(String),
+
+    #[error("KEK fingerprint mismatch: envelope expects {expected}, key has {actual}")]
+    KekFingerprintMismatch { expected: String, actual: String },
+
+    #[error("Algorithm mismatch: expected {expected}, envelope uses {actual}")]
+    AlgorithmMismatch { expected: String, actual: String },
+
+    #[error("Unsupported envelope version: {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Invalid chunk size: {0} bytes")]
+    InvalidChunkSize(usize),
+
+    #[error("Stream truncated: {0}")]
+    StreamTruncated(String),
+
+    #[error("Chunk count mismatch: footer records {expected}, stream has {actual}")]
+    ChunkCountMismatch { expected: u64, actual: u64 },
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid envelope name: {0}")]
+    InvalidEnvelopeName(String),
+
+    #[error("No stored envelope named {0}")]
+    EnvelopeNotFound(String),
+
+    #[error("An envelope named {0} already exists")]
+    EnvelopeExists(String),
+
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+
+    /// Failure in a subsystem with no dedicated variant, e.g. a key provider
+    #[error("{0}")]
+    Other(String),
 }
 
 pub type Result<T> = std::result::Result<T, VioletError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    fn read_missing() -> Result<Vec<u8>> {
+        Ok(std::fs::read("/nonexistent/violet-error-test")?)
+    }
+
+    #[test]
+    fn test_io_error_propagates_with_question_mark() {
+        let err = read_missing().unwrap_err();
+        assert!(matches!(&err, VioletError::Io(e) if e.kind() == ErrorKind::NotFound));
+
+        let err: VioletError = Error::new(ErrorKind::PermissionDenied, "no access").into();
+        assert_eq!(err.to_string(), "I/O error: no access");
+    }
+
+    #[test]
+    fn test_url_error_and_other() {
+        fn parse(endpoint: &str) -> Result<url::Url> {
+            Ok(url::Url::parse(endpoint)?)
+        }
+        assert!(matches!(parse("not a url"), Err(VioletError::InvalidUrl(_))));
+        assert!(parse("https://keys.example").is_ok());
+
+        assert_eq!(VioletError::Other("provider offline".into()).to_string(), "provider offline");
+    }
+}
@@ -1,9 +1,30 @@
+#[cfg(not(any(feature = "aes-gcm", feature = "aes-gcm-siv")))]
+compile_error!("violet-core needs at least one algorithm feature: `aes-gcm` or `aes-gcm-siv`");
+
+#[cfg(any(test, feature = "bench"))]
+pub mod bench;
 pub mod crypto;
 pub mod error;
 pub mod models;
+pub mod secret;
+pub mod store;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
 
 // Re-export commonly used types
 pub use error::{Result, VioletError};
-pub use models::encryption_envelope::EncryptionEnvelope;
-pub use crypto::envelope::EnvelopeEncryptor;
+pub use models::encryption_envelope::{EncryptionEnvelope, EnvelopeReport, ENVELOPE_VERSION, LEGACY_ENVELOPE_VERSION};
+#[cfg(feature = "cbor")]
+pub use models::cbor_envelope::CborEnvelope;
+pub use models::armor::{ARMOR_BEGIN, ARMOR_END};
+pub use models::container::{is_container, CONTAINER_EXTENSION, CONTAINER_MAGIC};
+pub use crypto::envelope::{EnvelopeEncryptor, ESTIMATED_KEY_ID_LEN, PASSWORD_KEY_ID};
+pub use crypto::kdf::PasswordKdf;
+pub use crypto::stream::{
+    StreamEncryptor, StreamHeader, StreamOpener, StreamSealer, DEFAULT_CHUNK_SIZE, STREAM_FRAME_OVERHEAD, MAX_CHUNK_SIZE,
+    MIN_CHUNK_SIZE,
+};
 pub use crypto::types::Algorithm;
+pub use secret::SecretKey;
+pub use store::{EnvelopeStore, FsEnvelopeStore};
+pub use crypto::wrapper::{DekWrapper, LocalKekWrapper, WRAPPED_DEK_SIZE};
@@ -0,0 +1,113 @@
+//! ASCII-armored envelopes, for pasting into email, tickets or config files
+//!
+//! The armor is a `.violet` container (see [`container`](crate::models::container))
+//! in base64, in lines of 64 characters between a BEGIN and an END line:
+//!
+//! ```text
+//! -----BEGIN VIOLET ENVELOPE-----
+//! VkxUQwEAAAE...
+//! -----END VIOLET ENVELOPE-----
+//! ```
+
+use crate::error::{Result, VioletError};
+use crate::models::encryption_envelope::EncryptionEnvelope;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+/// First line of an armored envelope
+pub const ARMOR_BEGIN: &str = "-----BEGIN VIOLET ENVELOPE-----";
+
+/// Last line of an armored envelope
+pub const ARMOR_END: &str = "-----END VIOLET ENVELOPE-----";
+
+/// Base64 characters per line
+const LINE_LEN: usize = 64;
+
+impl EncryptionEnvelope {
+    /// Encode this envelope as ASCII armor, ending with a newline
+    pub fn to_armored(&self) -> Result<String> {
+        let encoded = BASE64.encode(self.to_container()?);
+        let mut armored = String::with_capacity(encoded.len() * (LINE_LEN + 1) / LINE_LEN + 2 * ARMOR_BEGIN.len());
+        armored.push_str(ARMOR_BEGIN);
+        armored.push('\n');
+        for line in encoded.as_bytes().chunks(LINE_LEN) {
+            // Base64 output is ASCII, so every chunk is valid UTF-8
+            armored.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+            armored.push('\n');
+        }
+        armored.push_str(ARMOR_END);
+        armored.push('\n');
+        Ok(armored)
+    }
+
+    /// Decode an envelope written by [`EncryptionEnvelope::to_armored`]
+    ///
+    /// Whitespace around the armor and `\r\n` line endings are accepted;
+    /// anything else outside the BEGIN and END lines is not.
+    ///
+    /// # Errors
+    /// `VioletError::InvalidEnvelope` if the BEGIN or END line is missing or
+    /// the body is not a base64 container.
+    pub fn from_armored(armored: &str) -> Result<Self> {
+        let body = armored
+            .trim()
+            .strip_prefix(ARMOR_BEGIN)
+            .ok_or_else(|| VioletError::InvalidEnvelope(format!("Armor must start with {}", ARMOR_BEGIN)))?
+            .strip_suffix(ARMOR_END)
+            .ok_or_else(|| VioletError::InvalidEnvelope(format!("Armor must end with {}", ARMOR_END)))?;
+
+        let encoded: String = body.split_ascii_whitespace().collect();
+        let container = BASE64
+            .decode(encoded)
+            .map_err(|e| VioletError::InvalidEnvelope(format!("Armor body is not valid base64: {}", e)))?;
+        Self::from_container(&container)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::envelope::EnvelopeEncryptor;
+    use crate::crypto::types::Algorithm;
+
+    fn envelope(plaintext: &[u8]) -> EncryptionEnvelope {
+        EnvelopeEncryptor::new(Algorithm::default())
+            .encrypt(plaintext, &[3u8; 32], "key-armor".to_string())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_armor_roundtrip() {
+        let envelope = envelope(&[0x11u8; 500]);
+        let armored = envelope.to_armored().unwrap();
+
+        let lines: Vec<_> = armored.lines().collect();
+        assert_eq!(lines[0], ARMOR_BEGIN);
+        assert_eq!(*lines.last().unwrap(), ARMOR_END);
+        assert!(lines[1..lines.len() - 1].iter().all(|line| line.len() <= LINE_LEN && line.is_ascii()));
+        assert!(armored.ends_with('\n'));
+
+        assert_eq!(EncryptionEnvelope::from_armored(&armored).unwrap(), envelope);
+        let pasted = format!("\n  {}\r\n", armored.replace('\n', "\r\n"));
+        assert_eq!(EncryptionEnvelope::from_armored(&pasted).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_invalid_armor() {
+        let armored = envelope(b"data").to_armored().unwrap();
+        let message = |text: &str| match EncryptionEnvelope::from_armored(text) {
+            Err(VioletError::InvalidEnvelope(message)) => message,
+            other => panic!("expected InvalidEnvelope, got {:?}", other),
+        };
+
+        assert!(message(&armored[1..]).contains("must start with"));
+        assert!(message(armored.trim_end().trim_end_matches('-')).contains("must end with"));
+        assert!(message(&format!("Subject: key\n{}", armored)).contains("must start with"));
+        // The body opens with base64 of the container magic, "VkxU"
+        assert!(message(&armored.replacen("\nVkxU", "\n*kxU", 1)).contains("not valid base64"));
+
+        // Lines dropped from the middle leave a truncated container
+        let mut lines: Vec<_> = armored.lines().collect();
+        lines.remove(2);
+        assert!(EncryptionEnvelope::from_armored(&lines.join("\n")).is_err());
+    }
+}
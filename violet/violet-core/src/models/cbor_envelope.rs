@@ -0,0 +1,160 @@
+use crate::crypto::kdf::PasswordKdf;
+use crate::error::{Result, VioletError};
+use crate::models::encryption_envelope::{legacy_version, EncryptionEnvelope};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+
+/// Binary form of an [`EncryptionEnvelope`] for CBOR serialization
+///
+/// Ciphertext, wrapped DEK, IV and tag are raw bytes rather than base64
+/// strings, avoiding the ~33% base64 overhead. Field names match the JSON form.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CborEnvelope {
+    #[serde(default = "legacy_version")]
+    pub version: u32,
+
+    pub key_id: String,
+
+    #[serde(with = "serde_bytes")]
+    pub encrypted_data: Vec<u8>,
+
+    #[serde(with = "serde_bytes")]
+    pub encrypted_key: Vec<u8>,
+
+    #[serde(with = "serde_bytes")]
+    pub iv: Vec<u8>,
+
+    pub algorithm: String,
+
+    #[serde(with = "serde_bytes", default)]
+    pub auth_tag: Vec<u8>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kek_fingerprint: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<PasswordKdf>,
+
+    #[serde(with = "serde_bytes", default, skip_serializing_if = "Option::is_none")]
+    pub ephemeral_public_key: Option<Vec<u8>>,
+}
+
+impl TryFrom<&EncryptionEnvelope> for CborEnvelope {
+    type Error = VioletError;
+
+    fn try_from(envelope: &EncryptionEnvelope) -> Result<Self> {
+        Ok(Self {
+            version: envelope.version,
+            key_id: envelope.key_id.clone(),
+            encrypted_data: BASE64.decode(&envelope.encrypted_data)?,
+            encrypted_key: BASE64.decode(&envelope.encrypted_key)?,
+            iv: BASE64.decode(&envelope.iv)?,
+            algorithm: envelope.algorithm.clone(),
+            auth_tag: BASE64.decode(&envelope.auth_tag)?,
+            kek_fingerprint: envelope.kek_fingerprint.clone(),
+            kdf: envelope.kdf.clone(),
+            ephemeral_public_key: envelope.ephemeral_public_key.as_ref().map(|key| BASE64.decode(key)).transpose()?,
+        })
+    }
+}
+
+impl From<&CborEnvelope> for EncryptionEnvelope {
+    fn from(envelope: &CborEnvelope) -> Self {
+        Self {
+            version: envelope.version,
+            key_id: envelope.key_id.clone(),
+            encrypted_data: BASE64.encode(&envelope.encrypted_data),
+            encrypted_key: BASE64.encode(&envelope.encrypted_key),
+            iv: BASE64.encode(&envelope.iv),
+            algorithm: envelope.algorithm.clone(),
+            auth_tag: BASE64.encode(&envelope.auth_tag),
+            kek_fingerprint: envelope.kek_fingerprint.clone(),
+            kdf: envelope.kdf.clone(),
+            ephemeral_public_key: envelope.ephemeral_public_key.as_ref().map(|key| BASE64.encode(key)),
+        }
+    }
+}
+
+impl CborEnvelope {
+    /// Serialize to CBOR bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(self, &mut bytes).map_err(|e| VioletError::CborError(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    /// Deserialize from CBOR bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        ciborium::from_reader(bytes).map_err(|e| VioletError::CborError(e.to_string()))
+    }
+}
+
+impl EncryptionEnvelope {
+    /// Encode this envelope as CBOR with binary fields
+    pub fn to_cbor(&self) -> Result<Vec<u8>> {
+        CborEnvelope::try_from(self)?.to_bytes()
+    }
+
+    /// Decode an envelope previously encoded with [`EncryptionEnvelope::to_cbor`]
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self> {
+        Ok(EncryptionEnvelope::from(&CborEnvelope::from_bytes(bytes)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::envelope::EnvelopeEncryptor;
+    use crate::crypto::types::Algorithm;
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_cbor_roundtrip() {
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .with_kek_fingerprint(true)
+            .encrypt(b"cbor roundtrip", &[4u8; 32], "key-cbor".to_string())
+            .unwrap();
+
+        let bytes = envelope.to_cbor().unwrap();
+        assert_eq!(EncryptionEnvelope::from_cbor(&bytes).unwrap(), envelope);
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn test_json_and_cbor_decrypt_to_same_plaintext() {
+        let kek = [6u8; 32];
+        let plaintext = vec![0xabu8; 4096];
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv);
+        let envelope = encryptor.encrypt(&plaintext, &kek, "key-both".to_string()).unwrap();
+
+        let json = serde_json::to_vec(&envelope).unwrap();
+        let cbor = envelope.to_cbor().unwrap();
+        assert!(cbor.len() < json.len());
+
+        let from_json: EncryptionEnvelope = serde_json::from_slice(&json).unwrap();
+        let from_cbor = EncryptionEnvelope::from_cbor(&cbor).unwrap();
+
+        assert_eq!(encryptor.decrypt(&from_json, &kek).unwrap(), plaintext);
+        assert_eq!(encryptor.decrypt(&from_cbor, &kek).unwrap(), plaintext);
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_cbor_stores_raw_bytes() {
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"raw", &[1u8; 32], "key".to_string())
+            .unwrap();
+
+        let cbor = CborEnvelope::try_from(&envelope).unwrap();
+        assert_eq!(cbor.iv.len(), 12);
+        assert_eq!(cbor.auth_tag.len(), 16);
+        assert_eq!(cbor.encrypted_data.len(), 3);
+    }
+
+    #[test]
+    fn test_invalid_cbor() {
+        let result = EncryptionEnvelope::from_cbor(b"\xff\x00not cbor");
+        assert!(matches!(result, Err(VioletError::CborError(_))));
+    }
+}
@@ -0,0 +1,187 @@
+//! Single-file `.violet` containers
+//!
+//! A container holds one envelope as a single artifact:
+//!
+//! ```text
+//! "VLTC" | version (1 byte) | header length (u32 BE) | header | ciphertext
+//! ```
+//!
+//! The header is the envelope's JSON with an empty `encryptedData`, and the
+//! ciphertext follows it as raw bytes, so large files carry no base64 overhead.
+
+use crate::error::{Result, VioletError};
+use crate::models::encryption_envelope::EncryptionEnvelope;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+/// Bytes every container starts with
+pub const CONTAINER_MAGIC: &[u8; 4] = b"VLTC";
+
+/// Current container layout version
+pub const CONTAINER_VERSION: u8 = 1;
+
+/// Extension of container files
+pub const CONTAINER_EXTENSION: &str = "violet";
+
+/// Headers are small; anything larger is corrupt
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Magic, version and header length
+const PREAMBLE_SIZE: usize = CONTAINER_MAGIC.len() + 1 + 4;
+
+/// Whether `data` starts like a container, without checking the rest
+pub fn is_container(data: &[u8]) -> bool {
+    data.starts_with(CONTAINER_MAGIC)
+}
+
+impl EncryptionEnvelope {
+    /// Encode this envelope as a `.violet` container
+    pub fn to_container(&self) -> Result<Vec<u8>> {
+        let ciphertext = BASE64.decode(&self.encrypted_data)?;
+        let header = serde_json::to_vec(&EncryptionEnvelope { encrypted_data: String::new(), ..self.clone() })?;
+        let header_len = u32::try_from(header.len())
+            .ok()
+            .filter(|&len| len as usize <= MAX_HEADER_SIZE)
+            .ok_or_else(|| VioletError::InvalidEnvelope(format!("Container header too large: {} bytes", header.len())))?;
+
+        let mut container = Vec::with_capacity(PREAMBLE_SIZE + header.len() + ciphertext.len());
+        container.extend_from_slice(CONTAINER_MAGIC);
+        container.push(CONTAINER_VERSION);
+        container.extend_from_slice(&header_len.to_be_bytes());
+        container.extend_from_slice(&header);
+        container.extend_from_slice(&ciphertext);
+        Ok(container)
+    }
+
+    /// Decode a container written by [`EncryptionEnvelope::to_container`]
+    ///
+    /// # Errors
+    /// `VioletError::InvalidEnvelope` if `bytes` is not a container, is cut
+    /// short inside its header, or the header is not an envelope with an
+    /// empty `encryptedData`.
+    pub fn from_container(bytes: &[u8]) -> Result<Self> {
+        if !is_container(bytes) {
+            return Err(VioletError::InvalidEnvelope("Not a .violet container".into()));
+        }
+        if bytes.len() < PREAMBLE_SIZE {
+            return Err(VioletError::InvalidEnvelope(format!(
+                "Container truncated: {} bytes, shorter than its {}-byte preamble",
+                bytes.len(),
+                PREAMBLE_SIZE
+            )));
+        }
+
+        let version = bytes[CONTAINER_MAGIC.len()];
+        if version != CONTAINER_VERSION {
+            return Err(VioletError::UnsupportedVersion(version.into()));
+        }
+        let mut header_len = [0u8; 4];
+        header_len.copy_from_slice(&bytes[CONTAINER_MAGIC.len() + 1..PREAMBLE_SIZE]);
+        let header_len = u32::from_be_bytes(header_len) as usize;
+        if header_len > MAX_HEADER_SIZE {
+            return Err(VioletError::InvalidEnvelope(format!("Container header too large: {} bytes", header_len)));
+        }
+        let rest = &bytes[PREAMBLE_SIZE..];
+        if rest.len() < header_len {
+            return Err(VioletError::InvalidEnvelope(format!(
+                "Container truncated: header is {} bytes, only {} present",
+                header_len,
+                rest.len()
+            )));
+        }
+
+        let (header, ciphertext) = rest.split_at(header_len);
+        let mut envelope: EncryptionEnvelope = serde_json::from_slice(header)
+            .map_err(|e| VioletError::InvalidEnvelope(format!("Corrupt container header: {}", e)))?;
+        if !envelope.encrypted_data.is_empty() {
+            return Err(VioletError::InvalidEnvelope("Container header must not hold encryptedData".into()));
+        }
+        envelope.encrypted_data = BASE64.encode(ciphertext);
+        Ok(envelope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::envelope::EnvelopeEncryptor;
+    use crate::crypto::types::Algorithm;
+
+    const KEK: [u8; 32] = [9u8; 32];
+
+    fn container(plaintext: &[u8]) -> (EncryptionEnvelope, Vec<u8>) {
+        let envelope = EnvelopeEncryptor::new(Algorithm::default())
+            .with_kek_fingerprint(true)
+            .encrypt(plaintext, &KEK, "key-container".to_string())
+            .unwrap();
+        let bytes = envelope.to_container().unwrap();
+        (envelope, bytes)
+    }
+
+    fn invalid(bytes: &[u8]) -> String {
+        match EncryptionEnvelope::from_container(bytes) {
+            Err(VioletError::InvalidEnvelope(message)) => message,
+            other => panic!("expected InvalidEnvelope, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_container_roundtrip() {
+        let plaintext = vec![0x5au8; 10_000];
+        let (envelope, bytes) = container(&plaintext);
+        assert!(is_container(&bytes));
+        assert_eq!(bytes[4], CONTAINER_VERSION);
+        // Ciphertext is stored raw: the container is smaller than the base64 alone
+        assert!(bytes.len() < envelope.encrypted_data.len());
+
+        let decoded = EncryptionEnvelope::from_container(&bytes).unwrap();
+        assert_eq!(decoded, envelope);
+        let encryptor = EnvelopeEncryptor::new(Algorithm::default());
+        assert_eq!(encryptor.decrypt(&decoded, &KEK).unwrap(), plaintext);
+
+        // An empty plaintext leaves nothing after the header
+        let (envelope, bytes) = container(b"");
+        assert_eq!(EncryptionEnvelope::from_container(&bytes).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_truncated_container() {
+        let (_, bytes) = container(b"some file contents");
+        let header_len = u32::from_be_bytes(bytes[5..9].try_into().unwrap()) as usize;
+
+        assert!(invalid(&bytes[..3]).contains("Not a .violet container"));
+        assert!(invalid(&bytes[..6]).contains("shorter than its 9-byte preamble"));
+        assert!(invalid(&bytes[..PREAMBLE_SIZE + header_len - 1]).contains("Container truncated: header is"));
+
+        // Cut inside the ciphertext: the header parses, and the tag check fails
+        let short = EncryptionEnvelope::from_container(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(EnvelopeEncryptor::new(Algorithm::default()).decrypt(&short, &KEK).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_header() {
+        let (_, bytes) = container(b"some file contents");
+
+        let mut version = bytes.clone();
+        version[4] = 7;
+        assert!(matches!(EncryptionEnvelope::from_container(&version), Err(VioletError::UnsupportedVersion(7))));
+
+        let mut huge = bytes.clone();
+        huge[5..9].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(invalid(&huge).contains("header too large"));
+
+        let mut garbled = bytes.clone();
+        garbled[PREAMBLE_SIZE] = b'[';
+        assert!(invalid(&garbled).contains("Corrupt container header"));
+
+        // A header claiming inline data is not one this crate wrote
+        let (envelope, _) = container(b"x");
+        let header = serde_json::to_vec(&envelope).unwrap();
+        let mut inline = CONTAINER_MAGIC.to_vec();
+        inline.push(CONTAINER_VERSION);
+        inline.extend_from_slice(&(header.len() as u32).to_be_bytes());
+        inline.extend_from_slice(&header);
+        assert!(invalid(&inline).contains("must not hold encryptedData"));
+
+        assert!(invalid(b"{\"keyId\":\"not a container\"}").contains("Not a .violet container"));
+    }
+}
@@ -1,4 +1,20 @@
-use serde::{Deserialize, Serialize};
+use crate::crypto::envelope::split_combined_tag;
+use crate::crypto::fingerprint::FINGERPRINT_SIZE;
+use crate::crypto::kdf::PasswordKdf;
+use crate::crypto::types::{Algorithm, GCM_NONCE_SIZE, GCM_SIV_NONCE_SIZE, GCM_TAG_SIZE, X25519_KEY_SIZE};
+use crate::error::{Result, VioletError};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::de::{self, IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+/// Envelope version written by this library: DEKs are wrapped under a key
+/// derived from the KEK with HKDF
+pub const ENVELOPE_VERSION: u32 = 2;
+
+/// Original envelope version: DEKs are wrapped directly under the KEK.
+/// Envelopes without a `version` field are treated as this version.
+pub const LEGACY_ENVELOPE_VERSION: u32 = 1;
 
 /// Represents an encrypted data package containing the ciphertext,
 /// encrypted data encryption key (DEK), and metadata needed for decryption.
@@ -7,6 +23,10 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct EncryptionEnvelope {
+    /// Envelope format version, see [`ENVELOPE_VERSION`]
+    #[serde(default = "legacy_version")]
+    pub version: u32,
+
     /// UUID of the master key (KEK) from Keys server
     pub key_id: String,
 
@@ -25,21 +45,284 @@ pub struct EncryptionEnvelope {
     /// Base64-encoded authentication tag (may be empty for some algorithms)
     #[serde(default)]
     pub auth_tag: String,
+
+    /// Optional truncated SHA-256 fingerprint of the KEK (hex), never the key itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kek_fingerprint: Option<String>,
+
+    /// Present when the KEK is derived from a password rather than fetched for
+    /// `keyId`; see [`EnvelopeEncryptor::encrypt_with_password`](crate::EnvelopeEncryptor::encrypt_with_password)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<PasswordKdf>,
+
+    /// Base64-encoded ephemeral X25519 public key, present when the DEK is
+    /// wrapped to a recipient's public key rather than under a KEK; see
+    /// [`EnvelopeEncryptor::encrypt_to_public_key`](crate::EnvelopeEncryptor::encrypt_to_public_key)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ephemeral_public_key: Option<String>,
+}
+
+/// Structural summary of an envelope, produced without any key material
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnvelopeReport {
+    pub version: u32,
+    pub key_id: String,
+    pub algorithm: Algorithm,
+
+    /// Decoded ciphertext length in bytes
+    pub encrypted_data_len: usize,
+
+    /// Decoded wrapped-DEK length in bytes
+    pub encrypted_key_len: usize,
+
+    /// Decoded IV length in bytes
+    pub iv_len: usize,
+
+    /// Decoded authentication tag length in bytes
+    pub auth_tag_len: usize,
+
+    /// Whether the tag is appended to the ciphertext rather than stored in `authTag`.
+    /// `encrypted_data_len` then excludes the tag.
+    pub combined_tag: bool,
+
+    pub kek_fingerprint: Option<String>,
+}
+
+impl EncryptionEnvelope {
+    /// Check that the envelope is structurally intact without decrypting it
+    ///
+    /// Verifies that the version and algorithm are known, every binary field is
+    /// valid base64, and the IV, tag and fingerprint have the lengths that
+    /// algorithm requires.
+    /// No cryptographic operations are performed, so no KEK is needed and a
+    /// passing report does not prove the envelope will decrypt.
+    pub fn validate_structure(&self) -> Result<EnvelopeReport> {
+        self.check_version()?;
+        if self.key_id.trim().is_empty() {
+            return Err(VioletError::InvalidEnvelope("keyId is empty".into()));
+        }
+
+        let algorithm = Algorithm::from_str(&self.algorithm)?;
+        let encrypted_data = decode_field("encryptedData", &self.encrypted_data)?;
+        let encrypted_key = decode_field("encryptedKey", &self.encrypted_key)?;
+        let iv = decode_field("iv", &self.iv)?;
+        let auth_tag = decode_field("authTag", &self.auth_tag)?;
+
+        let combined_tag = auth_tag.is_empty();
+        let (encrypted_data, auth_tag) = if combined_tag {
+            split_combined_tag(&encrypted_data)?
+        } else {
+            (encrypted_data.as_slice(), auth_tag.as_slice())
+        };
+
+        if encrypted_key.is_empty() {
+            return Err(VioletError::InvalidEnvelope("encryptedKey is empty".into()));
+        }
+
+        let expected_iv_len = match algorithm {
+            Algorithm::Aes256Gcm => GCM_NONCE_SIZE,
+            Algorithm::Aes256GcmSiv => GCM_SIV_NONCE_SIZE,
+        };
+        if iv.len() != expected_iv_len {
+            return Err(VioletError::InvalidNonceSize(iv.len()));
+        }
+        if auth_tag.len() != GCM_TAG_SIZE {
+            return Err(VioletError::InvalidTagSize(auth_tag.len()));
+        }
+
+        if let Some(kdf) = &self.kdf {
+            kdf.validate()?;
+        }
+
+        if let Some(ephemeral_public_key) = &self.ephemeral_public_key {
+            let len = decode_field("ephemeralPublicKey", ephemeral_public_key)?.len();
+            if len != X25519_KEY_SIZE {
+                return Err(VioletError::InvalidEnvelope(format!(
+                    "ephemeralPublicKey is {} bytes, not {}",
+                    len, X25519_KEY_SIZE
+                )));
+            }
+        }
+
+        if let Some(fingerprint) = &self.kek_fingerprint {
+            let valid = fingerprint.len() == FINGERPRINT_SIZE * 2
+                && fingerprint.chars().all(|c| c.is_ascii_hexdigit());
+            if !valid {
+                return Err(VioletError::InvalidEnvelope(format!(
+                    "kekFingerprint is not {} hex characters",
+                    FINGERPRINT_SIZE * 2
+                )));
+            }
+        }
+
+        Ok(EnvelopeReport {
+            version: self.version,
+            key_id: self.key_id.clone(),
+            algorithm,
+            encrypted_data_len: encrypted_data.len(),
+            encrypted_key_len: encrypted_key.len(),
+            iv_len: iv.len(),
+            auth_tag_len: auth_tag.len(),
+            combined_tag,
+            kek_fingerprint: self.kek_fingerprint.clone(),
+        })
+    }
+
+    /// Fail with `VioletError::UnsupportedVersion` unless this library can read the envelope's version
+    pub fn check_version(&self) -> Result<()> {
+        match self.version {
+            LEGACY_ENVELOPE_VERSION | ENVELOPE_VERSION => Ok(()),
+            other => Err(VioletError::UnsupportedVersion(other)),
+        }
+    }
+
+    /// Serialize as canonical JSON for byte-for-byte reproducible output
+    ///
+    /// Object keys are sorted by their UTF-8 bytes at every level, regardless
+    /// of field declaration order, and the document is indented by two spaces
+    /// and ends with a newline. The same envelope always gives the same bytes,
+    /// so committed envelopes can be diffed.
+    pub fn to_canonical_json(&self) -> Result<Vec<u8>> {
+        let value = serde_json::to_value(self)?;
+        let mut out = Vec::new();
+        write_canonical(&value, 0, &mut out)?;
+        out.push(b'\n');
+        Ok(out)
+    }
+
+    /// Read only the `keyId` of a JSON envelope, to fetch its KEK before parsing the rest
+    ///
+    /// The document is read in one pass and every other field is skipped
+    /// without being decoded or copied, so this stays cheap for envelopes with
+    /// large `encryptedData`. Unknown fields are ignored and nothing else is
+    /// validated: a key ID does not mean the envelope is well formed.
+    ///
+    /// # Errors
+    /// `VioletError::SerializationError` if `json` is not a JSON object, or has
+    /// no `keyId` string or more than one.
+    pub fn peek_key_id(json: &[u8]) -> Result<String> {
+        let mut deserializer = serde_json::Deserializer::from_slice(json);
+        let key_id = deserializer.deserialize_map(KeyIdVisitor)?;
+        deserializer.end()?;
+        Ok(key_id)
+    }
+}
+
+/// Fields [`EncryptionEnvelope::peek_key_id`] tells apart
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "camelCase")]
+enum PeekField {
+    KeyId,
+    #[serde(other)]
+    Other,
+}
+
+/// Takes `keyId` from an envelope object, skipping every other value
+struct KeyIdVisitor;
+
+impl<'de> Visitor<'de> for KeyIdVisitor {
+    type Value = String;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an envelope object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<String, A::Error> {
+        let mut key_id = None;
+        while let Some(field) = map.next_key()? {
+            match field {
+                PeekField::KeyId if key_id.is_some() => return Err(de::Error::duplicate_field("keyId")),
+                PeekField::KeyId => key_id = Some(map.next_value()?),
+                PeekField::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        key_id.ok_or_else(|| de::Error::missing_field("keyId"))
+    }
+}
+
+/// Write `value` with sorted object keys, nested `depth` levels deep
+fn write_canonical(value: &serde_json::Value, depth: usize, out: &mut Vec<u8>) -> Result<()> {
+    use serde_json::Value;
+
+    fn indent(depth: usize, out: &mut Vec<u8>) {
+        out.resize(out.len() + 2 * depth, b' ');
+    }
+
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+            out.extend_from_slice(b"{\n");
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.extend_from_slice(b",\n");
+                }
+                indent(depth + 1, out);
+                serde_json::to_writer(&mut *out, key)?;
+                out.extend_from_slice(b": ");
+                write_canonical(value, depth + 1, out)?;
+            }
+            out.push(b'\n');
+            indent(depth, out);
+            out.push(b'}');
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.extend_from_slice(b"[\n");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.extend_from_slice(b",\n");
+                }
+                indent(depth + 1, out);
+                write_canonical(item, depth + 1, out)?;
+            }
+            out.push(b'\n');
+            indent(depth, out);
+            out.push(b']');
+        }
+        scalar => serde_json::to_writer(&mut *out, scalar)?,
+    }
+    Ok(())
+}
+
+pub(crate) fn legacy_version() -> u32 {
+    LEGACY_ENVELOPE_VERSION
+}
+
+fn decode_field(name: &str, value: &str) -> Result<Vec<u8>> {
+    BASE64
+        .decode(value)
+        .map_err(|e| VioletError::InvalidEnvelope(format!("{} is not valid base64: {}", name, e)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::envelope::EnvelopeEncryptor;
+
+    #[cfg(feature = "aes-gcm-siv")]
+    fn valid_envelope() -> EncryptionEnvelope {
+        EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv)
+            .with_kek_fingerprint(true)
+            .encrypt(b"twelve bytes", &[8u8; 32], "key-1".to_string())
+            .unwrap()
+    }
 
     #[test]
     fn test_serialization() {
         let envelope = EncryptionEnvelope {
+            version: ENVELOPE_VERSION,
             key_id: "test-uuid-1234".to_string(),
             encrypted_data: "Y2lwaGVydGV4dA==".to_string(),
             encrypted_key: "ZW5jcnlwdGVkLWRlaw==".to_string(),
             iv: "bm9uY2U=".to_string(),
             algorithm: "AES-256-GCM".to_string(),
             auth_tag: "dGFn".to_string(),
+            kek_fingerprint: None,
+            kdf: None,
+            ephemeral_public_key: None,
         };
 
         let json = serde_json::to_string(&envelope).unwrap();
@@ -53,5 +336,196 @@ mod tests {
         let json = r#"{"keyId":"test","encryptedData":"data","encryptedKey":"key","iv":"iv","algorithm":"AES-256-GCM"}"#;
         let envelope: EncryptionEnvelope = serde_json::from_str(json).unwrap();
         assert_eq!(envelope.auth_tag, "");
+        assert_eq!(envelope.kek_fingerprint, None);
+        assert_eq!(envelope.version, LEGACY_ENVELOPE_VERSION);
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn test_validate_structure_well_formed() {
+        let report = valid_envelope().validate_structure().unwrap();
+
+        assert_eq!(report.version, ENVELOPE_VERSION);
+        assert_eq!(report.key_id, "key-1");
+        assert_eq!(report.algorithm, Algorithm::Aes256GcmSiv);
+        assert_eq!(report.encrypted_data_len, 12);
+        assert_eq!(report.encrypted_key_len, GCM_NONCE_SIZE + 32 + GCM_TAG_SIZE);
+        assert_eq!(report.iv_len, GCM_SIV_NONCE_SIZE);
+        assert_eq!(report.auth_tag_len, GCM_TAG_SIZE);
+        assert!(!report.combined_tag);
+        assert!(report.kek_fingerprint.is_some());
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn test_validate_structure_combined_tag() {
+        let envelope = valid_envelope();
+        let mut data = BASE64.decode(&envelope.encrypted_data).unwrap();
+        data.extend(BASE64.decode(&envelope.auth_tag).unwrap());
+        let combined = EncryptionEnvelope {
+            encrypted_data: BASE64.encode(&data),
+            auth_tag: String::new(),
+            ..envelope
+        };
+
+        let report = combined.validate_structure().unwrap();
+        assert!(report.combined_tag);
+        assert_eq!(report.encrypted_data_len, 12);
+        assert_eq!(report.auth_tag_len, GCM_TAG_SIZE);
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn test_validate_structure_corrupted() {
+        let bad_base64 = EncryptionEnvelope {
+            encrypted_data: "not base64!".to_string(),
+            ..valid_envelope()
+        };
+        assert!(matches!(bad_base64.validate_structure(), Err(VioletError::InvalidEnvelope(_))));
+
+        let unknown_algorithm = EncryptionEnvelope {
+            algorithm: "ROT13".to_string(),
+            ..valid_envelope()
+        };
+        assert!(matches!(unknown_algorithm.validate_structure(), Err(VioletError::InvalidAlgorithm(_))));
+
+        let short_iv = EncryptionEnvelope {
+            iv: BASE64.encode([0u8; 8]),
+            ..valid_envelope()
+        };
+        assert!(matches!(short_iv.validate_structure(), Err(VioletError::InvalidNonceSize(8))));
+
+        let missing_tag = EncryptionEnvelope {
+            auth_tag: String::new(),
+            ..valid_envelope()
+        };
+        assert!(matches!(missing_tag.validate_structure(), Err(VioletError::InvalidTagSize(0))));
+
+        let empty_key = EncryptionEnvelope {
+            encrypted_key: String::new(),
+            ..valid_envelope()
+        };
+        assert!(matches!(empty_key.validate_structure(), Err(VioletError::InvalidEnvelope(_))));
+
+        let bad_fingerprint = EncryptionEnvelope {
+            kek_fingerprint: Some("xyz".to_string()),
+            ..valid_envelope()
+        };
+        assert!(matches!(bad_fingerprint.validate_structure(), Err(VioletError::InvalidEnvelope(_))));
+
+        let future_version = EncryptionEnvelope {
+            version: 3,
+            ..valid_envelope()
+        };
+        assert!(matches!(future_version.validate_structure(), Err(VioletError::UnsupportedVersion(3))));
+    }
+
+    #[test]
+    fn test_kek_fingerprint_omitted_when_absent() {
+        let envelope = EncryptionEnvelope {
+            version: ENVELOPE_VERSION,
+            key_id: "test".to_string(),
+            encrypted_data: "data".to_string(),
+            encrypted_key: "key".to_string(),
+            iv: "iv".to_string(),
+            algorithm: "AES-256-GCM".to_string(),
+            auth_tag: "tag".to_string(),
+            kek_fingerprint: None,
+            kdf: None,
+            ephemeral_public_key: None,
+        };
+        assert!(!serde_json::to_string(&envelope).unwrap().contains("kekFingerprint"));
+
+        let with_fingerprint = EncryptionEnvelope {
+            kek_fingerprint: Some("0011223344556677".to_string()),
+            ..envelope
+        };
+        let json = serde_json::to_string(&with_fingerprint).unwrap();
+        assert!(json.contains(r#""kekFingerprint":"0011223344556677""#));
+    }
+
+    #[test]
+    fn test_canonical_json_is_sorted_and_reproducible() {
+        // Keys deliberately out of order, as another serializer might write them
+        let json = r#"{"iv":"bm9uY2U=","authTag":"dGFn","version":2,"keyId":"k","algorithm":"AES-256-GCM",
+            "encryptedKey":"ZGVr","encryptedData":"Y3Q=","kekFingerprint":"0011223344556677"}"#;
+        let envelope: EncryptionEnvelope = serde_json::from_str(json).unwrap();
+
+        let canonical = envelope.to_canonical_json().unwrap();
+        let expected = r#"{
+  "algorithm": "AES-256-GCM",
+  "authTag": "dGFn",
+  "encryptedData": "Y3Q=",
+  "encryptedKey": "ZGVr",
+  "iv": "bm9uY2U=",
+  "kekFingerprint": "0011223344556677",
+  "keyId": "k",
+  "version": 2
+}
+"#;
+        assert_eq!(String::from_utf8(canonical.clone()).unwrap(), expected);
+
+        // A second run, and a run over the parsed output, give the same bytes
+        assert_eq!(envelope.to_canonical_json().unwrap(), canonical);
+        let reparsed: EncryptionEnvelope = serde_json::from_slice(&canonical).unwrap();
+        assert_eq!(reparsed, envelope);
+        assert_eq!(reparsed.to_canonical_json().unwrap(), canonical);
+    }
+
+    #[test]
+    fn test_canonical_json_sorts_nested_objects() {
+        let envelope = EncryptionEnvelope {
+            kdf: Some(PasswordKdf::with_params(8, 1, 1)),
+            ..EnvelopeEncryptor::new(Algorithm::default())
+                .encrypt(b"twelve bytes", &[8u8; 32], "key-1".to_string())
+                .unwrap()
+        };
+        let canonical = String::from_utf8(envelope.to_canonical_json().unwrap()).unwrap();
+
+        let kdf = &canonical[canonical.find("\"kdf\": {").unwrap()..];
+        let kdf = &kdf[..kdf.find('}').unwrap()];
+        let keys: Vec<_> = kdf
+            .lines()
+            .skip(1)
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.trim().split(':').next().unwrap())
+            .collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys.len(), 5, "{}", kdf);
+        assert_eq!(keys, sorted);
+    }
+
+    #[test]
+    fn test_peek_key_id() {
+        let json = br#"{"version":2,"keyId":"key-1","encryptedData":"Y2lwaGVydGV4dA==","encryptedKey":"a2V5","iv":"bm9uY2U=","algorithm":"AES-256-GCM","authTag":"dGFn"}"#;
+        assert_eq!(EncryptionEnvelope::peek_key_id(json).unwrap(), "key-1");
+
+        // keyId last, escaped, between fields this library has never heard of
+        let json = br#"{
+            "encryptedData": "AAAA",
+            "routing": {"region": "eu-west-1", "keyId": "nested, not the envelope's"},
+            "tags": ["a", 1, null, {"deep": [true]}],
+            "keyId": "key\u002d2"
+        }"#;
+        assert_eq!(EncryptionEnvelope::peek_key_id(json).unwrap(), "key-2");
+
+        // Nothing but the key ID is needed or checked
+        assert_eq!(EncryptionEnvelope::peek_key_id(br#"{"keyId":"k","algorithm":7}"#).unwrap(), "k");
+    }
+
+    #[test]
+    fn test_peek_key_id_rejects() {
+        let message = |json: &[u8]| match EncryptionEnvelope::peek_key_id(json) {
+            Err(VioletError::SerializationError(e)) => e.to_string(),
+            other => panic!("expected a serialization error, got {:?}", other),
+        };
+
+        assert!(message(br#"{"encryptedData":"AAAA","iv":"AAAA"}"#).contains("missing field `keyId`"));
+        assert!(message(br#"{"keyId":"a","keyId":"b"}"#).contains("duplicate field `keyId`"));
+        assert!(message(br#"{"keyId":42}"#).contains("invalid type"));
+        assert!(message(br#"["keyId","k"]"#).contains("expected an envelope object"));
+        assert!(message(br#"{"keyId":"k""#).contains("EOF"));
+        assert!(message(br#"{"keyId":"k"} trailing"#).contains("trailing characters"));
     }
 }
@@ -1 +1,5 @@
+pub mod armor;
+pub mod container;
 pub mod encryption_envelope;
+#[cfg(feature = "cbor")]
+pub mod cbor_envelope;
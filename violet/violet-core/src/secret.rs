@@ -0,0 +1,250 @@
+use std::fmt;
+use std::ops::Deref;
+#[cfg(all(feature = "secure-mem", unix))]
+use std::collections::BTreeMap;
+#[cfg(all(feature = "secure-mem", unix))]
+use std::sync::{Mutex, OnceLock, PoisonError};
+use zeroize::{Zeroize, Zeroizing};
+
+/// KEK bytes kept out of swap where possible and zeroed when dropped
+///
+/// With the `secure-mem` feature on Unix the buffer is `mlock`ed so the
+/// kernel never writes it to disk, and left out of core dumps on Linux. If
+/// locking fails, usually because `RLIMIT_MEMLOCK` is used up, a warning is
+/// logged once and the key stays in ordinary memory. Otherwise the buffer is
+/// never locked. Either way it is zeroed on drop, before it is unlocked.
+pub struct SecretKey {
+    bytes: Zeroizing<Box<[u8]>>,
+    locked: bool,
+}
+
+impl SecretKey {
+    /// Copy `bytes` into a new buffer
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut bytes = Zeroizing::new(Box::<[u8]>::from(bytes));
+        let locked = lock(&mut bytes);
+        Self { bytes, locked }
+    }
+
+    /// Move `bytes` into a new buffer, zeroing the vector
+    pub fn from_vec(mut bytes: Vec<u8>) -> Self {
+        let secret = Self::new(&bytes);
+        bytes.zeroize();
+        secret
+    }
+
+    /// Decode a hex-encoded key, such as the `key` field sent by the Keys server
+    pub fn from_hex(hex: &str) -> Result<Self, hex::FromHexError> {
+        hex::decode(hex).map(Self::from_vec)
+    }
+
+    /// Whether the buffer is locked in memory
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl Deref for SecretKey {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl AsRef<[u8]> for SecretKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Clone for SecretKey {
+    fn clone(&self) -> Self {
+        Self::new(&self.bytes)
+    }
+}
+
+/// Zero the key in place, leaving a buffer of the same length
+impl Zeroize for SecretKey {
+    fn zeroize(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        if self.locked {
+            self.bytes.zeroize();
+            unlock(&self.bytes);
+        }
+    }
+}
+
+/// Key material is redacted so keys can't leak through `{:?}` logging
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretKey")
+            .field("len", &self.bytes.len())
+            .field("locked", &self.locked)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Number of live locked keys on each locked page, by page address
+///
+/// Locking works on whole pages and does not nest, so unlocking one key's
+/// range would also unlock every other key sharing a page with it. A page is
+/// only unlocked once the last key on it is dropped.
+#[cfg(all(feature = "secure-mem", unix))]
+static LOCKED_PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+#[cfg(all(feature = "secure-mem", unix))]
+fn page_size() -> usize {
+    static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
+    *PAGE_SIZE.get_or_init(|| {
+        // SAFETY: sysconf has no preconditions
+        match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            size if size > 0 => size as usize,
+            _ => 4096,
+        }
+    })
+}
+
+/// Addresses of the pages `bytes` spans
+#[cfg(all(feature = "secure-mem", unix))]
+fn pages(bytes: &[u8]) -> impl Iterator<Item = usize> {
+    let size = page_size();
+    let start = bytes.as_ptr() as usize;
+    (start & !(size - 1)..start + bytes.len()).step_by(size)
+}
+
+#[cfg(all(feature = "secure-mem", unix))]
+fn lock(bytes: &mut [u8]) -> bool {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+
+    if bytes.is_empty() {
+        return false;
+    }
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap_or_else(PoisonError::into_inner);
+    // SAFETY: the range is a live allocation owned by the caller
+    let locked = unsafe { libc::mlock(bytes.as_ptr().cast(), bytes.len()) } == 0;
+    if !locked {
+        let error = std::io::Error::last_os_error();
+        WARNED.call_once(|| {
+            tracing::warn!("Failed to lock key memory ({}); keys may be swapped to disk", error);
+        });
+        return false;
+    }
+    for page in pages(bytes) {
+        let count = locked_pages.entry(page).or_insert(0);
+        if *count == 0 {
+            exclude_from_core_dumps(page, true);
+        }
+        *count += 1;
+    }
+    locked
+}
+
+#[cfg(not(all(feature = "secure-mem", unix)))]
+fn lock(_bytes: &mut [u8]) -> bool {
+    false
+}
+
+/// Release a buffer locked by [`lock`], unlocking the pages no other live key is on
+#[cfg(all(feature = "secure-mem", unix))]
+fn unlock(bytes: &[u8]) {
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap_or_else(PoisonError::into_inner);
+    for page in pages(bytes) {
+        let Some(count) = locked_pages.get_mut(&page) else {
+            continue;
+        };
+        *count -= 1;
+        if *count == 0 {
+            locked_pages.remove(&page);
+            exclude_from_core_dumps(page, false);
+            // SAFETY: munlock only changes whether the page may be swapped, never its contents
+            unsafe {
+                libc::munlock(page as *const libc::c_void, page_size());
+            }
+        }
+    }
+}
+
+#[cfg(not(all(feature = "secure-mem", unix)))]
+fn unlock(_bytes: &[u8]) {}
+
+#[cfg(all(feature = "secure-mem", target_os = "linux"))]
+fn exclude_from_core_dumps(page: usize, exclude: bool) {
+    let advice = if exclude { libc::MADV_DONTDUMP } else { libc::MADV_DODUMP };
+    // SAFETY: madvise with these flags only changes how the page is dumped, never its contents
+    unsafe {
+        libc::madvise(page as *mut libc::c_void, page_size(), advice);
+    }
+}
+
+#[cfg(all(feature = "secure-mem", unix, not(target_os = "linux")))]
+fn exclude_from_core_dumps(_page: usize, _exclude: bool) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_key_bytes() {
+        let kek: Vec<u8> = (0..32).collect();
+        let secret = SecretKey::new(&kek);
+        assert_eq!(&*secret, kek.as_slice());
+        assert_eq!(secret.as_ref(), kek.as_slice());
+        assert_eq!(&*secret.clone(), kek.as_slice());
+
+        let secret = SecretKey::from_vec(kek.clone());
+        assert_eq!(&*secret, kek.as_slice());
+
+        let secret = SecretKey::from_hex(&hex::encode(&kek)).unwrap();
+        assert_eq!(&*secret, kek.as_slice());
+        assert!(SecretKey::from_hex("not hex").is_err());
+    }
+
+    #[test]
+    fn test_zeroize_clears_in_place() {
+        let mut secret = SecretKey::new(&[0xab; 32]);
+        secret.zeroize();
+        assert_eq!(&*secret, &[0u8; 32][..]);
+    }
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let secret = SecretKey::new(&[0xab; 32]);
+        let debug = format!("{:?}", secret);
+        assert!(debug.contains("len: 32"), "{}", debug);
+        assert!(!debug.contains("171") && !debug.contains("ab"), "{}", debug);
+    }
+
+    #[cfg(all(feature = "secure-mem", unix))]
+    #[test]
+    fn test_page_stays_locked_while_a_key_on_it_lives() {
+        let first = SecretKey::new(&[1; 32]);
+        let second = SecretKey::new(&[2; 32]);
+        if !first.is_locked() || !second.is_locked() {
+            // RLIMIT_MEMLOCK is used up on this machine
+            return;
+        }
+        let page = pages(&second).next().unwrap();
+        drop(first);
+        assert!(LOCKED_PAGES.lock().unwrap().contains_key(&page));
+        assert_eq!(&*second, &[2; 32][..]);
+    }
+
+    #[cfg(not(all(feature = "secure-mem", unix)))]
+    #[test]
+    fn test_not_locked_without_feature() {
+        assert!(!SecretKey::new(&[1; 32]).is_locked());
+    }
+
+    #[test]
+    fn test_empty_key_is_not_locked() {
+        let secret = SecretKey::new(&[]);
+        assert!(secret.is_empty());
+        assert!(!secret.is_locked());
+    }
+}
@@ -0,0 +1,328 @@
+use crate::error::{Result, VioletError};
+use crate::models::encryption_envelope::EncryptionEnvelope;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Longest file name most filesystems accept
+const MAX_FILE_NAME: usize = 255;
+
+const EXTENSION: &str = ".json";
+
+/// Distinguishes temporary files written by concurrent `put`s in one process
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Named storage for envelopes
+///
+/// Names are chosen by the user and may contain any characters; each backend
+/// is responsible for mapping them onto its own storage safely.
+pub trait EnvelopeStore: Send + Sync {
+    /// Store `envelope` under `name`
+    ///
+    /// Fails with [`VioletError::EnvelopeExists`] if the name is taken,
+    /// unless `overwrite` is set.
+    fn put(&self, name: &str, envelope: &EncryptionEnvelope, overwrite: bool) -> Result<()>;
+
+    /// Load the envelope stored under `name`
+    fn get(&self, name: &str) -> Result<EncryptionEnvelope>;
+
+    /// Names of all stored envelopes, sorted
+    fn list(&self) -> Result<Vec<String>>;
+
+    /// Remove the envelope stored under `name`, returning whether it existed
+    fn delete(&self, name: &str) -> Result<bool>;
+}
+
+/// Envelope store keeping one pretty-printed JSON file per envelope in a directory
+///
+/// Names are mapped to file names reversibly: ASCII letters, digits, `-`, `_`
+/// and non-leading `.` are kept and every other byte is written as `%XX`. Two
+/// different names therefore never share a file, and no name can escape the
+/// directory or produce a hidden file. Files in the directory that are not
+/// in this form are ignored.
+#[derive(Debug, Clone)]
+pub struct FsEnvelopeStore {
+    dir: PathBuf,
+}
+
+impl FsEnvelopeStore {
+    /// Create a store rooted at `dir`; the directory is created on first `put`
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Default store directory: `$XDG_DATA_HOME/violet/envelopes` or
+    /// `~/.local/share/violet/envelopes`
+    pub fn default_dir() -> Option<PathBuf> {
+        if let Some(dir) = std::env::var_os("XDG_DATA_HOME").filter(|d| !d.is_empty()) {
+            return Some(PathBuf::from(dir).join("violet").join("envelopes"));
+        }
+        std::env::var_os("HOME")
+            .filter(|d| !d.is_empty())
+            .map(|home| PathBuf::from(home).join(".local").join("share").join("violet").join("envelopes"))
+    }
+
+    /// Directory holding the envelopes
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        Ok(self.dir.join(file_name(name)?))
+    }
+}
+
+impl EnvelopeStore for FsEnvelopeStore {
+    fn put(&self, name: &str, envelope: &EncryptionEnvelope, overwrite: bool) -> Result<()> {
+        let file = file_name(name)?;
+        let path = self.dir.join(&file);
+        let data = serde_json::to_vec_pretty(envelope)?;
+        fs::create_dir_all(&self.dir)?;
+
+        // Write a temporary file first so readers never see a partial envelope.
+        // Its leading '.' keeps it out of `list`, and it does not embed `file`,
+        // which may already be as long as a file name can be.
+        let tmp = format!(".{}.{}.tmp", std::process::id(), TMP_COUNTER.fetch_add(1, Ordering::Relaxed));
+        let tmp_path = self.dir.join(tmp);
+        fs::write(&tmp_path, &data)?;
+
+        let result = if overwrite {
+            fs::rename(&tmp_path, &path)
+        } else {
+            // Linking fails if the name is taken, so two writers cannot both win
+            fs::hard_link(&tmp_path, &path)
+        };
+        let _ = fs::remove_file(&tmp_path);
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(VioletError::EnvelopeExists(name.to_string())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get(&self, name: &str) -> Result<EncryptionEnvelope> {
+        let data = match fs::read(self.path(name)?) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(VioletError::EnvelopeNotFound(name.to_string())),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if let Some(name) = entry.file_name().to_str().and_then(name_from_file) {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn delete(&self, name: &str) -> Result<bool> {
+        match fs::remove_file(self.path(name)?) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// File name for `name`, escaping every byte that is not safe in a file name
+fn file_name(name: &str) -> Result<String> {
+    if name.is_empty() {
+        return Err(VioletError::InvalidEnvelopeName("name must not be empty".to_string()));
+    }
+
+    let mut encoded = String::with_capacity(name.len() + EXTENSION.len());
+    for (i, byte) in name.bytes().enumerate() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' || (byte == b'.' && i > 0) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded.push_str(EXTENSION);
+
+    if encoded.len() > MAX_FILE_NAME {
+        return Err(VioletError::InvalidEnvelopeName(format!(
+            "{:?} is too long once escaped ({} bytes, limit {})",
+            name,
+            encoded.len(),
+            MAX_FILE_NAME
+        )));
+    }
+    Ok(encoded)
+}
+
+/// Name stored in `file`, or `None` if `file` was not written by [`file_name`]
+fn name_from_file(file: &str) -> Option<String> {
+    let stem = file.strip_suffix(EXTENSION)?;
+    let mut bytes = Vec::with_capacity(stem.len());
+    let mut rest = stem.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    // Only accept the canonical encoding, so each name has exactly one file
+    let name = String::from_utf8(bytes).ok()?;
+    (file_name(&name).ok()? == file).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Algorithm, EnvelopeEncryptor};
+
+    fn envelope(plaintext: &[u8]) -> EncryptionEnvelope {
+        EnvelopeEncryptor::new(Algorithm::default())
+            .encrypt(plaintext, &[7u8; 32], "key".to_string())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_put_get_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsEnvelopeStore::new(dir.path().join("envelopes"));
+        let stored = envelope(b"record");
+
+        store.put("myrecord", &stored, false).unwrap();
+        assert_eq!(store.get("myrecord").unwrap(), stored);
+        assert!(dir.path().join("envelopes").join("myrecord.json").exists());
+
+        // A second instance over the same directory sees the same envelopes
+        let reopened = FsEnvelopeStore::new(dir.path().join("envelopes"));
+        assert_eq!(reopened.get("myrecord").unwrap(), stored);
+    }
+
+    #[test]
+    fn test_get_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsEnvelopeStore::new(dir.path());
+
+        let err = store.get("absent").unwrap_err();
+        assert!(matches!(err, VioletError::EnvelopeNotFound(name) if name == "absent"));
+    }
+
+    #[test]
+    fn test_list_is_sorted_and_skips_foreign_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsEnvelopeStore::new(dir.path());
+        assert!(store.list().unwrap().is_empty());
+
+        for name in ["b", "a", "with space", "c.d"] {
+            store.put(name, &envelope(name.as_bytes()), false).unwrap();
+        }
+        fs::write(dir.path().join("notes.txt"), b"not an envelope").unwrap();
+        fs::write(dir.path().join(".a.json.123.tmp"), b"{").unwrap();
+        fs::write(dir.path().join("%2f.json"), b"{}").unwrap();
+
+        assert_eq!(store.list().unwrap(), ["a", "b", "c.d", "with space"]);
+    }
+
+    #[test]
+    fn test_list_missing_directory_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsEnvelopeStore::new(dir.path().join("never-created"));
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_existing_name_is_refused_unless_overwriting() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsEnvelopeStore::new(dir.path());
+        let first = envelope(b"first");
+        let second = envelope(b"second");
+
+        store.put("record", &first, false).unwrap();
+        let err = store.put("record", &second, false).unwrap_err();
+        assert!(matches!(err, VioletError::EnvelopeExists(name) if name == "record"));
+        assert_eq!(store.get("record").unwrap(), first);
+
+        store.put("record", &second, true).unwrap();
+        assert_eq!(store.get("record").unwrap(), second);
+
+        // No temporary files are left behind either way
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_similar_names_do_not_collide() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsEnvelopeStore::new(dir.path());
+        let names = ["a/b", "a_b", "a%2Fb", "a%2fb", "A_B"];
+
+        for name in names {
+            store.put(name, &envelope(name.as_bytes()), false).unwrap();
+        }
+
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256Gcm);
+        for name in names {
+            let plaintext = encryptor.decrypt(&store.get(name).unwrap(), &[7u8; 32]).unwrap();
+            assert_eq!(plaintext, name.as_bytes());
+        }
+        assert_eq!(store.list().unwrap().len(), names.len());
+    }
+
+    #[test]
+    fn test_names_stay_inside_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("store");
+        let store = FsEnvelopeStore::new(&base);
+
+        for name in ["../escape", "/etc/passwd", ".hidden", "..", "名前"] {
+            store.put(name, &envelope(b"x"), false).unwrap();
+            assert!(store.get(name).is_ok());
+        }
+
+        for entry in fs::read_dir(dir.path()).unwrap() {
+            assert_eq!(entry.unwrap().path(), base);
+        }
+        for entry in fs::read_dir(&base).unwrap() {
+            let file = entry.unwrap().file_name().into_string().unwrap();
+            assert!(!file.starts_with('.'), "{}", file);
+            assert!(!file.contains('/'), "{}", file);
+        }
+        assert_eq!(store.list().unwrap(), ["..", "../escape", ".hidden", "/etc/passwd", "名前"]);
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsEnvelopeStore::new(dir.path());
+
+        assert!(matches!(store.put("", &envelope(b"x"), false), Err(VioletError::InvalidEnvelopeName(_))));
+        assert!(matches!(store.get(&"/".repeat(100)), Err(VioletError::InvalidEnvelopeName(_))));
+        assert!(store.put(&"a".repeat(MAX_FILE_NAME - EXTENSION.len()), &envelope(b"x"), false).is_ok());
+    }
+
+    #[test]
+    fn test_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsEnvelopeStore::new(dir.path());
+
+        store.put("record", &envelope(b"x"), false).unwrap();
+        assert!(store.delete("record").unwrap());
+        assert!(!store.delete("record").unwrap());
+        assert!(matches!(store.get("record"), Err(VioletError::EnvelopeNotFound(_))));
+        assert!(store.list().unwrap().is_empty());
+    }
+}
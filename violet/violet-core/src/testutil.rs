@@ -0,0 +1,86 @@
+//! Assertions for tests of code that produces envelopes
+//!
+//! Available to this crate's tests and, with the `testutil` feature, to other
+//! crates' tests. Envelopes of the same plaintext differ in every encryption
+//! (fresh DEK and nonce), so these compare what envelopes decrypt to instead.
+
+use crate::{Algorithm, EncryptionEnvelope, EnvelopeEncryptor, Result};
+
+/// Assert that `envelope` decrypts under `kek` to `expected`
+///
+/// # Panics
+/// Panics if decryption fails or yields anything else.
+#[track_caller]
+pub fn assert_decrypts_to(envelope: &EncryptionEnvelope, kek: &[u8], expected: impl AsRef<[u8]>) {
+    let plaintext = match decrypt(envelope, kek) {
+        Ok(plaintext) => plaintext,
+        Err(e) => panic!("envelope for key {} did not decrypt: {}", envelope.key_id, e),
+    };
+    let expected = expected.as_ref();
+    assert!(
+        plaintext == expected,
+        "envelope for key {} decrypted to \"{}\", expected \"{}\"",
+        envelope.key_id,
+        plaintext.escape_ascii(),
+        expected.escape_ascii()
+    );
+}
+
+/// Whether two envelopes decrypt under `kek` to the same plaintext
+///
+/// # Errors
+/// Fails if either envelope does not decrypt under `kek`.
+pub fn envelopes_decrypt_equal(a: &EncryptionEnvelope, b: &EncryptionEnvelope, kek: &[u8]) -> Result<bool> {
+    Ok(decrypt(a, kek)? == decrypt(b, kek)?)
+}
+
+/// The algorithm is read from the envelope, so any encryptor will do
+fn decrypt(envelope: &EncryptionEnvelope, kek: &[u8]) -> Result<Vec<u8>> {
+    EnvelopeEncryptor::new(Algorithm::default()).decrypt(envelope, kek)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEK: [u8; 32] = [0x42; 32];
+
+    fn seal(plaintext: &[u8], algorithm: Algorithm) -> EncryptionEnvelope {
+        EnvelopeEncryptor::new(algorithm)
+            .encrypt(plaintext, &KEK, "test-key".to_string())
+            .unwrap()
+    }
+
+    #[cfg(all(feature = "aes-gcm", feature = "aes-gcm-siv"))]
+    #[test]
+    fn test_assert_decrypts_to() {
+        assert_decrypts_to(&seal(b"hello", Algorithm::Aes256Gcm), &KEK, b"hello");
+        assert_decrypts_to(&seal(b"hello", Algorithm::Aes256GcmSiv), &KEK, "hello");
+        assert_decrypts_to(&seal(b"", Algorithm::Aes256Gcm), &KEK, b"");
+    }
+
+    #[test]
+    #[should_panic(expected = "decrypted to \"hello\", expected \"goodbye\"")]
+    fn test_assert_decrypts_to_wrong_plaintext() {
+        assert_decrypts_to(&seal(b"hello", Algorithm::default()), &KEK, b"goodbye");
+    }
+
+    #[test]
+    #[should_panic(expected = "did not decrypt")]
+    fn test_assert_decrypts_to_wrong_kek() {
+        assert_decrypts_to(&seal(b"hello", Algorithm::default()), &[0x24; 32], b"hello");
+    }
+
+    #[cfg(all(feature = "aes-gcm", feature = "aes-gcm-siv"))]
+    #[test]
+    fn test_envelopes_decrypt_equal() {
+        let a = seal(b"same", Algorithm::Aes256Gcm);
+        let b = seal(b"same", Algorithm::Aes256GcmSiv);
+        assert_ne!(a.encrypted_data, b.encrypted_data);
+        assert!(envelopes_decrypt_equal(&a, &b, &KEK).unwrap());
+
+        let c = seal(b"different", Algorithm::Aes256Gcm);
+        assert!(!envelopes_decrypt_equal(&a, &c, &KEK).unwrap());
+        assert!(envelopes_decrypt_equal(&a, &b, &[0x24; 32]).is_err());
+    }
+}
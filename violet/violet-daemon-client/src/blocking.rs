@@ -0,0 +1,180 @@
+//! Blocking facade over [`DaemonClient`](crate::DaemonClient)
+//!
+//! Each client runs the async client on its own single-threaded runtime, so
+//! it must not be used from inside a tokio runtime.
+
+use crate::client::{self, Pong};
+use crate::error::Result;
+use std::path::Path;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use violet_core::{Algorithm, EncryptionEnvelope};
+use violet_daemon::{BatchItem, BatchItemResult, HelloInfo, StatsSnapshot};
+
+/// Blocking client for the daemon's Unix socket, with the same operations
+/// and behaviour as the async [`DaemonClient`](crate::DaemonClient)
+///
+/// # Example
+/// ```no_run
+/// use violet_daemon_client::blocking::DaemonClient;
+///
+/// let client = DaemonClient::connect("/tmp/violet.sock").unwrap();
+/// let envelope = client.encrypt(b"secret", Some("key-id"), None).unwrap();
+/// assert_eq!(client.decrypt(&envelope).unwrap(), b"secret");
+/// ```
+pub struct DaemonClient {
+    inner: client::DaemonClient,
+    runtime: Runtime,
+}
+
+/// Builder for the blocking [`DaemonClient`]
+pub struct DaemonClientBuilder(client::DaemonClientBuilder);
+
+impl DaemonClientBuilder {
+    /// See [`client::DaemonClientBuilder::timeout`]
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self(self.0.timeout(timeout))
+    }
+
+    /// See [`client::DaemonClientBuilder::max_frame_len`]
+    pub fn max_frame_len(self, max: usize) -> Self {
+        Self(self.0.max_frame_len(max))
+    }
+
+    /// See [`client::DaemonClientBuilder::compress_above`]
+    pub fn compress_above(self, min_len: usize) -> Self {
+        Self(self.0.compress_above(min_len))
+    }
+
+    pub fn connect(self) -> Result<DaemonClient> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let inner = runtime.block_on(self.0.connect())?;
+        Ok(DaemonClient { inner, runtime })
+    }
+}
+
+impl DaemonClient {
+    pub fn connect(socket_path: impl AsRef<Path>) -> Result<Self> {
+        Self::builder(socket_path).connect()
+    }
+
+    pub fn builder(socket_path: impl AsRef<Path>) -> DaemonClientBuilder {
+        DaemonClientBuilder(client::DaemonClient::builder(socket_path))
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        self.inner.socket_path()
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8], key_id: Option<&str>, algorithm: Option<Algorithm>) -> Result<EncryptionEnvelope> {
+        self.runtime.block_on(self.inner.encrypt(plaintext, key_id, algorithm))
+    }
+
+    pub fn decrypt(&self, envelope: &EncryptionEnvelope) -> Result<Vec<u8>> {
+        self.runtime.block_on(self.inner.decrypt(envelope))
+    }
+
+    pub fn rewrap(&self, envelope: &EncryptionEnvelope, new_key_id: Option<&str>) -> Result<EncryptionEnvelope> {
+        self.runtime.block_on(self.inner.rewrap(envelope, new_key_id))
+    }
+
+    pub fn create_key(&self) -> Result<String> {
+        self.runtime.block_on(self.inner.create_key())
+    }
+
+    pub fn batch(&self, items: Vec<BatchItem>) -> Result<Vec<BatchItemResult>> {
+        self.runtime.block_on(self.inner.batch(items))
+    }
+
+    pub fn ping(&self) -> Result<Pong> {
+        self.runtime.block_on(self.inner.ping())
+    }
+
+    pub fn hello(&self) -> Result<HelloInfo> {
+        self.runtime.block_on(self.inner.hello())
+    }
+
+    pub fn stats(&self) -> Result<StatsSnapshot> {
+        self.runtime.block_on(self.inner.stats())
+    }
+
+    pub fn flush_keys(&self, key_id: Option<&str>) -> Result<usize> {
+        self.runtime.block_on(self.inner.flush_keys(key_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{daemon, spawn_daemon, KEK_HEX};
+    use violet_daemon::{ErrorCode, Operation, RequestData};
+
+    #[test]
+    fn test_blocking_client() {
+        let mut server = mockito::Server::new();
+        let _key = server
+            .mock("GET", "/v1/keys/blocking-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"blocking-key","key":"{}"}}"#, KEK_HEX))
+            .create();
+        let _created = server
+            .mock("GET", "/v1/keys/created-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"created-key","key":"{}"}}"#, KEK_HEX))
+            .create();
+        let _create = server
+            .mock("POST", "/v1/keys/")
+            .with_status(201)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"created-key","key":"{}"}}"#, KEK_HEX))
+            .create();
+
+        // The daemon runs on its own runtime; the client blocks this thread
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("violet.sock");
+        let daemon_runtime = tokio::runtime::Runtime::new().unwrap();
+        daemon_runtime.block_on(spawn_daemon(daemon(&socket_path, &server.url())));
+
+        let client = DaemonClient::builder(&socket_path)
+            .timeout(Duration::from_secs(10))
+            .connect()
+            .unwrap();
+        assert_eq!(client.socket_path(), socket_path);
+
+        let envelope = client.encrypt(b"blocking", Some("blocking-key"), None).unwrap();
+        assert_eq!(client.decrypt(&envelope).unwrap(), b"blocking");
+
+        let created = client.create_key().unwrap();
+        assert_eq!(created, "created-key");
+        let rewrapped = client.rewrap(&envelope, Some(created.as_str())).unwrap();
+        assert_eq!(client.decrypt(&rewrapped).unwrap(), b"blocking");
+
+        let results = client
+            .batch(vec![BatchItem {
+                operation: Operation::Decrypt,
+                data: RequestData {
+                    envelope: Some(envelope),
+                    ..Default::default()
+                },
+            }])
+            .unwrap();
+        assert!(results[0].success);
+
+        assert!(!client.ping().unwrap().version.is_empty());
+        assert_eq!(client.hello().unwrap(), HelloInfo::current());
+        assert!(client.stats().unwrap().requests.contains_key("batch"));
+        assert!(client.flush_keys(None).unwrap() >= 1);
+
+        let error = client.decrypt(&tampered(&rewrapped)).unwrap_err();
+        assert_eq!(error.error_code(), Some(ErrorCode::CryptoFailed), "{}", error);
+    }
+
+    fn tampered(envelope: &EncryptionEnvelope) -> EncryptionEnvelope {
+        let mut tampered = envelope.clone();
+        let replacement = if tampered.auth_tag.starts_with('A') { "B" } else { "A" };
+        tampered.auth_tag.replace_range(..1, replacement);
+        tampered
+    }
+}
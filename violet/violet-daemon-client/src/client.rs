@@ -0,0 +1,661 @@
+use crate::error::{DaemonClientError, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures::{SinkExt, StreamExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::net::UnixStream;
+use tokio::sync::Mutex;
+use tokio_util::codec::Framed;
+use violet_core::{Algorithm, EncryptionEnvelope};
+use violet_daemon::codec::{compress, decompress, is_compressed, DEFAULT_MAX_FRAME_LEN};
+use violet_daemon::{
+    abstract_socket_name, BatchItem, BatchItemResult, FrameCodec, FrameError, HelloInfo, Operation, Request, RequestData, Response,
+    ResponseResult, StatsSnapshot, PROTOCOL_VERSION,
+};
+
+/// Default time allowed for one request, including any reconnect
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Connection = Framed<UnixStream, FrameCodec>;
+
+/// Async client for the daemon's Unix socket
+///
+/// Speaks the length-prefixed framing, tags every request with an ID and
+/// checks that the response carries it back. Requests on one client are sent
+/// one at a time over a single connection; open several clients for
+/// parallelism.
+///
+/// A dropped connection is reopened on the next request. A request that was
+/// in flight when the connection broke is sent again on the new connection if
+/// repeating it is harmless, i.e. it cannot create a key; otherwise the error
+/// is returned.
+///
+/// # Example
+/// ```no_run
+/// # async fn example() -> violet_daemon_client::Result<()> {
+/// use violet_daemon_client::DaemonClient;
+///
+/// let client = DaemonClient::connect("/tmp/violet.sock").await?;
+/// let envelope = client.encrypt(b"secret", Some("key-id"), None).await?;
+/// assert_eq!(client.decrypt(&envelope).await?, b"secret");
+/// # Ok(())
+/// # }
+/// ```
+pub struct DaemonClient {
+    socket_path: PathBuf,
+    timeout: Duration,
+    max_frame_len: usize,
+    compress_above: Option<usize>,
+    connection: Mutex<Option<Connection>>,
+    next_id: AtomicU64,
+}
+
+/// Builder for [`DaemonClient`]
+pub struct DaemonClientBuilder {
+    socket_path: PathBuf,
+    timeout: Duration,
+    max_frame_len: usize,
+    compress_above: Option<usize>,
+}
+
+/// Answer to a `ping`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pong {
+    pub uptime_secs: u64,
+
+    /// Whether the daemon's last Keys server health probe succeeded
+    pub keys_server_ok: bool,
+
+    /// Version of the daemon
+    pub version: String,
+}
+
+impl DaemonClientBuilder {
+    /// Time allowed for each request, including connecting (default 30 seconds)
+    ///
+    /// A request that times out closes the connection, so a late response
+    /// cannot be mistaken for the answer to the next request.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Largest frame sent or accepted (default 16 MiB, the daemon's own default)
+    pub fn max_frame_len(mut self, max: usize) -> Self {
+        self.max_frame_len = max;
+        self
+    }
+
+    /// Gzip requests of at least `min_len` bytes (default: never)
+    ///
+    /// The daemon answers a compressed request with a compressed response,
+    /// which is inflated transparently. Only daemons whose `hello` lists
+    /// `gzip` in `compression` accept compressed requests; older ones refuse
+    /// them as invalid.
+    pub fn compress_above(mut self, min_len: usize) -> Self {
+        self.compress_above = Some(min_len);
+        self
+    }
+
+    /// Connect to the daemon
+    ///
+    /// # Errors
+    /// Returns `DaemonClientError::Connect` if nothing is listening on the socket.
+    pub async fn connect(self) -> Result<DaemonClient> {
+        if self.timeout.is_zero() {
+            return Err(DaemonClientError::InvalidTimeout);
+        }
+        let mut client = DaemonClient {
+            socket_path: self.socket_path,
+            timeout: self.timeout,
+            max_frame_len: self.max_frame_len,
+            compress_above: self.compress_above,
+            connection: Mutex::new(None),
+            next_id: AtomicU64::new(1),
+        };
+        let connection = tokio::time::timeout(client.timeout, client.open())
+            .await
+            .map_err(|_| DaemonClientError::Timeout(client.timeout))??;
+        *client.connection.get_mut() = Some(connection);
+        Ok(client)
+    }
+}
+
+impl DaemonClient {
+    /// Connect to the daemon listening on `socket_path` with default settings
+    pub async fn connect(socket_path: impl AsRef<Path>) -> Result<Self> {
+        Self::builder(socket_path).connect().await
+    }
+
+    /// Start building a client for the daemon listening on `socket_path`
+    ///
+    /// On Linux, `@name` names an abstract-namespace socket instead of a file.
+    pub fn builder(socket_path: impl AsRef<Path>) -> DaemonClientBuilder {
+        DaemonClientBuilder {
+            socket_path: socket_path.as_ref().to_path_buf(),
+            timeout: DEFAULT_TIMEOUT,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            compress_above: None,
+        }
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Encrypt `plaintext` under `key_id`, or under a new key when `None`
+    ///
+    /// `algorithm` defaults to the daemon's default algorithm.
+    pub async fn encrypt(
+        &self,
+        plaintext: &[u8],
+        key_id: Option<&str>,
+        algorithm: Option<Algorithm>,
+    ) -> Result<EncryptionEnvelope> {
+        let data = RequestData {
+            plaintext: BASE64.encode(plaintext),
+            key_id: key_id.map(str::to_string),
+            algorithm,
+            ..Default::default()
+        };
+        match self.call(Operation::Encrypt, data).await? {
+            ResponseResult::Encrypt { envelope } => Ok(envelope),
+            _ => Err(unexpected(Operation::Encrypt)),
+        }
+    }
+
+    pub async fn decrypt(&self, envelope: &EncryptionEnvelope) -> Result<Vec<u8>> {
+        let data = RequestData {
+            envelope: Some(envelope.clone()),
+            ..Default::default()
+        };
+        match self.call(Operation::Decrypt, data).await? {
+            ResponseResult::Decrypt { plaintext } => BASE64
+                .decode(plaintext)
+                .map_err(|e| DaemonClientError::InvalidResponse(format!("plaintext is not base64: {}", e))),
+            _ => Err(unexpected(Operation::Decrypt)),
+        }
+    }
+
+    /// Move an envelope to `new_key_id`, or to a new key when `None`
+    pub async fn rewrap(&self, envelope: &EncryptionEnvelope, new_key_id: Option<&str>) -> Result<EncryptionEnvelope> {
+        let data = RequestData {
+            envelope: Some(envelope.clone()),
+            new_key_id: new_key_id.map(str::to_string),
+            ..Default::default()
+        };
+        match self.call(Operation::Rewrap, data).await? {
+            ResponseResult::Rewrapped { envelope, .. } => Ok(envelope),
+            _ => Err(unexpected(Operation::Rewrap)),
+        }
+    }
+
+    /// Create a KEK on the Keys server, returning its key_id
+    pub async fn create_key(&self) -> Result<String> {
+        match self.call(Operation::CreateKey, RequestData::default()).await? {
+            ResponseResult::KeyCreated { key_id, .. } => Ok(key_id),
+            _ => Err(unexpected(Operation::CreateKey)),
+        }
+    }
+
+    /// Run encrypt and decrypt items in one request
+    ///
+    /// Items succeed or fail independently; results are in item order.
+    pub async fn batch(&self, items: Vec<BatchItem>) -> Result<Vec<BatchItemResult>> {
+        let data = RequestData {
+            items,
+            ..Default::default()
+        };
+        match self.call(Operation::Batch, data).await? {
+            ResponseResult::Batch { results } => Ok(results),
+            _ => Err(unexpected(Operation::Batch)),
+        }
+    }
+
+    pub async fn ping(&self) -> Result<Pong> {
+        match self.call(Operation::Ping, RequestData::default()).await? {
+            ResponseResult::Pong {
+                uptime_secs,
+                keys_server_ok,
+                version,
+            } => Ok(Pong {
+                uptime_secs,
+                keys_server_ok,
+                version,
+            }),
+            _ => Err(unexpected(Operation::Ping)),
+        }
+    }
+
+    /// Protocol version, operations and algorithms the daemon supports
+    pub async fn hello(&self) -> Result<HelloInfo> {
+        match self.call(Operation::Hello, RequestData::default()).await? {
+            ResponseResult::Hello(info) => Ok(info),
+            _ => Err(unexpected(Operation::Hello)),
+        }
+    }
+
+    pub async fn stats(&self) -> Result<StatsSnapshot> {
+        match self.call(Operation::Stats, RequestData::default()).await? {
+            ResponseResult::Stats(stats) => Ok(stats),
+            _ => Err(unexpected(Operation::Stats)),
+        }
+    }
+
+    /// Drop the daemon's cached KEK for `key_id`, or all of them, returning how many were dropped
+    pub async fn flush_keys(&self, key_id: Option<&str>) -> Result<usize> {
+        let data = RequestData {
+            key_id: key_id.map(str::to_string),
+            ..Default::default()
+        };
+        match self.call(Operation::FlushKeys, data).await? {
+            ResponseResult::KeysFlushed { flushed } => Ok(flushed),
+            _ => Err(unexpected(Operation::FlushKeys)),
+        }
+    }
+
+    /// Send one request and return its result, reconnecting if the connection was lost
+    async fn call(&self, operation: Operation, data: RequestData) -> Result<ResponseResult> {
+        let id = format!("dc-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let retry_safe = is_retry_safe(operation, &data);
+        let request = Request {
+            id: Some(id.clone()),
+            version: Some(PROTOCOL_VERSION),
+            operation,
+            include_timings: false,
+            data,
+        };
+        let body = serde_json::to_vec(&request)
+            .map_err(|e| DaemonClientError::InvalidResponse(format!("failed to encode request: {}", e)))?;
+        let body = match self.compress_above {
+            Some(min_len) if body.len() >= min_len => compress(&body),
+            _ => body,
+        };
+
+        let mut connection = self.connection.lock().await;
+        let response = match self.exchange(&mut connection, &id, &body).await {
+            Err(e) if e.is_connection_lost() && retry_safe => {
+                tracing::debug!("Daemon connection lost ({}), retrying {} on a new one", e, id);
+                self.exchange(&mut connection, &id, &body).await?
+            }
+            result => result?,
+        };
+        into_result(response)
+    }
+
+    /// Send `body` and read its response, opening a connection first if there is none
+    ///
+    /// Any failure leaves no connection behind, since its state is unknown.
+    async fn exchange(&self, connection: &mut Option<Connection>, id: &str, body: &[u8]) -> Result<Response> {
+        let result = tokio::time::timeout(self.timeout, async {
+            if connection.is_none() {
+                *connection = Some(self.open().await?);
+            }
+            let framed = connection.as_mut().expect("connection was just opened");
+            framed.send(body.to_vec()).await.map_err(|e| match e {
+                FrameError::FrameTooLarge { len, max } => DaemonClientError::RequestTooLarge { len, max },
+                FrameError::Io(e) => DaemonClientError::Io(e),
+            })?;
+            read_response(framed).await
+        })
+        .await
+        .unwrap_or(Err(DaemonClientError::Timeout(self.timeout)));
+
+        let result = result.and_then(|response| {
+            // Failures from before the request was parsed, such as a refused
+            // connection, cannot carry its ID
+            let anonymous_failure = !response.success && response.id.is_none();
+            if response.id.as_deref() == Some(id) || anonymous_failure {
+                Ok(response)
+            } else {
+                Err(DaemonClientError::InvalidResponse(format!(
+                    "response ID {:?} does not match request {}",
+                    response.id, id
+                )))
+            }
+        });
+        if result.is_err() {
+            *connection = None;
+        }
+        result
+    }
+
+    async fn open(&self) -> Result<Connection> {
+        let stream = match self.socket_path.to_str().and_then(abstract_socket_name) {
+            Some(name) => connect_abstract(name).await,
+            None => UnixStream::connect(&self.socket_path).await,
+        };
+        let stream = stream.map_err(|source| DaemonClientError::Connect {
+                path: self.socket_path.display().to_string(),
+                source,
+            })?;
+        tracing::debug!("Connected to daemon at {}", self.socket_path.display());
+        Ok(Framed::new(stream, FrameCodec::new(self.max_frame_len)))
+    }
+}
+
+/// Connect to the Linux abstract-namespace socket `name`, given as `@name`
+#[cfg(target_os = "linux")]
+async fn connect_abstract(name: &str) -> std::io::Result<UnixStream> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    // Connecting only blocks while the daemon's backlog is full
+    let stream = tokio::task::spawn_blocking(move || std::os::unix::net::UnixStream::connect_addr(&addr))
+        .await
+        .map_err(std::io::Error::other)??;
+    stream.set_nonblocking(true)?;
+    UnixStream::from_std(stream)
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn connect_abstract(_name: &str) -> std::io::Result<UnixStream> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "abstract socket names (@name) are only supported on Linux",
+    ))
+}
+
+/// Read one response frame, inflating it if it is compressed
+///
+/// A daemon that refuses the connection outright answers with a single JSON
+/// line whatever the framing, which is recognised by its opening `{` and
+/// parsed as the response.
+async fn read_response(framed: &mut Connection) -> Result<Response> {
+    let frame = match framed.next().await {
+        Some(Ok(frame)) => frame,
+        Some(Err(FrameError::FrameTooLarge { .. })) if framed.read_buffer().first() == Some(&b'{') => {
+            let buffer: &[u8] = framed.read_buffer();
+            let line = buffer.split(|&b| b == b'\n').next().unwrap_or_default();
+            return serde_json::from_slice(line)
+                .map_err(|e| DaemonClientError::InvalidResponse(format!("unreadable refusal: {}", e)));
+        }
+        Some(Err(FrameError::FrameTooLarge { len, max })) => {
+            return Err(DaemonClientError::InvalidResponse(format!(
+                "response frame of {} bytes exceeds the {} byte limit",
+                len, max
+            )))
+        }
+        Some(Err(FrameError::Io(e))) => return Err(DaemonClientError::Io(e)),
+        None => return Err(DaemonClientError::ConnectionClosed),
+    };
+    if is_compressed(&frame) {
+        let body = decompress(&frame, framed.codec().max_frame_len())
+            .map_err(|e| DaemonClientError::InvalidResponse(format!("unreadable compressed response: {}", e)))?;
+        return serde_json::from_slice(&body).map_err(|e| DaemonClientError::InvalidResponse(e.to_string()));
+    }
+    serde_json::from_slice(&frame).map_err(|e| DaemonClientError::InvalidResponse(e.to_string()))
+}
+
+fn into_result(response: Response) -> Result<ResponseResult> {
+    if !response.success {
+        let message = response.error.unwrap_or_default();
+        return Err(match response.error_code {
+            Some(code) => DaemonClientError::Refused { code, message },
+            None => DaemonClientError::Failed(message),
+        });
+    }
+    response
+        .result
+        .ok_or_else(|| DaemonClientError::InvalidResponse("successful response has no result".into()))
+}
+
+fn unexpected(operation: Operation) -> DaemonClientError {
+    DaemonClientError::InvalidResponse(format!("unexpected result for {}", operation.as_str()))
+}
+
+/// Whether sending a request twice does no more than sending it once
+///
+/// Requests that may create a key on the Keys server are not, nor are file
+/// operations, which refuse to overwrite the output a first attempt created.
+fn is_retry_safe(operation: Operation, data: &RequestData) -> bool {
+    match operation {
+        Operation::Encrypt => data.key_id.is_some() || data.idempotency_key.is_some(),
+        Operation::Rewrap => data.new_key_id.is_some(),
+        Operation::Batch => data.items.iter().all(|item| is_retry_safe(item.operation, &item.data)),
+        Operation::CreateKey
+        | Operation::EncryptStream
+        | Operation::DecryptStream
+        | Operation::EncryptFile
+        | Operation::DecryptFile => false,
+        Operation::Decrypt | Operation::Hello | Operation::Ping | Operation::Stats | Operation::FlushKeys => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{daemon, spawn_daemon, KEK_HEX};
+    use tokio::net::UnixListener;
+    use violet_daemon::ErrorCode;
+
+    fn mock_key(server: &mut mockito::ServerGuard, uuid: &str) -> mockito::Mock {
+        server
+            .mock("GET", format!("/v1/keys/{}", uuid).as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"{}","key":"{}"}}"#, uuid, KEK_HEX))
+            .create()
+    }
+
+    #[test]
+    fn test_every_operation() {
+        let mut server = mockito::Server::new();
+        let _key = mock_key(&mut server, "client-key");
+        let _other = mock_key(&mut server, "other-key");
+        let _create = server
+            .mock("POST", "/v1/keys/")
+            .with_status(201)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"created-key","key":"{}"}}"#, KEK_HEX))
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let socket_path = dir.path().join("violet.sock");
+            spawn_daemon(daemon(&socket_path, &server.url())).await;
+            let client = DaemonClient::connect(&socket_path).await.unwrap();
+
+            let envelope = client.encrypt(b"hello", Some("client-key"), None).await.unwrap();
+            assert_eq!(envelope.key_id, "client-key");
+            let siv = client
+                .encrypt(b"hello", Some("client-key"), Some(Algorithm::Aes256GcmSiv))
+                .await
+                .unwrap();
+            assert_eq!(siv.algorithm, Algorithm::Aes256GcmSiv.as_str());
+            assert_eq!(client.decrypt(&envelope).await.unwrap(), b"hello");
+
+            let rewrapped = client.rewrap(&envelope, Some("other-key")).await.unwrap();
+            assert_eq!(rewrapped.key_id, "other-key");
+            assert_eq!(client.decrypt(&rewrapped).await.unwrap(), b"hello");
+
+            assert_eq!(client.create_key().await.unwrap(), "created-key");
+
+            let results = client
+                .batch(vec![
+                    BatchItem {
+                        operation: Operation::Encrypt,
+                        data: RequestData {
+                            plaintext: BASE64.encode(b"batched"),
+                            key_id: Some("client-key".into()),
+                            ..Default::default()
+                        },
+                    },
+                    BatchItem {
+                        operation: Operation::Decrypt,
+                        data: RequestData {
+                            envelope: Some(envelope.clone()),
+                            ..Default::default()
+                        },
+                    },
+                ])
+                .await
+                .unwrap();
+            assert_eq!(results.len(), 2);
+            assert!(results.iter().all(|result| result.success));
+
+            let pong = client.ping().await.unwrap();
+            assert_eq!(pong.version, env!("CARGO_PKG_VERSION"));
+
+            assert_eq!(client.hello().await.unwrap(), HelloInfo::current());
+
+            let stats = client.stats().await.unwrap();
+            assert_eq!(stats.requests.get("encrypt"), Some(&2));
+            assert_eq!(stats.requests.get("decrypt"), Some(&2));
+
+            assert_eq!(client.flush_keys(Some("client-key")).await.unwrap(), 1);
+            assert!(client.flush_keys(None).await.unwrap() >= 1);
+        });
+    }
+
+    #[test]
+    fn test_compressed_requests() {
+        let mut server = mockito::Server::new();
+        let _key = mock_key(&mut server, "client-key");
+
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let socket_path = dir.path().join("violet.sock");
+            spawn_daemon(daemon(&socket_path, &server.url())).await;
+            let client = DaemonClient::builder(&socket_path)
+                .compress_above(1024)
+                .connect()
+                .await
+                .unwrap();
+
+            // Small requests go plain, large ones compressed; both read back alike
+            assert_eq!(client.hello().await.unwrap().compression, ["gzip"]);
+            let plaintext = b"log line that repeats\n".repeat(100_000);
+            let envelope = client.encrypt(&plaintext, Some("client-key"), None).await.unwrap();
+            assert_eq!(client.decrypt(&envelope).await.unwrap(), plaintext);
+        });
+    }
+
+    #[test]
+    fn test_refusal_carries_error_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let socket_path = dir.path().join("violet.sock");
+            spawn_daemon(daemon(&socket_path, "http://127.0.0.1:9")).await;
+            let client = DaemonClient::connect(&socket_path).await.unwrap();
+
+            let error = client.encrypt(b"hello", Some("unreachable"), None).await.unwrap_err();
+            assert_eq!(error.error_code(), Some(ErrorCode::KeyUnavailable), "{}", error);
+
+            // The connection is still usable after a refused request
+            client.hello().await.unwrap();
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_abstract_socket() {
+        let mut server = mockito::Server::new();
+        let _key = mock_key(&mut server, "client-key");
+        let socket = format!("@violet-client-test-{}", std::process::id());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = violet_daemon::DaemonServer::new(socket.clone(), server.url());
+            spawn_daemon(daemon).await;
+            let client = DaemonClient::connect(&socket).await.unwrap();
+
+            let envelope = client.encrypt(b"hello", Some("client-key"), None).await.unwrap();
+            assert_eq!(client.decrypt(&envelope).await.unwrap(), b"hello");
+        });
+    }
+
+    #[test]
+    fn test_reconnects_after_daemon_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let socket_path = dir.path().join("violet.sock");
+            let server = daemon(&socket_path, "http://127.0.0.1:9");
+            let handle = server.shutdown_handle();
+            spawn_daemon(server).await;
+            let client = DaemonClient::connect(&socket_path).await.unwrap();
+            client.hello().await.unwrap();
+
+            // Shutdown closes the idle connection and removes the socket
+            handle.shutdown();
+            while socket_path.exists() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            let error = client.ping().await.unwrap_err();
+            assert!(matches!(error, DaemonClientError::Connect { .. }), "{}", error);
+
+            spawn_daemon(daemon(&socket_path, "http://127.0.0.1:9")).await;
+            client.ping().await.unwrap();
+            client.hello().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_unanswered_request_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("silent.sock");
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            // Accepts connections and never answers
+            let listener = UnixListener::bind(&socket_path).unwrap();
+            tokio::spawn(async move {
+                let mut held = Vec::new();
+                while let Ok((stream, _)) = listener.accept().await {
+                    held.push(stream);
+                }
+            });
+
+            let client = DaemonClient::builder(&socket_path)
+                .timeout(Duration::from_millis(200))
+                .connect()
+                .await
+                .unwrap();
+            let error = client.ping().await.unwrap_err();
+            assert!(matches!(error, DaemonClientError::Timeout(_)), "{}", error);
+        });
+    }
+
+    #[test]
+    fn test_connect_without_daemon_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let error = runtime
+            .block_on(DaemonClient::connect(dir.path().join("missing.sock")))
+            .err()
+            .unwrap();
+        assert!(matches!(error, DaemonClientError::Connect { .. }), "{}", error);
+        assert!(error.to_string().contains("missing.sock"), "{}", error);
+    }
+
+    #[test]
+    fn test_retry_safety() {
+        let with_key = RequestData {
+            key_id: Some("k".into()),
+            ..Default::default()
+        };
+        assert!(is_retry_safe(Operation::Encrypt, &with_key));
+        assert!(!is_retry_safe(Operation::Encrypt, &RequestData::default()));
+        assert!(!is_retry_safe(Operation::CreateKey, &RequestData::default()));
+        assert!(!is_retry_safe(Operation::Rewrap, &RequestData::default()));
+
+        let batch = RequestData {
+            items: vec![
+                BatchItem {
+                    operation: Operation::Encrypt,
+                    data: with_key,
+                },
+                BatchItem {
+                    operation: Operation::Encrypt,
+                    data: RequestData::default(),
+                },
+            ],
+            ..Default::default()
+        };
+        assert!(!is_retry_safe(Operation::Batch, &batch));
+    }
+}
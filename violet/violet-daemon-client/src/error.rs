@@ -0,0 +1,56 @@
+use std::time::Duration;
+use thiserror::Error;
+use violet_daemon::ErrorCode;
+
+#[derive(Error, Debug)]
+pub enum DaemonClientError {
+    #[error("Failed to connect to daemon at {path}: {source}")]
+    Connect {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Daemon did not answer within {0:?}")]
+    Timeout(Duration),
+
+    #[error("Timeout must be greater than zero")]
+    InvalidTimeout,
+
+    #[error("Connection to daemon failed: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Daemon closed the connection")]
+    ConnectionClosed,
+
+    #[error("Request of {len} bytes exceeds the {max} byte frame limit")]
+    RequestTooLarge { len: usize, max: usize },
+
+    #[error("Invalid response from daemon: {0}")]
+    InvalidResponse(String),
+
+    /// The daemon answered with a machine-readable failure
+    #[error("Daemon refused the request ({}): {message}", .code.as_str())]
+    Refused { code: ErrorCode, message: String },
+
+    /// The daemon answered with a failure that carries no error code
+    #[error("Daemon request failed: {0}")]
+    Failed(String),
+}
+
+impl DaemonClientError {
+    /// Error code the daemon gave for a refused request, if any
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            DaemonClientError::Refused { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Whether the connection broke, so a retry on a new one may succeed
+    pub(crate) fn is_connection_lost(&self) -> bool {
+        matches!(self, DaemonClientError::Io(_) | DaemonClientError::ConnectionClosed)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, DaemonClientError>;
@@ -0,0 +1,34 @@
+//! Client for the Violet daemon's Unix socket
+//!
+//! [`DaemonClient`] is async; [`blocking::DaemonClient`] offers the same
+//! operations for code without a tokio runtime.
+
+pub mod blocking;
+pub mod client;
+pub mod error;
+
+pub use client::{DaemonClient, DaemonClientBuilder, Pong, DEFAULT_TIMEOUT};
+pub use error::{DaemonClientError, Result};
+
+// Request and result types used by the client's methods
+pub use violet_daemon::{BatchItem, BatchItemResult, ErrorCode, HelloInfo, Operation, RequestData, StatsSnapshot};
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::path::Path;
+    use violet_daemon::DaemonServer;
+
+    /// Hex KEK served by the tests' mock Keys servers
+    pub const KEK_HEX: &str = "5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a";
+
+    /// A daemon on `socket_path` backed by the Keys server at `server_url`
+    pub fn daemon(socket_path: &Path, server_url: &str) -> DaemonServer {
+        DaemonServer::new(socket_path.display().to_string(), server_url.to_string())
+    }
+
+    /// Bind `server` and serve it on the current runtime
+    pub async fn spawn_daemon(server: DaemonServer) {
+        let daemon = server.bind().await.unwrap();
+        tokio::spawn(daemon.serve());
+    }
+}
@@ -0,0 +1,337 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use crate::protocol::{ErrorCode, Operation, Timings};
+
+/// Records waiting for the audit writer; beyond this, new records are
+/// dropped (or, in strict mode, wait for room)
+pub const AUDIT_QUEUE_CAPACITY: usize = 1024;
+
+/// One audited daemon operation
+///
+/// Records identify the caller, key, payload sizes and outcome only; they
+/// never carry plaintext, ciphertext or key material.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+
+    /// `id` of the request (for a batch item, of the batch)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub request_id: Option<String>,
+
+    pub operation: Operation,
+
+    /// KEK used, when known (absent if the request failed before a key was chosen)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub key_id: Option<String>,
+
+    /// Plaintext size in bytes, when the operation has one
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub plaintext_bytes: Option<u64>,
+
+    /// Ciphertext size in bytes (without nonce, tag or wrapped key), when the operation has one
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ciphertext_bytes: Option<u64>,
+
+    /// Credentials of the process on the other end of a Unix socket
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub peer: Option<PeerCredentials>,
+
+    pub success: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error_code: Option<ErrorCode>,
+
+    /// Where the request's time went, for top-level requests
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub timings: Option<Timings>,
+}
+
+impl AuditRecord {
+    pub fn new(operation: Operation, key_id: Option<String>, success: bool, error: Option<String>) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self {
+            timestamp_ms,
+            request_id: None,
+            operation,
+            key_id,
+            plaintext_bytes: None,
+            ciphertext_bytes: None,
+            peer: None,
+            success,
+            error,
+            error_code: None,
+            timings: None,
+        }
+    }
+}
+
+/// Identity of a Unix socket client, as reported by the kernel (`SO_PEERCRED`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+
+    /// Absent on platforms that do not report it
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pid: Option<i32>,
+}
+
+impl From<tokio::net::unix::UCred> for PeerCredentials {
+    fn from(cred: tokio::net::unix::UCred) -> Self {
+        Self {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+        }
+    }
+}
+
+/// Destination for audit records
+///
+/// Sinks are called from the [`AuditLog`] writer thread, one record at a
+/// time and in order, so they may block.
+pub trait AuditSink: Send + Sync {
+    fn record(&self, record: &AuditRecord) -> std::io::Result<()>;
+}
+
+type QueuedRecord = (AuditRecord, Option<oneshot::Sender<std::io::Result<()>>>);
+
+/// Queue in front of an [`AuditSink`], drained by a dedicated writer thread
+///
+/// By default recording never waits: the record is queued and a failure to
+/// write it (or a full queue) is logged without affecting the request. In
+/// strict mode, recording waits until the sink has written the record and
+/// returns its error, so the request can be failed instead.
+pub struct AuditLog {
+    sender: mpsc::Sender<QueuedRecord>,
+    strict: bool,
+}
+
+impl AuditLog {
+    /// Start a writer thread passing queued records to `sink`
+    ///
+    /// The thread exits once the `AuditLog` is dropped and the queue is drained.
+    pub fn new(sink: Arc<dyn AuditSink>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<QueuedRecord>(AUDIT_QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("violet-audit".to_string())
+            .spawn(move || {
+                while let Some((record, written)) = receiver.blocking_recv() {
+                    let result = sink.record(&record);
+                    if let Err(e) = &result {
+                        tracing::error!("Failed to write audit record: {}", e);
+                    }
+                    if let Some(written) = written {
+                        let _ = written.send(result);
+                    }
+                }
+            })
+            .expect("failed to spawn audit writer thread");
+        Self { sender, strict: false }
+    }
+
+    /// Wait for each record to be written and report failures to the caller
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Queue `record`; in strict mode, wait until it is written
+    ///
+    /// # Errors
+    /// Only in strict mode: the sink's error, or `BrokenPipe` if the writer has stopped.
+    pub async fn record(&self, record: AuditRecord) -> std::io::Result<()> {
+        if !self.strict {
+            match self.sender.try_send((record, None)) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    tracing::error!("Audit log queue is full; dropped audit record");
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    tracing::error!("Audit log writer has stopped; dropped audit record");
+                }
+            }
+            return Ok(());
+        }
+
+        let stopped = || std::io::Error::new(std::io::ErrorKind::BrokenPipe, "audit log writer has stopped");
+        let (written, result) = oneshot::channel();
+        self.sender.send((record, Some(written))).await.map_err(|_| stopped())?;
+        result.await.map_err(|_| stopped())?
+    }
+}
+
+/// Line written by [`FileAuditSink`]: the record plus the hash of the previous line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChainedRecord {
+    #[serde(flatten)]
+    record: AuditRecord,
+
+    /// Hex SHA-256 of the previous line (all zeros for the first line)
+    prev_hash: String,
+}
+
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Appends audit records to a file as JSON lines
+///
+/// Each line includes the SHA-256 of the line before it, so editing, removing
+/// or reordering earlier lines is detected by [`FileAuditSink::verify`].
+pub struct FileAuditSink {
+    path: PathBuf,
+    state: Mutex<FileState>,
+}
+
+struct FileState {
+    file: File,
+    last_hash: String,
+}
+
+impl FileAuditSink {
+    /// Open (or create) an audit log, continuing the hash chain of any existing lines
+    pub fn open(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let last_hash = match File::open(&path) {
+            Ok(file) => {
+                let mut last_hash = GENESIS_HASH.to_string();
+                for line in BufReader::new(file).lines() {
+                    last_hash = hash_line(&line?);
+                }
+                last_hash
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => GENESIS_HASH.to_string(),
+            Err(e) => return Err(e),
+        };
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            path,
+            state: Mutex::new(FileState { file, last_hash }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check the hash chain of an audit log, returning the number of records
+    ///
+    /// # Errors
+    /// Returns `InvalidData` naming the first line that does not follow from the one before it.
+    pub fn verify(path: impl AsRef<Path>) -> std::io::Result<usize> {
+        let file = File::open(path)?;
+        let mut expected = GENESIS_HASH.to_string();
+        let mut count = 0;
+
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let chained: ChainedRecord = serde_json::from_str(&line).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("line {}: {}", index + 1, e))
+            })?;
+            if chained.prev_hash != expected {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("line {}: hash chain broken", index + 1),
+                ));
+            }
+            expected = hash_line(&line);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn record(&self, record: &AuditRecord) -> std::io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let chained = ChainedRecord {
+            record: record.clone(),
+            prev_hash: state.last_hash.clone(),
+        };
+
+        let line = serde_json::to_string(&chained)?;
+        writeln!(state.file, "{}", line)
+            .and_then(|_| state.file.flush())
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", self.path.display(), e)))?;
+        state.last_hash = hash_line(&line);
+        Ok(())
+    }
+}
+
+fn hash_line(line: &str) -> String {
+    Sha256::digest(line.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(key_id: &str, success: bool) -> AuditRecord {
+        AuditRecord::new(Operation::Encrypt, Some(key_id.to_string()), success, None)
+    }
+
+    #[test]
+    fn test_file_sink_appends_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+
+        let sink = FileAuditSink::open(&path).unwrap();
+        sink.record(&record("key-1", true)).unwrap();
+        sink.record(&record("key-2", false)).unwrap();
+        drop(sink);
+
+        // Reopening continues the existing chain
+        FileAuditSink::open(&path).unwrap().record(&record("key-3", true)).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<AuditRecord> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].key_id.as_deref(), Some("key-2"));
+        assert!(!records[1].success);
+
+        assert_eq!(FileAuditSink::verify(&path).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+
+        let sink = FileAuditSink::open(&path).unwrap();
+        for i in 0..3 {
+            sink.record(&record(&format!("key-{}", i), true)).unwrap();
+        }
+        drop(sink);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, contents.replacen("key-0", "key-9", 1)).unwrap();
+
+        let err = FileAuditSink::verify(&path).unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+    }
+}
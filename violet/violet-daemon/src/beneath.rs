@@ -0,0 +1,120 @@
+//! Opening files beneath an allowed directory without following symlinks
+//!
+//! File operations check a request path in its canonical form, but opening
+//! that path afterwards would walk it again, so a directory or file swapped
+//! for a symlink in between would take the open somewhere else. Instead the
+//! allowed directory is opened and every component below it is opened
+//! relative to its parent's descriptor with `O_NOFOLLOW`, which fails on a
+//! symlink rather than following it.
+
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path};
+
+/// An open directory inside an allowed directory
+#[derive(Debug)]
+pub(crate) struct Dir {
+    fd: OwnedFd,
+}
+
+impl Dir {
+    /// Open `dir`, which must be `root` or beneath it, one component at a
+    /// time from `root`
+    pub(crate) fn open_beneath(root: &Path, dir: &Path) -> io::Result<Self> {
+        let relative = dir.strip_prefix(root).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not inside {}", dir.display(), root.display()))
+        })?;
+        let root = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY)
+            .open(root)?;
+        let mut fd = OwnedFd::from(root);
+        for component in relative.components() {
+            let Component::Normal(name) = component else {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not canonical", dir.display())));
+            };
+            fd = openat(fd.as_fd(), name, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+        }
+        Ok(Self { fd })
+    }
+
+    /// Open the existing file `name` in this directory for reading
+    pub(crate) fn open(&self, name: &OsStr) -> io::Result<File> {
+        openat(self.fd.as_fd(), name, libc::O_RDONLY, 0).map(File::from)
+    }
+
+    /// Create the file `name`, which must not exist yet, readable by the
+    /// daemon's user only
+    pub(crate) fn create_new(&self, name: &OsStr) -> io::Result<File> {
+        openat(self.fd.as_fd(), name, libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL, 0o600).map(File::from)
+    }
+
+    /// Remove the file `name` from this directory
+    pub(crate) fn remove(&self, name: &OsStr) -> io::Result<()> {
+        let name = CString::new(name.as_bytes())?;
+        // SAFETY: the descriptor is open and `name` is a NUL-terminated string
+        if unsafe { libc::unlinkat(self.fd.as_raw_fd(), name.as_ptr(), 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Open `name` relative to `dir` with `flags`, refusing a symlink
+fn openat(dir: BorrowedFd<'_>, name: &OsStr, flags: libc::c_int, mode: libc::mode_t) -> io::Result<OwnedFd> {
+    let name = CString::new(name.as_bytes())?;
+    let flags = flags | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    // SAFETY: the descriptor is open and `name` is a NUL-terminated string
+    let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags, libc::c_uint::from(mode)) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: openat returned a new descriptor that nothing else owns
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_open_create_and_remove_beneath_root() {
+        let root = TempDir::new().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+        std::fs::create_dir(root_path.join("sub")).unwrap();
+
+        let dir = Dir::open_beneath(&root_path, &root_path.join("sub")).unwrap();
+        dir.create_new(OsStr::new("file")).unwrap().write_all(b"data").unwrap();
+        assert!(dir.create_new(OsStr::new("file")).is_err());
+
+        let mut contents = String::new();
+        dir.open(OsStr::new("file")).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "data");
+
+        dir.remove(OsStr::new("file")).unwrap();
+        assert!(!root_path.join("sub/file").exists());
+    }
+
+    #[test]
+    fn test_symlinks_are_not_followed() {
+        let root = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+        std::fs::write(outside.path().join("secret"), b"secret").unwrap();
+        symlink(outside.path(), root_path.join("linked_dir")).unwrap();
+        symlink(outside.path().join("secret"), root_path.join("linked_file")).unwrap();
+
+        assert!(Dir::open_beneath(&root_path, &root_path.join("linked_dir")).is_err());
+        let dir = Dir::open_beneath(&root_path, &root_path).unwrap();
+        assert!(dir.open(OsStr::new("linked_file")).is_err());
+        assert!(dir.create_new(OsStr::new("linked_file")).is_err());
+        assert!(Dir::open_beneath(&root_path, outside.path()).is_err());
+    }
+}
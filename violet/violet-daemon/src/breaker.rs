@@ -0,0 +1,219 @@
+//! Circuit breaker in front of the Keys server
+//!
+//! After `threshold` consecutive upstream failures the circuit opens and Keys
+//! server calls fail at once for `cooldown`, so a hung or down server costs
+//! callers nothing and is not hammered by their retries. When the cooldown
+//! expires a single call is let through as a probe: if it succeeds the
+//! circuit closes, otherwise it stays open for another cooldown.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default consecutive Keys server failures that open the circuit
+pub const DEFAULT_BREAKER_THRESHOLD: u32 = 5;
+
+/// Default time the circuit stays open before a probe is let through
+pub const DEFAULT_BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Whether Keys server calls are being let through, as reported by `stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CircuitState {
+    /// Calls go through
+    #[default]
+    Closed,
+    /// Calls fail fast until the cooldown expires
+    Open,
+    /// One probe call is in flight; the rest fail fast until it finishes
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half-open",
+        }
+    }
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Consecutive-failure circuit breaker shared by every connection
+pub(crate) struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    /// Set while the circuit is open
+    open_until: Option<Instant>,
+    /// A probe call is in flight
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    /// A breaker that opens after `threshold` failures in a row; zero never opens
+    pub(crate) fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Permission to make one call, or how long until the next probe may be
+    /// made (zero while a probe is in flight)
+    pub(crate) fn acquire(&self) -> Result<Permit<'_>, Duration> {
+        let mut state = self.state.lock().unwrap();
+        let Some(open_until) = state.open_until else {
+            return Ok(Permit { breaker: self, probe: false });
+        };
+        let now = Instant::now();
+        if state.probing || now < open_until {
+            return Err(open_until.saturating_duration_since(now));
+        }
+        tracing::info!("Probing the Keys server after {:?} with the circuit open", self.cooldown);
+        state.probing = true;
+        Ok(Permit { breaker: self, probe: true })
+    }
+
+    /// Current state and the number of upstream failures in a row
+    pub(crate) fn state(&self) -> (CircuitState, u32) {
+        let state = self.state.lock().unwrap();
+        let circuit = match (state.open_until, state.probing) {
+            (None, _) => CircuitState::Closed,
+            (Some(_), true) => CircuitState::HalfOpen,
+            (Some(_), false) => CircuitState::Open,
+        };
+        (circuit, state.consecutive_failures)
+    }
+
+    fn record(&self, probe: bool, succeeded: bool) {
+        let mut state = self.state.lock().unwrap();
+        if probe {
+            state.probing = false;
+        }
+        if succeeded {
+            if state.open_until.take().is_some() {
+                tracing::info!("Keys server is answering again; closing the circuit");
+            }
+            state.consecutive_failures = 0;
+            return;
+        }
+
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        if probe {
+            tracing::warn!("Keys server probe failed; failing fast for another {:?}", self.cooldown);
+            state.open_until = Some(Instant::now() + self.cooldown);
+        } else if self.threshold > 0 && state.consecutive_failures >= self.threshold && state.open_until.is_none() {
+            tracing::warn!(
+                "Keys server failed {} times in a row; failing fast for {:?}",
+                state.consecutive_failures,
+                self.cooldown
+            );
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+/// One call allowed by the breaker, whose outcome must be reported with [`record`](Self::record)
+///
+/// A probe dropped without an outcome, e.g. because its request was
+/// cancelled, lets the next call probe instead.
+pub(crate) struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+}
+
+impl Permit<'_> {
+    /// Report whether the Keys server answered
+    pub(crate) fn record(mut self, succeeded: bool) {
+        self.breaker.record(self.probe, succeeded);
+        self.probe = false;
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe {
+            self.breaker.state.lock().unwrap().probing = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fail(breaker: &CircuitBreaker, times: u32) {
+        for _ in 0..times {
+            breaker.acquire().unwrap().record(false);
+        }
+    }
+
+    #[test]
+    fn test_opens_after_threshold_and_closes_after_probe() {
+        let breaker = CircuitBreaker::new(3, Duration::from_millis(50));
+        fail(&breaker, 2);
+        assert_eq!(breaker.state(), (CircuitState::Closed, 2));
+
+        fail(&breaker, 1);
+        assert_eq!(breaker.state(), (CircuitState::Open, 3));
+        assert!(breaker.acquire().is_err());
+
+        std::thread::sleep(Duration::from_millis(60));
+        let probe = breaker.acquire().unwrap();
+        assert_eq!(breaker.state().0, CircuitState::HalfOpen);
+        assert!(breaker.acquire().is_err(), "only one probe at a time");
+
+        probe.record(true);
+        assert_eq!(breaker.state(), (CircuitState::Closed, 0));
+        assert!(breaker.acquire().is_ok());
+    }
+
+    #[test]
+    fn test_failed_probe_reopens() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(50));
+        fail(&breaker, 1);
+        std::thread::sleep(Duration::from_millis(60));
+
+        breaker.acquire().unwrap().record(false);
+        assert_eq!(breaker.state().0, CircuitState::Open);
+        assert!(breaker.acquire().err().unwrap() > Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_dropped_probe_lets_another_probe() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(10));
+        fail(&breaker, 1);
+        std::thread::sleep(Duration::from_millis(20));
+
+        drop(breaker.acquire().unwrap());
+        assert_eq!(breaker.state().0, CircuitState::Open);
+        assert!(breaker.acquire().is_ok());
+    }
+
+    #[test]
+    fn test_success_resets_count_and_zero_threshold_never_opens() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        fail(&breaker, 1);
+        breaker.acquire().unwrap().record(true);
+        fail(&breaker, 1);
+        assert_eq!(breaker.state(), (CircuitState::Closed, 1));
+
+        let disabled = CircuitBreaker::new(0, Duration::from_secs(60));
+        fail(&disabled, 100);
+        assert_eq!(disabled.state().0, CircuitState::Closed);
+    }
+}
@@ -0,0 +1,226 @@
+use bytes::{Buf, BufMut, BytesMut};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+use thiserror::Error;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Size of the big-endian length prefix on each frame
+pub const FRAME_HEADER_LEN: usize = 4;
+
+/// Default cap on a single frame body
+pub const DEFAULT_MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// First bytes of a gzip stream, marking a compressed frame body
+///
+/// A JSON body starts with `{` or whitespace, so the two cannot be confused.
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Name of the frame compression reported by `hello`
+pub const GZIP: &str = "gzip";
+
+#[derive(Error, Debug)]
+pub enum FrameError {
+    #[error("Frame of {len} bytes exceeds the {max} byte limit")]
+    FrameTooLarge { len: usize, max: usize },
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Codec for the v2 daemon protocol: a 4-byte big-endian length followed by a JSON body
+///
+/// Frames larger than the configured maximum are rejected as soon as their
+/// header is read, without buffering the body.
+#[derive(Debug, Clone)]
+pub struct FrameCodec {
+    max_frame_len: usize,
+}
+
+impl FrameCodec {
+    pub fn new(max_frame_len: usize) -> Self {
+        Self { max_frame_len }
+    }
+
+    pub fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_LEN)
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = BytesMut;
+    type Error = FrameError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, FrameError> {
+        if src.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; FRAME_HEADER_LEN];
+        header.copy_from_slice(&src[..FRAME_HEADER_LEN]);
+        let len = u32::from_be_bytes(header) as usize;
+        if len > self.max_frame_len {
+            return Err(FrameError::FrameTooLarge {
+                len,
+                max: self.max_frame_len,
+            });
+        }
+
+        if src.len() < FRAME_HEADER_LEN + len {
+            src.reserve(FRAME_HEADER_LEN + len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(FRAME_HEADER_LEN);
+        Ok(Some(src.split_to(len)))
+    }
+}
+
+impl Encoder<Vec<u8>> for FrameCodec {
+    type Error = FrameError;
+
+    fn encode(&mut self, body: Vec<u8>, dst: &mut BytesMut) -> Result<(), FrameError> {
+        if body.len() > self.max_frame_len {
+            return Err(FrameError::FrameTooLarge {
+                len: body.len(),
+                max: self.max_frame_len,
+            });
+        }
+
+        dst.reserve(FRAME_HEADER_LEN + body.len());
+        dst.put_u32(body.len() as u32);
+        dst.extend_from_slice(&body);
+        Ok(())
+    }
+}
+
+/// Whether a frame body is gzip-compressed rather than plain JSON
+pub fn is_compressed(body: &[u8]) -> bool {
+    body.starts_with(&GZIP_MAGIC)
+}
+
+/// Gzip a frame body
+pub fn compress(body: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 4), Compression::fast());
+    // Writing to a Vec cannot fail
+    encoder.write_all(body).expect("gzip into memory");
+    encoder.finish().expect("gzip into memory")
+}
+
+/// Inflate a body written by [`compress`], to at most `max_len` bytes
+///
+/// Inflation stops as soon as the limit is passed, so a small frame cannot
+/// make the reader buffer an arbitrary amount.
+///
+/// # Errors
+/// `FrameError::FrameTooLarge` past the limit, where `len` counts only what was
+/// inflated before giving up, and `FrameError::Io` if the body is not gzip.
+pub fn decompress(body: &[u8], max_len: usize) -> Result<Vec<u8>, FrameError> {
+    let mut inflated = Vec::with_capacity(body.len().saturating_mul(4).min(max_len));
+    GzDecoder::new(body)
+        .take((max_len as u64).saturating_add(1))
+        .read_to_end(&mut inflated)?;
+    if inflated.len() > max_len {
+        return Err(FrameError::FrameTooLarge {
+            len: inflated.len(),
+            max: max_len,
+        });
+    }
+    Ok(inflated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(body: &[u8]) -> Vec<u8> {
+        let mut bytes = (body.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut codec = FrameCodec::default();
+        let mut buf = BytesMut::new();
+
+        codec.encode(b"{\"a\":1}".to_vec(), &mut buf).unwrap();
+        codec.encode(b"{}".to_vec(), &mut buf).unwrap();
+
+        assert_eq!(&codec.decode(&mut buf).unwrap().unwrap()[..], b"{\"a\":1}");
+        assert_eq!(&codec.decode(&mut buf).unwrap().unwrap()[..], b"{}");
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_split_reads() {
+        let mut codec = FrameCodec::default();
+        let bytes = frame(b"{\"operation\":\"encrypt\"}");
+        let mut buf = BytesMut::new();
+
+        // Feed one byte at a time: nothing is produced until the frame is complete
+        for (i, byte) in bytes.iter().enumerate() {
+            buf.put_u8(*byte);
+            let decoded = codec.decode(&mut buf).unwrap();
+            if i + 1 < bytes.len() {
+                assert!(decoded.is_none());
+            } else {
+                assert_eq!(&decoded.unwrap()[..], b"{\"operation\":\"encrypt\"}");
+            }
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_oversized_frame_rejected_from_header() {
+        let mut codec = FrameCodec::new(16);
+
+        // Only the header has arrived; the body is never buffered
+        let mut buf = BytesMut::from(&100u32.to_be_bytes()[..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(FrameError::FrameTooLarge { len: 100, max: 16 })
+        ));
+
+        let mut out = BytesMut::new();
+        assert!(matches!(
+            codec.encode(vec![0u8; 17], &mut out),
+            Err(FrameError::FrameTooLarge { len: 17, max: 16 })
+        ));
+    }
+
+    #[test]
+    fn test_empty_frame() {
+        let mut codec = FrameCodec::default();
+        let mut buf = BytesMut::from(&frame(b"")[..]);
+        assert!(codec.decode(&mut buf).unwrap().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_compressed_bodies() {
+        let body = format!(r#"{{"operation":"encrypt","data":{{"plaintext":"{}"}}}}"#, "QUFB".repeat(10_000));
+        let compressed = compress(body.as_bytes());
+        assert!(is_compressed(&compressed));
+        assert!(!is_compressed(body.as_bytes()));
+        assert!(compressed.len() < body.len() / 10);
+        assert_eq!(decompress(&compressed, body.len()).unwrap(), body.as_bytes());
+
+        // The limit applies to the inflated size, not the frame's
+        assert!(matches!(
+            decompress(&compressed, body.len() - 1),
+            Err(FrameError::FrameTooLarge { max, .. }) if max == body.len() - 1
+        ));
+
+        let mut corrupt = compressed.clone();
+        corrupt.truncate(compressed.len() / 2);
+        assert!(matches!(decompress(&corrupt, body.len()), Err(FrameError::Io(_))));
+        assert!(matches!(decompress(b"{}", 16), Err(FrameError::Io(_))));
+    }
+}
@@ -1,108 +1,3713 @@
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use violet_client::KeysClient;
-use violet_core::{Algorithm, EnvelopeEncryptor};
-use crate::protocol::{Request, Response, Operation};
+use bytes::BytesMut;
+use sha2::{Digest, Sha256};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+use zeroize::Zeroize;
+use violet_client::client::CallOptions;
+use violet_client::{ClientError, Key, KeysClient};
+use violet_core::{
+    Algorithm, EncryptionEnvelope, EnvelopeEncryptor, SecretKey, StreamEncryptor, StreamHeader, StreamOpener, VioletError,
+    DEFAULT_CHUNK_SIZE,
+};
+use crate::audit::{AuditLog, AuditRecord, AuditSink, PeerCredentials};
+use crate::beneath::Dir;
+use crate::breaker::{CircuitBreaker, DEFAULT_BREAKER_COOLDOWN, DEFAULT_BREAKER_THRESHOLD};
+use crate::codec::FrameError;
+use crate::idempotency::{request_digest, IdempotencyCache, DEFAULT_IDEMPOTENCY_CAPACITY};
+use crate::metrics::{base64_decoded_len, Metrics, StatsSnapshot};
+use crate::protocol::{
+    BatchItem, BatchItemResult, DaemonMode, ErrorCode, HelloInfo, Operation, Request, RequestData, Response, ResponseResult,
+    Timings, PROTOCOL_VERSION,
+};
 
+/// Default time a KEK is kept in memory after it was fetched or created
+pub const DEFAULT_KEK_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Default largest number of KEKs kept in memory at once
+pub const DEFAULT_KEK_CACHE_CAPACITY: usize = 1024;
+
+/// Default time the daemon waits for the Keys server on each call, well
+/// under the client's own 30 second default so a hung server fails fast
+pub const DEFAULT_KEYS_SERVER_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Default time a Keys server health probe result is reused by `ping`
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default largest number of items accepted in one `batch` request
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 1000;
+
+/// Batch items processed at once, and Keys server lookups in flight for a batch
+const BATCH_CONCURRENCY: usize = 8;
+
+/// Default payload size, in base64 bytes, from which encryption and
+/// decryption run on the blocking thread pool instead of the async worker
+pub const DEFAULT_BLOCKING_THRESHOLD: usize = 64 * 1024;
+
+/// Default largest number of payloads encrypted or decrypted on the blocking
+/// thread pool at once
+pub const DEFAULT_BLOCKING_THREADS: usize = 8;
+
+/// Key IDs a daemon may use, as defense in depth for a daemon serving one tenant
+///
+/// A key_id is permitted if it is listed exactly or starts with a listed
+/// prefix. An empty list permits every key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyAllowList {
+    pub key_ids: Vec<String>,
+    pub prefixes: Vec<String>,
+}
+
+impl KeyAllowList {
+    pub fn is_empty(&self) -> bool {
+        self.key_ids.is_empty() && self.prefixes.is_empty()
+    }
+
+    pub fn permits(&self, key_id: &str) -> bool {
+        self.is_empty()
+            || self.key_ids.iter().any(|allowed| allowed == key_id)
+            || self.prefixes.iter().any(|prefix| key_id.starts_with(prefix.as_str()))
+    }
+
+    /// Whether a key the Keys server creates could be permitted; with only
+    /// exact ids listed, a new id never is
+    fn permits_new_keys(&self) -> bool {
+        self.is_empty() || !self.prefixes.is_empty()
+    }
+}
+
+/// Why a KEK could not be fetched or created
+#[derive(Debug, Clone)]
+enum KeyError {
+    /// The Keys server failed or the key does not exist
+    Unavailable(String),
+    /// The key_id is outside the key allow-list
+    NotPermitted(String),
+    /// The circuit breaker is open, so the Keys server was not called
+    KeysServerDown(String),
+    /// An encrypt named no key_id and this daemon does not create keys for them
+    KeyIdRequired,
+}
+
+impl KeyError {
+    /// Prefix the message with what was being attempted
+    fn context(self, what: &str) -> Self {
+        match self {
+            KeyError::Unavailable(e) => KeyError::Unavailable(format!("{}: {}", what, e)),
+            KeyError::NotPermitted(e) => KeyError::NotPermitted(format!("{}: {}", what, e)),
+            KeyError::KeysServerDown(e) => KeyError::KeysServerDown(format!("{}: {}", what, e)),
+            KeyError::KeyIdRequired => KeyError::KeyIdRequired,
+        }
+    }
+
+    fn into_response(self) -> Response {
+        match self {
+            KeyError::Unavailable(e) => Response::failure(ErrorCode::KeyUnavailable, e),
+            KeyError::NotPermitted(e) => Response::failure(ErrorCode::KeyNotPermitted, e),
+            KeyError::KeysServerDown(e) => Response::failure(ErrorCode::KeysServerUnavailable, e),
+            e @ KeyError::KeyIdRequired => Response::failure(ErrorCode::InvalidRequest, e.to_string()),
+        }
+    }
+}
+
+impl From<String> for KeyError {
+    fn from(e: String) -> Self {
+        KeyError::Unavailable(e)
+    }
+}
+
+impl std::fmt::Display for KeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyError::Unavailable(e) | KeyError::NotPermitted(e) | KeyError::KeysServerDown(e) => f.write_str(e),
+            KeyError::KeyIdRequired => {
+                f.write_str("key_id is required: this daemon does not create keys for encrypt requests")
+            }
+        }
+    }
+}
+
+/// A KEK and its id, decoded once when it is fetched or created, so the
+/// cache and the requests using it only ever hold it in a [`SecretKey`]
+///
+/// Clones share the one locked buffer.
+#[derive(Debug, Clone)]
+struct Kek {
+    uuid: String,
+    secret: Arc<SecretKey>,
+}
+
+impl Kek {
+    /// Decode the hex key the Keys server sent, wiping the hex copy
+    fn decode(mut key: Key) -> Result<Self, KeyError> {
+        let secret = SecretKey::from_hex(&key.key);
+        key.key.zeroize();
+        match secret {
+            Ok(secret) => Ok(Self {
+                uuid: key.uuid,
+                secret: Arc::new(secret),
+            }),
+            Err(e) => Err(KeyError::Unavailable(format!("Key decode error: {}", e))),
+        }
+    }
+}
+
+tokio::task_local! {
+    /// `id` of the request being handled, forwarded to the Keys server
+    static REQUEST_ID: Option<String>;
+
+    /// Unix socket credentials of the client that sent the request, for the audit log
+    static PEER: Option<PeerCredentials>;
+
+    /// Time spent so far on KEKs and crypto for the request being handled
+    static TIMINGS: Cell<Timings>;
+}
+
+/// Handles daemon requests using a single `KeysClient` shared by all
+/// connections, so HTTP connections to the Keys server are pooled.
+///
+/// KEKs are cached in memory for `kek_cache_ttl`, so many requests for the
+/// same key_id cost one round-trip to the Keys server. At most
+/// `kek_cache_capacity` are kept; the oldest is evicted to make room.
+///
+/// Each Keys server call is cut off after `keys_server_timeout`, and a
+/// circuit breaker stops calling a server that keeps failing; see
+/// [`with_circuit_breaker`](Self::with_circuit_breaker).
 pub struct RequestHandler {
-    server_url: String,
+    client: Arc<KeysClient>,
+    idempotency: IdempotencyCache,
+    kek_cache: Mutex<HashMap<String, (Instant, Kek)>>,
+    kek_cache_ttl: Duration,
+    kek_cache_capacity: usize,
+    audit_log: Option<AuditLog>,
+    allow_key_export: bool,
+    require_key_id: bool,
+    mode: DaemonMode,
+    key_allow_list: KeyAllowList,
+    started_at: Instant,
+    health: Mutex<Option<(Instant, bool)>>,
+    health_check_interval: Duration,
+    keys_server_timeout: Option<Duration>,
+    breaker: CircuitBreaker,
+    max_batch_size: usize,
+    metrics: Arc<Metrics>,
+    shared_key_ttl: Option<Duration>,
+    /// KEK handed to keyless encrypts while it is younger than `shared_key_ttl`.
+    /// An async mutex, held while the key is created, so concurrent requests
+    /// wait for one key instead of each creating their own.
+    shared_key: tokio::sync::Mutex<Option<(Instant, Kek)>>,
+    stream_chunk_size: usize,
+    blocking_threshold: usize,
+    /// One permit per blocking thread crypto may occupy
+    blocking_threads: Arc<Semaphore>,
+    /// Canonical directories `encryptFile` and `decryptFile` may use; none disables them
+    file_dirs: Vec<PathBuf>,
 }
 
 impl RequestHandler {
-    pub fn new(server_url: &str) -> Self {
+    /// Create a handler with a new client for the Keys server
+    ///
+    /// The blocking client must not be built on an async runtime thread;
+    /// use `tokio::task::spawn_blocking` when calling this from async code.
+    pub fn new(server_url: &str) -> violet_client::Result<Self> {
+        Ok(Self::with_client(Arc::new(KeysClient::new(server_url)?)))
+    }
+
+    /// Create a handler around an existing client
+    pub fn with_client(client: Arc<KeysClient>) -> Self {
         Self {
-            server_url: server_url.to_string(),
+            client,
+            idempotency: IdempotencyCache::new(DEFAULT_IDEMPOTENCY_CAPACITY),
+            kek_cache: Mutex::new(HashMap::new()),
+            kek_cache_ttl: DEFAULT_KEK_CACHE_TTL,
+            kek_cache_capacity: DEFAULT_KEK_CACHE_CAPACITY,
+            audit_log: None,
+            allow_key_export: false,
+            require_key_id: false,
+            mode: DaemonMode::Full,
+            key_allow_list: KeyAllowList::default(),
+            started_at: Instant::now(),
+            health: Mutex::new(None),
+            health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+            keys_server_timeout: Some(DEFAULT_KEYS_SERVER_TIMEOUT),
+            breaker: CircuitBreaker::new(DEFAULT_BREAKER_THRESHOLD, DEFAULT_BREAKER_COOLDOWN),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            metrics: Arc::new(Metrics::new()),
+            shared_key_ttl: None,
+            shared_key: tokio::sync::Mutex::new(None),
+            stream_chunk_size: DEFAULT_CHUNK_SIZE,
+            blocking_threshold: DEFAULT_BLOCKING_THRESHOLD,
+            blocking_threads: Arc::new(Semaphore::new(DEFAULT_BLOCKING_THREADS)),
+            file_dirs: Vec::new(),
         }
     }
 
+    /// Counters updated by this handler, also reported by the `stats` operation
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Set the largest number of items accepted in one `batch` request
+    pub fn with_max_batch_size(mut self, max: usize) -> Self {
+        self.max_batch_size = max;
+        self
+    }
+
+    /// Set how long a Keys server health probe is reused by `ping`
+    pub fn with_health_check_interval(mut self, interval: Duration) -> Self {
+        self.health_check_interval = interval;
+        self
+    }
+
+    /// Set how long each Keys server call may take; zero leaves it to the client's timeout
+    ///
+    /// A call that runs out of time fails with `key_unavailable` and counts
+    /// towards the circuit breaker.
+    pub fn with_keys_server_timeout(mut self, timeout: Duration) -> Self {
+        self.keys_server_timeout = (!timeout.is_zero()).then_some(timeout);
+        self
+    }
+
+    /// Stop calling the Keys server for `cooldown` after `threshold`
+    /// consecutive failures; a zero threshold disables the breaker
+    ///
+    /// Failures are calls that time out, cannot connect, or get a 502, 503 or
+    /// 504; an unknown key is not one. While the circuit is open, requests
+    /// needing the Keys server fail at once with `keys_server_unavailable`.
+    /// Once the cooldown expires one call is let through as a probe, and its
+    /// outcome closes the circuit or keeps it open for another cooldown.
+    pub fn with_circuit_breaker(mut self, threshold: u32, cooldown: Duration) -> Self {
+        self.breaker = CircuitBreaker::new(threshold, cooldown);
+        self
+    }
+
+    /// Let `createKey` requests ask for the new key's material
+    ///
+    /// Off by default: anyone who can reach the daemon could then obtain KEKs,
+    /// so only enable it when every caller is trusted.
+    pub fn allow_key_export(mut self, allow: bool) -> Self {
+        self.allow_key_export = allow;
+        self
+    }
+
+    /// Refuse encrypts without a key_id instead of creating a key for them
+    ///
+    /// A mistyped or forgotten key_id would otherwise seal data under a new
+    /// KEK nobody knows about. Such requests fail with `invalid_request`,
+    /// streams and batch items included; `createKey` is unaffected.
+    pub fn require_key_id(mut self, require: bool) -> Self {
+        self.require_key_id = require;
+        self
+    }
+
+    /// Only accept the operations `mode` allows; the rest fail with
+    /// `operation_not_allowed` before any Keys server call or crypto
+    pub fn with_mode(mut self, mode: DaemonMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Only use KEKs whose key_id `list` permits
+    ///
+    /// Requests naming another key, for data, an envelope, a stream or a
+    /// rewrap target, fail with `key_not_permitted` before the key is fetched.
+    /// A key created for the request must be permitted too; with no prefixes
+    /// listed a new id never is, so key creation is refused outright.
+    pub fn with_key_allow_list(mut self, list: KeyAllowList) -> Self {
+        self.key_allow_list = list;
+        self
+    }
+
+    /// Record every encrypt and decrypt operation to `sink`, without waiting for the writes
+    pub fn with_audit_sink(self, sink: Arc<dyn AuditSink>) -> Self {
+        self.with_audit_log(AuditLog::new(sink))
+    }
+
+    /// Record every encrypt and decrypt operation to `log`
+    ///
+    /// If the log is strict, an operation whose record cannot be written
+    /// fails with `audit_failed` and its result is withheld.
+    pub fn with_audit_log(mut self, log: AuditLog) -> Self {
+        self.audit_log = Some(log);
+        self
+    }
+
+    /// Encrypt all requests without a key_id under one KEK, replaced every `ttl`
+    ///
+    /// By default each such request gets a KEK of its own. Sharing one cuts
+    /// Keys server round-trips at the cost of more data under each key.
+    pub fn with_shared_key_ttl(mut self, ttl: Duration) -> Self {
+        self.shared_key_ttl = Some(ttl);
+        self
+    }
+
+    /// Set the plaintext bytes per chunk of `encryptStream` output, which is
+    /// also the largest plaintext frame `decryptStream` sends
+    ///
+    /// Each output frame is this plus [`STREAM_FRAME_OVERHEAD`](violet_core::STREAM_FRAME_OVERHEAD)
+    /// bytes, so it must fit the transport's frame limit.
+    pub fn with_stream_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.stream_chunk_size = chunk_size;
+        self
+    }
+
+    /// Set the payload size from which crypto runs on the blocking thread pool
+    ///
+    /// Below it, base64 decoding and AES run inline on the async worker, where
+    /// handing them to another thread would cost more than the work itself.
+    /// Zero sends every payload to the pool.
+    pub fn with_blocking_threshold(mut self, threshold: usize) -> Self {
+        self.blocking_threshold = threshold;
+        self
+    }
+
+    /// Set how many payloads may be encrypted or decrypted on the blocking
+    /// thread pool at once (at least one)
+    ///
+    /// Further payloads over the threshold wait for a thread to free up, so a
+    /// burst of large requests occupies at most this many threads and cores
+    /// and leaves the rest of the machine, and the async workers, to others.
+    pub fn with_blocking_threads(mut self, threads: usize) -> Self {
+        self.blocking_threads = Arc::new(Semaphore::new(threads.max(1)));
+        self
+    }
+
+    /// Let `encryptFile` and `decryptFile` read and create files under `dirs`
+    ///
+    /// Without any directory both operations are refused with
+    /// `operation_not_allowed`, so the socket is not a way to read or write
+    /// the daemon's files. Request paths are resolved, following `..` and
+    /// symlinks, before they are checked against the directories, and those
+    /// outside fail with `path_not_permitted`. The files are then opened from
+    /// the directory down without following symlinks, so a path changed after
+    /// the check cannot lead outside either.
+    ///
+    /// # Errors
+    /// If a directory cannot be resolved, e.g. because it does not exist.
+    pub fn with_file_dirs(mut self, dirs: impl IntoIterator<Item = PathBuf>) -> std::io::Result<Self> {
+        self.file_dirs = dirs.into_iter().map(|dir| dir.canonicalize()).collect::<std::io::Result<_>>()?;
+        Ok(self)
+    }
+
+    /// Set how long fetched KEKs are cached in memory; zero disables the cache
+    pub fn with_kek_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.kek_cache_ttl = ttl;
+        self
+    }
+
+    /// Set the largest number of KEKs cached in memory; zero disables the cache
+    pub fn with_kek_cache_capacity(mut self, capacity: usize) -> Self {
+        self.kek_cache_capacity = capacity;
+        self
+    }
+
+    /// Set the largest number of idempotency keys remembered; zero disables replay
+    pub fn with_idempotency_capacity(mut self, capacity: usize) -> Self {
+        self.idempotency = IdempotencyCache::new(capacity);
+        self
+    }
+
+    /// Handle one request, echoing its `id` in the response
+    ///
+    /// Everything logged while handling it, including Keys server calls, is
+    /// inside a `daemon.request` span carrying the id. Once handled, the span
+    /// also records the algorithm, a hash of the key_id (see [`key_id_hash`]),
+    /// the outcome and the request's [`Timings`].
     pub async fn handle(&self, request: Request) -> Response {
-        match request.operation {
-            Operation::Encrypt => self.handle_encrypt(request).await,
-            Operation::Decrypt => self.handle_decrypt(request).await,
+        self.handle_from(request, None).await
+    }
+
+    /// Handle one request from a client whose credentials are known, recording
+    /// them in the audit log
+    pub async fn handle_from(&self, request: Request, peer: Option<PeerCredentials>) -> Response {
+        let id = request.id.clone();
+        let operation = request.operation;
+        let mut response = in_request_scope(id.clone(), operation, peer, self.dispatch(request)).await;
+        response.id = id;
+        response
+    }
+
+    /// Handle an `encryptStream` or `decryptStream` request whose payload
+    /// follows it as raw frames on `frames`
+    ///
+    /// The client sends input frames of any size and ends its input with an
+    /// empty frame. Output frames are sent as they are produced, followed by
+    /// an empty frame; the returned response is the final message, which the
+    /// caller sends. Concatenated, the output of `encryptStream` is the
+    /// [`StreamEncryptor`] format, which `decryptStream` takes as input.
+    /// Memory use is bounded by the chunk size and the frame limit.
+    ///
+    /// A stream that fails part way still has its input read through the
+    /// empty frame, so the connection stays usable; output already sent must
+    /// be discarded. Under a strict audit log a failed audit write ends the
+    /// stream with `audit_failed`, but cannot recall output already sent.
+    ///
+    /// # Errors
+    /// Only transport failures, after which the connection cannot be resynchronised.
+    pub async fn handle_stream<T>(
+        &self,
+        request: Request,
+        peer: Option<PeerCredentials>,
+        frames: &mut T,
+    ) -> Result<Response, FrameError>
+    where
+        T: Stream<Item = Result<BytesMut, FrameError>> + Sink<Vec<u8>, Error = FrameError> + Unpin,
+    {
+        let id = request.id.clone();
+        let operation = request.operation;
+        let mut response = in_request_scope(id.clone(), operation, peer, self.dispatch_stream(request, frames)).await?;
+        response.id = id;
+        Ok(response)
+    }
+
+    async fn dispatch(&self, request: Request) -> Response {
+        let started = Instant::now();
+        let operation = request.operation;
+        let include_timings = request.include_timings;
+        let plaintext_len = base64_decoded_len(&request.data.plaintext);
+        let ciphertext_len = envelope_ciphertext_len(&request.data);
+        let requested_key_id = requested_key_id(operation, &request.data);
+        let requested_algorithm = match &request.data.envelope {
+            Some(envelope) => Some(envelope.algorithm.clone()),
+            None => request.data.algorithm.map(|algorithm| algorithm.as_str().to_string()),
+        };
+
+        // Only major version 1 exists so far; absent means 1
+        let version = request.version.unwrap_or(1);
+        let response = if !(1..=PROTOCOL_VERSION).contains(&version) {
+            tracing::debug!("Rejecting request with protocol version {}", version);
+            Response::unsupported_version(version)
+        } else if let Some(refusal) = self.mode.refusal(operation, &request.data) {
+            tracing::debug!("Refusing {} in {} mode", operation.as_str(), self.mode);
+            Response {
+                version,
+                ..Response::failure(ErrorCode::OperationNotAllowed, refusal)
+            }
+        } else {
+            let mut response = match operation {
+                Operation::Encrypt => self.handle_encrypt(request).await,
+                Operation::Decrypt => self.handle_decrypt(request).await,
+                Operation::Hello => Response::success_hello(HelloInfo {
+                    mode: self.mode,
+                    ..HelloInfo::current()
+                }),
+                Operation::CreateKey => self.handle_create_key(request).await,
+                Operation::Rewrap => self.handle_rewrap(request).await,
+                Operation::Ping => self.handle_ping().await,
+                Operation::Batch => self.handle_batch(request).await,
+                Operation::Stats => {
+                    let (keys_server_circuit, keys_server_failures) = self.breaker.state();
+                    Response::success_stats(StatsSnapshot {
+                        mode: self.mode,
+                        keys_server_circuit,
+                        keys_server_failures,
+                        ..self.metrics.snapshot()
+                    })
+                }
+                Operation::FlushKeys => {
+                    let flushed = self.flush_keys(request.data.key_id.as_deref()).await;
+                    Response::success_keys_flushed(flushed)
+                }
+                Operation::EncryptFile => self.handle_encrypt_file(request.data).await,
+                Operation::DecryptFile => self.handle_decrypt_file(request.data).await,
+                Operation::EncryptStream | Operation::DecryptStream => Response::failure(
+                    ErrorCode::UnsupportedOperation,
+                    format!("{} needs the length-prefixed framing", operation.as_str()),
+                ),
+            };
+            response.version = version;
+            response
+        };
+
+        // Encrypts without a key_id and key creation only learn theirs from the
+        // result, and file operations their sizes too
+        let (key_id, algorithm) = match &response.result {
+            Some(ResponseResult::Encrypt { envelope }) => {
+                (Some(envelope.key_id.clone()), Some(envelope.algorithm.clone()))
+            }
+            Some(ResponseResult::KeyCreated { key_id, .. }) => (Some(key_id.clone()), None),
+            Some(ResponseResult::FileFinished { stream_header, .. }) => {
+                (Some(stream_header.key_id.clone()), Some(stream_header.algorithm.clone()))
+            }
+            _ => (requested_key_id, requested_algorithm),
+        };
+        let plaintext_len = match &response.result {
+            Some(ResponseResult::FileFinished { plaintext_bytes, .. }) => *plaintext_bytes,
+            _ => plaintext_len,
+        };
+        let span = tracing::Span::current();
+        let timings = finish_timings(&span, started.elapsed());
+        // Batch items are audited one by one
+        let mut response = if matches!(operation, Operation::Hello | Operation::Ping | Operation::Batch | Operation::Stats) {
+            response
+        } else {
+            self.audit(operation, key_id.clone(), plaintext_len, ciphertext_len, Some(timings), response).await
+        };
+
+        record_outcome(&span, key_id.as_deref(), algorithm.as_deref(), &response);
+        self.metrics.record_request(operation, &response, plaintext_len, started.elapsed());
+        if include_timings {
+            response.timings = Some(timings);
+        }
+        response
+    }
+
+    async fn dispatch_stream<T>(&self, request: Request, frames: &mut T) -> Result<Response, FrameError>
+    where
+        T: Stream<Item = Result<BytesMut, FrameError>> + Sink<Vec<u8>, Error = FrameError> + Unpin,
+    {
+        let started = Instant::now();
+        let operation = request.operation;
+        let include_timings = request.include_timings;
+        let requested_key_id = requested_key_id(operation, &request.data);
+
+        let version = request.version.unwrap_or(1);
+        let response = if !(1..=PROTOCOL_VERSION).contains(&version) {
+            tracing::debug!("Rejecting request with protocol version {}", version);
+            fail_stream(frames, Response::unsupported_version(version)).await?
+        } else if let Some(refusal) = self.mode.refusal(operation, &request.data) {
+            tracing::debug!("Refusing {} in {} mode", operation.as_str(), self.mode);
+            let mut response = fail_stream(frames, Response::failure(ErrorCode::OperationNotAllowed, refusal)).await?;
+            response.version = version;
+            response
+        } else {
+            let mut response = match operation {
+                Operation::EncryptStream => self.encrypt_stream(request.data, frames).await?,
+                Operation::DecryptStream => self.decrypt_stream(frames).await?,
+                _ => {
+                    let response = Response::failure(
+                        ErrorCode::UnsupportedOperation,
+                        format!("{} is not a streaming operation", operation.as_str()),
+                    );
+                    fail_stream(frames, response).await?
+                }
+            };
+            response.version = version;
+            response
+        };
+        frames.send(Vec::new()).await?;
+
+        let (key_id, algorithm, plaintext_len) = match &response.result {
+            Some(ResponseResult::StreamFinished {
+                stream_header,
+                plaintext_bytes,
+                ..
+            }) => (
+                Some(stream_header.key_id.clone()),
+                Some(stream_header.algorithm.clone()),
+                *plaintext_bytes,
+            ),
+            _ => (requested_key_id, None, 0),
+        };
+        let span = tracing::Span::current();
+        let timings = finish_timings(&span, started.elapsed());
+        let mut response = self.audit(operation, key_id.clone(), plaintext_len, None, Some(timings), response).await;
+
+        record_outcome(&span, key_id.as_deref(), algorithm.as_deref(), &response);
+        self.metrics.record_request(operation, &response, plaintext_len, started.elapsed());
+        if include_timings {
+            response.timings = Some(timings);
+        }
+        Ok(response)
+    }
+
+    /// Encrypt input frames into chunks of `stream_chunk_size`, sending each
+    /// sealed chunk as soon as it is full
+    async fn encrypt_stream<T>(&self, data: RequestData, frames: &mut T) -> Result<Response, FrameError>
+    where
+        T: Stream<Item = Result<BytesMut, FrameError>> + Sink<Vec<u8>, Error = FrameError> + Unpin,
+    {
+        let algorithm = data.algorithm.unwrap_or_default();
+        let key = if let Some(kid) = data.key_id {
+            self.get_key(kid).await.map_err(|e| e.context("Failed to get key"))
+        } else {
+            self.keyless_encrypt_key().await.map_err(|e| e.context("Failed to create key"))
+        };
+        let key = match key {
+            Ok(key) => key,
+            Err(e) => return fail_stream(frames, e.into_response()).await,
+        };
+        let sealer = StreamEncryptor::new(algorithm)
+            .with_chunk_size(self.stream_chunk_size)
+            .and_then(|encryptor| encryptor.sealer(&key.secret, key.uuid));
+        let (mut sealer, preamble) = match sealer {
+            Ok(sealer) => sealer,
+            Err(e) => return fail_stream(frames, encryption_failed(e)).await,
+        };
+        frames.send(preamble).await?;
+
+        let mut pending = Vec::with_capacity(self.stream_chunk_size);
+        let mut plaintext_bytes: u64 = 0;
+        loop {
+            let frame = next_input(frames).await?;
+            if frame.is_empty() {
+                break;
+            }
+            plaintext_bytes += frame.len() as u64;
+
+            let mut input = &frame[..];
+            while !input.is_empty() {
+                let take = (self.stream_chunk_size - pending.len()).min(input.len());
+                pending.extend_from_slice(&input[..take]);
+                input = &input[take..];
+                if pending.len() == self.stream_chunk_size {
+                    match sealer.seal_chunk(&pending) {
+                        Ok(chunk) => frames.send(chunk).await?,
+                        Err(e) => return fail_stream(frames, encryption_failed(e)).await,
+                    }
+                    pending.clear();
+                }
+            }
+        }
+
+        let stream_header = sealer.header().clone();
+        if !pending.is_empty() {
+            match sealer.seal_chunk(&pending) {
+                Ok(chunk) => frames.send(chunk).await?,
+                Err(e) => return Ok(encryption_failed(e)),
+            }
+        }
+        let (footer, chunks) = match sealer.finish() {
+            Ok(footer) => footer,
+            Err(e) => return Ok(encryption_failed(e)),
+        };
+        frames.send(footer).await?;
+        Ok(Response::success_stream(stream_header, chunks, plaintext_bytes))
+    }
+
+    /// Decrypt a stream pushed in input frames of any size, sending the
+    /// plaintext of each chunk once it authenticates
+    async fn decrypt_stream<T>(&self, frames: &mut T) -> Result<Response, FrameError>
+    where
+        T: Stream<Item = Result<BytesMut, FrameError>> + Sink<Vec<u8>, Error = FrameError> + Unpin,
+    {
+        let mut opener = StreamOpener::new();
+        let mut unlocked = false;
+        let mut failure = None;
+        loop {
+            let frame = next_input(frames).await?;
+            if frame.is_empty() {
+                break;
+            }
+            // After a failure the rest of the input is only read and dropped
+            if failure.is_some() {
+                continue;
+            }
+
+            opener.push(&frame);
+            if !unlocked {
+                match self.unlock_stream(&mut opener).await {
+                    Ok(true) => unlocked = true,
+                    Ok(false) => continue,
+                    Err(response) => {
+                        failure = Some(response);
+                        continue;
+                    }
+                }
+            }
+            loop {
+                match opener.next_chunk() {
+                    Ok(Some(plaintext)) => {
+                        for piece in plaintext.chunks(self.stream_chunk_size) {
+                            frames.send(piece.to_vec()).await?;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        failure = Some(decryption_failed(e));
+                        break;
+                    }
+                }
+            }
+        }
+        if let Some(response) = failure {
+            return Ok(response);
+        }
+
+        let stream_header = opener.header().ok().flatten().cloned();
+        let chunks = opener.chunks();
+        Ok(match opener.finish() {
+            Ok(plaintext_bytes) => Response::success_stream(
+                stream_header.expect("a finished stream has a header"),
+                chunks,
+                plaintext_bytes,
+            ),
+            Err(e) => decryption_failed(e),
+        })
+    }
+
+    /// Unlock `opener` with the KEK its header names, once the header has arrived
+    ///
+    /// Returns whether the stream is unlocked, or the response to fail it with.
+    async fn unlock_stream(&self, opener: &mut StreamOpener) -> Result<bool, Response> {
+        let key_id = match opener.header() {
+            Ok(Some(header)) => header.key_id.clone(),
+            Ok(None) => return Ok(false),
+            Err(e) => return Err(Response::failure(ErrorCode::InvalidRequest, format!("Invalid stream: {}", e))),
+        };
+        let key = self
+            .get_key(key_id)
+            .await
+            .map_err(|e| e.context("Failed to get key").into_response())?;
+        opener.unlock(&key.secret).map_err(decryption_failed)?;
+        Ok(true)
+    }
+
+    /// Encrypt the file at `inputPath` into a new stream file at `outputPath`,
+    /// with the stream header in a new `.envelope.json` sidecar next to it
+    async fn handle_encrypt_file(&self, data: RequestData) -> Response {
+        let (input, output) = match self.file_paths(&data) {
+            Ok(paths) => paths,
+            Err(response) => return *response,
+        };
+        let envelope = output.sidecar();
+
+        let key = if let Some(kid) = data.key_id {
+            self.get_key(kid).await.map_err(|e| e.context("Failed to get key"))
+        } else {
+            self.keyless_encrypt_key().await.map_err(|e| e.context("Failed to create key"))
+        };
+        let key = match key {
+            Ok(key) => key,
+            Err(e) => return e.into_response(),
+        };
+        let algorithm = data.algorithm.unwrap_or_default();
+        let encryptor = match StreamEncryptor::new(algorithm).with_chunk_size(self.stream_chunk_size) {
+            Ok(encryptor) => encryptor,
+            Err(e) => return encryption_failed(e),
+        };
+
+        // File I/O always goes to the blocking pool, whatever the file's size
+        self.crypto(usize::MAX, move || encrypt_file(&encryptor, &key.secret, key.uuid, &input, &output, &envelope))
+            .await
+            .unwrap_or_else(|failed| *failed)
+    }
+
+    /// Decrypt the stream file at `inputPath` into a new file at `outputPath`
+    async fn handle_decrypt_file(&self, data: RequestData) -> Response {
+        let (input, output) = match self.file_paths(&data) {
+            Ok(paths) => paths,
+            Err(response) => return *response,
+        };
+
+        let (file, mut opener) = match self.offload(usize::MAX, move || open_stream_file(&input)).await {
+            Ok(Ok(opened)) => opened,
+            Ok(Err(response)) | Err(response) => return *response,
+        };
+        match self.unlock_stream(&mut opener).await {
+            Ok(true) => {}
+            Ok(false) => {
+                let message = "Invalid stream: file ends inside its header".to_string();
+                return Response::failure(ErrorCode::InvalidRequest, message);
+            }
+            Err(response) => return response,
+        }
+
+        self.crypto(usize::MAX, move || decrypt_file(file, opener, &output))
+            .await
+            .unwrap_or_else(|failed| *failed)
+    }
+
+    /// Resolve the `inputPath` and `outputPath` of a file operation and check
+    /// both are inside an allowed directory
+    ///
+    /// The input must exist and is resolved whole. The output must not exist,
+    /// so only its directory is resolved; it is created with `O_EXCL`, which
+    /// also refuses a symlink planted at the path.
+    fn file_paths(&self, data: &RequestData) -> Result<(RequestFile, RequestFile), Box<Response>> {
+        if self.file_dirs.is_empty() {
+            return Err(Box::new(Response::failure(
+                ErrorCode::OperationNotAllowed,
+                "File operations are disabled on this daemon; no directories are allowed for them".into(),
+            )));
+        }
+        let (Some(input), Some(output)) = (&data.input_path, &data.output_path) else {
+            return Err(Box::new(Response::failure(
+                ErrorCode::InvalidRequest,
+                "File operations need an inputPath and an outputPath".into(),
+            )));
+        };
+
+        let resolved_input = resolve_input(input)?;
+        let resolved_output = resolve_output(output)?;
+        let input = self.permit_path(input, resolved_input)?;
+        let output = self.permit_path(output, resolved_output)?;
+        Ok((input, output))
+    }
+
+    /// Refuse a `requested` path whose `resolved` form is outside the allowed
+    /// directories, or return it with the directory it is in
+    fn permit_path(&self, requested: &Path, resolved: PathBuf) -> Result<RequestFile, Box<Response>> {
+        let Some(root) = self.file_dirs.iter().find(|dir| resolved.starts_with(dir)) else {
+            return Err(Box::new(Response::failure(
+                ErrorCode::PathNotPermitted,
+                format!("{} is outside this daemon's allowed directories", requested.display()),
+            )));
+        };
+        let dir = resolved.parent().unwrap_or(&resolved).to_path_buf();
+        let name = resolved.file_name().unwrap_or_default().to_os_string();
+        Ok(RequestFile { root: root.clone(), dir, name })
+    }
+
+    /// Record `response` in the audit log, if there is one
+    ///
+    /// Returns the response to send, which under a strict audit log is an
+    /// `audit_failed` error if the record could not be written. Batch items
+    /// are recorded without `timings`, which only exist for the whole batch.
+    async fn audit(
+        &self,
+        operation: Operation,
+        key_id: Option<String>,
+        plaintext_len: u64,
+        ciphertext_len: Option<u64>,
+        timings: Option<Timings>,
+        response: Response,
+    ) -> Response {
+        let Some(log) = &self.audit_log else {
+            return response;
+        };
+
+        let (plaintext_bytes, ciphertext_bytes) = payload_sizes(operation, plaintext_len, ciphertext_len, &response);
+        let mut record = AuditRecord::new(operation, key_id, response.success, response.error.clone());
+        record.request_id = REQUEST_ID.try_with(Clone::clone).ok().flatten();
+        record.peer = PEER.try_with(|peer| *peer).ok().flatten();
+        record.plaintext_bytes = plaintext_bytes;
+        record.ciphertext_bytes = ciphertext_bytes;
+        record.error_code = response.error_code;
+        record.timings = timings;
+
+        match log.record(record).await {
+            Ok(()) => response,
+            Err(e) => Response::failure(ErrorCode::AuditFailed, format!("Failed to write audit log: {}", e)),
         }
     }
 
     async fn handle_encrypt(&self, request: Request) -> Response {
-        // Extract request data
-        let plaintext = match BASE64.decode(&request.data.plaintext) {
-            Ok(pt) => pt,
-            Err(e) => return Response::error(format!("Invalid base64: {}", e)),
+        let Some(idempotency_key) = request.data.idempotency_key.clone() else {
+            return self.encrypt(request).await;
         };
+        let digest = request_digest(&request.data);
+        self.idempotency
+            .run(peer_uid(), &idempotency_key, digest, || self.encrypt(request))
+            .await
+    }
+
+    async fn encrypt(&self, request: Request) -> Response {
+        let RequestData { plaintext, key_id, algorithm, .. } = request.data;
+        let algorithm = algorithm.unwrap_or_default();
+        let size = plaintext.len();
 
-        let algorithm = request.data.algorithm.unwrap_or_default();
+        let plaintext = match self.offload(size, move || decode_plaintext(&plaintext)).await {
+            Ok(Ok(plaintext)) => plaintext,
+            Ok(Err(response)) | Err(response) => return *response,
+        };
 
         // Get or create key
-        let client = match KeysClient::new(&self.server_url) {
-            Ok(c) => c,
-            Err(e) => return Response::error(format!("Client error: {}", e)),
-        };
-
-        let (kek_id, kek_bytes) = if let Some(kid) = request.data.key_id {
-            match client.get_key(&kid) {
-                Ok(key) => {
-                    let bytes = match key.as_bytes() {
-                        Ok(b) => b,
-                        Err(e) => return Response::error(format!("Key decode error: {}", e)),
-                    };
-                    (key.uuid, bytes)
-                }
-                Err(e) => return Response::error(format!("Failed to get key: {}", e)),
+        let key = if let Some(kid) = key_id {
+            self.get_key(kid).await.map_err(|e| e.context("Failed to get key"))
+        } else {
+            self.keyless_encrypt_key().await.map_err(|e| e.context("Failed to create key"))
+        };
+
+        match key {
+            Ok(key) => self
+                .crypto(size, move || seal(&plaintext, algorithm, key))
+                .await
+                .unwrap_or_else(|failed| *failed),
+            Err(e) => e.into_response(),
+        }
+    }
+
+    async fn handle_ping(&self) -> Response {
+        let keys_server_ok = self.keys_server_ok().await;
+        Response::success_pong(self.started_at.elapsed().as_secs(), keys_server_ok)
+    }
+
+    /// Whether the Keys server answered the latest health probe, probing again
+    /// once the previous result is older than `health_check_interval`
+    pub async fn keys_server_ok(&self) -> bool {
+        let cached = *self.health.lock().unwrap();
+        if let Some((checked_at, ok)) = cached {
+            if checked_at.elapsed() < self.health_check_interval {
+                return ok;
+            }
+        }
+
+        let ok = match self.call_keys_server(|client| client.health_check()).await {
+            Ok(Ok(())) => true,
+            Ok(Err(e)) => {
+                tracing::warn!("Keys server health check failed: {}", e);
+                false
+            }
+            Err(e) => {
+                tracing::warn!("Keys server health check failed: {}", e);
+                false
+            }
+        };
+        *self.health.lock().unwrap() = Some((Instant::now(), ok));
+        ok
+    }
+
+    async fn handle_create_key(&self, request: Request) -> Response {
+        let include_key_material = request.data.include_key_material;
+        if include_key_material && !self.allow_key_export {
+            return Response::failure(
+                ErrorCode::InvalidRequest,
+                "Key material export is disabled on this daemon".into(),
+            );
+        }
+
+        match self.create_key().await {
+            Ok(key) => {
+                let material = include_key_material.then(|| hex::encode(&**key.secret));
+                Response::success_key_created(key.uuid, material)
+            }
+            Err(e) => e.context("Failed to create key").into_response(),
+        }
+    }
+
+    async fn handle_rewrap(&self, request: Request) -> Response {
+        let envelope = match request.data.envelope {
+            Some(env) => env,
+            None => {
+                return Response::failure(ErrorCode::InvalidRequest, "Missing envelope in rewrap request".into())
+            }
+        };
+
+        // Check both ends before fetching either key
+        for key_id in std::iter::once(&envelope.key_id).chain(&request.data.new_key_id) {
+            if let Err(e) = self.permit_key(key_id) {
+                return e.context("Rewrap refused").into_response();
+            }
+        }
+
+        let old_key = match self.get_key(envelope.key_id.clone()).await {
+            Ok(k) => k,
+            Err(e) => return e.context("Failed to get key").into_response(),
+        };
+
+        let new_key = if let Some(kid) = request.data.new_key_id {
+            match self.get_key(kid).await {
+                Ok(key) => key,
+                Err(e) => return e.context("Failed to get new key").into_response(),
             }
         } else {
-            match client.create_key() {
-                Ok(key) => {
-                    let bytes = match key.as_bytes() {
-                        Ok(b) => b,
-                        Err(e) => return Response::error(format!("Key decode error: {}", e)),
-                    };
-                    (key.uuid, bytes)
-                }
-                Err(e) => return Response::error(format!("Failed to create key: {}", e)),
+            match self.create_key().await {
+                Ok(key) => key,
+                Err(e) => return e.context("Failed to create key").into_response(),
             }
         };
 
-        // Encrypt
-        let encryptor = EnvelopeEncryptor::new(algorithm);
-        match encryptor.encrypt(&plaintext, &kek_bytes, kek_id) {
-            Ok(envelope) => Response::success_encrypt(envelope),
-            Err(e) => Response::error(format!("Encryption failed: {}", e)),
+        // The algorithm only matters for data encryption, which rewrapping leaves alone
+        let encryptor = EnvelopeEncryptor::new(Algorithm::default());
+        let started = Instant::now();
+        let rewrapped = encryptor.rewrap(&envelope, &old_key.secret, &new_key.secret, new_key.uuid);
+        add_timings(|timings| timings.crypto_ms += millis(started.elapsed()));
+        match rewrapped {
+            Ok(rewrapped) => Response::success_rewrap(rewrapped, envelope.key_id),
+            Err(e) => Response::failure(ErrorCode::CryptoFailed, format!("Rewrap failed: {}", e)),
         }
     }
 
     async fn handle_decrypt(&self, request: Request) -> Response {
         let envelope = match request.data.envelope {
             Some(env) => env,
-            None => return Response::error("Missing envelope in decrypt request".into()),
+            None => return missing_envelope(),
         };
 
         // Get KEK
-        let client = match KeysClient::new(&self.server_url) {
-            Ok(c) => c,
-            Err(e) => return Response::error(format!("Client error: {}", e)),
+        match self.get_key(envelope.key_id.clone()).await {
+            Ok(key) => self
+                .crypto(envelope.encrypted_data.len(), move || open(&envelope, &key))
+                .await
+                .unwrap_or_else(|failed| *failed),
+            Err(e) => e.context("Failed to get key").into_response(),
+        }
+    }
+
+    async fn handle_batch(&self, request: Request) -> Response {
+        let items = request.data.items;
+        if items.len() > self.max_batch_size {
+            return Response::failure(
+                ErrorCode::BatchTooLarge,
+                format!("Batch has {} items; the limit is {}", items.len(), self.max_batch_size),
+            );
+        }
+
+        let keys = self.batch_keys(&items).await;
+        let results = futures::stream::iter(items)
+            .map(|item| self.batch_item(item, &keys))
+            .buffered(BATCH_CONCURRENCY)
+            .collect()
+            .await;
+        Response::success_batch(results)
+    }
+
+    /// Look up every KEK the batch needs once, and create one new KEK shared
+    /// by all encrypt items without a key_id
+    async fn batch_keys(&self, items: &[BatchItem]) -> BatchKeys {
+        let mut key_ids = BTreeSet::new();
+        let mut needs_new_key = false;
+        for item in items {
+            if self.mode.refusal(item.operation, &item.data).is_some() {
+                continue;
+            }
+            match (item.operation, requested_key_id(item.operation, &item.data)) {
+                (Operation::Encrypt, None) => needs_new_key = true,
+                (Operation::Encrypt | Operation::Decrypt, Some(key_id)) => {
+                    key_ids.insert(key_id);
+                }
+                _ => {}
+            }
+        }
+
+        let by_id = futures::stream::iter(key_ids)
+            .map(|key_id| async move {
+                let key = self.get_key(key_id.clone()).await;
+                (key_id, key.map_err(|e| e.context("Failed to get key")))
+            })
+            .buffer_unordered(BATCH_CONCURRENCY)
+            .collect()
+            .await;
+
+        let created = if needs_new_key {
+            Some(self.keyless_encrypt_key().await.map_err(|e| e.context("Failed to create key")))
+        } else {
+            None
         };
 
-        let key = match client.get_key(&envelope.key_id) {
-            Ok(k) => k,
-            Err(e) => return Response::error(format!("Failed to get key: {}", e)),
+        BatchKeys { by_id, created }
+    }
+
+    async fn batch_item(&self, item: BatchItem, keys: &BatchKeys) -> BatchItemResult {
+        let operation = item.operation;
+        let requested_key_id = requested_key_id(operation, &item.data);
+        let plaintext_len = base64_decoded_len(&item.data.plaintext);
+        let ciphertext_len = envelope_ciphertext_len(&item.data);
+        if let Some(refusal) = self.mode.refusal(operation, &item.data) {
+            let response = Response::failure(ErrorCode::OperationNotAllowed, refusal);
+            let response = self.audit(operation, requested_key_id, plaintext_len, ciphertext_len, None, response).await;
+            self.metrics.record_item(operation, &response, 0);
+            return response.into();
+        }
+        let response = match operation {
+            Operation::Encrypt => self.batch_encrypt(item.data, keys).await,
+            Operation::Decrypt => self.batch_decrypt(item.data, keys).await,
+            _ => {
+                let response = Response::failure(
+                    ErrorCode::UnsupportedOperation,
+                    "Only encrypt and decrypt are allowed in a batch".into(),
+                );
+                self.metrics.record_item(operation, &response, 0);
+                return response.into();
+            }
+        };
+
+        let response = self.audit(operation, requested_key_id, plaintext_len, ciphertext_len, None, response).await;
+        self.metrics.record_item(operation, &response, plaintext_len);
+        response.into()
+    }
+
+    async fn batch_encrypt(&self, data: RequestData, keys: &BatchKeys) -> Response {
+        let Some(idempotency_key) = data.idempotency_key.clone() else {
+            return self.batch_seal(data, keys).await;
         };
+        let digest = request_digest(&data);
+        self.idempotency
+            .run(peer_uid(), &idempotency_key, digest, || self.batch_seal(data, keys))
+            .await
+    }
 
-        let kek_bytes = match key.as_bytes() {
-            Ok(b) => b,
-            Err(e) => return Response::error(format!("Key decode error: {}", e)),
+    async fn batch_seal(&self, data: RequestData, keys: &BatchKeys) -> Response {
+        let plaintext = match decode_plaintext(&data.plaintext) {
+            Ok(pt) => pt,
+            Err(response) => return *response,
+        };
+        let algorithm = data.algorithm.unwrap_or_default();
+        let key = match keys.get(data.key_id.as_deref()) {
+            Ok(key) => key,
+            Err(e) => return e.into_response(),
         };
 
-        // Decrypt
-        let algorithm = match Algorithm::from_str(&envelope.algorithm) {
-            Ok(a) => a,
-            Err(e) => return Response::error(format!("Invalid algorithm: {}", e)),
+        self.crypto(plaintext.len(), move || seal(&plaintext, algorithm, key))
+            .await
+            .unwrap_or_else(|failed| *failed)
+    }
+
+    async fn batch_decrypt(&self, data: RequestData, keys: &BatchKeys) -> Response {
+        let Some(envelope) = data.envelope else {
+            return missing_envelope();
         };
+        match keys.get(Some(&envelope.key_id)) {
+            Ok(key) => self
+                .crypto(envelope.encrypted_data.len(), move || open(&envelope, &key))
+                .await
+                .unwrap_or_else(|failed| *failed),
+            Err(e) => e.into_response(),
+        }
+    }
+
+    /// Run CPU-bound `work` on a payload of `size` bytes: inline if it is below
+    /// `blocking_threshold`, otherwise on the blocking thread pool, inside the
+    /// caller's span, once one of the `blocking_threads` is free
+    ///
+    /// Work handed to the pool cannot be interrupted. If the request is
+    /// dropped meanwhile (say its client disconnected), the work still runs
+    /// to completion, holding its thread, and its result is discarded. A
+    /// panic in the work fails only this request.
+    pub(crate) async fn offload<T, F>(&self, size: usize, work: F) -> Result<T, Box<Response>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        if size < self.blocking_threshold {
+            return Ok(work());
+        }
+        let permit = Arc::clone(&self.blocking_threads)
+            .acquire_owned()
+            .await
+            .expect("blocking thread semaphore is never closed");
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            span.in_scope(work)
+        })
+        .await
+        .map_err(|e| Box::new(Response::error(format!("Crypto task failed: {}", e))))
+    }
+
+    /// [`offload`](Self::offload) encryption or decryption, counting the time
+    /// it takes, waiting for the pool included, in the request's `cryptoMs`
+    async fn crypto<T, F>(&self, size: usize, work: F) -> Result<T, Box<Response>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let started = Instant::now();
+        let result = self.offload(size, work).await;
+        add_timings(|timings| timings.crypto_ms += millis(started.elapsed()));
+        result
+    }
+
+    /// Get a KEK from the in-memory cache, or from the Keys server on a miss
+    async fn get_key(&self, key_id: String) -> Result<Kek, KeyError> {
+        self.permit_key(&key_id)?;
+        let started = Instant::now();
+        if let Some(key) = self.cached_kek(&key_id) {
+            tracing::debug!("KEK cache hit: {}", key_id);
+            self.metrics.kek_cache_hit();
+            add_timings(|timings| {
+                timings.kek_cache_hits += 1;
+                timings.kek_fetch_ms += millis(started.elapsed());
+            });
+            return Ok(key);
+        }
+        self.metrics.kek_cache_miss();
 
-        let encryptor = EnvelopeEncryptor::new(algorithm);
-        match encryptor.decrypt(&envelope, &kek_bytes) {
-            Ok(plaintext) => {
-                let encoded = BASE64.encode(&plaintext);
-                Response::success_decrypt(encoded)
+        let options = call_options(self.keys_server_timeout);
+        let requested = key_id.clone();
+        let fetched = self
+            .call_keys_server(move |client| client.get_key_with(&requested, &options))
+            .await;
+        add_timings(|timings| {
+            timings.kek_cache_misses += 1;
+            timings.kek_fetch_ms += millis(started.elapsed());
+        });
+        match fetched? {
+            Ok(key) => {
+                let key = Kek::decode(key)?;
+                self.cache_kek(&key);
+                Ok(key)
+            }
+            Err(e) => {
+                // The key was deleted or revoked; don't leave a copy behind
+                if matches!(e, ClientError::KeyNotFound(_)) {
+                    self.invalidate_kek(&key_id).await;
+                }
+                Err(KeyError::Unavailable(e.to_string()))
             }
-            Err(e) => Response::error(format!("Decryption failed: {}", e)),
         }
     }
+
+    /// Refuse a key_id outside the key allow-list
+    fn permit_key(&self, key_id: &str) -> Result<(), KeyError> {
+        if self.key_allow_list.permits(key_id) {
+            Ok(())
+        } else {
+            Err(KeyError::NotPermitted(format!("Key {} is not on this daemon's key allow-list", key_id)))
+        }
+    }
+
+    /// Create a KEK on the Keys server and cache it
+    ///
+    /// A new key outside the key allow-list is refused, and deleted again so
+    /// it is not left orphaned on the Keys server.
+    async fn create_key(&self) -> Result<Kek, KeyError> {
+        if !self.key_allow_list.permits_new_keys() {
+            return Err(KeyError::NotPermitted(
+                "Creating keys is disabled by this daemon's key allow-list".into(),
+            ));
+        }
+        let options = call_options(self.keys_server_timeout);
+        let started = Instant::now();
+        let created = self.call_keys_server(move |client| client.create_key_with(&options)).await;
+        add_timings(|timings| timings.kek_fetch_ms += millis(started.elapsed()));
+        let key = created?.map_err(|e| KeyError::Unavailable(e.to_string()))?;
+        if let Err(e) = self.permit_key(&key.uuid) {
+            tracing::warn!("Refusing new key {}: outside the key allow-list", key.uuid);
+            let uuid = key.uuid.clone();
+            match self.call_keys_server(move |client| client.delete_key(&uuid)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(deleted)) => tracing::warn!("Failed to delete refused key {}: {}", key.uuid, deleted),
+                Err(deleted) => tracing::warn!("Failed to delete refused key {}: {}", key.uuid, deleted),
+            }
+            return Err(e);
+        }
+        let key = Kek::decode(key)?;
+        self.cache_kek(&key);
+        Ok(key)
+    }
+
+    /// KEK for an encrypt request without a key_id: the shared key if that
+    /// mode is on, otherwise a new one
+    ///
+    /// A failed creation is not remembered, so the next request tries again.
+    /// With [`require_key_id`](Self::require_key_id) no key is ever created.
+    async fn keyless_encrypt_key(&self) -> Result<Kek, KeyError> {
+        if self.require_key_id {
+            return Err(KeyError::KeyIdRequired);
+        }
+        let Some(ttl) = self.shared_key_ttl else {
+            return self.create_key().await;
+        };
+
+        let mut shared = self.shared_key.lock().await;
+        if let Some((created_at, key)) = shared.as_ref() {
+            if created_at.elapsed() < ttl {
+                return Ok(key.clone());
+            }
+        }
+
+        let key = self.create_key().await?;
+        tracing::debug!("Created shared KEK {} for the next {:?}", key.uuid, ttl);
+        *shared = Some((Instant::now(), key.clone()));
+        Ok(key)
+    }
+
+    fn cached_kek(&self, key_id: &str) -> Option<Kek> {
+        let cache = self.kek_cache.lock().unwrap();
+        cache
+            .get(key_id)
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < self.kek_cache_ttl)
+            .map(|(_, key)| key.clone())
+    }
+
+    fn cache_kek(&self, key: &Kek) {
+        if self.kek_cache_ttl.is_zero() || self.kek_cache_capacity == 0 {
+            return;
+        }
+        let mut cache = self.kek_cache.lock().unwrap();
+        let before = cache.len();
+        cache.retain(|_, (fetched_at, _)| fetched_at.elapsed() < self.kek_cache_ttl);
+        let mut evicted = before - cache.len();
+
+        while cache.len() >= self.kek_cache_capacity && !cache.contains_key(&key.uuid) {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, (fetched_at, _))| *fetched_at)
+                .map(|(key_id, _)| key_id.clone());
+            let Some(oldest) = oldest else { break };
+            cache.remove(&oldest);
+            evicted += 1;
+        }
+        cache.insert(key.uuid.clone(), (Instant::now(), key.clone()));
+        drop(cache);
+        self.metrics.kek_cache_evicted(evicted as u64);
+    }
+
+    /// Drop a KEK the Keys server no longer has, so the next request re-fetches it
+    async fn invalidate_kek(&self, key_id: &str) {
+        let removed = self.kek_cache.lock().unwrap().remove(key_id).is_some();
+        let mut shared = self.shared_key.lock().await;
+        let shared_removed = shared.as_ref().is_some_and(|(_, key)| key.uuid == key_id);
+        if shared_removed {
+            *shared = None;
+        }
+        if removed || shared_removed {
+            tracing::info!("Keys server no longer has KEK {}; dropped it from the cache", key_id);
+            self.metrics.kek_cache_evicted(1);
+        }
+    }
+
+    /// Remove one KEK, or every KEK, from the cache, returning how many were removed
+    ///
+    /// The shared key for keyless encrypts counts as cached, so flushing it
+    /// makes the next such request create a new one.
+    async fn flush_keys(&self, key_id: Option<&str>) -> usize {
+        let mut shared = self.shared_key.lock().await;
+        let shared_id = match (key_id, shared.as_ref()) {
+            (None, Some((_, key))) => Some(key.uuid.clone()),
+            (Some(key_id), Some((_, key))) if key.uuid == key_id => Some(key.uuid.clone()),
+            _ => None,
+        };
+        if shared_id.is_some() {
+            *shared = None;
+        }
+        drop(shared);
+
+        let mut cache = self.kek_cache.lock().unwrap();
+        let flushed = match key_id {
+            Some(key_id) => usize::from(cache.remove(key_id).is_some() || shared_id.is_some()),
+            None => {
+                // The shared key is normally cached too; count it once
+                let mut removed: BTreeSet<String> = cache.drain().map(|(key_id, _)| key_id).collect();
+                removed.extend(shared_id);
+                removed.len()
+            }
+        };
+        drop(cache);
+        tracing::info!("Flushed {} cached KEK(s)", flushed);
+        flushed
+    }
+
+    /// Run a blocking Keys server call on the blocking thread pool with the shared client
+    ///
+    /// The call runs inside the caller's span, so its logs keep the request id.
+    /// It is not made while the circuit breaker is open, and is abandoned
+    /// after `keys_server_timeout`. The outer error is the daemon giving up on
+    /// the call; the inner result is the Keys server's answer.
+    async fn call_keys_server<T, F>(&self, call: F) -> Result<violet_client::Result<T>, KeyError>
+    where
+        F: FnOnce(&KeysClient) -> violet_client::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = self.breaker.acquire().map_err(|retry_in| {
+            KeyError::KeysServerDown(if retry_in.is_zero() {
+                "Keys server is unavailable after repeated failures; checking whether it has recovered".to_string()
+            } else {
+                format!(
+                    "Keys server is unavailable after repeated failures; next attempt in {:.1}s",
+                    retry_in.as_secs_f64()
+                )
+            })
+        })?;
+
+        let client = Arc::clone(&self.client);
+        let span = tracing::Span::current();
+        let task = tokio::task::spawn_blocking(move || span.in_scope(|| call(&client)));
+        let joined = match self.keys_server_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, task).await {
+                Ok(joined) => joined,
+                Err(_) => {
+                    permit.record(false);
+                    return Err(KeyError::Unavailable(format!("Keys server did not answer within {:?}", timeout)));
+                }
+            },
+            None => task.await,
+        };
+        match joined {
+            Ok(result) => {
+                permit.record(!result.as_ref().is_err_and(is_upstream_failure));
+                Ok(result)
+            }
+            Err(e) => Err(KeyError::Unavailable(format!("Keys server call failed: {}", e))),
+        }
+    }
+}
+
+/// KEKs looked up once for a whole batch, with the error for any that failed
+struct BatchKeys {
+    by_id: HashMap<String, Result<Kek, KeyError>>,
+
+    /// New KEK shared by encrypt items without a key_id
+    created: Option<Result<Kek, KeyError>>,
+}
+
+impl BatchKeys {
+    fn get(&self, key_id: Option<&str>) -> Result<Kek, KeyError> {
+        let found = match key_id {
+            Some(key_id) => self.by_id.get(key_id),
+            None => self.created.as_ref(),
+        };
+        match found {
+            Some(result) => result.clone(),
+            None => Err(KeyError::Unavailable("Key was not looked up for this batch".into())),
+        }
+    }
+}
+
+/// Keys server call options for the request being handled
+fn call_options(timeout: Option<Duration>) -> CallOptions {
+    let options = match timeout {
+        Some(timeout) => CallOptions::new().timeout(timeout),
+        None => CallOptions::new(),
+    };
+    match REQUEST_ID.try_with(Option::clone).ok().flatten() {
+        Some(id) => options.request_id(id),
+        None => options,
+    }
+}
+
+/// Whether a Keys server error means the server is down or unreachable,
+/// rather than an answer about one key
+fn is_upstream_failure(error: &ClientError) -> bool {
+    match error {
+        ClientError::RequestFailed(_) | ClientError::Timeout(_) => true,
+        ClientError::UnexpectedStatus(status) => matches!(status, 502..=504),
+        _ => false,
+    }
+}
+
+/// Size of the ciphertext in a request's envelope, if it has one
+fn envelope_ciphertext_len(data: &RequestData) -> Option<u64> {
+    data.envelope.as_ref().map(|envelope| base64_decoded_len(&envelope.encrypted_data))
+}
+
+/// Plaintext and ciphertext sizes recorded in the audit log for an operation
+///
+/// Sizes taken from the request are recorded whatever the outcome; sizes
+/// only known from the result are recorded on success.
+fn payload_sizes(
+    operation: Operation,
+    plaintext_len: u64,
+    ciphertext_len: Option<u64>,
+    response: &Response,
+) -> (Option<u64>, Option<u64>) {
+    match (operation, &response.result) {
+        (Operation::Encrypt, Some(ResponseResult::Encrypt { envelope })) => {
+            (Some(plaintext_len), Some(base64_decoded_len(&envelope.encrypted_data)))
+        }
+        (Operation::Encrypt, _) => (Some(plaintext_len), None),
+        (Operation::Decrypt, Some(ResponseResult::Decrypt { plaintext })) => {
+            (Some(base64_decoded_len(plaintext)), ciphertext_len)
+        }
+        (Operation::Decrypt | Operation::Rewrap, _) => (None, ciphertext_len),
+        (Operation::EncryptStream, Some(ResponseResult::StreamFinished { .. })) => (Some(plaintext_len), None),
+        (Operation::DecryptStream, Some(ResponseResult::StreamFinished { plaintext_bytes, .. })) => {
+            (Some(*plaintext_bytes), None)
+        }
+        (
+            Operation::EncryptFile | Operation::DecryptFile,
+            Some(ResponseResult::FileFinished {
+                plaintext_bytes,
+                ciphertext_bytes,
+                ..
+            }),
+        ) => (Some(*plaintext_bytes), Some(*ciphertext_bytes)),
+        _ => (None, None),
+    }
+}
+
+/// Key the audit log attributes a request to before it runs
+fn requested_key_id(operation: Operation, data: &RequestData) -> Option<String> {
+    match operation {
+        Operation::Encrypt => data.key_id.clone(),
+        Operation::Decrypt | Operation::Rewrap => data.envelope.as_ref().map(|e| e.key_id.clone()),
+        Operation::EncryptStream | Operation::EncryptFile => data.key_id.clone(),
+        Operation::Hello
+        | Operation::CreateKey
+        | Operation::Ping
+        | Operation::Batch
+        | Operation::Stats
+        | Operation::DecryptStream
+        | Operation::DecryptFile => None,
+        Operation::FlushKeys => data.key_id.clone(),
+    }
+}
+
+/// Short, stable stand-in for a key_id in exported traces
+///
+/// The first 8 bytes of its SHA-256, in hex: enough to correlate requests
+/// for the same key without putting key_ids in a tracing backend.
+pub fn key_id_hash(key_id: &str) -> String {
+    Sha256::digest(key_id.as_bytes())[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Fill in the `daemon.request` span fields known once a request is handled
+fn record_outcome(span: &tracing::Span, key_id: Option<&str>, algorithm: Option<&str>, response: &Response) {
+    if let Some(key_id) = key_id {
+        span.record("key_id_hash", key_id_hash(key_id));
+    }
+    if let Some(algorithm) = algorithm {
+        span.record("algorithm", algorithm);
+    }
+    span.record("outcome", if response.success { "success" } else { "error" });
+    if let Some(code) = response.error_code {
+        span.record("error_code", tracing::field::debug(code));
+    }
+}
+
+/// Run `request` inside a `daemon.request` span, with its id and the client's
+/// credentials available to Keys server calls and the audit log
+async fn in_request_scope<F: Future>(
+    id: Option<String>,
+    operation: Operation,
+    peer: Option<PeerCredentials>,
+    request: F,
+) -> F::Output {
+    let span = tracing::info_span!(
+        "daemon.request",
+        id = id.as_deref(),
+        operation = ?operation,
+        peer_uid = peer.map(|peer| peer.uid),
+        algorithm = tracing::field::Empty,
+        key_id_hash = tracing::field::Empty,
+        outcome = tracing::field::Empty,
+        error_code = tracing::field::Empty,
+        total_ms = tracing::field::Empty,
+        kek_fetch_ms = tracing::field::Empty,
+        kek_cache_hits = tracing::field::Empty,
+        kek_cache_misses = tracing::field::Empty,
+        crypto_ms = tracing::field::Empty,
+    );
+    let request = TIMINGS.scope(Cell::new(Timings::default()), request);
+    REQUEST_ID.scope(id, PEER.scope(peer, request)).instrument(span).await
+}
+
+/// UID of the Unix socket client that sent the request being handled, if known
+fn peer_uid() -> Option<u32> {
+    PEER.try_with(|peer| peer.map(|peer| peer.uid)).ok().flatten()
+}
+
+/// Add to the timings of the request being handled, if there is one
+fn add_timings(update: impl FnOnce(&mut Timings)) {
+    let _ = TIMINGS.try_with(|cell| {
+        let mut timings = cell.get();
+        update(&mut timings);
+        cell.set(timings);
+    });
+}
+
+/// Timings of the request being handled, which took `total`, recorded on its span
+fn finish_timings(span: &tracing::Span, total: Duration) -> Timings {
+    let timings = Timings {
+        total_ms: millis(total),
+        ..TIMINGS.try_with(Cell::get).unwrap_or_default()
+    };
+    span.record("total_ms", timings.total_ms);
+    span.record("kek_fetch_ms", timings.kek_fetch_ms);
+    span.record("kek_cache_hits", timings.kek_cache_hits);
+    span.record("kek_cache_misses", timings.kek_cache_misses);
+    span.record("crypto_ms", timings.crypto_ms);
+    timings
+}
+
+fn millis(elapsed: Duration) -> f64 {
+    elapsed.as_secs_f64() * 1000.0
+}
+
+/// Next raw input frame of a stream; the connection closing first is an error
+async fn next_input<T>(frames: &mut T) -> Result<BytesMut, FrameError>
+where
+    T: Stream<Item = Result<BytesMut, FrameError>> + Unpin,
+{
+    match frames.next().await {
+        Some(frame) => frame,
+        None => Err(FrameError::Io(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "Connection closed before the end of the stream",
+        ))),
+    }
+}
+
+/// Read and drop the rest of a failed stream's input, through its empty end
+/// frame, then fail it with `response`
+async fn fail_stream<T>(frames: &mut T, response: Response) -> Result<Response, FrameError>
+where
+    T: Stream<Item = Result<BytesMut, FrameError>> + Unpin,
+{
+    while !next_input(frames).await?.is_empty() {}
+    Ok(response)
+}
+
+/// Canonical form of an existing file a request reads
+fn resolve_input(path: &Path) -> Result<PathBuf, Box<Response>> {
+    if !path.is_absolute() {
+        return Err(Box::new(Response::failure(
+            ErrorCode::InvalidRequest,
+            format!("inputPath {} is not absolute", path.display()),
+        )));
+    }
+    path.canonicalize().map_err(|e| Box::new(file_failed("resolve", path, e)))
+}
+
+/// Canonical directory of a file a request creates, joined with its file name
+fn resolve_output(path: &Path) -> Result<PathBuf, Box<Response>> {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(Box::new(Response::failure(
+            ErrorCode::InvalidRequest,
+            format!("outputPath {} does not name a file", path.display()),
+        )));
+    };
+    if !path.is_absolute() {
+        return Err(Box::new(Response::failure(
+            ErrorCode::InvalidRequest,
+            format!("outputPath {} is not absolute", path.display()),
+        )));
+    }
+    let parent = parent.canonicalize().map_err(|e| Box::new(file_failed("resolve", parent, e)))?;
+    Ok(parent.join(name))
+}
+
+/// A file named by a request, resolved beneath the allowed directory `root`
+struct RequestFile {
+    root: PathBuf,
+    /// Canonical directory the file is in
+    dir: PathBuf,
+    name: OsString,
+}
+
+impl RequestFile {
+    fn path(&self) -> PathBuf {
+        self.dir.join(&self.name)
+    }
+
+    /// Where the header of the stream file at this path is written
+    fn sidecar(&self) -> RequestFile {
+        let mut name = self.name.clone();
+        name.push(".envelope.json");
+        RequestFile {
+            root: self.root.clone(),
+            dir: self.dir.clone(),
+            name,
+        }
+    }
+
+    /// Open the file's directory, failing if a symlink has replaced any
+    /// directory between `root` and it
+    fn open_dir(&self) -> Result<Dir, Box<Response>> {
+        Dir::open_beneath(&self.root, &self.dir).map_err(|e| Box::new(file_failed("open", &self.dir, e)))
+    }
+
+    /// Open the existing file for reading
+    fn open(&self) -> Result<File, Box<Response>> {
+        self.open_dir()?
+            .open(&self.name)
+            .map_err(|e| Box::new(file_failed("open", &self.path(), e)))
+    }
+}
+
+/// Create the file `name` in `dir`, which must not exist yet
+fn create_file(dir: &Dir, name: &OsStr, path: &Path) -> Result<File, Box<Response>> {
+    dir.create_new(name).map_err(|e| Box::new(file_failed("create", path, e)))
+}
+
+fn file_failed(action: &str, path: &Path, e: std::io::Error) -> Response {
+    Response::failure(ErrorCode::InvalidRequest, format!("Failed to {} {}: {}", action, path.display(), e))
+}
+
+/// Encrypt the file `input` into a new stream file `output`, and write the
+/// stream header as JSON to a new file `envelope`
+///
+/// Both new files are removed again if anything fails.
+fn encrypt_file(
+    encryptor: &StreamEncryptor,
+    kek: &[u8],
+    key_id: String,
+    input: &RequestFile,
+    output: &RequestFile,
+    envelope: &RequestFile,
+) -> Response {
+    let (output_path, envelope_path) = (output.path(), envelope.path());
+    let mut reader = match input.open() {
+        Ok(file) => file,
+        Err(response) => return *response,
+    };
+    let dir = match output.open_dir() {
+        Ok(dir) => dir,
+        Err(response) => return *response,
+    };
+    let writer = match create_file(&dir, &output.name, &output_path) {
+        Ok(file) => file,
+        Err(response) => return *response,
+    };
+    let sidecar = match create_file(&dir, &envelope.name, &envelope_path) {
+        Ok(file) => file,
+        Err(response) => {
+            let _ = dir.remove(&output.name);
+            return *response;
+        }
+    };
+
+    match seal_file(encryptor, kek, key_id, &mut reader, writer, sidecar) {
+        Ok((stream_header, chunks, plaintext_bytes, ciphertext_bytes)) => Response::success_file(
+            stream_header,
+            output_path,
+            Some(envelope_path),
+            chunks,
+            plaintext_bytes,
+            ciphertext_bytes,
+        ),
+        Err(e) => {
+            let _ = dir.remove(&output.name);
+            let _ = dir.remove(&envelope.name);
+            encryption_failed(e)
+        }
+    }
+}
+
+/// Seal everything read from `reader` into `writer` and the stream header
+/// into `sidecar`, returning the header, the number of data chunks and the
+/// plaintext and ciphertext sizes
+fn seal_file(
+    encryptor: &StreamEncryptor,
+    kek: &[u8],
+    key_id: String,
+    reader: &mut File,
+    writer: File,
+    sidecar: File,
+) -> violet_core::Result<(StreamHeader, u64, u64, u64)> {
+    let (mut sealer, preamble) = encryptor.sealer(kek, key_id)?;
+    let chunk_size = sealer.header().chunk_size;
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(&preamble)?;
+    let mut ciphertext_bytes = preamble.len() as u64;
+    let mut plaintext_bytes: u64 = 0;
+
+    let mut chunk = Vec::with_capacity(chunk_size);
+    loop {
+        chunk.clear();
+        Read::by_ref(reader).take(chunk_size as u64).read_to_end(&mut chunk)?;
+        if chunk.is_empty() {
+            break;
+        }
+        let frame = sealer.seal_chunk(&chunk)?;
+        writer.write_all(&frame)?;
+        plaintext_bytes += chunk.len() as u64;
+        ciphertext_bytes += frame.len() as u64;
+        if chunk.len() < chunk_size {
+            break;
+        }
+    }
+
+    let stream_header = sealer.header().clone();
+    let (footer, chunks) = sealer.finish()?;
+    writer.write_all(&footer)?;
+    writer.flush()?;
+    ciphertext_bytes += footer.len() as u64;
+
+    let mut sidecar = BufWriter::new(sidecar);
+    serde_json::to_writer_pretty(&mut sidecar, &stream_header)?;
+    sidecar.flush()?;
+    Ok((stream_header, chunks, plaintext_bytes, ciphertext_bytes))
+}
+
+/// Open a stream file and read it as far as the end of its header
+fn open_stream_file(input: &RequestFile) -> Result<(File, StreamOpener), Box<Response>> {
+    let path = &input.path();
+    let mut file = input.open()?;
+    let mut opener = StreamOpener::new();
+    let mut buf = [0u8; 4096];
+    while matches!(opener.header(), Ok(None)) {
+        let n = file.read(&mut buf).map_err(|e| Box::new(file_failed("read", path, e)))?;
+        if n == 0 {
+            break;
+        }
+        opener.push(&buf[..n]);
+    }
+    Ok((file, opener))
+}
+
+/// Decrypt the rest of `file` through an unlocked `opener` into a new file at
+/// `output`, which is removed again if decryption fails
+fn decrypt_file(mut file: File, mut opener: StreamOpener, output: &RequestFile) -> Response {
+    let ciphertext_bytes = match file.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return Response::error(format!("Failed to read file metadata: {}", e)),
+    };
+    let stream_header = opener.header().ok().flatten().cloned().expect("an unlocked stream has a header");
+    let output_path = output.path();
+    let dir = match output.open_dir() {
+        Ok(dir) => dir,
+        Err(response) => return *response,
+    };
+    let writer = match create_file(&dir, &output.name, &output_path) {
+        Ok(file) => file,
+        Err(response) => return *response,
+    };
+
+    match open_file(&mut file, opener, writer) {
+        Ok((chunks, plaintext_bytes)) => Response::success_file(
+            stream_header,
+            output_path,
+            None,
+            chunks,
+            plaintext_bytes,
+            ciphertext_bytes,
+        ),
+        Err(e) => {
+            let _ = dir.remove(&output.name);
+            decryption_failed(e)
+        }
+    }
+}
+
+/// Write the plaintext of each chunk read from `reader` to `writer` as it
+/// authenticates, returning the number of chunks and plaintext bytes
+fn open_file(reader: &mut File, mut opener: StreamOpener, writer: File) -> violet_core::Result<(u64, u64)> {
+    let mut writer = BufWriter::new(writer);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        while let Some(plaintext) = opener.next_chunk()? {
+            writer.write_all(&plaintext)?;
+        }
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        opener.push(&buf[..n]);
+    }
+    writer.flush()?;
+
+    let chunks = opener.chunks();
+    Ok((chunks, opener.finish()?))
+}
+
+fn encryption_failed(e: VioletError) -> Response {
+    Response::failure(ErrorCode::CryptoFailed, format!("Encryption failed: {}", e))
+}
+
+fn decryption_failed(e: VioletError) -> Response {
+    Response::failure(ErrorCode::CryptoFailed, format!("Decryption failed: {}", e))
+}
+
+/// Decode a request's plaintext, or the `invalid_request` response to send
+///
+/// The response is boxed, as `Response` is too large to return by value in an `Err`.
+fn decode_plaintext(plaintext: &str) -> Result<Vec<u8>, Box<Response>> {
+    BASE64
+        .decode(plaintext)
+        .map_err(|e| Box::new(Response::failure(ErrorCode::InvalidRequest, format!("Invalid base64: {}", e))))
+}
+
+fn missing_envelope() -> Response {
+    Response::failure(ErrorCode::InvalidRequest, "Missing envelope in decrypt request".into())
+}
+
+/// Encrypt `plaintext` under `key`
+fn seal(plaintext: &[u8], algorithm: Algorithm, key: Kek) -> Response {
+    let encryptor = EnvelopeEncryptor::new(algorithm);
+    match encryptor.encrypt(plaintext, &key.secret, key.uuid) {
+        Ok(envelope) => Response::success_encrypt(envelope),
+        Err(e) => Response::failure(ErrorCode::CryptoFailed, format!("Encryption failed: {}", e)),
+    }
+}
+
+/// Decrypt `envelope` with `key`, returning the plaintext as base64
+fn open(envelope: &EncryptionEnvelope, key: &Kek) -> Response {
+    let algorithm = match Algorithm::from_str(&envelope.algorithm) {
+        Ok(a) => a,
+        Err(e) => return Response::failure(ErrorCode::InvalidRequest, format!("Invalid algorithm: {}", e)),
+    };
+
+    let encryptor = EnvelopeEncryptor::new(algorithm);
+    match encryptor.decrypt(envelope, &key.secret) {
+        Ok(plaintext) => Response::success_decrypt(BASE64.encode(&plaintext)),
+        Err(e) => Response::failure(ErrorCode::CryptoFailed, format!("Decryption failed: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{ErrorCode, RequestData};
+
+    fn encrypt_request(key_id: &str) -> Request {
+        Request {
+            id: None,
+            version: None,
+            operation: Operation::Encrypt,
+            include_timings: false,
+            data: RequestData {
+                plaintext: BASE64.encode(b"hello"),
+                key_id: Some(key_id.to_string()),
+                algorithm: None,
+                idempotency_key: None,
+                include_key_material: false,
+                envelope: None,
+                new_key_id: None,
+                items: Vec::new(),
+                input_path: None,
+                output_path: None,
+            },
+        }
+    }
+
+    fn encrypt_new_key_request(idempotency_key: &str) -> Request {
+        Request {
+            id: None,
+            version: None,
+            operation: Operation::Encrypt,
+            include_timings: false,
+            data: RequestData {
+                plaintext: BASE64.encode(b"hello"),
+                key_id: None,
+                algorithm: None,
+                idempotency_key: Some(idempotency_key.to_string()),
+                include_key_material: false,
+                envelope: None,
+                new_key_id: None,
+                items: Vec::new(),
+                input_path: None,
+                output_path: None,
+            },
+        }
+    }
+
+    fn envelope_of(response: Response) -> EncryptionEnvelope {
+        match response.result {
+            Some(ResponseResult::Encrypt { envelope }) => envelope,
+            other => panic!("expected encrypt result, got {:?} ({:?})", other, response.error),
+        }
+    }
+
+    #[test]
+    fn test_handler_reuses_client_across_requests() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("GET", "/v1/keys/shared-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"shared-key","key":"{}"}}"#, "11".repeat(32)))
+            .expect(3)
+            .create();
+
+        let client = Arc::new(KeysClient::new(server.url()).unwrap());
+        let handler =
+            RequestHandler::with_client(Arc::clone(&client)).with_kek_cache_ttl(Duration::ZERO);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            for _ in 0..3 {
+                let response = handler.handle(encrypt_request("shared-key")).await;
+                assert!(response.success, "{:?}", response.error);
+            }
+        });
+
+        // Every request went through the one shared client
+        assert!(Arc::ptr_eq(&handler.client, &client));
+        assert_eq!(Arc::strong_count(&client), 2);
+        mock.assert();
+    }
+
+    fn decrypt_request(envelope: EncryptionEnvelope) -> Request {
+        Request {
+            id: None,
+            version: None,
+            operation: Operation::Decrypt,
+            include_timings: false,
+            data: RequestData {
+                plaintext: String::new(),
+                key_id: None,
+                algorithm: None,
+                idempotency_key: None,
+                include_key_material: false,
+                envelope: Some(envelope),
+                new_key_id: None,
+                items: Vec::new(),
+                input_path: None,
+                output_path: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_timings_only_when_requested() {
+        let mut server = mockito::Server::new();
+        let _timed = server
+            .mock("GET", "/v1/keys/timed")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"timed","key":"{}"}}"#, "44".repeat(32)))
+            .create();
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let untimed = runtime.block_on(handler.handle(encrypt_request("timed")));
+        assert!(untimed.success, "{:?}", untimed.error);
+        assert!(untimed.timings.is_none());
+        let json = serde_json::to_string(&untimed).unwrap();
+        assert!(!json.contains("timings"), "{}", json);
+
+        // The KEK is cached now, so this one is a hit
+        let started = Instant::now();
+        let timed = runtime.block_on(handler.handle(Request {
+            include_timings: true,
+            ..decrypt_request(envelope_of(untimed))
+        }));
+        let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        assert!(timed.success, "{:?}", timed.error);
+        let timings = timed.timings.unwrap();
+        assert_eq!((timings.kek_cache_hits, timings.kek_cache_misses), (1, 0));
+        assert!(timings.total_ms > 0.0 && timings.total_ms <= elapsed_ms, "{:?}", timings);
+        assert!(timings.kek_fetch_ms + timings.crypto_ms <= timings.total_ms, "{:?}", timings);
+        assert!(timings.crypto_ms > 0.0, "{:?}", timings);
+
+        let json: serde_json::Value = serde_json::to_value(&timed).unwrap();
+        let keys: BTreeSet<&str> = json["timings"].as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(
+            keys,
+            BTreeSet::from(["totalMs", "kekFetchMs", "kekCacheHits", "kekCacheMisses", "cryptoMs"])
+        );
+
+        // A failed request is timed too, and a miss is counted
+        let _missing = server.mock("GET", "/v1/keys/missing").with_status(404).create();
+        let failed = runtime.block_on(handler.handle(Request {
+            include_timings: true,
+            ..encrypt_request("missing")
+        }));
+        assert!(!failed.success);
+        let timings = failed.timings.unwrap();
+        assert_eq!((timings.kek_cache_hits, timings.kek_cache_misses), (0, 1));
+        assert_eq!(timings.crypto_ms, 0.0);
+        assert!(timings.kek_fetch_ms > 0.0 && timings.kek_fetch_ms <= timings.total_ms, "{:?}", timings);
+    }
+
+    #[test]
+    fn test_kek_cache_fetches_key_once() {
+        let kek = [0x33u8; 32];
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("GET", "/v1/keys/hot-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"hot-key","key":"{}"}}"#, "33".repeat(32)))
+            .expect(1)
+            .create();
+
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"hello", &kek, "hot-key".to_string())
+            .unwrap();
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            for _ in 0..10 {
+                let response = handler.handle(decrypt_request(envelope.clone())).await;
+                assert!(response.success, "{:?}", response.error);
+            }
+            for _ in 0..10 {
+                let response = handler.handle(encrypt_request("hot-key")).await;
+                assert!(response.success, "{:?}", response.error);
+            }
+        });
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_kek_cache_expires() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("GET", "/v1/keys/short-lived")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"short-lived","key":"{}"}}"#, "44".repeat(32)))
+            .expect(2)
+            .create();
+
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_kek_cache_ttl(Duration::from_millis(50));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            assert!(handler.handle(encrypt_request("short-lived")).await.success);
+            assert!(handler.handle(encrypt_request("short-lived")).await.success);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(handler.handle(encrypt_request("short-lived")).await.success);
+        });
+
+        mock.assert();
+    }
+
+    fn flush_keys_request(key_id: Option<&str>) -> Request {
+        let mut request = encrypt_request("unused");
+        request.operation = Operation::FlushKeys;
+        request.data.plaintext = String::new();
+        request.data.key_id = key_id.map(str::to_string);
+        request
+    }
+
+    fn flushed(response: Response) -> usize {
+        match response.result {
+            Some(ResponseResult::KeysFlushed { flushed }) => flushed,
+            other => panic!("expected flushed count, got {:?} ({:?})", other, response.error),
+        }
+    }
+
+    /// Serve `uuid` as a KEK, expecting `hits` fetches
+    fn mock_cached_key(server: &mut mockito::ServerGuard, uuid: &str, hits: usize) -> mockito::Mock {
+        server
+            .mock("GET", format!("/v1/keys/{}", uuid).as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"{}","key":"{}"}}"#, uuid, "55".repeat(32)))
+            .expect(hits)
+            .create()
+    }
+
+    #[test]
+    fn test_flush_single_key() {
+        let mut server = mockito::Server::new();
+        let flushed_key = mock_cached_key(&mut server, "flushed", 2);
+        let kept_key = mock_cached_key(&mut server, "kept", 1);
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            for key_id in ["flushed", "kept"] {
+                assert!(handler.handle(encrypt_request(key_id)).await.success);
+            }
+            assert_eq!(flushed(handler.handle(flush_keys_request(Some("flushed"))).await), 1);
+            assert_eq!(flushed(handler.handle(flush_keys_request(Some("never-cached"))).await), 0);
+            for key_id in ["flushed", "kept"] {
+                assert!(handler.handle(encrypt_request(key_id)).await.success);
+            }
+        });
+
+        flushed_key.assert();
+        kept_key.assert();
+    }
+
+    #[test]
+    fn test_flush_all_keys() {
+        let mut server = mockito::Server::new();
+        let first = mock_cached_key(&mut server, "first", 2);
+        let second = mock_cached_key(&mut server, "second", 2);
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            for key_id in ["first", "second"] {
+                assert!(handler.handle(encrypt_request(key_id)).await.success);
+            }
+            assert_eq!(flushed(handler.handle(flush_keys_request(None)).await), 2);
+            assert_eq!(flushed(handler.handle(flush_keys_request(None)).await), 0);
+            for key_id in ["first", "second"] {
+                assert!(handler.handle(encrypt_request(key_id)).await.success);
+            }
+        });
+
+        first.assert();
+        second.assert();
+        assert_eq!(handler.metrics().snapshot().kek_cache_evictions, 0);
+    }
+
+    #[test]
+    fn test_not_found_invalidates_cached_kek() {
+        let mut server = mockito::Server::new();
+        let found = mock_cached_key(&mut server, "revoked", 1);
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_kek_cache_ttl(Duration::from_millis(50));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            assert!(handler.handle(encrypt_request("revoked")).await.success);
+        });
+        found.assert();
+        found.remove();
+        assert!(handler.kek_cache.lock().unwrap().contains_key("revoked"));
+
+        // Once expired, the entry is re-fetched, and the key is gone
+        let missing = server.mock("GET", "/v1/keys/revoked").with_status(404).expect(1).create();
+        runtime.block_on(async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let response = handler.handle(encrypt_request("revoked")).await;
+            assert_eq!(response.error_code, Some(ErrorCode::KeyUnavailable), "{:?}", response.error);
+        });
+        missing.assert();
+        assert!(!handler.kek_cache.lock().unwrap().contains_key("revoked"));
+        assert_eq!(handler.metrics().snapshot().kek_cache_evictions, 1);
+
+        // Restored on the server, the key is fetched afresh
+        missing.remove();
+        let restored = mock_cached_key(&mut server, "revoked", 1);
+        runtime.block_on(async {
+            assert!(handler.handle(encrypt_request("revoked")).await.success);
+        });
+        restored.assert();
+    }
+
+    #[test]
+    fn test_kek_cache_capacity_evicts_oldest() {
+        let mut server = mockito::Server::new();
+        let oldest = mock_cached_key(&mut server, "oldest", 2);
+        let newest = mock_cached_key(&mut server, "newest", 1);
+        let handler = RequestHandler::new(&server.url()).unwrap().with_kek_cache_capacity(1);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            for key_id in ["oldest", "newest", "newest", "oldest"] {
+                assert!(handler.handle(encrypt_request(key_id)).await.success);
+            }
+        });
+
+        oldest.assert();
+        newest.assert();
+        let stats = handler.metrics().snapshot();
+        assert_eq!((stats.kek_cache_hits, stats.kek_cache_misses, stats.kek_cache_evictions), (1, 3, 2));
+    }
+
+    #[test]
+    fn test_undecodable_kek_is_not_cached() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("GET", "/v1/keys/garbled")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"uuid":"garbled","key":"not hex"}"#)
+            .expect(2)
+            .create();
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for _ in 0..2 {
+            let response = runtime.block_on(handler.handle(encrypt_request("garbled")));
+            assert_eq!(response.error_code, Some(ErrorCode::KeyUnavailable), "{:?}", response.error);
+            assert!(response.error.unwrap().contains("Key decode error"));
+        }
+        mock.assert();
+    }
+
+    #[test]
+    fn test_crypto_on_blocking_pool_round_trips() {
+        let mut server = mockito::Server::new();
+        let mock = mock_cached_key(&mut server, "pooled", 1);
+        let handler = RequestHandler::new(&server.url()).unwrap().with_blocking_threshold(0);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let envelope = envelope_of(handler.handle(encrypt_request("pooled")).await);
+            let response = handler.handle(decrypt_request(envelope)).await;
+            match response.result {
+                Some(ResponseResult::Decrypt { plaintext }) => assert_eq!(plaintext, BASE64.encode(b"hello")),
+                other => panic!("expected plaintext, got {:?} ({:?})", other, response.error),
+            }
+
+            let mut invalid = encrypt_request("pooled");
+            invalid.data.plaintext = "not base64!".into();
+            let response = handler.handle(invalid).await;
+            assert_eq!(response.error_code, Some(ErrorCode::InvalidRequest));
+        });
+        mock.assert();
+    }
+
+    #[test]
+    fn test_blocking_threads_bound_concurrent_crypto() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let handler = RequestHandler::new("http://127.0.0.1:9")
+            .unwrap()
+            .with_blocking_threshold(0)
+            .with_blocking_threads(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let work = (0..6).map(|_| {
+                let (running, most) = (Arc::clone(&running), Arc::clone(&most));
+                handler.offload(1, move || {
+                    most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            });
+            for result in futures::future::join_all(work).await {
+                result.unwrap();
+            }
+        });
+
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+    }
+
+    fn file_request(operation: Operation, input: &Path, output: &Path) -> Request {
+        Request {
+            operation,
+            data: RequestData {
+                key_id: (operation == Operation::EncryptFile).then(|| "file-key".to_string()),
+                input_path: Some(input.to_path_buf()),
+                output_path: Some(output.to_path_buf()),
+                ..RequestData::default()
+            },
+            ..encrypt_request("file-key")
+        }
+    }
+
+    #[test]
+    fn test_file_round_trip_in_allowed_dir() {
+        let mut server = mockito::Server::new();
+        let mock = mock_cached_key(&mut server, "file-key", 1);
+        let dir = tempfile::tempdir().unwrap();
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_stream_chunk_size(violet_core::MIN_CHUNK_SIZE)
+            .with_file_dirs([dir.path().to_path_buf()])
+            .unwrap();
+
+        let plaintext: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let input = dir.path().join("data.bin");
+        let encrypted = dir.path().join("data.vstr");
+        let decrypted = dir.path().join("data.out");
+        std::fs::write(&input, &plaintext).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let response = handler.handle(file_request(Operation::EncryptFile, &input, &encrypted)).await;
+            match response.result {
+                Some(ResponseResult::FileFinished {
+                    stream_header,
+                    output_path,
+                    envelope_path,
+                    chunks,
+                    plaintext_bytes,
+                    ciphertext_bytes,
+                }) => {
+                    assert_eq!(stream_header.key_id, "file-key");
+                    assert_eq!((chunks, plaintext_bytes), (5, 5000));
+                    assert_eq!(ciphertext_bytes, std::fs::metadata(&output_path).unwrap().len());
+                    let sidecar: StreamHeader =
+                        serde_json::from_slice(&std::fs::read(envelope_path.unwrap()).unwrap()).unwrap();
+                    assert_eq!(sidecar, stream_header);
+                }
+                other => panic!("expected file result, got {:?} ({:?})", other, response.error),
+            }
+
+            // The file is in the stream format the library reads
+            let mut opened = Vec::new();
+            let kek = SecretKey::from_hex(&"55".repeat(32)).unwrap();
+            let reader = File::open(&encrypted).unwrap();
+            StreamEncryptor::new(Algorithm::default()).decrypt_stream(reader, &mut opened, &kek).unwrap();
+            assert_eq!(opened, plaintext);
+
+            let response = handler.handle(file_request(Operation::DecryptFile, &encrypted, &decrypted)).await;
+            assert!(response.success, "{:?}", response.error);
+            assert_eq!(std::fs::read(&decrypted).unwrap(), plaintext);
+
+            // An existing output is never overwritten
+            let response = handler.handle(file_request(Operation::DecryptFile, &encrypted, &decrypted)).await;
+            assert_eq!(response.error_code, Some(ErrorCode::InvalidRequest));
+        });
+        mock.assert();
+    }
+
+    #[test]
+    fn test_file_paths_outside_allow_list_are_refused() {
+        let allowed = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let secret = outside.path().join("secret.txt");
+        std::fs::write(&secret, b"not for the socket").unwrap();
+        let link = allowed.path().join("link.txt");
+        std::os::unix::fs::symlink(&secret, &link).unwrap();
+        let dotdot = allowed
+            .path()
+            .join("..")
+            .join(outside.path().file_name().unwrap())
+            .join("secret.txt");
+        let inside = allowed.path().join("out.vstr");
+
+        // The Keys server is never reached
+        let handler = RequestHandler::new("http://127.0.0.1:9")
+            .unwrap()
+            .with_file_dirs([allowed.path().to_path_buf()])
+            .unwrap();
+        // Without allowed directories file operations are off altogether
+        let disabled = RequestHandler::new("http://127.0.0.1:9").unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let refused = [
+                (Operation::EncryptFile, secret.clone(), inside.clone()),
+                (Operation::EncryptFile, dotdot.clone(), inside.clone()),
+                (Operation::EncryptFile, link.clone(), inside.clone()),
+                (Operation::DecryptFile, link.clone(), outside.path().join("plain.txt")),
+            ];
+            for (operation, input, output) in refused {
+                let response = handler.handle(file_request(operation, &input, &output)).await;
+                assert_eq!(response.error_code, Some(ErrorCode::PathNotPermitted), "{}", input.display());
+            }
+
+            let input = allowed.path().join("in.txt");
+            std::fs::write(&input, b"hello").unwrap();
+            let response = handler.handle(file_request(Operation::EncryptFile, &input, Path::new("out.vstr"))).await;
+            assert_eq!(response.error_code, Some(ErrorCode::InvalidRequest));
+
+            let response = disabled.handle(file_request(Operation::EncryptFile, &inside, &inside)).await;
+            assert_eq!(response.error_code, Some(ErrorCode::OperationNotAllowed));
+        });
+
+        assert!(!inside.exists());
+        assert!(!outside.path().join("plain.txt").exists());
+    }
+
+    #[test]
+    fn test_paths_swapped_for_symlinks_after_the_check_are_refused() {
+        let allowed = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let sub = allowed.path().join("sub");
+        std::fs::create_dir(&sub).unwrap();
+        std::fs::write(sub.join("in.txt"), b"checked").unwrap();
+        std::fs::write(outside.path().join("in.txt"), b"not for the socket").unwrap();
+
+        let handler = RequestHandler::new("http://127.0.0.1:9")
+            .unwrap()
+            .with_file_dirs([allowed.path().to_path_buf()])
+            .unwrap();
+        let request = file_request(Operation::EncryptFile, &sub.join("in.txt"), &sub.join("out.vstr"));
+        let (input, output) = handler.file_paths(&request.data).unwrap();
+
+        // The directory is replaced by a symlink out of the allowed directory
+        std::fs::remove_dir_all(&sub).unwrap();
+        std::os::unix::fs::symlink(outside.path(), &sub).unwrap();
+
+        let encryptor = StreamEncryptor::new(Algorithm::default());
+        let response = encrypt_file(&encryptor, &[0x55; 32], "file-key".into(), &input, &output, &output.sidecar());
+        assert_eq!(response.error_code, Some(ErrorCode::InvalidRequest));
+        assert!(matches!(open_stream_file(&input), Err(response) if response.error_code == Some(ErrorCode::InvalidRequest)));
+        assert!(!outside.path().join("out.vstr").exists());
+        assert!(!outside.path().join("out.vstr.envelope.json").exists());
+    }
+
+    /// Collects audit records in memory
+    #[derive(Default)]
+    struct MemoryAuditSink(Mutex<Vec<AuditRecord>>);
+
+    impl AuditSink for MemoryAuditSink {
+        fn record(&self, record: &AuditRecord) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_audit_records_success_and_failure() {
+        let kek = [0x66u8; 32];
+        let mut server = mockito::Server::new();
+        let _found = server
+            .mock("GET", "/v1/keys/audited")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"audited","key":"{}"}}"#, "66".repeat(32)))
+            .create();
+        let _missing = server.mock("GET", "/v1/keys/missing").with_status(404).create();
+
+        let sink = Arc::new(MemoryAuditSink::default());
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_audit_log(AuditLog::new(sink.clone()).strict(true));
+
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"secret plaintext", &kek, "audited".to_string())
+            .unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            assert!(handler.handle(encrypt_request("audited")).await.success);
+            assert!(!handler.handle(encrypt_request("missing")).await.success);
+            assert!(handler.handle(decrypt_request(envelope)).await.success);
+        });
+
+        let records = sink.0.lock().unwrap();
+        assert_eq!(records.len(), 3);
+
+        assert_eq!(records[0].operation, Operation::Encrypt);
+        assert_eq!(records[0].key_id.as_deref(), Some("audited"));
+        assert!(records[0].success);
+
+        assert_eq!(records[1].key_id.as_deref(), Some("missing"));
+        assert!(!records[1].success);
+        assert!(records[1].error.is_some());
+
+        assert_eq!(records[2].operation, Operation::Decrypt);
+        assert!(records[2].success);
+
+        // Nothing sensitive ever reaches the sink
+        let serialized = serde_json::to_string(&*records).unwrap();
+        assert!(!serialized.contains("secret plaintext"));
+        assert!(!serialized.contains(&BASE64.encode(b"secret plaintext")));
+        assert!(!serialized.contains(&BASE64.encode(b"hello")));
+        assert!(!serialized.contains(&"66".repeat(32)));
+    }
+
+    fn mock_audited_key(server: &mut mockito::ServerGuard) -> mockito::Mock {
+        server
+            .mock("GET", "/v1/keys/audited")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"audited","key":"{}"}}"#, "66".repeat(32)))
+            .create()
+    }
+
+    #[test]
+    fn test_audit_entry_shape() {
+        let mut server = mockito::Server::new();
+        let _found = mock_audited_key(&mut server);
+        let _missing = server.mock("GET", "/v1/keys/missing").with_status(404).create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let sink = Arc::new(crate::audit::FileAuditSink::open(&path).unwrap());
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_audit_log(AuditLog::new(sink).strict(true));
+        let peer = PeerCredentials { uid: 1000, gid: 100, pid: Some(4242) };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let envelope = runtime.block_on(async {
+            let request = Request { id: Some("req-7".into()), ..encrypt_request("audited") };
+            let response = handler.handle_from(request, Some(peer)).await;
+            assert!(!handler.handle(encrypt_request("missing")).await.success);
+            match response.result {
+                Some(ResponseResult::Encrypt { envelope }) => envelope,
+                other => panic!("expected envelope, got {:?}", other),
+            }
+        });
+        runtime.block_on(handler.handle(decrypt_request(envelope)));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 3);
+
+        let encrypt = &lines[0];
+        assert!(encrypt["timestampMs"].as_u64().unwrap() > 0);
+        assert_eq!(encrypt["requestId"], "req-7");
+        assert_eq!(encrypt["operation"], "encrypt");
+        assert_eq!(encrypt["keyId"], "audited");
+        assert_eq!(encrypt["plaintextBytes"], 5);
+        assert_eq!(encrypt["ciphertextBytes"], 5);
+        assert_eq!(encrypt["peer"], serde_json::json!({"uid": 1000, "gid": 100, "pid": 4242}));
+        assert_eq!(encrypt["success"], true);
+        assert!(encrypt.get("errorCode").is_none());
+        assert_eq!(encrypt["timings"]["kekCacheMisses"], 1);
+        assert!(encrypt["timings"]["totalMs"].as_f64().unwrap() > 0.0);
+
+        // Requests without an id or peer simply omit them
+        let failed = &lines[1];
+        assert!(failed.get("requestId").is_none());
+        assert!(failed.get("peer").is_none());
+        assert!(failed.get("ciphertextBytes").is_none());
+        assert_eq!(failed["success"], false);
+        assert_eq!(failed["errorCode"], "key_unavailable");
+
+        let decrypt = &lines[2];
+        assert_eq!(decrypt["operation"], "decrypt");
+        assert_eq!(decrypt["plaintextBytes"], 5);
+        assert_eq!(decrypt["ciphertextBytes"], 5);
+        assert_eq!(decrypt["timings"]["kekCacheHits"], 1);
+
+        assert_eq!(crate::audit::FileAuditSink::verify(&path).unwrap(), 3);
+    }
+
+    /// Fails every write, like a full disk
+    struct FailingAuditSink;
+
+    impl AuditSink for FailingAuditSink {
+        fn record(&self, _record: &AuditRecord) -> std::io::Result<()> {
+            Err(std::io::Error::new(std::io::ErrorKind::StorageFull, "disk full"))
+        }
+    }
+
+    #[test]
+    fn test_audit_failure_fails_request_only_when_strict() {
+        let mut server = mockito::Server::new();
+        let _found = mock_audited_key(&mut server);
+
+        let lenient = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_audit_sink(Arc::new(FailingAuditSink));
+        let strict = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_audit_log(AuditLog::new(Arc::new(FailingAuditSink)).strict(true));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let response = lenient.handle(encrypt_request("audited")).await;
+            assert!(response.success, "{:?}", response.error);
+
+            // The result is withheld when it cannot be audited
+            let response = strict.handle(encrypt_request("audited")).await;
+            assert!(!response.success);
+            assert_eq!(response.error_code, Some(ErrorCode::AuditFailed));
+            assert!(response.error.unwrap().contains("disk full"));
+            assert!(response.result.is_none());
+        });
+    }
+
+    fn hello_request(version: Option<u32>) -> Request {
+        Request {
+            id: None,
+            version,
+            operation: Operation::Hello,
+            include_timings: false,
+            data: RequestData::default(),
+        }
+    }
+
+    #[test]
+    fn test_hello_reports_capabilities() {
+        let handler = RequestHandler::new("http://127.0.0.1:1").unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(hello_request(Some(PROTOCOL_VERSION))));
+
+        assert!(response.success);
+        assert_eq!(response.version, PROTOCOL_VERSION);
+        match response.result {
+            Some(ResponseResult::Hello(info)) => {
+                assert_eq!(info.protocol_version, PROTOCOL_VERSION);
+                assert_eq!(info.crate_version, env!("CARGO_PKG_VERSION"));
+                assert!(info.operations.contains(&Operation::Encrypt));
+                assert!(info.algorithms.contains(&Algorithm::Aes256GcmSiv));
+            }
+            other => panic!("expected hello result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_future_version_rejected() {
+        let mut server = mockito::Server::new();
+        let mock = server.mock("GET", mockito::Matcher::Any).expect(0).create();
+
+        let sink = Arc::new(MemoryAuditSink::default());
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_audit_log(AuditLog::new(sink.clone()).strict(true));
+        let request = Request {
+            version: Some(PROTOCOL_VERSION + 1),
+            ..encrypt_request("any-key")
+        };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(request));
+
+        assert!(!response.success);
+        assert_eq!(response.error_code, Some(ErrorCode::UnsupportedVersion));
+        assert_eq!(response.version, PROTOCOL_VERSION);
+        assert!(response.error.unwrap().contains("version 2"));
+
+        // The rejected request still counts as a failed encrypt
+        assert_eq!(sink.0.lock().unwrap().len(), 1);
+        mock.assert();
+    }
+
+    #[test]
+    fn test_missing_version_is_v1() {
+        let handler = RequestHandler::new("http://127.0.0.1:1").unwrap();
+        let request: Request = serde_json::from_str(r#"{"operation":"hello"}"#).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(request));
+
+        assert!(response.success);
+        assert_eq!(response.version, 1);
+        assert_eq!(response.error_code, None);
+    }
+
+    fn create_key_request(include_key_material: bool) -> Request {
+        Request {
+            id: None,
+            version: None,
+            operation: Operation::CreateKey,
+            include_timings: false,
+            data: RequestData {
+                include_key_material,
+                ..RequestData::default()
+            },
+        }
+    }
+
+    fn mock_create_key(server: &mut mockito::ServerGuard, expect: usize) -> mockito::Mock {
+        server
+            .mock("POST", "/v1/keys/")
+            .with_status(201)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"provisioned","key":"{}"}}"#, "77".repeat(32)))
+            .expect(expect)
+            .create()
+    }
+
+    #[test]
+    fn test_create_key_returns_only_key_id_by_default() {
+        let mut server = mockito::Server::new();
+        let mock = mock_create_key(&mut server, 1);
+
+        let sink = Arc::new(MemoryAuditSink::default());
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_audit_log(AuditLog::new(sink.clone()).strict(true));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(create_key_request(false)));
+
+        assert!(response.success, "{:?}", response.error);
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(!json.contains(&"77".repeat(32)), "{}", json);
+        assert!(matches!(
+            response.result,
+            Some(ResponseResult::KeyCreated { ref key_id, key: None }) if key_id == "provisioned"
+        ));
+
+        // Asking for the material without export enabled is refused before creating a key
+        let refused = runtime.block_on(handler.handle(create_key_request(true)));
+        assert!(!refused.success);
+        assert_eq!(refused.error_code, Some(ErrorCode::InvalidRequest));
+        assert!(refused.error.unwrap().contains("disabled"));
+
+        let records = sink.0.lock().unwrap();
+        assert_eq!(records[0].operation, Operation::CreateKey);
+        assert_eq!(records[0].key_id.as_deref(), Some("provisioned"));
+        mock.assert();
+    }
+
+    #[test]
+    fn test_create_key_returns_material_when_allowed() {
+        let mut server = mockito::Server::new();
+        let mock = mock_create_key(&mut server, 1);
+
+        let handler = RequestHandler::new(&server.url()).unwrap().allow_key_export(true);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(create_key_request(true)));
+
+        match response.result {
+            Some(ResponseResult::KeyCreated { key_id, key }) => {
+                assert_eq!(key_id, "provisioned");
+                assert_eq!(key, Some("77".repeat(32)));
+            }
+            other => panic!("expected key created result, got {:?} ({:?})", other, response.error),
+        }
+        mock.assert();
+    }
+
+    #[test]
+    fn test_create_key_failure_has_error_code() {
+        let mut server = mockito::Server::new();
+        let mock = server.mock("POST", "/v1/keys/").with_status(500).create();
+
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(create_key_request(false)));
+
+        assert!(!response.success);
+        assert_eq!(response.error_code, Some(ErrorCode::KeyUnavailable), "{:?}", response.error);
+        mock.assert();
+    }
+
+    /// Serve `uuid` as a KEK of 32 copies of `byte`
+    fn mock_key(server: &mut mockito::ServerGuard, uuid: &str, byte: u8) -> mockito::Mock {
+        server
+            .mock("GET", format!("/v1/keys/{}", uuid).as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"{}","key":"{}"}}"#, uuid, format!("{:02x}", byte).repeat(32)))
+            .create()
+    }
+
+    fn rewrap_request(envelope: EncryptionEnvelope, new_key_id: Option<&str>) -> Request {
+        Request {
+            id: None,
+            version: None,
+            operation: Operation::Rewrap,
+            include_timings: false,
+            data: RequestData {
+                envelope: Some(envelope),
+                new_key_id: new_key_id.map(str::to_string),
+                ..RequestData::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_rewrap_to_existing_key() {
+        let old_kek = [0x88u8; 32];
+        let new_kek = [0x99u8; 32];
+        let mut server = mockito::Server::new();
+        let _old = mock_key(&mut server, "old-key", 0x88);
+        let _new = mock_key(&mut server, "new-key", 0x99);
+
+        let original = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv)
+            .encrypt(b"never leaves the daemon", &old_kek, "old-key".to_string())
+            .unwrap();
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(rewrap_request(original.clone(), Some("new-key"))));
+
+        let (rewrapped, previous_key_id) = match response.result {
+            Some(ResponseResult::Rewrapped { envelope, previous_key_id }) => (envelope, previous_key_id),
+            other => panic!("expected rewrap result, got {:?} ({:?})", other, response.error),
+        };
+        assert_eq!(previous_key_id, "old-key");
+        assert_eq!(rewrapped.key_id, "new-key");
+        assert_eq!(rewrapped.encrypted_data, original.encrypted_data);
+
+        let encryptor = EnvelopeEncryptor::new(Algorithm::Aes256GcmSiv);
+        assert_eq!(encryptor.decrypt(&rewrapped, &new_kek).unwrap(), b"never leaves the daemon");
+        assert!(encryptor.decrypt(&rewrapped, &old_kek).is_err());
+    }
+
+    #[test]
+    fn test_rewrap_creates_key_when_absent() {
+        let old_kek = [0x88u8; 32];
+        let mut server = mockito::Server::new();
+        let _old = mock_key(&mut server, "old-key", 0x88);
+        let create = mock_create_key(&mut server, 1);
+
+        let original = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"rotate", &old_kek, "old-key".to_string())
+            .unwrap();
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(rewrap_request(original, None)));
+
+        match response.result {
+            Some(ResponseResult::Rewrapped { envelope, .. }) => {
+                assert_eq!(envelope.key_id, "provisioned");
+                let plaintext = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+                    .decrypt(&envelope, &[0x77u8; 32])
+                    .unwrap();
+                assert_eq!(plaintext, b"rotate");
+            }
+            other => panic!("expected rewrap result, got {:?} ({:?})", other, response.error),
+        }
+        create.assert();
+    }
+
+    #[test]
+    fn test_rewrap_failures_have_error_codes() {
+        let mut server = mockito::Server::new();
+        // Serves a different KEK from the one the envelope was sealed under
+        let _old = mock_key(&mut server, "old-key", 0x11);
+        let _new = mock_key(&mut server, "new-key", 0x99);
+        let _missing = server.mock("GET", "/v1/keys/missing-key").with_status(404).create();
+
+        let original = EnvelopeEncryptor::new(Algorithm::default())
+            .encrypt(b"rotate", &[0x88u8; 32], "old-key".to_string())
+            .unwrap();
+        let handler = RequestHandler::new(&server.url()).unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let mut no_envelope = rewrap_request(original.clone(), Some("new-key"));
+        no_envelope.data.envelope = None;
+        let mut unknown_key = original.clone();
+        unknown_key.key_id = "missing-key".into();
+        let cases = [
+            (no_envelope, ErrorCode::InvalidRequest),
+            (rewrap_request(unknown_key, Some("new-key")), ErrorCode::KeyUnavailable),
+            (rewrap_request(original.clone(), Some("missing-key")), ErrorCode::KeyUnavailable),
+            (rewrap_request(original, Some("new-key")), ErrorCode::CryptoFailed),
+        ];
+        for (request, code) in cases {
+            let response = runtime.block_on(handler.handle(request));
+            assert!(!response.success, "{:?}", response.result);
+            assert_eq!(response.error_code, Some(code), "{:?}", response.error);
+        }
+    }
+
+    fn ping(handler: &RequestHandler, runtime: &tokio::runtime::Runtime) -> (u64, bool) {
+        let request = Request {
+            id: None,
+            version: None,
+            operation: Operation::Ping,
+            include_timings: false,
+            data: RequestData::default(),
+        };
+        let response = runtime.block_on(handler.handle(request));
+        assert!(response.success, "{:?}", response.error);
+        match response.result {
+            Some(ResponseResult::Pong { uptime_secs, keys_server_ok, version }) => {
+                assert_eq!(version, env!("CARGO_PKG_VERSION"));
+                (uptime_secs, keys_server_ok)
+            }
+            other => panic!("expected pong, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ping_caches_health_probe() {
+        let mut server = mockito::Server::new();
+        let probe = server.mock("HEAD", "/v1/keys/").with_status(200).expect(1).create();
+
+        let handler = RequestHandler::new(&server.url()).unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for _ in 0..5 {
+            assert_eq!(ping(&handler, &runtime), (0, true));
+        }
+        probe.assert();
+    }
+
+    #[test]
+    fn test_ping_reports_unreachable_keys_server() {
+        let handler = RequestHandler::new("http://127.0.0.1:9")
+            .unwrap()
+            .with_health_check_interval(Duration::ZERO);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        // The ping itself succeeds; only the upstream flag is down
+        let (_, keys_server_ok) = ping(&handler, &runtime);
+        assert!(!keys_server_ok);
+    }
+
+    fn batch_request(items: Vec<BatchItem>) -> Request {
+        Request {
+            id: None,
+            version: None,
+            operation: Operation::Batch,
+            include_timings: false,
+            data: RequestData {
+                items,
+                ..RequestData::default()
+            },
+        }
+    }
+
+    fn batch_item(request: Request) -> BatchItem {
+        BatchItem {
+            operation: request.operation,
+            data: request.data,
+        }
+    }
+
+    fn batch_results(response: Response) -> Vec<BatchItemResult> {
+        match response.result {
+            Some(ResponseResult::Batch { results }) => results,
+            other => panic!("expected batch result, got {:?} ({:?})", other, response.error),
+        }
+    }
+
+    #[test]
+    fn test_batch_mixed_success_and_failure() {
+        let kek = [0x55u8; 32];
+        let mut server = mockito::Server::new();
+        let found = server
+            .mock("GET", "/v1/keys/batch-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"batch-key","key":"{}"}}"#, "55".repeat(32)))
+            .expect(1)
+            .create();
+        let missing = server.mock("GET", "/v1/keys/missing").with_status(404).expect(1).create();
+
+        let envelope = EnvelopeEncryptor::new(Algorithm::Aes256Gcm)
+            .encrypt(b"stored record", &kek, "batch-key".to_string())
+            .unwrap();
+        let mut tampered = envelope.clone();
+        tampered.encrypted_data = BASE64.encode(b"not the ciphertext");
+
+        let mut bad_base64 = encrypt_request("batch-key");
+        bad_base64.data.plaintext = "not base64!".into();
+
+        let sink = Arc::new(MemoryAuditSink::default());
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_audit_log(AuditLog::new(sink.clone()).strict(true));
+
+        let request = batch_request(vec![
+            batch_item(encrypt_request("batch-key")),
+            batch_item(bad_base64),
+            batch_item(decrypt_request(envelope)),
+            batch_item(decrypt_request(tampered)),
+            batch_item(encrypt_request("missing")),
+            batch_item(hello_request(None)),
+            batch_item(encrypt_request("batch-key")),
+        ]);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(request));
+        assert!(response.success, "{:?}", response.error);
+        let results = batch_results(response);
+        assert_eq!(results.len(), 7);
+
+        let codes: Vec<_> = results.iter().map(|r| r.error_code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                None,
+                Some(ErrorCode::InvalidRequest),
+                None,
+                Some(ErrorCode::CryptoFailed),
+                Some(ErrorCode::KeyUnavailable),
+                Some(ErrorCode::UnsupportedOperation),
+                None,
+            ]
+        );
+        for result in &results {
+            assert_eq!(result.success, result.error_code.is_none(), "{:?}", result);
+            assert_eq!(result.success, result.error.is_none(), "{:?}", result);
+        }
+
+        match &results[0].result {
+            Some(ResponseResult::Encrypt { envelope }) => {
+                assert_eq!(envelope.key_id, "batch-key");
+                let plaintext = EnvelopeEncryptor::new(Algorithm::Aes256Gcm).decrypt(envelope, &kek).unwrap();
+                assert_eq!(plaintext, b"hello");
+            }
+            other => panic!("expected encrypt result, got {:?}", other),
+        }
+        match &results[2].result {
+            Some(ResponseResult::Decrypt { plaintext }) => assert_eq!(plaintext, &BASE64.encode(b"stored record")),
+            other => panic!("expected decrypt result, got {:?}", other),
+        }
+
+        // Each item is audited on its own; the unsupported one never ran
+        assert_eq!(sink.0.lock().unwrap().len(), 6);
+
+        // Both keys were looked up once for the whole batch
+        found.assert();
+        missing.assert();
+    }
+
+    fn keyless_encrypt_request() -> Request {
+        let mut request = encrypt_request("unused");
+        request.data.key_id = None;
+        request
+    }
+
+    fn envelope_key_id(response: Response) -> String {
+        match response.result {
+            Some(ResponseResult::Encrypt { envelope }) => envelope.key_id,
+            other => panic!("expected envelope, got {:?} ({:?})", other, response.error),
+        }
+    }
+
+    #[test]
+    fn test_concurrent_keyless_encrypts_share_one_key() {
+        let mut server = mockito::Server::new();
+        let mock = mock_create_key(&mut server, 1);
+
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_shared_key_ttl(Duration::from_secs(60));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let key_ids: Vec<String> = runtime.block_on(async {
+            let requests = (0..16).map(|_| handler.handle(keyless_encrypt_request()));
+            futures::future::join_all(requests).await.into_iter().map(envelope_key_id).collect()
+        });
+
+        assert_eq!(key_ids.len(), 16);
+        assert!(key_ids.iter().all(|key_id| key_id == "provisioned"), "{:?}", key_ids);
+        mock.assert();
+    }
+
+    #[test]
+    fn test_shared_key_is_replaced_after_ttl() {
+        let mut server = mockito::Server::new();
+        let mock = mock_create_key(&mut server, 2);
+
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_shared_key_ttl(Duration::from_millis(50));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            envelope_key_id(handler.handle(keyless_encrypt_request()).await);
+            envelope_key_id(handler.handle(keyless_encrypt_request()).await);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            envelope_key_id(handler.handle(keyless_encrypt_request()).await);
+        });
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_keyless_encrypts_get_own_key_by_default() {
+        let mut server = mockito::Server::new();
+        let mock = mock_create_key(&mut server, 2);
+
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            envelope_key_id(handler.handle(keyless_encrypt_request()).await);
+            envelope_key_id(handler.handle(keyless_encrypt_request()).await);
+        });
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_require_key_id_refuses_keyless_encrypts() {
+        let mut server = mockito::Server::new();
+        let create = mock_create_key(&mut server, 0);
+        let named = mock_key(&mut server, "named", 0x42);
+
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .require_key_id(true)
+            .with_shared_key_ttl(Duration::from_secs(60));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(keyless_encrypt_request()));
+        assert!(!response.success);
+        assert_eq!(response.error_code, Some(ErrorCode::InvalidRequest));
+        assert!(response.error.unwrap().contains("key_id is required"));
+
+        let response = runtime.block_on(handler.handle(encrypt_request("named")));
+        assert_eq!(envelope_key_id(response), "named");
+
+        create.assert();
+        named.assert();
+    }
+
+    #[test]
+    fn test_batch_shares_new_key_for_items_without_key_id() {
+        let mut server = mockito::Server::new();
+        let create = mock_create_key(&mut server, 1);
+
+        let handler = RequestHandler::new(&server.url()).unwrap();
+        let items = (0..3).map(|i| batch_item(encrypt_new_key_request(&format!("row-{}", i)))).collect();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let results = batch_results(runtime.block_on(handler.handle(batch_request(items))));
+
+        assert_eq!(results.len(), 3);
+        for result in results {
+            match result.result {
+                Some(ResponseResult::Encrypt { envelope }) => assert_eq!(envelope.key_id, "provisioned"),
+                other => panic!("expected encrypt result, got {:?} ({:?})", other, result.error),
+            }
+        }
+        create.assert();
+    }
+
+    #[test]
+    fn test_batch_size_limit() {
+        let mut server = mockito::Server::new();
+        let mock = server.mock("GET", mockito::Matcher::Any).expect(0).create();
+
+        let handler = RequestHandler::new(&server.url()).unwrap().with_max_batch_size(2);
+        let items = |n: usize| -> Vec<BatchItem> { (0..n).map(|_| batch_item(encrypt_request("any-key"))).collect() };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(batch_request(items(3))));
+        assert!(!response.success);
+        assert_eq!(response.error_code, Some(ErrorCode::BatchTooLarge));
+        assert!(response.error.unwrap().contains("limit is 2"));
+
+        // An empty batch is fine and needs no keys
+        let response = runtime.block_on(handler.handle(batch_request(items(0))));
+        assert!(batch_results(response).is_empty());
+        mock.assert();
+    }
+
+    #[test]
+    fn test_key_id_hash() {
+        let hash = key_id_hash("some-key");
+        assert_eq!(hash.len(), 16);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(hash, key_id_hash("some-key"));
+        assert_ne!(hash, key_id_hash("other-key"));
+    }
+
+    #[test]
+    fn test_request_id_echoed_and_forwarded() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("GET", "/v1/keys/traced-key")
+            .match_header("x-request-id", "req-123")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"traced-key","key":"{}"}}"#, "12".repeat(32)))
+            .expect(1)
+            .create();
+
+        let handler = RequestHandler::new(&server.url()).unwrap();
+        let request = Request {
+            id: Some("req-123".into()),
+            ..encrypt_request("traced-key")
+        };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(handler.handle(request));
+        assert!(response.success, "{:?}", response.error);
+        assert_eq!(response.id.as_deref(), Some("req-123"));
+
+        // Failures echo the id too, and requests without one get none back
+        let mut failing = hello_request(Some(PROTOCOL_VERSION + 1));
+        failing.id = Some("req-124".into());
+        assert_eq!(runtime.block_on(handler.handle(failing)).id.as_deref(), Some("req-124"));
+        assert_eq!(runtime.block_on(handler.handle(hello_request(None))).id, None);
+        mock.assert();
+    }
+
+    #[test]
+    fn test_idempotency_key_replays_envelope() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("POST", "/v1/keys/")
+            .with_status(201)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"created-once","key":"{}"}}"#, "22".repeat(32)))
+            .expect(1)
+            .create();
+
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (first, second) = runtime.block_on(async {
+            let first = handler.handle(encrypt_new_key_request("retry-1")).await;
+            let second = handler.handle(encrypt_new_key_request("retry-1")).await;
+            (envelope_of(first), envelope_of(second))
+        });
+
+        assert_eq!(first.key_id, "created-once");
+        assert_eq!(first.key_id, second.key_id);
+        assert_eq!(first, second);
+        mock.assert();
+    }
+
+    fn new_key_mock(server: &mut mockito::Server, created: usize) -> mockito::Mock {
+        server
+            .mock("POST", "/v1/keys/")
+            .with_status(201)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"created-once","key":"{}"}}"#, "22".repeat(32)))
+            .expect(created)
+            .create()
+    }
+
+    #[test]
+    fn test_idempotency_key_reused_for_another_request_conflicts() {
+        let mut server = mockito::Server::new();
+        let mock = new_key_mock(&mut server, 1);
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        // A failed request does not claim its key
+        let mut invalid = encrypt_new_key_request("retry-1");
+        invalid.data.plaintext = "not base64!".into();
+        let response = runtime.block_on(handler.handle(invalid));
+        assert_eq!(response.error_code, Some(ErrorCode::InvalidRequest), "{:?}", response.error);
+        envelope_of(runtime.block_on(handler.handle(encrypt_new_key_request("retry-1"))));
+
+        let mut other_plaintext = encrypt_new_key_request("retry-1");
+        other_plaintext.data.plaintext = BASE64.encode(b"goodbye");
+        let mut other_key = encrypt_new_key_request("retry-1");
+        other_key.data.key_id = Some("another-key".into());
+        for request in [other_plaintext, other_key] {
+            let response = runtime.block_on(handler.handle(request));
+            assert!(!response.success, "{:?}", response.result);
+            assert_eq!(response.error_code, Some(ErrorCode::IdempotencyConflict), "{:?}", response.error);
+        }
+        mock.assert();
+    }
+
+    #[test]
+    fn test_concurrent_idempotent_retries_encrypt_once() {
+        let mut server = mockito::Server::new();
+        let mock = new_key_mock(&mut server, 1);
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (first, second) = runtime.block_on(async {
+            futures::join!(
+                handler.handle(encrypt_new_key_request("retry-1")),
+                handler.handle(encrypt_new_key_request("retry-1"))
+            )
+        });
+
+        assert_eq!(envelope_of(first), envelope_of(second));
+        mock.assert();
+    }
+
+    #[test]
+    fn test_idempotency_keys_are_scoped_by_peer() {
+        let mut server = mockito::Server::new();
+        let mock = new_key_mock(&mut server, 2);
+        let handler = RequestHandler::new(&server.url()).unwrap();
+        let alice = PeerCredentials { uid: 1000, gid: 100, pid: None };
+        let bob = PeerCredentials { uid: 1001, gid: 100, pid: None };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (first, second, retry) = runtime.block_on(async {
+            let first = handler.handle_from(encrypt_new_key_request("retry-1"), Some(alice)).await;
+            let second = handler.handle_from(encrypt_new_key_request("retry-1"), Some(bob)).await;
+            let retry = handler.handle_from(encrypt_new_key_request("retry-1"), Some(alice)).await;
+            (envelope_of(first), envelope_of(second), envelope_of(retry))
+        });
+
+        assert_ne!(first, second);
+        assert_eq!(first, retry);
+        mock.assert();
+    }
+
+    #[test]
+    fn test_idempotency_capacity_evicts_oldest() {
+        let mut server = mockito::Server::new();
+        let mock = new_key_mock(&mut server, 3);
+        let handler = RequestHandler::new(&server.url()).unwrap().with_idempotency_capacity(1);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            for idempotency_key in ["retry-1", "retry-2", "retry-1"] {
+                envelope_of(handler.handle(encrypt_new_key_request(idempotency_key)).await);
+            }
+        });
+        mock.assert();
+    }
+
+    fn assert_not_allowed(response: &Response) {
+        assert!(!response.success, "{:?}", response.result);
+        assert_eq!(response.error_code, Some(ErrorCode::OperationNotAllowed), "{:?}", response.error);
+    }
+
+    fn reported_mode(handler: &RequestHandler, runtime: &tokio::runtime::Runtime) -> (DaemonMode, DaemonMode) {
+        let hello = match runtime.block_on(handler.handle(hello_request(None))).result {
+            Some(ResponseResult::Hello(info)) => info.mode,
+            other => panic!("expected hello result, got {:?}", other),
+        };
+        let stats_request = Request {
+            id: None,
+            version: None,
+            operation: Operation::Stats,
+            include_timings: false,
+            data: RequestData::default(),
+        };
+        let stats = match runtime.block_on(handler.handle(stats_request)).result {
+            Some(ResponseResult::Stats(stats)) => stats.mode,
+            other => panic!("expected stats result, got {:?}", other),
+        };
+        (hello, stats)
+    }
+
+    #[test]
+    fn test_decrypt_only_mode() {
+        let kek = [0x55u8; 32];
+        let mut server = mockito::Server::new();
+        let _key = mock_cached_key(&mut server, "archived", 1);
+        let create = mock_create_key(&mut server, 0);
+
+        let envelope = EnvelopeEncryptor::new(Algorithm::default())
+            .encrypt(b"archived record", &kek, "archived".to_string())
+            .unwrap();
+        let handler = RequestHandler::new(&server.url()).unwrap().with_mode(DaemonMode::DecryptOnly);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(reported_mode(&handler, &runtime), (DaemonMode::DecryptOnly, DaemonMode::DecryptOnly));
+
+        let response = runtime.block_on(handler.handle(decrypt_request(envelope.clone())));
+        assert!(response.success, "{:?}", response.error);
+
+        for request in [
+            encrypt_request("archived"),
+            keyless_encrypt_request(),
+            create_key_request(false),
+            rewrap_request(envelope.clone(), Some("archived")),
+            rewrap_request(envelope.clone(), None),
+        ] {
+            assert_not_allowed(&runtime.block_on(handler.handle(request)));
+        }
+
+        // Refused batch items get no key, created or fetched; the rest still run
+        let request = batch_request(vec![
+            batch_item(keyless_encrypt_request()),
+            batch_item(decrypt_request(envelope)),
+        ]);
+        let results = batch_results(runtime.block_on(handler.handle(request)));
+        assert_eq!(results[0].error_code, Some(ErrorCode::OperationNotAllowed));
+        assert!(results[1].success, "{:?}", results[1].error);
+
+        create.assert();
+    }
+
+    #[test]
+    fn test_encrypt_only_mode() {
+        let mut server = mockito::Server::new();
+        let _key = mock_cached_key(&mut server, "ingest", 1);
+        let create = mock_create_key(&mut server, 2);
+
+        let handler = RequestHandler::new(&server.url()).unwrap().with_mode(DaemonMode::EncryptOnly);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(reported_mode(&handler, &runtime), (DaemonMode::EncryptOnly, DaemonMode::EncryptOnly));
+
+        let envelope = envelope_of(runtime.block_on(handler.handle(encrypt_request("ingest"))));
+        let created = envelope_of(runtime.block_on(handler.handle(keyless_encrypt_request())));
+        assert_eq!(created.key_id, "provisioned");
+        let response = runtime.block_on(handler.handle(create_key_request(false)));
+        assert!(response.success, "{:?}", response.error);
+
+        assert_not_allowed(&runtime.block_on(handler.handle(decrypt_request(envelope.clone()))));
+        assert_not_allowed(&runtime.block_on(handler.handle(rewrap_request(envelope.clone(), Some("ingest")))));
+
+        let results = batch_results(runtime.block_on(handler.handle(batch_request(vec![
+            batch_item(encrypt_request("ingest")),
+            batch_item(decrypt_request(envelope)),
+        ]))));
+        assert!(results[0].success, "{:?}", results[0].error);
+        assert_eq!(results[1].error_code, Some(ErrorCode::OperationNotAllowed));
+
+        create.assert();
+    }
+
+    #[test]
+    fn test_full_mode_is_default() {
+        let handler = RequestHandler::new("http://127.0.0.1:1").unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(reported_mode(&handler, &runtime), (DaemonMode::Full, DaemonMode::Full));
+    }
+
+    fn key_allow_list(key_ids: &[&str], prefixes: &[&str]) -> KeyAllowList {
+        KeyAllowList {
+            key_ids: key_ids.iter().map(|id| id.to_string()).collect(),
+            prefixes: prefixes.iter().map(|prefix| prefix.to_string()).collect(),
+        }
+    }
+
+    fn assert_not_permitted(response: &Response) {
+        assert!(!response.success, "{:?}", response.result);
+        assert_eq!(response.error_code, Some(ErrorCode::KeyNotPermitted), "{:?}", response.error);
+    }
+
+    #[test]
+    fn test_key_allow_list_permits() {
+        assert!(KeyAllowList::default().permits("anything"));
+
+        let list = key_allow_list(&["tenant-a-key"], &["tenant-b-"]);
+        assert!(list.permits("tenant-a-key"));
+        assert!(list.permits("tenant-b-1234"));
+        assert!(!list.permits("tenant-a-key2"));
+        assert!(!list.permits("tenant-c-1234"));
+        assert!(!list.permits("tenant-b"));
+    }
+
+    #[test]
+    fn test_key_allow_list_allows_listed_key() {
+        let kek = [0x55u8; 32];
+        let mut server = mockito::Server::new();
+        let _key = mock_cached_key(&mut server, "tenant-a-key", 1);
+
+        let envelope = EnvelopeEncryptor::new(Algorithm::default())
+            .encrypt(b"tenant data", &kek, "tenant-a-key".to_string())
+            .unwrap();
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_key_allow_list(key_allow_list(&["tenant-a-key"], &[]));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let encrypted = envelope_of(runtime.block_on(handler.handle(encrypt_request("tenant-a-key"))));
+        assert_eq!(encrypted.key_id, "tenant-a-key");
+        let response = runtime.block_on(handler.handle(decrypt_request(envelope.clone())));
+        assert!(response.success, "{:?}", response.error);
+        let response = runtime.block_on(handler.handle(rewrap_request(envelope, Some("tenant-a-key"))));
+        assert!(response.success, "{:?}", response.error);
+    }
+
+    #[test]
+    fn test_key_allow_list_denies_other_keys() {
+        let mut server = mockito::Server::new();
+        let _allowed = mock_cached_key(&mut server, "tenant-a-key", 1);
+        let other = mock_cached_key(&mut server, "tenant-b-key", 0);
+        let create = mock_create_key(&mut server, 0);
+
+        let sink = Arc::new(MemoryAuditSink::default());
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_key_allow_list(key_allow_list(&["tenant-a-key"], &[]))
+            .with_audit_log(AuditLog::new(sink.clone()).strict(true));
+
+        let other_envelope = EnvelopeEncryptor::new(Algorithm::default())
+            .encrypt(b"other tenant", &[0x55u8; 32], "tenant-b-key".to_string())
+            .unwrap();
+        let own_envelope = EnvelopeEncryptor::new(Algorithm::default())
+            .encrypt(b"own tenant", &[0x55u8; 32], "tenant-a-key".to_string())
+            .unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for request in [
+            encrypt_request("tenant-b-key"),
+            decrypt_request(other_envelope.clone()),
+            rewrap_request(other_envelope, Some("tenant-a-key")),
+            rewrap_request(own_envelope, Some("tenant-b-key")),
+            // Only exact ids are listed, so no new key could be permitted
+            keyless_encrypt_request(),
+            create_key_request(false),
+        ] {
+            assert_not_permitted(&runtime.block_on(handler.handle(request)));
+        }
+
+        let results = batch_results(runtime.block_on(handler.handle(batch_request(vec![
+            batch_item(encrypt_request("tenant-a-key")),
+            batch_item(encrypt_request("tenant-b-key")),
+        ]))));
+        assert!(results[0].success, "{:?}", results[0].error);
+        assert_eq!(results[1].error_code, Some(ErrorCode::KeyNotPermitted));
+
+        // The refusals are audited like any other failure
+        let records = sink.0.lock().unwrap();
+        let refused: Vec<_> = records
+            .iter()
+            .filter(|record| record.error_code == Some(ErrorCode::KeyNotPermitted))
+            .collect();
+        assert_eq!(refused.len(), 7, "{:?}", *records);
+        assert!(refused.iter().all(|record| !record.success));
+        assert_eq!(refused[0].key_id.as_deref(), Some("tenant-b-key"));
+
+        other.assert();
+        create.assert();
+    }
+
+    #[test]
+    fn test_key_allow_list_prefix_match() {
+        let mut server = mockito::Server::new();
+        let _key = mock_cached_key(&mut server, "tenant-a-1", 1);
+        let other = mock_cached_key(&mut server, "tenant-b-1", 0);
+        let create = mock_create_key(&mut server, 2);
+
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_key_allow_list(key_allow_list(&[], &["tenant-a-", "prov"]));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let envelope = envelope_of(runtime.block_on(handler.handle(encrypt_request("tenant-a-1"))));
+        assert_eq!(envelope.key_id, "tenant-a-1");
+        assert_not_permitted(&runtime.block_on(handler.handle(encrypt_request("tenant-b-1"))));
+
+        // A created key is permitted by its prefix
+        let created = envelope_of(runtime.block_on(handler.handle(keyless_encrypt_request())));
+        assert_eq!(created.key_id, "provisioned");
+        let response = runtime.block_on(handler.handle(rewrap_request(envelope, None)));
+        assert!(response.success, "{:?}", response.error);
+
+        other.assert();
+        create.assert();
+    }
+
+    #[test]
+    fn test_key_allow_list_refuses_created_key_outside_it() {
+        let mut server = mockito::Server::new();
+        let create = mock_create_key(&mut server, 2);
+        // Each refused key is deleted again rather than left on the Keys server
+        let delete = server.mock("DELETE", "/v1/keys/provisioned").with_status(204).expect(2).create();
+
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_key_allow_list(key_allow_list(&[], &["tenant-a-"]));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert_not_permitted(&runtime.block_on(handler.handle(keyless_encrypt_request())));
+        assert_not_permitted(&runtime.block_on(handler.handle(create_key_request(false))));
+        assert!(handler.cached_kek("provisioned").is_none());
+
+        create.assert();
+        delete.assert();
+    }
+
+    fn keys_server_stats(handler: &RequestHandler, runtime: &tokio::runtime::Runtime) -> StatsSnapshot {
+        let request = Request {
+            id: None,
+            version: None,
+            operation: Operation::Stats,
+            include_timings: false,
+            data: RequestData::default(),
+        };
+        match runtime.block_on(handler.handle(request)).result {
+            Some(ResponseResult::Stats(stats)) => stats,
+            other => panic!("expected stats result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker_fails_fast_and_recovers() {
+        use crate::breaker::CircuitState;
+
+        let mut server = mockito::Server::new();
+        let unavailable = server.mock("GET", "/v1/keys/flaky").with_status(503).expect(2).create();
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_circuit_breaker(2, Duration::from_millis(200));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for _ in 0..2 {
+            let response = runtime.block_on(handler.handle(encrypt_request("flaky")));
+            assert_eq!(response.error_code, Some(ErrorCode::KeyUnavailable), "{:?}", response.error);
+        }
+
+        // Open: the Keys server is not called, for any key
+        for key_id in ["flaky", "other"] {
+            let response = runtime.block_on(handler.handle(encrypt_request(key_id)));
+            assert_eq!(response.error_code, Some(ErrorCode::KeysServerUnavailable), "{:?}", response.error);
+        }
+        let ping = runtime.block_on(handler.handle(Request {
+            operation: Operation::Ping,
+            ..hello_request(None)
+        }));
+        assert!(matches!(ping.result, Some(ResponseResult::Pong { keys_server_ok: false, .. })), "{:?}", ping);
+        let stats = keys_server_stats(&handler, &runtime);
+        assert_eq!((stats.keys_server_circuit, stats.keys_server_failures), (CircuitState::Open, 2));
+        unavailable.assert();
+        unavailable.remove();
+
+        // After the cooldown one probe goes through, and its success closes the circuit
+        let healthy = mock_cached_key(&mut server, "flaky", 1);
+        std::thread::sleep(Duration::from_millis(250));
+        let response = runtime.block_on(handler.handle(encrypt_request("flaky")));
+        assert!(response.success, "{:?}", response.error);
+        let stats = keys_server_stats(&handler, &runtime);
+        assert_eq!((stats.keys_server_circuit, stats.keys_server_failures), (CircuitState::Closed, 0));
+        healthy.assert();
+    }
+
+    #[test]
+    fn test_hung_keys_server_times_out_then_fails_fast() {
+        // Accepts connections and never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let held: Vec<_> = listener.incoming().collect();
+            drop(held);
+        });
+
+        let timeout = Duration::from_millis(200);
+        let handler = RequestHandler::new(&url)
+            .unwrap()
+            .with_keys_server_timeout(timeout)
+            .with_circuit_breaker(1, Duration::from_secs(60));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let started = Instant::now();
+        let response = runtime.block_on(handler.handle(encrypt_request("hung")));
+        assert_eq!(response.error_code, Some(ErrorCode::KeyUnavailable), "{:?}", response.error);
+        assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+
+        let started = Instant::now();
+        let response = runtime.block_on(handler.handle(encrypt_request("hung")));
+        assert_eq!(response.error_code, Some(ErrorCode::KeysServerUnavailable), "{:?}", response.error);
+        assert!(started.elapsed() < timeout, "took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_unknown_key_does_not_open_circuit() {
+        let mut server = mockito::Server::new();
+        let missing = server.mock("GET", "/v1/keys/missing").with_status(404).expect(3).create();
+        let handler = RequestHandler::new(&server.url())
+            .unwrap()
+            .with_circuit_breaker(2, Duration::from_secs(60));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for _ in 0..3 {
+            let response = runtime.block_on(handler.handle(encrypt_request("missing")));
+            assert_eq!(response.error_code, Some(ErrorCode::KeyUnavailable), "{:?}", response.error);
+        }
+        assert_eq!(keys_server_stats(&handler, &runtime).keys_server_failures, 0);
+        missing.assert();
+    }
 }
@@ -0,0 +1,162 @@
+//! Replay of encrypt results for retried requests
+//!
+//! An encrypt carrying an `idempotencyKey` is remembered for
+//! [`IDEMPOTENCY_TTL`] along with a digest of what it asked for. A retry with
+//! the same key and request gets the original envelope back instead of a new
+//! one, while a request reusing the key for anything else is refused with
+//! `idempotency_conflict`. Keys are scoped to the caller's UID, so one user
+//! cannot replay another's results, and retries arriving while the first
+//! request is still running wait for its result instead of encrypting again.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use violet_core::EncryptionEnvelope;
+use crate::protocol::{ErrorCode, RequestData, Response, ResponseResult};
+
+/// How long an encrypt result is replayed for a repeated idempotency key
+pub const IDEMPOTENCY_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Default largest number of idempotency keys remembered at once
+pub const DEFAULT_IDEMPOTENCY_CAPACITY: usize = 10_000;
+
+/// Caller UID and idempotency key. Clients without Unix socket credentials
+/// (over TCP or stdio) share the `None` scope.
+type Scope = (Option<u32>, String);
+
+/// Idempotency keys in use, shared by every connection
+///
+/// At most `capacity` keys are remembered; the oldest is forgotten to make room.
+pub(crate) struct IdempotencyCache {
+    capacity: usize,
+    entries: Mutex<HashMap<Scope, Arc<Entry>>>,
+}
+
+struct Entry {
+    created_at: Instant,
+    /// [`request_digest`] of the request that claimed the key
+    digest: [u8; 32],
+    /// Set once the encrypt succeeds. Retries wait on the cell while it runs,
+    /// and one of them takes over if it fails.
+    envelope: OnceCell<EncryptionEnvelope>,
+}
+
+impl IdempotencyCache {
+    /// Remember up to `capacity` keys; zero disables replay
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run `encrypt` for `uid`'s request with `digest`, unless the same request
+    /// under `idempotency_key` already succeeded or is running
+    pub(crate) async fn run<F, Fut>(&self, uid: Option<u32>, idempotency_key: &str, digest: [u8; 32], encrypt: F) -> Response
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Response>,
+    {
+        let scope = (uid, idempotency_key.to_string());
+        let entry = match self.claim(&scope, digest) {
+            Ok(entry) => entry,
+            Err(response) => return *response,
+        };
+
+        let mut fresh = None;
+        let fresh_response = &mut fresh;
+        let result = entry
+            .envelope
+            .get_or_try_init(|| async move {
+                let response = encrypt().await;
+                match &response.result {
+                    Some(ResponseResult::Encrypt { envelope }) => {
+                        let envelope = envelope.clone();
+                        *fresh_response = Some(response);
+                        Ok(envelope)
+                    }
+                    _ => Err(Box::new(response)),
+                }
+            })
+            .await;
+
+        match result {
+            Ok(envelope) => fresh.unwrap_or_else(|| {
+                tracing::debug!("Replaying encrypt result for idempotency key: {}", idempotency_key);
+                Response::success_encrypt(envelope.clone())
+            }),
+            Err(failed) => {
+                // A failed request does not hold on to its key, so a corrected retry is not a conflict
+                self.release(&scope, &entry);
+                *failed
+            }
+        }
+    }
+
+    /// Find the entry for `scope`, or add one for the request with `digest`
+    fn claim(&self, scope: &Scope, digest: [u8; 32]) -> Result<Arc<Entry>, Box<Response>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.created_at.elapsed() < IDEMPOTENCY_TTL);
+
+        if let Some(entry) = entries.get(scope) {
+            if entry.digest != digest {
+                return Err(Box::new(Response::failure(
+                    ErrorCode::IdempotencyConflict,
+                    format!("Idempotency key {} was already used for a different request", scope.1),
+                )));
+            }
+            return Ok(Arc::clone(entry));
+        }
+
+        let entry = Arc::new(Entry {
+            created_at: Instant::now(),
+            digest,
+            envelope: OnceCell::new(),
+        });
+        if self.capacity == 0 {
+            return Ok(entry);
+        }
+        while entries.len() >= self.capacity {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.created_at)
+                .map(|(scope, _)| scope.clone());
+            let Some(oldest) = oldest else { break };
+            entries.remove(&oldest);
+        }
+        entries.insert(scope.clone(), Arc::clone(&entry));
+        Ok(entry)
+    }
+
+    /// Forget `entry` if it is still the one for `scope` and has no result
+    fn release(&self, scope: &Scope, entry: &Arc<Entry>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries
+            .get(scope)
+            .is_some_and(|current| Arc::ptr_eq(current, entry) && current.envelope.get().is_none())
+        {
+            entries.remove(scope);
+        }
+    }
+}
+
+/// Digest of what an encrypt asks for: its plaintext, key_id and algorithm
+pub(crate) fn request_digest(data: &RequestData) -> [u8; 32] {
+    let algorithm = data.algorithm.unwrap_or_default();
+    let mut hasher = Sha256::new();
+    // Length-prefixed, so no two requests hash the same fields the same way
+    for field in [Some(data.plaintext.as_str()), data.key_id.as_deref(), Some(algorithm.as_str())] {
+        match field {
+            Some(value) => {
+                hasher.update([1]);
+                hasher.update((value.len() as u64).to_be_bytes());
+                hasher.update(value.as_bytes());
+            }
+            None => hasher.update([0]),
+        }
+    }
+    hasher.finalize().into()
+}
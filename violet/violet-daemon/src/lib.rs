@@ -1,8 +1,33 @@
+pub mod audit;
+mod beneath;
+pub mod breaker;
+pub mod codec;
 pub mod handler;
+pub mod idempotency;
+pub mod metrics;
 pub mod protocol;
 pub mod server;
+pub mod tls;
+#[cfg(feature = "otel")]
+pub mod telemetry;
 
 // Re-export commonly used types
-pub use handler::RequestHandler;
-pub use protocol::{Operation, Request, RequestData, Response, ResponseResult};
-pub use server::DaemonServer;
+pub use audit::{AuditLog, AuditRecord, AuditSink, FileAuditSink, PeerCredentials};
+pub use breaker::{CircuitState, DEFAULT_BREAKER_COOLDOWN, DEFAULT_BREAKER_THRESHOLD};
+pub use codec::{FrameCodec, FrameError};
+pub use handler::{
+    key_id_hash, KeyAllowList, RequestHandler, DEFAULT_BLOCKING_THREADS, DEFAULT_KEK_CACHE_CAPACITY, DEFAULT_KEK_CACHE_TTL,
+    DEFAULT_KEYS_SERVER_TIMEOUT, DEFAULT_MAX_BATCH_SIZE,
+};
+pub use idempotency::{DEFAULT_IDEMPOTENCY_CAPACITY, IDEMPOTENCY_TTL};
+pub use metrics::{LatencyBucket, LatencySummary, Metrics, StatsSnapshot};
+pub use protocol::{
+    BatchItem, BatchItemResult, DaemonMode, ErrorCode, HelloInfo, Operation, Request, RequestData, Response, ResponseResult,
+    Timings, PROTOCOL_VERSION,
+};
+pub use server::{
+    abstract_socket_name, default_socket_path, parse_group, parse_listen_addr, parse_user, BoundDaemon, ConnectionLimitPolicy,
+    DaemonServer, PeerAllowList, ShutdownHandle, Transport, DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_IN_FLIGHT,
+    DEFAULT_MAX_REQUEST_BYTES, DEFAULT_SHUTDOWN_GRACE, DEFAULT_SOCKET_MODE,
+};
+pub use tls::TLS_HANDSHAKE_TIMEOUT;
@@ -0,0 +1,606 @@
+//! Counters and latency histograms describing what the daemon is doing
+//!
+//! One [`Metrics`] registry is shared by the request handler and the server.
+//! Everything is a relaxed atomic, so recording never blocks a request. The
+//! registry is read through the `stats` operation ([`Metrics::snapshot`]) and,
+//! when `--metrics-addr` is given, a Prometheus text endpoint
+//! ([`Metrics::render_prometheus`], [`serve_prometheus`]) that also answers
+//! health checks.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio_util::task::TaskTracker;
+use crate::breaker::CircuitState;
+use crate::handler::RequestHandler;
+use crate::protocol::{DaemonMode, ErrorCode, Operation, Response, ResponseResult};
+
+/// Upper bounds of the request latency histogram buckets, in microseconds
+const LATENCY_BUCKETS_MICROS: [u64; 11] = [
+    1_000, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000, 2_500_000, 5_000_000,
+];
+
+/// Label used for failures that carry no error code
+const UNCODED_ERROR: &str = "other";
+
+/// Longest HTTP request head read by the metrics endpoint
+const MAX_HTTP_HEAD: usize = 8 * 1024;
+
+/// How long the metrics endpoint waits for a request head before closing the connection
+const SCRAPE_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most metrics connections answered at once; further ones wait to be accepted
+const MAX_CONCURRENT_SCRAPES: usize = 16;
+
+/// Daemon-wide counters, updated as connections and requests are handled
+pub struct Metrics {
+    started_at: Instant,
+    connections_active: AtomicU64,
+    connections_total: AtomicU64,
+    connections_refused: AtomicU64,
+    /// Indexed like [`Operation::all`]
+    requests: Vec<Histogram>,
+    /// Requests answered with an error, indexed like [`Operation::all`]
+    failed_requests: Vec<AtomicU64>,
+    /// Indexed like [`ErrorCode::all`], plus one slot for uncoded errors
+    errors: Vec<AtomicU64>,
+    bytes_encrypted: AtomicU64,
+    bytes_decrypted: AtomicU64,
+    kek_cache_hits: AtomicU64,
+    kek_cache_misses: AtomicU64,
+    kek_cache_evictions: AtomicU64,
+}
+
+/// Request count and latency distribution for one operation
+struct Histogram {
+    /// Non-cumulative; the last slot counts requests slower than every bound
+    buckets: [AtomicU64; LATENCY_BUCKETS_MICROS.len() + 1],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = LATENCY_BUCKETS_MICROS
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MICROS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+}
+
+/// Decrements the active connection count when dropped
+pub struct ConnectionGauge(Arc<Metrics>);
+
+impl Drop for ConnectionGauge {
+    fn drop(&mut self) {
+        self.0.connections_active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            connections_active: AtomicU64::new(0),
+            connections_total: AtomicU64::new(0),
+            connections_refused: AtomicU64::new(0),
+            requests: Operation::all().iter().map(|_| Histogram::new()).collect(),
+            failed_requests: Operation::all().iter().map(|_| AtomicU64::new(0)).collect(),
+            errors: (0..=ErrorCode::all().len()).map(|_| AtomicU64::new(0)).collect(),
+            bytes_encrypted: AtomicU64::new(0),
+            bytes_decrypted: AtomicU64::new(0),
+            kek_cache_hits: AtomicU64::new(0),
+            kek_cache_misses: AtomicU64::new(0),
+            kek_cache_evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Count an accepted connection as active until the returned gauge is dropped
+    pub fn connection_opened(self: &Arc<Self>) -> ConnectionGauge {
+        self.connections_active.fetch_add(1, Ordering::Relaxed);
+        self.connections_total.fetch_add(1, Ordering::Relaxed);
+        ConnectionGauge(Arc::clone(self))
+    }
+
+    /// Count a connection turned away at the connection limit, returning the new total
+    pub fn connection_refused(&self) -> u64 {
+        self.count_error(Some(ErrorCode::TooManyConnections));
+        self.connections_refused.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Record a handled top-level request and how long it took
+    ///
+    /// `plaintext_len` is the decoded length of an encrypt request's plaintext.
+    pub fn record_request(&self, operation: Operation, response: &Response, plaintext_len: u64, elapsed: Duration) {
+        self.requests[operation_index(operation)].observe(elapsed);
+        if !response.success {
+            self.failed_requests[operation_index(operation)].fetch_add(1, Ordering::Relaxed);
+        }
+        self.record_item(operation, response, plaintext_len);
+    }
+
+    /// Record the error or bytes of one item of a batch
+    pub fn record_item(&self, operation: Operation, response: &Response, plaintext_len: u64) {
+        if !response.success {
+            self.count_error(response.error_code);
+            return;
+        }
+        match (operation, &response.result) {
+            (Operation::Encrypt | Operation::EncryptStream, _) => {
+                self.bytes_encrypted.fetch_add(plaintext_len, Ordering::Relaxed);
+            }
+            (Operation::Decrypt, Some(ResponseResult::Decrypt { plaintext })) => {
+                self.bytes_decrypted.fetch_add(base64_decoded_len(plaintext), Ordering::Relaxed);
+            }
+            (Operation::DecryptStream, Some(ResponseResult::StreamFinished { plaintext_bytes, .. })) => {
+                self.bytes_decrypted.fetch_add(*plaintext_bytes, Ordering::Relaxed);
+            }
+            (Operation::EncryptFile, Some(ResponseResult::FileFinished { plaintext_bytes, .. })) => {
+                self.bytes_encrypted.fetch_add(*plaintext_bytes, Ordering::Relaxed);
+            }
+            (Operation::DecryptFile, Some(ResponseResult::FileFinished { plaintext_bytes, .. })) => {
+                self.bytes_decrypted.fetch_add(*plaintext_bytes, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    /// Record an error answered before a request reached the handler, e.g. an oversized line
+    pub fn record_rejected(&self, response: &Response) {
+        if !response.success {
+            self.count_error(response.error_code);
+        }
+    }
+
+    fn count_error(&self, code: Option<ErrorCode>) {
+        let slot = code.map_or(ErrorCode::all().len(), error_code_index);
+        self.errors[slot].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn kek_cache_hit(&self) {
+        self.kek_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn kek_cache_miss(&self) {
+        self.kek_cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count KEKs dropped from the cache other than by `flushKeys`
+    pub fn kek_cache_evicted(&self, count: u64) {
+        self.kek_cache_evictions.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Current values, as returned by the `stats` operation
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let requests = Operation::all()
+            .iter()
+            .zip(&self.requests)
+            .map(|(operation, histogram)| (operation.as_str().to_string(), histogram.count.load(Ordering::Relaxed)))
+            .collect();
+        let errors = self
+            .error_labels()
+            .zip(&self.errors)
+            .map(|(label, count)| (label.to_string(), count.load(Ordering::Relaxed)))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        let latency = Operation::all()
+            .iter()
+            .zip(&self.requests)
+            .filter(|(_, histogram)| histogram.count.load(Ordering::Relaxed) > 0)
+            .map(|(operation, histogram)| {
+                let count = histogram.count.load(Ordering::Relaxed);
+                let sum_micros = histogram.sum_micros.load(Ordering::Relaxed);
+                let mut cumulative = 0;
+                let buckets = LATENCY_BUCKETS_MICROS
+                    .iter()
+                    .zip(&histogram.buckets)
+                    .map(|(bound, bucket)| {
+                        cumulative += bucket.load(Ordering::Relaxed);
+                        LatencyBucket {
+                            le_ms: *bound as f64 / 1000.0,
+                            count: cumulative,
+                        }
+                    })
+                    .collect();
+                let summary = LatencySummary {
+                    count,
+                    mean_ms: sum_micros as f64 / count as f64 / 1000.0,
+                    buckets,
+                };
+                (operation.as_str().to_string(), summary)
+            })
+            .collect();
+
+        StatsSnapshot {
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            active_connections: self.connections_active.load(Ordering::Relaxed),
+            total_connections: self.connections_total.load(Ordering::Relaxed),
+            refused_connections: self.connections_refused.load(Ordering::Relaxed),
+            requests,
+            errors,
+            bytes_encrypted: self.bytes_encrypted.load(Ordering::Relaxed),
+            bytes_decrypted: self.bytes_decrypted.load(Ordering::Relaxed),
+            kek_cache_hits: self.kek_cache_hits.load(Ordering::Relaxed),
+            kek_cache_misses: self.kek_cache_misses.load(Ordering::Relaxed),
+            kek_cache_evictions: self.kek_cache_evictions.load(Ordering::Relaxed),
+            latency,
+            mode: DaemonMode::Full,
+            keys_server_circuit: CircuitState::Closed,
+            keys_server_failures: 0,
+        }
+    }
+
+    /// Current values in the Prometheus text exposition format (version 0.0.4)
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        metric_header(&mut out, "violet_uptime_seconds", "gauge", "Seconds since the daemon started");
+        writeln!(out, "violet_uptime_seconds {}", self.started_at.elapsed().as_secs()).ok();
+
+        metric_header(&mut out, "violet_connections_active", "gauge", "Connections currently open");
+        writeln!(out, "violet_connections_active {}", load(&self.connections_active)).ok();
+        metric_header(&mut out, "violet_connections_total", "counter", "Connections accepted");
+        writeln!(out, "violet_connections_total {}", load(&self.connections_total)).ok();
+        metric_header(
+            &mut out,
+            "violet_connections_refused_total",
+            "counter",
+            "Connections refused at the connection limit",
+        );
+        writeln!(out, "violet_connections_refused_total {}", load(&self.connections_refused)).ok();
+
+        metric_header(
+            &mut out,
+            "violet_requests_total",
+            "counter",
+            "Requests handled, by operation and outcome",
+        );
+        for ((operation, histogram), failed) in Operation::all().iter().zip(&self.requests).zip(&self.failed_requests) {
+            let failed = load(failed);
+            for (outcome, count) in [("success", load(&histogram.count).saturating_sub(failed)), ("error", failed)] {
+                writeln!(
+                    out,
+                    "violet_requests_total{{operation=\"{}\",outcome=\"{}\"}} {}",
+                    operation.as_str(),
+                    outcome,
+                    count
+                )
+                .ok();
+            }
+        }
+
+        metric_header(
+            &mut out,
+            "violet_errors_total",
+            "counter",
+            "Failed requests and batch items, by error code",
+        );
+        for (label, count) in self.error_labels().zip(&self.errors) {
+            writeln!(out, "violet_errors_total{{code=\"{}\"}} {}", label, load(count)).ok();
+        }
+
+        metric_header(&mut out, "violet_encrypted_bytes_total", "counter", "Plaintext bytes encrypted");
+        writeln!(out, "violet_encrypted_bytes_total {}", load(&self.bytes_encrypted)).ok();
+        metric_header(&mut out, "violet_decrypted_bytes_total", "counter", "Plaintext bytes decrypted");
+        writeln!(out, "violet_decrypted_bytes_total {}", load(&self.bytes_decrypted)).ok();
+
+        metric_header(&mut out, "violet_kek_cache_hits_total", "counter", "KEK lookups served from memory");
+        writeln!(out, "violet_kek_cache_hits_total {}", load(&self.kek_cache_hits)).ok();
+        metric_header(&mut out, "violet_kek_cache_misses_total", "counter", "KEK lookups sent to the Keys server");
+        writeln!(out, "violet_kek_cache_misses_total {}", load(&self.kek_cache_misses)).ok();
+        metric_header(&mut out, "violet_kek_cache_evictions_total", "counter", "KEKs dropped from memory when expired, over capacity or gone from the Keys server");
+        writeln!(out, "violet_kek_cache_evictions_total {}", load(&self.kek_cache_evictions)).ok();
+
+        metric_header(
+            &mut out,
+            "violet_request_duration_seconds",
+            "histogram",
+            "Time to handle a request, by operation",
+        );
+        for (operation, histogram) in Operation::all().iter().zip(&self.requests) {
+            let operation = operation.as_str();
+            let mut cumulative = 0;
+            for (bound, bucket) in LATENCY_BUCKETS_MICROS.iter().zip(&histogram.buckets) {
+                cumulative += load(bucket);
+                writeln!(
+                    out,
+                    "violet_request_duration_seconds_bucket{{operation=\"{}\",le=\"{}\"}} {}",
+                    operation,
+                    *bound as f64 / 1e6,
+                    cumulative
+                )
+                .ok();
+            }
+            let count = load(&histogram.count);
+            writeln!(
+                out,
+                "violet_request_duration_seconds_bucket{{operation=\"{}\",le=\"+Inf\"}} {}",
+                operation, count
+            )
+            .ok();
+            writeln!(
+                out,
+                "violet_request_duration_seconds_sum{{operation=\"{}\"}} {}",
+                operation,
+                load(&histogram.sum_micros) as f64 / 1e6
+            )
+            .ok();
+            writeln!(out, "violet_request_duration_seconds_count{{operation=\"{}\"}} {}", operation, count).ok();
+        }
+        out
+    }
+
+    fn error_labels(&self) -> impl Iterator<Item = &'static str> {
+        ErrorCode::all()
+            .iter()
+            .map(|code| code.as_str())
+            .chain(std::iter::once(UNCODED_ERROR))
+    }
+}
+
+/// Point-in-time copy of the daemon's metrics, returned by the `stats` operation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsSnapshot {
+    pub uptime_secs: u64,
+    pub active_connections: u64,
+    pub total_connections: u64,
+    pub refused_connections: u64,
+
+    /// Top-level requests handled, by operation
+    pub requests: BTreeMap<String, u64>,
+
+    /// Failed requests and batch items by error code, `other` for uncoded
+    /// failures; codes that never occurred are omitted
+    pub errors: BTreeMap<String, u64>,
+
+    /// Plaintext bytes encrypted and decrypted, batch items included
+    pub bytes_encrypted: u64,
+    pub bytes_decrypted: u64,
+
+    pub kek_cache_hits: u64,
+    pub kek_cache_misses: u64,
+
+    /// KEKs dropped when expired, over capacity or gone from the Keys server
+    pub kek_cache_evictions: u64,
+
+    /// Latency of operations that have been requested at least once
+    pub latency: BTreeMap<String, LatencySummary>,
+
+    /// Mode the daemon runs in; the registry itself always reports `full`
+    #[serde(default)]
+    pub mode: DaemonMode,
+
+    /// Circuit breaker in front of the Keys server; the registry itself
+    /// always reports `closed`
+    #[serde(default)]
+    pub keys_server_circuit: CircuitState,
+
+    /// Keys server calls that have failed in a row
+    #[serde(default)]
+    pub keys_server_failures: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean_ms: f64,
+
+    /// Cumulative histogram: requests that took at most `leMs` each; `count`
+    /// covers the ones slower than every bound
+    #[serde(default)]
+    pub buckets: Vec<LatencyBucket>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyBucket {
+    pub le_ms: f64,
+    pub count: u64,
+}
+
+/// Serve `handler`'s metrics as Prometheus text on `GET /metrics`, and its
+/// view of the Keys server on `GET /healthz`, to every connection on `listener`
+///
+/// A deliberately small, read-only HTTP/1.1 responder: each connection gets
+/// one response and is closed. `/healthz` answers 200 while the Keys server
+/// passes the handler's health probe (the result `ping` reports, cached for
+/// the health check interval) and 503 otherwise. Methods other than GET are
+/// answered with 405 and other paths with 404.
+///
+/// A connection that has not sent its request head within a few seconds is
+/// closed, and only a few connections are answered at once, so idle clients
+/// cannot tie the endpoint up. Connections are answered on `tasks`, so
+/// shutdown waits for them like for any other.
+pub async fn serve_prometheus(
+    listener: TcpListener,
+    handler: Arc<RequestHandler>,
+    tasks: TaskTracker,
+) -> std::io::Result<()> {
+    serve_scrapes(listener, handler, tasks, MAX_CONCURRENT_SCRAPES, SCRAPE_READ_TIMEOUT).await
+}
+
+async fn serve_scrapes(
+    listener: TcpListener,
+    handler: Arc<RequestHandler>,
+    tasks: TaskTracker,
+    max_concurrent: usize,
+    read_timeout: Duration,
+) -> std::io::Result<()> {
+    let slots = Arc::new(Semaphore::new(max_concurrent));
+    loop {
+        let slot = Arc::clone(&slots).acquire_owned().await.expect("the scrape semaphore is never closed");
+        let (stream, peer) = listener.accept().await?;
+        let handler = Arc::clone(&handler);
+        tasks.spawn(async move {
+            let _slot = slot;
+            if let Err(e) = answer_scrape(stream, &handler, read_timeout).await {
+                tracing::debug!("Metrics request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn answer_scrape(mut stream: TcpStream, handler: &RequestHandler, read_timeout: Duration) -> std::io::Result<()> {
+    let mut head = Vec::new();
+    let read_head = async {
+        let mut buf = [0u8; 1024];
+        while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 || head.len() + n > MAX_HTTP_HEAD {
+                return Ok(false);
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, std::io::Error>(true)
+    };
+    match tokio::time::timeout(read_timeout, read_head).await {
+        Ok(Ok(true)) => {}
+        Ok(Ok(false)) => return Ok(()),
+        Ok(Err(e)) => return Err(e),
+        Err(_) => {
+            tracing::debug!("Closing metrics connection that sent no request within {:?}", read_timeout);
+            return Ok(());
+        }
+    }
+
+    let request_line = head.split(|b| *b == b'\r').next().unwrap_or_default();
+    let mut parts = std::str::from_utf8(request_line).unwrap_or_default().split(' ');
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let path = target.split('?').next().unwrap_or_default();
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", handler.metrics().render_prometheus()),
+        ("GET", "/healthz") if handler.keys_server_ok().await => ("200 OK", "text/plain", "ok\n".to_string()),
+        ("GET", "/healthz") => ("503 Service Unavailable", "text/plain", "Keys server unavailable\n".to_string()),
+        ("GET", _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Method not allowed\n".to_string()),
+    };
+    // Nothing here changes state, so GET is all there is
+    let allow = if method == "GET" { "" } else { "allow: GET\r\n" };
+    let response = format!(
+        "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\n{}connection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        allow,
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn metric_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).ok();
+    writeln!(out, "# TYPE {} {}", name, kind).ok();
+}
+
+fn operation_index(operation: Operation) -> usize {
+    Operation::all()
+        .iter()
+        .position(|candidate| *candidate == operation)
+        .expect("Operation::all lists every operation")
+}
+
+fn error_code_index(code: ErrorCode) -> usize {
+    ErrorCode::all()
+        .iter()
+        .position(|candidate| *candidate == code)
+        .expect("ErrorCode::all lists every error code")
+}
+
+/// Length of the data encoded by a padded base64 string, without decoding it
+pub(crate) fn base64_decoded_len(encoded: &str) -> u64 {
+    let padding = encoded.bytes().rev().take_while(|byte| *byte == b'=').count();
+    (encoded.len() / 4 * 3).saturating_sub(padding) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_scrapes_time_out_and_are_bounded() {
+        let handler = Arc::new(RequestHandler::new("http://127.0.0.1:9").unwrap());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let tasks = TaskTracker::new();
+            let read_timeout = Duration::from_millis(300);
+            tokio::spawn(serve_scrapes(listener, handler, tasks.clone(), 1, read_timeout));
+
+            // A client that never sends a request holds the only slot until it times out
+            let started = Instant::now();
+            let mut idle = TcpStream::connect(addr).await.unwrap();
+            let mut scrape = TcpStream::connect(addr).await.unwrap();
+            scrape.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").await.unwrap();
+
+            let mut response = String::new();
+            scrape.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            assert!(started.elapsed() >= read_timeout, "{:?}", started.elapsed());
+
+            // The idle connection was closed without an answer
+            assert_eq!(idle.read(&mut [0u8; 16]).await.unwrap(), 0);
+            tasks.close();
+            tasks.wait().await;
+        });
+    }
+
+    #[test]
+    fn test_base64_decoded_len() {
+        assert_eq!(base64_decoded_len(""), 0);
+        assert_eq!(base64_decoded_len("aGk="), 2);
+        assert_eq!(base64_decoded_len("SGVsbG8="), 5);
+        assert_eq!(base64_decoded_len("AAAA"), 3);
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let metrics = Metrics::new();
+        let ok = Response::success_decrypt("aGk=".into());
+        metrics.record_request(Operation::Decrypt, &ok, 0, Duration::from_micros(500));
+        metrics.record_request(Operation::Decrypt, &ok, 0, Duration::from_millis(20));
+        metrics.record_request(Operation::Decrypt, &ok, 0, Duration::from_secs(10));
+
+        let text = metrics.render_prometheus();
+        assert!(text.contains("violet_request_duration_seconds_bucket{operation=\"decrypt\",le=\"0.001\"} 1\n"), "{}", text);
+        assert!(text.contains("violet_request_duration_seconds_bucket{operation=\"decrypt\",le=\"0.025\"} 2\n"), "{}", text);
+        assert!(text.contains("violet_request_duration_seconds_bucket{operation=\"decrypt\",le=\"+Inf\"} 3\n"), "{}", text);
+        assert!(text.contains("violet_request_duration_seconds_count{operation=\"decrypt\"} 3\n"), "{}", text);
+        assert!(text.contains("violet_decrypted_bytes_total 6\n"), "{}", text);
+
+        let latency = &metrics.snapshot().latency["decrypt"];
+        assert_eq!(latency.count, 3);
+        assert_eq!(latency.buckets.len(), LATENCY_BUCKETS_MICROS.len());
+        assert_eq!(latency.buckets[0], LatencyBucket { le_ms: 1.0, count: 1 });
+        assert_eq!(latency.buckets[3], LatencyBucket { le_ms: 25.0, count: 2 });
+        assert_eq!(latency.buckets.last().unwrap().count, 2);
+    }
+}
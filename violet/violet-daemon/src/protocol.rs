@@ -1,21 +1,201 @@
 use serde::{Deserialize, Serialize};
-use violet_core::{Algorithm, EncryptionEnvelope};
+use std::path::PathBuf;
+use violet_core::{Algorithm, EncryptionEnvelope, StreamHeader};
+use crate::metrics::StatsSnapshot;
+use crate::codec::GZIP;
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
+/// Major protocol version spoken by this daemon
+///
+/// Requests without a `version` field are treated as this version's first release, 1.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum Operation {
     Encrypt,
     Decrypt,
+    /// Report the protocol version, operations and algorithms this daemon supports
+    Hello,
+    /// Create a KEK on the Keys server and return its key_id
+    CreateKey,
+    /// Move an envelope to a new KEK without decrypting its data
+    Rewrap,
+    /// Liveness probe that also reports whether the Keys server is reachable
+    Ping,
+    /// Run many encrypt and decrypt items in one request
+    Batch,
+    /// Report request, error, byte and connection counters
+    Stats,
+    /// Encrypt raw plaintext frames that follow the request into a chunked stream
+    EncryptStream,
+    /// Decrypt a chunked stream sent as raw frames that follow the request
+    DecryptStream,
+    /// Drop cached KEKs, all of them or just `key_id`, so they are re-fetched
+    FlushKeys,
+    /// Encrypt a file the daemon can read into a chunked stream file, with its header in a sidecar
+    EncryptFile,
+    /// Decrypt a chunked stream file into a plaintext file
+    DecryptFile,
+}
+
+impl Operation {
+    /// Every operation this daemon handles, as reported by `hello`
+    pub fn all() -> &'static [Operation] {
+        &[
+            Operation::Encrypt,
+            Operation::Decrypt,
+            Operation::Hello,
+            Operation::CreateKey,
+            Operation::Rewrap,
+            Operation::Ping,
+            Operation::Batch,
+            Operation::Stats,
+            Operation::EncryptStream,
+            Operation::DecryptStream,
+            Operation::FlushKeys,
+            Operation::EncryptFile,
+            Operation::DecryptFile,
+        ]
+    }
+
+    /// Whether the operation carries its payload in raw frames after the
+    /// request, which only the length-prefixed framing can carry
+    pub fn is_streaming(&self) -> bool {
+        matches!(self, Operation::EncryptStream | Operation::DecryptStream)
+    }
+
+    /// Name of the operation on the wire
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Encrypt => "encrypt",
+            Operation::Decrypt => "decrypt",
+            Operation::Hello => "hello",
+            Operation::CreateKey => "createKey",
+            Operation::Rewrap => "rewrap",
+            Operation::Ping => "ping",
+            Operation::Batch => "batch",
+            Operation::Stats => "stats",
+            Operation::EncryptStream => "encryptStream",
+            Operation::DecryptStream => "decryptStream",
+            Operation::FlushKeys => "flushKeys",
+            Operation::EncryptFile => "encryptFile",
+            Operation::DecryptFile => "decryptFile",
+        }
+    }
+}
+
+/// Which kinds of work a daemon accepts, set with `--mode`
+///
+/// Operations outside the mode are refused with `operation_not_allowed`
+/// before any work is done, including encrypts without a `key_id` in a mode
+/// that may not create keys.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DaemonMode {
+    /// Every operation
+    #[default]
+    Full,
+    /// Decrypt existing envelopes and streams; no encryption, rewrapping or key creation
+    DecryptOnly,
+    /// Encrypt and create keys; no decryption or rewrapping
+    EncryptOnly,
+}
+
+impl DaemonMode {
+    pub fn all() -> &'static [DaemonMode] {
+        &[DaemonMode::Full, DaemonMode::DecryptOnly, DaemonMode::EncryptOnly]
+    }
+
+    /// Name of the mode on the wire and on the command line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DaemonMode::Full => "full",
+            DaemonMode::DecryptOnly => "decrypt-only",
+            DaemonMode::EncryptOnly => "encrypt-only",
+        }
+    }
+
+    /// Why `operation` with `data` is refused in this mode, if it is
+    ///
+    /// Rewrapping unwraps a DEK, so it counts as decryption as well as
+    /// encryption. Operations that touch no data, such as `hello` or
+    /// `flushKeys`, are always allowed; batch items are checked one by one.
+    pub fn refusal(&self, operation: Operation, data: &RequestData) -> Option<String> {
+        let encrypts = matches!(
+            operation,
+            Operation::Encrypt | Operation::EncryptStream | Operation::EncryptFile | Operation::Rewrap
+        );
+        let decrypts = matches!(
+            operation,
+            Operation::Decrypt | Operation::DecryptStream | Operation::DecryptFile | Operation::Rewrap
+        );
+        let creates_key = match operation {
+            Operation::CreateKey => true,
+            Operation::Encrypt | Operation::EncryptStream | Operation::EncryptFile => data.key_id.is_none(),
+            Operation::Rewrap => data.new_key_id.is_none(),
+            _ => false,
+        };
+
+        let refused = match self {
+            DaemonMode::Full => false,
+            DaemonMode::DecryptOnly => encrypts || creates_key,
+            DaemonMode::EncryptOnly => decrypts,
+        };
+        refused.then(|| {
+            let what = if creates_key && !encrypts && !decrypts {
+                "creating keys"
+            } else if creates_key && operation != Operation::Rewrap {
+                "encrypting without a key_id, which creates a key,"
+            } else {
+                operation.as_str()
+            };
+            format!("{} is not allowed on a {} daemon", what, self.as_str())
+        })
+    }
+}
+
+impl std::fmt::Display for DaemonMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for DaemonMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DaemonMode::all()
+            .iter()
+            .copied()
+            .find(|mode| mode.as_str() == s)
+            .ok_or_else(|| format!("Unknown daemon mode {}; expected full, decrypt-only or encrypt-only", s))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Request {
+    /// Caller-chosen ID echoed in the response, logged with the request and
+    /// sent to the Keys server as `X-Request-Id`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub id: Option<String>,
+
+    /// Protocol version the client speaks; absent means version 1
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub version: Option<u32>,
+
     pub operation: Operation,
+
+    /// Echo how long the daemon spent on the request in the response's `timings`
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub include_timings: bool,
+
+    /// May be omitted for operations that take no data, such as `hello`
+    #[serde(default)]
     pub data: RequestData,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RequestData {
     // Encrypt fields
@@ -28,13 +208,65 @@ pub struct RequestData {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub algorithm: Option<Algorithm>,
 
-    // Decrypt fields
+    /// Retries carrying the same key receive the original envelope instead of a new KEK
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub idempotency_key: Option<String>,
+
+    // CreateKey fields
+    /// Return the hex key material as well as the key_id. Refused unless the
+    /// daemon was started with key export enabled.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub include_key_material: bool,
+
+    // Decrypt and Rewrap fields
     #[serde(skip_serializing_if = "Option::is_none")]
     pub envelope: Option<EncryptionEnvelope>,
+
+    // Rewrap fields
+    /// KEK to move the envelope to; a new key is created when absent
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub new_key_id: Option<String>,
+
+    // Batch fields
+    /// Encrypt and decrypt items, answered in the same order
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub items: Vec<BatchItem>,
+
+    // EncryptFile and DecryptFile fields
+    /// Absolute path of the file to read, inside a directory the daemon allows
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub input_path: Option<PathBuf>,
+
+    /// Absolute path of the file to create, inside a directory the daemon
+    /// allows; an existing file is never overwritten
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub output_path: Option<PathBuf>,
+}
+
+/// One encrypt or decrypt inside a `batch` request
+///
+/// `data` takes the same fields as a top-level encrypt or decrypt request.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItem {
+    pub operation: Operation,
+
+    #[serde(default)]
+    pub data: RequestData,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Response {
+    /// The request's `id`, omitted when the request had none
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub id: Option<String>,
+
+    /// Protocol version the response is written in: the request's version when
+    /// it is supported, otherwise the daemon's own [`PROTOCOL_VERSION`]
+    #[serde(default = "default_version")]
+    pub version: u32,
+
     pub success: bool,
 
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -42,37 +274,569 @@ pub struct Response {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+
+    /// Machine-readable reason for failures clients are expected to handle
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error_code: Option<ErrorCode>,
+
+    /// Where the time went, when the request set `includeTimings`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub timings: Option<Timings>,
+}
+
+/// Time the daemon spent on one request, in milliseconds
+///
+/// KEK fetches and crypto are summed over every key and item the request
+/// needed, so for a batch they can exceed `totalMs`, items running concurrently.
+/// Streams interleave crypto with reading their input, so their `cryptoMs` is 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Timings {
+    pub total_ms: f64,
+
+    /// Time spent getting KEKs, from the cache or the Keys server
+    pub kek_fetch_ms: f64,
+
+    pub kek_cache_hits: u32,
+    pub kek_cache_misses: u32,
+
+    /// Time spent encrypting and decrypting, including any wait for the blocking thread pool
+    pub crypto_ms: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request's `version` is not one this daemon speaks
+    UnsupportedVersion,
+    /// A required field is missing or malformed
+    InvalidRequest,
+    /// The KEK could not be fetched, created or decoded
+    KeyUnavailable,
+    /// Encryption or decryption failed, e.g. for a tampered envelope
+    CryptoFailed,
+    /// A batch item asked for something other than encrypt or decrypt
+    UnsupportedOperation,
+    /// A batch has more items than the daemon accepts
+    BatchTooLarge,
+    /// A request line or frame is longer than the daemon accepts
+    PayloadTooLarge,
+    /// The daemon is already serving as many connections as it allows
+    TooManyConnections,
+    /// The operation ran but its audit record could not be written (strict audit mode)
+    AuditFailed,
+    /// The client's Unix socket credentials are not on the daemon's allow-list
+    Unauthorized,
+    /// The daemon's mode does not allow the operation, e.g. encrypt on a decrypt-only daemon
+    OperationNotAllowed,
+    /// A key_id the request names or a new key's id is outside the daemon's key allow-list
+    KeyNotPermitted,
+    /// The Keys server failed repeatedly, so the daemon is not calling it until a cooldown expires
+    KeysServerUnavailable,
+    /// A file path the request names is outside the daemon's allowed directories
+    PathNotPermitted,
+    /// The request's idempotency key was already used for a different request
+    IdempotencyConflict,
+}
+
+impl ErrorCode {
+    /// Every error code, as counted by the daemon's metrics
+    pub fn all() -> &'static [ErrorCode] {
+        &[
+            ErrorCode::UnsupportedVersion,
+            ErrorCode::InvalidRequest,
+            ErrorCode::KeyUnavailable,
+            ErrorCode::CryptoFailed,
+            ErrorCode::UnsupportedOperation,
+            ErrorCode::BatchTooLarge,
+            ErrorCode::PayloadTooLarge,
+            ErrorCode::TooManyConnections,
+            ErrorCode::AuditFailed,
+            ErrorCode::Unauthorized,
+            ErrorCode::OperationNotAllowed,
+            ErrorCode::KeyNotPermitted,
+            ErrorCode::KeysServerUnavailable,
+            ErrorCode::PathNotPermitted,
+            ErrorCode::IdempotencyConflict,
+        ]
+    }
+
+    /// Name of the code on the wire
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::UnsupportedVersion => "unsupported_version",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::KeyUnavailable => "key_unavailable",
+            ErrorCode::CryptoFailed => "crypto_failed",
+            ErrorCode::UnsupportedOperation => "unsupported_operation",
+            ErrorCode::BatchTooLarge => "batch_too_large",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::TooManyConnections => "too_many_connections",
+            ErrorCode::AuditFailed => "audit_failed",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::OperationNotAllowed => "operation_not_allowed",
+            ErrorCode::KeyNotPermitted => "key_not_permitted",
+            ErrorCode::KeysServerUnavailable => "keys_server_unavailable",
+            ErrorCode::PathNotPermitted => "path_not_permitted",
+            ErrorCode::IdempotencyConflict => "idempotency_conflict",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ResponseResult {
+    // Listed before Encrypt, which would otherwise also match its `envelope` field
+    #[serde(rename_all = "camelCase")]
+    Rewrapped {
+        envelope: EncryptionEnvelope,
+        previous_key_id: String,
+    },
     Encrypt { envelope: EncryptionEnvelope },
     Decrypt { plaintext: String },
+    Hello(HelloInfo),
+    #[serde(rename_all = "camelCase")]
+    Pong {
+        uptime_secs: u64,
+
+        /// Result of the most recent (cached) Keys server health probe
+        keys_server_ok: bool,
+
+        /// Version of the violet-daemon crate
+        version: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    KeyCreated {
+        key_id: String,
+
+        /// Hex key material, only present when requested and permitted
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        key: Option<String>,
+    },
+    Batch { results: Vec<BatchItemResult> },
+    /// Outcome of an `encryptFile` or `decryptFile`, listed before
+    /// StreamFinished, which would otherwise also match it
+    #[serde(rename_all = "camelCase")]
+    FileFinished {
+        /// Header of the stream file, naming its key and algorithm
+        stream_header: StreamHeader,
+
+        /// Canonical path of the file written
+        output_path: PathBuf,
+
+        /// Sidecar holding the stream header, written next to an encrypted file
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        envelope_path: Option<PathBuf>,
+
+        chunks: u64,
+        plaintext_bytes: u64,
+        ciphertext_bytes: u64,
+    },
+    /// End of an `encryptStream` or `decryptStream`, after the last output frame
+    #[serde(rename_all = "camelCase")]
+    StreamFinished {
+        /// Header of the stream, naming its key and algorithm
+        stream_header: StreamHeader,
+        chunks: u64,
+        plaintext_bytes: u64,
+    },
+    /// Number of KEKs a `flushKeys` removed from the cache
+    KeysFlushed { flushed: usize },
+    Stats(StatsSnapshot),
 }
 
-impl Response {
-    pub fn success_encrypt(envelope: EncryptionEnvelope) -> Self {
+impl ResponseResult {
+    /// Bytes of ciphertext or plaintext carried, which dominate the serialized size
+    pub fn payload_len(&self) -> usize {
+        match self {
+            ResponseResult::Encrypt { envelope } | ResponseResult::Rewrapped { envelope, .. } => {
+                envelope.encrypted_data.len()
+            }
+            ResponseResult::Decrypt { plaintext } => plaintext.len(),
+            ResponseResult::Batch { results } => {
+                results.iter().filter_map(|item| item.result.as_ref()).map(ResponseResult::payload_len).sum()
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// Outcome of one batch item, in the shape of a top-level response
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItemResult {
+    pub success: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<ResponseResult>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error_code: Option<ErrorCode>,
+}
+
+impl From<Response> for BatchItemResult {
+    fn from(response: Response) -> Self {
         Self {
-            success: true,
-            result: Some(ResponseResult::Encrypt { envelope }),
-            error: None,
+            success: response.success,
+            result: response.result,
+            error: response.error,
+            error_code: response.error_code,
         }
     }
+}
+
+/// Capabilities reported by the `hello` operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HelloInfo {
+    pub protocol_version: u32,
+
+    /// Version of the violet-daemon crate serving the request
+    pub crate_version: String,
+
+    pub operations: Vec<Operation>,
+    pub algorithms: Vec<Algorithm>,
+
+    /// Which operations this daemon accepts; absent from daemons older than modes
+    #[serde(default)]
+    pub mode: DaemonMode,
+
+    /// Compressions accepted on framed connections; empty for daemons older than compression
+    #[serde(default)]
+    pub compression: Vec<String>,
+}
+
+impl HelloInfo {
+    /// Capabilities of this build of the daemon, running in full mode
+    pub fn current() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            operations: Operation::all().to_vec(),
+            algorithms: Algorithm::all().to_vec(),
+            mode: DaemonMode::Full,
+            compression: vec![GZIP.to_string()],
+        }
+    }
+}
+
+impl Response {
+    /// Bytes of ciphertext or plaintext carried, see [`ResponseResult::payload_len`]
+    pub fn payload_len(&self) -> usize {
+        self.result.as_ref().map_or(0, ResponseResult::payload_len)
+    }
+
+    pub fn success_encrypt(envelope: EncryptionEnvelope) -> Self {
+        Self::success(ResponseResult::Encrypt { envelope })
+    }
 
     pub fn success_decrypt(plaintext: String) -> Self {
+        Self::success(ResponseResult::Decrypt { plaintext })
+    }
+
+    pub fn success_hello(info: HelloInfo) -> Self {
+        Self::success(ResponseResult::Hello(info))
+    }
+
+    pub fn success_rewrap(envelope: EncryptionEnvelope, previous_key_id: String) -> Self {
+        Self::success(ResponseResult::Rewrapped {
+            envelope,
+            previous_key_id,
+        })
+    }
+
+    pub fn success_pong(uptime_secs: u64, keys_server_ok: bool) -> Self {
+        Self::success(ResponseResult::Pong {
+            uptime_secs,
+            keys_server_ok,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        })
+    }
+
+    pub fn success_key_created(key_id: String, key: Option<String>) -> Self {
+        Self::success(ResponseResult::KeyCreated { key_id, key })
+    }
+
+    pub fn success_batch(results: Vec<BatchItemResult>) -> Self {
+        Self::success(ResponseResult::Batch { results })
+    }
+
+    pub fn success_stream(stream_header: StreamHeader, chunks: u64, plaintext_bytes: u64) -> Self {
+        Self::success(ResponseResult::StreamFinished {
+            stream_header,
+            chunks,
+            plaintext_bytes,
+        })
+    }
+
+    pub fn success_file(
+        stream_header: StreamHeader,
+        output_path: PathBuf,
+        envelope_path: Option<PathBuf>,
+        chunks: u64,
+        plaintext_bytes: u64,
+        ciphertext_bytes: u64,
+    ) -> Self {
+        Self::success(ResponseResult::FileFinished {
+            stream_header,
+            output_path,
+            envelope_path,
+            chunks,
+            plaintext_bytes,
+            ciphertext_bytes,
+        })
+    }
+
+    pub fn success_stats(stats: StatsSnapshot) -> Self {
+        Self::success(ResponseResult::Stats(stats))
+    }
+
+    pub fn success_keys_flushed(flushed: usize) -> Self {
+        Self::success(ResponseResult::KeysFlushed { flushed })
+    }
+
+    fn success(result: ResponseResult) -> Self {
         Self {
+            id: None,
+            version: PROTOCOL_VERSION,
             success: true,
-            result: Some(ResponseResult::Decrypt { plaintext }),
+            result: Some(result),
             error: None,
+            error_code: None,
+            timings: None,
         }
     }
 
     pub fn error(message: String) -> Self {
         Self {
+            id: None,
+            version: PROTOCOL_VERSION,
             success: false,
             result: None,
             error: Some(message),
+            error_code: None,
+            timings: None,
+        }
+    }
+
+    /// An error with a machine-readable code
+    pub fn failure(code: ErrorCode, message: String) -> Self {
+        Self {
+            error_code: Some(code),
+            ..Self::error(message)
+        }
+    }
+
+    /// Refuse a request written for a protocol version this daemon does not speak
+    pub fn unsupported_version(requested: u32) -> Self {
+        Self::failure(
+            ErrorCode::UnsupportedVersion,
+            format!(
+                "Unsupported protocol version {} (daemon speaks version {})",
+                requested, PROTOCOL_VERSION
+            ),
+        )
+    }
+}
+
+fn default_version() -> u32 {
+    PROTOCOL_VERSION
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_without_version_or_data() {
+        let request: Request = serde_json::from_str(r#"{"operation":"hello"}"#).unwrap();
+        assert_eq!(request.version, None);
+        assert_eq!(request.id, None);
+        assert_eq!(request.operation, Operation::Hello);
+        assert!(!request.include_timings);
+        assert!(request.data.plaintext.is_empty());
+    }
+
+    #[test]
+    fn test_response_id_omitted_when_absent() {
+        let json = serde_json::to_string(&Response::error("boom".into())).unwrap();
+        assert!(!json.contains(r#""id""#), "{}", json);
+
+        let response = Response {
+            id: Some("req-1".into()),
+            ..Response::error("boom".into())
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.starts_with(r#"{"id":"req-1","#), "{}", json);
+    }
+
+    #[test]
+    fn test_hello_response_roundtrip() {
+        let json = serde_json::to_string(&Response::success_hello(HelloInfo::current())).unwrap();
+        assert!(json.contains(r#""protocolVersion":1"#), "{}", json);
+        assert!(json.contains(r#""operations":["encrypt","decrypt","hello","createKey","rewrap","ping","batch","stats","#), "{}", json);
+        assert!(json.contains(r#""mode":"full""#), "{}", json);
+        assert!(json.contains(r#""algorithms":["AES-256-GCM","AES-256-GCM-SIV"]"#), "{}", json);
+
+        let response: Response = serde_json::from_str(&json).unwrap();
+        match response.result {
+            Some(ResponseResult::Hello(info)) => assert_eq!(info, HelloInfo::current()),
+            other => panic!("expected hello result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_key_created_omits_absent_key() {
+        let json = serde_json::to_string(&Response::success_key_created("new-key".into(), None)).unwrap();
+        assert!(json.contains(r#""result":{"keyId":"new-key"}"#), "{}", json);
+
+        let response: Response = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            response.result,
+            Some(ResponseResult::KeyCreated { ref key_id, key: None }) if key_id == "new-key"
+        ));
+    }
+
+    #[test]
+    fn test_batch_request_and_results() {
+        let request: Request = serde_json::from_str(
+            r#"{"operation":"batch","data":{"items":[
+                {"operation":"encrypt","data":{"plaintext":"aGk=","keyId":"k1"}},
+                {"operation":"decrypt","data":{}}
+            ]}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.data.items.len(), 2);
+        assert_eq!(request.data.items[0].operation, Operation::Encrypt);
+        assert_eq!(request.data.items[0].data.key_id.as_deref(), Some("k1"));
+        assert!(request.data.items[1].data.envelope.is_none());
+
+        let failed = Response::failure(ErrorCode::InvalidRequest, "Missing envelope".into());
+        let response = Response::success_batch(vec![
+            Response::success_decrypt("aGk=".into()).into(),
+            failed.into(),
+        ]);
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains(r#""errorCode":"invalid_request""#), "{}", json);
+
+        match serde_json::from_str::<Response>(&json).unwrap().result {
+            Some(ResponseResult::Batch { results }) => {
+                assert!(results[0].success);
+                assert!(matches!(results[0].result, Some(ResponseResult::Decrypt { .. })));
+                assert!(!results[1].success);
+                assert_eq!(results[1].error_code, Some(ErrorCode::InvalidRequest));
+            }
+            other => panic!("expected batch result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stream_finished_roundtrip() {
+        let header = StreamHeader {
+            version: 1,
+            key_id: "k1".into(),
+            algorithm: "AES-256-GCM".into(),
+            encrypted_key: "a2V5".into(),
+            chunk_size: 1024,
+            nonce_prefix: "bm9uY2U=".into(),
+            kek_fingerprint: None,
+        };
+        let json = serde_json::to_string(&Response::success_stream(header.clone(), 3, 2500)).unwrap();
+        assert!(json.contains(r#""plaintextBytes":2500"#), "{}", json);
+
+        match serde_json::from_str::<Response>(&json).unwrap().result {
+            Some(ResponseResult::StreamFinished { stream_header, chunks, plaintext_bytes }) => {
+                assert_eq!(stream_header, header);
+                assert_eq!((chunks, plaintext_bytes), (3, 2500));
+            }
+            other => panic!("expected stream result, got {:?}", other),
         }
+        assert!(Operation::EncryptStream.is_streaming());
+        assert!(!Operation::Encrypt.is_streaming());
+
+        // A file result is not mistaken for the stream result it extends
+        let envelope_path = Some("/data/out.vstr.envelope.json".into());
+        let response = Response::success_file(header, "/data/out.vstr".into(), envelope_path, 3, 2500, 2651);
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains(r#""ciphertextBytes":2651"#), "{}", json);
+        match serde_json::from_str::<Response>(&json).unwrap().result {
+            Some(ResponseResult::FileFinished { output_path, envelope_path, .. }) => {
+                assert_eq!(output_path, PathBuf::from("/data/out.vstr"));
+                assert_eq!(envelope_path, Some(PathBuf::from("/data/out.vstr.envelope.json")));
+            }
+            other => panic!("expected file result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_names_match_serde() {
+        for operation in Operation::all() {
+            assert_eq!(serde_json::to_value(operation).unwrap(), operation.as_str());
+        }
+        for code in ErrorCode::all() {
+            assert_eq!(serde_json::to_value(code).unwrap(), code.as_str());
+        }
+    }
+
+    #[test]
+    fn test_daemon_mode_names() {
+        for mode in DaemonMode::all() {
+            assert_eq!(serde_json::to_value(mode).unwrap(), mode.as_str());
+            assert_eq!(mode.as_str().parse::<DaemonMode>().unwrap(), *mode);
+        }
+        assert!("read-only".parse::<DaemonMode>().is_err());
+
+        // Hello from a daemon without modes
+        let info: HelloInfo = serde_json::from_str(
+            r#"{"protocolVersion":1,"crateVersion":"0.1.0","operations":["encrypt"],"algorithms":["AES-256-GCM"]}"#,
+        )
+        .unwrap();
+        assert_eq!(info.mode, DaemonMode::Full);
+        assert!(info.compression.is_empty());
+    }
+
+    #[test]
+    fn test_daemon_mode_refusals() {
+        let with_key = RequestData {
+            key_id: Some("k1".into()),
+            new_key_id: Some("k2".into()),
+            ..Default::default()
+        };
+        let without_key = RequestData::default();
+        let refused = |mode: DaemonMode, operation: Operation, data: &RequestData| mode.refusal(operation, data).is_some();
+
+        for operation in Operation::all() {
+            assert!(!refused(DaemonMode::Full, *operation, &without_key), "{:?}", operation);
+        }
+
+        let decrypt_only = DaemonMode::DecryptOnly;
+        for operation in [Operation::Encrypt, Operation::EncryptStream, Operation::Rewrap, Operation::CreateKey] {
+            assert!(refused(decrypt_only, operation, &with_key), "{:?}", operation);
+        }
+        for operation in [Operation::Decrypt, Operation::DecryptStream, Operation::Hello, Operation::Stats, Operation::FlushKeys] {
+            assert!(!refused(decrypt_only, operation, &without_key), "{:?}", operation);
+        }
+
+        let encrypt_only = DaemonMode::EncryptOnly;
+        for operation in [Operation::Decrypt, Operation::DecryptStream, Operation::Rewrap] {
+            assert!(refused(encrypt_only, operation, &with_key), "{:?}", operation);
+        }
+        for operation in [Operation::Encrypt, Operation::EncryptStream, Operation::CreateKey, Operation::Ping] {
+            assert!(!refused(encrypt_only, operation, &without_key), "{:?}", operation);
+        }
+
+        let message = decrypt_only.refusal(Operation::Encrypt, &without_key).unwrap();
+        assert!(message.contains("creates a key"), "{}", message);
+        assert!(message.contains("decrypt-only"), "{}", message);
+    }
+
+    #[test]
+    fn test_unsupported_version_error_code() {
+        let json = serde_json::to_string(&Response::unsupported_version(7)).unwrap();
+        assert!(json.contains(r#""errorCode":"unsupported_version""#), "{}", json);
+        assert!(json.contains(r#""version":1"#), "{}", json);
     }
 }
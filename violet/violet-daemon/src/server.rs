@@ -1,82 +1,3442 @@
-use tokio::net::{UnixListener, UnixStream};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use std::path::Path;
-use anyhow::Result;
-use crate::handler::RequestHandler;
-use crate::protocol::{Request, Response};
+use tokio::net::{TcpListener, TcpStream, UnixListener};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio_util::codec::Framed;
+use bytes::BytesMut;
+use futures::stream::FuturesUnordered;
+use futures::{SinkExt, StreamExt};
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::net::SocketAddr;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use anyhow::{bail, Context, Result};
+use crate::audit::{AuditLog, FileAuditSink, PeerCredentials};
+use crate::breaker::{DEFAULT_BREAKER_COOLDOWN, DEFAULT_BREAKER_THRESHOLD};
+use crate::codec::{compress, decompress, is_compressed, FrameCodec, FrameError};
+use crate::handler::{
+    KeyAllowList, RequestHandler, DEFAULT_BLOCKING_THREADS, DEFAULT_KEK_CACHE_CAPACITY, DEFAULT_KEK_CACHE_TTL,
+    DEFAULT_KEYS_SERVER_TIMEOUT, DEFAULT_MAX_BATCH_SIZE,
+};
+use crate::metrics::serve_prometheus;
+use crate::protocol::{DaemonMode, ErrorCode, Request, Response};
+use crate::tls::TLS_HANDSHAKE_TIMEOUT;
+use tokio_rustls::TlsAcceptor;
+use violet_core::{DEFAULT_CHUNK_SIZE, STREAM_FRAME_OVERHEAD};
+
+/// Default cap on a single request line or frame body
+pub const DEFAULT_MAX_REQUEST_BYTES: usize = 16 * 1024 * 1024;
+
+/// Default number of connections served at once
+pub const DEFAULT_MAX_CONNECTIONS: usize = 256;
+
+/// Default number of requests handled at once across all connections
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 64;
+
+/// Default time connections get to finish their requests after shutdown starts
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+/// Most requests with an `id` handled at once on one connection
+///
+/// Past this, the connection stops reading until one of them is answered.
+const MAX_PIPELINED_REQUESTS: usize = 8;
+
+/// Default permissions of the Unix socket: owner only
+pub const DEFAULT_SOCKET_MODE: u32 = 0o600;
+
+/// Default Unix socket path: `$XDG_RUNTIME_DIR/violet.sock`, or `/tmp/violet.sock`
+/// when `XDG_RUNTIME_DIR` is not set
+pub fn default_socket_path() -> String {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => Path::new(&dir).join("violet.sock").display().to_string(),
+        _ => "/tmp/violet.sock".to_string(),
+    }
+}
+
+/// The name of a Linux abstract-namespace socket written as `@name`, or `None` for a file path
+///
+/// Abstract sockets live in the kernel rather than the filesystem, so they need
+/// no directory both daemon and clients can write to and leave nothing behind.
+/// They have no file permissions either: any process in the same network
+/// namespace can connect.
+pub fn abstract_socket_name(socket_path: &str) -> Option<&str> {
+    socket_path.strip_prefix('@')
+}
+
+/// Where a daemon takes its requests from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// Accept connections on the Unix socket and/or the TCP listener
+    #[default]
+    Listeners,
+    /// Serve a single client over the process's stdin and stdout, e.g. as a
+    /// subprocess of another program, and shut down when stdin is closed
+    Stdio,
+}
+
+/// What happens to a connection beyond the connection limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectionLimitPolicy {
+    /// Accept it, answer with a `too_many_connections` error line and close it
+    #[default]
+    Refuse,
+    /// Leave it in the listen backlog until a connection closes
+    Queue,
+}
+
+/// Unix socket peers allowed to use the daemon, checked with `SO_PEERCRED`
+///
+/// A peer is allowed if its UID or its primary GID is listed. An empty list
+/// allows everyone who can open the socket.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerAllowList {
+    pub uids: Vec<u32>,
+    pub gids: Vec<u32>,
+}
+
+impl PeerAllowList {
+    pub fn is_empty(&self) -> bool {
+        self.uids.is_empty() && self.gids.is_empty()
+    }
+
+    /// Whether a peer with these credentials may be served; a peer whose
+    /// credentials are unknown is only allowed by an empty list
+    pub fn permits(&self, peer: Option<&PeerCredentials>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        match peer {
+            Some(peer) => self.uids.contains(&peer.uid) || self.gids.contains(&peer.gid),
+            None => false,
+        }
+    }
+}
 
 pub struct DaemonServer {
-    socket_path: String,
+    transport: Transport,
+    socket_path: Option<String>,
+    socket_mode: u32,
+    socket_group: Option<u32>,
+    allow_insecure_socket_dir: bool,
+    peer_allow_list: PeerAllowList,
+    tcp_addr: Option<SocketAddr>,
+    metrics_addr: Option<SocketAddr>,
+    allow_remote: bool,
+    allow_insecure_remote: bool,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    tls_client_ca: Option<PathBuf>,
+    allow_key_export: bool,
+    require_key_id: bool,
+    mode: DaemonMode,
+    key_allow_list: KeyAllowList,
+    file_dirs: Vec<PathBuf>,
+    force_start: bool,
+    audit_log: Option<PathBuf>,
+    audit_strict: bool,
+    shared_key_ttl: Option<Duration>,
+    kek_cache_ttl: Duration,
+    kek_cache_capacity: usize,
+    keys_server_timeout: Duration,
+    breaker_threshold: u32,
+    breaker_cooldown: Duration,
+    max_batch_size: usize,
+    max_request_bytes: usize,
+    max_connections: usize,
+    connection_limit_policy: ConnectionLimitPolicy,
+    max_in_flight: usize,
+    blocking_threads: usize,
+    shutdown: CancellationToken,
+    shutdown_grace: Duration,
     server_url: String,
 }
 
+/// Stops a [`DaemonServer`] from another task, see [`DaemonServer::shutdown_handle`]
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle(CancellationToken);
+
+impl ShutdownHandle {
+    /// Stop accepting connections and let the daemon drain and return
+    pub fn shutdown(&self) {
+        self.0.cancel();
+    }
+}
+
+/// A daemon whose listeners are bound but not yet accepting connections
+///
+/// The Unix socket file is removed when this (or the future returned by
+/// [`serve`](Self::serve)) is dropped.
+pub struct BoundDaemon {
+    unix: Option<UnixListener>,
+    tcp: Option<TcpListener>,
+    /// Set when the TCP listener serves TLS
+    tls: Option<TlsAcceptor>,
+    /// Stdin and stdout, under [`Transport::Stdio`]
+    stdio: Option<Connection>,
+    metrics: Option<TcpListener>,
+    connections: Arc<Connections>,
+    shutdown_grace: Duration,
+    socket_guard: Option<SocketFileGuard>,
+    instance_lock: Option<InstanceLock>,
+}
+
+/// Both directions of a client connection as one stream, whatever the transport
+type Connection = Box<dyn Duplex>;
+
+trait Duplex: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Duplex for T {}
+
+/// State shared by all connections of a daemon, including its limits
+struct Connections {
+    handler: Arc<RequestHandler>,
+    max_request_bytes: usize,
+    slots: Arc<Semaphore>,
+    policy: ConnectionLimitPolicy,
+    in_flight: Semaphore,
+    peer_allow_list: PeerAllowList,
+    /// Cancelled when shutdown starts: stop reading new requests
+    shutdown: CancellationToken,
+    /// Cancelled when the grace period ends: drop connections outright
+    abandon: CancellationToken,
+    tasks: TaskTracker,
+}
+
+/// Exclusive `flock` on the lock file next to the Unix socket, naming the daemon's PID
+///
+/// Held for as long as the daemon serves, so a second daemon started on the
+/// same socket path fails instead of deleting the first one's socket. The
+/// kernel drops the lock when the process dies, so a lock file left behind by
+/// a dead daemon is simply taken over. The file is removed when dropped.
+struct InstanceLock {
+    file: File,
+    path: PathBuf,
+}
+
+/// Why an [`InstanceLock`] could not be taken
+enum LockError {
+    /// A live daemon holds the lock; its PID, if the lock file names one
+    Held(Option<u32>),
+    Io(std::io::Error),
+}
+
+impl InstanceLock {
+    /// Lock file for the socket at `socket_path`: the same path with `.lock` appended
+    fn path_for(socket_path: &Path) -> PathBuf {
+        let mut path = socket_path.as_os_str().to_owned();
+        path.push(".lock");
+        PathBuf::from(path)
+    }
+
+    fn acquire(path: PathBuf) -> std::result::Result<Self, LockError> {
+        let mut file = loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .mode(0o600)
+                .open(&path)
+                .map_err(LockError::Io)?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => return Err(LockError::Held(read_pid(&mut file))),
+                Err(TryLockError::Error(e)) => return Err(LockError::Io(e)),
+            }
+            // A daemon shutting down removes its lock file before unlocking it,
+            // so the lock may be on a file that is no longer at `path`; try the
+            // one there now instead
+            if still_at(&file, &path).map_err(LockError::Io)? {
+                break file;
+            }
+        };
+
+        if let Some(pid) = read_pid(&mut file) {
+            tracing::info!("Taking over stale lock file {} left by PID {}", path.display(), pid);
+        }
+        file.set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| writeln!(file, "{}", std::process::id()))
+            .map_err(LockError::Io)?;
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Remove the file while still holding the lock; closing `file` releases it
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!("Failed to remove lock file {}: {}", self.path.display(), e);
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+/// Whether `file` is still the file at `path`, rather than one since removed or replaced
+fn still_at(file: &File, path: &Path) -> std::io::Result<bool> {
+    let opened = file.metadata()?;
+    match std::fs::metadata(path) {
+        Ok(current) => Ok((opened.dev(), opened.ino()) == (current.dev(), current.ino())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// PID written to a lock file, if it holds one
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.rewind().and_then(|()| file.read_to_string(&mut contents)).ok()?;
+    contents.trim().parse().ok()
+}
+
+/// Removes the Unix socket file when dropped
+///
+/// Owned by the serving daemon, so the file is cleaned up after a shutdown,
+/// on error and while unwinding from a panic.
+struct SocketFileGuard {
+    path: PathBuf,
+}
+
+impl Drop for SocketFileGuard {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => tracing::debug!("Removed socket {}", self.path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to remove socket {}: {}", self.path.display(), e),
+        }
+    }
+}
+
 impl DaemonServer {
     pub fn new(socket_path: String, server_url: String) -> Self {
         Self {
-            socket_path,
+            transport: Transport::Listeners,
+            socket_path: Some(socket_path),
+            socket_mode: DEFAULT_SOCKET_MODE,
+            socket_group: None,
+            allow_insecure_socket_dir: false,
+            peer_allow_list: PeerAllowList::default(),
+            tcp_addr: None,
+            metrics_addr: None,
+            allow_remote: false,
+            allow_insecure_remote: false,
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            allow_key_export: false,
+            require_key_id: false,
+            mode: DaemonMode::Full,
+            key_allow_list: KeyAllowList::default(),
+            file_dirs: Vec::new(),
+            force_start: false,
+            audit_log: None,
+            audit_strict: false,
+            shared_key_ttl: None,
+            kek_cache_ttl: DEFAULT_KEK_CACHE_TTL,
+            kek_cache_capacity: DEFAULT_KEK_CACHE_CAPACITY,
+            keys_server_timeout: DEFAULT_KEYS_SERVER_TIMEOUT,
+            breaker_threshold: DEFAULT_BREAKER_THRESHOLD,
+            breaker_cooldown: DEFAULT_BREAKER_COOLDOWN,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            max_request_bytes: DEFAULT_MAX_REQUEST_BYTES,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            connection_limit_policy: ConnectionLimitPolicy::default(),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            blocking_threads: DEFAULT_BLOCKING_THREADS,
+            shutdown: CancellationToken::new(),
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
             server_url,
         }
     }
 
-    pub async fn run(&self) -> Result<()> {
-        // Remove existing socket if present
-        let path = Path::new(&self.socket_path);
-        if path.exists() {
-            std::fs::remove_file(path)?;
+    /// Create a daemon that only listens on TCP
+    pub fn tcp(addr: SocketAddr, server_url: String) -> Self {
+        Self {
+            transport: Transport::Listeners,
+            socket_path: None,
+            socket_mode: DEFAULT_SOCKET_MODE,
+            socket_group: None,
+            allow_insecure_socket_dir: false,
+            peer_allow_list: PeerAllowList::default(),
+            tcp_addr: Some(addr),
+            metrics_addr: None,
+            allow_remote: false,
+            allow_insecure_remote: false,
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            allow_key_export: false,
+            require_key_id: false,
+            mode: DaemonMode::Full,
+            key_allow_list: KeyAllowList::default(),
+            file_dirs: Vec::new(),
+            force_start: false,
+            audit_log: None,
+            audit_strict: false,
+            shared_key_ttl: None,
+            kek_cache_ttl: DEFAULT_KEK_CACHE_TTL,
+            kek_cache_capacity: DEFAULT_KEK_CACHE_CAPACITY,
+            keys_server_timeout: DEFAULT_KEYS_SERVER_TIMEOUT,
+            breaker_threshold: DEFAULT_BREAKER_THRESHOLD,
+            breaker_cooldown: DEFAULT_BREAKER_COOLDOWN,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            max_request_bytes: DEFAULT_MAX_REQUEST_BYTES,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            connection_limit_policy: ConnectionLimitPolicy::default(),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            blocking_threads: DEFAULT_BLOCKING_THREADS,
+            shutdown: CancellationToken::new(),
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+            server_url,
+        }
+    }
+
+    /// Create a daemon that serves one client over stdin and stdout
+    ///
+    /// Requests are read from stdin and answered on stdout, in either framing,
+    /// as on a socket connection; logs must go elsewhere. The daemon shuts
+    /// down once stdin is closed and its last requests are answered.
+    pub fn stdio(server_url: String) -> Self {
+        Self {
+            transport: Transport::Stdio,
+            socket_path: None,
+            socket_mode: DEFAULT_SOCKET_MODE,
+            socket_group: None,
+            allow_insecure_socket_dir: false,
+            peer_allow_list: PeerAllowList::default(),
+            tcp_addr: None,
+            metrics_addr: None,
+            allow_remote: false,
+            allow_insecure_remote: false,
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            allow_key_export: false,
+            require_key_id: false,
+            mode: DaemonMode::Full,
+            key_allow_list: KeyAllowList::default(),
+            file_dirs: Vec::new(),
+            force_start: false,
+            audit_log: None,
+            audit_strict: false,
+            shared_key_ttl: None,
+            kek_cache_ttl: DEFAULT_KEK_CACHE_TTL,
+            kek_cache_capacity: DEFAULT_KEK_CACHE_CAPACITY,
+            keys_server_timeout: DEFAULT_KEYS_SERVER_TIMEOUT,
+            breaker_threshold: DEFAULT_BREAKER_THRESHOLD,
+            breaker_cooldown: DEFAULT_BREAKER_COOLDOWN,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            max_request_bytes: DEFAULT_MAX_REQUEST_BYTES,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            connection_limit_policy: ConnectionLimitPolicy::default(),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            blocking_threads: DEFAULT_BLOCKING_THREADS,
+            shutdown: CancellationToken::new(),
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+            server_url,
         }
+    }
+
+    /// How this daemon takes its requests
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    /// Set the permission bits of the Unix socket, e.g. `0o660` to admit a group
+    ///
+    /// Anyone who can connect can ask the daemon to decrypt, so the default is
+    /// owner only.
+    pub fn with_socket_mode(mut self, mode: u32) -> Self {
+        self.socket_mode = mode;
+        self
+    }
+
+    /// Give the Unix socket to group `gid`, for use with a group-accessible mode
+    pub fn with_socket_group(mut self, gid: u32) -> Self {
+        self.socket_group = Some(gid);
+        self
+    }
+
+    /// Permit a Unix socket in a world-writable directory such as `/tmp`
+    ///
+    /// Other users can replace a socket in such a directory with their own.
+    pub fn allow_insecure_socket_dir(mut self, allow: bool) -> Self {
+        self.allow_insecure_socket_dir = allow;
+        self
+    }
+
+    /// Serve Unix socket clients running as `uid` (repeatable)
+    ///
+    /// Once any UID or GID is allowed, clients matching none of them are sent
+    /// an `unauthorized` error and disconnected before any request is read.
+    pub fn with_allowed_uid(mut self, uid: u32) -> Self {
+        self.peer_allow_list.uids.push(uid);
+        self
+    }
+
+    /// Serve Unix socket clients whose primary group is `gid` (repeatable)
+    pub fn with_allowed_gid(mut self, gid: u32) -> Self {
+        self.peer_allow_list.gids.push(gid);
+        self
+    }
+
+    /// Start even if another live daemon holds the Unix socket's lock file
+    ///
+    /// By default [`bind`](Self::bind) fails, naming the other daemon's PID,
+    /// rather than replace its socket. Forcing takes the socket over and leaves
+    /// the other daemon running with no socket of its own.
+    pub fn force_start(mut self, force: bool) -> Self {
+        self.force_start = force;
+        self
+    }
+
+    /// Also listen on a TCP address, using the same protocol as the Unix socket
+    pub fn with_tcp_listener(mut self, addr: SocketAddr) -> Self {
+        self.tcp_addr = Some(addr);
+        self
+    }
+
+    /// Serve Prometheus text-format metrics over HTTP at `http://<addr>/metrics`,
+    /// and a Keys server health check at `http://<addr>/healthz`
+    pub fn with_metrics_addr(mut self, addr: SocketAddr) -> Self {
+        self.metrics_addr = Some(addr);
+        self
+    }
+
+    /// Permit binding the TCP and metrics listeners to a non-loopback address
+    pub fn allow_remote(mut self, allow: bool) -> Self {
+        self.allow_remote = allow;
+        self
+    }
+
+    /// Serve TLS on the TCP listener with the PEM certificate chain `cert` and private key `key`
+    pub fn with_tls(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.tls_cert = Some(cert.into());
+        self.tls_key = Some(key.into());
+        self
+    }
+
+    /// Require TCP clients to present a certificate signed by a CA in the PEM file `ca`
+    ///
+    /// Needs [`with_tls`](Self::with_tls).
+    pub fn with_tls_client_ca(mut self, ca: impl Into<PathBuf>) -> Self {
+        self.tls_client_ca = Some(ca.into());
+        self
+    }
+
+    /// Permit a non-loopback TCP listener without TLS
+    ///
+    /// Requests, plaintexts and keys then cross the network unencrypted.
+    pub fn allow_insecure_remote(mut self, allow: bool) -> Self {
+        self.allow_insecure_remote = allow;
+        self
+    }
+
+    /// Let `createKey` requests receive key material, see [`RequestHandler::allow_key_export`]
+    pub fn allow_key_export(mut self, allow: bool) -> Self {
+        self.allow_key_export = allow;
+        self
+    }
+
+    /// Refuse encrypts without a key_id, see [`RequestHandler::require_key_id`]
+    pub fn require_key_id(mut self, require: bool) -> Self {
+        self.require_key_id = require;
+        self
+    }
+
+    /// Only accept the operations `mode` allows, see [`RequestHandler::with_mode`]
+    pub fn with_mode(mut self, mode: DaemonMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Only use the KEK `key_id` and others allowed here (repeatable)
+    ///
+    /// Once any key_id or prefix is allowed, requests for other keys fail with
+    /// `key_not_permitted`, see [`RequestHandler::with_key_allow_list`].
+    pub fn with_allowed_key_id(mut self, key_id: String) -> Self {
+        self.key_allow_list.key_ids.push(key_id);
+        self
+    }
+
+    /// Only use KEKs whose key_id starts with `prefix` and others allowed here (repeatable)
+    pub fn with_allowed_key_prefix(mut self, prefix: String) -> Self {
+        self.key_allow_list.prefixes.push(prefix);
+        self
+    }
+
+    /// Let `encryptFile` and `decryptFile` read and create files under `dir` (repeatable)
+    ///
+    /// Without any directory both operations are refused; see
+    /// [`RequestHandler::with_file_dirs`].
+    pub fn with_allowed_file_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.file_dirs.push(dir.into());
+        self
+    }
+
+    /// Set the largest number of items accepted in one `batch` request
+    pub fn with_max_batch_size(mut self, max: usize) -> Self {
+        self.max_batch_size = max;
+        self
+    }
+
+    /// Set the largest request line or frame body accepted, in bytes
+    ///
+    /// An oversized request gets a `payload_too_large` error without the daemon
+    /// buffering it. A newline-delimited connection then carries on from the
+    /// next line; a framed connection is closed.
+    pub fn with_max_request_bytes(mut self, max: usize) -> Self {
+        self.max_request_bytes = max;
+        self
+    }
+
+    /// Set how many connections are served at once, and what happens to the rest
+    pub fn with_max_connections(mut self, max: usize, policy: ConnectionLimitPolicy) -> Self {
+        self.max_connections = max;
+        self.connection_limit_policy = policy;
+        self
+    }
+
+    /// Set how many requests are handled at once across all connections
+    ///
+    /// Requests beyond the limit wait for a running one to finish.
+    pub fn with_max_in_flight(mut self, max: usize) -> Self {
+        self.max_in_flight = max;
+        self
+    }
+
+    /// Set how many large payloads are encrypted or decrypted at once on the
+    /// blocking thread pool; see [`RequestHandler::with_blocking_threads`]
+    pub fn with_blocking_threads(mut self, threads: usize) -> Self {
+        self.blocking_threads = threads;
+        self
+    }
+
+    /// Set how long connections may keep working on requests once shutdown starts
+    ///
+    /// Connections still busy after the grace period are dropped.
+    pub fn with_shutdown_grace(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = grace;
+        self
+    }
+
+    /// Handle for stopping this daemon from elsewhere, as SIGINT and SIGTERM do
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(self.shutdown.clone())
+    }
+
+    /// Append a hash-chained JSON line per operation to `path`
+    pub fn with_audit_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.audit_log = Some(path.into());
+        self
+    }
+
+    /// Encrypt requests without a key_id under one KEK, replaced every `ttl`
+    pub fn with_shared_key_ttl(mut self, ttl: Duration) -> Self {
+        self.shared_key_ttl = Some(ttl);
+        self
+    }
+
+    /// Set how long fetched KEKs are cached in memory; zero disables the cache
+    pub fn with_kek_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.kek_cache_ttl = ttl;
+        self
+    }
+
+    /// Set the largest number of KEKs cached in memory; zero disables the cache
+    pub fn with_kek_cache_capacity(mut self, capacity: usize) -> Self {
+        self.kek_cache_capacity = capacity;
+        self
+    }
+
+    /// Set how long each Keys server call may take (default: 3 seconds); zero
+    /// leaves it to the client's 30 second timeout
+    pub fn with_keys_server_timeout(mut self, timeout: Duration) -> Self {
+        self.keys_server_timeout = timeout;
+        self
+    }
+
+    /// Fail fast for `cooldown` after `threshold` consecutive Keys server
+    /// failures (default: 5 failures, 30 seconds); a zero threshold disables this
+    ///
+    /// See [`RequestHandler::with_circuit_breaker`].
+    pub fn with_circuit_breaker(mut self, threshold: u32, cooldown: Duration) -> Self {
+        self.breaker_threshold = threshold;
+        self.breaker_cooldown = cooldown;
+        self
+    }
+
+    /// Fail operations whose audit record cannot be written, instead of only logging the failure
+    pub fn with_audit_strict(mut self, strict: bool) -> Self {
+        self.audit_strict = strict;
+        self
+    }
 
-        let listener = UnixListener::bind(&self.socket_path)?;
-        tracing::info!("Daemon listening on {}", self.socket_path);
+    /// Serve until SIGINT, SIGTERM or a [`ShutdownHandle`] stops the daemon
+    ///
+    /// See [`BoundDaemon::serve`] for how shutdown proceeds. Returns `Ok(())`
+    /// once it is complete.
+    pub async fn run(&self) -> Result<()> {
+        let daemon = self.bind().await?;
 
-        // Handle Ctrl+C for graceful shutdown
-        let socket_path_clone = self.socket_path.clone();
-        tokio::spawn(async move {
-            tokio::signal::ctrl_c().await.ok();
-            tracing::info!("Shutting down...");
-            let _ = std::fs::remove_file(&socket_path_clone);
-            std::process::exit(0);
+        let shutdown = self.shutdown.clone();
+        let mut terminate = signal(SignalKind::terminate())?;
+        let signals = tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => tracing::info!("Received SIGINT, shutting down"),
+                _ = terminate.recv() => tracing::info!("Received SIGTERM, shutting down"),
+                _ = shutdown.cancelled() => return,
+            }
+            shutdown.cancel();
         });
 
-        loop {
-            let (stream, _) = listener.accept().await?;
-            let server_url = self.server_url.clone();
+        let result = daemon.serve().await;
+        signals.abort();
+        result
+    }
+
+    /// Build the request handler and bind all configured listeners
+    pub async fn bind(&self) -> Result<BoundDaemon> {
+        match self.transport {
+            Transport::Listeners if self.socket_path.is_none() && self.tcp_addr.is_none() => {
+                bail!("No socket path or TCP address to listen on");
+            }
+            Transport::Stdio if self.socket_path.is_some() || self.tcp_addr.is_some() => {
+                bail!("A daemon serving stdin and stdout cannot also listen on a socket or TCP address");
+            }
+            Transport::Stdio if !self.peer_allow_list.is_empty() => {
+                bail!("Stdin and stdout have no peer credentials; --allow-uid and --allow-gid cannot be combined with --stdio");
+            }
+            _ => {}
+        }
+
+        if self.socket_mode > 0o777 {
+            bail!("Invalid socket mode {:o}", self.socket_mode);
+        }
+        let abstract_name = self.socket_path.as_deref().and_then(abstract_socket_name);
+        let mut peer_allow_list = self.peer_allow_list.clone();
+        if let (Some(socket_path), Some(_)) = (&self.socket_path, abstract_name) {
+            if self.socket_group.is_some() {
+                bail!("Abstract socket {} has no file to give to a group; restrict clients with --allow-gid", socket_path);
+            }
+            if peer_allow_list.is_empty() {
+                // An abstract socket has no file permissions, so without a list
+                // every process in the network namespace could connect. Serve
+                // the daemon's own user only, as a 0600 socket file would.
+                // SAFETY: geteuid cannot fail and touches no memory
+                let uid = unsafe { libc::geteuid() };
+                tracing::info!(
+                    "Abstract socket {} serves UID {} only; pass --allow-uid or --allow-gid to serve others",
+                    socket_path,
+                    uid
+                );
+                peer_allow_list.uids.push(uid);
+            }
+        }
+        let instance_lock = match &self.socket_path {
+            // The kernel refuses to bind an abstract name twice, so it needs no lock file
+            Some(_) if abstract_name.is_some() => None,
+            Some(socket_path) => {
+                check_socket_dir(Path::new(socket_path), self.allow_insecure_socket_dir)?;
+                self.lock_socket(Path::new(socket_path))?
+            }
+            None => None,
+        };
+        if !self.peer_allow_list.is_empty() && self.tcp_addr.is_some() {
+            bail!("TCP clients have no peer credentials; --allow-uid and --allow-gid cannot be combined with --listen");
+        }
+
+        let tls = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => {
+                if self.tcp_addr.is_none() {
+                    bail!("TLS only applies to the TCP listener; --tls-cert needs --listen");
+                }
+                Some(crate::tls::acceptor(cert, key, self.tls_client_ca.as_deref())?)
+            }
+            _ if self.tls_client_ca.is_some() => bail!("--tls-client-ca needs --tls-cert and --tls-key"),
+            _ => None,
+        };
 
-            tokio::spawn(async move {
-                if let Err(e) = handle_connection(stream, server_url).await {
-                    tracing::error!("Connection handler error: {}", e);
+        if let Some(addr) = self.tcp_addr {
+            if !addr.ip().is_loopback() {
+                if !self.allow_remote {
+                    bail!(
+                        "Refusing to listen on non-loopback address {} without --allow-remote",
+                        addr
+                    );
                 }
-            });
+                if tls.is_none() {
+                    if !self.allow_insecure_remote {
+                        bail!(
+                            "Refusing to serve non-loopback address {} without TLS; \
+                             pass --tls-cert and --tls-key, or --allow-insecure-remote",
+                            addr
+                        );
+                    }
+                    tracing::warn!(
+                        "Daemon TCP listener on {} is reachable from other hosts; \
+                         requests are unauthenticated and unencrypted",
+                        addr
+                    );
+                } else if self.tls_client_ca.is_none() {
+                    tracing::warn!(
+                        "Daemon TCP listener on {} is reachable from other hosts and does not \
+                         authenticate clients; pass --tls-client-ca to require client certificates",
+                        addr
+                    );
+                }
+            }
+        }
+
+        // Build the shared handler (and its blocking HTTP client) off the async runtime
+        let server_url = self.server_url.clone();
+        let mut handler = tokio::task::spawn_blocking(move || RequestHandler::new(&server_url))
+            .await??
+            .allow_key_export(self.allow_key_export)
+            .require_key_id(self.require_key_id)
+            .with_mode(self.mode)
+            .with_key_allow_list(self.key_allow_list.clone())
+            .with_max_batch_size(self.max_batch_size)
+            .with_kek_cache_ttl(self.kek_cache_ttl)
+            .with_kek_cache_capacity(self.kek_cache_capacity)
+            .with_keys_server_timeout(self.keys_server_timeout)
+            .with_circuit_breaker(self.breaker_threshold, self.breaker_cooldown)
+            .with_blocking_threads(self.blocking_threads)
+            .with_stream_chunk_size(stream_chunk_size(self.max_request_bytes));
+        if let Some(ttl) = self.shared_key_ttl {
+            tracing::info!("Keyless encrypts share one KEK for {:?} at a time", ttl);
+            handler = handler.with_shared_key_ttl(ttl);
+        }
+        if self.max_connections == 0 || self.max_in_flight == 0 {
+            bail!("Connection and in-flight request limits must be at least 1");
+        }
+        if self.blocking_threads == 0 {
+            bail!("The blocking thread count must be at least 1");
+        }
+        if self.mode != DaemonMode::Full {
+            tracing::info!("Running in {} mode", self.mode);
+        }
+        if !self.key_allow_list.is_empty() {
+            tracing::info!(
+                "Only using {} allowed key IDs and {} allowed key ID prefixes",
+                self.key_allow_list.key_ids.len(),
+                self.key_allow_list.prefixes.len()
+            );
+        }
+        if self.require_key_id {
+            tracing::info!("Encrypts without a key_id are refused instead of creating a key");
+        }
+        if self.allow_key_export {
+            tracing::warn!("Key material export is enabled; any client of this daemon can obtain KEKs");
+        }
+        if !self.file_dirs.is_empty() {
+            let mut dirs = Vec::with_capacity(self.file_dirs.len());
+            for dir in &self.file_dirs {
+                let resolved = dir
+                    .canonicalize()
+                    .with_context(|| format!("Failed to resolve allowed file directory {}", dir.display()))?;
+                if !resolved.is_dir() {
+                    bail!("Allowed file directory {} is not a directory", dir.display());
+                }
+                tracing::info!("File operations may read and create files under {}", resolved.display());
+                dirs.push(resolved);
+            }
+            handler = handler.with_file_dirs(dirs)?;
+        }
+        if let Some(path) = &self.audit_log {
+            let sink = FileAuditSink::open(path)
+                .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+            tracing::info!("Writing audit log to {}", path.display());
+            handler = handler.with_audit_log(AuditLog::new(Arc::new(sink)).strict(self.audit_strict));
+        } else if self.audit_strict {
+            bail!("Strict auditing needs an audit log");
+        }
+
+        let (unix, socket_guard) = match (&self.socket_path, abstract_name) {
+            (Some(socket_path), Some(name)) => {
+                let listener = bind_abstract(name).map_err(|e| match e.kind() {
+                    std::io::ErrorKind::AddrInUse => {
+                        anyhow::anyhow!("Another daemon is already serving abstract socket {}", socket_path)
+                    }
+                    _ => anyhow::Error::new(e).context(format!("Failed to bind abstract socket {}", socket_path)),
+                })?;
+                tracing::info!("Daemon listening on abstract socket {}", socket_path);
+                (Some(listener), None)
+            }
+            (Some(socket_path), None) => {
+                // Remove an existing socket, which the instance lock says no live daemon is using
+                let path = Path::new(socket_path);
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(socket_path)?;
+                let guard = SocketFileGuard { path: path.to_path_buf() };
+                restrict_socket(path, self.socket_mode, self.socket_group)?;
+                tracing::info!("Daemon listening on {} (mode {:o})", socket_path, self.socket_mode);
+                (Some(listener), Some(guard))
+            }
+            (None, _) => (None, None),
+        };
+
+        let tcp = match self.tcp_addr {
+            Some(addr) => {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("Failed to bind {}", addr))?;
+                let scheme = if tls.is_some() { "tls" } else { "tcp" };
+                tracing::info!("Daemon listening on {}://{}", scheme, listener.local_addr()?);
+                Some(listener)
+            }
+            None => None,
+        };
+
+        let metrics = match self.metrics_addr {
+            Some(addr) => {
+                if !addr.ip().is_loopback() {
+                    if !self.allow_remote {
+                        bail!(
+                            "Refusing to serve metrics on non-loopback address {} without --allow-remote",
+                            addr
+                        );
+                    }
+                    tracing::warn!("Metrics on {} are reachable from other hosts without authentication", addr);
+                }
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("Failed to bind metrics listener {}", addr))?;
+                tracing::info!("Serving metrics on http://{}/metrics", listener.local_addr()?);
+                Some(listener)
+            }
+            None => None,
+        };
+
+        let stdio: Option<Connection> = match self.transport {
+            Transport::Stdio => {
+                tracing::info!("Daemon serving requests on stdin and stdout");
+                Some(Box::new(tokio::io::join(tokio::io::stdin(), tokio::io::stdout())))
+            }
+            Transport::Listeners => None,
+        };
+
+        Ok(BoundDaemon {
+            unix,
+            tcp,
+            tls,
+            stdio,
+            metrics,
+            connections: Arc::new(Connections {
+                handler: Arc::new(handler),
+                max_request_bytes: self.max_request_bytes,
+                slots: Arc::new(Semaphore::new(self.max_connections)),
+                policy: self.connection_limit_policy,
+                in_flight: Semaphore::new(self.max_in_flight),
+                peer_allow_list,
+                shutdown: self.shutdown.clone(),
+                abandon: CancellationToken::new(),
+                tasks: TaskTracker::new(),
+            }),
+            shutdown_grace: self.shutdown_grace,
+            socket_guard,
+            instance_lock,
+        })
+    }
+
+    /// Take the lock file next to `socket_path`, or fail if a live daemon holds it
+    ///
+    /// Returns `None` if [`force_start`](Self::force_start) overrode a held lock.
+    fn lock_socket(&self, socket_path: &Path) -> Result<Option<InstanceLock>> {
+        let lock_path = InstanceLock::path_for(socket_path);
+        let pid = match InstanceLock::acquire(lock_path.clone()) {
+            Ok(lock) => return Ok(Some(lock)),
+            Err(LockError::Held(pid)) => pid.map_or_else(|| "unknown PID".to_string(), |pid| format!("PID {}", pid)),
+            Err(LockError::Io(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", lock_path.display()));
+            }
+        };
+        if self.force_start {
+            tracing::warn!("Another daemon ({}) holds {}; taking over its socket", pid, lock_path.display());
+            return Ok(None);
+        }
+        bail!(
+            "Another daemon ({}) is already serving {}; stop it first, or pass --force to take over its socket",
+            pid,
+            socket_path.display()
+        )
+    }
+}
+
+impl BoundDaemon {
+    /// Address the TCP listener is bound to, if any (useful with port 0)
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        self.tcp.as_ref().and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// Address of the metrics listener, if one is configured
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics.as_ref().and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// Accept connections on all listeners until shutdown or until one fails
+    ///
+    /// Shutdown stops the listeners and closes idle connections. Requests
+    /// already being handled get up to the grace period to finish and send
+    /// their response; connections still busy after that are dropped. The
+    /// socket file is removed before this returns.
+    pub async fn serve(self) -> Result<()> {
+        // Keep the socket file and its lock until serving stops, however that
+        // happens; the socket goes first
+        let _instance_lock = self.instance_lock;
+        let _socket_guard = self.socket_guard;
+        let connections = self.connections;
+
+        let mut listeners = JoinSet::new();
+        if let Some(listener) = self.unix {
+            listeners.spawn(accept_unix(listener, Arc::clone(&connections)));
+        }
+        if let Some(listener) = self.tcp {
+            listeners.spawn(accept_tcp(listener, self.tls, Arc::clone(&connections)));
+        }
+        if let Some(listener) = self.metrics {
+            let handler = Arc::clone(&connections.handler);
+            listeners.spawn(serve_prometheus(listener, handler, connections.tasks.clone()));
+        }
+        if let Some(stream) = self.stdio {
+            connections.serve_stdio(stream);
+        }
+
+        let result: Result<()> = tokio::select! {
+            Some(joined) = listeners.join_next() => match joined {
+                Ok(accepted) => accepted.map_err(Into::into),
+                Err(e) => Err(e.into()),
+            },
+            _ = connections.shutdown.cancelled() => Ok(()),
+        };
+
+        // Also reached when a listener fails: stop the others and wind down
+        listeners.abort_all();
+        connections.shutdown.cancel();
+        connections.tasks.close();
+        if !connections.tasks.is_empty() {
+            tracing::info!(
+                "Waiting up to {:?} for {} connection(s) to finish",
+                self.shutdown_grace,
+                connections.tasks.len()
+            );
+        }
+        if tokio::time::timeout(self.shutdown_grace, connections.tasks.wait()).await.is_err() {
+            tracing::warn!(
+                "Dropping {} connection(s) still busy after {:?}",
+                connections.tasks.len(),
+                self.shutdown_grace
+            );
+            connections.abandon.cancel();
+            connections.tasks.wait().await;
         }
+        result
+    }
+}
+
+/// Refuse a socket directory that other users can write to, unless `allow_insecure`
+fn check_socket_dir(socket_path: &Path, allow_insecure: bool) -> Result<()> {
+    let dir = match socket_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let metadata = std::fs::metadata(dir)
+        .with_context(|| format!("Failed to inspect socket directory {}", dir.display()))?;
+    if metadata.permissions().mode() & 0o002 == 0 {
+        return Ok(());
+    }
+    if !allow_insecure {
+        bail!(
+            "Refusing to create the socket in world-writable directory {}; \
+             choose another --socket path or pass --insecure-socket-dir",
+            dir.display()
+        );
+    }
+    tracing::warn!(
+        "Socket directory {} is world-writable; other users can replace the socket",
+        dir.display()
+    );
+    Ok(())
+}
+
+/// Bind the abstract-namespace socket `name`, see [`abstract_socket_name`]
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &str) -> std::io::Result<UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    let listener = std::os::unix::net::UnixListener::bind_addr(&addr)?;
+    listener.set_nonblocking(true)?;
+    UnixListener::from_std(listener)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(_name: &str) -> std::io::Result<UnixListener> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "abstract socket names (@name) are only supported on Linux",
+    ))
+}
+
+/// Apply the configured mode and group to a freshly bound socket
+fn restrict_socket(path: &Path, mode: u32, group: Option<u32>) -> Result<()> {
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set mode {:o} on {}", mode, path.display()))?;
+    if let Some(gid) = group {
+        std::os::unix::fs::chown(path, None, Some(gid))
+            .with_context(|| format!("Failed to give {} to group {}", path.display(), gid))?;
+    }
+    Ok(())
+}
+
+/// Resolve a `--socket-group` or `--allow-gid` given as a numeric GID or a name from `/etc/group`
+pub fn parse_group(group: &str) -> Result<u32> {
+    lookup_id("/etc/group", group)
+        .with_context(|| format!("Unknown group {} (give a numeric GID for groups not in /etc/group)", group))
+}
+
+/// Resolve an `--allow-uid` given as a numeric UID or a name from `/etc/passwd`
+pub fn parse_user(user: &str) -> Result<u32> {
+    lookup_id("/etc/passwd", user)
+        .with_context(|| format!("Unknown user {} (give a numeric UID for users not in /etc/passwd)", user))
+}
+
+/// Parse `name` as a number, or find its ID (third field) in a passwd-style file
+fn lookup_id(file: &str, name: &str) -> Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
     }
+    let entries = std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?;
+    entries
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() > 2 && fields[0] == name)
+        .and_then(|fields| fields[2].parse().ok())
+        .with_context(|| format!("No entry for {} in {}", name, file))
 }
 
-async fn handle_connection(stream: UnixStream, server_url: String) -> Result<()> {
-    let (reader, mut writer) = stream.into_split();
-    let mut reader = BufReader::new(reader);
-    let mut line = String::new();
+/// Parse a `--listen` address of the form `tcp://host:port`
+pub fn parse_listen_addr(listen: &str) -> Result<SocketAddr> {
+    let Some(addr) = listen.strip_prefix("tcp://") else {
+        bail!("Unsupported listen address {} (expected tcp://host:port)", listen);
+    };
+    addr.parse()
+        .with_context(|| format!("Invalid TCP listen address: {}", addr))
+}
 
-    while reader.read_line(&mut line).await? > 0 {
-        let request: Request = match serde_json::from_str(&line) {
-            Ok(req) => req,
+async fn accept_unix(listener: UnixListener, connections: Arc<Connections>) -> std::io::Result<()> {
+    loop {
+        let slot = connections.queued_slot().await;
+        let (stream, _) = listener.accept().await?;
+        let peer = match stream.peer_cred() {
+            Ok(cred) => Some(PeerCredentials::from(cred)),
             Err(e) => {
-                let error_response = Response::error(format!("Invalid request: {}", e));
-                let json = serde_json::to_string(&error_response)?;
-                writer.write_all(json.as_bytes()).await?;
-                writer.write_all(b"\n").await?;
-                line.clear();
-                continue;
+                tracing::debug!("Peer credentials unavailable: {}", e);
+                None
             }
         };
+        if !connections.peer_allow_list.permits(peer.as_ref()) {
+            connections.refuse_unauthorized(stream, peer);
+            continue;
+        }
+        connections.spawn(Box::new(stream), peer, slot);
+    }
+}
+
+async fn accept_tcp(listener: TcpListener, tls: Option<TlsAcceptor>, connections: Arc<Connections>) -> std::io::Result<()> {
+    loop {
+        let slot = connections.queued_slot().await;
+        let (stream, peer) = listener.accept().await?;
+        tracing::debug!("TCP connection from {}", peer);
+        match &tls {
+            Some(acceptor) => connections.accept_tls(acceptor.clone(), stream, peer, slot),
+            None => connections.spawn(Box::new(stream), None, slot),
+        }
+    }
+}
 
-        let handler = RequestHandler::new(&server_url);
-        let response = handler.handle(request).await;
+impl Connections {
+    /// Under [`ConnectionLimitPolicy::Queue`], wait for a free slot before accepting
+    async fn queued_slot(&self) -> Option<OwnedSemaphorePermit> {
+        match self.policy {
+            ConnectionLimitPolicy::Queue => {
+                if self.slots.available_permits() == 0 {
+                    tracing::debug!("Connection limit reached; new connections wait in the backlog");
+                }
+                Some(Arc::clone(&self.slots).acquire_owned().await.expect("connection semaphore is never closed"))
+            }
+            ConnectionLimitPolicy::Refuse => None,
+        }
+    }
 
-        let json = serde_json::to_string(&response)?;
-        writer.write_all(json.as_bytes()).await?;
-        writer.write_all(b"\n").await?;
+    /// Serve an accepted connection in its own task, or refuse it if no slot is free
+    ///
+    /// `peer` is recorded in the audit log for every request on the connection.
+    fn spawn(self: &Arc<Self>, stream: Connection, peer: Option<PeerCredentials>, slot: Option<OwnedSemaphorePermit>) {
+        let slot = match slot.or_else(|| Arc::clone(&self.slots).try_acquire_owned().ok()) {
+            Some(slot) => slot,
+            None => {
+                let refused = self.handler.metrics().connection_refused();
+                tracing::warn!("Connection limit reached; refused connection ({} refused so far)", refused);
+                let response = Response::failure(
+                    ErrorCode::TooManyConnections,
+                    "Too many connections; try again later".to_string(),
+                );
+                self.tasks.spawn(refuse_connection(stream, response));
+                return;
+            }
+        };
 
-        line.clear();
+        let connections = Arc::clone(self);
+        self.tasks.spawn(async move {
+            let _slot = slot;
+            let _gauge = connections.handler.metrics().connection_opened();
+            tokio::select! {
+                result = handle_connection(stream, peer, Arc::clone(&connections)) => {
+                    if let Err(e) = result {
+                        tracing::error!("Connection handler error: {}", e);
+                    }
+                }
+                _ = connections.abandon.cancelled() => {}
+            }
+        });
     }
 
-    Ok(())
+    /// Complete a TLS handshake in its own task, then serve the connection
+    ///
+    /// Clients that fail the handshake, e.g. without a certificate signed by
+    /// the client CA, or that take longer than [`TLS_HANDSHAKE_TIMEOUT`], are
+    /// dropped without a response.
+    fn accept_tls(self: &Arc<Self>, acceptor: TlsAcceptor, stream: TcpStream, addr: SocketAddr, slot: Option<OwnedSemaphorePermit>) {
+        let connections = Arc::clone(self);
+        self.tasks.spawn(async move {
+            let handshake = tokio::select! {
+                handshake = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)) => handshake,
+                _ = connections.shutdown.cancelled() => return,
+            };
+            match handshake {
+                Ok(Ok(stream)) => connections.spawn(Box::new(stream), None, slot),
+                Ok(Err(e)) => tracing::warn!("TLS handshake with {} failed: {}", addr, e),
+                Err(_) => tracing::warn!("TLS handshake with {} timed out after {:?}", addr, TLS_HANDSHAKE_TIMEOUT),
+            }
+        });
+    }
+
+    /// Serve the stdio client in its own task, starting shutdown once stdin is closed
+    ///
+    /// The client is not subject to the connection limit.
+    fn serve_stdio(self: &Arc<Self>, stream: Connection) {
+        let connections = Arc::clone(self);
+        self.tasks.spawn(async move {
+            let _gauge = connections.handler.metrics().connection_opened();
+            tokio::select! {
+                result = handle_connection(stream, None, Arc::clone(&connections)) => {
+                    if let Err(e) = result {
+                        tracing::error!("Stdio handler error: {}", e);
+                    }
+                    if !connections.shutdown.is_cancelled() {
+                        tracing::info!("Stdin closed, shutting down");
+                        connections.shutdown.cancel();
+                    }
+                }
+                _ = connections.abandon.cancelled() => {}
+            }
+        });
+    }
+
+    /// Turn away a Unix socket client that is not on the peer allow-list
+    fn refuse_unauthorized<S>(&self, stream: S, peer: Option<PeerCredentials>)
+    where
+        S: AsyncWrite + Unpin + Send + 'static,
+    {
+        let response = match peer {
+            Some(peer) => {
+                tracing::warn!(peer_uid = peer.uid, peer_gid = peer.gid, peer_pid = peer.pid, "Refused connection from unauthorized peer");
+                Response::failure(
+                    ErrorCode::Unauthorized,
+                    format!("UID {} is not allowed to use this daemon", peer.uid),
+                )
+            }
+            None => {
+                tracing::warn!("Refused connection from peer with unknown credentials");
+                Response::failure(ErrorCode::Unauthorized, "Peer credentials are unavailable".to_string())
+            }
+        };
+        self.tasks.spawn(refuse_connection(stream, self.rejected(response)));
+    }
+
+    /// Parse a request body, answering anything that is not a valid request with `invalid_request`
+    ///
+    /// The response is boxed, as `Response` is too large to return by value in an `Err`.
+    fn parse(&self, body: &[u8]) -> Result<Request, Box<Response>> {
+        serde_json::from_slice::<Request>(body).map_err(|e| {
+            let message = match std::str::from_utf8(body) {
+                Ok(_) => format!("Invalid request: {}", e),
+                Err(utf8) => format!("Invalid request: not UTF-8 ({})", utf8),
+            };
+            Box::new(self.rejected(Response::failure(ErrorCode::InvalidRequest, message)))
+        })
+    }
+
+    /// Inflate a compressed frame body, answering a corrupt or oversized one with an error
+    ///
+    /// A plain JSON body is returned as it is. The response is boxed, like
+    /// [`parse`](Self::parse)'s.
+    fn inflate<'a>(&self, frame: &'a [u8]) -> Result<Cow<'a, [u8]>, Box<Response>> {
+        if !is_compressed(frame) {
+            return Ok(Cow::Borrowed(frame));
+        }
+        decompress(frame, self.max_request_bytes).map(Cow::Owned).map_err(|e| {
+            Box::new(self.rejected(match e {
+                FrameError::FrameTooLarge { max, .. } => payload_too_large(max),
+                FrameError::Io(e) => {
+                    Response::failure(ErrorCode::InvalidRequest, format!("Invalid request: corrupt gzip body ({})", e))
+                }
+            }))
+        })
+    }
+
+    /// Handle one request
+    ///
+    /// Waits for an in-flight slot first, so at most the configured number of
+    /// requests are handled at once across all connections.
+    async fn handle(&self, request: Request, peer: Option<PeerCredentials>) -> Response {
+        let _permit = self.in_flight_permit().await;
+        self.handler.handle_from(request, peer).await
+    }
+
+    /// Serialize a response, on the blocking pool when it carries a large payload
+    ///
+    /// Encoding a multi-megabyte envelope takes long enough to hold up every
+    /// other connection served by the same worker thread.
+    async fn encode(&self, response: Response, compressed: bool) -> Result<Vec<u8>> {
+        let size = response.payload_len();
+        match self.handler.offload(size, move || encode_response(&response, compressed)).await {
+            Ok(body) => body,
+            Err(failed) => encode_response(&failed, compressed),
+        }
+    }
+
+    /// Handle a streaming request, whose input and output frames follow it on `frames`
+    async fn stream<T>(
+        &self,
+        request: Request,
+        peer: Option<PeerCredentials>,
+        frames: &mut T,
+    ) -> Result<Response, FrameError>
+    where
+        T: futures::Stream<Item = Result<BytesMut, FrameError>> + futures::Sink<Vec<u8>, Error = FrameError> + Unpin,
+    {
+        let _permit = self.in_flight_permit().await;
+        self.handler.handle_stream(request, peer, frames).await
+    }
+
+    async fn in_flight_permit(&self) -> SemaphorePermit<'_> {
+        if self.in_flight.available_permits() == 0 {
+            tracing::debug!("In-flight request limit reached; request waiting");
+        }
+        self.in_flight.acquire().await.expect("in-flight semaphore is never closed")
+    }
+
+    /// Count an error response produced before the request reached the handler
+    fn rejected(&self, response: Response) -> Response {
+        self.handler.metrics().record_rejected(&response);
+        response
+    }
+}
+
+/// Send a client `response` and close its connection, e.g. over the connection limit
+///
+/// The refusal is a newline-delimited JSON error whatever framing the client
+/// meant to use, since nothing has been read from it.
+async fn refuse_connection<S>(mut stream: S, response: Response)
+where
+    S: AsyncWrite + Unpin,
+{
+    let Ok(mut json) = serde_json::to_vec(&response) else {
+        return;
+    };
+    json.push(b'\n');
+    if let Err(e) = stream.write_all(&json).await {
+        tracing::debug!("Failed to send refusal: {}", e);
+    }
+    stream.shutdown().await.ok();
+}
+
+/// Serve one connection, choosing the framing from its first byte
+///
+/// A connection starting with `{` uses legacy newline-delimited JSON; anything
+/// else is treated as length-prefixed frames (see [`FrameCodec`]). Requests
+/// longer than the configured maximum are refused in either mode.
+async fn handle_connection<S>(stream: S, peer: Option<PeerCredentials>, connections: Arc<Connections>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = BufReader::new(stream);
+    let first_byte = tokio::select! {
+        biased;
+        _ = connections.shutdown.cancelled() => return Ok(()),
+        buf = stream.fill_buf() => match buf?.first() {
+            Some(byte) => *byte,
+            None => return Ok(()),
+        },
+    };
+
+    if first_byte == b'{' {
+        handle_lines(stream, peer, &connections).await
+    } else {
+        tracing::debug!("Using length-prefixed framing");
+        handle_frames(stream, peer, &connections).await
+    }
+}
+
+/// Serve newline-delimited JSON
+///
+/// Requests with an `id` are handled concurrently, up to
+/// [`MAX_PIPELINED_REQUESTS`] at a time, and answered as they finish, so a
+/// slow request does not hold up the ones behind it. A request without an id
+/// waits for those to be answered and is then handled on its own, so clients
+/// that send no ids get their responses in order.
+///
+/// A request pretty-printed over several lines is read until its JSON value
+/// is complete, see [`read_bounded_request`].
+///
+/// An oversized line is answered with `payload_too_large` and skipped up to its
+/// newline, so the connection stays usable for the requests that follow.
+async fn handle_lines<S>(stream: S, peer: Option<PeerCredentials>, connections: &Connections) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let max_request_bytes = connections.max_request_bytes;
+    let (reader, mut writer) = tokio::io::split(stream);
+
+    // Reading through a stream keeps a partly read line when a response
+    // completes first, so the read can be raced against the pending requests
+    let lines = futures::stream::unfold(BufReader::new(reader), move |mut reader| async move {
+        let mut line = Vec::new();
+        match read_bounded_request(&mut reader, &mut line, max_request_bytes).await {
+            Ok(LineRead::Eof) => None,
+            read => Some((read.map(|read| (read, line)), reader)),
+        }
+    });
+    let mut lines = std::pin::pin!(lines);
+    let mut pending = FuturesUnordered::new();
+
+    loop {
+        // Between requests, shutdown closes the connection
+        let read = tokio::select! {
+            biased;
+            Some(response) = pending.next() => {
+                write_line(&mut writer, connections, response).await?;
+                continue;
+            }
+            _ = connections.shutdown.cancelled() => break,
+            read = lines.next(), if pending.len() < MAX_PIPELINED_REQUESTS => read,
+        };
+        let Some(read) = read else {
+            break;
+        };
+        let parsed = match read? {
+            (LineRead::Line, line) => connections.parse(&line),
+            // A last request sent without its newline is still answered;
+            // anything else is what was left when the client went away
+            (LineRead::Partial, line) => match serde_json::from_slice::<Request>(&line) {
+                Ok(request) => Ok(request),
+                Err(_) => {
+                    tracing::warn!("Connection closed partway through a request; discarded {} bytes", line.len());
+                    break;
+                }
+            },
+            (LineRead::TooLong | LineRead::Eof, _) => {
+                tracing::warn!("Skipped request line over the {} byte limit", max_request_bytes);
+                Err(Box::new(connections.rejected(payload_too_large(max_request_bytes))))
+            }
+        };
+
+        let response = match parsed {
+            Ok(request) if request.id.is_some() => {
+                pending.push(connections.handle(request, peer));
+                continue;
+            }
+            Ok(request) => {
+                while let Some(response) = pending.next().await {
+                    write_line(&mut writer, connections, response).await?;
+                }
+                connections.handle(request, peer).await
+            }
+            Err(response) => *response,
+        };
+        write_line(&mut writer, connections, response).await?;
+    }
+
+    // Requests already started are still answered
+    while let Some(response) = pending.next().await {
+        write_line(&mut writer, connections, response).await?;
+    }
+    Ok(())
+}
+
+async fn write_line<W>(writer: &mut W, connections: &Connections, response: Response) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut json = connections.encode(response, false).await?;
+    json.push(b'\n');
+    writer.write_all(&json).await?;
+    Ok(())
+}
+
+/// Outcome of [`read_bounded_line`]
+#[derive(Debug, PartialEq)]
+enum LineRead {
+    /// A complete line (without its newline) is in the buffer
+    Line,
+    /// The stream ended partway through a line, whose bytes are in the buffer
+    Partial,
+    /// The line exceeded the limit and was discarded through its newline
+    TooLong,
+    /// The stream ended before any bytes of a new line
+    Eof,
+}
+
+/// Read one line into `line`, holding at most `max` bytes of it in memory
+///
+/// Bytes are split on `\n` without being decoded, so invalid UTF-8 is left
+/// for the JSON parser to reject. Once a line grows past `max`, the buffered
+/// part is dropped and the rest is consumed from the reader and discarded
+/// until the next newline (or end of stream).
+async fn read_bounded_line<R>(reader: &mut R, line: &mut Vec<u8>, max: usize) -> std::io::Result<LineRead>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut too_long = false;
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            return Ok(match (too_long, line.is_empty()) {
+                (true, _) => LineRead::TooLong,
+                (false, true) => LineRead::Eof,
+                (false, false) => LineRead::Partial,
+            });
+        }
+
+        let (chunk, found_newline) = match available.iter().position(|&b| b == b'\n') {
+            Some(end) => (&available[..end], true),
+            None => (available, false),
+        };
+        let used = chunk.len() + usize::from(found_newline);
+
+        if !too_long && line.len() + chunk.len() > max {
+            too_long = true;
+            *line = Vec::new();
+        }
+        if !too_long {
+            line.extend_from_slice(chunk);
+        }
+        reader.consume(used);
+
+        if found_newline {
+            return Ok(if too_long { LineRead::TooLong } else { LineRead::Line });
+        }
+    }
+}
+
+/// Read one request into `request`, holding at most `max` bytes of it in memory
+///
+/// Usually a request is one line. A line that ends in `}` is taken as it is,
+/// so newline-delimited JSON costs nothing extra; otherwise lines are added
+/// until they hold a complete JSON value (or one that can never complete,
+/// which is left for the parser to reject), letting clients send requests
+/// pretty-printed. The limit applies to the request as a whole.
+async fn read_bounded_request<R>(reader: &mut R, request: &mut Vec<u8>, max: usize) -> std::io::Result<LineRead>
+where
+    R: AsyncBufRead + Unpin,
+{
+    loop {
+        let mut line = Vec::new();
+        let read = read_bounded_line(reader, &mut line, max.saturating_sub(request.len() + 1)).await?;
+        match read {
+            LineRead::Line | LineRead::Partial => {}
+            LineRead::TooLong => {
+                *request = Vec::new();
+                return Ok(LineRead::TooLong);
+            }
+            LineRead::Eof if request.is_empty() => return Ok(LineRead::Eof),
+            LineRead::Eof => return Ok(LineRead::Partial),
+        }
+
+        if request.is_empty() {
+            *request = line;
+            if request.iter().rev().find(|b| !b.is_ascii_whitespace()) == Some(&b'}') {
+                return Ok(read);
+            }
+        } else {
+            request.push(b'\n');
+            request.extend_from_slice(&line);
+        }
+        if read == LineRead::Partial || !is_incomplete_json(request) {
+            return Ok(read);
+        }
+    }
+}
+
+/// Whether `data` is the start of a JSON value that more input could complete
+fn is_incomplete_json(data: &[u8]) -> bool {
+    let mut values = serde_json::Deserializer::from_slice(data).into_iter::<serde::de::IgnoredAny>();
+    matches!(values.next(), Some(Err(e)) if e.is_eof())
+}
+
+/// Largest stream chunk whose output frames fit under the frame limit
+fn stream_chunk_size(max_frame_len: usize) -> usize {
+    DEFAULT_CHUNK_SIZE.min(max_frame_len.saturating_sub(STREAM_FRAME_OVERHEAD))
+}
+
+fn payload_too_large(max: usize) -> Response {
+    Response::failure(
+        ErrorCode::PayloadTooLarge,
+        format!("Request exceeds the {} byte limit", max),
+    )
+}
+
+/// Serve length-prefixed frames
+///
+/// Requests with an `id` are handled concurrently and answered as they
+/// finish, as in [`handle_lines`]. A streaming request, like a request
+/// without an id, waits for those first, since its frames need the
+/// connection to themselves.
+///
+/// A request body may be gzip-compressed, recognised by its
+/// [`GZIP_MAGIC`](crate::codec::GZIP_MAGIC), and its response is then
+/// compressed too. The request limit applies to the inflated body. Raw stream
+/// frames are never compressed.
+///
+/// An oversized frame is answered with `payload_too_large` and the connection
+/// is closed: its body is never read, so the stream cannot be resynchronised.
+/// Streaming requests are followed by raw input and output frames, see
+/// [`RequestHandler::handle_stream`].
+async fn handle_frames<S>(stream: S, peer: Option<PeerCredentials>, connections: &Connections) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut framed = Framed::new(stream, FrameCodec::new(connections.max_request_bytes));
+    let mut pending = FuturesUnordered::new();
+
+    loop {
+        // Between requests, shutdown closes the connection
+        let frame = tokio::select! {
+            biased;
+            Some((response, compressed)) = pending.next() => {
+                send_response(&mut framed, connections, response, compressed).await?;
+                continue;
+            }
+            _ = connections.shutdown.cancelled() => break,
+            frame = framed.next(), if pending.len() < MAX_PIPELINED_REQUESTS => frame,
+        };
+        let Some(frame) = frame else {
+            break;
+        };
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => return refuse_frame(&mut framed, connections, e).await,
+        };
+
+        let compressed = is_compressed(&frame);
+        let request = match connections.inflate(&frame).and_then(|body| connections.parse(&body)) {
+            Ok(request) if request.id.is_some() && !request.operation.is_streaming() => {
+                pending.push(async move { (connections.handle(request, peer).await, compressed) });
+                continue;
+            }
+            Ok(request) => request,
+            Err(response) => {
+                send_response(&mut framed, connections, *response, compressed).await?;
+                continue;
+            }
+        };
+
+        while let Some((response, compressed)) = pending.next().await {
+            send_response(&mut framed, connections, response, compressed).await?;
+        }
+        let response = if request.operation.is_streaming() {
+            match connections.stream(request, peer, &mut framed).await {
+                Ok(response) => response,
+                Err(e) => return refuse_frame(&mut framed, connections, e).await,
+            }
+        } else {
+            connections.handle(request, peer).await
+        };
+        send_response(&mut framed, connections, response, compressed).await?;
+    }
+
+    // Requests already started are still answered
+    while let Some((response, compressed)) = pending.next().await {
+        send_response(&mut framed, connections, response, compressed).await?;
+    }
+    Ok(())
+}
+
+/// Send a JSON response frame, gzipped if its request was
+async fn send_response<S>(
+    framed: &mut Framed<S, FrameCodec>,
+    connections: &Connections,
+    response: Response,
+    compressed: bool,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    framed.send(connections.encode(response, compressed).await?).await?;
+    Ok(())
+}
+
+/// Serialize `response` as JSON, gzipped if `compressed`
+fn encode_response(response: &Response, compressed: bool) -> Result<Vec<u8>> {
+    let body = serde_json::to_vec(response)?;
+    Ok(if compressed { compress(&body) } else { body })
+}
+
+/// End a framed connection after a frame could not be read
+async fn refuse_frame<S>(framed: &mut Framed<S, FrameCodec>, connections: &Connections, error: FrameError) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match error {
+        FrameError::FrameTooLarge { len, max } => {
+            framed.send(serde_json::to_vec(&connections.rejected(payload_too_large(max)))?).await?;
+            bail!("Closing connection after oversized frame ({} bytes)", len);
+        }
+        FrameError::Io(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ResponseResult;
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::net::TcpStream;
+    use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    use tokio_rustls::TlsConnector;
+    use tokio_util::codec::{FramedRead, FramedWrite};
+    use violet_core::{Algorithm, StreamEncryptor};
+
+    async fn send<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut BufReader<S>, request: &str) -> Response {
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        stream.get_mut().write_all(b"\n").await.unwrap();
+
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn test_tcp_roundtrip() {
+        let mut server = mockito::Server::new();
+        let _mock = server
+            .mock("GET", "/v1/keys/tcp-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"tcp-key","key":"{}"}}"#, "55".repeat(32)))
+            .create();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), server.url())
+                .bind()
+                .await
+                .unwrap();
+            let addr = daemon.tcp_addr().unwrap();
+            tokio::spawn(daemon.serve());
+
+            let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+            let response = send(
+                &mut stream,
+                r#"{"operation":"encrypt","data":{"plaintext":"SGVsbG8=","keyId":"tcp-key"}}"#,
+            )
+            .await;
+            let envelope = match response.result {
+                Some(ResponseResult::Encrypt { envelope }) => envelope,
+                other => panic!("expected envelope, got {:?} ({:?})", other, response.error),
+            };
+
+            let decrypt = serde_json::json!({
+                "operation": "decrypt",
+                "data": { "envelope": envelope },
+            });
+            let response = send(&mut stream, &decrypt.to_string()).await;
+            match response.result {
+                Some(ResponseResult::Decrypt { plaintext }) => assert_eq!(plaintext, "SGVsbG8="),
+                other => panic!("expected plaintext, got {:?} ({:?})", other, response.error),
+            }
+        });
+    }
+
+    async fn send_frame(stream: &mut TcpStream, body: &[u8]) -> Response {
+        serde_json::from_slice(&exchange_frame(stream, body).await).unwrap()
+    }
+
+    /// Send one frame and return the body of the frame answering it
+    async fn exchange_frame(stream: &mut TcpStream, body: &[u8]) -> Vec<u8> {
+        use tokio::io::AsyncReadExt;
+
+        stream.write_all(&(body.len() as u32).to_be_bytes()).await.unwrap();
+        stream.write_all(body).await.unwrap();
+
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await.unwrap();
+        let mut body = vec![0u8; u32::from_be_bytes(header) as usize];
+        stream.read_exact(&mut body).await.unwrap();
+        body
+    }
+
+    #[test]
+    fn test_stats_and_prometheus_metrics() {
+        let mut server = mockito::Server::new();
+        let _mock = server
+            .mock("GET", "/v1/keys/stats-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"stats-key","key":"{}"}}"#, "99".repeat(32)))
+            .create();
+        let _health = server.mock("HEAD", "/v1/keys/").with_status(200).create();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), server.url())
+                .with_metrics_addr("127.0.0.1:0".parse().unwrap())
+                .bind()
+                .await
+                .unwrap();
+            let addr = daemon.tcp_addr().unwrap();
+            let metrics_addr = daemon.metrics_addr().unwrap();
+            tokio::spawn(daemon.serve());
+
+            let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            let encrypt = r#"{"operation":"encrypt","data":{"plaintext":"SGVsbG8=","keyId":"stats-key"}}"#;
+            send(&mut stream, encrypt).await;
+            let envelope = match send(&mut stream, encrypt).await.result {
+                Some(ResponseResult::Encrypt { envelope }) => envelope,
+                other => panic!("expected envelope, got {:?}", other),
+            };
+            let decrypt = serde_json::json!({ "operation": "decrypt", "data": { "envelope": envelope } });
+            assert!(send(&mut stream, &decrypt.to_string()).await.success);
+            let invalid = r#"{"operation":"encrypt","data":{"plaintext":"***","keyId":"stats-key"}}"#;
+            assert!(!send(&mut stream, invalid).await.success);
+            assert!(!send(&mut stream, "not json").await.success);
+
+            let stats = match send(&mut stream, r#"{"operation":"stats"}"#).await.result {
+                Some(ResponseResult::Stats(stats)) => stats,
+                other => panic!("expected stats, got {:?}", other),
+            };
+            assert_eq!(stats.requests["encrypt"], 3);
+            assert_eq!(stats.requests["decrypt"], 1);
+            assert_eq!(stats.requests["stats"], 0, "a stats request is counted once it is answered");
+            // Bad base64 and a line that is not JSON are both invalid requests
+            assert_eq!(stats.errors.get("invalid_request"), Some(&2), "{:?}", stats.errors);
+            assert_eq!(stats.bytes_encrypted, 10);
+            assert_eq!(stats.bytes_decrypted, 5);
+            assert_eq!(stats.kek_cache_misses, 1);
+            assert_eq!(stats.kek_cache_hits, 2);
+            assert_eq!(stats.active_connections, 1);
+            assert_eq!(stats.latency["encrypt"].count, 3);
+
+            let body = scrape(metrics_addr, "GET /metrics").await;
+            assert!(body.starts_with("HTTP/1.1 200 OK\r\n"), "{}", body);
+            for expected in [
+                "violet_requests_total{operation=\"encrypt\",outcome=\"success\"} 2\n",
+                "violet_requests_total{operation=\"encrypt\",outcome=\"error\"} 1\n",
+                "violet_requests_total{operation=\"decrypt\",outcome=\"success\"} 1\n",
+                "violet_requests_total{operation=\"rewrap\",outcome=\"error\"} 0\n",
+                "violet_errors_total{code=\"invalid_request\"} 2\n",
+                "violet_encrypted_bytes_total 10\n",
+                "violet_decrypted_bytes_total 5\n",
+                "violet_kek_cache_hits_total 2\n",
+                "violet_connections_active 1\n",
+                "# TYPE violet_request_duration_seconds histogram\n",
+                "violet_request_duration_seconds_count{operation=\"decrypt\"} 1\n",
+            ] {
+                assert!(body.contains(expected), "missing {:?} in\n{}", expected, body);
+            }
+            // Every sample of a metric carries the same labels
+            for line in body.lines().filter(|line| line.starts_with("violet_requests_total")) {
+                assert!(line.starts_with("violet_requests_total{operation=\""), "{}", line);
+                assert!(line.contains("\",outcome=\""), "{}", line);
+            }
+            for line in body.lines().filter(|line| line.starts_with("violet_errors_total")) {
+                assert!(line.starts_with("violet_errors_total{code=\""), "{}", line);
+            }
+
+            let health = scrape(metrics_addr, "GET /healthz").await;
+            assert!(health.starts_with("HTTP/1.1 200 OK\r\n"), "{}", health);
+            assert!(health.ends_with("\r\n\r\nok\n"), "{}", health);
+
+            // Read-only: nothing but GET, and nothing but the two paths
+            let post = scrape(metrics_addr, "POST /metrics").await;
+            assert!(post.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}", post);
+            assert!(post.contains("\r\nallow: GET\r\n"), "{}", post);
+            let other = scrape(metrics_addr, "GET /admin").await;
+            assert!(other.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", other);
+        });
+    }
+
+    /// Send one HTTP request with `request_line` to the metrics listener and return the whole response
+    async fn scrape(addr: SocketAddr, request_line: &str) -> String {
+        use tokio::io::AsyncReadExt;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("{} HTTP/1.1\r\nHost: localhost\r\n\r\n", request_line);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[test]
+    fn test_healthz_reports_unreachable_keys_server() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), "http://127.0.0.1:9".into())
+                .with_metrics_addr("127.0.0.1:0".parse().unwrap())
+                .bind()
+                .await
+                .unwrap();
+            let metrics_addr = daemon.metrics_addr().unwrap();
+            tokio::spawn(daemon.serve());
+
+            let health = scrape(metrics_addr, "GET /healthz").await;
+            assert!(health.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", health);
+        });
+    }
+
+    #[test]
+    fn test_remote_metrics_requires_allow_remote() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), "http://localhost:8080".into())
+                .with_metrics_addr("0.0.0.0:0".parse().unwrap());
+            let err = server.bind().await.err().expect("non-loopback metrics must be refused");
+            assert!(err.to_string().contains("--allow-remote"), "{}", err);
+
+            let daemon = server.allow_remote(true).bind().await.unwrap();
+            assert!(daemon.metrics_addr().is_some());
+        });
+    }
+
+    #[test]
+    fn test_missing_file_dir_fails_bind() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), "http://localhost:8080".into())
+                .with_allowed_file_dir(dir.path().join("missing"));
+            let err = server.bind().await.err().expect("a missing file directory must be refused");
+            assert!(format!("{:#}", err).contains("allowed file directory"), "{:#}", err);
+
+            let server = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), "http://localhost:8080".into())
+                .with_allowed_file_dir(dir.path());
+            server.bind().await.unwrap();
+        });
+    }
+
+    /// Start a TCP daemon whose Keys server is never reached
+    async fn offline_daemon() -> SocketAddr {
+        let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), "http://127.0.0.1:9".into())
+            .bind()
+            .await
+            .unwrap();
+        let addr = daemon.tcp_addr().unwrap();
+        tokio::spawn(daemon.serve());
+        addr
+    }
+
+    #[test]
+    fn test_framed_roundtrip() {
+        let mut server = mockito::Server::new();
+        let _mock = server
+            .mock("GET", "/v1/keys/framed-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"framed-key","key":"{}"}}"#, "77".repeat(32)))
+            .create();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), server.url())
+                .bind()
+                .await
+                .unwrap();
+            let addr = daemon.tcp_addr().unwrap();
+            tokio::spawn(daemon.serve());
+
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+
+            // Pretty-printed JSON with embedded newlines is fine inside a frame
+            let request = serde_json::to_vec_pretty(&serde_json::json!({
+                "operation": "encrypt",
+                "data": { "plaintext": "SGVsbG8=", "keyId": "framed-key" },
+            }))
+            .unwrap();
+            let response = send_frame(&mut stream, &request).await;
+            let envelope = match response.result {
+                Some(ResponseResult::Encrypt { envelope }) => envelope,
+                other => panic!("expected envelope, got {:?} ({:?})", other, response.error),
+            };
+
+            let request = serde_json::to_vec(&serde_json::json!({
+                "operation": "decrypt",
+                "data": { "envelope": envelope },
+            }))
+            .unwrap();
+            let response = send_frame(&mut stream, &request).await;
+            match response.result {
+                Some(ResponseResult::Decrypt { plaintext }) => assert_eq!(plaintext, "SGVsbG8="),
+                other => panic!("expected plaintext, got {:?} ({:?})", other, response.error),
+            }
+        });
+    }
+
+    #[test]
+    fn test_compressed_frames() {
+        use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+        use crate::codec::{compress, decompress, is_compressed};
+
+        let mut server = mockito::Server::new();
+        let _mock = server
+            .mock("GET", "/v1/keys/zipped-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"zipped-key","key":"{}"}}"#, "66".repeat(32)))
+            .create();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let max_request_bytes = 4 * 1024 * 1024;
+            let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), server.url())
+                .with_max_request_bytes(max_request_bytes)
+                .bind()
+                .await
+                .unwrap();
+            let addr = daemon.tcp_addr().unwrap();
+            tokio::spawn(daemon.serve());
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+
+            // 2 MiB of repetitive plaintext, near 3 MiB of JSON once base64-encoded
+            let plaintext = b"row,of,repetitive,csv,data\n".repeat(80_000);
+            let request = serde_json::to_vec(&serde_json::json!({
+                "id": "big",
+                "operation": "encrypt",
+                "data": { "plaintext": BASE64.encode(&plaintext), "keyId": "zipped-key" },
+            }))
+            .unwrap();
+            let compressed = compress(&request);
+            assert!(compressed.len() < request.len() / 10);
+
+            let body = exchange_frame(&mut stream, &compressed).await;
+            assert!(is_compressed(&body), "response to a compressed request is compressed");
+            let response: Response = serde_json::from_slice(&decompress(&body, usize::MAX).unwrap()).unwrap();
+            assert_eq!(response.id.as_deref(), Some("big"));
+            let envelope = match response.result {
+                Some(ResponseResult::Encrypt { envelope }) => envelope,
+                other => panic!("expected envelope, got {:?} ({:?})", other, response.error),
+            };
+
+            // Plain frames still get plain answers on the same connection
+            let request = serde_json::to_vec(&serde_json::json!({
+                "operation": "decrypt",
+                "data": { "envelope": envelope },
+            }))
+            .unwrap();
+            let body = exchange_frame(&mut stream, &request).await;
+            assert_eq!(body.first(), Some(&b'{'));
+            let response: Response = serde_json::from_slice(&body).unwrap();
+            match response.result {
+                Some(ResponseResult::Decrypt { plaintext: decrypted }) => {
+                    assert_eq!(BASE64.decode(decrypted).unwrap(), plaintext)
+                }
+                other => panic!("expected plaintext, got {:?} ({:?})", other, response.error),
+            }
+
+            // The limit applies to the inflated body, so a small frame cannot
+            // smuggle in an oversized request
+            let bomb = compress(&vec![b' '; max_request_bytes + 1]);
+            let body = exchange_frame(&mut stream, &bomb).await;
+            let response: Response = serde_json::from_slice(&decompress(&body, usize::MAX).unwrap()).unwrap();
+            assert_eq!(response.error_code, Some(ErrorCode::PayloadTooLarge));
+
+            let mut corrupt = compress(br#"{"operation":"hello"}"#);
+            corrupt.truncate(12);
+            let body = exchange_frame(&mut stream, &corrupt).await;
+            let response: Response = serde_json::from_slice(&decompress(&body, usize::MAX).unwrap()).unwrap();
+            assert_eq!(response.error_code, Some(ErrorCode::InvalidRequest));
+            assert!(response.error.unwrap().contains("corrupt gzip body"));
+
+            // Neither error closed the connection
+            let response = send_frame(&mut stream, br#"{"operation":"hello"}"#).await;
+            match response.result {
+                Some(ResponseResult::Hello(info)) => assert_eq!(info.compression, ["gzip"]),
+                other => panic!("expected hello, got {:?}", other),
+            }
+        });
+    }
+
+    /// Send a streaming request followed by `input` in frames of `piece` bytes,
+    /// reading the output frames at the same time
+    async fn stream_through(
+        reader: &mut FramedRead<OwnedReadHalf, FrameCodec>,
+        writer: &mut FramedWrite<OwnedWriteHalf, FrameCodec>,
+        request: serde_json::Value,
+        input: &[u8],
+        piece: usize,
+    ) -> (Vec<u8>, Response) {
+        let send = async {
+            writer.send(serde_json::to_vec(&request).unwrap()).await.unwrap();
+            for data in input.chunks(piece) {
+                writer.send(data.to_vec()).await.unwrap();
+            }
+            writer.send(Vec::new()).await.unwrap();
+        };
+        let receive = async {
+            let mut output = Vec::new();
+            loop {
+                let frame = reader.next().await.unwrap().unwrap();
+                if frame.is_empty() {
+                    break;
+                }
+                output.extend_from_slice(&frame);
+            }
+            let response: Response = serde_json::from_slice(&reader.next().await.unwrap().unwrap()).unwrap();
+            (output, response)
+        };
+        tokio::join!(send, receive).1
+    }
+
+    #[test]
+    fn test_stream_larger_than_request_limit() {
+        let mut server = mockito::Server::new();
+        let _mock = server
+            .mock("GET", "/v1/keys/stream-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"stream-key","key":"{}"}}"#, "55".repeat(32)))
+            .create();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let max_request_bytes = 8 * 1024;
+            let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), server.url())
+                .with_max_request_bytes(max_request_bytes)
+                .bind()
+                .await
+                .unwrap();
+            let addr = daemon.tcp_addr().unwrap();
+            tokio::spawn(daemon.serve());
+
+            let (read_half, write_half) = TcpStream::connect(addr).await.unwrap().into_split();
+            let mut reader = FramedRead::new(read_half, FrameCodec::new(max_request_bytes));
+            let mut writer = FramedWrite::new(write_half, FrameCodec::new(max_request_bytes));
+
+            // 32 times the largest request the daemon accepts
+            let plaintext: Vec<u8> = (0..32 * max_request_bytes).map(|i| (i % 251) as u8).collect();
+            let request = serde_json::json!({ "operation": "encryptStream", "data": { "keyId": "stream-key" } });
+            let (ciphertext, response) =
+                stream_through(&mut reader, &mut writer, request, &plaintext, max_request_bytes).await;
+            let chunk_size = match response.result {
+                Some(ResponseResult::StreamFinished { stream_header, chunks, plaintext_bytes }) => {
+                    assert_eq!(stream_header.key_id, "stream-key");
+                    assert_eq!(plaintext_bytes, plaintext.len() as u64);
+                    assert!(chunks > 32, "{} chunks", chunks);
+                    stream_header.chunk_size
+                }
+                other => panic!("expected stream result, got {:?} ({:?})", other, response.error),
+            };
+            assert!(chunk_size + STREAM_FRAME_OVERHEAD <= max_request_bytes);
+
+            // The output is the ordinary stream format
+            let mut decrypted = Vec::new();
+            StreamEncryptor::new(Algorithm::default())
+                .decrypt_stream(&ciphertext[..], &mut decrypted, &[0x55; 32])
+                .unwrap();
+            assert_eq!(decrypted, plaintext);
+
+            // And decrypts back through the same connection, sent in uneven pieces
+            let request = serde_json::json!({ "operation": "decryptStream" });
+            let (roundtrip, response) = stream_through(&mut reader, &mut writer, request, &ciphertext, 3001).await;
+            assert!(response.success, "{:?}", response.error);
+            assert_eq!(roundtrip, plaintext);
+        });
+    }
+
+    #[test]
+    fn test_failed_stream_leaves_connection_usable() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (read_half, write_half) = TcpStream::connect(offline_daemon().await).await.unwrap().into_split();
+            let mut reader = FramedRead::new(read_half, FrameCodec::default());
+            let mut writer = FramedWrite::new(write_half, FrameCodec::default());
+
+            let request = serde_json::json!({ "operation": "decryptStream" });
+            let (output, response) =
+                stream_through(&mut reader, &mut writer, request, b"not an encrypted stream at all", 4).await;
+            assert!(output.is_empty());
+            assert_eq!(response.error_code, Some(ErrorCode::InvalidRequest));
+
+            // The Keys server is unreachable, so the input is read and dropped
+            let request = serde_json::json!({ "operation": "encryptStream", "data": { "keyId": "k" } });
+            let (output, response) = stream_through(&mut reader, &mut writer, request, &[0u8; 5000], 1000).await;
+            assert!(output.is_empty());
+            assert_eq!(response.error_code, Some(ErrorCode::KeyUnavailable));
+
+            writer.send(br#"{"operation":"hello"}"#.to_vec()).await.unwrap();
+            let response: Response = serde_json::from_slice(&reader.next().await.unwrap().unwrap()).unwrap();
+            assert!(response.success);
+        });
+    }
+
+    #[test]
+    fn test_stream_needs_framing() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let mut stream = BufReader::new(TcpStream::connect(offline_daemon().await).await.unwrap());
+            let response = send(&mut stream, r#"{"operation":"encryptStream","data":{}}"#).await;
+            assert_eq!(response.error_code, Some(ErrorCode::UnsupportedOperation));
+        });
+    }
+
+    #[test]
+    fn test_framed_invalid_body_gets_error_frame() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let mut stream = TcpStream::connect(offline_daemon().await).await.unwrap();
+
+            let response = send_frame(&mut stream, b"not json").await;
+            assert!(!response.success);
+            assert!(response.error.unwrap().contains("Invalid request"));
+        });
+    }
+
+    #[test]
+    fn test_mixed_mode_rejected() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            use tokio::io::AsyncReadExt;
+
+            // A framed connection that switches to a JSON line: the line's first
+            // bytes read as an enormous length, so the daemon reports and closes
+            let mut stream = TcpStream::connect(offline_daemon().await).await.unwrap();
+            let response = send_frame(&mut stream, b"not json").await;
+            assert!(!response.success);
+
+            stream
+                .write_all(b"{\"operation\":\"encrypt\",\"data\":{}}\n")
+                .await
+                .unwrap();
+            let mut header = [0u8; 4];
+            stream.read_exact(&mut header).await.unwrap();
+            let mut body = vec![0u8; u32::from_be_bytes(header) as usize];
+            stream.read_exact(&mut body).await.unwrap();
+            let response: Response = serde_json::from_slice(&body).unwrap();
+            assert_eq!(response.error_code, Some(ErrorCode::PayloadTooLarge));
+            assert!(response.error.unwrap().contains("exceeds"));
+
+            // Closed (or reset, since the rest of the line was never read)
+            let mut rest = [0u8; 1];
+            assert!(matches!(stream.read(&mut rest).await, Ok(0) | Err(_)));
+
+            // A line-mode connection that sends a binary frame gets a JSON error line
+            let mut stream = BufReader::new(TcpStream::connect(offline_daemon().await).await.unwrap());
+            let response = send(&mut stream, r#"{"operation":"bogus"}"#).await;
+            assert!(!response.success);
+
+            let mut frame = 2u32.to_be_bytes().to_vec();
+            frame.extend_from_slice(b"{}\n");
+            stream.get_mut().write_all(&frame).await.unwrap();
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            assert!(response.error.unwrap().contains("Invalid request"));
+        });
+    }
+
+    #[test]
+    fn test_read_bounded_line_discards_oversized_line() {
+        use tokio::io::AsyncReadExt;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            // 64 MiB without a newline, then two ordinary lines
+            let input = tokio::io::repeat(b'x')
+                .take(64 * 1024 * 1024)
+                .chain(&b"\nnext\nlast"[..]);
+            let mut reader = BufReader::new(input);
+            let mut line = Vec::new();
+
+            let read = read_bounded_line(&mut reader, &mut line, 1024).await.unwrap();
+            assert_eq!(read, LineRead::TooLong);
+            assert!(line.capacity() <= 2048, "buffered {} bytes", line.capacity());
+
+            line.clear();
+            assert_eq!(read_bounded_line(&mut reader, &mut line, 1024).await.unwrap(), LineRead::Line);
+            assert_eq!(line, b"next");
+
+            line.clear();
+            assert_eq!(read_bounded_line(&mut reader, &mut line, 1024).await.unwrap(), LineRead::Partial);
+            assert_eq!(line, b"last");
+
+            line.clear();
+            assert_eq!(read_bounded_line(&mut reader, &mut line, 1024).await.unwrap(), LineRead::Eof);
+        });
+    }
+
+    #[test]
+    fn test_read_bounded_request_joins_pretty_printed_lines() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let input = b"{\"operation\":\"hello\"}\n{\n  \"operation\": \"ping\",\n  \"data\": {}\n}\nnot json\n{\n";
+            let mut reader = BufReader::new(&input[..]);
+            let mut request = Vec::new();
+
+            assert_eq!(read_bounded_request(&mut reader, &mut request, 1024).await.unwrap(), LineRead::Line);
+            assert_eq!(request, br#"{"operation":"hello"}"#);
+
+            request.clear();
+            assert_eq!(read_bounded_request(&mut reader, &mut request, 1024).await.unwrap(), LineRead::Line);
+            assert_eq!(request, b"{\n  \"operation\": \"ping\",\n  \"data\": {}\n}");
+
+            // A line that can never become JSON is not held back
+            request.clear();
+            assert_eq!(read_bounded_request(&mut reader, &mut request, 1024).await.unwrap(), LineRead::Line);
+            assert_eq!(request, b"not json");
+
+            request.clear();
+            assert_eq!(read_bounded_request(&mut reader, &mut request, 1024).await.unwrap(), LineRead::Partial);
+            assert_eq!(request, b"{");
+
+            // The limit counts every line of the request
+            let lines = format!("{{\n\"operation\":\n\"{}\"}}\n", "x".repeat(1020));
+            let mut reader = BufReader::new(lines.as_bytes());
+            request.clear();
+            assert_eq!(read_bounded_request(&mut reader, &mut request, 1024).await.unwrap(), LineRead::TooLong);
+        });
+    }
+
+    #[test]
+    fn test_pretty_printed_request_is_answered() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let addr = offline_daemon().await;
+            let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+            let pretty = serde_json::to_string_pretty(&serde_json::json!({
+                "id": "pretty",
+                "operation": "hello",
+            }))
+            .unwrap();
+            let response = send(&mut stream, &pretty).await;
+            assert!(response.success, "{:?}", response.error);
+            assert_eq!(response.id.as_deref(), Some("pretty"));
+
+            // Newline-delimited requests on the same connection are unaffected
+            let response = send(&mut stream, r#"{"operation":"hello"}"#).await;
+            assert!(response.success, "{:?}", response.error);
+        });
+    }
+
+    #[test]
+    fn test_oversized_line_rejected_and_connection_recovers() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), "http://127.0.0.1:9".into())
+                .with_max_request_bytes(1024)
+                .bind()
+                .await
+                .unwrap();
+            let addr = daemon.tcp_addr().unwrap();
+            tokio::spawn(daemon.serve());
+
+            // A 4 MiB line: the daemon discards it as it arrives instead of buffering it
+            let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            let mut oversized = br#"{"operation":"encrypt","data":{"plaintext":""#.to_vec();
+            oversized.resize(4 * 1024 * 1024, b'A');
+            oversized.extend_from_slice(br#""}}"#);
+            let response = send(&mut stream, std::str::from_utf8(&oversized).unwrap()).await;
+            assert!(!response.success);
+            assert_eq!(response.error_code, Some(ErrorCode::PayloadTooLarge));
+
+            // The same connection carries on with the next request
+            let response = send(&mut stream, r#"{"operation":"hello"}"#).await;
+            assert!(response.success, "{:?}", response.error);
+
+            // And the daemon still accepts new connections
+            let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            let response = send(&mut stream, r#"{"operation":"hello"}"#).await;
+            assert!(response.success, "{:?}", response.error);
+        });
+    }
+
+    #[test]
+    fn test_invalid_utf8_line_rejected_and_connection_recovers() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let addr = offline_daemon().await;
+            let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+            // Valid, invalid UTF-8 and valid again, in one write
+            let mut input = br#"{"id":"1","operation":"hello"}"#.to_vec();
+            input.extend_from_slice(b"\n{\"operation\":\"hel\xff\xfelo\"}\n");
+            input.extend_from_slice(br#"{"id":"3","operation":"hello"}"#);
+            input.push(b'\n');
+            stream.get_mut().write_all(&input).await.unwrap();
+
+            let mut responses = Vec::new();
+            for _ in 0..3 {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                responses.push(serde_json::from_str::<Response>(&line).unwrap());
+            }
+            let invalid = responses.iter().find(|r| r.id.is_none()).unwrap();
+            assert!(!invalid.success);
+            assert_eq!(invalid.error_code, Some(ErrorCode::InvalidRequest));
+            assert!(invalid.error.as_deref().unwrap().contains("not UTF-8"), "{:?}", invalid.error);
+            let mut ids: Vec<_> = responses.iter().filter_map(|r| r.id.as_deref()).collect();
+            ids.sort();
+            assert_eq!(ids, ["1", "3"]);
+            assert!(responses.iter().filter(|r| r.id.is_some()).all(|r| r.success));
+        });
+    }
+
+    #[test]
+    fn test_request_without_trailing_newline_is_answered() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let addr = offline_daemon().await;
+
+            // The last request has no newline before the client stops writing
+            let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            stream.get_mut().write_all(b"{\"operation\":\"hello\"}\n{\"operation\":\"ping\"}").await.unwrap();
+            stream.get_mut().shutdown().await.unwrap();
+
+            let mut lines = Vec::new();
+            let mut line = String::new();
+            while stream.read_line(&mut line).await.unwrap() > 0 {
+                lines.push(serde_json::from_str::<Response>(&line).unwrap());
+                line.clear();
+            }
+            assert_eq!(lines.len(), 2);
+            assert!(lines.iter().all(|response| response.success));
+
+            // A truncated request is discarded and the connection closed without an answer
+            let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            stream.get_mut().write_all(b"{\"operation\":\"hello\"}\n{\"operation\":\"he").await.unwrap();
+            stream.get_mut().shutdown().await.unwrap();
+
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            assert!(serde_json::from_str::<Response>(&line).unwrap().success);
+            line.clear();
+            assert_eq!(stream.read_line(&mut line).await.unwrap(), 0);
+
+            // The daemon carries on serving
+            let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            assert!(send(&mut stream, r#"{"operation":"hello"}"#).await.success);
+        });
+    }
+
+    /// Start a TCP daemon that serves one connection at a time
+    async fn single_connection_daemon(policy: ConnectionLimitPolicy) -> SocketAddr {
+        let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), "http://127.0.0.1:9".into())
+            .with_max_connections(1, policy)
+            .bind()
+            .await
+            .unwrap();
+        let addr = daemon.tcp_addr().unwrap();
+        tokio::spawn(daemon.serve());
+        addr
+    }
+
+    #[test]
+    fn test_connection_limit_refuses_extra_connection() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let addr = single_connection_daemon(ConnectionLimitPolicy::Refuse).await;
+            let mut first = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            assert!(send(&mut first, r#"{"operation":"hello"}"#).await.success);
+
+            // The second connection gets one error line and is closed
+            let mut second = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            let mut line = String::new();
+            second.read_line(&mut line).await.unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            assert!(!response.success);
+            assert_eq!(response.error_code, Some(ErrorCode::TooManyConnections));
+            line.clear();
+            assert_eq!(second.read_line(&mut line).await.unwrap(), 0);
+
+            // The first connection is unaffected, and its slot is reused once it closes
+            assert!(send(&mut first, r#"{"operation":"hello"}"#).await.success);
+            drop(first);
+            for attempt in 0.. {
+                assert!(attempt < 100, "slot was not released");
+                let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+                let _ = stream.get_mut().write_all(b"{\"operation\":\"hello\"}\n").await;
+                let mut line = String::new();
+                let _ = stream.read_line(&mut line).await;
+                if serde_json::from_str::<Response>(&line).is_ok_and(|response| response.success) {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        });
+    }
+
+    #[test]
+    fn test_connection_limit_queues_extra_connection() {
+        use std::time::Duration;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let addr = single_connection_daemon(ConnectionLimitPolicy::Queue).await;
+            let mut first = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            assert!(send(&mut first, r#"{"operation":"hello"}"#).await.success);
+
+            // The second connection waits in the backlog without an answer...
+            let mut second = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            second.get_mut().write_all(b"{\"operation\":\"hello\"}\n").await.unwrap();
+            let mut line = String::new();
+            let early = tokio::time::timeout(Duration::from_millis(200), second.read_line(&mut line)).await;
+            assert!(early.is_err(), "queued connection answered early: {}", line);
+
+            // ...and is served once the first closes
+            drop(first);
+            tokio::time::timeout(Duration::from_secs(5), second.read_line(&mut line))
+                .await
+                .expect("queued connection was never served")
+                .unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            assert!(response.success, "{:?}", response.error);
+        });
+    }
+
+    #[test]
+    fn test_ping_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("violet.sock");
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            // Nothing listens on the Keys server address, so the upstream is down
+            let daemon = DaemonServer::new(socket_path.display().to_string(), "http://127.0.0.1:9".into())
+                .bind()
+                .await
+                .unwrap();
+            tokio::spawn(daemon.serve());
+
+            let mut stream = BufReader::new(tokio::net::UnixStream::connect(&socket_path).await.unwrap());
+            stream.get_mut().write_all(b"{\"operation\":\"ping\"}\n").await.unwrap();
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+
+            assert!(response.success, "{:?}", response.error);
+            match response.result {
+                Some(ResponseResult::Pong { keys_server_ok, .. }) => assert!(!keys_server_ok),
+                other => panic!("expected pong, got {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn test_socket_removed_after_panic_and_restart_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("violet.sock");
+        let socket = socket_path.display().to_string();
+
+        let crashed = std::thread::spawn({
+            let socket = socket.clone();
+            move || {
+                let runtime = tokio::runtime::Runtime::new().unwrap();
+                runtime.block_on(async {
+                    let daemon = DaemonServer::new(socket, "http://127.0.0.1:9".into())
+                        .bind()
+                        .await
+                        .unwrap();
+                    tokio::select! {
+                        _ = daemon.serve() => {}
+                        _ = async { panic!("simulated handler crash") } => {}
+                    }
+                });
+            }
+        })
+        .join();
+        assert!(crashed.is_err());
+        assert!(!socket_path.exists(), "socket file left behind after panic");
+
+        // A new daemon binds the same path and serves requests
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::new(socket.clone(), "http://127.0.0.1:9".into())
+                .bind()
+                .await
+                .unwrap();
+            tokio::spawn(daemon.serve());
+
+            let mut stream = BufReader::new(tokio::net::UnixStream::connect(&socket_path).await.unwrap());
+            stream.get_mut().write_all(b"{\"operation\":\"hello\"}\n").await.unwrap();
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            assert!(response.success);
+        });
+    }
+
+    #[test]
+    fn test_second_daemon_on_same_socket_fails_fast() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("violet.sock");
+        let socket = socket_path.display().to_string();
+        let lock_path = dir.path().join("violet.sock.lock");
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let first = DaemonServer::new(socket.clone(), "http://127.0.0.1:9".into())
+                .bind()
+                .await
+                .unwrap();
+            let pid = std::process::id().to_string();
+            assert_eq!(std::fs::read_to_string(&lock_path).unwrap().trim(), pid);
+            tokio::spawn(first.serve());
+
+            let second = DaemonServer::new(socket.clone(), "http://127.0.0.1:9".into()).bind().await;
+            let error = match second {
+                Ok(_) => panic!("second daemon bound a socket already in use"),
+                Err(e) => e.to_string(),
+            };
+            assert!(error.contains(&format!("PID {}", pid)), "{}", error);
+
+            // The first daemon keeps its socket and lock
+            assert!(send_unix(&socket_path, r#"{"operation":"hello"}"#).await.success);
+            assert_eq!(std::fs::read_to_string(&lock_path).unwrap().trim(), pid);
+
+            // Forcing takes the socket over
+            let forced = DaemonServer::new(socket.clone(), "http://127.0.0.1:9".into())
+                .force_start(true)
+                .bind()
+                .await
+                .unwrap();
+            tokio::spawn(forced.serve());
+            assert!(send_unix(&socket_path, r#"{"operation":"hello"}"#).await.success);
+        });
+    }
+
+    #[test]
+    fn test_stale_lock_file_is_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("violet.sock");
+        let lock_path = dir.path().join("violet.sock.lock");
+
+        // Left behind by a daemon that died without cleaning up
+        std::fs::write(&lock_path, "4194304\n").unwrap();
+        std::os::unix::net::UnixListener::bind(&socket_path).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = DaemonServer::new(socket_path.display().to_string(), "http://127.0.0.1:9".into());
+            let handle = server.shutdown_handle();
+            let daemon = server.bind().await.unwrap();
+            assert_eq!(std::fs::read_to_string(&lock_path).unwrap().trim(), std::process::id().to_string());
+
+            let served = tokio::spawn(daemon.serve());
+            assert!(send_unix(&socket_path, r#"{"operation":"hello"}"#).await.success);
+
+            handle.shutdown();
+            served.await.unwrap().unwrap();
+        });
+        assert!(!socket_path.exists());
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn test_lock_on_a_removed_lock_file_is_not_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("violet.sock.lock");
+        let first = InstanceLock::acquire(lock_path.clone()).ok().unwrap();
+
+        // A second daemon opened the lock file just before the first removed it,
+        // and wins the lock on the removed file once the first lets go
+        let removed = File::open(&lock_path).unwrap();
+        drop(first);
+        removed.try_lock().unwrap();
+        assert!(!still_at(&removed, &lock_path).unwrap());
+
+        // A third daemon locks a new file at the path, which then keeps everyone else out
+        let third = InstanceLock::acquire(lock_path.clone()).ok().unwrap();
+        assert!(still_at(&third.file, &lock_path).unwrap());
+        assert!(matches!(InstanceLock::acquire(lock_path.clone()), Err(LockError::Held(Some(pid))) if pid == std::process::id()));
+    }
+
+    #[test]
+    fn test_large_encrypts_do_not_stall_pings() {
+        use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+        const PAYLOADS: usize = 8;
+        let mut server = mockito::Server::new();
+        let _mock = server
+            .mock("GET", "/v1/keys/bulk-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"bulk-key","key":"{}"}}"#, "66".repeat(32)))
+            .create();
+
+        // With only two workers, crypto running on them would hold up every
+        // ping; with two blocking threads, most encrypts wait for one
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), server.url())
+                .with_blocking_threads(2)
+                .bind()
+                .await
+                .unwrap();
+            let addr = daemon.tcp_addr().unwrap();
+            tokio::spawn(daemon.serve());
+
+            let plaintext = BASE64.encode(vec![0x61u8; 10 * 1024 * 1024]);
+            let request = Arc::new(
+                serde_json::json!({
+                    "operation": "encrypt",
+                    "data": { "plaintext": plaintext, "keyId": "bulk-key" },
+                })
+                .to_string(),
+            );
+            let encrypts: Vec<_> = (0..PAYLOADS)
+                .map(|_| {
+                    let request = Arc::clone(&request);
+                    tokio::spawn(async move {
+                        let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+                        let response = send(&mut stream, &request).await;
+                        assert!(response.success, "{:?}", response.error);
+                    })
+                })
+                .collect();
+
+            let mut pinger = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            let mut slowest = Duration::ZERO;
+            let mut pings = 0;
+            while !encrypts.iter().all(|encrypt| encrypt.is_finished()) {
+                let started = std::time::Instant::now();
+                let response = send(&mut pinger, r#"{"operation":"ping"}"#).await;
+                assert!(response.success, "{:?}", response.error);
+                slowest = slowest.max(started.elapsed());
+                pings += 1;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            for encrypt in encrypts {
+                encrypt.await.unwrap();
+            }
+
+            assert!(pings > 0);
+            assert!(slowest < Duration::from_secs(1), "slowest of {} pings took {:?}", pings, slowest);
+        });
+    }
+
+    #[test]
+    fn test_pipelined_request_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("violet.sock");
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::new(socket_path.display().to_string(), "http://127.0.0.1:9".into())
+                .bind()
+                .await
+                .unwrap();
+            tokio::spawn(daemon.serve());
+
+            // Three requests in one write; the second fails without reaching the Keys server
+            let mut stream = BufReader::new(tokio::net::UnixStream::connect(&socket_path).await.unwrap());
+            stream
+                .get_mut()
+                .write_all(
+                    concat!(
+                        r#"{"id":"first","operation":"hello"}"#, "\n",
+                        r#"{"id":"second","operation":"encrypt","data":{"plaintext":"not base64!"}}"#, "\n",
+                        r#"{"operation":"hello"}"#, "\n",
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+
+            let mut lines = Vec::new();
+            for _ in 0..3 {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                lines.push(line);
+            }
+            let mut responses: Vec<Response> = lines.iter().map(|l| serde_json::from_str(l).unwrap()).collect();
+
+            // Requests with ids may be answered in either order, but before the one without
+            responses[..2].sort_by(|a, b| a.id.cmp(&b.id));
+            assert_eq!(responses[0].id.as_deref(), Some("first"));
+            assert!(responses[0].success);
+            assert_eq!(responses[1].id.as_deref(), Some("second"));
+            assert!(!responses[1].success);
+            assert_eq!(responses[2].id, None);
+            assert!(!lines[2].contains(r#""id""#), "{}", lines[2]);
+        });
+    }
+
+    #[test]
+    fn test_requests_with_ids_answered_as_they_finish() {
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("violet.sock");
+        let (keys_url, arrived) = slow_keys_server(Duration::from_millis(300));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::new(socket_path.display().to_string(), keys_url).bind().await.unwrap();
+            tokio::spawn(daemon.serve());
+
+            let slow = r#"{"operation":"encrypt","data":{"plaintext":"SGVsbG8=","keyId":"slow-key"}}"#;
+            let mut stream = BufReader::new(tokio::net::UnixStream::connect(&socket_path).await.unwrap());
+
+            // With ids, the fast request overtakes the slow one
+            let pipelined = format!(
+                "{}\n{}\n",
+                slow.replacen('{', r#"{"id":"slow","#, 1),
+                r#"{"id":"fast","operation":"hello"}"#
+            );
+            stream.get_mut().write_all(pipelined.as_bytes()).await.unwrap();
+            arrived.await.unwrap();
+
+            let first = read_response(&mut stream).await;
+            assert_eq!(first.id.as_deref(), Some("fast"));
+            let second = read_response(&mut stream).await;
+            assert_eq!(second.id.as_deref(), Some("slow"));
+            assert!(second.success, "{:?}", second.error);
+
+            // Without ids, responses stay in request order; the key is cached by now
+            let ordered = format!("{}\n{}\n", slow, r#"{"operation":"hello"}"#);
+            stream.get_mut().write_all(ordered.as_bytes()).await.unwrap();
+            let first = read_response(&mut stream).await;
+            assert!(matches!(first.result, Some(ResponseResult::Encrypt { .. })), "{:?}", first);
+            let second = read_response(&mut stream).await;
+            assert!(second.success && !matches!(second.result, Some(ResponseResult::Encrypt { .. })), "{:?}", second);
+        });
+    }
+
+    async fn read_response(stream: &mut BufReader<tokio::net::UnixStream>) -> Response {
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    /// A Keys server that signals when a request arrives and answers after `delay`
+    fn slow_keys_server(delay: std::time::Duration) -> (String, tokio::sync::oneshot::Receiver<()>) {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (arrived, arrived_rx) = tokio::sync::oneshot::channel();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            arrived.send(()).ok();
+            std::thread::sleep(delay);
+
+            let body = format!(r#"{{"uuid":"slow-key","key":"{}"}}"#, "88".repeat(32));
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+        });
+        (url, arrived_rx)
+    }
+
+    #[test]
+    fn test_shutdown_lets_in_flight_request_finish() {
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("violet.sock");
+        let (keys_url, arrived) = slow_keys_server(Duration::from_millis(300));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = DaemonServer::new(socket_path.display().to_string(), keys_url)
+                .with_shutdown_grace(Duration::from_secs(10));
+            let handle = server.shutdown_handle();
+            let daemon = server.bind().await.unwrap();
+            let serving = tokio::spawn(daemon.serve());
+
+            // An idle connection is closed by the shutdown
+            let mut idle = BufReader::new(tokio::net::UnixStream::connect(&socket_path).await.unwrap());
+
+            let mut busy = BufReader::new(tokio::net::UnixStream::connect(&socket_path).await.unwrap());
+            busy.get_mut()
+                .write_all(b"{\"operation\":\"encrypt\",\"data\":{\"plaintext\":\"SGVsbG8=\",\"keyId\":\"slow-key\"}}\n")
+                .await
+                .unwrap();
+
+            // Shut down while the request waits on the Keys server
+            arrived.await.unwrap();
+            handle.shutdown();
+
+            let mut line = String::new();
+            busy.read_line(&mut line).await.unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            assert!(response.success, "{:?}", response.error);
+            assert!(matches!(response.result, Some(ResponseResult::Encrypt { .. })));
+
+            line.clear();
+            assert_eq!(busy.read_line(&mut line).await.unwrap(), 0);
+            assert_eq!(idle.read_line(&mut line).await.unwrap(), 0);
+
+            tokio::time::timeout(Duration::from_secs(5), serving)
+                .await
+                .expect("serve did not return after shutdown")
+                .unwrap()
+                .unwrap();
+            assert!(!socket_path.exists());
+        });
+    }
+
+    #[test]
+    fn test_stdio_transport_answers_piped_request() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let mut daemon = DaemonServer::stdio("http://127.0.0.1:9".to_string()).bind().await.unwrap();
+            assert!(daemon.unix.is_none() && daemon.tcp.is_none());
+
+            // Stand in for stdin and stdout
+            let (client, daemon_end) = tokio::io::duplex(64 * 1024);
+            daemon.stdio = Some(Box::new(daemon_end));
+            let serving = tokio::spawn(daemon.serve());
+
+            let (read, mut write) = tokio::io::split(client);
+            write.write_all(b"{\"id\":\"piped\",\"operation\":\"hello\"}\n").await.unwrap();
+            let mut reader = BufReader::new(read);
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            let response: serde_json::Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(response["id"], "piped");
+            assert_eq!(response["success"], true, "{}", line);
+
+            // Closing stdin stops the daemon
+            write.shutdown().await.unwrap();
+            tokio::time::timeout(Duration::from_secs(5), serving)
+                .await
+                .expect("serve did not return after stdin closed")
+                .unwrap()
+                .unwrap();
+        });
+
+        let server = DaemonServer::stdio("http://127.0.0.1:9".to_string())
+            .with_tcp_listener("127.0.0.1:0".parse().unwrap());
+        let err = runtime.block_on(server.bind()).err().unwrap();
+        assert!(err.to_string().contains("cannot also listen"), "{}", err);
+    }
+
+    #[test]
+    fn test_dropping_bound_daemon_removes_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("violet.sock");
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::new(socket_path.display().to_string(), "http://127.0.0.1:9".into())
+                .bind()
+                .await
+                .unwrap();
+            assert!(socket_path.exists());
+            drop(daemon);
+            assert!(!socket_path.exists());
+        });
+    }
+
+    #[test]
+    fn test_socket_is_owner_only_by_default() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("violet.sock");
+        let gid = std::fs::metadata(dir.path()).unwrap().gid();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = DaemonServer::new(socket_path.display().to_string(), "http://127.0.0.1:9".into());
+            let daemon = server.bind().await.unwrap();
+            let metadata = std::fs::metadata(&socket_path).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+            drop(daemon);
+
+            let server = DaemonServer::new(socket_path.display().to_string(), "http://127.0.0.1:9".into())
+                .with_socket_mode(0o660)
+                .with_socket_group(gid);
+            let _daemon = server.bind().await.unwrap();
+            let metadata = std::fs::metadata(&socket_path).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+            assert_eq!(metadata.gid(), gid);
+        });
+    }
+
+    #[test]
+    fn test_audit_log_records_unix_peer_credentials() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("violet.sock");
+        let audit_path = dir.path().join("audit.log");
+        let uid = std::fs::metadata(dir.path()).unwrap().uid();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::new(socket_path.display().to_string(), "http://127.0.0.1:9".into())
+                .with_audit_log(&audit_path)
+                .with_audit_strict(true)
+                .bind()
+                .await
+                .unwrap();
+            tokio::spawn(daemon.serve());
+
+            // The Keys server is offline, so the encrypt fails but is still audited
+            let mut stream = BufReader::new(tokio::net::UnixStream::connect(&socket_path).await.unwrap());
+            stream
+                .get_mut()
+                .write_all(b"{\"id\":\"req-1\",\"operation\":\"encrypt\",\"data\":{\"plaintext\":\"aGk=\",\"keyId\":\"k\"}}\n")
+                .await
+                .unwrap();
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            assert_eq!(response.error_code, Some(ErrorCode::KeyUnavailable));
+        });
+
+        let contents = std::fs::read_to_string(&audit_path).unwrap();
+        let record: serde_json::Value = serde_json::from_str(contents.lines().next().unwrap()).unwrap();
+        assert_eq!(record["requestId"], "req-1");
+        assert_eq!(record["peer"]["uid"], uid);
+        assert_eq!(record["peer"]["pid"], std::process::id());
+        assert_eq!(record["plaintextBytes"], 2);
+    }
+
+    #[test]
+    fn test_audit_strict_requires_audit_log() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let err = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), "http://127.0.0.1:9".into())
+                .with_audit_strict(true)
+                .bind()
+                .await
+                .err()
+                .expect("strict auditing without a log must be refused");
+            assert!(err.to_string().contains("audit log"), "{}", err);
+        });
+    }
+
+    #[test]
+    fn test_world_writable_socket_dir_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+        let socket_path = dir.path().join("violet.sock");
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = DaemonServer::new(socket_path.display().to_string(), "http://127.0.0.1:9".into());
+            let err = server.bind().await.err().expect("world-writable directory must be refused");
+            assert!(err.to_string().contains("--insecure-socket-dir"), "{}", err);
+            assert!(!socket_path.exists());
+
+            let _daemon = server.allow_insecure_socket_dir(true).bind().await.unwrap();
+            assert!(socket_path.exists());
+        });
+    }
+
+    #[test]
+    fn test_parse_group() {
+        assert_eq!(parse_group("1234").unwrap(), 1234);
+        assert_eq!(parse_group("root").unwrap(), 0);
+        assert!(parse_group("no-such-group-violet").is_err());
+    }
+
+    #[test]
+    fn test_parse_user() {
+        assert_eq!(parse_user("1234").unwrap(), 1234);
+        assert_eq!(parse_user("root").unwrap(), 0);
+        assert!(parse_user("no-such-user-violet").is_err());
+    }
+
+    #[test]
+    fn test_peer_allow_list() {
+        let peer = PeerCredentials { uid: 1000, gid: 100, pid: Some(7) };
+
+        // No list: everyone is served, even without credentials
+        let open = PeerAllowList::default();
+        assert!(open.permits(Some(&peer)));
+        assert!(open.permits(None));
+
+        let by_uid = PeerAllowList { uids: vec![1000], gids: vec![] };
+        assert!(by_uid.permits(Some(&peer)));
+        assert!(!by_uid.permits(Some(&PeerCredentials { uid: 1001, ..peer })));
+        assert!(!by_uid.permits(None));
+
+        // A shared group member is refused unless its own UID or the group is listed
+        let by_gid = PeerAllowList { uids: vec![0], gids: vec![100] };
+        assert!(by_gid.permits(Some(&PeerCredentials { uid: 1001, ..peer })));
+        assert!(!by_gid.permits(Some(&PeerCredentials { uid: 1001, gid: 101, pid: None })));
+    }
+
+    /// Start a Unix socket daemon with an allow-list, returning its socket path
+    async fn allow_listed_daemon(dir: &Path, server: impl FnOnce(DaemonServer) -> DaemonServer) -> PathBuf {
+        let socket_path = dir.join("violet.sock");
+        let daemon = server(DaemonServer::new(socket_path.display().to_string(), "http://127.0.0.1:9".into()))
+            .bind()
+            .await
+            .unwrap();
+        tokio::spawn(daemon.serve());
+        socket_path
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_abstract_socket_roundtrip() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let socket = format!("@violet-test-{}", std::process::id());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = DaemonServer::new(socket.clone(), "http://localhost:8080".into());
+            let daemon = server.bind().await.unwrap();
+            tokio::spawn(daemon.serve());
+
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(&socket[1..]).unwrap();
+            let stream = std::os::unix::net::UnixStream::connect_addr(&addr).unwrap();
+            stream.set_nonblocking(true).unwrap();
+            let mut stream = BufReader::new(tokio::net::UnixStream::from_std(stream).unwrap());
+            let response = send(&mut stream, r#"{"operation":"hello"}"#).await;
+            assert!(response.success, "{:?}", response.error);
+
+            // Nothing on disk: no socket file and no lock file
+            assert!(!Path::new(&socket).exists());
+            assert!(!InstanceLock::path_for(Path::new(&socket)).exists());
+
+            // The kernel keeps a second daemon off the name
+            let err = server.bind().await.err().expect("the name is taken");
+            assert!(err.to_string().contains("already serving"), "{}", err);
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_abstract_socket_refuses_unlisted_peer() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let socket = format!("@violet-unlisted-{}", std::process::id());
+        // SAFETY: geteuid cannot fail and touches no memory
+        let uid = unsafe { libc::geteuid() };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::new(socket.clone(), "http://localhost:8080".into())
+                .with_allowed_uid(uid.wrapping_add(1))
+                .bind()
+                .await
+                .unwrap();
+            tokio::spawn(daemon.serve());
+
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(&socket[1..]).unwrap();
+            let stream = std::os::unix::net::UnixStream::connect_addr(&addr).unwrap();
+            stream.set_nonblocking(true).unwrap();
+            let mut stream = BufReader::new(tokio::net::UnixStream::from_std(stream).unwrap());
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            assert_eq!(response.error_code, Some(ErrorCode::Unauthorized));
+        });
+    }
+
+    async fn send_unix(socket_path: &Path, request: &str) -> Response {
+        let mut stream = BufReader::new(tokio::net::UnixStream::connect(socket_path).await.unwrap());
+        stream.get_mut().write_all(request.as_bytes()).await.unwrap();
+        stream.get_mut().write_all(b"\n").await.unwrap();
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn test_allow_listed_uid_is_served() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let uid = std::fs::metadata(dir.path()).unwrap().uid();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let socket_path = allow_listed_daemon(dir.path(), |server| server.with_allowed_uid(uid)).await;
+            let response = send_unix(&socket_path, r#"{"operation":"hello"}"#).await;
+            assert!(response.success, "{:?}", response.error);
+        });
+    }
+
+    #[test]
+    fn test_unlisted_peer_is_refused_before_reading() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let uid = std::fs::metadata(dir.path()).unwrap().uid();
+        let gid = std::fs::metadata(dir.path()).unwrap().gid();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let socket_path = allow_listed_daemon(dir.path(), |server| {
+                server.with_allowed_uid(uid.wrapping_add(1)).with_allowed_gid(gid.wrapping_add(1))
+            })
+            .await;
+
+            // The refusal arrives without the client sending anything
+            let mut stream = BufReader::new(tokio::net::UnixStream::connect(&socket_path).await.unwrap());
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            assert!(!response.success);
+            assert_eq!(response.error_code, Some(ErrorCode::Unauthorized));
+            assert!(response.error.unwrap().contains(&uid.to_string()));
+
+            line.clear();
+            assert_eq!(stream.read_line(&mut line).await.unwrap(), 0, "connection must be closed");
+        });
+    }
+
+    #[test]
+    fn test_allow_list_cannot_be_combined_with_tcp() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let err = DaemonServer::new(dir.path().join("violet.sock").display().to_string(), "http://127.0.0.1:9".into())
+                .with_tcp_listener("127.0.0.1:0".parse().unwrap())
+                .with_allowed_uid(0)
+                .bind()
+                .await
+                .err()
+                .expect("allow-list with TCP must be refused");
+            assert!(err.to_string().contains("--listen"), "{}", err);
+        });
+    }
+
+    #[test]
+    fn test_remote_bind_requires_allow_remote() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = DaemonServer::tcp("0.0.0.0:0".parse().unwrap(), "http://localhost:8080".into());
+            let err = server.bind().await.err().expect("non-loopback bind must be refused");
+            assert!(err.to_string().contains("--allow-remote"));
+
+            let server = server.allow_remote(true);
+            let err = server.bind().await.err().expect("non-loopback bind without TLS must be refused");
+            assert!(err.to_string().contains("--allow-insecure-remote"), "{}", err);
+
+            let daemon = server.allow_insecure_remote(true).bind().await.unwrap();
+            assert!(daemon.tcp_addr().is_some());
+        });
+    }
+
+    /// Write `certified`'s certificate and key as PEM files in `dir`
+    fn write_pem(dir: &Path, name: &str, certified: &rcgen::CertifiedKey) -> (PathBuf, PathBuf) {
+        let cert = dir.join(format!("{}.pem", name));
+        let key = dir.join(format!("{}-key.pem", name));
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+        (cert, key)
+    }
+
+    /// Client trusting `server_cert`, presenting the certificate and key in `client` if given
+    fn tls_connector(server_cert: &rcgen::Certificate, client: Option<&rcgen::CertifiedKey>) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        roots.add(server_cert.der().clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(tokio_rustls::rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = match client {
+            Some(client) => {
+                let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(client.key_pair.serialize_der()));
+                builder.with_client_auth_cert(vec![client.cert.der().clone()], key).unwrap()
+            }
+            None => builder.with_no_client_auth(),
+        };
+        TlsConnector::from(Arc::new(config))
+    }
+
+    #[test]
+    fn test_tls_roundtrip() {
+        let mut server = mockito::Server::new();
+        let _mock = server
+            .mock("GET", "/v1/keys/tls-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"tls-key","key":"{}"}}"#, "55".repeat(32)))
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (cert, key) = write_pem(dir.path(), "server", &certified);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), server.url())
+                .with_tls(cert, key)
+                .bind()
+                .await
+                .unwrap();
+            let addr = daemon.tcp_addr().unwrap();
+            tokio::spawn(daemon.serve());
+
+            let tcp = TcpStream::connect(addr).await.unwrap();
+            let tls = tls_connector(&certified.cert, None)
+                .connect(ServerName::try_from("localhost").unwrap(), tcp)
+                .await
+                .unwrap();
+            let mut stream = BufReader::new(tls);
+
+            let response = send(
+                &mut stream,
+                r#"{"operation":"encrypt","data":{"plaintext":"SGVsbG8=","keyId":"tls-key"}}"#,
+            )
+            .await;
+            let envelope = match response.result {
+                Some(ResponseResult::Encrypt { envelope }) => envelope,
+                other => panic!("expected envelope, got {:?} ({:?})", other, response.error),
+            };
+            let decrypt = serde_json::json!({ "operation": "decrypt", "data": { "envelope": envelope } });
+            match send(&mut stream, &decrypt.to_string()).await.result {
+                Some(ResponseResult::Decrypt { plaintext }) => assert_eq!(plaintext, "SGVsbG8="),
+                other => panic!("expected plaintext, got {:?}", other),
+            }
+
+            // A plaintext client gets nothing back from a TLS listener
+            let mut plain = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            plain.get_mut().write_all(b"{\"operation\":\"hello\"}\n").await.unwrap();
+            let mut line = String::new();
+            let _ = plain.read_line(&mut line).await;
+            assert!(!line.contains("success"), "{}", line);
+        });
+    }
+
+    #[test]
+    fn test_tls_client_ca_rejects_client_without_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (cert, key) = write_pem(dir.path(), "server", &certified);
+
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+        let ca = dir.path().join("ca.pem");
+        std::fs::write(&ca, ca_cert.pem()).unwrap();
+
+        let client_key = rcgen::KeyPair::generate().unwrap();
+        let mut client_params = rcgen::CertificateParams::new(vec!["client".to_string()]).unwrap();
+        client_params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+        let client = rcgen::CertifiedKey {
+            cert: client_params.signed_by(&client_key, &ca_cert, &ca_key).unwrap(),
+            key_pair: client_key,
+        };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let daemon = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), "http://localhost:1".into())
+                .with_tls(cert, key)
+                .with_tls_client_ca(&ca)
+                .bind()
+                .await
+                .unwrap();
+            let addr = daemon.tcp_addr().unwrap();
+            tokio::spawn(daemon.serve());
+            let name = ServerName::try_from("localhost").unwrap();
+
+            // Under TLS 1.3 the client may finish its side of the handshake
+            // before the server rejects it, so the failure can surface on read
+            let tcp = TcpStream::connect(addr).await.unwrap();
+            let rejected = match tls_connector(&certified.cert, None).connect(name.clone(), tcp).await {
+                Err(_) => true,
+                Ok(tls) => {
+                    let mut stream = BufReader::new(tls);
+                    let _ = stream.get_mut().write_all(b"{\"operation\":\"hello\"}\n").await;
+                    let mut line = String::new();
+                    matches!(stream.read_line(&mut line).await, Err(_) | Ok(0))
+                }
+            };
+            assert!(rejected, "client without a certificate must be rejected");
+
+            let tcp = TcpStream::connect(addr).await.unwrap();
+            let tls = tls_connector(&certified.cert, Some(&client)).connect(name, tcp).await.unwrap();
+            let response = send(&mut BufReader::new(tls), r#"{"operation":"hello"}"#).await;
+            assert!(response.success, "{:?}", response.error);
+        });
+    }
+
+    #[test]
+    fn test_tls_settings_are_validated() {
+        let dir = tempfile::tempdir().unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (cert, key) = write_pem(dir.path(), "server", &certified);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let socket_path = dir.path().join("violet.sock");
+            let err = DaemonServer::new(socket_path.to_string_lossy().into_owned(), "http://localhost:8080".into())
+                .with_tls(&cert, &key)
+                .bind()
+                .await
+                .err()
+                .expect("TLS without a TCP listener must be refused");
+            assert!(err.to_string().contains("--listen"), "{}", err);
+
+            let err = DaemonServer::tcp("127.0.0.1:0".parse().unwrap(), "http://localhost:8080".into())
+                .with_tls_client_ca(&cert)
+                .bind()
+                .await
+                .err()
+                .expect("a client CA without a certificate must be refused");
+            assert!(err.to_string().contains("--tls-cert"), "{}", err);
+
+            let daemon = DaemonServer::tcp("0.0.0.0:0".parse().unwrap(), "http://localhost:8080".into())
+                .allow_remote(true)
+                .with_tls(&cert, &key)
+                .bind()
+                .await
+                .unwrap();
+            assert!(daemon.tcp_addr().is_some());
+        });
+    }
+
+    #[test]
+    fn test_parse_listen_addr() {
+        assert_eq!(
+            parse_listen_addr("tcp://127.0.0.1:9876").unwrap(),
+            "127.0.0.1:9876".parse::<SocketAddr>().unwrap()
+        );
+        assert!(parse_listen_addr("127.0.0.1:9876").is_err());
+        assert!(parse_listen_addr("tcp://localhost").is_err());
+    }
 }
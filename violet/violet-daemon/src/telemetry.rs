@@ -0,0 +1,105 @@
+//! Export of daemon spans to an OpenTelemetry collector (`otel` feature)
+//!
+//! Each request is handled inside a `daemon.request` span whose attributes
+//! include the operation, algorithm, [`key_id_hash`](crate::handler::key_id_hash)
+//! and outcome; Keys server calls are child spans. Adding [`layer`] to the
+//! `tracing` subscriber exports all of them.
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// `service.name` reported with every span
+pub const SERVICE_NAME: &str = "violet-daemon";
+
+/// Tracer provider that batches spans to the OTLP/gRPC collector at `endpoint`
+///
+/// Must be called on a Tokio runtime. Call `shutdown` on the provider before
+/// exiting to flush spans that have not been sent yet.
+pub fn otlp_provider(endpoint: &str) -> anyhow::Result<TracerProvider> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new("service.name", SERVICE_NAME)]))
+        .build())
+}
+
+/// `tracing` layer that records spans through `provider`
+pub fn layer<S>(provider: &TracerProvider) -> OpenTelemetryLayer<S, Tracer>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::{key_id_hash, RequestHandler};
+    use crate::protocol::{Operation, Request, RequestData};
+    use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_request_span_exported_with_attributes() {
+        let mut server = mockito::Server::new();
+        let _mock = server
+            .mock("GET", "/v1/keys/otel-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(r#"{{"uuid":"otel-key","key":"{}"}}"#, "66".repeat(32)))
+            .create();
+        let handler = RequestHandler::new(&server.url()).unwrap();
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry().with(layer(&provider));
+
+        let request = Request {
+            id: None,
+            version: None,
+            operation: Operation::Encrypt,
+            include_timings: false,
+            data: RequestData {
+                plaintext: "SGVsbG8=".to_string(),
+                key_id: Some("otel-key".to_string()),
+                ..Default::default()
+            },
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = tracing::subscriber::with_default(subscriber, || runtime.block_on(handler.handle(request)));
+        assert!(response.success, "{:?}", response.error);
+
+        for result in provider.force_flush() {
+            result.unwrap();
+        }
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = spans
+            .iter()
+            .find(|span| span.name == "daemon.request")
+            .expect("daemon.request span exported");
+        let attribute = |key: &str| {
+            span.attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.to_string())
+        };
+
+        assert_eq!(attribute("operation").as_deref(), Some("Encrypt"));
+        assert_eq!(attribute("algorithm").as_deref(), Some("AES-256-GCM"));
+        assert_eq!(attribute("key_id_hash"), Some(key_id_hash("otel-key")));
+        assert_eq!(attribute("outcome").as_deref(), Some("success"));
+        assert!(span.attributes.iter().all(|kv| kv.value.to_string() != "otel-key"));
+    }
+}
@@ -0,0 +1,84 @@
+//! TLS for the daemon's TCP listener
+//!
+//! The certificate chain and key are read from PEM files. With a client CA the
+//! listener also requires every client to present a certificate that CA
+//! signed (mutual TLS); clients without one fail the handshake.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+/// Time a TCP client gets to complete the TLS handshake
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Build the acceptor for `cert` and `key`, verifying clients against `client_ca` if given
+pub(crate) fn acceptor(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<TlsAcceptor> {
+    let chain = read_certs(cert)?;
+    let private_key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| anyhow!("Failed to read TLS private key {}: {}", key.display(), e))?;
+
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .context("Failed to set up TLS")?;
+    let builder = match client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("Invalid CA certificate in {}", ca.display()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .with_context(|| format!("Failed to use {} as the client CA", ca.display()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(chain, private_key)
+        .with_context(|| format!("Invalid TLS certificate {} or key {}", cert.display(), key.display()))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Every certificate in a PEM file, failing if there are none
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| anyhow!("Failed to read certificates from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        bail!("No PEM certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acceptor_from_self_signed_cert() {
+        let dir = tempfile::tempdir().unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert = dir.path().join("cert.pem");
+        let key = dir.path().join("key.pem");
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+
+        acceptor(&cert, &key, None).unwrap();
+        acceptor(&cert, &key, Some(&cert)).unwrap();
+
+        let error = acceptor(&key, &key, None).err().unwrap().to_string();
+        assert!(error.contains("No PEM certificates"), "{}", error);
+        let error = acceptor(&cert, &cert, None).err().unwrap().to_string();
+        assert!(error.contains("private key"), "{}", error);
+    }
+}